@@ -0,0 +1,53 @@
+# SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+# SPDX-License-Identifier: Apache-2.0
+
+# Compares DiskSpace.du/2 with 1, 2, 4 and 8 workers on a synthetic tree.
+#
+#     mix run bench/du.exs [files]
+#
+# The tree has `files` small files (default 200_000) spread over nested
+# directories under the system temp dir, and is removed afterwards. Every
+# configuration runs once to warm the page cache before being timed, so this
+# measures the CPU/syscall side of the walk; drop the caches between runs
+# (e.g. `echo 3 > /proc/sys/vm/drop_caches` as root) to see cold-cache IO.
+
+{files, _} =
+  case System.argv() do
+    [n | _] -> Integer.parse(n)
+    [] -> {200_000, ""}
+  end
+
+root = Path.join(System.tmp_dir!(), "disk_space_du_bench_#{System.unique_integer([:positive])}")
+per_dir = 100
+
+IO.puts("Creating #{files} files under #{root} ...")
+
+for i <- 0..(div(files, per_dir) - 1) do
+  dir = Path.join([root, "d#{rem(i, 10)}", "d#{rem(div(i, 10), 10)}", "d#{i}"])
+  File.mkdir_p!(dir)
+
+  for j <- 1..per_dir do
+    File.write!(Path.join(dir, "f#{j}"), :binary.copy("x", rem(j * 37, 4096)))
+  end
+end
+
+time = fn workers ->
+  {:ok, _} = DiskSpace.du(root, workers: workers)
+
+  {usec, {:ok, summary}} = :timer.tc(fn -> DiskSpace.du(root, workers: workers) end)
+  {usec, summary}
+end
+
+{base, expected} = time.(1)
+
+for workers <- [1, 2, 4, 8] do
+  {usec, summary} = if workers == 1, do: {base, expected}, else: time.(workers)
+  ^expected = summary
+
+  IO.puts(
+    "workers: #{workers}\t#{Float.round(usec / 1000, 1)} ms\t" <>
+      "speedup: #{Float.round(base / usec, 2)}x"
+  )
+end
+
+File.rm_rf!(root)
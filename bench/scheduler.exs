@@ -0,0 +1,57 @@
+# SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+# SPDX-License-Identifier: Apache-2.0
+
+# Compares the schedulers DiskSpace.stat/2 can run its stat on.
+#
+#     mix run bench/scheduler.exs [path] [calls]
+#
+# Each scheduler takes `calls` stats (default 100_000) of `path` (default the
+# system temp dir), after as many again to warm up, first from one process in
+# a row, then spread over as many processes as there are schedulers, and the
+# mean time per stat is printed. On tmpfs or a local disk the handoff to a
+# dirty scheduler is most of the time of a stat; on a network filesystem the
+# stat itself is, and :normal should not be used there at all.
+
+{path, calls} =
+  case System.argv() do
+    [path, n | _] -> {path, String.to_integer(n)}
+    [path] -> {path, 100_000}
+    [] -> {System.tmp_dir!(), 100_000}
+  end
+
+processes = System.schedulers_online()
+
+# A budget no stat overruns, as debug builds fail those that do
+opts = [humanize: nil, budget_ms: 60_000]
+
+run = fn scheduler, count ->
+  for _ <- 1..count, do: {:ok, _} = DiskSpace.stat(path, [scheduler: scheduler] ++ opts)
+end
+
+time = fn scheduler ->
+  run.(scheduler, calls)
+  {serial, _} = :timer.tc(fn -> run.(scheduler, calls) end)
+
+  {parallel, _} =
+    :timer.tc(fn ->
+      1..processes
+      |> Enum.map(fn _ -> Task.async(fn -> run.(scheduler, div(calls, processes)) end) end)
+      |> Task.await_many(:infinity)
+    end)
+
+  {serial, parallel}
+end
+
+IO.puts("#{calls} stats of #{path}, then over #{processes} processes")
+
+{base, _} = times = time.(:dirty_io)
+
+for scheduler <- [:dirty_io, :dirty_cpu, :normal] do
+  {serial, parallel} = if scheduler == :dirty_io, do: times, else: time.(scheduler)
+
+  IO.puts(
+    "#{scheduler}\t#{Float.round(serial / calls, 2)} us/stat\t" <>
+      "#{Float.round(parallel / calls, 2)} us/stat in parallel\t" <>
+      "speedup: #{Float.round(base / serial, 2)}x"
+  )
+end
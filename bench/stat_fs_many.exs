@@ -0,0 +1,46 @@
+# SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+# SPDX-License-Identifier: Apache-2.0
+
+# Compares the result shapes of DiskSpace.stat_fs_many/2 on a large batch.
+#
+#     mix run bench/stat_fs_many.exs [path] [entries] [calls]
+#
+# Each shape takes `calls` batches (default 200) of `entries` copies of
+# `path` (default 1000 copies of the system temp dir), after as many again to
+# warm up, and the mean time per batch and per entry is printed. With
+# unique: true the path is statted once per batch, so what is timed is
+# mostly building the result: a map per entry, or five flat lists.
+
+{path, entries, calls} =
+  case System.argv() do
+    [path, n, c | _] -> {path, String.to_integer(n), String.to_integer(c)}
+    [path, n] -> {path, String.to_integer(n), 200}
+    [path] -> {path, 1000, 200}
+    [] -> {System.tmp_dir!(), 1000, 200}
+  end
+
+paths = List.duplicate(path, entries)
+
+run = fn opts ->
+  for _ <- 1..calls, do: DiskSpace.stat_fs_many(paths, [unique: true] ++ opts)
+end
+
+time = fn opts ->
+  run.(opts)
+  {micros, _} = :timer.tc(fn -> run.(opts) end)
+  micros / calls
+end
+
+IO.puts("#{calls} batches of #{entries} copies of #{path}")
+
+base = time.([])
+
+for {name, opts} <- [maps: [], columnar: [columnar: true]] do
+  per_batch = if opts == [], do: base, else: time.(opts)
+
+  IO.puts(
+    "#{name}\t#{Float.round(per_batch, 1)} us/batch\t" <>
+      "#{Float.round(per_batch * 1000 / entries, 1)} ns/entry\t" <>
+      "speedup: #{Float.round(base / per_batch, 2)}x"
+  )
+end
@@ -0,0 +1,40 @@
+# SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+# SPDX-License-Identifier: Apache-2.0
+
+# Compares DiskSpace.stat/2 with DiskSpace.stat_target/1 on the same directory.
+#
+#     mix run bench/stat_target.exs [path] [calls]
+#
+# Each is called `calls` times (default 100_000) in a row from one process,
+# after as many calls again to warm up, and the mean time per call is printed.
+# `path` defaults to the system temp dir; a path deep in a tree, or on a
+# network filesystem, shows more of what open_target/1 saves.
+
+{path, calls} =
+  case System.argv() do
+    [path, n | _] -> {path, String.to_integer(n)}
+    [path] -> {path, 100_000}
+    [] -> {System.tmp_dir!(), 100_000}
+  end
+
+{:ok, target} = DiskSpace.open_target(path)
+
+time = fn stat ->
+  for _ <- 1..calls, do: {:ok, _} = stat.()
+
+  {usec, _} = :timer.tc(fn -> for _ <- 1..calls, do: {:ok, _} = stat.() end)
+  usec
+end
+
+base = time.(fn -> DiskSpace.stat(path, humanize: nil) end)
+usec = time.(fn -> DiskSpace.stat_target(target) end)
+
+IO.puts("#{calls} calls on #{path}")
+IO.puts("stat/2\t\t#{Float.round(base / calls, 2)} us/call")
+
+IO.puts(
+  "stat_target/1\t#{Float.round(usec / calls, 2)} us/call\t" <>
+    "speedup: #{Float.round(base / usec, 2)}x"
+)
+
+:ok = DiskSpace.close_target(target)
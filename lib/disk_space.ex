@@ -7,10 +7,25 @@ defmodule DiskSpace do
 
   The main function `stat/2` returns disk space stats for a given filesystem path.
 
-  It also provides a bang variant `stat!/2`, which raises a `DiskSpace.Error` exception on error.
+  It also provides a bang variant `stat!/2`, which raises a `DiskSpace.Error` exception on error,
+  and `stat_fs_async/3`, which delivers the result as a message instead of blocking the caller.
+  `stat_fs_many/2` stats a list of paths at once, `watch/2` keeps statting one on an
+  interval, and `watch_all_mounts/1` every mounted filesystem.
 
-  Both functions support optionally humanizing the output into
+  `stat/2` and `stat!/2` support optionally humanizing the output into
   strings (`:humanize` and `:base` options).
+
+  `list_mounts/1` enumerates the mounted filesystems, optionally dropping
+  noise such as pseudo-filesystems and snap loop mounts, and `mounts_diff/2`
+  compares two mount tables, e.g. taken with `snapshot/1`.
+
+  `du/2` computes the size of a directory tree natively, without spawning `du`;
+  `du_start/2` runs the same walk in the background, cancellable with `du_cancel/1`,
+  `du_async/4` does so on behalf of another process,
+  `du_stream/4` delivers its result one directory at a time,
+  repeated walks can skip unchanged directories with a cache from `du_cache_new/1`,
+  `du_estimate/2` gives a quick approximation from a sample of the tree,
+  and `fits?/2` checks a measured tree against the free space and inodes elsewhere.
   """
 
   # @on_load :load_nifs
@@ -31,6 +46,18 @@ defmodule DiskSpace do
     end
   end
 
+  defmodule Event do
+    @moduledoc """
+    A message of a watcher started with `message_format: :struct`.
+
+    `:event` is `:sample`, `:alert`, `:event`, `:report` or `:mounts_changed`, after the
+    tag of the tuple the message would otherwise be; `:type` is the transition of an alert
+    or the kind of an event, and `nil` for the rest; `:ref` is the watcher's reference;
+    and `:data` is the sample, event map, report or diff.
+    """
+    defstruct [:event, :type, :ref, :data]
+  end
+
   # defp load_nifs do
   #   priv_dir = :code.priv_dir(:disk_space) |> to_string()
   #   base_name = "disk_space"
@@ -38,8 +65,82 @@ defmodule DiskSpace do
   #   :erlang.load_nif(to_charlist(path), 0)
   # end
 
-  # stub with minimal arity for NIF binding
+  # stubs with minimal arity for NIF binding
   defp stat_fs(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp stat_fs_fast(_path, _budget_ms), do: :erlang.nif_error(:nif_not_loaded)
+  defp stat_fs_dirty_cpu(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp configure_nif(_opts), do: :erlang.nif_error(:nif_not_loaded)
+  defp get_config_nif(), do: :erlang.nif_error(:nif_not_loaded)
+  defp default_humanize_nif(), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_stats_nif(), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_stats_reset_nif(), do: :erlang.nif_error(:nif_not_loaded)
+  # Left as it is in builds without the bench feature, which have no such NIF
+  defp bench_stat_nif(_path, _iterations, _encode), do: {:error, :bench_disabled}
+  defp stat_fs_async_nif(_path, _pid, _ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp stat_fs_cached_nif(_path, _ttl_ms), do: :erlang.nif_error(:nif_not_loaded)
+  defp cache_invalidate_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp open_target_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp stat_target_nif(_target), do: :erlang.nif_error(:nif_not_loaded)
+  defp close_target_nif(_target), do: :erlang.nif_error(:nif_not_loaded)
+  defp quota_nif(_path, _kind, _id), do: :erlang.nif_error(:nif_not_loaded)
+  defp filesystem_overhead_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp btrfs_details_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp btrfs_qgroup_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp zfs_details_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp quota_info_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp space_information_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp apfs_details_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp capacity_details_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp apfs_snapshots_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp tmpfs_details_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp volume_id_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp media_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp container_context_nif(), do: :erlang.nif_error(:nif_not_loaded)
+  defp stat_fs_many_nif(_paths, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  defp stat_all_async_nif(_opts, _pid, _ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp watch_nif(_path, _opts, _ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp watch_all_mounts_nif(_opts, _ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp unwatch_nif(_watcher), do: :erlang.nif_error(:nif_not_loaded)
+  defp list_watchers_nif(), do: :erlang.nif_error(:nif_not_loaded)
+  defp export_watchers_nif(), do: :erlang.nif_error(:nif_not_loaded)
+  defp set_interval_nif(_watcher, _interval_ms), do: :erlang.nif_error(:nif_not_loaded)
+  defp watcher_info_nif(_watcher), do: :erlang.nif_error(:nif_not_loaded)
+  defp watcher_pause_nif(_watcher), do: :erlang.nif_error(:nif_not_loaded)
+  defp watcher_resume_nif(_watcher), do: :erlang.nif_error(:nif_not_loaded)
+  defp watcher_ack_nif(_watcher), do: :erlang.nif_error(:nif_not_loaded)
+  defp reset_nif(_watcher_or_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp watcher_history_nif(_watcher, _count), do: :erlang.nif_error(:nif_not_loaded)
+  defp list_mounts_nif(_opts), do: :erlang.nif_error(:nif_not_loaded)
+  defp snapshot_nif(_opts), do: :erlang.nif_error(:nif_not_loaded)
+  defp mounts_diff_nif(_old, _new), do: :erlang.nif_error(:nif_not_loaded)
+  defp du_start_nif(_path, _opts, _ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp du_async_nif(_path, _opts, _pid, _ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp du_cancel_nif(_token), do: :erlang.nif_error(:nif_not_loaded)
+  defp du_stream_nif(_path, _opts, _pid, _ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp du_ack_nif(_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp fits_nif(_path, _bytes, _inodes), do: :erlang.nif_error(:nif_not_loaded)
+  defp du_estimate_nif(_path, _opts, _ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp du_cache_new_nif(_opts), do: :erlang.nif_error(:nif_not_loaded)
+  defp du_cache_invalidate_nif(_cache, _prefix), do: :erlang.nif_error(:nif_not_loaded)
+  defp du_cache_stats_nif(_cache), do: :erlang.nif_error(:nif_not_loaded)
+  defp preallocate_nif(_path, _bytes, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  defp estimate_on_disk_nif(_path, _planned, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  defp reserve_nif(_path, _bytes), do: :erlang.nif_error(:nif_not_loaded)
+  defp release_nif(_reservation), do: :erlang.nif_error(:nif_not_loaded)
+  defp reserved_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp reservations_nif(), do: :erlang.nif_error(:nif_not_loaded)
+  defp probe_writable_nif(_path, _bytes, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  defp sync_fs_nif(_path, _mode), do: :erlang.nif_error(:nif_not_loaded)
+  defp backing_device_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp device_info_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp diskstats_nif(), do: :erlang.nif_error(:nif_not_loaded)
+  defp path_diskstats_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp raid_info_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp partitions_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp device_health_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp encryption_status_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp volume_dirty_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
+  defp media_ready_nif(_path), do: :erlang.nif_error(:nif_not_loaded)
 
   @doc """
   Retrieves disk space statistics for the given `path`.
@@ -56,25 +157,212 @@ defmodule DiskSpace do
 
     * `:used` - the number of bytes currently used (total - free).
 
+    * `:is_quota_limited` - on Windows only, whether `:available` is less than `:free`, which
+      there means that a quota limits the current user; see `quota_info/1`. Elsewhere the
+      difference is mostly space reserved for the root user, and the key is absent.
+
     Returns `{:error, info}` if the operation fails, where `info` is a map with keys `:reason` and `:info`; `:reason` is always an atom, `:info` provides more information or is `nil`, depending on what is reported by the NIF.
 
   ## Options
 
     * `:humanize` (`nil`, `:binary`, or `:decimal`) - whether to convert byte counts into human-readable strings.
-      Defaults to `nil`. If non-`nil`, the atom denotes the base used for human-readable formatting. See `humanize/2`.
+      Defaults to `nil`, or the `:humanize` set by `configure/1`. If non-`nil`, the atom denotes the base used for human-readable formatting. See `humanize/2`.
+
+    * `:btrfs_details` (boolean) - whether to add a `:btrfs` key describing how a btrfs
+      filesystem allocates its space, whose raw figures above don't tell how much can still be
+      written with RAID1 or heavy metadata use. Defaults to `false`, as it takes an open file
+      descriptor and a few ioctls. The `:btrfs` map has the following keys, and is `nil` on
+      other filesystems:
+
+        * `:profiles` - for each kind of chunk (`:type`, one of `:data`, `:metadata`,
+          `:mixed`, `:system` and `:global_reserve`) and RAID profile (`:profile`, such as
+          `:single`, `:dup` or `:raid1`), the `:total_bytes` allocated and `:used_bytes`.
+        * `:devices` - the number of devices.
+        * `:unallocated_bytes` - the raw space of the devices not allocated to chunks yet.
+        * `:data_ratio` - the raw bytes each byte of data takes, e.g. `2.0` for RAID1.
+        * `:estimated_writable_bytes` - the data that can still be written, as estimated
+          like `btrfs filesystem usage` does.
+
+    * `:zfs_details` (boolean) - whether to add a `:zfs` key naming the ZFS dataset and the
+      properties that bound its space, read through `/dev/zfs`. Defaults to `false`. Linux
+      only. The `:zfs` map has the following keys, and is `nil` on other filesystems:
+
+        * `:dataset` - the name of the dataset, e.g. `"tank/home"`.
+        * `:used_bytes`, `:available_bytes`, `:referenced_bytes` - its properties of the same
+          name.
+        * `:quota_bytes`, `:reservation_bytes`, `:refquota_bytes`, `:refreservation_bytes` -
+          its limits and reservations, `nil` when unset.
+        * `:pool_available_bytes` - the space available to the root dataset of its pool.
+        * `:limited_by` - `:dataset` if a quota leaves it less space than its pool has,
+          `:pool` otherwise.
+
+      The stat fails with `{:error, %{reason: :zfs_unavailable, info: nil}}` if the zfs
+      module is not loaded.
+
+    * `:apfs_details` (boolean) - whether to add an `:apfs` key naming the APFS container of
+      the volume, whose volumes all share its free space: summing the `:available` of each
+      counts it as many times. Defaults to `false`. macOS only. The `:apfs` map has the
+      following keys, and is `nil` on other filesystems and platforms:
+
+        * `:container` - the disk of the container, e.g. `"disk3"`, the same for all of its
+          volumes.
+        * `:volume` - the disk of the volume, e.g. `"disk3s5"`.
+        * `:container_free` - the free space of the container, in bytes.
+
+    * `:tmpfs_details` (boolean) - whether to add a `:tmpfs` key telling how a memory-backed
+      filesystem (tmpfs or ramfs), whose space competes with RAM, relates to it. Defaults to
+      `false`. Linux only. The `:tmpfs` map has the keys that `list_mounts/1` adds to such
+      mounts, and is `nil` on other filesystems and platforms.
+
+    * `:volume_id` (boolean) - whether to add the keys below, identifying the filesystem
+      holding `path` across mounts and reboots, e.g. for inventories. Defaults to `false`, as
+      it takes a few more syscalls. Both are `nil` for filesystems without an identifier,
+      such as tmpfs or NFS, and on the BSDs.
+
+        * `:volume_id` - the UUID of the filesystem on Linux, as in `/dev/disk/by-uuid`, the
+          volume UUID on macOS and the GUID of the volume on Windows, as the lowercase
+          canonical text of a UUID; identifiers that aren't one, such as the serial number of
+          a FAT filesystem on Linux, are lowercased.
+        * `:volume_id_raw` - the same as the platform gives it, e.g.
+          `"\\\\?\\Volume{6b29fc40-ca47-1067-b31d-00dd010662da}\\"` on Windows.
+
+    * `:volume_dirty` (boolean) - whether to add a `:volume_dirty` key telling whether the
+      volume has its dirty bit set, as `volume_dirty/1` does. Defaults to `false`. Windows
+      only; the key is `nil` on other platforms and where the bit is not to be read.
+
+    * `:media` (boolean) - whether to add a `:media` key telling what kind of drive the
+      volume holding `path` is on, so that destinations can be picked sensibly: `:fixed`,
+      `:removable`, `:usb_fixed` (a USB hard disk or SSD, which Windows counts as fixed),
+      `:cdrom`, `:ramdisk`, `:remote` or `:unknown`. Defaults to `false`. Windows only; the
+      key is `nil` on other platforms.
+
+    * `:scheduler` (`:dirty_io`, `:dirty_cpu` or `:normal`) - which scheduler of the BEAM
+      the stat runs on. Defaults to `:dirty_io`, which suits any filesystem, a slow network
+      one included. On tmpfs or a local disk the stat takes microseconds, less than handing
+      it to a dirty scheduler does, and `:normal` runs it on the scheduler of the caller
+      instead; `bench/scheduler.exs` compares them. Only pass `:normal` for paths known to
+      be local and fast: a stat that blocks, e.g. on a hung NFS server or a spun-down disk,
+      blocks the scheduler and every process waiting for it with it. A stat on a normal
+      scheduler that takes longer than `:budget_ms` counts as a `:budget_exceeded`
+      failure for `nif_stats/0`, and debug builds of the NIF fail it with
+      `{:error, %{reason: :budget_exceeded, info: %{elapsed_us: us, budget_ms: ms}}}`, so
+      that such a path shows in development. `:dirty_cpu` runs it on a dirty CPU
+      scheduler, leaving the dirty IO ones to IO that takes long.
+
+    * `:budget_ms` (non-negative integer) - with `scheduler: :normal`, how long the stat
+      may take, in milliseconds. Defaults to `1`.
+
+    * `:sync` (`:none`, `:syncfs` or `:sync`) - whether to flush what is waiting to be
+      written before statting, as the space of files just deleted may only show as free once
+      that is. `:syncfs` flushes the filesystem holding `path`, with `syncfs(2)` on Linux and
+      `sync(2)` elsewhere on Unix, which flushes all of them as `:sync` does everywhere. On
+      Windows both flush the volume, which takes administrator rights; without them the
+      stat goes ahead unflushed. Defaults to `:none`. Otherwise the map also has the keys:
+
+        * `:duration_ms` - how long flushing and statting took, in milliseconds, as
+          flushing can take long.
+        * `:sync_warning` - the POSIX name of the error flushing was refused with, such as
+          `:eacces`, when it was skipped; absent otherwise.
+
+      Flushing may fail, e.g. with `:eio` if data could not be written back, which fails
+      the stat with `{:error, %{reason: posix, info: %{errno: errno, errstr: errstr}}}`.
   """
 
   # no point in a guard, as the stub function is replaced and
   # lib.rs already checks the type of the path argument
   def stat(path, opts \\ []) when is_bitstring(path) and is_list(opts) do
-    humanize = Keyword.get(opts, :humanize, nil)
+    humanize = Keyword.get_lazy(opts, :humanize, &default_humanize_nif/0)
+    sync = Keyword.get(opts, :sync, :none)
+    started = System.monotonic_time()
 
-    path
-    |> stat_fs()
-    |> reshape_error_tuple()
-    |> then(fn stats -> if not is_nil(humanize), do: humanize(stats, humanize), else: stats end)
+    with {:ok, sync_warning} <- sync_fs(path, sync) do
+      path
+      |> stat_on(Keyword.get(opts, :scheduler, :dirty_io), Keyword.get(opts, :budget_ms))
+      |> reshape_error_tuple()
+      |> put_quota_limited()
+      |> then(fn stats -> if not is_nil(humanize), do: humanize(stats, humanize), else: stats end)
+      |> put_synced(sync, sync_warning, started)
+      |> put_details(:btrfs, Keyword.get(opts, :btrfs_details, false), &btrfs_details_nif/1, path)
+      |> put_details(:zfs, Keyword.get(opts, :zfs_details, false), &zfs_details_nif/1, path)
+      |> put_details(:apfs, Keyword.get(opts, :apfs_details, false), &apfs_details_nif/1, path)
+      |> put_details(:tmpfs, Keyword.get(opts, :tmpfs_details, false), &tmpfs_details_nif/1, path)
+      |> put_volume_id(Keyword.get(opts, :volume_id, false), path)
+      |> put_volume_dirty(Keyword.get(opts, :volume_dirty, false), path)
+      |> put_details(:media, Keyword.get(opts, :media, false), &media_nif/1, path)
+    end
+  end
+
+  defp stat_on(path, :dirty_io, _budget_ms), do: stat_fs(path)
+
+  defp stat_on(path, :normal, budget_ms)
+       when is_nil(budget_ms) or (is_integer(budget_ms) and budget_ms >= 0),
+       do: stat_fs_fast(path, budget_ms)
+
+  defp stat_on(_path, :normal, _budget_ms), do: {:error, :invalid_option, :budget_ms}
+  defp stat_on(path, :dirty_cpu, _budget_ms), do: stat_fs_dirty_cpu(path)
+  defp stat_on(_path, _scheduler, _budget_ms), do: {:error, :invalid_option, :scheduler}
+
+  defp sync_fs(_path, :none), do: {:ok, nil}
+
+  defp sync_fs(path, sync) when sync in [:syncfs, :sync],
+    do: path |> sync_fs_nif(sync) |> reshape_error_tuple()
+
+  defp sync_fs(_path, _sync), do: {:error, %{reason: :invalid_option, info: :sync}}
+
+  defp put_synced({:ok, stats}, sync, sync_warning, started) when sync != :none do
+    duration_ms =
+      System.convert_time_unit(System.monotonic_time() - started, :native, :millisecond)
+
+    stats = Map.put(stats, :duration_ms, duration_ms)
+
+    case sync_warning do
+      nil -> {:ok, stats}
+      warning -> {:ok, Map.put(stats, :sync_warning, warning)}
+    end
+  end
+
+  defp put_synced(result, _sync, _sync_warning, _started), do: result
+
+  defp put_details({:ok, stats}, key, true, details_nif, path) do
+    with {:ok, details} <- path |> details_nif.() |> reshape_error_tuple() do
+      {:ok, Map.put(stats, key, details)}
+    end
+  end
+
+  defp put_details(result, _key, _enabled, _details_nif, _path), do: result
+
+  defp put_volume_id({:ok, stats}, true, path) do
+    with {:ok, volume_id} <- path |> volume_id_nif() |> reshape_error_tuple() do
+      {:ok, Map.merge(stats, volume_id)}
+    end
+  end
+
+  defp put_volume_id(result, _enabled, _path), do: result
+
+  defp put_volume_dirty({:ok, stats}, true, path) do
+    case volume_dirty(path) do
+      {:ok, dirty} ->
+        {:ok, Map.put(stats, :volume_dirty, dirty)}
+
+      {:error, %{reason: reason}} when reason in [:unsupported, :access_denied] ->
+        {:ok, Map.put(stats, :volume_dirty, nil)}
+
+      error ->
+        error
+    end
+  end
+
+  defp put_volume_dirty(result, _enabled, _path), do: result
+
+  defp put_quota_limited({:ok, %{available: available, free: free} = stats}) do
+    case :os.type() do
+      {:win32, _} -> {:ok, Map.put(stats, :is_quota_limited, available < free)}
+      _ -> {:ok, stats}
+    end
   end
 
+  defp put_quota_limited(result), do: result
+
   @doc """
   Same as `stat/2` (and with the same `opts` keyword-list options), but returns the `stats_map` plain Elixir map directly or raises `DiskSpace.Error` on failure.
   """
@@ -85,55 +373,2459 @@ defmodule DiskSpace do
     end
   end
 
-  defp reshape_error_tuple({:error, reason}), do: {:error, %{reason: reason, info: nil}}
-  defp reshape_error_tuple({:error, reason, info}), do: {:error, %{reason: reason, info: info}}
-  defp reshape_error_tuple({:ok, stats_map} = success) when is_map(stats_map), do: success
+  @doc """
+  Retrieves the disk space statistics of `path` like `stat/2`, bundled with what
+  `stat_fs_delta/2` needs to compare two of them.
+
+  Returns `{:ok, sample}`, where `sample` has the keys of the `stats_map` of `stat/2`
+  plus:
+
+    * `:device` - the device of `path`, as in `File.Stat`, which tells filesystems apart.
+    * `:measured_at` - the monotonic time of the sample, in milliseconds; see
+      `System.monotonic_time/1`. It only means something within the same node.
+
+  Returns `{:error, info}` like `stat/2`.
+  """
+  def stat_fs_sample(path) when is_bitstring(path) do
+    with {:ok, stats} <- stat(path),
+         {:ok, %File.Stat{major_device: device}} <- File.stat(path) do
+      {:ok, Map.merge(stats, %{device: device, measured_at: System.monotonic_time(:millisecond)})}
+    else
+      {:error, %{reason: _}} = error -> error
+      error -> reshape_error_tuple(error)
+    end
+  end
 
   @doc """
-  Converts disk space statistics coming from `stat/2` and `stat!/2` from raw byte counts to human-readable strings.
+  Computes the change from sample `a` to sample `b`, taken with `stat_fs_sample/1` or
+  `stat/2`, without touching the filesystem.
 
-  Accepts either a tuple `{:ok, stats_map}` (from `stat/2`) or a `stats_map` plain Elixir map (from a successful `stat!/2`), where the key values of `stats_map` are integer byte counts.  Transparent for `{:error, info}` tuples returned from `stat/2`.
+  Returns `{:ok, delta}`, where `delta` has the keys `:available`, `:free` and `:used`
+  with how many bytes each grew by (negative if it shrank), and `:elapsed_ms`, the time
+  between the samples. `:available_per_sec`, `:free_per_sec` and `:used_per_sec` are the
+  same changes as rates in bytes per second. Without a `:measured_at` in both samples, or
+  with no time between them, `:elapsed_ms` and the rates are `nil`.
 
-  Returns the same structure but with all byte values converted to formatted human-readable strings (e.g., `"10 GiB"`), if the `base_type` argument value is non-`nil` and one of `:binary` (default) or `:decimal`.
+  Returns `{:error, %{reason: :different_filesystem, info: %{a: device_a, b: device_b}}}`
+  if both samples have a `:device` and these differ, and
+  `{:error, %{reason: :invalid_sample, info: nil}}` if either lacks an integer
+  `:available`, `:free` or `:used`.
 
-  ## Parameters
+  ## Examples
 
-    * `stats` - either `{:ok, stats_map}` or a `stats_map` with keys like `:available`, `:free`, etc. and integer values representing bytes.
-    * `base_type` - formatting base, either `nil` (do not do anything) or `:binary` (default, powers of 1024) or `:decimal` (powers of 1000). If non-`nil`, the atom determines the unit suffixes (`KiB` vs `kB`, etc.).
+      {:ok, a} = DiskSpace.stat_fs_sample("/var")
+      Process.sleep(60_000)
+      {:ok, b} = DiskSpace.stat_fs_sample("/var")
+      {:ok, %{used_per_sec: used_per_sec}} = DiskSpace.stat_fs_delta(a, b)
+  """
+  def stat_fs_delta(%{} = a, %{} = b) do
+    keys = [:available, :free, :used]
+
+    cond do
+      not Enum.all?(keys, &(is_integer(a[&1]) and is_integer(b[&1]))) ->
+        {:error, %{reason: :invalid_sample, info: nil}}
+
+      is_map_key(a, :device) and is_map_key(b, :device) and a.device != b.device ->
+        {:error, %{reason: :different_filesystem, info: %{a: a.device, b: b.device}}}
+
+      true ->
+        elapsed_ms =
+          case {a[:measured_at], b[:measured_at]} do
+            {from, to} when is_integer(from) and is_integer(to) and from != to -> to - from
+            _ -> nil
+          end
+
+        delta = Map.new(keys, &{&1, b[&1] - a[&1]})
+
+        rates =
+          Map.new(keys, fn key ->
+            rate = if elapsed_ms, do: delta[key] * 1000 / elapsed_ms
+            {:"#{key}_per_sec", rate}
+          end)
+
+        {:ok, delta |> Map.merge(rates) |> Map.put(:elapsed_ms, elapsed_ms)}
+    end
+  end
+
+  def stat_fs_delta(_a, _b), do: {:error, %{reason: :invalid_sample, info: nil}}
+
+  @doc """
+  Configures the NIF's worker pool and the defaults of the options that would otherwise
+  be passed on every call, such as once at application start. An option passed to a
+  call still takes precedence over its default.
+
+  The worker pool is shared by `stat_fs_async/3`, the `:concurrency` of
+  `stat_fs_many/2` and watchers, so that however many of them there are, they take no
+  more than a set number of OS threads.
+
+  Threads are started when there is work for them, up to `:pool_size`, and end after 10
+  seconds without work. Jobs beyond that wait in a queue, up to `:pool_queue_limit` of
+  them; beyond that, `stat_fs_async/3` returns `{:error, %{reason: :overloaded}}`, a
+  path of `stat_fs_many/2` gets that as its result, and a watcher skips the sample.
+  Lowering `:pool_size` takes effect as running jobs finish.
+
+  The settings not given are left as they are, and a default given as `nil` goes back
+  to the built-in one. The settings are changed all at once: `get_config/0` and the
+  calls made meanwhile see them either all before or all after. Returns `:ok`, or
+  `{:error, info}` without changing any setting if an option is invalid.
+
+  ## Options
+
+    * `:pool_size` (positive integer) - the most threads to run jobs on at once.
+      Defaults to `8`.
+
+    * `:pool_queue_limit` (positive integer) - the most jobs to keep waiting for a
+      thread. Defaults to `4096`.
+
+    * `:timeout` (non-negative integer, milliseconds) - the default `:timeout` of
+      `stat_fs_many/2`, `stat_all_async/3`, `watch/2` and `watch_all_mounts/1`.
+
+    * `:exclude` - the default `:exclude` of `list_mounts/1`, `snapshot/1`,
+      `stat_all_async/3` and `watch_all_mounts/1`, in place of their own.
+
+    * `:humanize` (`:binary` or `:decimal`) - the default `:humanize` of `stat/2` and
+      `stat!/2`.
 
   ## Examples
-      iex> DiskSpace.humanize({:ok, %{free: 123456789}}, nil)
-      {:ok, %{free: 123456789}}
 
-      iex> DiskSpace.humanize({:ok, %{free: 123456789}}, :binary)
-      {:ok, %{free: "117.74 MiB"}}
+      :ok = DiskSpace.configure(pool_size: 32, pool_queue_limit: 10_000)
+      :ok = DiskSpace.configure(timeout: 2_000, exclude: [:pseudo, :squashfs_loop])
+  """
+  def configure(opts) when is_list(opts) or is_map(opts) do
+    case configure_nif(Map.new(opts)) do
+      :ok -> :ok
+      error -> reshape_error_tuple(error)
+    end
+  end
 
-      iex> DiskSpace.humanize(%{total: 1000000}, :decimal)
-      %{total: "1 MB"}
+  @doc """
+  Returns the settings of `configure/1` as a map with the keys `:pool_size`,
+  `:pool_queue_limit`, `:timeout`, `:exclude` and `:humanize`, the defaults left unset
+  being `nil`.
+  """
+  def get_config, do: get_config_nif()
 
-      iex> DiskSpace.humanize({:error, :eio}, :binary)
-      {:error, :eio}
+  @doc """
+  Returns the NIF's own counters since it was loaded or `nif_stats_reset/0` was last
+  called, for debugging without instrumenting every call.
 
-      iex> DiskSpace.stat("/tmp") |> DiskSpace.humanize
+  Returns a map with the keys `:stat_fs`, each stat of a path, whichever function it
+  was made for; `:du`, each walk; `:list_mounts`, each read of the mount table; and
+  `:watcher_tick`, each tick of a watcher. Each is a map with the following keys:
+
+    * `:calls` - how many there were.
+    * `:failures` - how many failed, as a map from the reason of their error, such as
+      `:timeout` for a stat or `:overloaded` for a tick the pool had no room for, to
+      their count. A stat on a normal scheduler that took longer than a millisecond
+      counts as failing with `:budget_exceeded`; see the `:scheduler` option of
+      `stat/2`.
+    * `:latency_us` - how long they took in microseconds: a map of the `:sum`, the `:max`
+      and the `:histogram`, a list of `{upper_bound, count}` for the bounds 10, 100 and so
+      on up to 10 seconds, then `:infinity`.
 
+  The map also has the key `:stat_fs_cached`, a map of the `:hits` and `:misses` of
+  `stat_fs_cached/2`, a call that waits for the stat of another counting as a hit.
+
+  The counters are read one at a time while calls go on, so they may be off by those in
+  flight. They cost a few atomic increments per call, and can be compiled out by
+  building the crate without its default `telemetry` feature, in which case this
+  function returns `{:error, %{reason: :telemetry_disabled}}`.
+
+  ## Examples
+
+      %{stat_fs: %{calls: calls, failures: failures}} = DiskSpace.nif_stats()
   """
-  def humanize(_, base_type \\ :binary)
+  def nif_stats do
+    case nif_stats_nif() do
+      %{} = stats -> stats
+      error -> reshape_error_tuple(error)
+    end
+  end
 
-  def humanize(tagged_tuple, nil) when is_tuple(tagged_tuple), do: tagged_tuple
+  @doc """
+  Sets the counters of `nif_stats/0` back to zero. Returns `:ok`.
+  """
+  def nif_stats_reset do
+    case nif_stats_reset_nif() do
+      :ok -> :ok
+      error -> reshape_error_tuple(error)
+    end
+  end
 
-  def humanize({:ok, stats}, base_type)
-      when is_map(stats) and base_type in [:binary, :decimal],
-      do: {:ok, humanize(stats, base_type)}
+  @doc """
+  Stats `path` `iterations` times in a row as `stat/2` does and reports how long that took,
+  for telling where `stat/2` is slow, e.g. on a NAS: run it there and paste its output into
+  the report.
 
-  def humanize(stats, base_type) when is_map(stats) and base_type in [:binary, :decimal],
-    do:
-      Enum.map(
-        stats,
-        fn {k, v} -> {k, humanize_bytes(v, base_type)} end
-      )
-      |> Map.new()
+  Returns `{:ok, report}`, a map with the following keys:
 
-  def humanize({:error, _} = failure, _), do: failure
+    * `:iterations`, `:encode` - as given.
+    * `:backend` - the call that asked the OS: `"statfs"` on Linux, `"statvfs"` on macOS and
+      the BSDs, `"GetDiskFreeSpaceExW"` on Windows.
+    * `:failures` - how many of the stats failed.
+    * `:latency_ns` - a map of the `:min`, `:median`, `:p99` and `:max` latencies of the
+      stats, in nanoseconds.
+    * `:allocations` - a map of the `:total` allocations the stats made in the NIF, and
+      their mean `:per_call`.
+
+  The stats are not counted by `nif_stats/0`, and `path` is decoded once before them.
+
+  The benchmark is behind the `bench` feature of the crate, off by default, which adds
+  nothing to builds without it. To turn it on:
+
+      config :disk_space, DiskSpace, features: ["bench"]
+
+  Without it, returns `{:error, %{reason: :bench_disabled, info: nil}}`.
+
+  ## Options
+
+    * `:encode` (boolean) - whether each stat is also encoded into the map `stat/2`
+      returns, to see the cost of building it on top of that of the call. Defaults to
+      `false`, which leaves the figures unencoded.
+
+  ## Examples
+
+      {:ok, %{latency_ns: %{median: median, p99: p99}}} = DiskSpace.bench_stat("/mnt/nas", 10_000)
+  """
+  def bench_stat(path, iterations, opts \\ [])
+      when is_bitstring(path) and is_integer(iterations) and iterations > 0 and is_list(opts) do
+    path
+    |> bench_stat_nif(iterations, Keyword.get(opts, :encode, false))
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Retrieves the disk space statistics of `path` like `stat/2`, reusing those of an
+  earlier call if they are no older than `ttl_ms` milliseconds, for the many callers of
+  a busy application that would otherwise stat the same path over and over.
+
+  The cache is shared by all processes and keyed by `path` as given, so a hit costs no
+  syscall. Concurrent calls that miss on the same path wait for a single stat and share
+  its result. Errors are cached like successes. At most 4096 paths are kept, the least
+  recently used making way for a new one; `cache_invalidate/1` drops them sooner, and
+  `nif_stats/0` counts the hits and misses.
+
+  ## Examples
+
+      {:ok, %{available: available}} = DiskSpace.stat_fs_cached("/var/lib/data", 1_000)
+  """
+  def stat_fs_cached(path, ttl_ms)
+      when is_bitstring(path) and is_integer(ttl_ms) and ttl_ms >= 0 do
+    path
+    |> stat_fs_cached_nif(ttl_ms)
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Drops the cached statistics of `path` from `stat_fs_cached/2`, however it was spelled
+  when cached, such as `"/data"` and `"/data/"`, or with `:all`, every cached path. A
+  stat under way as it is called is not cached either. Returns `:ok`.
+  """
+  def cache_invalidate(path) when is_bitstring(path) or path == :all do
+    case cache_invalidate_nif(path) do
+      :ok -> :ok
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Opens the directory `path` for `stat_target/1`, which stats it over and over without
+  decoding, resolving and checking `path` each time, for a directory statted hundreds of
+  times per second. `bench/stat_target.exs` compares the two.
+
+  Returns `{:ok, target}`, which holds an open descriptor of the directory on Unix, or the
+  volume name of its root on Windows, until passed to `close_target/1` or garbage
+  collected. Returns `{:error, info}` like `stat/2` if `path` is no directory or can't be
+  opened.
+
+  The descriptor keeps the filesystem mounted: `umount` fails with `EBUSY` while the target
+  is open, and after a lazy `umount -l` the target reports the detached filesystem, not
+  whatever is mounted at `path` since. Close it once done.
+
+  ## Examples
+
+      {:ok, target} = DiskSpace.open_target("/var/lib/data")
+      {:ok, %{available: available}} = DiskSpace.stat_target(target)
+  """
+  def open_target(path) when is_bitstring(path) do
+    path
+    |> open_target_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Retrieves the disk space statistics of the directory of `target`, opened with
+  `open_target/1`, with a single `fstatfs` (`fstatvfs` but on Linux), or
+  `GetDiskFreeSpaceExW` on Windows.
+
+  Returns `{:ok, stats}` with the keys of `stat/2` without options, in bytes, and counted
+  as a stat by `nif_stats/0`. Returns `{:error, %{reason: :unmounted, info: info}}` if the
+  filesystem is gone, as after a forced unmount or a USB disk pulled out, where `info` has
+  the `:errno` and `:errstr` of the failed call, and `{:error, %{reason: :closed, info:
+  nil}}` if `target` was closed.
+
+  ## Examples
+
+      {:ok, %{available: _, free: _, total: _, used: _}} = DiskSpace.stat_target(target)
+  """
+  def stat_target(target) do
+    target
+    |> stat_target_nif()
+    |> reshape_error_tuple()
+    |> put_quota_limited()
+  end
+
+  @doc """
+  Closes `target`, letting go of the directory it holds open without waiting for the
+  garbage collector. `stat_target/1` fails with `:closed` from then on. Returns `:ok`,
+  also if `target` was closed already, and right away even while a stat of `target`
+  hangs, the directory then being let go of once that stat returns.
+  """
+  def close_target(target), do: close_target_nif(target)
+
+  @doc """
+  Retrieves the disk quota of a user (`{:user, uid}`), group (`{:group, gid}`) or project
+  (`{:project, projid}`) on the filesystem holding `path`, for when the headroom that matters
+  is theirs rather than the filesystem's. Linux only.
+
+  Returns `{:ok, quota}`, where `quota` is a map with the following keys:
+
+    * `:bytes_used`, `:inodes_used` - the space and inodes charged to the user, group or
+      project.
+    * `:bytes_soft_limit`, `:bytes_hard_limit`, `:inodes_soft_limit`, `:inodes_hard_limit` -
+      their limits, `nil` for none.
+    * `:bytes_grace_expires`, `:inodes_grace_expires` - the Unix time at which the grace
+      period over the soft limit ends, `nil` while under it.
+
+  The quota is read with `quotactl(2)` from the block device the filesystem is mounted from,
+  with `Q_XGETQUOTA` on XFS and `Q_GETQUOTA` elsewhere. Project quotas, as set with
+  `xfs_quota` or `chattr -p`, need a filesystem mounted with `prjquota` (`pquota` on XFS).
+  Reading that of another user or group, or of a project, takes `CAP_SYS_ADMIN`.
+
+  Returns `{:error, %{reason: :quotas_not_enabled, info: nil}}` if the filesystem has no
+  quota accounting, `{:error, %{reason: :quotactl_failed, info: %{errno: errno, errstr: errstr}}}`
+  if `quotactl(2)` fails otherwise, and `{:error, %{reason: :unsupported, info: nil}}` on other
+  platforms.
+
+  ## Examples
+
+      {:ok, %{bytes_used: used, bytes_hard_limit: limit}} =
+        DiskSpace.quota("/srv/home/alice", {:user, 1001})
+  """
+  def quota(path, {kind, id})
+      when is_bitstring(path) and kind in [:user, :group, :project] and is_integer(id) and
+             id >= 0 do
+    path
+    |> quota_nif(kind, id)
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Tells whether the VM runs in a container, and what limits the space of the layer it writes
+  to. In a container, `stat/2` of `"/"` reports the host filesystem under the overlay, while
+  writes stop at whatever storage quota the runtime imposed, such as the XFS project quota of
+  Docker's `overlay2` driver with `pquota`. Linux only.
+
+  Returns `{:ok, context}`, where `context` is a map with the following keys, any of them
+  `:unknown` where it can't be told, as the container-only ones are on bare metal:
+
+    * `:containerized` - whether in a container, told by `/.dockerenv`, `/run/.containerenv`,
+      the cgroup paths of `/proc/self/cgroup`, `KUBERNETES_SERVICE_HOST`, or an overlay root.
+    * `:runtime` - `:kubernetes`, `:podman`, `:docker`, `:containerd` or `:lxc`.
+    * `:root_fs_type` - the filesystem type of `"/"`, such as `"overlay"`.
+    * `:upperdir` - the upperdir of an overlay root, the directory its writes go to.
+    * `:upperdir_device` - the `"major:minor"` device of the upperdir, if it is visible from
+      where the VM runs, which it usually isn't from inside the container.
+    * `:project_id` - the project of the writable layer (the upperdir, or `"/"` if not an
+      overlay), `nil` for none.
+    * `:project_quota` - the quota of that project as returned by `quota/2`, `nil` if none
+      applies.
+
+  Returns `{:error, %{reason: :unsupported, info: nil}}` on other platforms.
+
+  ## Examples
+
+      {:ok, %{containerized: true, project_quota: %{bytes_hard_limit: limit}}} =
+        DiskSpace.container_context()
+  """
+  def container_context do
+    container_context_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Retrieves the NTFS quota of the current user on the volume holding `path`, the usual reason
+  `stat/2` reports less space `:available` than `:free` on Windows. Windows only.
+
+  Returns `{:ok, quota}`, where `quota` is a map with the following keys:
+
+    * `:enforced` - whether the volume denies space over the limit, rather than only tracking
+      its use.
+    * `:limit_bytes` - the limit of the user, `nil` for none.
+    * `:used_bytes` - the space charged to the user.
+    * `:default_limit_bytes` - the limit of users given none of their own, `nil` for none.
+
+  The quota is read through the `IDiskQuotaControl` COM interface, on a thread of its own.
+
+  Returns `{:error, %{reason: :quotas_disabled, info: nil}}` if the volume neither tracks nor
+  enforces quotas, `{:error, %{reason: :winapi_failed, info: %{errno: code, errstr: errstr}}}`
+  if a call fails otherwise, and `{:error, %{reason: :unsupported, info: nil}}` on other
+  platforms.
+
+  ## Examples
+
+      {:ok, %{limit_bytes: limit, used_bytes: used}} = DiskSpace.quota_info("D:\\Shares")
+  """
+  def quota_info(path) when is_bitstring(path) do
+    path
+    |> quota_info_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Retrieves the space of the volume holding `path` both in all and as the current user sees
+  it, with the geometry of the volume. Windows only.
+
+  Returns `{:ok, info}`, where `info` is a map with the following keys:
+
+    * `:api` - the API the figures come from: `:get_disk_space_information`
+      (`GetDiskSpaceInformationW`, Windows 10 1809 and later), or `:get_disk_free_space_ex`
+      (`GetDiskFreeSpaceExW`) where it is missing or fails.
+    * `:actual_total`, `:actual_available` - the size and free space of the volume, in bytes.
+      `:actual_total` is `nil` from `:get_disk_free_space_ex`.
+    * `:caller_total`, `:caller_available` - the same as limited for the current user, by
+      quotas including those of quota-managed folders, which only
+      `:get_disk_space_information` knows of.
+    * `:bytes_per_sector`, `:allocation_unit_bytes` - the sector and cluster sizes.
+
+  Returns `{:error, %{reason: :winapi_failed, info: %{errno: code, errstr: errstr}}}` if the
+  fallback fails too, and `{:error, %{reason: :unsupported, info: nil}}` on other platforms.
+
+  ## Examples
+
+      {:ok, %{caller_available: available, api: api}} =
+        DiskSpace.space_information("C:\\Users\\Public")
+  """
+  def space_information(path) when is_bitstring(path) do
+    path
+    |> space_information_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Retrieves the capacity of the volume holding `path` as Finder reports it, which counts
+  purgeable space (caches, local Time Machine snapshots) as available where `stat/2` doesn't.
+  macOS 10.13 and later only.
+
+  Returns `{:ok, capacity}`, where `capacity` is a map with the following keys, in bytes:
+
+    * `:total_capacity`, `:available_capacity` - the size and available space of the volume.
+    * `:important_capacity` - the space available for important usage, purgeable space
+      included; what Finder shows as available.
+    * `:opportunistic_capacity` - the space available for opportunistic usage, such as
+      prefetching, which the system won't purge for.
+    * `:purgeable` - how much more `:important_capacity` is than `:available_capacity`.
+    * `:statvfs` - the `stats_map` of `stat/2`, for comparison.
+
+  The figures are resource values of the `CFURL` of `path`, read through CoreFoundation.
+
+  Returns `{:error, info}` like `stat/2` if the path can't be statted, and
+  `{:error, %{reason: :unsupported, info: nil}}` on older macOS versions and other platforms.
+
+  ## Examples
+
+      {:ok, %{important_capacity: available, purgeable: purgeable}} =
+        DiskSpace.capacity_details("/Users/alice")
+  """
+  def capacity_details(path) when is_bitstring(path) do
+    path
+    |> capacity_details_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Lists the snapshots of the APFS volume holding `path`, mostly the local snapshots of Time
+  Machine, which often hold tens of gigabytes that nothing else accounts for. macOS only.
+
+  Returns `{:ok, %{count: count, snapshots: snapshots}}`, where each of `snapshots` is a map
+  with the keys `:name`, e.g. `"com.apple.TimeMachine.2025-06-01-101500.local"`, and
+  `:created_at`, its creation time in Unix seconds. The space each holds is not reported, as
+  only private interfaces tell it.
+
+  The snapshots are listed with `fs_snapshot_list(2)`, which takes Full Disk Access: without
+  it, returns `{:error, %{reason: :requires_full_disk_access, info: nil}}`. Returns
+  `{:error, %{reason: :not_apfs, info: nil}}` on other filesystems, and
+  `{:error, %{reason: :unsupported, info: nil}}` on other platforms.
+
+  ## Examples
+
+      {:ok, %{count: count, snapshots: snapshots}} = DiskSpace.apfs_snapshots("/")
+  """
+  def apfs_snapshots(path) when is_bitstring(path) do
+    path
+    |> apfs_snapshots_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Explains where the space of the ext2/3/4 filesystem holding `path` goes before its users
+  get any, the usual reason `stat/2` reports less space than the disk has. Linux only.
+
+  Returns `{:ok, overhead}`, where `overhead` is a map with the following keys:
+
+    * `:fs_type` - the filesystem type.
+    * `:reserved_blocks`, `:reserved_bytes` - the free space only root may use, as set with
+      `tune2fs -m` or `-r`.
+    * `:reserved_percent` - the reserved blocks as a percentage of the blocks of the device.
+    * `:overhead_bytes` - the space of the device taken by the filesystem's metadata, left out
+      of its `:total`.
+    * `:non_root_total` - the part of `:total` a user other than root can ever use.
+
+  The reserved blocks are told apart from ext4's own few reserved clusters, which nobody may
+  use and which are counted in neither. `:reserved_percent` and `:overhead_bytes` are `nil`
+  if the size of the device is not in sysfs, and all but `:fs_type` are `nil` on other
+  filesystems. Returns `{:error, %{reason: :unsupported, info: nil}}` on other platforms.
+
+  ## Examples
+
+      {:ok, %{reserved_percent: 5.0, non_root_total: usable}} =
+        DiskSpace.filesystem_overhead("/srv")
+  """
+  def filesystem_overhead(path) when is_bitstring(path) do
+    path
+    |> filesystem_overhead_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Retrieves the usage and limits of the qgroup of the btrfs subvolume holding `path`, for
+  when each tenant gets a subvolume with a qgroup limit and the free space of the filesystem
+  says nothing about theirs. Linux only.
+
+  Returns `{:ok, qgroup}`, where `qgroup` is a map with the following keys:
+
+    * `:subvolume_id` - the id of the subvolume.
+    * `:qgroup_id` - the id of its qgroup as `btrfs qgroup show` writes it, e.g. `"0/257"`.
+    * `:referenced_bytes` - the bytes the subvolume references, shared or not.
+    * `:exclusive_bytes` - the bytes only the subvolume references.
+    * `:max_referenced_bytes`, `:max_exclusive_bytes` - their limits, `nil` for none.
+    * `:parents` - the ids of the higher-level qgroups the qgroup belongs to, e.g.
+      `["1/100"]`, for aggregating nested subvolumes.
+    * `:inconsistent` - whether the figures are awaiting a rescan.
+
+  Reading the quota tree takes `CAP_SYS_ADMIN`; without it, this function returns
+  `{:error, %{reason: :btrfs_ioctl_failed, info: %{errno: errno, errstr: errstr}}}`.
+
+  Returns `{:error, %{reason: :qgroups_disabled, info: nil}}` if quotas are not enabled on
+  the filesystem, `{:error, %{reason: :qgroup_not_found, info: nil}}` if the subvolume has
+  no qgroup, and `{:error, %{reason: :not_btrfs, info: nil}}` for a path on another
+  filesystem.
+
+  ## Examples
+
+      {:ok, %{exclusive_bytes: used, max_exclusive_bytes: limit}} =
+        DiskSpace.btrfs_qgroup("/srv/tenants/acme")
+  """
+  def btrfs_qgroup(path) when is_bitstring(path) do
+    path
+    |> btrfs_qgroup_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Retrieves the disk space statistics of `path` like `stat/2`, but without blocking the
+  calling process: returns `:ok` right away, and `pid` later receives
+  `{:disk_space, ref, result}`, where `result` is what `stat/2` would have returned,
+  errors included.
+
+  The stat runs on the NIF's worker pool rather than on a dirty scheduler, so a slow or
+  hung network mount holds up neither the caller nor other NIF calls; see `configure/1`.
+  Should `pid` have exited by the time the stat completes, the result is dropped.
+
+  Returns `{:error, %{reason: :overloaded, info: nil}}` if the pool's queue is full, and
+  `{:error, info}` if no thread could be started.
+
+  ## Examples
+
+      ref = make_ref()
+      :ok = DiskSpace.stat_fs_async("/mnt/nfs", self(), ref)
+
+      receive do
+        {:disk_space, ^ref, {:ok, %{available: available}}} -> available
+        {:disk_space, ^ref, {:error, info}} -> info
+      end
+  """
+  def stat_fs_async(path, pid, ref) when is_bitstring(path) and is_pid(pid) do
+    case stat_fs_async_nif(path, pid, ref) do
+      :ok -> :ok
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Retrieves the disk space statistics of each of `paths` in a single NIF call, saving
+  the per-call overhead of `stat/2` when polling many paths.
+
+  Returns a list of `{path, result}` tuples in the order of `paths`, where `result` is
+  what `stat/2` would have returned for `path`; a path that cannot be statted gets an
+  error of its own rather than failing the whole batch. Returns `{:error, info}` only
+  for malformed options.
+
+  ## Options
+
+    * `:unique` (boolean) - whether to stat each distinct path only once, repeating its
+      result at every position it appears in. Defaults to `false`.
+
+    * `:concurrency` (positive integer) - how many paths to stat at a time, on the
+      worker pool of `configure/1`, while the calling process waits. A path the pool has
+      no room to queue gets `{:error, %{reason: :overloaded, info: nil}}`. Defaults to
+      `1`.
+
+    * `:timeout` (non-negative integer, milliseconds) - how long each stat may take,
+      counted from when it starts, so that a hung network mount delays only its own
+      entry. A path that takes longer gets
+      `{:error, %{reason: :timeout, info: %{timeout: ms}}}`; its stat is left to finish
+      on its own, no longer counting against `:concurrency`. Defaults to no timeout.
+
+    * `:columnar` (boolean) - whether to return the figures as one list each rather than
+      a map per path: `%{paths: paths, available: [...], free: [...], total: [...],
+      used: [...], errors: errors}`, the figures in the order of `paths`, `nil` for a
+      path that cannot be statted, and `errors` the `{path, {:error, info}}` entries of
+      those. Cheaper to build for a large batch, and ready to feed to a time-series
+      database. Figures are in bytes. Defaults to `false`.
+
+  ## Examples
+
+      [{"/", {:ok, root}}, {"/mnt/nfs", {:error, %{reason: reason}}}] =
+        DiskSpace.stat_fs_many(["/", "/mnt/nfs"])
+
+      DiskSpace.stat_fs_many(mounts, concurrency: 8, timeout: 2_000)
+
+      %{paths: ["/", "/mnt/nfs"], available: [available, nil], errors: [{"/mnt/nfs", _}]} =
+        DiskSpace.stat_fs_many(["/", "/mnt/nfs"], columnar: true)
+  """
+  def stat_fs_many(paths, opts \\ []) when is_list(paths) and is_list(opts) do
+    case stat_fs_many_nif(paths, Map.new(opts)) do
+      results when is_list(results) -> results
+      %{paths: _} = columns -> columns
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Snapshots the disk space of every mounted filesystem in the background: returns `:ok`
+  right away, and `pid` later receives `{:disk_space_report, ref, mounts}`, shaped like
+  the reports of `watch_all_mounts/1`, each mount map also having `:duration_ms`, how
+  long its stat took, so that slow mounts stand out. A stat given up on after `:timeout`
+  counts as taking that long. Should the mount table fail to be read, `mounts` is
+  `{:error, info}` instead.
+
+  The mounts are listed and statted on the worker pool of `configure/1`, off the calling
+  process. Until `pid` has been sent the report, another call with the same `ref` returns
+  `{:error, %{reason: :already_running, info: nil}}`.
+
+  Returns `{:error, info}` straight away if the options are invalid, or if the pool is
+  overloaded.
+
+  ## Options
+
+    * `:exclude` - filters as for `list_mounts/1`. Defaults to `[:pseudo]`.
+
+    * `:concurrency`, `:timeout` - as for `stat_fs_many/2`.
+
+    * `:collapse_apfs` (boolean) - whether to report the APFS volumes of a container, which
+      share its space, as a single entry: that of the first, with an `:apfs_volumes` key
+      listing the mount points of all of them. Defaults to `false`.
+
+  ## Examples
+
+      ref = make_ref()
+      :ok = DiskSpace.stat_all_async([concurrency: 8, timeout: 2_000], self(), ref)
+
+      receive do
+        {:disk_space_report, ^ref, mounts} when is_list(mounts) ->
+          for %{mount_point: mount_point, duration_ms: ms} <- mounts, ms > 1_000,
+              do: mount_point
+      end
+  """
+  def stat_all_async(opts, pid, ref) when is_list(opts) and is_pid(pid) do
+    case stat_all_async_nif(Map.new(opts), pid, ref) do
+      :ok -> :ok
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Starts statting `path` on an interval and returns `{ref, watcher}` right away.
+
+  The NIF's worker pool (see `configure/1`) stats `path` straight away and then every
+  `:interval_ms`, sending `{:disk_space_sample, ref, %{path: path, at: at, result: result}}`
+  to the destination process each time, where `at` is the time of the sample in
+  milliseconds since the Unix epoch and `result` is what `stat/2` would have returned. A
+  path that goes missing or gets unmounted yields error samples until it is back.
+
+  The watcher also checks on each sample that `path` is still on the filesystem of its
+  first successful sample, by its device, since a volume unmounted from under `path`
+  leaves it on the parent filesystem, whose numbers would otherwise be reported as if
+  nothing happened. Each time the device changes from one sample to the next, it sends
+  `{:disk_space_event, ref, :filesystem_changed, %{old: old, new: new}}` before the
+  sample, and the fill rate starts over from the new filesystem's samples. See the
+  `:on_filesystem_change` option for what becomes of alerts.
+
+  With `:timeout`, a path whose stats keep timing out, such as one on an NFS server that
+  is down, is marked unresponsive after `:max_consecutive_timeouts` of them in a row, so
+  that it doesn't tie up a thread for the whole timeout every tick. The watcher sends
+  `{:disk_space_event, ref, :unresponsive, %{timeouts: n, retry_in_ms: ms}}` and from
+  then on only probes the path, first twice the interval later, then twice that and so
+  on up to `:backoff_max_ms`. The first probe that doesn't time out sends
+  `{:disk_space_event, ref, :responsive, %{}}` and goes back to the interval. `reset/1`
+  probes right away. Each sample has the key `:circuit`, `:responsive` or
+  `:unresponsive`.
+
+  Each sample, alert and event map also has the key `:labels`, the `:labels` option as
+  given, or `nil`.
+
+  Each sample also has the keys `:fill_rate_bytes_per_sec`, the rate at which the used
+  space grows, estimated from the samples of the last `:trend_window_ms` as the median of
+  the slopes between each pair of them so that a single outlier barely moves it, and
+  `:eta_full_seconds`, the time left until the available space runs out at that rate.
+  Both are `nil` until there are `:trend_min_samples` samples and in error samples, and
+  `:eta_full_seconds` also while the used space holds steady or shrinks. At most the
+  last 256 samples are taken into account.
+
+  The watcher stops once `unwatch/1` is called, once `watcher` and any handles to it from
+  `list_watchers/0` are garbage collected, or once the destination process has exited.
+  Ticks that a slow stat overruns are skipped rather than caught up on, as are those the
+  pool has no room to queue, and a single thread of the NIF's own schedules the ticks of
+  all watchers, so many watchers don't take a thread each. Ticks are scheduled
+  on the monotonic clock, so that a jump of the system clock neither stalls a watcher nor
+  makes it sample in a burst.
+
+  Returns `{:error, info}` straight away if `path` or the options are invalid.
+
+  ## Options
+
+    * `:interval_ms` (positive integer) - the time between samples, which
+      `set_interval/2` can change later on. Defaults to `1_000`.
+
+    * `:align_to` - `:interval` to take the samples at the multiples of `:interval_ms`
+      since the Unix epoch, such as on the full minute with `60_000`, for time series with
+      clean timestamps, the first one included; or `:none`, to take the first sample
+      straight away and each next one `:interval_ms` after the one before. Defaults to
+      `:none`.
+
+    * `:jitter_ms` (non-negative integer) - puts off each sample by a random delay of up
+      to this many milliseconds, its own for each sample, so that many watchers started
+      at once don't all stat at the same instant. Best kept well below `:interval_ms`.
+      Defaults to `0`.
+
+    * `:dest` (pid) - the process to send the samples to. Defaults to the caller.
+
+    * `:paused` (boolean) - starts the watcher paused, as by `watcher_pause/1`. Defaults
+      to `false`.
+
+    * `:start_at` (non-negative integer) - takes the first sample no earlier than this
+      time in milliseconds since the Unix epoch, as `import_watchers/1` does. Defaults to
+      `nil`, right away.
+
+    * `:message_format` - `:tuple` for the messages above; `:map` to send each as
+      `%{event: event, type: type, ref: ref, data: data}` instead, `event` being the tag
+      without its `disk_space_` prefix, such as `:sample` or `:alert`, `type` the
+      `:low` or `:recovered` of an alert or the kind of an event, `nil` for the rest, and
+      `data` the sample or event map; or `:struct`, for the same as a
+      `DiskSpace.Event` struct. Defaults to `:tuple`.
+
+    * `:labels` (any term) - passed back as is in every sample, alert and event and by
+      `watcher_info/1` and `list_watchers/0`, such as `%{tenant: "acme", role: "wal"}`
+      for routing them. Kept in a copy of its own, so it outlives the process that
+      started the watcher. Defaults to `nil`.
+
+    * `:history_size` (non-negative integer, at most `65_536`) - how many samples to keep
+      for `watcher_history/2`, at 24 bytes each, set aside up front. Defaults to `256`.
+
+    * `:trend_window_ms` (positive integer) - how far back the fill rate looks. Defaults to
+      `600_000`, ten minutes.
+
+    * `:trend_min_samples` (integer, at least 2) - how many samples in the window it takes
+      to estimate the fill rate. Defaults to `3`.
+
+    * `:notify` - which samples to send: `:always`, every one; `:on_change`, only those
+      whose available or used bytes differ from the last sample sent; or
+      `{:on_change_by, bytes}`, only those where either has moved by more than `bytes`
+      since the last sample sent, so that a slow drift still gets through once it adds up.
+      The first sample is always sent, and so is the first error sample after a successful
+      one and the other way round. Samples not sent still count towards the history and
+      the fill rate, and an exited destination process is only noticed at the next sample
+      sent. Ignored with `:alert_below`. Defaults to `:always`.
+
+    * `:alert_below` (map) - `%{bytes: n, percent: p}`, with either key or both. Instead
+      of every sample, only sends `{:disk_space_alert, ref, :low, sample}` once the
+      available space drops below `n` bytes or `p` percent of the total, and
+      `{:disk_space_alert, ref, :recovered, sample}` once it is back above both. Error
+      samples are not sent, and an exited destination process is only noticed at the next
+      alert. Defaults to `nil`, sending every sample.
+
+    * `:on_filesystem_change` - `:follow` to take the filesystem `path` is now on for
+      the watched one right away, or `:hold_alerts` to keep to the old one, holding back
+      alerts while `path` is on another, until `watcher_ack/1` is called or the old one
+      is back. Defaults to `:follow`.
+
+    * `:timeout` (non-negative integer, milliseconds) - how long each stat may take, as
+      for `stat_fs_many/2`. Defaults to no timeout.
+
+    * `:max_consecutive_timeouts` (positive integer) - with `:timeout`, how many stats in
+      a row have to time out for the path to be marked unresponsive. Defaults to `3`.
+
+    * `:backoff_max_ms` (positive integer) - the longest time between the probes of an
+      unresponsive path. Defaults to `600_000`, ten minutes.
+
+    * `:hysteresis` (map) - with `:alert_below`, `%{bytes: n, percent: p}`, with either key
+      or both: how far above the thresholds the available space has to get back before
+      `:recovered` is sent, so that space hovering around a threshold doesn't make the
+      alerts flap. Both keys add up. Defaults to `nil`, recovering right at the thresholds.
+
+    * `:debounce_samples` (positive integer) - with `:alert_below`, how many samples in a
+      row must be low, or recovered, before the alert is sent. Error samples are skipped
+      rather than breaking the run. Defaults to `1`.
+
+  ## Examples
+
+      {ref, watcher} = DiskSpace.watch("/var", interval_ms: 5_000)
+
+      receive do
+        {:disk_space_sample, ^ref, %{result: {:ok, %{available: available}}}} -> available
+      end
+
+      DiskSpace.unwatch(watcher)
+
+      {ref, _watcher} = DiskSpace.watch("/var", alert_below: %{bytes: 5 * 1024 ** 3, percent: 10})
+
+      receive do
+        {:disk_space_alert, ^ref, :low, %{result: {:ok, stats}}} -> stats
+      end
+  """
+  def watch(path, opts \\ []) when is_bitstring(path) and is_list(opts) do
+    ref = make_ref()
+
+    case watch_nif(path, Map.new(opts), ref) do
+      {:ok, watcher} -> {ref, watcher}
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Starts statting every mounted filesystem on an interval and returns `{ref, watcher}`
+  right away, like `watch/2` does for a single path.
+
+  Each tick, the mounts are listed as by `list_mounts/1` and the destination process
+  receives `{:disk_space_report, ref, mounts}`, where `mounts` has the map of each
+  mount plus `:at`, the time of its stat in milliseconds since the Unix epoch, and
+  `:result`, what `stat/2` would have returned for its mount point. A mount that fails
+  to stat gets an error result; should the mount table itself fail to be read, `mounts`
+  is `{:error, info}` instead. When the mounts differ from those of the previous tick,
+  `{:mounts_changed, ref, diff}` comes first, `diff` being shaped like the result of
+  `mounts_diff/2`. Each mount map and `diff` also have the watcher's `:labels`.
+
+  The watcher is stopped, listed and described like one from `watch/2`, its `:path`
+  being `nil`.
+
+  ## Options
+
+    * `:interval_ms`, `:align_to`, `:jitter_ms`, `:dest`, `:paused`, `:start_at`,
+      `:message_format`, `:labels` - as for `watch/2`, the reports and diffs becoming
+      `:report` and `:mounts_changed` events.
+
+    * `:exclude` - filters as for `list_mounts/1`. Defaults to `[:pseudo]`.
+
+    * `:concurrency`, `:timeout` - as for `stat_fs_many/2`, so that a hung network mount
+      holds up neither the other mounts nor the next tick for longer than `:timeout`.
+
+  ## Examples
+
+      {ref, watcher} = DiskSpace.watch_all_mounts(interval_ms: 10_000, timeout: 2_000)
+
+      receive do
+        {:disk_space_report, ^ref, mounts} when is_list(mounts) ->
+          for %{mount_point: mount_point, result: {:ok, %{available: available}}} <- mounts,
+              do: {mount_point, available}
+      end
+  """
+  def watch_all_mounts(opts \\ []) when is_list(opts) do
+    ref = make_ref()
+
+    case watch_all_mounts_nif(Map.new(opts), ref) do
+      {:ok, watcher} -> {ref, watcher}
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Stops the watcher started by `watch/2` or `watch_all_mounts/1`. Returns `:ok`, also if it has stopped already.
+
+  A sample being taken as it stops may still arrive.
+  """
+  def unwatch(watcher), do: unwatch_nif(watcher)
+
+  @doc """
+  Lists the running watchers started by `watch/2`, from any process.
+
+  Returns a list of maps shaped like those of `watcher_info/1`, each with a `:watcher`
+  handle of its own that can be passed to `unwatch/1`.
+  """
+  def list_watchers, do: list_watchers_nif()
+
+  @doc """
+  Stops every watcher, returning for each a descriptor from which `import_watchers/1`
+  starts it again, such as around a hot code upgrade of this module.
+
+  The NIF library can't hand its running watchers over to a new version of itself, as
+  the BEAM refuses to load one while the old code still has this one loaded. So the
+  watchers have to be stopped before the upgrade and started again after, which these
+  two functions do without losing a tick or sending one twice: samples being taken as
+  the watchers stop arrive before this function returns, waiting up to 5 seconds in all
+  for them, and each watcher started again takes its first sample when the old one would
+  have taken its next. The watcher handles held on to have to be dropped, so that the old
+  library can be unloaded. The history, trend and alert state start over.
+
+  Each descriptor is a map with the keys `:path`, `nil` for `watch_all_mounts/1`, `:ref`
+  and `:opts`, the options given plus `:interval_ms` as last set, `:dest`, `:paused` and
+  `:start_at`, and can be kept as a term across the upgrade.
+
+  ## Examples
+
+      descriptors = DiskSpace.export_watchers()
+      # ... upgrade ...
+      watchers = DiskSpace.import_watchers(descriptors)
+  """
+  def export_watchers, do: export_watchers_nif()
+
+  @doc """
+  Starts again the watchers stopped by `export_watchers/0`, each with the reference it
+  had, so that the messages it sends are matched as before.
+
+  Returns a list with `{ref, watcher}` for each descriptor, in order, or `{:error, info}`
+  for one that failed to start.
+  """
+  def import_watchers(descriptors) when is_list(descriptors) do
+    for %{path: path, ref: ref, opts: opts} <- descriptors do
+      result =
+        case path do
+          nil -> watch_all_mounts_nif(opts, ref)
+          path -> watch_nif(path, opts, ref)
+        end
+
+      case result do
+        {:ok, watcher} -> {ref, watcher}
+        error -> reshape_error_tuple(error)
+      end
+    end
+  end
+
+  @doc """
+  Describes the watcher started by `watch/2`.
+
+  Returns a map with the following keys:
+
+    * `:watcher` - the watcher itself.
+    * `:ref` - the reference its samples are sent with.
+    * `:path`, `:interval_ms`, `:dest` - as given to `watch/2`, the interval as last set by
+      `set_interval/2`.
+    * `:running` - `false` once it has stopped.
+    * `:labels` - as given to `watch/2`.
+    * `:state` - `:running`, `:paused` while paused by `watcher_pause/1`, or `:stopped`.
+    * `:samples` - the number of samples taken so far.
+    * `:last_sample` - the last sample sent, or `nil`.
+    * `:last_error` - the last sample whose `:result` was an error, or `nil`.
+    * `:alert_state` - with `:alert_below`, `:low` or `:ok` as of the last successful
+      sample, and `:ok` before the first; `nil` without.
+    * `:device` - the device of the filesystem the watcher is bound to, or `nil` before
+      the first successful sample and for `watch_all_mounts/1`.
+    * `:alerts_held` - `true` while `:on_filesystem_change` is `:hold_alerts` and the
+      path is on another filesystem than that.
+    * `:circuit` - `:unresponsive` while the path is marked so after timeouts in a row,
+      and `:responsive` otherwise.
+    * `:timeouts` - the number of stats in a row that have timed out.
+    * `:fill_rate_bytes_per_sec`, `:eta_full_seconds` - as of the last successful sample.
+  """
+  def watcher_info(watcher), do: watcher_info_nif(watcher)
+
+  @doc """
+  Changes the interval of the watcher started by `watch/2` or `watch_all_mounts/1` to
+  `interval_ms`, from the next sample on. The sample it was waiting for is rescheduled
+  accordingly, so lengthening the interval puts it off and shortening it may take it
+  right away. Returns `:ok`, also if the watcher has stopped.
+  """
+  def set_interval(watcher, interval_ms) when is_integer(interval_ms) and interval_ms > 0,
+    do: set_interval_nif(watcher, interval_ms)
+
+  @doc """
+  Pauses the watcher started by `watch/2` or `watch_all_mounts/1`, such as for a
+  maintenance window: it takes and sends no samples until `watcher_resume/1` is called,
+  but keeps its state, such as its history, alert state and filesystem, rather than
+  losing it as `unwatch/1` would. Returns `:ok`, also if it is paused already.
+
+  A sample being taken as it pauses may still arrive.
+  """
+  def watcher_pause(watcher), do: watcher_pause_nif(watcher)
+
+  @doc """
+  Resumes the watcher paused by `watcher_pause/1`, taking a sample right away and then
+  going back to its interval. Returns `:ok`, also if it isn't paused.
+  """
+  def watcher_resume(watcher), do: watcher_resume_nif(watcher)
+
+  @doc """
+  Binds the watcher started by `watch/2` with `on_filesystem_change: :hold_alerts` to
+  the filesystem its path is now on, acknowledging the change and resuming alerts.
+  Returns `:ok`, also if there was no change to acknowledge.
+  """
+  def watcher_ack(watcher), do: watcher_ack_nif(watcher)
+
+  @doc """
+  Makes the watcher started by `watch/2` whose path is marked unresponsive, or given a
+  path, each such watcher of that path, probe it right away rather than wait out its
+  backoff, such as once the NFS server is known to be back. It is marked responsive
+  again, and marked unresponsive anew only after `:max_consecutive_timeouts` more.
+  Returns `:ok`, also if none was unresponsive.
+
+  ## Examples
+
+      :ok = DiskSpace.reset("/mnt/nfs")
+  """
+  def reset(watcher_or_path) do
+    case reset_nif(watcher_or_path) do
+      :ok -> :ok
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Returns up to the last `count` successful samples of the watcher started by `watch/2`,
+  oldest first, as `{at, available, used}` tuples, `at` being in milliseconds since the
+  Unix epoch. The watcher keeps the last `:history_size` of them, also once stopped.
+
+  ## Examples
+
+      {_ref, watcher} = DiskSpace.watch("/var", interval_ms: 60_000, history_size: 1440)
+      sparkline = for {_at, available, _used} <- DiskSpace.watcher_history(watcher, 60), do: available
+  """
+  def watcher_history(watcher, count) when is_integer(count) and count >= 0,
+    do: watcher_history_nif(watcher, count)
+
+  @doc """
+  Computes the disk usage of the directory tree at `path`, like `du -s`.
+
+  The tree is walked natively, in parallel by several OS threads of the NIF's own (see
+  the `:workers` option), while the calling process waits for the result. Unlike a
+  dirty scheduler, these threads are not shared with other NIF calls, so `stat/2` and
+  the like stay responsive however many and however large walks are running. By
+  default symlinks are not
+  followed (each counts with its own size), and files reached through several hard
+  links are counted once.
+
+  Returns `{:ok, summary}` where `summary` is a map with the following keys:
+
+    * `:bytes` - the space allocated on disk for the tree, in bytes: the sum of
+      `st_blocks × 512` on Unix, and of the compressed/sparse on-disk sizes
+      (`GetCompressedFileSizeW`, or the length where that fails) rounded up to whole
+      clusters of the volume of `path` on Windows.
+    * `:apparent_bytes` - the sum of the lengths of the entries (`st_size`), in bytes.
+      The two figures diverge for sparse files (e.g. VM images) and for compressed files
+      (e.g. on btrfs or NTFS), where `:bytes` can be much smaller, and for many tiny
+      files, where `:bytes` is larger as each occupies at least one block. Use `:bytes`
+      to answer "how much disk does this consume" and `:apparent_bytes` for "how much
+      data will a copy transfer", e.g. for progress reporting.
+    * `:files` - the number of regular files.
+    * `:dirs` - the number of directories, including `path` itself.
+    * `:symlinks` - the number of symlinks, counted as such (see `:symlinks` below).
+    * `:other` - the number of other entries: sockets, FIFOs and devices.
+    * `:inodes` - the number of inodes a copy of the tree would create: the sum of the
+      above, so hard-linked files count once or at every link as set by
+      `:dedupe_hardlinks`. See `fits?/2`. These counts leave out what `:exclude` or
+      `:respect_ignore_files` leave out and what lies beyond the filesystem boundary
+      with `one_file_system: true`, so together they are the number of inodes the tree
+      takes up on its filesystem (bar additional hard links).
+    * `:hardlinked_saved_bytes` - the allocated bytes of the additional hard links
+      that were not counted again.
+    * `:sparse_files`, `:sparse_savings_bytes` - how many of the counted regular files
+      are sparse, and by how many bytes their lengths exceed their allocated size. On
+      Windows these are the files with the sparse attribute (`FILE_ATTRIBUTE_SPARSE_FILE`);
+      Unix has no such flag, so there they are the files allocated less than their
+      length, which includes files compressed by the filesystem.
+    * `:compression_savings_bytes` - Windows only: by how many bytes the lengths of the
+      NTFS-compressed files exceed their compressed size on disk.
+    * `:slack_bytes` - Windows only: the space allocated to files beyond their on-disk
+      size, i.e. the unused part of their last clusters. Tiny files stored within the
+      MFT have no on-disk size and no slack.
+    * `:placeholder_files`, `:placeholder_bytes` - Windows only: how many cloud
+      placeholders (e.g. OneDrive Files-On-Demand) were found, and their full lengths,
+      however `:count_placeholders` counts them.
+    * `:skipped_mounts` - the directories that were not entered because they are on
+      another filesystem, when `one_file_system: true` (empty otherwise).
+    * `:deduplicated_dirs` - the directories that were neither walked nor counted because
+      the same directory had already been entered through another path, such as a bind
+      mount of a directory of the tree inside it (e.g. `/srv/data` bound to
+      `/srv/export/data`). Which of the paths is walked depends on the order of the walk.
+      Always empty on Windows, whose mount points and junctions count as symlinks.
+    * `:excluded_entries`, `:excluded_bytes` - how many entries the `:exclude` patterns
+      matched, and their allocated size. An excluded directory counts as one entry of
+      its own size, since its content is never read.
+    * `:ignored_entries`, `:ignored_bytes` - how many entries the ignore files of
+      `:respect_ignore_files` ignored, and their allocated size, counting everything
+      inside ignored directories (`0` without the option).
+    * `:children` - only with `max_depth: n` greater than 0 and the default `shape: :tree`:
+      the subdirectories of `path`, largest `:bytes` first, each a map with `:path`,
+      `:bytes`, `:apparent_bytes`, `:files`, `:dirs` (counting the directory itself),
+      `:symlinks`, `:other` and its own `:children`, nested down to `n` levels.
+      Everything below the deepest level is counted into its closest listed ancestor.
+    * `:entries` - only with `max_depth: n` greater than 0 and `shape: :flat`: the same
+      directories as `:children`, as one list in the same order (each directory followed
+      by its subdirectories, largest first), each a map like those of `:children` with
+      its `:depth` below `path` (from `1` to `n`) instead of `:children`.
+    * `:top_files` - only with `top_files: n` greater than 0: the `n` largest regular
+      files as `{path, size}` tuples, largest first, `size` being measured as set by
+      `:top_by`. Files of equal size are ordered by path, and a file reached through
+      several hard links is listed once, under the first of its paths. With `:min_size`
+      or `:older_than`, only files meeting them are listed.
+    * `:top_dirs` - only with `top_dirs: n` greater than 0: `n` of the largest
+      directories below `path` at any depth, as `{path, size}` tuples with their
+      cumulative size, largest first.
+    * `:by_extension` - only with `by_extension: true`: a map from lowercase extension
+      (e.g. `"log"`), or `:none` for files without one, to `%{bytes: bytes, files: files}`,
+      the allocated size and number of the regular files counted above.
+    * `:by_owner` - only with `by_owner: true`: a map from owner to
+      `%{bytes: bytes, files: files}` for the regular files counted above, plus `:name`
+      with `resolve_names: true`. Owners are uids on Unix and SID strings such as
+      `"S-1-5-21-...-1001"` on Windows; files whose owner couldn't be read are under
+      `:unknown`.
+    * `:matching` - only with `:min_size` or `:older_than`: `%{bytes: b, apparent_bytes: a, files: f}`
+      for the counted regular files that meet both, while the totals above still cover
+      the whole tree.
+    * `:errors` - a list of `%{path: path, errno: errno, errstr: errstr, posix: posix}`
+      maps for the entries that could not be read (e.g. due to missing permissions),
+      sorted by path; these entries are skipped rather than aborting the walk. `:posix`
+      is the error's name as used by `:file`, e.g. `:eacces` or `:enoent` (Windows
+      errors are mapped to the closest name), or `:unknown`. At most `:max_errors`
+      entries are listed.
+    * `:error_count` - the number of entries that could not be read, including those
+      beyond `:max_errors`.
+
+  Returns `{:error, info}` if `path` itself cannot be accessed, shaped like the errors of `stat/2`.
+  `path` may also be a regular file, in which case the summary describes just that file.
+  Paths longer than Windows' 260-character `MAX_PATH` are walked as well, through their
+  `\\\\?\\` form, while the paths in the result are shaped like `path`.
+  With `on_error: :halt`, the first entry that cannot be read ends the walk with
+  `{:error, %{reason: :du_failed, info: %{path: path, errno: errno, errstr: errstr, posix: posix}}}`.
+
+  ## Options
+
+    * `:dedupe_hardlinks` (boolean) - whether to count a file reached through several
+      hard links only once. Defaults to `true`. Files are identified by device and inode
+      on Unix, and by volume serial number and file index on Windows (which requires
+      opening each file for its attributes). To bound memory use, at most 1,000,000
+      partially visited hard-linked files (about 40 MB) are tracked at a time; a file is
+      forgotten once all its links have been seen, and beyond the bound further
+      hard-linked files are counted at every link.
+    * `:one_file_system` (boolean) - whether to stay on the filesystem of `path`, like
+      `du -x`. Defaults to `false`. Directories on other devices (volumes on Windows) are
+      neither entered nor counted, and are listed in `:skipped_mounts` instead. Bind
+      mounts of the same filesystem are entered either way, but a directory already walked
+      through another path is not walked again (see `:deduplicated_dirs`).
+    * `:max_depth` (non-negative integer) - how many levels of subdirectories to break
+      the usage down into, as set by `:shape`. Defaults to `0`, which returns just the totals.
+    * `:shape` (`:tree`, `:flat` or `:summary`) - how to return the subdirectories down to
+      `:max_depth`: nested under `:children` (`:tree`), listed under `:entries` (`:flat`),
+      e.g. to insert them into a table, or not at all (`:summary`). Defaults to `:tree`.
+    * `:exclude` (list of strings) - gitignore-style glob patterns, matched against the
+      path relative to `path`. Matching entries are left out of every count, and
+      matching directories are not walked at all. Defaults to `[]`.
+      * A pattern without a slash matches the name at any depth (`"node_modules"`,
+        `"*.o"`); a pattern containing a slash is anchored at `path` (`"/target"`,
+        `"build/out"`).
+      * `*`, `?` and `[a-z]` / `[!a-z]` match within a single path component, while
+        `**` matches any number of directories (`"**/.git"`, `"docs/**/*.png"`).
+      * A trailing `/` only matches directories, and a leading `!` re-includes what
+        an earlier pattern excluded (the last matching pattern wins).
+    * `:respect_ignore_files` (list of file names) - ignore files to honor, such as
+      `[".gitignore", ".duignore"]`, so that the totals match what would be committed or
+      packaged. Defaults to `[]`. Each directory's ignore files hold patterns in the
+      syntax of `:exclude`, relative to that directory, one per line; blank lines and
+      lines starting with `#` are skipped, and `\\#` or `\\!` start a pattern with a
+      literal `#` or `!`. A directory's own files take precedence over those of its
+      ancestors, so they can re-include what an outer file ignores, but nothing inside an
+      ignored directory can be re-included. Only ignore files within `path` are read,
+      not those of its parents or git's global and `.git/info/exclude` ones. Ignored
+      entries are left out of every count but `:ignored_entries` and `:ignored_bytes`,
+      which walks ignored directories in order to size them; use `:exclude` to skip
+      directories altogether.
+    * `:symlinks` (`:skip`, `:count_link` or `:follow`) - how symlinks are counted.
+      Defaults to `:count_link`.
+      * `:skip` leaves symlinks out of every count.
+      * `:count_link` counts each symlink with its own (tiny) size, which is what a
+        backup of the tree stores.
+      * `:follow` counts what each symlink points to and walks linked directories,
+        which is what a dereferencing copy (`cp -L`) occupies. A directory is walked
+        only once: reaching it again through a symlink (e.g. a link to an ancestor)
+        is recorded in `:errors` with the `ELOOP` errno instead. Dangling symlinks
+        are recorded in `:errors` as well.
+    * `:count_placeholders` (`:on_disk`, `:nominal` or `:exclude`) - how cloud
+      placeholders count on Windows. Defaults to `:on_disk`. Placeholders are told from
+      the attributes their directory listing reports (the recall and pinning attributes
+      that cloud filters set on reparse points) and are never opened, which could have
+      them downloaded; in turn, they are never recognized as hard links.
+      * `:on_disk` counts the space their local data takes: nothing for files that are
+        only in the cloud, and their length rounded up to clusters for those also kept
+        on the device.
+      * `:nominal` counts their full length, as if they were all local.
+      * `:exclude` leaves them out of every count but `:placeholder_files` and
+        `:placeholder_bytes`.
+    * `:workers` (positive integer) - how many OS threads walk the tree. Defaults to
+      the number of CPUs, at most
+      `4`. Directories are handed to idle threads as the walk proceeds, and the result
+      (including hard-link deduplication) doesn't depend on the number of workers.
+      Parallel walks mostly pay off on SSDs and network filesystems, or with a cold
+      cache; `workers: 1` walks sequentially, which suits spinning disks. `mix run bench/du.exs` compares the settings on a synthetic tree.
+    * `:on_error` (`:continue` or `:halt`) - whether to carry on past entries that
+      cannot be read, recording them in `:errors`, or to fail on the first one.
+      Defaults to `:continue`.
+    * `:max_errors` (non-negative integer) - how many failures to list in `:errors`;
+      further ones are only counted in `:error_count`. Defaults to `1000`.
+    * `:progress` (`{pid, ref}`) - have `pid` receive
+      `{:du_progress, ref, %{bytes_so_far: b, apparent_bytes_so_far: a, files_so_far: f, current_path: p}}`
+      messages while the walk runs, `ref` being any term of the caller's choosing, e.g. for
+      driving a progress bar. `current_path` is the directory most recently walked. The
+      messages are sent from a thread of their own, so a slow or dead receiver doesn't
+      hold up the walk (reporting stops once `pid` is gone); the result is returned as usual.
+    * `:progress_interval` (positive integer) - the time between two progress messages,
+      in milliseconds. Defaults to `100`, i.e. at most 10 messages per second.
+    * `:top_files` (non-negative integer) - how many of the largest files to list under
+      `:top_files`. Defaults to `0`, which lists none. Only a bounded selection is kept
+      during the walk, so this adds little to its memory use.
+    * `:top_dirs` (non-negative integer) - how many directories to list under
+      `:top_dirs`, like `du | sort -rh | head` without the output in between. Defaults
+      to `0`, which lists none. The path and cumulative size of every directory are
+      kept until the walk ends, which takes memory in proportion to the number of
+      directories. A directory is only listed while what it holds besides the listed
+      directories below it still ranks among the `n` largest, so a chain of ancestors
+      that are large only because of one deep directory gives way to that directory.
+    * `:top_dirs_nested` (boolean) - whether to rank directories by their full
+      cumulative size instead, listing ancestors along with the directories they
+      contain. Defaults to `false`.
+    * `:top_by` (`:bytes` or `:apparent_bytes`) - whether `:top_files` and `:top_dirs`
+      rank by allocated or by apparent size, which `:min_size` also goes by. Defaults to
+      `:bytes`.
+    * `:min_size` (non-negative integer) - the size in bytes from which regular files
+      count towards `:top_files` and `:matching`. Defaults to `0`.
+    * `:older_than` (non-negative integer) - the age in seconds from which regular files
+      count towards `:top_files` and `:matching`, going by their last modification.
+      Ages are measured from the start of the walk, and a timestamp in the future counts
+      as age zero. Defaults to no limit.
+    * `:atime` (boolean) - whether `:older_than` goes by the last access instead.
+      Defaults to `false`. Filesystems mounted with `noatime` or `relatime` (and NTFS by
+      default) update access times rarely or never, which makes files look older.
+    * `:by_extension` (boolean) - whether to break the usage of regular files down by
+      extension under `:by_extension`. Defaults to `false`. The extension is the part
+      of the name after the last dot, so `"backup.tar.gz"` counts as `"gz"`; names
+      whose only dot leads (`".bashrc"`) and extensions over 16 characters count as
+      `:none`. Excluded files are left out, and hard-linked files are counted once as
+      set by `:dedupe_hardlinks`.
+    * `:compound_extensions` (list of strings) - extensions spanning several dots to
+      recognize as a whole with `:by_extension`, e.g. `["tar.gz", "tar.zst"]`.
+      Defaults to `[]`.
+    * `:by_owner` (boolean) - whether to break the usage of regular files down by owner
+      under `:by_owner`. Defaults to `false`. This is cheap on Unix, where the uid comes
+      with the metadata, but on Windows it reads every file's security descriptor
+      (`GetNamedSecurityInfoW`), which can slow the walk down considerably; it is
+      therefore never done unless asked for.
+    * `:resolve_names` (boolean) - whether to add each owner's `:name` to `:by_owner`:
+      the user name on Unix (`getpwuid_r`) and `"DOMAIN\\user"` on Windows
+      (`LookupAccountSidW`). Owners without a name, such as deleted accounts, get their
+      uid or SID as a string instead. Defaults to `false`.
+    * `:cache` (a cache from `du_cache_new/1`) - reuse the sizes of directories that
+      look unchanged since an earlier walk, and remember those of the others. Requires
+      `:cache_mode`.
+    * `:cache_mode` (`:mtime_heuristic`) - how the `:cache` tells that a directory is
+      unchanged, spelled out as it trades accuracy for speed. With `:mtime_heuristic`,
+      a directory below `:max_depth` (or below `path` if that's `0`) whose
+      modification time, number of entries, and the sums of its entries' modification
+      times and sizes are as cached is counted from the cache instead of walked. Its
+      entries are still listed and stat'ed, but nothing further down is read. This
+      misses:
+      * changes two or more levels down that leave the directory's own entries as
+        they were, e.g. a file growing inside a subdirectory of a cached directory
+        whose mtime didn't move,
+      * and rewrites that keep sizes the same within the filesystem's mtime granularity.
+
+      Directories counted from the cache add nothing to `:top_files`, `:top_dirs`,
+      `:by_extension`, `:by_owner` or `:matching`, nor to `:excluded_entries`, `:excluded_bytes`,
+      `:hardlinked_saved_bytes` or `:skipped_mounts`, and hard links between them and
+      the rest of the tree are counted as in the walk that cached them. Entries are kept
+      per combination of `:dedupe_hardlinks`, `:one_file_system`, `:symlinks` and
+      `:exclude`. Walks that are cancelled or run into errors store nothing.
+      Call `du_cache_invalidate/2` for paths known to have changed.
+
+  ## Examples
+
+      DiskSpace.du("/home", one_file_system: true)
+
+      {:ok, %{children: [%{path: largest, bytes: bytes} | _]}} = DiskSpace.du("/var", max_depth: 1)
+
+      {:ok, %{entries: entries}} = DiskSpace.du("/srv", max_depth: 3, shape: :flat)
+
+      DiskSpace.du("/home/me/src/app", exclude: ["**/.git", "node_modules", "*.o"])
+
+      {:ok, %{top_files: [{largest, bytes} | _]}} = DiskSpace.du("/home", top_files: 20)
+
+      {:ok, %{matching: %{bytes: stale_bytes, files: stale_files}}} =
+        DiskSpace.du("/srv", min_size: 100_000_000, older_than: 90 * 86_400, top_files: 50)
+
+      {:ok, %{top_dirs: top_dirs}} = DiskSpace.du("/home", top_dirs: 10, top_by: :apparent_bytes)
+
+      {:ok, %{by_extension: %{"log" => %{bytes: log_bytes}}}} =
+        DiskSpace.du("/var/log", by_extension: true, compound_extensions: ["log.gz"])
+
+      {:ok, %{by_owner: by_owner}} = DiskSpace.du("/home", by_owner: true, resolve_names: true)
+  """
+  def du(path, opts \\ []) when is_bitstring(path) and is_list(opts) do
+    case du_start(path, opts) do
+      {ref, _token} when is_reference(ref) -> du_await(ref)
+      error -> error
+    end
+  end
+
+  @doc """
+  Starts `du/2` in the background and returns `{ref, token}` right away.
+
+  The walk runs on an OS thread of its own. When it ends, the calling process receives
+  `{:du_result, ref, result}`, where `result` is what `du/2` would have returned but not
+  yet reshaped; `du_await/2` receives and reshapes it. `token` can be passed to
+  `du_cancel/1`, from any process.
+
+  Takes the same options as `du/2`, and returns `{:error, info}` straight away if they
+  or `path` are invalid. Discarding `token` doesn't stop the walk: the thread finishes
+  and exits on its own, and its message is dropped if the caller has exited.
+
+  ## Examples
+
+      {ref, token} = DiskSpace.du_start("/var")
+      DiskSpace.du_cancel(token)
+      {:error, %{reason: :cancelled, info: partial_summary}} = DiskSpace.du_await(ref)
+  """
+  def du_start(path, opts \\ []) when is_bitstring(path) and is_list(opts) do
+    ref = make_ref()
+
+    case du_start_nif(path, Map.new(opts), ref) do
+      {:ok, token} -> {ref, token}
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Starts `du/2` in the background like `du_start/2`, but for `pid` to receive
+  `{:du_result, ref, result}`, where `result` is what `du/2` would have returned,
+  reshaped already. Suits a process such as a GenServer that shouldn't block on a walk
+  of minutes but hands its result to itself or another process.
+
+  Each walk runs on an OS thread of its own rather than on the shared worker pool, so
+  that however long it takes it holds up no other work, and several can run at once
+  without interfering. Takes the same options as `du/2`, including `:progress`.
+
+  Returns `{:ok, token}` for `du_cancel/1`, or `{:error, info}` straight away if the
+  options or `path` are invalid. `ref` should be unique to the walk, e.g. `make_ref()`.
+
+  ## Examples
+
+      ref = make_ref()
+      {:ok, token} = DiskSpace.du_async("/srv", [], self(), ref)
+
+      receive do
+        {:du_result, ^ref, {:ok, %{bytes: bytes}}} -> bytes
+        {:du_result, ^ref, {:error, %{reason: reason}}} -> reason
+      end
+  """
+  def du_async(path, opts, pid, ref) when is_bitstring(path) and is_list(opts) and is_pid(pid) do
+    case du_async_nif(path, Map.new(opts), pid, ref) do
+      {:ok, token} -> {:ok, token}
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Walks `path` like `du/2`, sending the result to `pid` one directory at a time.
+
+  For each directory below `path`, once its whole subtree has been walked, `pid` receives
+  `{:du_entry, ref, %{path: p, bytes: b, apparent_bytes: a, files: f, dirs: d, symlinks: s, other: o}}`
+  with the directory's cumulative usage, shaped like the `:children` of `du/2` without
+  their own `:children`. A directory always comes after all its subdirectories, so the
+  consumer can fold the tree as it arrives rather than receive one large term at the end.
+  Finally `pid` receives `{:du_done, ref, result}`, where `result` is what `du/2` returns
+  (`path` itself being described by its summary).
+
+  Entries must be acknowledged with `du_ack/1`: once `:max_in_flight` of them are
+  unacknowledged, the walk pauses until the consumer catches up. Should `pid` exit, the
+  walk is cancelled. Takes the same options as `du/2`, plus:
+
+    * `:max_in_flight` (positive integer) - how many entries may await acknowledgment.
+      Defaults to `64`.
+
+  Returns `{:ok, token}` for `du_cancel/1`, or `{:error, info}` straight away if the
+  options or `path` are invalid. `ref` should be unique to the stream, e.g. `make_ref()`.
+
+  ## Examples
+
+      ref = make_ref()
+      {:ok, _token} = DiskSpace.du_stream("/home", [max_in_flight: 100], self(), ref)
+
+      receive do
+        {:du_entry, ^ref, %{path: path, bytes: bytes}} -> DiskSpace.du_ack(ref)
+      end
+  """
+  def du_stream(path, opts, pid, ref) when is_bitstring(path) and is_list(opts) and is_pid(pid) do
+    case du_stream_nif(path, Map.new(opts), pid, ref) do
+      {:ok, token} -> {:ok, token}
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Acknowledges one entry of the `du_stream/4` stream with reference `ref`. Returns `:ok`,
+  also once the stream is over.
+  """
+  def du_ack(ref), do: du_ack_nif(ref)
+
+  @doc """
+  Cancels a walk started with `du_start/2`, `du_async/4` or `du_stream/4`, given its
+  token. Returns `:ok`.
+
+  The walk stops within a few entries, and its result becomes
+  `{:error, %{reason: :cancelled, info: partial_summary}}`, where `partial_summary`
+  counts what had been walked so far. Cancelling a finished walk, or cancelling twice,
+  does nothing.
+  """
+  def du_cancel(token), do: du_cancel_nif(token)
+
+  @doc """
+  Waits for the result of a walk started with `du_start/2` by the calling process.
+
+  Returns the result shaped like that of `du/2`, or `{:error, %{reason: :timeout, info: nil}}`
+  if none arrives within `timeout` milliseconds.
+  """
+  def du_await(ref, timeout \\ :infinity) when is_reference(ref) do
+    receive do
+      {:du_result, ^ref, result} -> reshape_error_tuple(result)
+    after
+      timeout -> {:error, %{reason: :timeout, info: nil}}
+    end
+  end
+
+  @doc """
+  Estimates the allocated size of the tree under `path` from a sample of it, for a rough
+  answer in a fraction of the time of `du/2`.
+
+  The first two levels below `path` are walked exactly. Of the directories two levels
+  down, a uniformly drawn `:sample_fraction` is walked in full, and the size of the others
+  is extrapolated from their mean. When a single sampled directory holds more than half
+  of the sampled bytes, the directory it sits in is walked completely instead of
+  extrapolated, as the rest of the sample says little about it; this is repeated up to 8
+  times while the sample stays dominated by one directory.
+
+  Returns `{:ok, %{estimate_bytes: e, confidence_low: l, confidence_high: h, sampled_fraction: f}}`,
+  where `l` and `h` bound a 95% confidence interval around `e` (never below what was
+  counted exactly), and `f` is the share of the directories two levels down that were
+  walked. They all equal the exact size once every directory was walked. The interval
+  assumes the unsampled directories resemble the sampled ones, so a single huge directory
+  that the sample missed escapes it. Hard links are only recognized within each walked
+  part. Errors are shaped like those of `du/2`.
+
+  ## Options
+
+  Takes the options of `du/2` that decide what is counted (`:dedupe_hardlinks`,
+  `:one_file_system`, `:exclude`, `:symlinks`, `:workers`, `:on_error`, `:max_errors`),
+  plus:
+
+    * `:sample_fraction` (number between `0` and `1`) - the share of the directories two
+      levels down to walk. Defaults to `0.1`; `1` walks them all. At least one is walked.
+    * `:seed` (non-negative integer) - the seed for drawing the sample, for repeatable
+      estimates. Defaults to one drawn from the clock.
+
+  ## Examples
+
+      {:ok, %{estimate_bytes: bytes, confidence_high: at_most}} = DiskSpace.du_estimate("/srv")
+
+      DiskSpace.du_estimate("/home", sample_fraction: 0.02, one_file_system: true)
+  """
+  def du_estimate(path, opts \\ []) when is_bitstring(path) and is_list(opts) do
+    ref = make_ref()
+
+    case du_estimate_nif(path, Map.new(opts), ref) do
+      {:ok, _token} -> du_await(ref)
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Creates a cache that `du/2` and its variants can reuse directory sizes from across
+  walks, with the `:cache` and `:cache_mode` options. Returns `{:ok, cache}`.
+
+  The cache lives as long as `cache` is referenced, and may be shared by several
+  processes and walks at once. Entries take in the order of 100 bytes plus the path each.
+
+  ## Options
+
+    * `:max_entries` (non-negative integer) - how many directories to keep. Defaults to
+      `100_000`. Beyond that, the entries least recently used by a walk are evicted when a
+      walk ends.
+
+  ## Examples
+
+      {:ok, cache} = DiskSpace.du_cache_new(max_entries: 1_000_000)
+      DiskSpace.du("/srv", cache: cache, cache_mode: :mtime_heuristic)
+  """
+  def du_cache_new(opts \\ []) when is_list(opts) do
+    du_cache_new_nif(Map.new(opts)) |> reshape_error_tuple()
+  end
+
+  @doc """
+  Drops the cached sizes of `path_prefix` and of every directory below it, so that the
+  next walk reads them again. Returns `:ok`.
+
+  Since a cached directory stands for its whole subtree, invalidate an ancestor of a
+  change to make sure it's seen, e.g. a directory's parent when the directory may have
+  changed deep inside.
+
+  ## Examples
+
+      :ok = DiskSpace.du_cache_invalidate(cache, "/srv/uploads")
+  """
+  def du_cache_invalidate(cache, path_prefix) when is_bitstring(path_prefix) do
+    case du_cache_invalidate_nif(cache, path_prefix) do
+      :ok -> :ok
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Returns `%{entries: e, max_entries: m, hits: h, misses: x, evictions: v}` for a cache from
+  `du_cache_new/1`: how many directories it holds and may hold, how many directories walks
+  took from it or had to read since it was created, and how many entries were evicted.
+  """
+  def du_cache_stats(cache), do: du_cache_stats_nif(cache)
+
+  @doc """
+  Tells whether a tree measured with `du/2` would fit onto the filesystem of `dest_path`.
+
+  Compares the summary's `:bytes` against the space available to the current user (see
+  `:available` in `stat/2`), and its `:inodes` against the inodes available to the user.
+  Filesystems that allocate inodes on demand, such as btrfs or NTFS, report
+  no inode limit, so only the bytes are compared there. As `:bytes` is the space
+  the tree takes up at its source, a destination with larger blocks may need more.
+
+  Accepts either a summary or `{:ok, summary}`, and raises `DiskSpace.Error` if
+  `dest_path` cannot be examined.
+
+  ## Examples
+
+      DiskSpace.du("/home/me/photos") |> DiskSpace.fits?("/media/usb")
+  """
+  def fits?({:ok, summary}, dest_path), do: fits?(summary, dest_path)
+
+  def fits?(%{bytes: bytes, inodes: inodes}, dest_path) when is_bitstring(dest_path) do
+    case fits_nif(dest_path, bytes, inodes) |> reshape_error_tuple() do
+      {:ok, fits} -> fits
+      {:error, info} -> raise Error, info
+    end
+  end
+
+  @doc """
+  Estimates the space files would take up on the filesystem of `dest_path`, which can be far
+  more than their sizes add up to: each file takes up a whole number of allocation units, so
+  that a million small files copied to a FAT32 card with 32 KiB clusters need gigabytes more,
+  and some filesystems take metadata for each file from their free space.
+
+  `planned` is either a list of file sizes in bytes, each of which is rounded up to the
+  allocation unit, or a summary of `du/2` (or `{:ok, summary}`), whose `:files` are taken to
+  waste half a unit each, as they do on average.
+
+  Returns `{:ok, estimate}`, where `estimate` is a map with the following keys:
+
+    * `:estimated_bytes` - the space the files would take up, metadata included.
+    * `:apparent_bytes`, `:files` - what their sizes add up to, and how many there are.
+    * `:allocation_unit_bytes` - the allocation unit of the filesystem: the fragment size
+      of `statvfs(3)`, or the cluster size on Windows.
+    * `:metadata_bytes` - the metadata counted for the files, in all.
+    * `:fs_type` - the type of the filesystem, `""` if it can't be told.
+    * `:available` - the space available to the current user, as in `stat/2`.
+    * `:fits`, `:shortfall` - whether `:estimated_bytes` fits into `:available`, and by how
+      many bytes it doesn't, `0` if it does.
+
+  Returns `{:error, info}` like `stat/2` if `dest_path` cannot be examined.
+
+  ## Options
+
+    * `:metadata_bytes` - the metadata to count for each file: a number of bytes, or a map
+      from filesystem type (as in `:fs_type`) to one, the types it leaves out taking the
+      defaults. These are 64 bytes on FAT (two directory entries), 96 on exFAT, 1024 on
+      NTFS (an MFT record), 512 on XFS (an inode), and 0 elsewhere, ext2/3/4 among them,
+      which allocate their inodes up front.
+
+  ## Examples
+
+      {:ok, %{fits: false, shortfall: shortfall}} =
+        DiskSpace.du("/home/me/photos") |> then(&DiskSpace.estimate_on_disk("/media/sd", &1))
+
+      {:ok, %{estimated_bytes: bytes}} =
+        DiskSpace.estimate_on_disk("/media/sd", [1_024, 52_000], metadata_bytes: %{"vfat" => 96})
+  """
+  def estimate_on_disk(dest_path, planned, opts \\ [])
+
+  def estimate_on_disk(dest_path, {:ok, summary}, opts),
+    do: estimate_on_disk(dest_path, summary, opts)
+
+  def estimate_on_disk(dest_path, planned, opts)
+      when is_bitstring(dest_path) and (is_list(planned) or is_map(planned)) and is_list(opts) do
+    planned = if is_map(planned), do: Map.take(planned, [:apparent_bytes, :files]), else: planned
+
+    dest_path
+    |> estimate_on_disk_nif(planned, Map.new(opts))
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Tells whether the file or tree `src_path` can be copied or moved into `dest_dir`, walking
+  it with `du/2` and estimating what it would take up there with `estimate_on_disk/3`.
+
+  Hard links don't survive a copy onto another filesystem, so each link counts as a file of
+  its own there; on the same filesystem, they count once with `preserve_hardlinks: true`.
+  Moving within a filesystem only renames, so `:move` requires no space there and doesn't
+  walk `src_path` at all.
+
+  Returns `{:ok, %{required: required, available: available, margin: margin}}`, in bytes,
+  if `required` plus `margin` fits into the space `available` to the current user, and
+  otherwise `{:error, %{reason: :will_not_fit, info: %{required: required,
+  available: available, margin: margin, shortfall: shortfall}}}`. Returns `{:error, info}`
+  like `du/2` or `stat/2` if either path cannot be examined.
+
+  ## Options
+
+    * `:operation` (`:copy` or `:move`) - what is to be done. Defaults to `:copy`.
+
+    * `:margin` - space to leave free on the destination: a number of bytes, or
+      `{:percent, percent}` of the size of its filesystem. Defaults to `0`.
+
+    * `:preserve_hardlinks` (boolean) - whether the copy keeps hard links, as `cp -a` does,
+      which it only can on the same filesystem. Defaults to `false`.
+
+    * `:metadata_bytes` - as for `estimate_on_disk/3`.
+
+  Any other options, such as `:exclude` or `:one_file_system`, are those of `du/2`.
+
+  ## Examples
+
+      {:ok, %{required: required}} = DiskSpace.can_fit("/home/me/photos", "/media/usb")
+
+      {:error, %{reason: :will_not_fit, info: %{shortfall: shortfall}}} =
+        DiskSpace.can_fit("/srv/data", "/mnt/backup", exclude: ["*.tmp"], margin: {:percent, 5})
+  """
+  def can_fit(src_path, dest_dir, opts \\ [])
+      when is_bitstring(src_path) and is_bitstring(dest_dir) and is_list(opts) do
+    {own, du_opts} =
+      Keyword.split(opts, [:operation, :margin, :preserve_hardlinks, :metadata_bytes])
+    operation = Keyword.get(own, :operation, :copy)
+
+    with {:ok, %File.Stat{major_device: src_device}} <- File.stat(src_path),
+         {:ok, %File.Stat{major_device: dest_device}} <- File.stat(dest_dir),
+         same_fs = src_device == dest_device,
+         {:ok, required} <- required_bytes(src_path, dest_dir, same_fs, operation, own, du_opts),
+         {:ok, %{available: available, total: total}} <-
+           dest_dir |> stat_fs() |> reshape_error_tuple() do
+      margin = margin_bytes(Keyword.get(own, :margin, 0), total)
+      info = %{required: required, available: available, margin: margin}
+
+      if required + margin <= available do
+        {:ok, info}
+      else
+        shortfall = required + margin - available
+        {:error, %{reason: :will_not_fit, info: Map.put(info, :shortfall, shortfall)}}
+      end
+    else
+      {:error, %{reason: _}} = error -> error
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  defp required_bytes(_src_path, _dest_dir, true, :move, _own, _du_opts), do: {:ok, 0}
+
+  defp required_bytes(src_path, dest_dir, same_fs, operation, own, du_opts)
+       when operation in [:copy, :move] do
+    dedupe = same_fs and Keyword.get(own, :preserve_hardlinks, false)
+    metadata = Keyword.take(own, [:metadata_bytes])
+
+    with {:ok, summary} <- du(src_path, Keyword.put(du_opts, :dedupe_hardlinks, dedupe)),
+         {:ok, %{estimated_bytes: bytes}} <- estimate_on_disk(dest_dir, summary, metadata) do
+      {:ok, bytes}
+    end
+  end
+
+  defp required_bytes(_src_path, _dest_dir, _same_fs, _operation, _own, _du_opts),
+    do: {:error, :invalid_option, :operation}
+
+  @doc """
+  Tells whether `bytes` can be written under `path`, before an operation that would fail
+  halfway through without them.
+
+  Compares what is required against the space available to the current user (see
+  `:available` in `stat/2`, which on Windows is already limited by the user's quota), rather
+  than the free space, of which a part may be reserved for root, less what `reserve/2` holds
+  on the same filesystem.
+
+  Returns `:ok` if there is enough, and otherwise
+  `{:error, %{reason: :insufficient_space, info: %{required: required, available: available,
+  shortfall: shortfall}}}`, in bytes, `required` including the margin and file overhead
+  below. Returns `{:error, info}` like `stat/2` if `path` cannot be examined.
+
+  ## Options
+
+    * `:margin` - space to leave free beyond `bytes`: a number of bytes, or
+      `{:percent, percent}` of the size of the filesystem. Defaults to `0`.
+
+    * `:file_count` - how many files the `bytes` will be written to. Each file takes up a
+      whole number of blocks, and some metadata, so that many small files need more space
+      than their sizes add up to. Defaults to `0`.
+
+    * `:per_file_overhead` - the bytes to count for each of the `:file_count` files. Defaults
+      to `4096`, a common block size.
+
+    * `:quota` - a `{:user, uid}`, `{:group, gid}` or `{:project, projid}` whose hard limit
+      (see `quota/2`) also bounds the available space, if it has one. Linux only; ignored
+      where quotas can't be read.
+
+  ## Examples
+
+      :ok = DiskSpace.ensure_free("/var/uploads", 50_000_000, margin: {:percent, 5})
+
+      {:error, %{reason: :insufficient_space, info: %{shortfall: shortfall}}} =
+        DiskSpace.ensure_free("/media/usb", 64_000_000_000, file_count: 12_000)
+  """
+  def ensure_free(path, bytes, opts \\ [])
+      when is_bitstring(path) and is_integer(bytes) and bytes >= 0 and is_list(opts) do
+    with {:ok, %{available: available, total: total}} <-
+           path |> stat_fs() |> reshape_error_tuple() do
+      required =
+        bytes + margin_bytes(Keyword.get(opts, :margin, 0), total) +
+          Keyword.get(opts, :file_count, 0) * Keyword.get(opts, :per_file_overhead, 4096)
+
+      available =
+        available
+        |> Kernel.-(reserved_bytes(path))
+        |> max(0)
+        |> min(quota_available(path, Keyword.get(opts, :quota)))
+
+      if required <= available do
+        :ok
+      else
+        info = %{required: required, available: available, shortfall: required - available}
+        {:error, %{reason: :insufficient_space, info: info}}
+      end
+    end
+  end
+
+  @doc """
+  Creates the file `path`, or extends it, to `bytes` with its space actually allocated, so
+  that a large download or database segment written into it later can't run out of space
+  halfway. A file already as large keeps its size and contents.
+
+  The space is allocated with `fallocate(2)` on Linux, `posix_fallocate(2)` on FreeBSD,
+  `F_PREALLOCATE` on macOS and `SetFileInformationByHandle` with `FileAllocationInfo` on
+  Windows; the file reads as zeros where it was extended.
+
+  Returns `:ok`, or `{:error, %{reason: posix, info: %{errno: errno, errstr: errstr}}}` with
+  the POSIX name of the error as in `:file`, such as `:enospc` if there isn't enough space,
+  or `:eopnotsupp` if the filesystem (or platform) can't preallocate. On failure, a file
+  that was created is removed, and one that existed is truncated back to its former size.
+
+  ## Options
+
+    * `:write_zeros` (boolean) - whether to write zeros up to `bytes` where the filesystem
+      can't preallocate, which allocates the space too, if slowly, on all but compressing or
+      deduplicating filesystems. Defaults to `false`.
+
+    * `:keep_partial` (boolean) - whether to keep what was allocated when failing, rather
+      than removing or truncating the file. Defaults to `false`.
+
+  ## Examples
+
+      :ok = DiskSpace.preallocate("/var/lib/db/segment.0042", 256 * 1024 * 1024)
+
+      {:error, %{reason: :enospc}} = DiskSpace.preallocate("/media/usb/huge.img", 2 ** 50)
+  """
+  def preallocate(path, bytes, opts \\ [])
+      when is_bitstring(path) and is_integer(bytes) and bytes >= 0 and is_list(opts) do
+    case preallocate_nif(path, bytes, Map.new(opts)) do
+      :ok -> :ok
+      error -> reshape_error_tuple(error)
+    end
+  end
+
+  @doc """
+  Checks that `bytes` can actually be written under the directory `path`, by writing them to
+  a temporary file there, syncing it and removing it. `stat/2` reports what the filesystem
+  believes it has, which on thin-provisioned volumes (LVM thin pools, SAN LUNs, cloud disks)
+  and some NFS servers can be more than the storage behind it still has; writes then fail
+  all the same.
+
+  This does real IO, of all of `bytes`, and takes as long as writing them does: probe with
+  what is about to be written, or a sample of it, not with the size of the disk.
+
+  The page cache is bypassed where it can be, with `O_DIRECT` on Linux and FreeBSD,
+  `F_NOCACHE` on macOS and `FILE_FLAG_WRITE_THROUGH` on Windows, so that the storage itself
+  has to take the data; `bytes` is then rounded up to a multiple of 4 KiB. Filesystems that
+  refuse that, such as tmpfs, are written through the cache, which the result tells.
+
+  Returns `{:ok, info}`, with `info` a map with the following keys:
+
+    * `:bytes_written` - the bytes written.
+    * `:duration_ms` - how long writing and syncing them took, in milliseconds.
+    * `:bytes_per_second` - the throughput that makes.
+    * `:direct` - whether the page cache was bypassed.
+
+  Or `{:error, %{reason: posix, info: %{errno: errno, errstr: errstr, bytes_written:
+  written}}}` with the POSIX name of the error as in `:file`, telling apart `:enospc` (no
+  space), `:edquot` (over a quota) and `:eio` (the storage failing the write, as an
+  overcommitted thin pool does), with the bytes written before. The temporary file is
+  removed either way. Returns `{:error, %{reason: :not_directory}}` if `path` isn't a
+  directory.
+
+  ## Options
+
+    * `:direct` (boolean) - whether to bypass the page cache where possible. Defaults to
+      `true`.
+
+  ## Examples
+
+      {:ok, %{bytes_per_second: rate}} = DiskSpace.probe_writable("/mnt/thin", 64 * 1024 * 1024)
+
+      {:error, %{reason: :eio}} = DiskSpace.probe_writable("/mnt/overcommitted", 1024 ** 3)
+  """
+  def probe_writable(path, bytes, opts \\ [])
+      when is_bitstring(path) and is_integer(bytes) and bytes >= 0 and is_list(opts) do
+    path
+    |> probe_writable_nif(bytes, Map.new(opts))
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Reserves `bytes` on the filesystem holding `path`, so that two operations that each check
+  for enough space don't then fill it together: `reserve/2` and `ensure_free/3` take the
+  bytes reserved on a filesystem off the space `stat/2` reports available there.
+
+  Returns `{:ok, reservation}`, which holds the bytes until passed to `release/1` or garbage
+  collected, or `{:error, %{reason: :insufficient_space, info: %{required: bytes,
+  available: available, shortfall: shortfall}}}` if what is available, net of the other
+  reservations, is less than `bytes`. Checking and reserving are done at once, so that of
+  two callers racing for the last of the space, only one gets it. Returns `{:error, info}`
+  like `stat/2` if `path` cannot be examined.
+
+  Reservations are advisory: they bind the callers of this function and `ensure_free/3` in
+  this VM, not other programs, and don't survive an upgrade of the NIF library, after which
+  those made before no longer count.
+
+  ## Examples
+
+      with {:ok, reservation} <- DiskSpace.reserve("/srv/uploads", upload_size) do
+        try do
+          receive_upload("/srv/uploads")
+        after
+          DiskSpace.release(reservation)
+        end
+      end
+  """
+  def reserve(path, bytes) when is_bitstring(path) and is_integer(bytes) and bytes >= 0 do
+    path
+    |> reserve_nif(bytes)
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Releases a reservation made with `reserve/2`. Releasing one again, or one made before the
+  NIF library was upgraded, does nothing. Returns `:ok`.
+  """
+  def release(reservation), do: release_nif(reservation)
+
+  @doc """
+  Lists the outstanding reservations of `reserve/2`, oldest first, as maps with the following
+  keys, for debugging:
+
+    * `:path` - the path given to `reserve/2`.
+    * `:device` - the filesystem it is on, as told apart by watchers.
+    * `:bytes` - the bytes reserved.
+    * `:reserved_at` - when, as Unix time in milliseconds.
+  """
+  def reservations, do: reservations_nif()
+
+  @doc """
+  Resolves the devices `path` is stored on, from the mount holding it down to the physical
+  disks, e.g. `"/srv/data"` on `"/dev/mapper/vg0-data"`, a logical volume on
+  `"/dev/nvme0n1p2"`, a partition of `"/dev/nvme0n1"`.
+
+  Returns `{:ok, backing}`, where `backing` is a map with the following keys:
+
+    * `:mount_point` - the mount point of the filesystem holding `path`.
+    * `:source` - the device it is mounted from, as the mount table has it.
+    * `:chain` - the devices beneath the filesystem, those nearer it first, each a map of:
+        * `:name` - its kernel name, such as `"dm-0"`, `"disk3s5"` or `"PhysicalDrive0"`.
+        * `:device` - its path, such as `"/dev/mapper/vg0-data"` or `"\\\\.\\PhysicalDrive0"`.
+        * `:kind` - `:disk`, `:partition`, `:dm` (device-mapper: LVM, dm-crypt, multipath),
+          `:md` (md RAID), `:loop`, `:volume` (Windows) or `:other`.
+        * `:lower` - the names of the devices directly beneath it, none for a disk.
+        * `:dm` - for a device-mapper device on Linux, what it is, `nil` otherwise:
+            * `:name` - the name operators know it by, such as `"vg0-data"` or
+              `"luks-0f3c..."`, as under `/dev/mapper`.
+            * `:type` - the mapping, from the prefix of its uuid: `:lvm`, `:crypt`
+              (dm-crypt, LUKS or plain), `:mpath` (multipath) or `:other`.
+            * `:vg` and `:lv` - for LVM, the volume group and logical volume, `nil` otherwise.
+            * `:luks_uuid` - for LUKS, the uuid of its header as `cryptsetup luksUUID` tells
+              it, `nil` otherwise.
+
+      A LUKS volume on LVM has both in the chain, the `:crypt` mapping first.
+    * `:disks` - the devices at the bottom of the chain, all of them for a filesystem or
+      volume over several disks, such as btrfs RAID, striped LVM, md or a spanned volume.
+    * `:encrypted` - on Linux, whether any device of the chain is dm-crypt, however it is
+      stacked: LUKS on a partition, LVM on LUKS or LUKS on LVM; `nil` elsewhere, where
+      `encryption_status/1` tells of BitLocker.
+    * `:encryption` - a map for each dm-crypt device of the chain, with its `:name`,
+      `:device` and `:luks_uuid` and the `:disks` beneath it; none off Linux.
+    * `:fscrypt` - on Linux, whether the filesystem has the encrypt feature ext4 and f2fs
+      encrypt directories with, `nil` elsewhere or if it can't be told.
+
+  On Linux the devices are read from sysfs, on macOS from the IOKit registry, an APFS volume
+  being in a container disk on a physical store, and on Windows from the disk extents of the
+  volume.
+
+  Returns `{:error, %{reason: :not_block_device, info: nil}}` for a filesystem on no block
+  device, such as NFS, tmpfs or an overlay, and `{:error, %{reason: :unsupported, info:
+  nil}}` on the BSDs.
+
+  ## Examples
+
+      {:ok, %{disks: ["/dev/nvme0n1"]}} = DiskSpace.backing_device("/srv/data")
+  """
+  def backing_device(path) when is_bitstring(path) do
+    path
+    |> backing_device_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Tells what the disks `path` is stored on are, as those who get to replace them know them:
+  "the Samsung 980 PRO is filling up" rather than `"/dev/nvme0n1"`.
+
+  Returns `{:ok, disks}`, with a map for each of the `:disks` of `backing_device/1`, having
+  the following keys:
+
+    * `:name` and `:device` - those of the disk in the `:chain` of `backing_device/1`.
+    * `:model` - the model, such as `"Samsung SSD 980 PRO 1TB"`.
+    * `:vendor` - the vendor, where the disk tells it apart from the model; SATA disks on
+      Linux don't, and NVMe ones never do.
+    * `:serial` - the serial number.
+    * `:firmware` - the firmware revision.
+    * `:is_removable` - whether the media are removable, or the disk is attached by USB, as
+      USB hard disks and card readers don't all say they are.
+    * `:bus` - the bus the disk is attached by: `:usb`, `:nvme`, `:sata` (ATA too), `:mmc`
+      (SD cards), `:scsi` (SAS too) or, on Linux, `:virtio`.
+    * `:transport` - the protocol the disk is reached by, telling apart what `:bus` lumps
+      together: `:nvme` (NVMe over fabrics too), `:sata`, `:sas`, `:scsi`, `:iscsi`, `:fc`
+      (Fibre Channel), `:usb`, `:mmc`, on Linux `:virtio`, or `:unknown` for virtual disks
+      and those that don't tell. From the path of the device in sysfs on Linux, the
+      Physical Interconnect of its Protocol Characteristics on macOS and its BusType on
+      Windows.
+    * `:size_bytes` - the size of the disk.
+    * `:rotational` - whether it is a hard disk rather than solid state; from the Medium
+      Type of its Device Characteristics on macOS, and whether it incurs a seek penalty on
+      Windows.
+    * `:logical_sector_size` and `:physical_sector_size` - the sector sizes in bytes, such as
+      512 and 4096 for a 512e disk, and 4096 and 4096 for a 4Kn one.
+    * `:permission_limited` - whether the device node could not be opened to ask it its
+      sector sizes; they are then read from sysfs or the IOKit registry, and `nil` if not
+      there either.
+    * `:discard` - whether TRIM/discard can be used, a map of:
+
+      * `:device_supports` - whether the disk takes it.
+      * `:mount_option_enabled` - whether the filesystem is mounted to discard the blocks it
+        frees, with `discard` or btrfs' `discard=async`; Linux only, as elsewhere there is no
+        such option, and `nil` there.
+      * `:fstrim_capable` - whether the filesystem can be trimmed in one go, with `fstrim` on
+        Linux or `Optimize-Volume -ReTrim` on Windows: it supports that, and the device it is
+        on takes discard. On macOS, APFS and HFS+ volumes on a disk taking it are trimmed as
+        they are mounted.
+
+  Each is `nil` where the disk doesn't tell, as virtio disks without a serial don't. They
+  come from sysfs on Linux, the IOKit registry on macOS and `StorageDeviceProperty` on
+  Windows, none of which needs more rights than reading the path, but for the sector sizes,
+  asked of the device with `BLKSSZGET` and `BLKPBSZGET` on Linux and `DKIOCGETBLOCKSIZE` and
+  `DKIOCGETPHYSICALBLOCKSIZE` on macOS, which take read access to its node. Returns the
+  errors of `backing_device/1`.
+
+  ## Examples
+
+      {:ok, [%{model: "Samsung SSD 980 PRO 1TB", serial: serial}]} =
+        DiskSpace.device_info("/srv/data")
+  """
+  def device_info(path) when is_bitstring(path) do
+    path
+    |> device_info_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Reads the I/O counters of each block device from `/proc/diskstats`, as `iostat` does.
+
+  Returns `{:ok, devices}`, with a map for each device having its `:name`, `:major` and
+  `:minor` numbers and the following counters, all since boot:
+
+    * `:reads_completed`, `:reads_merged`, `:sectors_read` and `:read_time_ms` - the reads,
+      those merged with others, the 512-byte sectors read and the milliseconds spent reading.
+    * `:writes_completed`, `:writes_merged`, `:sectors_written` and `:write_time_ms` - the
+      same for writes.
+    * `:ios_in_progress` - the I/O requests in flight now, the only counter that goes down.
+    * `:io_time_ms` and `:weighted_io_time_ms` - the milliseconds the device was busy, and
+      those multiplied by the requests in flight, i.e. the time spent in the queue.
+    * `:discards_completed`, `:discards_merged`, `:sectors_discarded` and
+      `:discard_time_ms` - the same for discards, since Linux 4.18.
+    * `:flushes_completed` and `:flush_time_ms` - the same for flushes, since Linux 5.5.
+
+  Counters the kernel doesn't have are `nil`; before Linux 2.6.25 partitions had only
+  the reads, sectors read, writes and sectors written. Sampling twice gives the throughput
+  and utilisation of a device. Returns `{:error, %{reason: :unsupported, info: nil}}` off
+  Linux; `diskstats/1` has the counters of the disks beneath a path on Windows too.
+
+  ## Examples
+
+      {:ok, [%{name: "nvme0n1", sectors_read: sectors} | _]} = DiskSpace.diskstats()
+  """
+  def diskstats do
+    diskstats_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Reads the I/O counters of the devices `path` is stored on.
+
+  Returns `{:ok, devices}` as `diskstats/0` does, for each device of the `:chain` of
+  `backing_device/1` and in that order: the device the filesystem is on first, the disks
+  last.
+
+  On Windows, where `diskstats/0` is unsupported, the list has a map for each of the
+  `:disks` of `backing_device/1`, from `IOCTL_DISK_PERFORMANCE`, with the keys:
+
+    * `:name` - the disk, such as `"PhysicalDrive0"`.
+    * `:reads_completed` and `:writes_completed` - the reads and writes.
+    * `:bytes_read` and `:bytes_written` - the bytes read and written.
+    * `:read_time_ms`, `:write_time_ms` and `:idle_time_ms` - the milliseconds spent
+      reading, writing and idle.
+    * `:ios_in_progress` - the queue depth now.
+
+  Those counters may have been disabled with `diskperf -N`, giving `{:error, %{reason:
+  :counters_disabled, info: hint}}`, the hint being how to enable them again. Opening a disk
+  for them takes no rights but in locked-down configurations, where it fails with
+  `{:error, %{reason: :eacces, info: %{errno: errno, errstr: errstr}}}`.
+
+  Returns the errors of `backing_device/1`, and `{:error, %{reason: :unsupported, info:
+  nil}}` on macOS and the BSDs.
+
+  ## Examples
+
+      {:ok, [%{name: "dm-0"}, %{name: "nvme0n1p2"}, %{name: "nvme0n1"}]} =
+        DiskSpace.diskstats("/srv/data")
+  """
+  def diskstats(path) when is_bitstring(path) do
+    path
+    |> path_diskstats_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Describes the md RAID array `path` is the device node of, such as `"/dev/md0"`, or the
+  filesystem holding `path` is stored on, from `/proc/mdstat` and sysfs. Linux only.
+
+  Returns `{:ok, array}`, a map with the keys:
+
+    * `:name` and `:device` - the array, such as `"md0"`, and its node, `"/dev/md0"`.
+    * `:level` - such as `"raid1"` or `"raid5"`, `nil` while the array is inactive.
+    * `:active` - whether the array is assembled and running.
+    * `:array_state` - the state in sysfs, such as `"clean"`, `"active"` or
+      `"read-auto"`, `nil` if unreadable.
+    * `:raid_disks` and `:active_disks` - the slots of the array and the working members
+      in them, `nil` for raid0 and linear arrays, which have no redundancy.
+    * `:degraded` - whether a member is missing or faulty.
+    * `:members` - a map for each member with its `:name`, `:device`, `:slot` (`nil` for
+      spares, and where sysfs is unreadable) and `:state`, one of `:in_sync`,
+      `:rebuilding`, `:spare`, `:faulty` and `:replacement`.
+    * `:sync` - `nil`, or a map of the resync, recovery, reshape, check or repair under way
+      or waiting: its `:action`, one of `:resync`, `:recovery`, `:reshape`, `:check` and
+      `:repair`, and its `:percent` done, `:finish_minutes` and `:speed_kib_per_sec`, all
+      `nil` while it's delayed or pending.
+
+  Returns `{:error, %{reason: :not_raid, info: nil}}` if the device isn't an md array, the
+  errors of `backing_device/1`, and `{:error, %{reason: :unsupported, info: nil}}` off
+  Linux.
+
+  ## Examples
+
+      {:ok, %{level: "raid5", degraded: true, sync: %{action: :recovery, percent: 8.5}}} =
+        DiskSpace.raid_info("/dev/md1")
+  """
+  def raid_info(path) when is_bitstring(path) do
+    path
+    |> raid_info_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Reads the partition table of each disk `path` is stored on, to tell whether there is
+  unallocated space on it to grow into.
+
+  Returns `{:ok, disks}`, a map for each of the `:disks` of `backing_device/1` with its
+  `:name`, `:device`, `:size_bytes` (`nil` if it doesn't tell) and `:partitions`, in the
+  order they are on the disk, each a map with the keys:
+
+    * `:number` - the number of the partition in the table.
+    * `:name` and `:device` - such as `"nvme0n1p2"` and `"/dev/nvme0n1p2"`, `"disk0s2"`
+      and `"/dev/disk0s2"` on macOS, or `"Harddisk0Partition2"` and
+      `"\\\\.\\Harddisk0Partition2"` on Windows.
+    * `:start` and `:size` - where the partition starts on the disk and how big it is, in
+      bytes.
+    * `:type_guid_or_id` - the type GUID of a GPT partition, in lowercase, or the type id
+      of an MBR one, such as `"0x83"`; on macOS MBR partitions have a hint such as
+      `"DOS_FAT_32"` instead. `nil` where unknown, as on Linux without udev, which keeps
+      the types.
+    * `:mounted_at` - the mount points of the filesystem on the partition, none if it is
+      unmounted or holds another device, such as an LVM physical volume.
+
+  The space between partitions, and between the last and the end of the disk, is
+  unallocated; on a GPT disk its last 33 sectors hold the backup of the table. Returns the
+  errors of `backing_device/1`.
+
+  ## Examples
+
+      {:ok, [%{size_bytes: size, partitions: partitions}]} = DiskSpace.partitions("/")
+      last = List.last(partitions)
+      unallocated = size - (last.start + last.size)
+  """
+  def partitions(path) when is_bitstring(path) do
+    path
+    |> partitions_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Tells whether the disks `path` is stored on predict their own failure, the one bit of
+  SMART worth having next to their capacity; full SMART data is left to `smartctl`.
+
+  Returns `{:ok, disks}`, a map for each of the `:disks` of `backing_device/1` with its
+  `:name` and `:device` and the keys:
+
+    * `:failure_predicted` - whether the disk predicts its failure, `nil` if it can't be
+      asked. On Linux, whether an NVMe disk has a critical warning in its SMART / Health
+      Information log, or an ATA disk fails SMART RETURN STATUS; on Windows, what
+      `IOCTL_STORAGE_PREDICT_FAILURE` says; on macOS, the SMART Status the IOKit registry
+      has for disks that are SMART capable, which not all drivers publish.
+    * `:critical_warning` - the critical warning byte of an NVMe disk on Linux, its bits
+      telling whether the spare is low, the temperature out of bounds, the reliability
+      degraded or the media read-only; `nil` elsewhere.
+    * `:state` - the state of the device on Linux, such as `"running"` or `"offline"` for
+      a SCSI disk and `"live"` for an NVMe controller; `nil` elsewhere.
+
+  Asking a disk on Linux takes `CAP_SYS_ADMIN`. If no disk can be asked, for want of
+  rights or as none does SMART, returns `{:error, %{reason: :unavailable, info: nil}}`.
+  Returns the errors of `backing_device/1` too.
+
+  The queries are behind the `device_health` feature of the crate, off by default, which
+  is turned on in the config of the application building it:
+
+      config :disk_space, DiskSpace, features: ["device_health"]
+
+  Without it, returns `{:error, %{reason: :device_health_disabled, info: nil}}`.
+
+  ## Examples
+
+      {:ok, [%{name: "nvme0n1", failure_predicted: false}]} = DiskSpace.device_health("/")
+  """
+  def device_health(path) when is_bitstring(path) do
+    path
+    |> device_health_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Tells whether the volume holding `path` is protected by BitLocker, for compliance reports.
+  Windows only.
+
+  Returns `{:ok, status}`, where `status` is a map with the following keys:
+
+    * `:protected` - whether BitLocker protects the volume; also `true` for a locked volume.
+    * `:encryption_in_progress` - whether the volume is being encrypted, its data not all
+      encrypted yet.
+    * `:conversion_status` - one of `:fully_decrypted`, `:fully_encrypted`,
+      `:encryption_in_progress`, `:decryption_in_progress`, `:encryption_paused` and
+      `:decryption_paused`, `nil` if unknown.
+    * `:method` - the encryption method, one of `:none`, `:aes_128_diffuser`,
+      `:aes_256_diffuser`, `:aes_128`, `:aes_256`, `:hardware`, `:xts_aes_128` and
+      `:xts_aes_256`, `nil` if unknown.
+
+  The status is read from the `Win32_EncryptableVolume` of the volume through WMI, on a
+  thread of its own. A volume BitLocker can't encrypt is reported unprotected, with the
+  other keys `nil`.
+
+  The WMI namespace of BitLocker is open to administrators only; others get
+  `{:error, %{reason: :access_denied, info: nil}}`. Returns
+  `{:error, %{reason: :not_block_device, info: nil}}` for a network share,
+  `{:error, %{reason: :winapi_failed, info: %{errno: code, errstr: errstr}}}` if a call
+  fails otherwise, and `{:error, %{reason: :unsupported, info: nil}}` on editions of Windows
+  without BitLocker and on other platforms, where `backing_device/1` tells of dm-crypt.
+
+  ## Examples
+
+      {:ok, %{protected: true, method: :xts_aes_128}} = DiskSpace.encryption_status("C:\\")
+  """
+  def encryption_status(path) when is_bitstring(path) do
+    path
+    |> encryption_status_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Tells whether the volume holding `path` has its dirty bit set, as NTFS and FAT volumes do
+  when not dismounted cleanly, e.g. after a crash, until `chkdsk` has checked them. Their
+  free space may be counted wrong until then. Windows only.
+
+  Returns `{:ok, dirty}`. The bit is asked for with `FSCTL_IS_VOLUME_DIRTY`, on a handle to
+  the volume opened to read its attributes only, which needs no administrator rights.
+
+  Returns `{:error, %{reason: :access_denied, info: nil}}` if the volume still is not to be
+  opened, `{:error, %{reason: :not_block_device, info: nil}}` for a network share,
+  `{:error, %{reason: :winapi_failed, info: %{errno: code, errstr: errstr}}}` if a call
+  fails otherwise, and `{:error, %{reason: :unsupported, info: nil}}` on other platforms.
+
+  ## Examples
+
+      {:ok, false} = DiskSpace.volume_dirty("C:\\")
+  """
+  def volume_dirty(path) when is_bitstring(path) do
+    path
+    |> volume_dirty_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Tells whether the media of the removable drive holding `path` are in it and mounted, e.g.
+  whether an SD card is in its reader, so that exporting to it is offered only then.
+  `path` may be a path on the drive, its root, a drive letter as `"E:"` on Windows, or its
+  device node as `"/dev/mmcblk0"` on Linux and macOS.
+
+  Returns `{:ok, :ready}` if the media are in and mounted, `{:ok, :no_media}` if the drive
+  is empty or its media are not mounted, and `{:ok, :not_removable}` for a fixed disk or a
+  network share; a disk attached by USB counts as removable.
+
+  On Windows the drive is asked with the critical error mode of the calling thread on, so
+  that an empty drive returns `:no_media` rather than Windows asking the user to insert a
+  disk. On Linux and macOS a device node missing under `/dev` has no media, as the node of
+  a card reader comes and goes with the card.
+
+  Returns `{:error, %{reason: reason, info: %{errno: errno, errstr: errstr}}}` if `path`
+  can't be read, `{:error, %{reason: :winapi_failed, info: %{errno: code, errstr: errstr}}}`
+  if a call fails on Windows, and `{:error, %{reason: :unsupported, info: nil}}` on other
+  platforms.
+
+  ## Examples
+
+      {:ok, :no_media} = DiskSpace.media_ready("E:")
+
+      {:ok, :ready} = DiskSpace.media_ready("/media/sd")
+  """
+  def media_ready(path) when is_bitstring(path) do
+    path
+    |> media_ready_nif()
+    |> reshape_error_tuple()
+  end
+
+  defp reserved_bytes(path) do
+    case reserved_nif(path) do
+      {:ok, bytes} -> bytes
+      _ -> 0
+    end
+  end
+
+  defp margin_bytes({:percent, percent}, total) when is_number(percent) and percent >= 0,
+    do: ceil(total * percent / 100)
+
+  defp margin_bytes(bytes, _total) when is_integer(bytes) and bytes >= 0, do: bytes
+
+  defp quota_available(_path, nil), do: :infinity
+
+  defp quota_available(path, owner) do
+    case quota(path, owner) do
+      {:ok, %{bytes_hard_limit: limit, bytes_used: used}} when is_integer(limit) ->
+        max(limit - used, 0)
+
+      _ ->
+        :infinity
+    end
+  end
+
+  defp reshape_error_tuple({:error, reason}), do: {:error, %{reason: reason, info: nil}}
+  defp reshape_error_tuple({:error, reason, info}), do: {:error, %{reason: reason, info: info}}
+  defp reshape_error_tuple({:ok, _} = success), do: success
+
+  @doc """
+  Lists the mounted filesystems.
+
+  Returns `{:ok, mounts}` where each entry of `mounts` is a map with the following keys:
+
+    * `:mount_point` - where the filesystem is mounted (a drive letter root or mounted folder on Windows).
+    * `:fs_type` - the filesystem type, e.g. `"ext4"`, `"apfs"`, `"NTFS"`.
+    * `:source` - the mounted device or remote export (the volume GUID path on Windows).
+    * `:device` - the device identifier: `"major:minor"` on Linux, the fsid on macOS and the BSDs, the volume serial number on Windows.
+    * `:options` - the mount options as a list of strings.
+    * `:read_only` - whether the filesystem is mounted read-only.
+    * `:mount_id`, `:parent_id`, `:root` - the mount ID, the parent mount ID and the root of the mount within its filesystem (Linux only, `nil` elsewhere).
+
+  Memory-backed mounts (tmpfs and ramfs), whose space is taken from RAM and swap, also have:
+
+    * `:memory_backed` - `true`.
+    * `:size_bytes` - the `size=` limit of the mount, `nil` if unlimited.
+    * `:size_is_ram_fraction` - that limit as a fraction of `MemTotal` in `/proc/meminfo`,
+      e.g. `0.5` for the usual half of RAM.
+    * `:noswap` - whether the mount keeps its pages out of swap.
+
+  Mounts of a device-mapper device on Linux, such as an LVM logical volume or a LUKS volume,
+  also have a `:dm` key, a map as in the `:chain` of `backing_device/1`.
+
+  Returns `{:error, info}` on failure, shaped like the errors of `stat/2`.
+
+  ## Options
+
+    * `:exclude` - a filter or list of filters; mounts matching any of them are dropped. Defaults to `[]`. Filters are either presets:
+
+      * `:pseudo` - kernel and virtual filesystems (`proc`, `sysfs`, `cgroup2`, `devpts`, `tracefs`, ...).
+      * `:squashfs_loop` - read-only squashfs loop mounts under `/snap`, `/var/lib/snapd` and `/run`, i.e. snap packages. Squashfs images mounted deliberately elsewhere are kept.
+      * `:appimage` - the read-only FUSE mounts of running AppImages under `/tmp/.mount_*`.
+
+      or tuples:
+
+      * `{:fs_type, type}` - mounts of the given filesystem type.
+      * `{:path_prefix, prefix}` - mounts at or below the given path (matched per path component).
+
+    * `:device_info` (boolean) - whether to add a `:device_info` key to each mount, with the
+      disks beneath it as `device_info/1` describes them, e.g. to tell mounts on USB sticks
+      and SD cards by their `:is_removable`, or `nil` for mounts on no block device and on
+      the BSDs. Defaults to `false`, as it takes a look into sysfs, IOKit or the volume for
+      each mount.
+
+    * `:volume_id` (boolean) - whether to add the `:volume_id` and `:volume_id_raw` keys that
+      the option of the same name of `stat/2` adds, identifying the filesystem of each mount.
+      Defaults to `false`.
+
+    * `:media` (boolean) - whether to add the `:media` key that the option of the same name
+      of `stat/2` adds, telling drives on USB disks from internal ones on Windows. Defaults
+      to `false`.
+
+  ## Examples
+
+      DiskSpace.list_mounts(exclude: [:pseudo, :squashfs_loop, {:fs_type, "tmpfs"}])
+      DiskSpace.list_mounts(device_info: true)
+  """
+  def list_mounts(opts \\ []) when is_list(opts) do
+    opts
+    |> Map.new()
+    |> list_mounts_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Captures the current mount table as an opaque snapshot for `mounts_diff/2`.
+
+  Returns `{:ok, snapshot}` or `{:error, info}`. Takes the same options as `list_mounts/1`.
+  Comparing snapshots is cheaper than comparing `list_mounts/1` results, as the
+  mount tables never have to be converted to and from Elixir terms.
+  """
+  def snapshot(opts \\ []) when is_list(opts) do
+    opts
+    |> Map.new()
+    |> snapshot_nif()
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Compares two mount tables, each either a `snapshot/1` or the list of a `list_mounts/1` result.
+
+  Returns `{:ok, %{added: mounts, removed: mounts, changed: changes}}`, where `changes` is a list of
+  `%{old: mount, new: mount}` maps for filesystems that are still mounted but whose read-only state,
+  options, type or source changed (e.g. a remount from `rw` to `ro`).
+
+  Mounts are matched on their mount point, device and (on Linux) root within the filesystem;
+  mount IDs are ignored since the kernel recycles them. Returns `{:error, %{reason: :invalid_snapshot, info: nil}}`
+  if either argument is neither a snapshot nor a list of mount maps.
+
+  ## Examples
+
+      {:ok, before} = DiskSpace.snapshot(exclude: :pseudo)
+      # ... time passes ...
+      {:ok, now} = DiskSpace.snapshot(exclude: :pseudo)
+      {:ok, %{added: added, removed: removed, changed: changed}} = DiskSpace.mounts_diff(before, now)
+  """
+  def mounts_diff(old, new) do
+    old
+    |> mounts_diff_nif(new)
+    |> reshape_error_tuple()
+  end
+
+  @doc """
+  Converts disk space statistics coming from `stat/2` and `stat!/2` from raw byte counts to human-readable strings.
+
+  Accepts either a tuple `{:ok, stats_map}` (from `stat/2`) or a `stats_map` plain Elixir map (from a successful `stat!/2`), where the key values of `stats_map` are integer byte counts.  Transparent for `{:error, info}` tuples returned from `stat/2`.
+
+  Returns the same structure but with all byte values converted to formatted human-readable strings (e.g., `"10 GiB"`), if the `base_type` argument value is non-`nil` and one of `:binary` (default) or `:decimal`.
+
+  ## Parameters
+
+    * `stats` - either `{:ok, stats_map}` or a `stats_map` with keys like `:available`, `:free`, etc. and integer values representing bytes.
+    * `base_type` - formatting base, either `nil` (do not do anything) or `:binary` (default, powers of 1024) or `:decimal` (powers of 1000). If non-`nil`, the atom determines the unit suffixes (`KiB` vs `kB`, etc.).
+
+  ## Examples
+      iex> DiskSpace.humanize({:ok, %{free: 123456789}}, nil)
+      {:ok, %{free: 123456789}}
+
+      iex> DiskSpace.humanize({:ok, %{free: 123456789}}, :binary)
+      {:ok, %{free: "117.74 MiB"}}
+
+      iex> DiskSpace.humanize(%{total: 1000000}, :decimal)
+      %{total: "1 MB"}
+
+      iex> DiskSpace.humanize({:error, :eio}, :binary)
+      {:error, :eio}
+
+      iex> DiskSpace.stat("/tmp") |> DiskSpace.humanize
+
+  """
+  def humanize(_, base_type \\ :binary)
+
+  def humanize(tagged_tuple, nil) when is_tuple(tagged_tuple), do: tagged_tuple
+
+  def humanize({:ok, stats}, base_type)
+      when is_map(stats) and base_type in [:binary, :decimal],
+      do: {:ok, humanize(stats, base_type)}
+
+  def humanize(stats, base_type) when is_map(stats) and base_type in [:binary, :decimal],
+    do:
+      Enum.map(
+        stats,
+        fn {k, v} -> {k, humanize_bytes(v, base_type)} end
+      )
+      |> Map.new()
+
+  def humanize({:error, _} = failure, _), do: failure
+
+  defp humanize_bytes(flag, _base_type) when is_boolean(flag), do: flag
 
   defp humanize_bytes(bytes, base_type)
        when is_integer(bytes) and bytes >= 0 and base_type in [:binary, :decimal] do
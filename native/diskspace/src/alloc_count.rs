@@ -0,0 +1,36 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// alloc_count: the global allocator of the tests and of builds with the
+// bench feature, which counts the allocations of each thread, so that they
+// can tell how many a stat made. Counting takes a thread-local increment on
+// top of the system allocator; normal builds keep the system allocator as
+// it is.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+// The allocations made by this thread so far
+pub(crate) fn count() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
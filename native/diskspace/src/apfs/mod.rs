@@ -0,0 +1,178 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// apfs_details: the APFS container of a macOS volume, for stat's
+// apfs_details option. The volumes of a container share its free space, so
+// the available space of each is that of all, and summing them over the
+// volumes of one container counts it as many times.
+//
+// A container is named after its synthesized disk, that of its volumes
+// without their slice: /dev/disk3s1 is in disk3, and so is the snapshot
+// com.apple.os.update-…@/dev/disk3s1s1 the system volume is mounted from.
+// Its free space is that statfs reports for any of its volumes, tagged here
+// as container_free. Other filesystems, and other platforms, get nil.
+//
+// With collapse_apfs: true, stat_all_async keeps only the first volume of
+// each container, listing the mount points of all of them in its
+// apfs_volumes, so that its report has a single capacity entry per
+// container.
+
+mod snapshots;
+
+use crate::mounts::Mount;
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Encoder, Env, NifResult, Term};
+use std::collections::HashMap;
+#[cfg(target_os = "macos")]
+use {
+    crate::make_errno_error_tuple, std::ffi::CStr, std::io, std::mem::MaybeUninit,
+    std::os::raw::c_char,
+};
+
+// The container of the APFS volume mounted from `source`, None if it isn't
+// named like one
+fn container(source: &str) -> Option<&str> {
+    let device = source.rsplit('@').next().unwrap_or(source);
+    let device = device.strip_prefix("/dev/").unwrap_or(device);
+    let unit = device.strip_prefix("disk")?;
+    let digits = unit.bytes().take_while(u8::is_ascii_digit).count();
+    let slice = unit[digits..].strip_prefix('s')?;
+    if digits == 0 || !slice.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(&device[..4 + digits])
+}
+
+// `table` without the APFS volumes of a container but its first, and for
+// each mount kept the mount points of the volumes of its container, None
+// for other filesystems
+pub(crate) fn collapse(table: Vec<Mount>) -> (Vec<Mount>, Vec<Option<Vec<String>>>) {
+    let mut kept: Vec<Mount> = Vec::with_capacity(table.len());
+    let mut volumes: Vec<Option<Vec<String>>> = Vec::with_capacity(table.len());
+    let mut first: HashMap<String, usize> = HashMap::new();
+    for mount in table {
+        let container = Some(mount.source.as_str())
+            .filter(|_| mount.fs_type == "apfs")
+            .and_then(container);
+        let Some(container) = container else {
+            kept.push(mount);
+            volumes.push(None);
+            continue;
+        };
+        match first.get(container) {
+            Some(&at) => volumes[at]
+                .get_or_insert_with(Vec::new)
+                .push(mount.mount_point),
+            None => {
+                first.insert(container.to_string(), kept.len());
+                volumes.push(Some(vec![mount.mount_point.clone()]));
+                kept.push(mount);
+            }
+        }
+    }
+    (kept, volumes)
+}
+
+// Helper: A NUL-terminated field of a statfs
+#[cfg(target_os = "macos")]
+fn c_field(field: &[c_char]) -> String {
+    unsafe { CStr::from_ptr(field.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Helper: The statfs of the filesystem holding `path`
+#[cfg(target_os = "macos")]
+fn statfs(path: &CStr) -> io::Result<libc::statfs> {
+    let mut buf = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(path.as_ptr(), buf.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { buf.assume_init() })
+}
+
+// %{container, volume, container_free} of the APFS volume holding `path`,
+// or nil for another filesystem
+#[rustler::nif(schedule = "DirtyIo")]
+fn apfs_details_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "macos")]
+    {
+        let buf = match statfs(&path) {
+            Ok(buf) => buf,
+            Err(err) => return make_errno_error_tuple(env, atoms::statfs_failed(), err),
+        };
+        let source = c_field(&buf.f_mntfromname);
+        let container = Some(&source)
+            .filter(|_| c_field(&buf.f_fstypename) == "apfs")
+            .and_then(|source| container(source));
+        let Some(container) = container else {
+            return Ok((atoms::ok(), rustler::types::atom::nil()).encode(env));
+        };
+        let volume = source.rsplit('@').next().unwrap_or(&source);
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::container().to_term(env), container)?
+            .map_put(
+                atoms::volume().to_term(env),
+                volume.strip_prefix("/dev/").unwrap_or(volume),
+            )?
+            .map_put(
+                atoms::container_free().to_term(env),
+                buf.f_bfree.saturating_mul(u64::from(buf.f_bsize)),
+            )?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = path;
+        Ok((atoms::ok(), rustler::types::atom::nil()).encode(env))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mount(mount_point: &str, fs_type: &str, source: &str) -> Mount {
+        Mount {
+            mount_id: None,
+            parent_id: None,
+            device: String::new(),
+            root: None,
+            mount_point: mount_point.to_string(),
+            fs_type: fs_type.to_string(),
+            source: source.to_string(),
+            options: vec!["rw".to_string()],
+            read_only: false,
+        }
+    }
+
+    #[test]
+    fn collapses_the_volumes_of_a_container() {
+        assert_eq!(container("/dev/disk3s1"), Some("disk3"));
+        assert_eq!(
+            container("com.apple.os.update-ABC@/dev/disk13s1s1"),
+            Some("disk13")
+        );
+        assert_eq!(container("/dev/disk3"), None);
+        assert_eq!(container("map auto_home"), None);
+        let (kept, volumes) = collapse(vec![
+            mount("/", "apfs", "com.apple.os.update-ABC@/dev/disk3s1s1"),
+            mount("/dev", "devfs", "devfs"),
+            mount("/System/Volumes/Data", "apfs", "/dev/disk3s5"),
+            mount("/Volumes/Backup", "apfs", "/dev/disk5s1"),
+        ]);
+        let mount_points: Vec<&str> = kept.iter().map(|m| m.mount_point.as_str()).collect();
+        assert_eq!(mount_points, ["/", "/dev", "/Volumes/Backup"]);
+        assert_eq!(
+            volumes,
+            [
+                Some(vec!["/".to_string(), "/System/Volumes/Data".to_string()]),
+                None,
+                Some(vec!["/Volumes/Backup".to_string()]),
+            ]
+        );
+    }
+}
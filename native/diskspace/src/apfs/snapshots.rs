@@ -0,0 +1,252 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// apfs_snapshots: the snapshots of the APFS volume holding a path, most of
+// them the local snapshots of Time Machine, the usual answer to where tens
+// of gigabytes of a laptop went. They are listed with fs_snapshot_list(2)
+// on the root of the volume, like getattrlistbulk(2) a batch of entries per
+// call until it returns 0, asking for the name and creation time of each.
+//
+// The space a snapshot holds is only known to APFS and tmutil, through
+// private interfaces, so only the names and creation times are reported.
+// Listing them takes Full Disk Access, for want of which the call fails
+// with EPERM: that is {:error, :requires_full_disk_access}. Other
+// filesystems return {:error, :not_apfs}. macOS only; elsewhere
+// apfs_snapshots returns {:error, :unsupported}.
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Env, NifResult, Term};
+#[cfg(target_os = "macos")]
+use {
+    super::{c_field, statfs},
+    crate::make_errno_error_tuple,
+    rustler::Encoder,
+    std::ffi::c_void,
+    std::fs::File,
+    std::io,
+    std::os::raw::c_int,
+    std::os::unix::io::AsRawFd,
+};
+
+// The attributes of <sys/attr.h> asked of each snapshot
+#[cfg(any(target_os = "macos", test))]
+const ATTR_CMN_NAME: u32 = 0x0000_0001;
+#[cfg(any(target_os = "macos", test))]
+const ATTR_CMN_CRTIME: u32 = 0x0000_0200;
+#[cfg(target_os = "macos")]
+const ATTR_CMN_RETURNED_ATTRS: u32 = 0x8000_0000;
+
+// Offset of the first attribute of an entry, past its length and the
+// attribute_set_t of the attributes returned
+#[cfg(any(target_os = "macos", test))]
+const ATTRIBUTES: usize = 4 + 20;
+
+#[cfg(any(target_os = "macos", test))]
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    name: String,
+    // Unix time, None if not returned
+    created: Option<i64>,
+}
+
+#[cfg(any(target_os = "macos", test))]
+impl Snapshot {
+    // From an entry of fs_snapshot_list, None if truncated or nameless
+    fn decode(entry: &[u8]) -> Option<Snapshot> {
+        let returned = u32_at(entry, 4)?;
+        if returned & ATTR_CMN_NAME == 0 {
+            return None;
+        }
+        // An attrreference_t: the offset of the name from itself, and its
+        // length with the NUL
+        let offset = usize::try_from(i32::from_ne_bytes(
+            entry.get(ATTRIBUTES..ATTRIBUTES + 4)?.try_into().ok()?,
+        ))
+        .ok()?;
+        let length = u32_at(entry, ATTRIBUTES + 4)? as usize;
+        let name = entry.get(ATTRIBUTES + offset..ATTRIBUTES + offset + length)?;
+        let name = name.split(|byte| *byte == 0).next().unwrap_or(name);
+        let created = if returned & ATTR_CMN_CRTIME != 0 {
+            let at = ATTRIBUTES + 8;
+            Some(i64::from_ne_bytes(entry.get(at..at + 8)?.try_into().ok()?))
+        } else {
+            None
+        };
+        Some(Snapshot {
+            name: String::from_utf8_lossy(name).into_owned(),
+            created,
+        })
+    }
+}
+
+// Helper: The `count` snapshots of a buffer filled by fs_snapshot_list,
+// each entry led by its length
+#[cfg(any(target_os = "macos", test))]
+fn parse_entries(buffer: &[u8], count: usize) -> Vec<Snapshot> {
+    let mut snapshots = Vec::with_capacity(count);
+    let mut offset = 0;
+    for _ in 0..count {
+        let Some(length) = u32_at(buffer, offset).filter(|length| *length > 0) else {
+            break;
+        };
+        let Some(entry) = buffer.get(offset..offset + length as usize) else {
+            break;
+        };
+        offset += length as usize;
+        snapshots.extend(Snapshot::decode(entry));
+    }
+    snapshots
+}
+
+// Helper: The native u32 at `offset`
+#[cfg(any(target_os = "macos", test))]
+fn u32_at(buffer: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(
+        buffer
+            .get(offset..offset.checked_add(4)?)?
+            .try_into()
+            .ok()?,
+    ))
+}
+
+#[cfg(target_os = "macos")]
+extern "C" {
+    fn fs_snapshot_list(
+        dirfd: c_int,
+        attrlist: *mut libc::attrlist,
+        attrbuf: *mut c_void,
+        bufsize: usize,
+        flags: u32,
+    ) -> c_int;
+}
+
+// Helper: The snapshots of the volume whose root is `root`
+#[cfg(target_os = "macos")]
+fn list(root: &File) -> io::Result<Vec<Snapshot>> {
+    let mut attrlist = libc::attrlist {
+        bitmapcount: libc::ATTR_BIT_MAP_COUNT,
+        reserved: 0,
+        commonattr: ATTR_CMN_RETURNED_ATTRS | ATTR_CMN_NAME | ATTR_CMN_CRTIME,
+        volattr: 0,
+        dirattr: 0,
+        fileattr: 0,
+        forkattr: 0,
+    };
+    // u64s to align the entries
+    let mut buffer = vec![0u64; 8 * 1024];
+    let mut snapshots = Vec::new();
+    loop {
+        let count = unsafe {
+            fs_snapshot_list(
+                root.as_raw_fd(),
+                &mut attrlist,
+                buffer.as_mut_ptr().cast(),
+                buffer.len() * 8,
+                0,
+            )
+        };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if count == 0 {
+            return Ok(snapshots);
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast(), buffer.len() * 8) };
+        snapshots.extend(parse_entries(bytes, count as usize));
+    }
+}
+
+// %{count, snapshots: [%{name, created_at}]} of the APFS volume holding
+// `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn apfs_snapshots_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "macos")]
+    {
+        let buf = match statfs(&path) {
+            Ok(buf) => buf,
+            Err(err) => return make_errno_error_tuple(env, atoms::statfs_failed(), err),
+        };
+        if c_field(&buf.f_fstypename) != "apfs" {
+            return make_error_tuple(env, atoms::not_apfs());
+        }
+        let snapshots = File::open(c_field(&buf.f_mntonname)).and_then(|root| list(&root));
+        let snapshots = match snapshots {
+            Ok(snapshots) => snapshots,
+            Err(err) if matches!(err.raw_os_error(), Some(libc::EPERM | libc::EACCES)) => {
+                return make_error_tuple(env, atoms::requires_full_disk_access());
+            }
+            Err(err) => return make_errno_error_tuple(env, atoms::snapshot_list_failed(), err),
+        };
+        let entries = snapshots
+            .iter()
+            .map(|snapshot| {
+                rustler::types::map::map_new(env)
+                    .map_put(atoms::name().to_term(env), snapshot.name.as_str())?
+                    .map_put(atoms::created_at().to_term(env), snapshot.created)
+            })
+            .collect::<NifResult<Vec<Term>>>()?;
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::count().to_term(env), entries.len())?
+            .map_put(atoms::snapshots().to_term(env), entries)?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper: An entry as fs_snapshot_list returns it
+    fn entry(name: &str, created: Option<i64>) -> Vec<u8> {
+        let returned = ATTR_CMN_NAME | created.map_or(0, |_| ATTR_CMN_CRTIME);
+        let fixed = ATTRIBUTES + 8 + created.map_or(0, |_| 16);
+        let length = (fixed + name.len() + 1).next_multiple_of(8);
+        let mut bytes = vec![0; length];
+        bytes[0..4].copy_from_slice(&(length as u32).to_ne_bytes());
+        bytes[4..8].copy_from_slice(&returned.to_ne_bytes());
+        let offset = (fixed - ATTRIBUTES) as i32;
+        bytes[ATTRIBUTES..ATTRIBUTES + 4].copy_from_slice(&offset.to_ne_bytes());
+        bytes[ATTRIBUTES + 4..ATTRIBUTES + 8]
+            .copy_from_slice(&(name.len() as u32 + 1).to_ne_bytes());
+        if let Some(created) = created {
+            bytes[ATTRIBUTES + 8..ATTRIBUTES + 16].copy_from_slice(&created.to_ne_bytes());
+        }
+        bytes[fixed..fixed + name.len()].copy_from_slice(name.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn decodes_snapshot_entries() {
+        let buffer = [
+            entry(
+                "com.apple.TimeMachine.2025-06-01-101500.local",
+                Some(1_748_772_900),
+            ),
+            entry("manual", None),
+            vec![0; 16],
+        ]
+        .concat();
+        assert_eq!(
+            parse_entries(&buffer, 3),
+            [
+                Snapshot {
+                    name: "com.apple.TimeMachine.2025-06-01-101500.local".to_string(),
+                    created: Some(1_748_772_900),
+                },
+                Snapshot {
+                    name: "manual".to_string(),
+                    created: None,
+                },
+            ]
+        );
+        assert_eq!(parse_entries(&buffer[..20], 1), []);
+    }
+}
@@ -0,0 +1,524 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// stat_fs_many: the stats of a list of paths in one NIF call, for health
+// checks that poll dozens of mounts every few seconds. All paths are decoded
+// before the first stat, and each gets a result of its own, in input order;
+// with unique: true, each distinct path is statted once and its result
+// repeated at every position it appears in.
+//
+// Building the result is most of the time of a batch of paths on local
+// disks, so the terms every entry shares, the keys of its map and :ok, are
+// made once per call, and the list of entries is made at once from an
+// array of the size it takes. With columnar: true, the result is instead a
+// map of five lists, `paths` and one of each figure, nil for a path that
+// failed, plus the {path, result} entries of those under `errors`: no map
+// per path, for callers feeding the figures to a time-series database.
+//
+// With concurrency: n or timeout: ms, the stats run on the shared worker
+// pool, n at a time, while the NIF waits; a stat the pool has no room to
+// queue gets :overloaded. Each stat's timeout counts from when it starts, so a
+// hung mount only costs its own slot that long; its worker is then left to
+// finish it on its own, no longer counting against n, and its late result is
+// dropped. Called from a pool job, as mount watchers do, the stats run on
+// threads of their own instead, as a job waiting on jobs queued behind it
+// could wait forever.
+//
+// stat_all_async does the same for every mount point as a pool job, sending
+// {:disk_space_report, ref, entries} once done, each entry being the mount
+// map of list_mounts plus the `at`, `result` and `duration_ms` of its stat.
+// Its references are kept in RUNNING until the report is sent, to turn away
+// a second call with a reference still in use. With collapse_apfs: true,
+// the volumes of an APFS container get a single entry (see apfs.rs).
+
+use crate::apfs;
+use crate::config;
+use crate::mounts::{self, filter, filter::Filter, Mount};
+use crate::pool::{self, Refused};
+use crate::{atoms, encode_stat, get_path_from_term, options, reshape_error, stat_path};
+use crate::{make_errno_error_tuple, make_error_tuple, make_error_tuple3, Space, StatError};
+use crate::{make_list, make_map, make_map_of};
+use rustler::env::OwnedEnv;
+use rustler::wrapper::NIF_TERM;
+use rustler::{Atom, Encoder, Env, ListIterator, LocalPid, NifResult, Term};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::{mpsc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub(crate) struct Options {
+    unique: bool,
+    concurrency: usize,
+    timeout: Option<Duration>,
+}
+
+impl Options {
+    pub(crate) fn decode(opts: Term) -> Result<Options, Atom> {
+        Ok(Options {
+            unique: options::get(opts, atoms::unique())?.unwrap_or(false),
+            concurrency: options::get_with(opts, atoms::concurrency(), |value| {
+                value.decode::<usize>().ok().filter(|n| *n > 0)
+            })?
+            .unwrap_or(1),
+            timeout: options::get::<u64>(opts, atoms::timeout())?
+                .map(Duration::from_millis)
+                .or_else(config::timeout),
+        })
+    }
+}
+
+// Why a job of `run` has no result
+#[derive(Debug)]
+enum Failure {
+    TimedOut,
+    NotStarted(Refused),
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn stat_fs_many_nif<'a>(
+    env: Env<'a>,
+    paths: ListIterator<'a>,
+    opts: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let decoded = Options::decode(opts).and_then(|options| {
+        Ok((
+            options,
+            options::get(opts, atoms::columnar())?.unwrap_or(false),
+        ))
+    });
+    let (options, columnar) = match decoded {
+        Ok(decoded) => decoded,
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let terms: Vec<Term<'a>> = paths.collect();
+    // The paths to stat, and for each position the index of its path
+    let mut jobs: Vec<Option<CString>> = Vec::with_capacity(terms.len());
+    let mut slots: Vec<usize> = Vec::with_capacity(terms.len());
+    let mut first: HashMap<CString, usize> = HashMap::new();
+    for term in &terms {
+        let path = get_path_from_term(env, *term).ok();
+        let earlier = path
+            .as_ref()
+            .filter(|_| options.unique)
+            .and_then(|path| first.get(path).copied());
+        match earlier {
+            Some(earlier) => slots.push(earlier),
+            None => {
+                if let Some(path) = path.as_ref().filter(|_| options.unique) {
+                    first.insert(path.clone(), jobs.len());
+                }
+                slots.push(jobs.len());
+                jobs.push(path);
+            }
+        }
+    }
+    let stats = stat_all(jobs, &options);
+    let shared = Shared::new(env);
+    if columnar {
+        return encode_columns(&shared, &terms, &slots, stats);
+    }
+    let results = stats
+        .into_iter()
+        .map(|stat| shared.result(stat))
+        .collect::<NifResult<Vec<Term<'a>>>>()?;
+    let mut pairs: Vec<NIF_TERM> = Vec::with_capacity(terms.len());
+    for (path, slot) in terms.iter().zip(slots) {
+        let pair = rustler::types::tuple::make_tuple(env, &[*path, results[slot]]);
+        pairs.push(pair.as_c_arg());
+    }
+    Ok(make_list(env, &pairs))
+}
+
+// The terms the entries of a batch share, made once per call
+struct Shared<'a> {
+    env: Env<'a>,
+    ok: Term<'a>,
+    nil: NIF_TERM,
+    // The keys of the map of a stat, in the order of `figures`
+    keys: [NIF_TERM; 4],
+}
+
+impl<'a> Shared<'a> {
+    fn new(env: Env<'a>) -> Shared<'a> {
+        let keys = [
+            atoms::available(),
+            atoms::free(),
+            atoms::total(),
+            atoms::used(),
+        ];
+        Shared {
+            env,
+            ok: atoms::ok().to_term(env),
+            nil: rustler::types::atom::nil().to_term(env).as_c_arg(),
+            keys: keys.map(|key| key.to_term(env).as_c_arg()),
+        }
+    }
+
+    // Helper: The figures of `space`, in the order of `keys`
+    fn figures(&self, space: &Space) -> [NIF_TERM; 4] {
+        [space.available, space.free, space.total, space.used]
+            .map(|figure| figure.encode(self.env).as_c_arg())
+    }
+
+    // Helper: The result of `stat` as stat/2 would return it
+    fn result(&self, stat: Result<Space, StatError>) -> NifResult<Term<'a>> {
+        match stat {
+            Ok(space) => {
+                let map = make_map_of(self.env, &self.keys, &self.figures(&space))?;
+                Ok(rustler::types::tuple::make_tuple(self.env, &[self.ok, map]))
+            }
+            Err(err) => Ok(reshape_error(self.env, encode_stat(self.env, Err(err))?)),
+        }
+    }
+}
+
+// Helper: %{paths, available, free, total, used, errors} of the stats of
+// `terms`, the stat of each being that of its slot in `stats`
+fn encode_columns<'a>(
+    shared: &Shared<'a>,
+    terms: &[Term<'a>],
+    slots: &[usize],
+    stats: Vec<Result<Space, StatError>>,
+) -> NifResult<Term<'a>> {
+    let env = shared.env;
+    let stats = stats
+        .into_iter()
+        .map(|stat| match stat {
+            Ok(space) => Ok(Ok(shared.figures(&space))),
+            Err(err) => shared.result(Err(err)).map(Err),
+        })
+        .collect::<NifResult<Vec<Result<[NIF_TERM; 4], Term<'a>>>>>()?;
+    let paths: Vec<NIF_TERM> = terms.iter().map(|path| path.as_c_arg()).collect();
+    let mut columns: [Vec<NIF_TERM>; 4] = std::array::from_fn(|_| Vec::with_capacity(terms.len()));
+    let mut errors: Vec<NIF_TERM> = Vec::new();
+    for (path, slot) in terms.iter().zip(slots) {
+        let figures = match &stats[*slot] {
+            Ok(figures) => *figures,
+            Err(result) => {
+                let entry = rustler::types::tuple::make_tuple(env, &[*path, *result]);
+                errors.push(entry.as_c_arg());
+                [shared.nil; 4]
+            }
+        };
+        for (column, figure) in columns.iter_mut().zip(figures) {
+            column.push(figure);
+        }
+    }
+    let [available, free, total, used] = columns.map(|column| make_list(env, &column));
+    make_map(
+        env,
+        [
+            (atoms::paths(), make_list(env, &paths)),
+            (atoms::available(), available),
+            (atoms::free(), free),
+            (atoms::total(), total),
+            (atoms::used(), used),
+            (atoms::errors(), make_list(env, &errors)),
+        ],
+    )
+}
+
+// The stats of `paths`, in order, with the concurrency and timeout of
+// `options`; a path that didn't decode gets invalid_path
+pub(crate) fn stat_all(
+    paths: Vec<Option<CString>>,
+    options: &Options,
+) -> Vec<Result<Space, StatError>> {
+    stat_all_timed(paths, options)
+        .into_iter()
+        .map(|(stat, _)| stat)
+        .collect()
+}
+
+// The same, each with how long it took: the timeout for a stat given up on,
+// and nothing for one that never started
+fn stat_all_timed(
+    paths: Vec<Option<CString>>,
+    options: &Options,
+) -> Vec<(Result<Space, StatError>, Duration)> {
+    if options.concurrency == 1 && options.timeout.is_none() {
+        return paths.into_iter().map(stat).collect();
+    }
+    let timeout = options.timeout.unwrap_or_default();
+    run(paths, options.concurrency, options.timeout, stat)
+        .into_iter()
+        .map(|stat| {
+            stat.unwrap_or_else(|failure| match failure {
+                Failure::TimedOut => (Err(StatError::Timeout(timeout)), timeout),
+                Failure::NotStarted(Refused::Overloaded) => {
+                    (Err(StatError::Reason(atoms::overloaded())), Duration::ZERO)
+                }
+                Failure::NotStarted(Refused::NotStarted(e)) => (
+                    Err(StatError::Errno(atoms::pool_failed(), e)),
+                    Duration::ZERO,
+                ),
+            })
+        })
+        .collect()
+}
+
+// The stat of `path`, given up on after `timeout`, as stat_fs_many would
+pub(crate) fn stat_within(path: &CString, timeout: Duration) -> Result<Space, StatError> {
+    let options = Options {
+        unique: false,
+        concurrency: 1,
+        timeout: Some(timeout),
+    };
+    let mut stats = stat_all(vec![Some(path.clone())], &options);
+    stats.pop().unwrap_or(Err(StatError::Timeout(timeout)))
+}
+
+// Helper: stat_path, for paths that decoded, timed
+fn stat(path: Option<CString>) -> (Result<Space, StatError>, Duration) {
+    let started = Instant::now();
+    let stat = match path {
+        Some(path) => stat_path(&path),
+        None => Err(StatError::Reason(atoms::invalid_path())),
+    };
+    (stat, started.elapsed())
+}
+
+// The references of the stat_all_async calls still running, in external
+// term format
+static RUNNING: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+// Takes its reference out of RUNNING once dropped
+struct Running(Vec<u8>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+        running.retain(|reference| *reference != self.0);
+    }
+}
+
+// Sends {:disk_space_report, ref, entries} to `pid` once every mount point
+// that survives `exclude:` (default [:pseudo]) has been statted with the
+// concurrency and timeout of `opts`, APFS volumes collapsed with
+// `collapse_apfs:`, or {:disk_space_report, ref, {:error,
+// info}} if the mount table can't be read
+#[rustler::nif]
+fn stat_all_async_nif<'a>(
+    env: Env<'a>,
+    opts: Term<'a>,
+    pid: LocalPid,
+    reference: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let decoded = options::get_with(opts, atoms::exclude(), filter::decode).and_then(|filters| {
+        Ok((
+            filters
+                .or_else(config::exclude)
+                .unwrap_or_else(|| vec![Filter::Pseudo]),
+            Options::decode(opts)?,
+            options::get(opts, atoms::collapse_apfs())?.unwrap_or(false),
+        ))
+    });
+    let (filters, options, collapse_apfs) = match decoded {
+        Ok(decoded) => decoded,
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let stored = reference.to_binary().as_slice().to_vec();
+    let running = {
+        let mut running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+        if running.contains(&stored) {
+            return make_error_tuple(env, atoms::already_running());
+        }
+        running.push(stored.clone());
+        Running(stored)
+    };
+    let mut owned_env = OwnedEnv::new();
+    let reference = owned_env.save(reference);
+    let submitted = pool::submit(move || {
+        let report = mounts::list().map(|mut table| {
+            table.retain(|mount| !filter::excluded(&filters, mount));
+            let (table, volumes) = if collapse_apfs {
+                apfs::collapse(table)
+            } else {
+                let volumes = vec![None; table.len()];
+                (table, volumes)
+            };
+            let paths = table
+                .iter()
+                .map(|mount| CString::new(mount.mount_point.as_str()).ok())
+                .collect();
+            let stats = stat_all_timed(paths, &options);
+            (table, volumes, stats, crate::watch::now())
+        });
+        // The process may be gone by now, which is fine
+        let _ = owned_env.send_and_clear(&pid, |env| {
+            let report = match report {
+                Ok((table, volumes, stats, at)) => encode_entries(env, at, &table, &volumes, stats),
+                Err(e) => make_errno_error_tuple(env, atoms::list_mounts_failed(), e)
+                    .map(|error| reshape_error(env, error)),
+            };
+            let report = report.unwrap_or_else(|_| atoms::error().to_term(env));
+            (atoms::disk_space_report(), reference.load(env), report)
+        });
+        drop(running);
+    });
+    match submitted {
+        Ok(()) => Ok(atoms::ok().encode(env)),
+        Err(refused) => pool::encode_refused(env, refused),
+    }
+}
+
+// Helper: The mount map of list_mounts plus the `at` and `result` of its stat
+pub(crate) fn encode_entry<'a>(
+    env: Env<'a>,
+    at: u64,
+    mount: &Mount,
+    stat: Result<Space, StatError>,
+) -> NifResult<Term<'a>> {
+    let result = reshape_error(env, encode_stat(env, stat)?);
+    mount
+        .encode(env)?
+        .map_put(atoms::at().to_term(env), at)?
+        .map_put(atoms::result().to_term(env), result)
+}
+
+// Helper: Each entry, with the `duration_ms` of its stat, and the
+// `apfs_volumes` of its container if collapsed
+fn encode_entries<'a>(
+    env: Env<'a>,
+    at: u64,
+    table: &[Mount],
+    volumes: &[Option<Vec<String>>],
+    stats: Vec<(Result<Space, StatError>, Duration)>,
+) -> NifResult<Term<'a>> {
+    let entries = table
+        .iter()
+        .zip(volumes)
+        .zip(stats)
+        .map(|((mount, volumes), (stat, duration))| {
+            let entry = encode_entry(env, at, mount, stat)?.map_put(
+                atoms::duration_ms().to_term(env),
+                duration.as_secs_f64() * 1000.0,
+            )?;
+            match volumes {
+                Some(volumes) => entry.map_put(atoms::apfs_volumes().to_term(env), volumes),
+                None => Ok(entry),
+            }
+        })
+        .collect::<NifResult<Vec<Term>>>()?;
+    Ok(entries.encode(env))
+}
+
+// Helper: `work` on each of `jobs` on the pool, up to
+// `concurrency` at a time, each given up on after `timeout`; the results are
+// in the order of `jobs`
+fn run<J, T>(
+    jobs: Vec<J>,
+    concurrency: usize,
+    timeout: Option<Duration>,
+    work: fn(J) -> T,
+) -> Vec<Result<T, Failure>>
+where
+    J: Send + 'static,
+    T: Send + 'static,
+{
+    let count = jobs.len();
+    let mut results: Vec<Option<Result<T, Failure>>> = (0..count).map(|_| None).collect();
+    let mut jobs = jobs.into_iter().enumerate();
+    let (sender, receiver) = mpsc::channel();
+    // The jobs running, with when they started
+    let mut running: Vec<(usize, Instant)> = Vec::new();
+    let mut done = 0;
+    while done < count {
+        while running.len() < concurrency {
+            let Some((i, job)) = jobs.next() else {
+                break;
+            };
+            let sender = sender.clone();
+            let job = move || {
+                // Nobody may be listening any more
+                let _ = sender.send((i, work(job)));
+            };
+            let started = if pool::on_worker() {
+                thread::Builder::new()
+                    .name("disk_space_stat".to_string())
+                    .spawn(job)
+                    .map(drop)
+                    .map_err(Refused::NotStarted)
+            } else {
+                pool::submit(job)
+            };
+            match started {
+                Ok(_) => running.push((i, Instant::now())),
+                Err(e) => {
+                    results[i] = Some(Err(Failure::NotStarted(e)));
+                    done += 1;
+                }
+            }
+        }
+        if running.is_empty() {
+            continue;
+        }
+        let deadline =
+            timeout.and_then(|timeout| running.iter().map(|(_, started)| *started + timeout).min());
+        let received = match deadline {
+            Some(deadline) => receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+            None => receiver.recv().ok(),
+        };
+        match received {
+            Some((i, result)) => {
+                // Unless it already timed out
+                if let Some(at) = running.iter().position(|(j, _)| *j == i) {
+                    running.swap_remove(at);
+                    results[i] = Some(Ok(result));
+                    done += 1;
+                }
+            }
+            None => {
+                let now = Instant::now();
+                running.retain(|(i, started)| {
+                    let expired = timeout.is_some_and(|timeout| now >= *started + timeout);
+                    if expired {
+                        results[*i] = Some(Err(Failure::TimedOut));
+                        done += 1;
+                    }
+                    !expired
+                });
+            }
+        }
+    }
+    results.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nap(millis: u64) -> u64 {
+        thread::sleep(Duration::from_millis(millis));
+        millis
+    }
+
+    #[test]
+    fn keeps_the_order_of_the_jobs() {
+        let jobs = vec![40, 0, 20, 10, 30];
+        let results = run(jobs.clone(), 3, None, nap);
+        let results: Vec<u64> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, jobs);
+    }
+
+    #[test]
+    fn times_out_each_job_on_its_own() {
+        let started = Instant::now();
+        let results = run(
+            vec![2000, 10, 2000, 10, 10],
+            2,
+            Some(Duration::from_millis(200)),
+            nap,
+        );
+        assert!(matches!(results[0], Err(Failure::TimedOut)));
+        assert!(matches!(results[1], Ok(10)));
+        assert!(matches!(results[2], Err(Failure::TimedOut)));
+        assert!(matches!(results[3], Ok(10)));
+        assert!(matches!(results[4], Ok(10)));
+        // Neither hung job held up the rest for longer than its own timeout
+        assert!(started.elapsed() < Duration::from_millis(1500));
+    }
+}
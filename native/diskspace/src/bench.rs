@@ -0,0 +1,140 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// bench_stat: a micro-benchmark of stat_fs for whoever finds it slow, say
+// on a NAS, to run there and paste the output of into a report. It stats a
+// path `iterations` times in a row as stat_fs does, without counting them
+// for nif_stats, and reports the latencies of the stats, the allocations
+// made on the way and the call that asked the OS for the figures.
+//
+// Without encode the stats are only made, none encoded, so that what the
+// call costs is told apart from what building its result costs; with it,
+// each is also encoded as stat_fs returns it, into an environment of its
+// own that is cleared after each. The path is decoded once, before.
+//
+// Behind the bench feature, off by default: without it, none of this is
+// compiled, nor is the allocator that counts the allocations, and the
+// Elixir stub of bench_stat returns {:error, :bench_disabled}.
+
+use crate::small_path::SmallPath;
+use crate::{alloc_count, encode_stat, make_error_tuple, stat_dir};
+use rustler::env::OwnedEnv;
+use rustler::{Encoder, Env, NifResult, Term};
+use std::time::{Duration, Instant};
+
+mod atoms {
+    rustler::atoms! {
+        ok,
+        invalid_path,
+        iterations,
+        encode,
+        backend,
+        failures,
+        latency_ns,
+        min,
+        median,
+        p99,
+        max,
+        allocations,
+        total,
+        per_call,
+    }
+}
+
+// The call that asks the OS
+#[cfg(target_os = "linux")]
+const BACKEND: &str = "statfs";
+#[cfg(all(unix, not(target_os = "linux")))]
+const BACKEND: &str = "statvfs";
+#[cfg(windows)]
+const BACKEND: &str = "GetDiskFreeSpaceExW";
+
+// Helper: The latency of `sorted` that `percent` of them are no longer than
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (sorted.len() * percent).div_ceil(100);
+    sorted
+        .get(rank.saturating_sub(1))
+        .copied()
+        .unwrap_or_default()
+}
+
+// Helper: `latency` in nanoseconds
+fn nanos(latency: Duration) -> u64 {
+    u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX)
+}
+
+// {:ok, %{iterations, encode, backend, failures, latency_ns: %{min, median,
+// p99, max}, allocations: %{total, per_call}}} of statting `path`
+// `iterations` times
+#[rustler::nif(schedule = "DirtyIo")]
+fn bench_stat_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    iterations: usize,
+    encode: bool,
+) -> NifResult<Term<'a>> {
+    let Ok(path) = SmallPath::from_term(path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let mut latencies = Vec::with_capacity(iterations);
+    let mut failures = 0u64;
+    let mut owned_env = OwnedEnv::new();
+    let before = alloc_count::count();
+    for _ in 0..iterations {
+        let started = Instant::now();
+        let stat = stat_dir(path.as_c_str());
+        failures += u64::from(stat.is_err());
+        if encode {
+            let _ = owned_env.run(|env| encode_stat(env, stat).is_ok());
+        }
+        latencies.push(started.elapsed());
+        if encode {
+            owned_env.clear();
+        }
+    }
+    let allocations = alloc_count::count() - before;
+    latencies.sort_unstable();
+    let latency = rustler::types::map::map_new(env)
+        .map_put(
+            atoms::min().to_term(env),
+            nanos(latencies.first().copied().unwrap_or_default()),
+        )?
+        .map_put(
+            atoms::median().to_term(env),
+            nanos(percentile(&latencies, 50)),
+        )?
+        .map_put(atoms::p99().to_term(env), nanos(percentile(&latencies, 99)))?
+        .map_put(
+            atoms::max().to_term(env),
+            nanos(latencies.last().copied().unwrap_or_default()),
+        )?;
+    let per_call = match iterations {
+        0 => 0.0,
+        iterations => allocations as f64 / iterations as f64,
+    };
+    let allocations = rustler::types::map::map_new(env)
+        .map_put(atoms::total().to_term(env), allocations)?
+        .map_put(atoms::per_call().to_term(env), per_call)?;
+    let map = rustler::types::map::map_new(env)
+        .map_put(atoms::iterations().to_term(env), iterations)?
+        .map_put(atoms::encode().to_term(env), encode)?
+        .map_put(atoms::backend().to_term(env), BACKEND)?
+        .map_put(atoms::failures().to_term(env), failures)?
+        .map_put(atoms::latency_ns().to_term(env), latency)?
+        .map_put(atoms::allocations().to_term(env), allocations)?;
+    Ok((atoms::ok(), map).encode(env))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_latencies_to_the_nearest() {
+        let sorted: Vec<Duration> = (1..=200).map(Duration::from_micros).collect();
+        assert_eq!(percentile(&sorted, 50), Duration::from_micros(100));
+        assert_eq!(percentile(&sorted, 99), Duration::from_micros(198));
+        assert_eq!(percentile(&sorted[..1], 99), Duration::from_micros(1));
+        assert_eq!(percentile(&[], 50), Duration::ZERO);
+    }
+}
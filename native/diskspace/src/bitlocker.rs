@@ -0,0 +1,317 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// encryption_status: whether the volume holding a path is protected by
+// BitLocker, for compliance reports. Windows only; elsewhere it returns
+// {:error, :unsupported}, backing_device telling of dm-crypt on Linux.
+//
+// The status comes from the Win32_EncryptableVolume of the volume in the
+// root\CIMV2\Security\MicrosoftVolumeEncryption namespace of WMI, found by
+// its DeviceID, the \\?\Volume{GUID}\ name of the volume: its
+// ProtectionStatus, ConversionStatus and EncryptionMethod, as
+// GetProtectionStatus, GetConversionStatus and GetEncryptionMethod return
+// them, kept as properties since Windows 8. The namespace is open to
+// administrators only, others getting {:error, :access_denied}, and missing
+// on editions without BitLocker, which get {:error, :unsupported}. A volume
+// BitLocker can't encrypt has no Win32_EncryptableVolume, and is reported
+// unprotected. A locked volume is protected, though nothing more is known.
+//
+// As for quota_info, COM is initialized on a thread of its own for each
+// call, multithreaded, as WMI prefers.
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Env, NifResult, Term};
+#[cfg(windows)]
+use {
+    crate::{make_winapi_error_tuple, volume_device, volume_root},
+    rustler::{Atom, Encoder},
+    std::thread,
+    widestring::WideCString,
+    windows::core::{w, BSTR, HRESULT, PCWSTR},
+    windows::Win32::Foundation::E_ACCESSDENIED,
+    windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoSetProxyBlanket, CoUninitialize, CLSCTX_INPROC_SERVER,
+        COINIT_MULTITHREADED, EOAC_NONE, RPC_C_AUTHN_LEVEL_PKT_PRIVACY,
+        RPC_C_IMP_LEVEL_IMPERSONATE,
+    },
+    windows::Win32::System::Rpc::{RPC_C_AUTHN_WINNT, RPC_C_AUTHZ_NONE},
+    windows::Win32::System::Variant::{VariantClear, VariantToUInt32, VARIANT},
+    windows::Win32::System::Wmi::{
+        IWbemClassObject, IWbemLocator, WbemLocator, WBEM_E_ACCESS_DENIED,
+        WBEM_E_INVALID_NAMESPACE, WBEM_FLAG_FORWARD_ONLY, WBEM_FLAG_RETURN_IMMEDIATELY,
+        WBEM_INFINITE,
+    },
+};
+
+// The ConversionStatus of a volume
+#[cfg(any(windows, test))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Conversion {
+    FullyDecrypted,
+    FullyEncrypted,
+    EncryptionInProgress,
+    DecryptionInProgress,
+    EncryptionPaused,
+    DecryptionPaused,
+}
+
+// The EncryptionMethod of a volume
+#[cfg(any(windows, test))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Method {
+    None,
+    Aes128Diffuser,
+    Aes256Diffuser,
+    Aes128,
+    Aes256,
+    // Encrypted by the drive itself, as eDrives are
+    Hardware,
+    XtsAes128,
+    XtsAes256,
+}
+
+#[cfg(any(windows, test))]
+#[derive(Debug, PartialEq)]
+struct Encryption {
+    protected: bool,
+    conversion: Option<Conversion>,
+    method: Option<Method>,
+}
+
+#[cfg(any(windows, test))]
+impl Encryption {
+    // From the ProtectionStatus, ConversionStatus and EncryptionMethod of a
+    // Win32_EncryptableVolume, None for those it lacks or that are unknown
+    fn decode(protection: Option<u32>, conversion: Option<u32>, method: Option<u32>) -> Self {
+        let conversions = [
+            Conversion::FullyDecrypted,
+            Conversion::FullyEncrypted,
+            Conversion::EncryptionInProgress,
+            Conversion::DecryptionInProgress,
+            Conversion::EncryptionPaused,
+            Conversion::DecryptionPaused,
+        ];
+        let methods = [
+            Method::None,
+            Method::Aes128Diffuser,
+            Method::Aes256Diffuser,
+            Method::Aes128,
+            Method::Aes256,
+            Method::Hardware,
+            Method::XtsAes128,
+            Method::XtsAes256,
+        ];
+        Encryption {
+            // 2 for a locked volume, whose status is unknown
+            protected: matches!(protection, Some(1 | 2)),
+            conversion: conversion.and_then(|status| conversions.get(status as usize).copied()),
+            method: method.and_then(|method| methods.get(method as usize).copied()),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Conversion {
+    fn atom(self) -> Atom {
+        match self {
+            Conversion::FullyDecrypted => atoms::fully_decrypted(),
+            Conversion::FullyEncrypted => atoms::fully_encrypted(),
+            Conversion::EncryptionInProgress => atoms::encryption_in_progress(),
+            Conversion::DecryptionInProgress => atoms::decryption_in_progress(),
+            Conversion::EncryptionPaused => atoms::encryption_paused(),
+            Conversion::DecryptionPaused => atoms::decryption_paused(),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Method {
+    fn atom(self) -> Atom {
+        match self {
+            Method::None => atoms::none(),
+            Method::Aes128Diffuser => atoms::aes_128_diffuser(),
+            Method::Aes256Diffuser => atoms::aes_256_diffuser(),
+            Method::Aes128 => atoms::aes_128(),
+            Method::Aes256 => atoms::aes_256(),
+            Method::Hardware => atoms::hardware(),
+            Method::XtsAes128 => atoms::xts_aes_128(),
+            Method::XtsAes256 => atoms::xts_aes_256(),
+        }
+    }
+}
+
+// Helper: The unsigned property `name` of `object`, None if it lacks it
+#[cfg(windows)]
+fn property(object: &IWbemClassObject, name: PCWSTR) -> Option<u32> {
+    let mut value = VARIANT::default();
+    unsafe { object.Get(name, 0, &mut value, None, None) }.ok()?;
+    let number = unsafe { VariantToUInt32(&value) }.ok();
+    let _ = unsafe { VariantClear(&mut value) };
+    number
+}
+
+// Helper: The encryption of the volume whose DeviceID is `device_id`, None
+// if BitLocker can't encrypt it
+#[cfg(windows)]
+fn query(device_id: &str) -> windows::core::Result<Option<Encryption>> {
+    let locator: IWbemLocator =
+        unsafe { CoCreateInstance(&WbemLocator, None, CLSCTX_INPROC_SERVER) }?;
+    let services = unsafe {
+        locator.ConnectServer(
+            &BSTR::from(r"root\CIMV2\Security\MicrosoftVolumeEncryption"),
+            &BSTR::new(),
+            &BSTR::new(),
+            &BSTR::new(),
+            0,
+            &BSTR::new(),
+            None,
+        )
+    }?;
+    // The namespace takes calls encrypted, and made as the caller
+    unsafe {
+        CoSetProxyBlanket(
+            &services,
+            RPC_C_AUTHN_WINNT,
+            RPC_C_AUTHZ_NONE,
+            PCWSTR::null(),
+            RPC_C_AUTHN_LEVEL_PKT_PRIVACY,
+            RPC_C_IMP_LEVEL_IMPERSONATE,
+            None,
+            EOAC_NONE,
+        )
+    }?;
+    // Backslashes are escaped in WQL strings
+    let query = format!(
+        "SELECT * FROM Win32_EncryptableVolume WHERE DeviceID = '{}'",
+        device_id.replace('\\', "\\\\")
+    );
+    let objects = unsafe {
+        services.ExecQuery(
+            &BSTR::from("WQL"),
+            &BSTR::from(query),
+            WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY,
+            None,
+        )
+    }?;
+    let mut found = [None];
+    let mut returned = 0u32;
+    unsafe { objects.Next(WBEM_INFINITE, &mut found, &mut returned) }.ok()?;
+    let Some(volume) = found[0].take().filter(|_| returned == 1) else {
+        return Ok(None);
+    };
+    Ok(Some(Encryption::decode(
+        property(&volume, w!("ProtectionStatus")),
+        property(&volume, w!("ConversionStatus")),
+        property(&volume, w!("EncryptionMethod")),
+    )))
+}
+
+// {:ok, %{protected, encryption_in_progress, conversion_status, method}} of
+// the volume holding `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn encryption_status_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(windows)]
+    {
+        let Some(path) = path
+            .to_str()
+            .ok()
+            .and_then(|path| WideCString::from_str(path).ok())
+        else {
+            return make_error_tuple(env, atoms::path_conversion_failed());
+        };
+        let root = match volume_root(&path) {
+            Ok(root) => root,
+            Err(code) => return make_winapi_error_tuple(env, atoms::invalid_path(), code),
+        };
+        // Network shares and subst drives have no volume
+        let Ok(volume) = volume_device(&root) else {
+            return make_error_tuple(env, atoms::not_block_device());
+        };
+        let device_id = format!("{}\\", volume.to_string_lossy());
+        let encryption = thread::spawn(move || {
+            unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) }.ok()?;
+            let encryption = query(&device_id);
+            unsafe { CoUninitialize() };
+            encryption
+        })
+        .join()
+        .unwrap_or_else(|_| Err(windows::core::Error::empty()));
+        let encryption = match encryption {
+            Ok(encryption) => encryption.unwrap_or(Encryption {
+                protected: false,
+                conversion: None,
+                method: None,
+            }),
+            Err(e) if e.code() == HRESULT(WBEM_E_ACCESS_DENIED.0) || e.code() == E_ACCESSDENIED => {
+                return make_error_tuple(env, atoms::access_denied())
+            }
+            Err(e) if e.code() == HRESULT(WBEM_E_INVALID_NAMESPACE.0) => {
+                return make_error_tuple(env, atoms::unsupported())
+            }
+            Err(e) => {
+                return make_winapi_error_tuple(env, atoms::winapi_failed(), e.code().0 as u32)
+            }
+        };
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::protected().to_term(env), encryption.protected)?
+            .map_put(
+                atoms::encryption_in_progress().to_term(env),
+                encryption.conversion == Some(Conversion::EncryptionInProgress),
+            )?
+            .map_put(
+                atoms::conversion_status().to_term(env),
+                encryption.conversion.map(Conversion::atom),
+            )?
+            .map_put(
+                atoms::method().to_term(env),
+                encryption.method.map(Method::atom),
+            )?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_the_status_of_an_encryptable_volume() {
+        assert_eq!(
+            Encryption::decode(Some(1), Some(1), Some(7)),
+            Encryption {
+                protected: true,
+                conversion: Some(Conversion::FullyEncrypted),
+                method: Some(Method::XtsAes256),
+            }
+        );
+        // Encrypting, the protectors not on yet
+        let encrypting = Encryption::decode(Some(0), Some(2), Some(6));
+        assert!(!encrypting.protected);
+        assert_eq!(
+            encrypting.conversion,
+            Some(Conversion::EncryptionInProgress)
+        );
+        // Locked, and before Windows 8
+        assert_eq!(
+            Encryption::decode(Some(2), None, None),
+            Encryption {
+                protected: true,
+                conversion: None,
+                method: None,
+            }
+        );
+        assert_eq!(
+            Encryption::decode(Some(0), Some(0), Some(0)).method,
+            Some(Method::None)
+        );
+        assert_eq!(Encryption::decode(None, Some(9), Some(9)).method, None);
+    }
+}
@@ -0,0 +1,432 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// btrfs_details: how the space of a btrfs filesystem is allocated, for stat's
+// btrfs_details option. Its statfs figures can't say how much a user may
+// still write: data and metadata live in chunks of their own, allocated from
+// the devices on demand, each kind with its own RAID profile keeping one or
+// more copies of every byte.
+//
+// On a descriptor of the path, BTRFS_IOC_FS_INFO gives the number of
+// devices, BTRFS_IOC_DEV_INFO the size of each and how much of it chunks
+// take, and BTRFS_IOC_SPACE_INFO the size and use of the chunks of each
+// kind and profile. The estimate of the bytes still writable follows
+// `btrfs filesystem usage`: the free space of the data chunks, plus the
+// unallocated space of the devices divided by the number of copies the data
+// profile keeps.
+//
+// None of these ioctls take privileges. btrfs being Linux only, elsewhere
+// btrfs_details returns {:ok, nil} like for any other filesystem. The
+// qgroups of subvolumes, for btrfs_qgroup, are in qgroup.rs.
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Encoder, Env, NifResult, Term};
+#[cfg(target_os = "linux")]
+use {
+    crate::{make_errno_error_tuple, mounts, path_from_cstring},
+    rustler::Atom,
+    std::fs::File,
+    std::io,
+    std::os::unix::io::AsRawFd,
+    std::path::Path,
+};
+
+mod qgroup;
+
+// Block group flags of <linux/btrfs_tree.h>
+#[cfg(any(target_os = "linux", test))]
+mod flags {
+    pub(super) const DATA: u64 = 1 << 0;
+    pub(super) const METADATA: u64 = 1 << 2;
+    pub(super) const RAID0: u64 = 1 << 3;
+    pub(super) const RAID1: u64 = 1 << 4;
+    pub(super) const DUP: u64 = 1 << 5;
+    pub(super) const RAID10: u64 = 1 << 6;
+    pub(super) const RAID5: u64 = 1 << 7;
+    pub(super) const RAID6: u64 = 1 << 8;
+    pub(super) const RAID1C3: u64 = 1 << 9;
+    pub(super) const RAID1C4: u64 = 1 << 10;
+    pub(super) const GLOBAL_RSV: u64 = 1 << 49;
+}
+
+#[cfg(any(target_os = "linux", test))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Data,
+    Metadata,
+    // Data and metadata in the same chunks, on small filesystems
+    Mixed,
+    System,
+    GlobalReserve,
+}
+
+#[cfg(any(target_os = "linux", test))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Profile {
+    Single,
+    Dup,
+    Raid0,
+    Raid1,
+    Raid1c3,
+    Raid1c4,
+    Raid10,
+    Raid5,
+    Raid6,
+}
+
+// One entry of BTRFS_IOC_SPACE_INFO
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq)]
+struct Space {
+    kind: Kind,
+    profile: Profile,
+    total: u64,
+    used: u64,
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl Space {
+    fn decode(flags: u64, total: u64, used: u64) -> Space {
+        let kind = if flags & flags::GLOBAL_RSV != 0 {
+            Kind::GlobalReserve
+        } else if flags & flags::DATA != 0 && flags & flags::METADATA != 0 {
+            Kind::Mixed
+        } else if flags & flags::DATA != 0 {
+            Kind::Data
+        } else if flags & flags::METADATA != 0 {
+            Kind::Metadata
+        } else {
+            // Flagged SYSTEM, the chunks of the chunk tree
+            Kind::System
+        };
+        Space {
+            kind,
+            profile: Profile::decode(flags),
+            total,
+            used,
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl Profile {
+    fn decode(flags: u64) -> Profile {
+        [
+            (flags::RAID0, Profile::Raid0),
+            (flags::RAID1, Profile::Raid1),
+            (flags::DUP, Profile::Dup),
+            (flags::RAID10, Profile::Raid10),
+            (flags::RAID5, Profile::Raid5),
+            (flags::RAID6, Profile::Raid6),
+            (flags::RAID1C3, Profile::Raid1c3),
+            (flags::RAID1C4, Profile::Raid1c4),
+        ]
+        .into_iter()
+        .find(|(flag, _)| flags & flag != 0)
+        .map_or(Profile::Single, |(_, profile)| profile)
+    }
+
+    // The raw bytes each byte takes on `devices` devices, as a fraction
+    fn ratio(self, devices: u64) -> (u64, u64) {
+        match self {
+            Profile::Single | Profile::Raid0 => (1, 1),
+            Profile::Dup | Profile::Raid1 | Profile::Raid10 => (2, 1),
+            Profile::Raid1c3 => (3, 1),
+            Profile::Raid1c4 => (4, 1),
+            Profile::Raid5 => (devices.max(2), devices.max(2) - 1),
+            Profile::Raid6 => (devices.max(3), devices.max(3) - 2),
+        }
+    }
+}
+
+// The allocation of a filesystem
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug)]
+struct Details {
+    spaces: Vec<Space>,
+    devices: u64,
+    unallocated: u64,
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl Details {
+    // The copies the data keeps, that of its most demanding profile
+    fn data_ratio(&self) -> (u64, u64) {
+        self.spaces
+            .iter()
+            .filter(|space| matches!(space.kind, Kind::Data | Kind::Mixed))
+            .map(|space| space.profile.ratio(self.devices))
+            .max_by(|(a, b), (c, d)| (a * d).cmp(&(c * b)))
+            .unwrap_or((1, 1))
+    }
+
+    fn estimated_writable(&self) -> u64 {
+        let free: u64 = self
+            .spaces
+            .iter()
+            .filter(|space| matches!(space.kind, Kind::Data | Kind::Mixed))
+            .map(|space| space.total.saturating_sub(space.used))
+            .sum();
+        let (raw, logical) = self.data_ratio();
+        let unallocated = (self.unallocated as u128 * logical as u128 / raw as u128) as u64;
+        free.saturating_add(unallocated)
+    }
+}
+
+// Helper: The entries of a BTRFS_IOC_SPACE_INFO buffer, {space_slots,
+// total_spaces} followed by {flags, total_bytes, used_bytes} for each
+#[cfg(any(target_os = "linux", test))]
+fn parse_spaces(buffer: &[u64]) -> Vec<Space> {
+    let count = buffer.get(1).copied().unwrap_or(0) as usize;
+    buffer
+        .get(2..)
+        .unwrap_or_default()
+        .chunks_exact(3)
+        .take(count)
+        .map(|entry| Space::decode(entry[0], entry[1], entry[2]))
+        .collect()
+}
+
+// Helper: _IOC of <asm-generic/ioctl.h>, for the BTRFS_IOCTL_MAGIC ioctls
+#[cfg(target_os = "linux")]
+const fn ioc(dir: u64, nr: u64, size: usize) -> u64 {
+    (dir << 30) | ((size as u64) << 16) | (0x94 << 8) | nr
+}
+
+#[cfg(target_os = "linux")]
+const IOC_READ: u64 = 2;
+#[cfg(target_os = "linux")]
+const IOC_READ_WRITE: u64 = 3;
+
+// struct btrfs_ioctl_fs_info_args, of which only the number of devices and
+// the highest devid matter here
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FsInfo {
+    max_id: u64,
+    num_devices: u64,
+    rest: [u8; 1008],
+}
+
+// struct btrfs_ioctl_dev_info_args
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct DevInfo {
+    devid: u64,
+    uuid: [u8; 16],
+    bytes_used: u64,
+    total_bytes: u64,
+    rest: [u8; 4056],
+}
+
+// Helper: An ioctl on `file`, taking `arg`
+#[cfg(target_os = "linux")]
+fn ioctl<T>(file: &File, request: u64, arg: &mut T) -> io::Result<()> {
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg as *mut T) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Helper: Whether `path` lies on a btrfs filesystem
+#[cfg(target_os = "linux")]
+fn on_btrfs(path: &Path) -> io::Result<bool> {
+    Ok(mounts::holding(path)?.is_some_and(|mount| mount.fs_type == "btrfs"))
+}
+
+// Helper: The allocation of the btrfs filesystem `file` lies on
+#[cfg(target_os = "linux")]
+fn read(file: &File) -> io::Result<Details> {
+    let mut info = FsInfo {
+        max_id: 0,
+        num_devices: 0,
+        rest: [0; 1008],
+    };
+    ioctl(file, ioc(IOC_READ, 31, size_of::<FsInfo>()), &mut info)?;
+    let mut unallocated = 0u64;
+    for devid in 1..=info.max_id {
+        let mut dev = DevInfo {
+            devid,
+            uuid: [0; 16],
+            bytes_used: 0,
+            total_bytes: 0,
+            rest: [0; 4056],
+        };
+        match ioctl(
+            file,
+            ioc(IOC_READ_WRITE, 30, size_of::<DevInfo>()),
+            &mut dev,
+        ) {
+            Ok(()) => unallocated += dev.total_bytes.saturating_sub(dev.bytes_used),
+            // A devid left unused by a removed device
+            Err(err) if err.raw_os_error() == Some(libc::ENODEV) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    // A first call with no slots tells how many there are; the allocation
+    // may change in between, so the second one is given some more
+    let space_info = ioc(IOC_READ_WRITE, 20, 2 * size_of::<u64>());
+    let mut header = [0u64; 2];
+    ioctl(file, space_info, &mut header)?;
+    let slots = header[1] as usize + 4;
+    let mut buffer = vec![0u64; 2 + 3 * slots];
+    buffer[0] = slots as u64;
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), space_info as _, buffer.as_mut_ptr()) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Details {
+        spaces: parse_spaces(&buffer),
+        devices: info.num_devices,
+        unallocated,
+    })
+}
+
+// %{profiles, devices, unallocated_bytes, data_ratio, estimated_writable_bytes}
+// of the btrfs filesystem holding `path`, or nil for another filesystem
+#[rustler::nif(schedule = "DirtyIo")]
+fn btrfs_details_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "linux")]
+    {
+        let Some(path) = path_from_cstring(&path) else {
+            return make_error_tuple(env, atoms::invalid_path());
+        };
+        match on_btrfs(&path) {
+            Ok(true) => {}
+            Ok(false) => return Ok((atoms::ok(), rustler::types::atom::nil()).encode(env)),
+            Err(err) => return make_errno_error_tuple(env, atoms::invalid_path(), err),
+        }
+        let details = match File::open(&path).and_then(|file| read(&file)) {
+            Ok(details) => details,
+            Err(err) => return make_errno_error_tuple(env, atoms::btrfs_ioctl_failed(), err),
+        };
+        let profiles = details
+            .spaces
+            .iter()
+            .map(|space| {
+                rustler::types::map::map_new(env)
+                    .map_put(atoms::type_().to_term(env), space.kind.atom())?
+                    .map_put(atoms::profile().to_term(env), space.profile.atom())?
+                    .map_put(atoms::total_bytes().to_term(env), space.total)?
+                    .map_put(atoms::used_bytes().to_term(env), space.used)
+            })
+            .collect::<NifResult<Vec<Term<'a>>>>()?;
+        let (raw, logical) = details.data_ratio();
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::profiles().to_term(env), profiles)?
+            .map_put(atoms::devices().to_term(env), details.devices)?
+            .map_put(atoms::unallocated_bytes().to_term(env), details.unallocated)?
+            .map_put(
+                atoms::data_ratio().to_term(env),
+                raw as f64 / logical as f64,
+            )?
+            .map_put(
+                atoms::estimated_writable_bytes().to_term(env),
+                details.estimated_writable(),
+            )?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        Ok((atoms::ok(), rustler::types::atom::nil()).encode(env))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Kind {
+    fn atom(self) -> Atom {
+        match self {
+            Kind::Data => atoms::data(),
+            Kind::Metadata => atoms::metadata(),
+            Kind::Mixed => atoms::mixed(),
+            Kind::System => atoms::system(),
+            Kind::GlobalReserve => atoms::global_reserve(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Profile {
+    fn atom(self) -> Atom {
+        match self {
+            Profile::Single => atoms::single(),
+            Profile::Dup => atoms::dup(),
+            Profile::Raid0 => atoms::raid0(),
+            Profile::Raid1 => atoms::raid1(),
+            Profile::Raid1c3 => atoms::raid1c3(),
+            Profile::Raid1c4 => atoms::raid1c4(),
+            Profile::Raid10 => atoms::raid10(),
+            Profile::Raid5 => atoms::raid5(),
+            Profile::Raid6 => atoms::raid6(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_space_info_entries() {
+        let buffer = [
+            8,
+            4,
+            flags::DATA,
+            100,
+            40,
+            flags::RAID1,
+            8,
+            1,
+            flags::METADATA | flags::RAID1,
+            20,
+            5,
+            flags::GLOBAL_RSV | flags::METADATA,
+            3,
+            0,
+            0,
+            0,
+            0,
+        ];
+        let spaces = parse_spaces(&buffer);
+        assert_eq!(spaces.len(), 4);
+        assert_eq!(
+            spaces[1],
+            Space {
+                kind: Kind::System,
+                profile: Profile::Raid1,
+                total: 8,
+                used: 1
+            }
+        );
+        assert_eq!(spaces[3].kind, Kind::GlobalReserve);
+        assert_eq!(
+            Space::decode(flags::DATA | flags::METADATA, 0, 0).kind,
+            Kind::Mixed
+        );
+    }
+
+    #[test]
+    fn estimates_writable_bytes_by_data_profile() {
+        let details = Details {
+            spaces: vec![
+                Space::decode(flags::DATA | flags::RAID1, 100, 40),
+                Space::decode(flags::METADATA | flags::RAID1, 20, 5),
+            ],
+            devices: 2,
+            unallocated: 200,
+        };
+        assert_eq!(details.data_ratio(), (2, 1));
+        assert_eq!(details.estimated_writable(), 60 + 100);
+        let raid5 = Details {
+            spaces: vec![Space::decode(flags::DATA | flags::RAID5, 0, 0)],
+            devices: 3,
+            unallocated: 300,
+        };
+        assert_eq!(raid5.estimated_writable(), 200);
+    }
+}
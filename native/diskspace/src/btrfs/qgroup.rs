@@ -0,0 +1,356 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// btrfs_qgroup: the usage and limits of the qgroup of the subvolume holding
+// a path, for when each tenant gets a subvolume of their own and the free
+// space of the filesystem says nothing about theirs.
+//
+// The subvolume comes from BTRFS_IOC_INO_LOOKUP, which tells any caller the
+// tree a file lies in, and its qgroup is 0/<subvolume id>. The qgroup's
+// items come from a BTRFS_IOC_TREE_SEARCH of the quota tree, which takes
+// CAP_SYS_ADMIN: its info item holds the bytes it references and those it
+// alone references (exclusive), its limit item the limits of each, and its
+// relation items the higher-level qgroups it belongs to, which a caller
+// aggregating nested subvolumes can follow. A filesystem without a quota
+// tree, or with qgroups turned off, gives {:error, :qgroups_disabled}.
+
+use super::*;
+#[cfg(target_os = "linux")]
+use rustler::Encoder;
+
+// Key types of <linux/btrfs_tree.h>
+#[cfg(target_os = "linux")]
+const QGROUP_STATUS_KEY: u32 = 240;
+#[cfg(any(target_os = "linux", test))]
+const QGROUP_INFO_KEY: u32 = 242;
+#[cfg(any(target_os = "linux", test))]
+const QGROUP_LIMIT_KEY: u32 = 244;
+#[cfg(any(target_os = "linux", test))]
+const QGROUP_RELATION_KEY: u32 = 246;
+
+// Flags of the qgroup status and limit items
+#[cfg(target_os = "linux")]
+const STATUS_FLAG_ON: u64 = 1 << 0;
+#[cfg(target_os = "linux")]
+const STATUS_FLAG_INCONSISTENT: u64 = 1 << 2;
+#[cfg(any(target_os = "linux", test))]
+const LIMIT_MAX_RFER: u64 = 1 << 0;
+#[cfg(any(target_os = "linux", test))]
+const LIMIT_MAX_EXCL: u64 = 1 << 1;
+
+// An item found by a tree search, its data in on-disk (little-endian) order
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq)]
+struct Item {
+    objectid: u64,
+    type_: u32,
+    offset: u64,
+    data: Vec<u8>,
+}
+
+// Helper: The `count` items of a tree search buffer, each a struct
+// btrfs_ioctl_search_header {transid, objectid, offset, type, len} followed
+// by `len` bytes of data
+#[cfg(any(target_os = "linux", test))]
+fn parse_items(buffer: &[u8], count: u32) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut rest = buffer;
+    for _ in 0..count {
+        let Some((header, tail)) = rest.split_first_chunk::<32>() else {
+            break;
+        };
+        let len = u32::from_ne_bytes(header[28..32].try_into().unwrap()) as usize;
+        let Some((data, tail)) = tail.split_at_checked(len) else {
+            break;
+        };
+        items.push(Item {
+            objectid: u64::from_ne_bytes(header[8..16].try_into().unwrap()),
+            type_: u32::from_ne_bytes(header[24..28].try_into().unwrap()),
+            offset: u64::from_ne_bytes(header[16..24].try_into().unwrap()),
+            data: data.to_vec(),
+        });
+        rest = tail;
+    }
+    items
+}
+
+// Helper: The `index`th little-endian u64 of an item's data, 0 if cut short
+#[cfg(any(target_os = "linux", test))]
+fn field(data: &[u8], index: usize) -> u64 {
+    data.get(index * 8..index * 8 + 8)
+        .map_or(0, |bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, Default, PartialEq)]
+struct Qgroup {
+    referenced: u64,
+    exclusive: u64,
+    max_referenced: Option<u64>,
+    max_exclusive: Option<u64>,
+    parents: Vec<u64>,
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl Qgroup {
+    // From the items of qgroup `id`: struct btrfs_qgroup_info_item
+    // {generation, rfer, rfer_cmpr, excl, excl_cmpr}, struct
+    // btrfs_qgroup_limit_item {flags, max_rfer, max_excl, rsv_rfer,
+    // rsv_excl} and its relations, None without an info item
+    fn decode(id: u64, items: &[Item]) -> Option<Qgroup> {
+        let info = items
+            .iter()
+            .find(|item| item.type_ == QGROUP_INFO_KEY && item.offset == id)?;
+        let mut qgroup = Qgroup {
+            referenced: field(&info.data, 1),
+            exclusive: field(&info.data, 3),
+            ..Qgroup::default()
+        };
+        if let Some(limit) = items
+            .iter()
+            .find(|item| item.type_ == QGROUP_LIMIT_KEY && item.offset == id)
+        {
+            let flags = field(&limit.data, 0);
+            qgroup.max_referenced = (flags & LIMIT_MAX_RFER != 0).then(|| field(&limit.data, 1));
+            qgroup.max_exclusive = (flags & LIMIT_MAX_EXCL != 0).then(|| field(&limit.data, 2));
+        }
+        // Each relation is kept both ways; those to a parent have a higher level
+        qgroup.parents = items
+            .iter()
+            .filter(|item| item.type_ == QGROUP_RELATION_KEY && item.objectid == id)
+            .map(|item| item.offset)
+            .filter(|parent| level(*parent) > level(id))
+            .collect();
+        Some(qgroup)
+    }
+}
+
+// Helper: The level of a qgroup id, its top 16 bits
+#[cfg(any(target_os = "linux", test))]
+fn level(id: u64) -> u64 {
+    id >> 48
+}
+
+// Helper: A qgroup id as btrfs-progs writes it, "<level>/<id>"
+#[cfg(any(target_os = "linux", test))]
+fn qgroup_name(id: u64) -> String {
+    format!("{}/{}", level(id), id & ((1 << 48) - 1))
+}
+
+// The objectid of the quota tree, and of the first file of a subvolume
+#[cfg(target_os = "linux")]
+const QUOTA_TREE_OBJECTID: u64 = 8;
+#[cfg(target_os = "linux")]
+const FIRST_FREE_OBJECTID: u64 = 256;
+
+// struct btrfs_ioctl_ino_lookup_args
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct InoLookup {
+    treeid: u64,
+    objectid: u64,
+    name: [u8; 4080],
+}
+
+// struct btrfs_ioctl_search_args
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct Search {
+    tree_id: u64,
+    min_objectid: u64,
+    max_objectid: u64,
+    min_offset: u64,
+    max_offset: u64,
+    min_transid: u64,
+    max_transid: u64,
+    min_type: u32,
+    max_type: u32,
+    nr_items: u32,
+    unused: u32,
+    unused1: [u64; 4],
+    buf: [u8; 3992],
+}
+
+// Helper: The id of the subvolume `file` lies in
+#[cfg(target_os = "linux")]
+fn subvolume_id(file: &File) -> io::Result<u64> {
+    let mut lookup = InoLookup {
+        treeid: 0,
+        objectid: FIRST_FREE_OBJECTID,
+        name: [0; 4080],
+    };
+    ioctl(
+        file,
+        ioc(IOC_READ_WRITE, 18, size_of::<InoLookup>()),
+        &mut lookup,
+    )?;
+    Ok(lookup.treeid)
+}
+
+// Helper: The quota tree items of `objectid` and `type_` whose offsets lie
+// within `offsets`
+#[cfg(target_os = "linux")]
+fn search(
+    file: &File,
+    (objectid, type_): (u64, u32),
+    offsets: (u64, u64),
+) -> io::Result<Vec<Item>> {
+    let mut search = Search {
+        tree_id: QUOTA_TREE_OBJECTID,
+        min_objectid: objectid,
+        max_objectid: objectid,
+        min_offset: offsets.0,
+        max_offset: offsets.1,
+        min_transid: 0,
+        max_transid: u64::MAX,
+        min_type: type_,
+        max_type: type_,
+        nr_items: u32::MAX,
+        unused: 0,
+        unused1: [0; 4],
+        buf: [0; 3992],
+    };
+    ioctl(
+        file,
+        ioc(IOC_READ_WRITE, 17, size_of::<Search>()),
+        &mut search,
+    )?;
+    Ok(parse_items(&search.buf, search.nr_items))
+}
+
+#[cfg(target_os = "linux")]
+enum Lookup {
+    Found(u64, Qgroup, bool),
+    Disabled,
+    Missing,
+}
+
+// Helper: The qgroup of the subvolume `file` lies in, with its id and
+// whether its figures are inconsistent, awaiting a rescan
+#[cfg(target_os = "linux")]
+fn lookup(file: &File) -> io::Result<Lookup> {
+    let subvolume = subvolume_id(file)?;
+    let status = match search(file, (0, QGROUP_STATUS_KEY), (0, 0)) {
+        Ok(items) => items.first().map(|item| field(&item.data, 2)),
+        // No quota tree at all
+        Err(err) if err.raw_os_error() == Some(libc::ENOENT) => return Ok(Lookup::Disabled),
+        Err(err) => return Err(err),
+    };
+    let Some(status) = status.filter(|status| status & STATUS_FLAG_ON != 0) else {
+        return Ok(Lookup::Disabled);
+    };
+    let own = (subvolume, subvolume);
+    let mut items = search(file, (0, QGROUP_INFO_KEY), own)?;
+    items.extend(search(file, (0, QGROUP_LIMIT_KEY), own)?);
+    items.extend(search(
+        file,
+        (subvolume, QGROUP_RELATION_KEY),
+        (0, u64::MAX),
+    )?);
+    Ok(match Qgroup::decode(subvolume, &items) {
+        Some(qgroup) => Lookup::Found(subvolume, qgroup, status & STATUS_FLAG_INCONSISTENT != 0),
+        None => Lookup::Missing,
+    })
+}
+
+// %{subvolume_id, qgroup_id, referenced_bytes, exclusive_bytes,
+// max_referenced_bytes, max_exclusive_bytes, parents, inconsistent} of the
+// subvolume holding `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn btrfs_qgroup_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "linux")]
+    {
+        let Some(path) = path_from_cstring(&path) else {
+            return make_error_tuple(env, atoms::invalid_path());
+        };
+        match on_btrfs(&path) {
+            Ok(true) => {}
+            Ok(false) => return make_error_tuple(env, atoms::not_btrfs()),
+            Err(err) => return make_errno_error_tuple(env, atoms::invalid_path(), err),
+        }
+        let (subvolume, qgroup, inconsistent) = match File::open(&path)
+            .and_then(|file| lookup(&file))
+        {
+            Ok(Lookup::Found(subvolume, qgroup, inconsistent)) => (subvolume, qgroup, inconsistent),
+            Ok(Lookup::Disabled) => return make_error_tuple(env, atoms::qgroups_disabled()),
+            Ok(Lookup::Missing) => return make_error_tuple(env, atoms::qgroup_not_found()),
+            Err(err) => return make_errno_error_tuple(env, atoms::btrfs_ioctl_failed(), err),
+        };
+        let parents: Vec<String> = qgroup.parents.iter().map(|id| qgroup_name(*id)).collect();
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::subvolume_id().to_term(env), subvolume)?
+            .map_put(atoms::qgroup_id().to_term(env), qgroup_name(subvolume))?
+            .map_put(atoms::referenced_bytes().to_term(env), qgroup.referenced)?
+            .map_put(atoms::exclusive_bytes().to_term(env), qgroup.exclusive)?
+            .map_put(
+                atoms::max_referenced_bytes().to_term(env),
+                qgroup.max_referenced,
+            )?
+            .map_put(
+                atoms::max_exclusive_bytes().to_term(env),
+                qgroup.max_exclusive,
+            )?
+            .map_put(atoms::parents().to_term(env), parents)?
+            .map_put(atoms::inconsistent().to_term(env), inconsistent)?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::not_btrfs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper: A search header and its data
+    fn item(objectid: u64, type_: u32, offset: u64, data: &[u64]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&7u64.to_ne_bytes());
+        bytes.extend_from_slice(&objectid.to_ne_bytes());
+        bytes.extend_from_slice(&offset.to_ne_bytes());
+        bytes.extend_from_slice(&type_.to_ne_bytes());
+        bytes.extend_from_slice(&(data.len() as u32 * 8).to_ne_bytes());
+        for value in data {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn decodes_qgroup_items() {
+        let parent = (1 << 48) | 100;
+        let buffer = [
+            item(0, QGROUP_INFO_KEY, 257, &[9, 4096, 4096, 1024, 1024]),
+            item(
+                0,
+                QGROUP_LIMIT_KEY,
+                257,
+                &[LIMIT_MAX_RFER, 1 << 30, 0, 0, 0],
+            ),
+            item(257, QGROUP_RELATION_KEY, parent, &[]),
+            item(parent, QGROUP_RELATION_KEY, 257, &[]),
+        ]
+        .concat();
+        let items = parse_items(&buffer, 4);
+        assert_eq!(items.len(), 4);
+        assert_eq!(
+            Qgroup::decode(257, &items),
+            Some(Qgroup {
+                referenced: 4096,
+                exclusive: 1024,
+                max_referenced: Some(1 << 30),
+                max_exclusive: None,
+                parents: vec![parent],
+            })
+        );
+        assert_eq!(Qgroup::decode(258, &items), None);
+        assert_eq!(qgroup_name(parent), "1/100");
+        assert_eq!(parse_items(&buffer[..40], 4).len(), 0);
+    }
+}
@@ -0,0 +1,296 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// stat_fs_cached: stat results shared by callers that would otherwise stat
+// the same path over and over, each kept for as long as the caller's ttl_ms
+// allows. The cache is keyed by the path as given, so that a hit takes no
+// syscall at all; each entry also keeps its path canonicalized, so that
+// cache_invalidate drops it under whichever spelling it is given.
+//
+// A miss leaves a flight in the entry while it stats, which the callers
+// missing on the same path meanwhile wait for instead of statting it again,
+// so a burst of misses costs a single stat. A flight that ends without a
+// result, as a panic would, makes its waiters stat on their own.
+//
+// Results are kept in external term format, as stat_fs returns them. At most
+// CAPACITY of them are kept, the least recently used making way for a new
+// one; finding it takes a scan of the entries, which only a miss on a full
+// cache pays for.
+
+use crate::{
+    atoms, encode_stat, get_path_from_term, make_error_tuple, path_from_cstring, stat_path,
+    telemetry,
+};
+use rustler::{Atom, Encoder, Env, NifResult, Term};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+const CAPACITY: usize = 4096;
+
+struct Cache {
+    entries: HashMap<CString, Slot>,
+    // Stamps each use of an entry, for finding the least recently used
+    clock: u64,
+}
+
+enum Slot {
+    Ready(Ready),
+    Pending(Arc<Flight>),
+}
+
+struct Ready {
+    result: Arc<Vec<u8>>,
+    at: Instant,
+    canonical: Option<PathBuf>,
+    used: u64,
+}
+
+// A stat under way; its result is None until it ends, then Some(None) if it
+// ended without one
+#[derive(Default)]
+struct Flight {
+    result: Mutex<Option<Option<Arc<Vec<u8>>>>>,
+    done: Condvar,
+}
+
+static CACHE: LazyLock<Mutex<Cache>> = LazyLock::new(|| {
+    Mutex::new(Cache {
+        entries: HashMap::new(),
+        clock: 0,
+    })
+});
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// What a lookup found
+enum Lookup {
+    Hit(Arc<Vec<u8>>),
+    Wait(Arc<Flight>),
+    Miss(Arc<Flight>),
+}
+
+impl Cache {
+    fn lookup(&mut self, path: &CString, ttl: Duration) -> Lookup {
+        self.clock += 1;
+        let clock = self.clock;
+        match self.entries.get_mut(path) {
+            Some(Slot::Ready(ready)) if ready.at.elapsed() <= ttl => {
+                ready.used = clock;
+                return Lookup::Hit(ready.result.clone());
+            }
+            Some(Slot::Pending(flight)) => return Lookup::Wait(flight.clone()),
+            _ => {}
+        }
+        if self.entries.len() >= CAPACITY && !self.entries.contains_key(path) {
+            self.evict();
+        }
+        let flight = Arc::new(Flight::default());
+        self.entries
+            .insert(path.clone(), Slot::Pending(flight.clone()));
+        Lookup::Miss(flight)
+    }
+
+    // Helper: Drops the least recently used result
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .filter_map(|(path, slot)| match slot {
+                Slot::Ready(ready) => Some((ready.used, path)),
+                Slot::Pending(_) => None,
+            })
+            .min()
+            .map(|(_, path)| path.clone());
+        if let Some(oldest) = oldest {
+            self.entries.remove(&oldest);
+        }
+    }
+
+    // Helper: Keeps the result of `flight`, unless invalidated since
+    fn store(&mut self, path: &CString, flight: &Arc<Flight>, mut ready: Ready) {
+        ready.used = self.clock;
+        let ours = matches!(
+            self.entries.get(path),
+            Some(Slot::Pending(pending)) if Arc::ptr_eq(pending, flight)
+        );
+        if ours {
+            self.entries.insert(path.clone(), Slot::Ready(ready));
+        }
+    }
+}
+
+// Ends a flight without a result unless it has one, waking its waiters
+struct Landing<'a> {
+    path: &'a CString,
+    flight: &'a Arc<Flight>,
+}
+
+impl Drop for Landing<'_> {
+    fn drop(&mut self) {
+        let mut result = lock(&self.flight.result);
+        if result.is_none() {
+            *result = Some(None);
+            let mut cache = lock(&CACHE);
+            let ours = matches!(
+                cache.entries.get(self.path),
+                Some(Slot::Pending(pending)) if Arc::ptr_eq(pending, self.flight)
+            );
+            if ours {
+                cache.entries.remove(self.path);
+            }
+        }
+        self.flight.done.notify_all();
+    }
+}
+
+// What stat_fs returns for `path`, taken from the cache if no older than
+// `ttl_ms`
+#[rustler::nif(schedule = "DirtyIo")]
+fn stat_fs_cached_nif<'a>(env: Env<'a>, path_term: Term<'a>, ttl_ms: u64) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let lookup = lock(&CACHE).lookup(&path, Duration::from_millis(ttl_ms));
+    let flight = match lookup {
+        Lookup::Hit(result) => {
+            telemetry::count_cache(true);
+            return Ok(decode(env, &result));
+        }
+        Lookup::Wait(flight) => {
+            telemetry::count_cache(true);
+            let result = flight
+                .done
+                .wait_while(lock(&flight.result), |result| result.is_none())
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone()
+                .flatten();
+            return match result {
+                Some(result) => Ok(decode(env, &result)),
+                None => encode_stat(env, stat_path(&path)),
+            };
+        }
+        Lookup::Miss(flight) => flight,
+    };
+    telemetry::count_cache(false);
+    let landing = Landing {
+        path: &path,
+        flight: &flight,
+    };
+    let at = Instant::now();
+    let term = encode_stat(env, stat_path(&path))?;
+    let result = Arc::new(term.to_binary().as_slice().to_vec());
+    let canonical = path_from_cstring(&path).and_then(|path| fs::canonicalize(path).ok());
+    lock(&CACHE).store(
+        &path,
+        &flight,
+        Ready {
+            result: result.clone(),
+            at,
+            canonical,
+            used: 0,
+        },
+    );
+    *lock(&flight.result) = Some(Some(result));
+    drop(landing);
+    Ok(term)
+}
+
+// Drops the cached results of `path`, under any spelling of it, or with
+// :all, every one; a stat under way is then not kept either
+#[rustler::nif]
+fn cache_invalidate_nif<'a>(env: Env<'a>, target: Term<'a>) -> NifResult<Term<'a>> {
+    if target.decode::<Atom>().ok() == Some(atoms::all()) {
+        lock(&CACHE).entries.clear();
+        return Ok(atoms::ok().encode(env));
+    }
+    let Ok(path) = get_path_from_term(env, target) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let canonical = path_from_cstring(&path).and_then(|path| fs::canonicalize(path).ok());
+    lock(&CACHE).entries.retain(|key, slot| {
+        let same = match (slot, &canonical) {
+            (Slot::Ready(ready), Some(canonical)) => ready.canonical.as_ref() == Some(canonical),
+            _ => false,
+        };
+        *key != path && !same
+    });
+    Ok(atoms::ok().encode(env))
+}
+
+// Helper: A result kept in external term format
+fn decode<'a>(env: Env<'a>, bytes: &[u8]) -> Term<'a> {
+    env.binary_to_term(bytes)
+        .map_or_else(|| atoms::error().to_term(env), |(term, _)| term)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(i: usize) -> CString {
+        CString::new(format!("/mnt/{i}")).unwrap()
+    }
+
+    fn fill(cache: &mut Cache, path: &CString) {
+        let Lookup::Miss(flight) = cache.lookup(path, Duration::ZERO) else {
+            panic!("expected a miss");
+        };
+        let ready = Ready {
+            result: Arc::new(vec![]),
+            at: Instant::now(),
+            canonical: None,
+            used: 0,
+        };
+        cache.store(path, &flight, ready);
+    }
+
+    #[test]
+    fn hits_while_fresh_and_coalesces_misses() {
+        let mut cache = Cache {
+            entries: HashMap::new(),
+            clock: 0,
+        };
+        let Lookup::Miss(flight) = cache.lookup(&path(0), Duration::ZERO) else {
+            panic!("expected a miss");
+        };
+        assert!(
+            matches!(cache.lookup(&path(0), Duration::ZERO), Lookup::Wait(waiting) if Arc::ptr_eq(&waiting, &flight))
+        );
+        cache.entries.clear();
+        fill(&mut cache, &path(0));
+        assert!(matches!(
+            cache.lookup(&path(0), Duration::from_secs(60)),
+            Lookup::Hit(_)
+        ));
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(matches!(
+            cache.lookup(&path(0), Duration::from_millis(1)),
+            Lookup::Miss(_)
+        ));
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let mut cache = Cache {
+            entries: HashMap::new(),
+            clock: 0,
+        };
+        for i in 0..CAPACITY {
+            fill(&mut cache, &path(i));
+        }
+        assert!(matches!(
+            cache.lookup(&path(0), Duration::MAX),
+            Lookup::Hit(_)
+        ));
+        fill(&mut cache, &path(CAPACITY));
+        assert_eq!(cache.entries.len(), CAPACITY);
+        assert!(cache.entries.contains_key(&path(0)));
+        assert!(!cache.entries.contains_key(&path(1)));
+    }
+}
@@ -0,0 +1,211 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// capacity_details: the capacity of a macOS volume as Finder reports it.
+// Finder counts purgeable space (caches, local Time Machine snapshots) as
+// available, which statvfs doesn't, so that the two can differ by tens of
+// gigabytes. The figures are resource values of the CFURL of the path:
+// its total and available capacity, and the capacity available for
+// important usage (the one Finder shows, purgeable space included) and for
+// opportunistic usage, returned alongside the statvfs figures of stat.
+//
+// The keys of the last two only exist since macOS 10.13, and the NIF would
+// not load on older ones if it linked against them, so all four are looked
+// up with dlsym; without them, capacity_details returns
+// {:error, :unsupported}, as it does on other platforms. Every CF object
+// created or copied is released before returning.
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Env, NifResult, Term};
+#[cfg(target_os = "macos")]
+use {
+    crate::{encode_space, encode_stat, stat_path},
+    rustler::Encoder,
+    std::ffi::{c_void, CStr},
+    std::ptr,
+};
+
+// The capacity of a volume as CFURL reports it, in bytes
+#[cfg(any(target_os = "macos", test))]
+#[derive(Debug, PartialEq)]
+struct Capacity {
+    total: u64,
+    available: u64,
+    important: u64,
+    opportunistic: u64,
+}
+
+#[cfg(any(target_os = "macos", test))]
+impl Capacity {
+    // The space the system would free for important usage, beyond what is
+    // available already
+    fn purgeable(&self) -> u64 {
+        self.important.saturating_sub(self.available)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod cf {
+    use std::ffi::c_void;
+
+    pub(super) type CFTypeRef = *const c_void;
+    pub(super) type CFIndex = isize;
+    pub(super) const K_CF_NUMBER_SINT64_TYPE: CFIndex = 4;
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    extern "C" {
+        pub(super) fn CFURLCreateFromFileSystemRepresentation(
+            allocator: CFTypeRef,
+            buffer: *const u8,
+            length: CFIndex,
+            is_directory: u8,
+        ) -> CFTypeRef;
+        pub(super) fn CFURLCopyResourcePropertyForKey(
+            url: CFTypeRef,
+            key: CFTypeRef,
+            value: *mut CFTypeRef,
+            error: *mut CFTypeRef,
+        ) -> u8;
+        pub(super) fn CFGetTypeID(object: CFTypeRef) -> usize;
+        pub(super) fn CFNumberGetTypeID() -> usize;
+        pub(super) fn CFNumberGetValue(number: CFTypeRef, type_: CFIndex, value: *mut c_void)
+            -> u8;
+        pub(super) fn CFRelease(object: CFTypeRef);
+    }
+}
+
+// Releases the CF object it holds once dropped
+#[cfg(target_os = "macos")]
+struct Owned(cf::CFTypeRef);
+
+#[cfg(target_os = "macos")]
+impl Drop for Owned {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { cf::CFRelease(self.0) };
+        }
+    }
+}
+
+// Helper: The CFStringRef of the CFURL resource key `name`, if this macOS
+// has it
+#[cfg(target_os = "macos")]
+fn resource_key(name: &CStr) -> Option<cf::CFTypeRef> {
+    let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+    if symbol.is_null() {
+        return None;
+    }
+    let key = unsafe { *(symbol as *const cf::CFTypeRef) };
+    (!key.is_null()).then_some(key)
+}
+
+// Helper: The numeric resource value `key` of `url`
+#[cfg(target_os = "macos")]
+fn resource_number(url: &Owned, key: cf::CFTypeRef) -> Option<u64> {
+    let mut value: cf::CFTypeRef = ptr::null();
+    let mut error: cf::CFTypeRef = ptr::null();
+    let found =
+        unsafe { cf::CFURLCopyResourcePropertyForKey(url.0, key, &mut value, &mut error) } != 0;
+    let (value, _error) = (Owned(value), Owned(error));
+    if !found || value.0.is_null() || unsafe { cf::CFGetTypeID(value.0) != cf::CFNumberGetTypeID() }
+    {
+        return None;
+    }
+    let mut number: i64 = 0;
+    let converted = unsafe {
+        cf::CFNumberGetValue(
+            value.0,
+            cf::K_CF_NUMBER_SINT64_TYPE,
+            &mut number as *mut i64 as *mut c_void,
+        )
+    } != 0;
+    converted.then(|| u64::try_from(number).unwrap_or(0))
+}
+
+// Helper: The capacity of the volume holding `path`, None if this macOS
+// lacks a key or the path has no such resource values
+#[cfg(target_os = "macos")]
+fn capacity(path: &CStr) -> Option<Capacity> {
+    let keys = [
+        resource_key(c"kCFURLVolumeTotalCapacityKey")?,
+        resource_key(c"kCFURLVolumeAvailableCapacityKey")?,
+        resource_key(c"kCFURLVolumeAvailableCapacityForImportantUsageKey")?,
+        resource_key(c"kCFURLVolumeAvailableCapacityForOpportunisticUsageKey")?,
+    ];
+    let bytes = path.to_bytes();
+    let url = Owned(unsafe {
+        cf::CFURLCreateFromFileSystemRepresentation(
+            ptr::null(),
+            bytes.as_ptr(),
+            bytes.len() as cf::CFIndex,
+            1,
+        )
+    });
+    if url.0.is_null() {
+        return None;
+    }
+    let [total, available, important, opportunistic] = keys.map(|key| resource_number(&url, key));
+    Some(Capacity {
+        total: total?,
+        available: available?,
+        important: important?,
+        opportunistic: opportunistic?,
+    })
+}
+
+// %{total_capacity, available_capacity, important_capacity,
+// opportunistic_capacity, purgeable, statvfs} of the volume holding `path`,
+// statvfs being the stats of stat
+#[rustler::nif(schedule = "DirtyIo")]
+fn capacity_details_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "macos")]
+    {
+        let space = match stat_path(&path) {
+            Ok(space) => space,
+            Err(err) => return encode_stat(env, Err(err)),
+        };
+        let Some(capacity) = capacity(&path) else {
+            return make_error_tuple(env, atoms::unsupported());
+        };
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::total_capacity().to_term(env), capacity.total)?
+            .map_put(atoms::available_capacity().to_term(env), capacity.available)?
+            .map_put(atoms::important_capacity().to_term(env), capacity.important)?
+            .map_put(
+                atoms::opportunistic_capacity().to_term(env),
+                capacity.opportunistic,
+            )?
+            .map_put(atoms::purgeable().to_term(env), capacity.purgeable())?
+            .map_put(atoms::statvfs().to_term(env), encode_space(env, &space)?)?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_purgeable_space_beyond_the_available() {
+        let capacity = Capacity {
+            total: 500 << 30,
+            available: 20 << 30,
+            important: 100 << 30,
+            opportunistic: 60 << 30,
+        };
+        assert_eq!(capacity.purgeable(), 80 << 30);
+        let lower = Capacity {
+            important: 10 << 30,
+            ..capacity
+        };
+        assert_eq!(lower.purgeable(), 0);
+    }
+}
@@ -0,0 +1,165 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// configure: the settings of the worker pool (see pool.rs) and the defaults
+// of the options that every call would otherwise pass: timeout, for
+// stat_fs_many, stat_all_async and watchers; exclude, for whatever lists
+// the mounts; and humanize, for the Elixir side of stat. An option given to
+// a call still wins over its default, and a setting given as nil goes back
+// to the built-in one.
+//
+// A configure call is validated as a whole before anything is set, and then
+// set under the write lock of CONFIG, pool settings included, which
+// get_config takes the read lock of: neither it nor a NIF reading a default
+// sees a configure call half done.
+
+use crate::mounts::filter::{self, Filter};
+use crate::{atoms, make_error_tuple3, options, pool};
+use rustler::{Atom, Encoder, Env, NifResult, Term};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard};
+use std::time::Duration;
+
+struct Config {
+    timeout: Option<Duration>,
+    exclude: Option<Exclude>,
+    humanize: Option<Atom>,
+}
+
+// The filters as decoded, and as given, for get_config
+struct Exclude {
+    filters: Vec<Filter>,
+    given: Vec<u8>,
+}
+
+static CONFIG: RwLock<Config> = RwLock::new(Config {
+    timeout: None,
+    exclude: None,
+    humanize: None,
+});
+
+fn read() -> RwLockReadGuard<'static, Config> {
+    CONFIG.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+// The default timeout of a stat
+pub(crate) fn timeout() -> Option<Duration> {
+    read().timeout
+}
+
+// The default filters of the mount table, instead of the function's own
+pub(crate) fn exclude() -> Option<Vec<Filter>> {
+    read()
+        .exclude
+        .as_ref()
+        .map(|exclude| exclude.filters.clone())
+}
+
+// The settings a configure call changes: None for those not given, and
+// Some(None) for those given as nil
+struct Update {
+    timeout: Option<Option<Duration>>,
+    exclude: Option<Option<Exclude>>,
+    humanize: Option<Option<Atom>>,
+}
+
+impl Update {
+    fn decode(opts: Term) -> Result<Update, Atom> {
+        Ok(Update {
+            timeout: options::get_with(
+                opts,
+                atoms::timeout(),
+                clearable(|value| value.decode::<u64>().ok().map(Duration::from_millis)),
+            )?,
+            exclude: options::get_with(
+                opts,
+                atoms::exclude(),
+                clearable(|value| {
+                    filter::decode(value).map(|filters| Exclude {
+                        filters,
+                        given: value.to_binary().as_slice().to_vec(),
+                    })
+                }),
+            )?,
+            humanize: options::get_with(
+                opts,
+                atoms::humanize(),
+                clearable(|value| {
+                    value
+                        .decode::<Atom>()
+                        .ok()
+                        .filter(|base| *base == atoms::binary() || *base == atoms::decimal())
+                }),
+            )?,
+        })
+    }
+
+    fn apply(self, config: &mut Config) {
+        if let Some(timeout) = self.timeout {
+            config.timeout = timeout;
+        }
+        if let Some(exclude) = self.exclude {
+            config.exclude = exclude;
+        }
+        if let Some(humanize) = self.humanize {
+            config.humanize = humanize;
+        }
+    }
+}
+
+// Helper: A decoder of a setting that may also be nil
+fn clearable<'a, T>(
+    decode: impl FnOnce(Term<'a>) -> Option<T>,
+) -> impl FnOnce(Term<'a>) -> Option<Option<T>> {
+    move |value| {
+        if value.decode::<Atom>().ok() == Some(rustler::types::atom::nil()) {
+            Some(None)
+        } else {
+            decode(value).map(Some)
+        }
+    }
+}
+
+// Sets the settings given, %{pool_size, pool_queue_limit, timeout, exclude,
+// humanize}, leaving the others as they are
+#[rustler::nif]
+fn configure_nif<'a>(env: Env<'a>, opts: Term<'a>) -> NifResult<Term<'a>> {
+    let decoded = pool::decode(opts).and_then(|pool| Ok((pool, Update::decode(opts)?)));
+    let ((size, queue_limit), update) = match decoded {
+        Ok(decoded) => decoded,
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let mut config = CONFIG.write().unwrap_or_else(PoisonError::into_inner);
+    pool::set(size, queue_limit);
+    update.apply(&mut config);
+    Ok(atoms::ok().encode(env))
+}
+
+// The default humanize of stat, nil if unset: what stat asks for on every
+// call, without the rest of get_config
+#[rustler::nif]
+fn default_humanize_nif() -> Option<Atom> {
+    read().humanize
+}
+
+// %{pool_size, pool_queue_limit, timeout, exclude, humanize}, nil for the
+// defaults left unset
+#[rustler::nif]
+fn get_config_nif(env: Env) -> NifResult<Term> {
+    let config = read();
+    let (size, queue_limit) = pool::settings();
+    let exclude = match &config.exclude {
+        Some(exclude) => env
+            .binary_to_term(&exclude.given)
+            .map_or_else(|| atoms::error().to_term(env), |(term, _)| term),
+        None => rustler::types::atom::nil().to_term(env),
+    };
+    rustler::types::map::map_new(env)
+        .map_put(atoms::pool_size().to_term(env), size)?
+        .map_put(atoms::pool_queue_limit().to_term(env), queue_limit)?
+        .map_put(
+            atoms::timeout().to_term(env),
+            config.timeout.map(|timeout| timeout.as_millis() as u64),
+        )?
+        .map_put(atoms::exclude().to_term(env), exclude)?
+        .map_put(atoms::humanize().to_term(env), config.humanize)
+}
@@ -0,0 +1,259 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// container_context: whether the VM runs in a container, and what limits
+// the space of the layer it writes to. In a container, stat of "/" reports
+// the host filesystem under the overlay, while the writes stop at whatever
+// storage quota the runtime imposed, such as the XFS project quota Docker
+// sets on the upperdir with overlay2 and pquota.
+//
+// The runtime is told by /.dockerenv, /run/.containerenv (podman), the
+// cgroup paths of /proc/self/cgroup, and KUBERNETES_SERVICE_HOST; an
+// overlay root alone also counts as containerized. The upperdir comes from
+// the options of the root mount, and is usually a path on the host that
+// the container can't see; its device, project and quota are then
+// :unknown, as is everything that doesn't apply on bare metal, where the
+// root itself is the writable layer. Linux only; elsewhere
+// container_context returns {:error, :unsupported}.
+
+use crate::atoms;
+#[cfg(not(target_os = "linux"))]
+use crate::make_error_tuple;
+use rustler::{Env, NifResult, Term};
+#[cfg(target_os = "linux")]
+use {
+    crate::{mounts, quota},
+    rustler::Encoder,
+    std::os::unix::fs::MetadataExt,
+    std::path::Path,
+};
+
+#[cfg(any(target_os = "linux", test))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Runtime {
+    Docker,
+    Podman,
+    Kubernetes,
+    Containerd,
+    Lxc,
+}
+
+#[cfg(target_os = "linux")]
+impl Runtime {
+    fn atom(self) -> rustler::Atom {
+        match self {
+            Runtime::Docker => atoms::docker(),
+            Runtime::Podman => atoms::podman(),
+            Runtime::Kubernetes => atoms::kubernetes(),
+            Runtime::Containerd => atoms::containerd(),
+            Runtime::Lxc => atoms::lxc(),
+        }
+    }
+}
+
+// What a container leaves behind for its processes to see
+#[cfg(any(target_os = "linux", test))]
+struct Evidence<'a> {
+    dockerenv: bool,
+    containerenv: bool,
+    cgroup: &'a str,
+    kubernetes_env: bool,
+    overlay_root: bool,
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl Evidence<'_> {
+    // Whether in a container, and by which runtime if it can be told
+    fn detect(&self) -> (bool, Option<Runtime>) {
+        let paths: Vec<&str> = self
+            .cgroup
+            .lines()
+            .filter_map(|line| line.splitn(3, ':').nth(2))
+            .collect();
+        let in_cgroup = |name: &str| paths.iter().any(|path| path.contains(name));
+        let runtime = if in_cgroup("kubepods") || self.kubernetes_env {
+            Some(Runtime::Kubernetes)
+        } else if in_cgroup("libpod") || self.containerenv {
+            Some(Runtime::Podman)
+        } else if in_cgroup("docker") || self.dockerenv {
+            Some(Runtime::Docker)
+        } else if in_cgroup("containerd") {
+            Some(Runtime::Containerd)
+        } else if in_cgroup("lxc") {
+            Some(Runtime::Lxc)
+        } else {
+            None
+        };
+        (runtime.is_some() || self.overlay_root, runtime)
+    }
+}
+
+// Helper: The upperdir of an overlay mount's options
+#[cfg(any(target_os = "linux", test))]
+fn upperdir(options: &[String]) -> Option<&str> {
+    options
+        .iter()
+        .find_map(|option| option.strip_prefix("upperdir="))
+}
+
+// Helper: `value`, or :unknown for None
+#[cfg(target_os = "linux")]
+fn or_unknown<'a, T: Encoder>(env: Env<'a>, value: Option<T>) -> Term<'a> {
+    match value {
+        Some(value) => value.encode(env),
+        None => atoms::unknown().encode(env),
+    }
+}
+
+// Helper: The project of `layer` and its quota, nil for none and None for
+// unknown
+#[cfg(target_os = "linux")]
+fn project_quota<'a>(
+    env: Env<'a>,
+    layer: &Path,
+) -> NifResult<(Option<Term<'a>>, Option<Term<'a>>)> {
+    let nil = || rustler::types::atom::nil().encode(env);
+    let id = match quota::project_id(layer) {
+        Ok(0) => return Ok((Some(nil()), Some(nil()))),
+        Ok(id) => id,
+        Err(err) if matches!(err.raw_os_error(), Some(libc::ENOTTY | libc::EOPNOTSUPP)) => {
+            return Ok((Some(nil()), Some(nil())))
+        }
+        Err(_) => return Ok((None, None)),
+    };
+    let Ok(Some(mount)) = mounts::holding(layer) else {
+        return Ok((Some(id.encode(env)), None));
+    };
+    let quota = match quota::read(&mount, quota::PRJQUOTA, id) {
+        Ok(quota) => Some(quota.encode(env)?),
+        Err(err) if quota::not_enabled(&err) => Some(nil()),
+        Err(_) => None,
+    };
+    Ok((Some(id.encode(env)), quota))
+}
+
+// %{containerized, runtime, root_fs_type, upperdir, upperdir_device,
+// project_id, project_quota}, whatever can't be told being :unknown
+#[rustler::nif(schedule = "DirtyIo")]
+fn container_context_nif(env: Env) -> NifResult<Term> {
+    #[cfg(target_os = "linux")]
+    {
+        let root = mounts::holding(Path::new("/")).ok().flatten();
+        let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+        let overlay_root = root.as_ref().is_some_and(|root| root.fs_type == "overlay");
+        let (containerized, runtime) = Evidence {
+            dockerenv: Path::new("/.dockerenv").exists(),
+            containerenv: Path::new("/run/.containerenv").exists(),
+            cgroup: &cgroup,
+            kubernetes_env: std::env::var_os("KUBERNETES_SERVICE_HOST").is_some(),
+            overlay_root,
+        }
+        .detect();
+        let upper = root
+            .as_ref()
+            .and_then(|root| upperdir(&root.options))
+            .map(Path::new);
+        let upper_device = upper
+            .and_then(|upper| std::fs::metadata(upper).ok())
+            .map(|meta| format!("{}:{}", libc::major(meta.dev()), libc::minor(meta.dev())));
+        // The writable layer: the upperdir if visible, the root if not an overlay
+        let layer = match (overlay_root, upper_device.as_ref()) {
+            (false, _) => Some(Path::new("/")),
+            (true, Some(_)) => upper,
+            (true, None) => None,
+        };
+        let (project_id, quota) = match layer {
+            Some(layer) => project_quota(env, layer)?,
+            None => (None, None),
+        };
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::containerized().to_term(env), containerized)?
+            .map_put(
+                atoms::runtime().to_term(env),
+                or_unknown(env, runtime.map(Runtime::atom)),
+            )?
+            .map_put(
+                atoms::root_fs_type().to_term(env),
+                or_unknown(env, root.as_ref().map(|root| root.fs_type.as_str())),
+            )?
+            .map_put(
+                atoms::upperdir().to_term(env),
+                or_unknown(env, upper.and_then(Path::to_str)),
+            )?
+            .map_put(
+                atoms::upperdir_device().to_term(env),
+                or_unknown(env, upper_device),
+            )?
+            .map_put(
+                atoms::project_id().to_term(env),
+                or_unknown(env, project_id),
+            )?
+            .map_put(atoms::project_quota().to_term(env), or_unknown(env, quota))?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evidence(cgroup: &str) -> Evidence<'_> {
+        Evidence {
+            dockerenv: false,
+            containerenv: false,
+            cgroup,
+            kubernetes_env: false,
+            overlay_root: false,
+        }
+    }
+
+    #[test]
+    fn detects_the_runtime() {
+        assert_eq!(evidence("0::/init.scope\n").detect(), (false, None));
+        assert_eq!(
+            evidence("0::/kubepods.slice/kubepods-burstable.slice/cri-containerd-1f2e.scope\n")
+                .detect(),
+            (true, Some(Runtime::Kubernetes))
+        );
+        assert_eq!(
+            evidence("12:memory:/docker/4b1c\n11:cpu:/docker/4b1c\n").detect(),
+            (true, Some(Runtime::Docker))
+        );
+        assert_eq!(
+            evidence("0::/machine.slice/libpod-9a8b.scope/container\n").detect(),
+            (true, Some(Runtime::Podman))
+        );
+        // A private cgroup namespace shows only "/"
+        let dockerenv = Evidence {
+            dockerenv: true,
+            ..evidence("0::/\n")
+        };
+        assert_eq!(dockerenv.detect(), (true, Some(Runtime::Docker)));
+        let overlay = Evidence {
+            overlay_root: true,
+            ..evidence("0::/\n")
+        };
+        assert_eq!(overlay.detect(), (true, None));
+    }
+
+    #[test]
+    fn finds_the_upperdir() {
+        let options: Vec<String> = [
+            "rw",
+            "lowerdir=/var/lib/docker/overlay2/l/ABC",
+            "upperdir=/var/lib/docker/overlay2/4b1c/diff",
+            "workdir=/var/lib/docker/overlay2/4b1c/work",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            upperdir(&options),
+            Some("/var/lib/docker/overlay2/4b1c/diff")
+        );
+        assert_eq!(upperdir(&["rw".to_string()]), None);
+    }
+}
@@ -0,0 +1,127 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// device_health: whether the disks beneath a path predict their own
+// failure, the one bit of SMART worth having next to their capacity. On
+// Linux an NVMe disk is asked for its SMART / Health Information log page,
+// whose critical warning is nonzero once its spare runs low, its
+// temperature is out of bounds, its reliability degraded or its media
+// read-only, and an ATA disk for its SMART RETURN STATUS; both take
+// CAP_SYS_ADMIN. The state of the SCSI device or NVMe controller, running
+// or offline, is read from sysfs alongside. Windows asks each disk with
+// IOCTL_STORAGE_PREDICT_FAILURE, and macOS reads the SMART Status the
+// registry has for disks that are SMART capable.
+//
+// Where a disk can't be asked, for want of rights or as it doesn't do
+// SMART, whether it predicts failure is nil; if no disk can be asked the
+// result is {:error, :unavailable}. Built without the device_health
+// feature, the queries are compiled out and the NIF returns
+// {:error, :device_health_disabled}.
+
+use crate::{atoms, get_path_from_term, make_error_tuple, path_from_cstring};
+use rustler::{Env, NifResult, Term};
+#[cfg(all(
+    feature = "device_health",
+    any(target_os = "linux", target_os = "macos", windows)
+))]
+use {
+    super::Layer,
+    crate::{make_errno_error_tuple, posix},
+    rustler::Encoder,
+};
+
+#[cfg(all(
+    feature = "device_health",
+    any(target_os = "linux", target_os = "macos", windows)
+))]
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Health {
+    // Linux only: the state of the device, as "running"
+    pub state: Option<String>,
+    pub failure_predicted: Option<bool>,
+    // NVMe on Linux only: the critical warning of its health log
+    pub critical_warning: Option<u8>,
+}
+
+// Helper: How the disk `disk` says it is
+#[cfg(all(
+    feature = "device_health",
+    any(target_os = "linux", target_os = "macos", windows)
+))]
+fn health(disk: &Layer) -> Health {
+    #[cfg(target_os = "linux")]
+    {
+        super::linux::health(disk)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        super::macos::health(disk)
+    }
+    #[cfg(windows)]
+    {
+        super::windows::health(disk)
+    }
+}
+
+// {:ok, [%{name, device, state, failure_predicted, critical_warning}]} of
+// each disk beneath the filesystem holding `path`, {:error, :unavailable}
+// if none can be asked, or the error of backing_device
+#[rustler::nif(schedule = "DirtyIo")]
+fn device_health_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(all(
+        feature = "device_health",
+        any(target_os = "linux", target_os = "macos", windows)
+    ))]
+    {
+        let backing = match super::resolve(&path) {
+            Ok(Some(backing)) => backing,
+            Ok(None) => return make_error_tuple(env, atoms::not_block_device()),
+            Err(err) => return make_errno_error_tuple(env, posix::atom(env, &err), err),
+        };
+        let disks: Vec<(&Layer, Health)> =
+            backing.disks().map(|disk| (disk, health(disk))).collect();
+        if disks
+            .iter()
+            .all(|(_, health)| health.failure_predicted.is_none())
+        {
+            return make_error_tuple(env, atoms::unavailable());
+        }
+        let disks = disks
+            .iter()
+            .map(|(disk, health)| {
+                rustler::types::map::map_new(env)
+                    .map_put(atoms::name().to_term(env), disk.name.as_str())?
+                    .map_put(atoms::device().to_term(env), disk.device.as_str())?
+                    .map_put(atoms::state().to_term(env), health.state.as_deref())?
+                    .map_put(
+                        atoms::failure_predicted().to_term(env),
+                        health.failure_predicted,
+                    )?
+                    .map_put(
+                        atoms::critical_warning().to_term(env),
+                        health.critical_warning,
+                    )
+            })
+            .collect::<NifResult<Vec<Term>>>()?;
+        Ok((atoms::ok(), disks).encode(env))
+    }
+    #[cfg(not(feature = "device_health"))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::device_health_disabled())
+    }
+    #[cfg(all(
+        feature = "device_health",
+        not(any(target_os = "linux", target_os = "macos", windows))
+    ))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
@@ -0,0 +1,1074 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The block devices beneath a mount on Linux, from sysfs. The device the
+// mount is of is that of its source, or of the device number stat gives
+// its files; btrfs hands out device numbers of its own, and a btrfs
+// filesystem of several devices lists all of them under
+// /sys/fs/btrfs/<uuid>/devices. From there each device leads to those
+// beneath it: a partition to its disk, the directory above it in
+// /sys/devices, and device-mapper (LVM, dm-crypt, multipath) and md devices
+// to their slaves.
+//
+// What a disk tells of itself is in the attributes of its device: model,
+// vendor and rev (the firmware) for SCSI and SATA disks, model, serial and
+// firmware_rev for the controller of an NVMe namespace, name and serial for
+// MMC cards. SCSI disks have their serial in the unit serial number VPD
+// page, vpd_pg80, and virtio disks in the serial of the block device. The
+// bus a disk is on is the first of USB, NVMe, virtio, ATA, MMC or a SCSI
+// host on the path of its device in /sys/devices, and it is removable if
+// its removable attribute says so or it is attached by USB, as USB hard
+// disks and card readers with the card in don't say so. Its transport is
+// told by the path as well, SAS end devices, iSCSI sessions and Fibre
+// Channel remote ports being between the SCSI host and the disk.
+//
+// The sector sizes are asked of the device node with BLKSSZGET and
+// BLKPBSZGET, which takes read access to it, as members of the disk group
+// have; otherwise they are the logical_block_size and physical_block_size
+// of its queue in sysfs. The size is in 512-byte sectors whatever those
+// are, and the queue tells whether the disk is rotational.
+//
+// A device takes discard if its queue has a discard_granularity, and a
+// filesystem can be trimmed with fstrim if it implements FITRIM and the
+// device it is on takes discard, dm and md devices passing it down to
+// disks that do.
+//
+// A device-mapper device has its name and uuid in its dm directory. The
+// uuid starts with the subsystem that made it: LVM- for LVM, CRYPT- for
+// cryptsetup, mpath- for multipath, part<N>-mpath- for the partitions of a
+// multipath device. LVM names the device <vg>-<lv>, doubling the dashes in
+// each. cryptsetup has the uuid of the LUKS header in that of the device,
+// CRYPT-LUKS2-<uuid without its dashes>-<name>, or CRYPT-LUKS1- for LUKS1;
+// plain dm-crypt has CRYPT-PLAIN-<name>.
+//
+// Whether a filesystem has the encrypt feature fscrypt needs is told by
+// FS_IOC_GET_ENCRYPTION_POLICY on its root: ENODATA if the root isn't
+// encrypted, the policy if it is, and EOPNOTSUPP without the feature, or
+// ENOTTY from a filesystem with no fscrypt at all.
+//
+// The partitions of a disk are the directories of its device with a
+// partition attribute, their number, and their start and size in 512-byte
+// sectors. The kernel doesn't keep their types; udev does, in
+// /run/udev/data/b<major>:<minor>, as the ID_PART_ENTRY_TYPE of each.
+//
+// The health of an NVMe disk is the critical warning of its SMART / Health
+// Information log page, which NVME_IOCTL_ADMIN_CMD gets with Get Log Page,
+// and that of an ATA disk the SMART RETURN STATUS libata runs for
+// HDIO_DRIVE_TASK, answering in the LBA mid and high registers: 4Fh C2h if
+// the thresholds hold, F4h 2Ch once one is exceeded.
+
+#[cfg(feature = "device_health")]
+use super::health::Health;
+use super::{trimmed, Backing, Bus, Discard, Dm, DmType, Info, Kind, Layer, Partition, Transport};
+use crate::mounts::{self, Mount};
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+// _IOWR('N', 0x41, struct nvme_admin_cmd)
+#[cfg(feature = "device_health")]
+const NVME_IOCTL_ADMIN_CMD: libc::Ioctl = 0xC048_4E41;
+// _IOW('f', 21, struct fscrypt_policy_v1)
+const FS_IOC_GET_ENCRYPTION_POLICY: libc::Ioctl = 0x400C_6615;
+// Of linux/hdreg.h
+#[cfg(feature = "device_health")]
+const HDIO_DRIVE_TASK: libc::Ioctl = 0x031E;
+
+// struct nvme_admin_cmd of linux/nvme_ioctl.h
+#[cfg(feature = "device_health")]
+#[repr(C)]
+#[derive(Default)]
+struct NvmeAdminCmd {
+    opcode: u8,
+    flags: u8,
+    rsvd1: u16,
+    nsid: u32,
+    cdw2: u32,
+    cdw3: u32,
+    metadata: u64,
+    addr: u64,
+    metadata_len: u32,
+    data_len: u32,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+    timeout_ms: u32,
+    result: u32,
+}
+
+pub(super) fn resolve(path: &Path) -> io::Result<Option<Backing>> {
+    Ok(mounts::holding(path)?.as_ref().and_then(resolve_mount))
+}
+
+pub(super) fn resolve_mount(mount: &Mount) -> Option<Backing> {
+    let sys = Path::new("/sys");
+    let name = block_name(sys, mount)?;
+    let start = match mount.fs_type.as_str() {
+        "btrfs" => btrfs_devices(sys, &name).unwrap_or_else(|| vec![name]),
+        _ => vec![name],
+    };
+    Some(Backing {
+        mount_point: mount.mount_point.clone(),
+        source: mount.source.clone(),
+        fs_type: mount.fs_type.clone(),
+        options: mount.options.clone(),
+        chain: walk(sys, start),
+    })
+}
+
+// Helper: The name in sysfs of the block device of `mount`, that of its
+// source if a block device, or of the device of its files
+fn block_name(sys: &Path, mount: &Mount) -> Option<String> {
+    let source = fs::metadata(&mount.source)
+        .ok()
+        .filter(|metadata| metadata.file_type().is_block_device())
+        .map(|metadata| {
+            let rdev = metadata.rdev();
+            format!("{}:{}", libc::major(rdev), libc::minor(rdev))
+        });
+    [source, Some(mount.device.clone())]
+        .into_iter()
+        .flatten()
+        .find_map(|device| dev_name(sys, &device))
+}
+
+// Helper: The name in sysfs of the block device numbered `device`, as
+// "major:minor"
+fn dev_name(sys: &Path, device: &str) -> Option<String> {
+    let target = fs::canonicalize(sys.join("dev/block").join(device)).ok()?;
+    Some(target.file_name()?.to_str()?.to_string())
+}
+
+// The md array `path` is the device node of, or the filesystem holding it
+// is on, None if neither
+pub(super) fn md_array(path: &Path) -> io::Result<Option<String>> {
+    let sys = Path::new("/sys");
+    let metadata = fs::metadata(path)?;
+    let name = if metadata.file_type().is_block_device() {
+        let rdev = metadata.rdev();
+        dev_name(sys, &format!("{}:{}", libc::major(rdev), libc::minor(rdev)))
+    } else {
+        resolve(path)?.and_then(|backing| {
+            let md = backing
+                .chain
+                .into_iter()
+                .find(|layer| layer.kind == Kind::Md);
+            md.map(|layer| layer.name)
+        })
+    };
+    Ok(name.filter(|name| sys.join("class/block").join(name).join("md").is_dir()))
+}
+
+// Helper: The devices of the btrfs filesystem one of whose devices is
+// `name`
+fn btrfs_devices(sys: &Path, name: &str) -> Option<Vec<String>> {
+    fs::read_dir(sys.join("fs/btrfs"))
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|fs| entries(&fs.path().join("devices")))
+        .find(|devices| devices.iter().any(|device| device == name))
+}
+
+// Helper: The names in the directory `dir`, sorted, none if it is missing
+fn entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+// Helper: The devices from `start` down, each once, those nearer the mount
+// first
+fn walk(sys: &Path, start: Vec<String>) -> Vec<Layer> {
+    let mut chain: Vec<Layer> = Vec::new();
+    let mut queue = VecDeque::from(start);
+    while let Some(name) = queue.pop_front() {
+        if chain.iter().any(|layer| layer.name == name) {
+            continue;
+        }
+        let layer = layer(sys, name);
+        queue.extend(layer.lower.iter().cloned());
+        chain.push(layer);
+    }
+    chain
+}
+
+// Helper: The block device `name` and the names of those beneath it
+fn layer(sys: &Path, name: String) -> Layer {
+    let dir = sys.join("class/block").join(&name);
+    let (kind, lower) = if dir.join("partition").exists() {
+        let disk = fs::canonicalize(&dir)
+            .ok()
+            .and_then(|target| Some(target.parent()?.file_name()?.to_str()?.to_string()));
+        (Kind::Partition, disk.into_iter().collect())
+    } else {
+        let slaves = entries(&dir.join("slaves"));
+        let kind = if dir.join("dm").is_dir() {
+            Kind::Dm
+        } else if dir.join("md").is_dir() {
+            Kind::Md
+        } else if dir.join("loop").is_dir() {
+            Kind::Loop
+        } else if slaves.is_empty() {
+            Kind::Disk
+        } else {
+            Kind::Other
+        };
+        (kind, slaves)
+    };
+    // dm-N as the /dev/mapper name operators know, and cciss!c0d0 as the
+    // /dev/cciss/c0d0 it stands for
+    let device = match fs::read_to_string(dir.join("dm/name")) {
+        Ok(dm_name) if kind == Kind::Dm => format!("/dev/mapper/{}", dm_name.trim_end()),
+        _ => format!("/dev/{}", name.replace('!', "/")),
+    };
+    Layer {
+        dm: (kind == Kind::Dm).then(|| dm_in(&dir)).flatten(),
+        name,
+        device,
+        kind,
+        lower,
+    }
+}
+
+// Helper: What the device-mapper device whose sysfs directory is `dir` is
+fn dm_in(dir: &Path) -> Option<Dm> {
+    let name = fs::read_to_string(dir.join("dm/name")).ok()?;
+    let uuid = fs::read_to_string(dir.join("dm/uuid")).unwrap_or_default();
+    Some(dm_of(name.trim_end(), uuid.trim_end()))
+}
+
+// Helper: The device-mapper device `name` with the uuid `uuid`
+fn dm_of(name: &str, uuid: &str) -> Dm {
+    let kind = if uuid.starts_with("LVM-") {
+        DmType::Lvm
+    } else if uuid.starts_with("CRYPT-") {
+        DmType::Crypt
+    } else if uuid.starts_with("mpath-") || uuid.starts_with("part") && uuid.contains("-mpath-") {
+        DmType::Mpath
+    } else {
+        DmType::Other
+    };
+    let (vg, lv) = match kind {
+        DmType::Lvm => split_lvm(name).unzip(),
+        _ => (None, None),
+    };
+    Dm {
+        name: name.to_string(),
+        kind,
+        vg,
+        lv,
+        luks_uuid: luks_uuid(uuid),
+    }
+}
+
+// Helper: The uuid of the LUKS header of the dm-crypt device with the uuid
+// `uuid`, None if it isn't LUKS
+fn luks_uuid(uuid: &str) -> Option<String> {
+    let rest = uuid
+        .strip_prefix("CRYPT-LUKS2-")
+        .or_else(|| uuid.strip_prefix("CRYPT-LUKS1-"))?;
+    let hex = rest.split('-').next()?;
+    if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+// Helper: The volume group and logical volume of the LVM device `name`,
+// split at the first dash that isn't doubled
+fn split_lvm(name: &str) -> Option<(String, String)> {
+    let bytes = name.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'-' {
+            if bytes.get(i + 1) == Some(&b'-') {
+                i += 2;
+                continue;
+            }
+            let (vg, lv) = (&name[..i], &name[i + 1..]);
+            return Some((vg.replace("--", "-"), lv.replace("--", "-")));
+        }
+        i += 1;
+    }
+    None
+}
+
+// The device-mapper device `mount` is on, None if on another
+pub(super) fn mount_dm(mount: &Mount) -> Option<Dm> {
+    let sys = Path::new("/sys");
+    dm_in(&sys.join("class/block").join(block_name(sys, mount)?))
+}
+
+// Whether the filesystem mounted at `mount_point` has the encrypt feature,
+// None if that can't be told
+pub(super) fn fscrypt(mount_point: &Path) -> Option<bool> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY | libc::O_NONBLOCK)
+        .open(mount_point)
+        .ok()?;
+    let mut policy = [0u8; 12];
+    if unsafe {
+        libc::ioctl(
+            file.as_raw_fd(),
+            FS_IOC_GET_ENCRYPTION_POLICY,
+            policy.as_mut_ptr(),
+        )
+    } == 0
+    {
+        return Some(true);
+    }
+    match io::Error::last_os_error().raw_os_error()? {
+        // EINVAL for a v2 policy, which the v1 struct can't hold
+        libc::ENODATA | libc::EINVAL | libc::EOVERFLOW => Some(true),
+        libc::EOPNOTSUPP | libc::ENOTTY => Some(false),
+        _ => None,
+    }
+}
+
+pub(super) fn info(disk: &Layer) -> Info {
+    info_in(Path::new("/sys"), &disk.name, Path::new(&disk.device))
+}
+
+// Helper: The logical and physical sector sizes of the block device at
+// `device`
+fn sector_sizes(device: &Path) -> io::Result<(u32, u32)> {
+    // O_NONBLOCK, not to wait for the media of an optical drive
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(device)?;
+    let mut logical: libc::c_int = 0;
+    let mut physical: libc::c_uint = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::BLKSSZGET, &mut logical) } != 0
+        || unsafe { libc::ioctl(file.as_raw_fd(), libc::BLKPBSZGET, &mut physical) } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((logical as u32, physical))
+}
+
+// Helper: What the disk `name`, whose node is `device`, tells of itself in
+// the sysfs at `sys`
+fn info_in(sys: &Path, name: &str, device: &Path) -> Info {
+    let dir = sys.join("class/block").join(name);
+    let attribute = |attribute: &str| {
+        fs::read_to_string(dir.join(attribute))
+            .ok()
+            .and_then(|value| trimmed(&value))
+    };
+    let target = fs::canonicalize(&dir).ok();
+    let bus = target.as_deref().and_then(bus_of);
+    let transport = target.as_deref().map_or(Transport::Unknown, transport_of);
+    let removable = attribute("removable").map(|removable| removable == "1");
+    let (logical, physical, permission_limited) = match sector_sizes(device) {
+        Ok((logical, physical)) => (Some(logical), Some(physical), false),
+        Err(err) => {
+            let size = |attribute: &str| {
+                fs::read_to_string(dir.join(attribute))
+                    .ok()
+                    .and_then(|size| size.trim().parse().ok())
+            };
+            (
+                size("queue/logical_block_size"),
+                size("queue/physical_block_size"),
+                err.kind() == io::ErrorKind::PermissionDenied,
+            )
+        }
+    };
+    Info {
+        model: attribute("device/model").or_else(|| attribute("device/name")),
+        // SATA disks behind libata are all of the SCSI vendor "ATA"
+        vendor: attribute("device/vendor").filter(|vendor| vendor != "ATA"),
+        serial: attribute("device/serial")
+            .or_else(|| attribute("serial"))
+            .or_else(|| vpd_serial(&fs::read(dir.join("device/vpd_pg80")).ok()?)),
+        firmware: attribute("device/firmware_rev").or_else(|| attribute("device/rev")),
+        removable: match bus {
+            Some(Bus::Usb) => Some(true),
+            _ => removable,
+        },
+        bus,
+        transport,
+        size: attribute("size")
+            .and_then(|sectors| sectors.parse::<u64>().ok())
+            .map(|sectors| sectors * 512),
+        rotational: attribute("queue/rotational").map(|rotational| rotational == "1"),
+        logical_sector_size: logical,
+        physical_sector_size: physical,
+        permission_limited,
+    }
+}
+
+pub(super) fn discard(backing: &Backing, disk: &Layer) -> Discard {
+    discard_in(Path::new("/sys"), backing, disk)
+}
+
+// Helper: Whether the disk `disk` of `backing` takes discard, and its
+// filesystem uses it, in the sysfs at `sys`
+fn discard_in(sys: &Path, backing: &Backing, disk: &Layer) -> Discard {
+    let supports = |name: &str| {
+        fs::read_to_string(
+            sys.join("class/block")
+                .join(name)
+                .join("queue/discard_granularity"),
+        )
+        .ok()
+        .and_then(|granularity| granularity.trim().parse::<u64>().ok())
+        .map(|granularity| granularity > 0)
+    };
+    // The devices the filesystem is on, none above them; a partition has
+    // the queue of its disk
+    let top = backing
+        .chain
+        .iter()
+        .filter(|layer| {
+            !backing
+                .chain
+                .iter()
+                .any(|other| other.lower.contains(&layer.name))
+        })
+        .map(|layer| match layer.kind {
+            Kind::Partition => layer.lower.first().unwrap_or(&layer.name),
+            _ => &layer.name,
+        })
+        .map(|name| supports(name))
+        .collect::<Option<Vec<bool>>>();
+    let fitrim = [
+        "ext4", "ext3", "ext2", "xfs", "btrfs", "f2fs", "vfat", "exfat", "ntfs3", "jfs", "gfs2",
+        "ocfs2", "nilfs2", "bcachefs",
+    ]
+    .contains(&backing.fs_type.as_str());
+    Discard {
+        device_supports: supports(&disk.name),
+        mount_option_enabled: Some(
+            backing
+                .options
+                .iter()
+                .any(|option| option == "discard" || option.starts_with("discard=")),
+        ),
+        fstrim_capable: top.map(|top| fitrim && top.iter().any(|supports| *supports)),
+    }
+}
+
+// Helper: The bus of the block device at `device` in /sys/devices, from the
+// first component naming one on the way down to it
+fn bus_of(device: &Path) -> Option<Bus> {
+    device.components().find_map(|component| {
+        let component = component.as_os_str().to_str()?;
+        let host = component
+            .strip_prefix("host")
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+        if component.starts_with("usb") {
+            Some(Bus::Usb)
+        } else if component == "nvme" || component.starts_with("nvme-subsys") {
+            Some(Bus::Nvme)
+        } else if component.starts_with("virtio") {
+            Some(Bus::Virtio)
+        } else if component.starts_with("ata") {
+            Some(Bus::Sata)
+        } else if component.starts_with("mmc") {
+            Some(Bus::Mmc)
+        } else if host {
+            Some(Bus::Scsi)
+        } else {
+            None
+        }
+    })
+}
+
+pub(super) fn partitions(disk: &Layer, mounts: &[Mount]) -> io::Result<Vec<Partition>> {
+    let sys = Path::new("/sys");
+    let mounted: Vec<(String, String)> = mounts
+        .iter()
+        .filter_map(|mount| Some((block_name(sys, mount)?, mount.mount_point.clone())))
+        .collect();
+    Ok(partitions_in(
+        sys,
+        Path::new("/run/udev/data"),
+        &disk.name,
+        &mounted,
+    ))
+}
+
+// Helper: The partitions of the disk `name` in the sysfs at `sys`, their
+// types from the udev database at `udev`, each mounted where `mounted`,
+// pairs of a device name and a mount point, has it
+fn partitions_in(
+    sys: &Path,
+    udev: &Path,
+    name: &str,
+    mounted: &[(String, String)],
+) -> Vec<Partition> {
+    let dir = sys.join("class/block").join(name);
+    let mut partitions: Vec<Partition> = entries(&dir)
+        .into_iter()
+        .filter_map(|part| {
+            let attribute = |attribute: &str| {
+                fs::read_to_string(dir.join(&part).join(attribute))
+                    .ok()
+                    .map(|value| value.trim().to_string())
+            };
+            let sectors = |name: &str| attribute(name)?.parse::<u64>().ok();
+            let number = attribute("partition")?.parse().ok()?;
+            let type_id = attribute("dev").and_then(|dev| {
+                let data = fs::read_to_string(udev.join(format!("b{dev}"))).ok()?;
+                data.lines()
+                    .find_map(|line| line.strip_prefix("E:ID_PART_ENTRY_TYPE="))
+                    .map(str::to_ascii_lowercase)
+            });
+            Some(Partition {
+                number,
+                device: format!("/dev/{}", part.replace('!', "/")),
+                start: sectors("start")? * 512,
+                size: sectors("size")? * 512,
+                type_id,
+                mounted_at: mounted
+                    .iter()
+                    .filter(|(device, _)| *device == part)
+                    .map(|(_, mount_point)| mount_point.clone())
+                    .collect(),
+                name: part,
+            })
+        })
+        .collect();
+    partitions.sort_by_key(|partition| partition.start);
+    partitions
+}
+
+// The transports of disks by a prefix of a component of the path of their
+// device in /sys/devices, those beneath others first: USB and virtio
+// disks, iSCSI sessions, SAS end devices and Fibre Channel remote ports are
+// all beneath a SCSI host, and SATA disks beneath one of libata
+const TRANSPORTS: [(&str, Transport); 9] = [
+    ("usb", Transport::Usb),
+    ("nvme", Transport::Nvme),
+    ("virtio", Transport::Virtio),
+    ("mmc", Transport::Mmc),
+    ("session", Transport::Iscsi),
+    ("end_device-", Transport::Sas),
+    ("rport-", Transport::FibreChannel),
+    ("ata", Transport::Sata),
+    ("host", Transport::Scsi),
+];
+
+// Helper: The transport of the block device at `device` in /sys/devices,
+// from the path to it, not its own name
+fn transport_of(device: &Path) -> Transport {
+    let components: Vec<&str> = device
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .filter_map(|component| component.as_os_str().to_str())
+        .collect();
+    TRANSPORTS
+        .iter()
+        .find(|(prefix, _)| {
+            components
+                .iter()
+                .any(|component| component.starts_with(prefix))
+        })
+        .map_or(Transport::Unknown, |(_, transport)| *transport)
+}
+
+#[cfg(feature = "device_health")]
+pub(super) fn health(disk: &Layer) -> Health {
+    let dir = Path::new("/sys/class/block").join(&disk.name);
+    let state = fs::read_to_string(dir.join("device/state"))
+        .ok()
+        .and_then(|state| trimmed(&state));
+    // O_NONBLOCK, not to wait for the media of an optical drive
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&disk.device);
+    let (failure_predicted, critical_warning) = match file {
+        Ok(file) if disk.name.starts_with("nvme") => match nvme_critical_warning(&file) {
+            Ok(warning) => (Some(warning != 0), Some(warning)),
+            Err(_) => (None, None),
+        },
+        Ok(file) => (ata_smart_status(&file).ok().flatten(), None),
+        Err(_) => (None, None),
+    };
+    Health {
+        state,
+        failure_predicted,
+        critical_warning,
+    }
+}
+
+// Helper: The critical warning of the SMART / Health Information log of
+// the NVMe disk open as `file`
+#[cfg(feature = "device_health")]
+fn nvme_critical_warning(file: &fs::File) -> io::Result<u8> {
+    let mut log = [0u8; 512];
+    let mut cmd = NvmeAdminCmd {
+        // Get Log Page, of the log 02h, 128 dwords of it, for the controller
+        opcode: 0x02,
+        nsid: 0xFFFF_FFFF,
+        addr: log.as_mut_ptr() as u64,
+        data_len: log.len() as u32,
+        cdw10: ((log.len() as u32 / 4 - 1) << 16) | 0x02,
+        ..NvmeAdminCmd::default()
+    };
+    match unsafe { libc::ioctl(file.as_raw_fd(), NVME_IOCTL_ADMIN_CMD, &mut cmd) } {
+        0 => Ok(log[0]),
+        // The NVMe status of a command the controller failed
+        status if status > 0 => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+// Helper: Whether the ATA disk open as `file` predicts its failure, None if
+// it doesn't answer as SMART RETURN STATUS does
+#[cfg(feature = "device_health")]
+fn ata_smart_status(file: &fs::File) -> io::Result<Option<bool>> {
+    // The command, feature, sector count, LBA low, mid and high, and device
+    let mut task: [u8; 7] = [0xB0, 0xDA, 0, 0, 0x4F, 0xC2, 0];
+    if unsafe { libc::ioctl(file.as_raw_fd(), HDIO_DRIVE_TASK, task.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(smart_status(task[4], task[5]))
+}
+
+// Helper: Whether the LBA mid and high registers after SMART RETURN STATUS
+// say a threshold is exceeded, None if neither answer
+#[cfg(feature = "device_health")]
+fn smart_status(mid: u8, high: u8) -> Option<bool> {
+    match (mid, high) {
+        (0x4F, 0xC2) => Some(false),
+        (0xF4, 0x2C) => Some(true),
+        _ => None,
+    }
+}
+
+// Helper: The serial number of a unit serial number VPD page: the page
+// code 0x80, and the length of the serial in bytes 2 and 3 before it
+fn vpd_serial(page: &[u8]) -> Option<String> {
+    if page.get(1) != Some(&0x80) {
+        return None;
+    }
+    let len = u16::from_be_bytes([*page.get(2)?, *page.get(3)?]) as usize;
+    let serial = page.get(4..4 + len)?;
+    trimmed(&String::from_utf8_lossy(serial))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn walks_down_to_the_disks() {
+        let sys = std::env::temp_dir().join(format!("disk_space_sysfs_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&sys);
+        let block = sys.join("class/block");
+        let mkdir = |dir: &Path| fs::create_dir_all(dir).unwrap();
+        // vg0-data striped over a partition of each of two NVMe disks
+        mkdir(&block.join("dm-0/dm"));
+        fs::write(block.join("dm-0/dm/name"), "vg0-data\n").unwrap();
+        fs::write(block.join("dm-0/dm/uuid"), "LVM-Kq1nC0aR8xYw\n").unwrap();
+        for disk in ["nvme0n1", "nvme1n1"] {
+            let part = format!("{disk}p2");
+            let dir = sys.join("devices").join(disk).join(&part);
+            mkdir(&dir);
+            fs::write(dir.join("partition"), "2\n").unwrap();
+            symlink(&dir, block.join(&part)).unwrap();
+            mkdir(&sys.join("devices").join(disk));
+            symlink(sys.join("devices").join(disk), block.join(disk)).unwrap();
+            mkdir(&block.join("dm-0/slaves").join(&part));
+        }
+        let chain = walk(&sys, vec!["dm-0".to_string()]);
+        let devices: Vec<(&str, Kind)> = chain
+            .iter()
+            .map(|layer| (layer.device.as_str(), layer.kind))
+            .collect();
+        assert_eq!(
+            devices,
+            [
+                ("/dev/mapper/vg0-data", Kind::Dm),
+                ("/dev/nvme0n1p2", Kind::Partition),
+                ("/dev/nvme1n1p2", Kind::Partition),
+                ("/dev/nvme0n1", Kind::Disk),
+                ("/dev/nvme1n1", Kind::Disk),
+            ]
+        );
+        assert_eq!(chain[1].lower, ["nvme0n1"]);
+        let dm = chain[0].dm.as_ref().unwrap();
+        assert_eq!((dm.kind, dm.vg.as_deref()), (DmType::Lvm, Some("vg0")));
+        assert_eq!(chain[1].dm, None);
+        fs::remove_dir_all(&sys).unwrap();
+    }
+
+    #[test]
+    fn finds_dm_crypt_however_it_is_stacked() {
+        let sys = std::env::temp_dir().join(format!("disk_space_crypt_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&sys);
+        let block = sys.join("class/block");
+        let dm = |name: &str, dm_name: &str, uuid: &str, slave: &str| {
+            fs::create_dir_all(block.join(name).join("dm")).unwrap();
+            fs::create_dir_all(block.join(name).join("slaves").join(slave)).unwrap();
+            fs::write(block.join(name).join("dm/name"), format!("{dm_name}\n")).unwrap();
+            fs::write(block.join(name).join("dm/uuid"), format!("{uuid}\n")).unwrap();
+        };
+        let luks = "CRYPT-LUKS2-0f3c2a7e9b1d4c58a6e2d7f01b3c9e44-luks";
+        // LVM on LUKS on a partition of sda, and LUKS on LVM on sdb
+        dm("dm-0", "vg0-root", "LVM-Kq1nC0aR8xYw", "dm-1");
+        dm("dm-1", "luks", luks, "sda2");
+        dm("dm-2", "home", luks, "dm-3");
+        dm("dm-3", "vg1-home", "LVM-Zt4pW9bE2uQs", "sdb");
+        let dir = sys.join("devices/sda/sda2");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("partition"), "2\n").unwrap();
+        symlink(&dir, block.join("sda2")).unwrap();
+        for disk in ["sda", "sdb"] {
+            fs::create_dir_all(sys.join("devices").join(disk)).unwrap();
+            symlink(sys.join("devices").join(disk), block.join(disk)).unwrap();
+        }
+        for (start, crypt, disk) in [("dm-0", "dm-1", "/dev/sda"), ("dm-2", "dm-2", "/dev/sdb")] {
+            let backing = Backing {
+                mount_point: "/".to_string(),
+                source: String::new(),
+                fs_type: "ext4".to_string(),
+                options: Vec::new(),
+                chain: walk(&sys, vec![start.to_string()]),
+            };
+            let layer = backing
+                .chain
+                .iter()
+                .find(|layer| layer.dm.as_ref().is_some_and(|dm| dm.kind == DmType::Crypt))
+                .unwrap();
+            assert_eq!(layer.name, crypt);
+            let disks: Vec<&str> = backing
+                .disks_beneath(layer)
+                .iter()
+                .map(|disk| disk.device.as_str())
+                .collect();
+            assert_eq!(disks, [disk]);
+        }
+        fs::remove_dir_all(&sys).unwrap();
+    }
+
+    #[test]
+    fn reads_what_disks_tell_of_themselves() {
+        let sys = std::env::temp_dir().join(format!("disk_space_ident_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&sys);
+        let write = |file: &str, contents: &[u8]| {
+            let file = sys.join("class/block").join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, contents).unwrap();
+        };
+        write(
+            "nvme0n1/device/model",
+            b"Samsung SSD 980 PRO 1TB                 \n",
+        );
+        write("nvme0n1/device/serial", b"S5GXNX0R123456A     \n");
+        write("nvme0n1/device/firmware_rev", b"5B2QGXA7\n");
+        write("sda/device/model", b"WDC WD40EFRX-68N\n");
+        write("sda/device/vendor", b"ATA     \n");
+        write("sda/device/rev", b"0A82\n");
+        write("sda/device/vpd_pg80", b"\x00\x80\x00\x0b  WD-WCC7K1");
+        write("vda/serial", b"\n");
+        write("nvme0n1/removable", b"0\n");
+        write("nvme0n1/queue/logical_block_size", b"512\n");
+        write("nvme0n1/queue/physical_block_size", b"4096\n");
+        write("nvme0n1/queue/rotational", b"0\n");
+        write("nvme0n1/size", b"1953525168\n");
+        let node = |name: &str| sys.join("dev").join(name);
+        assert_eq!(
+            info_in(&sys, "nvme0n1", &node("nvme0n1")),
+            Info {
+                model: Some("Samsung SSD 980 PRO 1TB".to_string()),
+                vendor: None,
+                serial: Some("S5GXNX0R123456A".to_string()),
+                firmware: Some("5B2QGXA7".to_string()),
+                removable: Some(false),
+                bus: None,
+                transport: Transport::Unknown,
+                size: Some(1000204886016),
+                rotational: Some(false),
+                logical_sector_size: Some(512),
+                physical_sector_size: Some(4096),
+                permission_limited: false,
+            }
+        );
+        let sda = info_in(&sys, "sda", &node("sda"));
+        assert_eq!(sda.vendor, None);
+        assert_eq!(sda.serial.as_deref(), Some("WD-WCC7K1"));
+        assert_eq!(sda.firmware.as_deref(), Some("0A82"));
+        assert_eq!(info_in(&sys, "vda", &node("vda")), Info::default());
+        fs::remove_dir_all(&sys).unwrap();
+    }
+
+    #[test]
+    fn names_device_mapper_devices_as_operators_know_them() {
+        assert_eq!(
+            dm_of("vg--ssd-home--old", "LVM-Kq1nC0aR8xYwJ2mD"),
+            Dm {
+                name: "vg--ssd-home--old".to_string(),
+                kind: DmType::Lvm,
+                vg: Some("vg-ssd".to_string()),
+                lv: Some("home-old".to_string()),
+                luks_uuid: None,
+            }
+        );
+        let luks = dm_of(
+            "luks-0f3c2a7e",
+            "CRYPT-LUKS2-0f3c2a7e9b1d4c58a6e2d7f01b3c9e44-luks-0f3c2a7e",
+        );
+        assert_eq!((luks.kind, luks.vg), (DmType::Crypt, None));
+        assert_eq!(
+            luks.luks_uuid.as_deref(),
+            Some("0f3c2a7e-9b1d-4c58-a6e2-d7f01b3c9e44")
+        );
+        let plain = dm_of("swap", "CRYPT-PLAIN-swap");
+        assert_eq!((plain.kind, plain.luks_uuid), (DmType::Crypt, None));
+        assert_eq!(dm_of("mpatha", "mpath-3600a0b80").kind, DmType::Mpath);
+        assert_eq!(
+            dm_of("mpatha1", "part1-mpath-3600a0b80").kind,
+            DmType::Mpath
+        );
+        assert_eq!(dm_of("docker-thinpool", "").kind, DmType::Other);
+    }
+
+    #[test]
+    fn tells_whether_discard_is_taken_and_used() {
+        let sys = std::env::temp_dir().join(format!("disk_space_discard_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&sys);
+        for (name, granularity) in [("dm-0", "512"), ("nvme0n1", "512"), ("sda", "0")] {
+            let queue = sys.join("class/block").join(name).join("queue");
+            fs::create_dir_all(&queue).unwrap();
+            fs::write(
+                queue.join("discard_granularity"),
+                format!("{granularity}\n"),
+            )
+            .unwrap();
+        }
+        let layer = |name: &str, kind: Kind, lower: &[&str]| Layer {
+            name: name.to_string(),
+            device: format!("/dev/{name}"),
+            kind,
+            lower: lower.iter().map(|name| name.to_string()).collect(),
+            dm: None,
+        };
+        let mut backing = Backing {
+            mount_point: "/srv".to_string(),
+            source: "/dev/mapper/vg0-srv".to_string(),
+            fs_type: "ext4".to_string(),
+            options: vec!["rw".to_string(), "discard".to_string()],
+            chain: vec![
+                layer("dm-0", Kind::Dm, &["nvme0n1p2"]),
+                layer("nvme0n1p2", Kind::Partition, &["nvme0n1"]),
+                layer("nvme0n1", Kind::Disk, &[]),
+            ],
+        };
+        let disk = layer("nvme0n1", Kind::Disk, &[]);
+        assert_eq!(
+            discard_in(&sys, &backing, &disk),
+            Discard {
+                device_supports: Some(true),
+                mount_option_enabled: Some(true),
+                fstrim_capable: Some(true),
+            }
+        );
+        backing.fs_type = "squashfs".to_string();
+        backing.options = vec!["ro".to_string()];
+        let discard = discard_in(&sys, &backing, &disk);
+        assert_eq!(discard.mount_option_enabled, Some(false));
+        assert_eq!(discard.fstrim_capable, Some(false));
+        let sda = discard_in(&sys, &backing, &layer("sda", Kind::Disk, &[]));
+        assert_eq!(sda.device_supports, Some(false));
+        let vda = discard_in(&sys, &backing, &layer("vda", Kind::Disk, &[]));
+        assert_eq!(vda.device_supports, None);
+        fs::remove_dir_all(&sys).unwrap();
+    }
+
+    #[cfg(feature = "device_health")]
+    #[test]
+    fn reads_the_smart_return_status() {
+        assert_eq!(smart_status(0x4F, 0xC2), Some(false));
+        assert_eq!(smart_status(0xF4, 0x2C), Some(true));
+        // Left as written by a device that ignored the command
+        assert_eq!(smart_status(0x00, 0x00), None);
+        assert_eq!(std::mem::size_of::<NvmeAdminCmd>(), 72);
+    }
+
+    #[test]
+    fn tells_the_transport_from_the_device_path() {
+        for (device, transport) in [
+            (
+                "/sys/devices/pci0000:00/0000:00:1d.0/0000:3d:00.0/nvme/nvme0/nvme0n1",
+                Transport::Nvme,
+            ),
+            (
+                "/sys/devices/virtual/nvme-fabrics/ctl/nvme1/nvme1c1n1",
+                Transport::Nvme,
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda",
+                Transport::Sata,
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0/host0/port-0:0/end_device-0:0/target0:0:0/0:0:0:0/block/sda",
+                Transport::Sas,
+            ),
+            (
+                "/sys/devices/platform/host3/session1/target3:0:0/3:0:0:0/block/sdb",
+                Transport::Iscsi,
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:03.0/0000:05:00.0/host5/rport-5:0-0/target5:0:0/5:0:0:0/block/sdc",
+                Transport::FibreChannel,
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:10.0/host2/target2:0:0/2:0:0:0/block/sda",
+                Transport::Scsi,
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:04.0/virtio1/block/vda",
+                Transport::Virtio,
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:05.0/virtio2/host0/target0:0:0/0:0:0:1/block/sda",
+                Transport::Virtio,
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb",
+                Transport::Usb,
+            ),
+            (
+                "/sys/devices/platform/fe340000.mmc/mmc_host/mmc0/mmc0:aaaa/block/mmcblk0",
+                Transport::Mmc,
+            ),
+            ("/sys/devices/virtual/block/loop0", Transport::Unknown),
+            ("/sys/devices/virtual/block/zram0", Transport::Unknown),
+        ] {
+            assert_eq!(transport_of(Path::new(device)), transport, "{device}");
+        }
+    }
+
+    #[test]
+    fn lists_the_partitions_of_a_disk() {
+        let sys = std::env::temp_dir().join(format!("disk_space_parts_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&sys);
+        let udev = sys.join("udev");
+        fs::create_dir_all(&udev).unwrap();
+        let disk = sys.join("class/block/sda");
+        // sda2 before sda1 on the disk, as after a partition is recreated
+        for (part, number, dev, start, size, guid) in [
+            (
+                "sda1",
+                "1",
+                "8:1",
+                "2099200",
+                "1048576",
+                "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+            ),
+            (
+                "sda2",
+                "2",
+                "8:2",
+                "2048",
+                "2097152",
+                "c12a7328-f81f-11d2-ba4b-00a0c93ec93b",
+            ),
+        ] {
+            let dir = disk.join(part);
+            fs::create_dir_all(&dir).unwrap();
+            for (attribute, value) in [
+                ("partition", number),
+                ("dev", dev),
+                ("start", start),
+                ("size", size),
+            ] {
+                fs::write(dir.join(attribute), format!("{value}\n")).unwrap();
+            }
+            fs::write(
+                udev.join(format!("b{dev}")),
+                format!("S:disk/by-partuuid/2f1c\nE:ID_PART_ENTRY_SCHEME=gpt\nE:ID_PART_ENTRY_TYPE={guid}\n"),
+            )
+            .unwrap();
+        }
+        // The queue and the like aren't partitions
+        fs::create_dir_all(disk.join("queue")).unwrap();
+        fs::create_dir_all(disk.join("sda3")).unwrap();
+        fs::write(disk.join("sda3/partition"), "3\n").unwrap();
+        let mounted = [
+            ("sda1".to_string(), "/".to_string()),
+            ("sda1".to_string(), "/var/lib/docker".to_string()),
+            ("sda2".to_string(), "/boot/efi".to_string()),
+        ];
+        let partitions = partitions_in(&sys, &udev, "sda", &mounted);
+        assert_eq!(
+            partitions[0],
+            Partition {
+                number: 2,
+                name: "sda2".to_string(),
+                device: "/dev/sda2".to_string(),
+                start: 1048576,
+                size: 1073741824,
+                type_id: Some("c12a7328-f81f-11d2-ba4b-00a0c93ec93b".to_string()),
+                mounted_at: vec!["/boot/efi".to_string()],
+            }
+        );
+        assert_eq!(partitions[1].start, 1074790400);
+        assert_eq!(
+            partitions[1].type_id.as_deref(),
+            Some("0fc63daf-8483-4772-8e79-3d69d8477de4")
+        );
+        assert_eq!(partitions[1].mounted_at, ["/", "/var/lib/docker"]);
+        assert_eq!(partitions.len(), 2);
+        assert_eq!(partitions_in(&sys, &udev, "sdb", &mounted), []);
+        fs::remove_dir_all(&sys).unwrap();
+    }
+
+    #[test]
+    fn tells_the_bus_from_the_device_path() {
+        let bus = |device: &str| bus_of(Path::new(device));
+        assert_eq!(
+            bus("/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb"),
+            Some(Bus::Usb)
+        );
+        assert_eq!(
+            bus("/sys/devices/pci0000:00/0000:00:1d.0/0000:3d:00.0/nvme/nvme0/nvme0n1"),
+            Some(Bus::Nvme)
+        );
+        assert_eq!(
+            bus("/sys/devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda"),
+            Some(Bus::Sata)
+        );
+        assert_eq!(
+            bus("/sys/devices/pci0000:00/0000:00:04.0/virtio1/block/vda"),
+            Some(Bus::Virtio)
+        );
+        assert_eq!(
+            bus("/sys/devices/platform/fe340000.mmc/mmc_host/mmc0/mmc0:aaaa/block/mmcblk0"),
+            Some(Bus::Mmc)
+        );
+        assert_eq!(
+            bus("/sys/devices/pci0000:00/0000:00:10.0/host2/target2:0:0/2:0:0:0/block/sda"),
+            Some(Bus::Scsi)
+        );
+        assert_eq!(bus("/sys/devices/virtual/block/loop0"), None);
+    }
+}
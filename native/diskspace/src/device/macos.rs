@@ -0,0 +1,518 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The disks beneath a volume on macOS, from the IOKit registry. A volume
+// is mounted from a BSD device, the f_mntfromname of statfs, which
+// IOBSDNameMatching finds the IOMedia object of. The IOMedia above it in
+// the IOService plane, skipping the drivers and schemes between them, are
+// the devices beneath it: an APFS volume (disk3s5) is in a container
+// (disk3), which is on a physical store (disk0s2), a partition of a disk
+// (disk0). A Fusion container has two physical stores, and an AppleRAID
+// set its members, so that an IOMedia may have several.
+//
+// What a disk tells of itself is in the Device Characteristics dictionary
+// of the block storage device its IOMedia is the medium of, a parent of it:
+// Product Name, Vendor Name, Serial Number and Product Revision Level. Its
+// Protocol Characteristics give the Physical Interconnect it is attached
+// by, and so its transport, and the IOMedia of the disk whether it is
+// Removable, or Ejectable as USB disks are.
+//
+// The sector sizes are asked of the /dev node of the disk with
+// DKIOCGETBLOCKSIZE and DKIOCGETPHYSICALBLOCKSIZE, which takes read access
+// to it, as root has; otherwise they are the Logical and Physical Block
+// Size of its Device Characteristics. Those also have the Medium Type,
+// Rotational or Solid State, and the IOMedia of the disk its Size.
+//
+// A disk takes TRIM (unmap) if its block storage driver lists Unmap among
+// its IOStorageFeatures, or its Device Characteristics say TRIM Support.
+// There is no mount option for it: APFS and HFS+ trim the free space of a
+// volume on such a disk as they mount it, and the blocks they free after.
+//
+// The partitions of a disk are the IOMedia below its partition scheme, the
+// nearest below the IOMedia of the disk in the IOService plane, each with
+// its Partition ID, its Base and Size in bytes, and its Content: the type
+// GUID of a GPT partition, or a hint such as DOS_FAT_32 for an MBR one.
+//
+// A disk that is SMART Capable, as its block storage device says, or NVMe
+// SMART Capable, may have its SMART Status in the registry, Verified or
+// Failing; not all drivers publish it.
+//
+// Every IOKit object and CF object obtained is released once dropped.
+
+#[cfg(feature = "device_health")]
+use super::health::Health;
+use super::{trimmed, Backing, Bus, Discard, Info, Kind, Layer, Partition, Transport};
+use crate::mounts::{self, Mount};
+use std::collections::VecDeque;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::io;
+use std::path::Path;
+use std::ptr;
+
+type CFTypeRef = *const c_void;
+type IoObject = u32;
+
+const K_CF_STRING_ENCODING_UTF8: u32 = 0x0800_0100;
+// kIORegistryIterateRecursively | kIORegistryIterateParents
+const SEARCH_PARENTS: u32 = 1 | 2;
+// _IOR('d', 24, u32) and _IOR('d', 77, u32)
+const DKIOCGETBLOCKSIZE: libc::c_ulong = 0x4004_6418;
+const DKIOCGETPHYSICALBLOCKSIZE: libc::c_ulong = 0x4004_644D;
+// kCFNumberSInt64Type
+const K_CF_NUMBER_SINT64_TYPE: isize = 4;
+// How far up the IOService plane to look for the IOMedia above another,
+// well beyond the drivers and controllers between two
+const MAX_DEPTH: u32 = 32;
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IOBSDNameMatching(main_port: u32, options: u32, bsd_name: *const c_char) -> CFTypeRef;
+    fn IOServiceGetMatchingService(main_port: u32, matching: CFTypeRef) -> IoObject;
+    fn IORegistryEntryGetParentIterator(
+        entry: IoObject,
+        plane: *const c_char,
+        iterator: *mut IoObject,
+    ) -> i32;
+    fn IORegistryEntryGetChildIterator(
+        entry: IoObject,
+        plane: *const c_char,
+        iterator: *mut IoObject,
+    ) -> i32;
+    fn IOIteratorNext(iterator: IoObject) -> IoObject;
+    fn IOObjectConformsTo(object: IoObject, class_name: *const c_char) -> u32;
+    fn IORegistryEntryCreateCFProperty(
+        entry: IoObject,
+        key: CFTypeRef,
+        allocator: CFTypeRef,
+        options: u32,
+    ) -> CFTypeRef;
+    fn IORegistryEntrySearchCFProperty(
+        entry: IoObject,
+        plane: *const c_char,
+        key: CFTypeRef,
+        allocator: CFTypeRef,
+        options: u32,
+    ) -> CFTypeRef;
+    fn IOObjectRelease(object: IoObject) -> i32;
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFStringCreateWithCString(
+        allocator: CFTypeRef,
+        string: *const c_char,
+        encoding: u32,
+    ) -> CFTypeRef;
+    fn CFStringGetCString(string: CFTypeRef, buffer: *mut c_char, size: isize, encoding: u32)
+        -> u8;
+    fn CFGetTypeID(object: CFTypeRef) -> usize;
+    fn CFStringGetTypeID() -> usize;
+    fn CFBooleanGetTypeID() -> usize;
+    fn CFBooleanGetValue(boolean: CFTypeRef) -> u8;
+    fn CFDictionaryGetTypeID() -> usize;
+    fn CFDictionaryGetValue(dictionary: CFTypeRef, key: CFTypeRef) -> CFTypeRef;
+    fn CFNumberGetTypeID() -> usize;
+    fn CFNumberGetValue(number: CFTypeRef, number_type: isize, value: *mut c_void) -> u8;
+    fn CFRelease(object: CFTypeRef);
+}
+
+// Releases the IOKit object it holds once dropped
+struct Object(IoObject);
+
+impl Drop for Object {
+    fn drop(&mut self) {
+        if self.0 != 0 {
+            unsafe { IOObjectRelease(self.0) };
+        }
+    }
+}
+
+// Releases the CF object it holds once dropped
+struct Owned(CFTypeRef);
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { CFRelease(self.0) };
+        }
+    }
+}
+
+// Helper: The CFString of `string`
+fn cf_string(string: &CStr) -> Option<Owned> {
+    let string = Owned(unsafe {
+        CFStringCreateWithCString(ptr::null(), string.as_ptr(), K_CF_STRING_ENCODING_UTF8)
+    });
+    (!string.0.is_null()).then_some(string)
+}
+
+// Helper: The text of `value`, if a CFString; not owned, as the values of
+// a dictionary aren't
+fn string_value(value: CFTypeRef) -> Option<String> {
+    if value.is_null() || unsafe { CFGetTypeID(value) != CFStringGetTypeID() } {
+        return None;
+    }
+    let mut buffer = [0 as c_char; 256];
+    let converted = unsafe {
+        CFStringGetCString(
+            value,
+            buffer.as_mut_ptr(),
+            buffer.len() as isize,
+            K_CF_STRING_ENCODING_UTF8,
+        )
+    } != 0;
+    converted.then(|| {
+        unsafe { CStr::from_ptr(buffer.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+impl Object {
+    // The property `key` of this registry entry
+    fn property(&self, key: &CStr) -> Option<Owned> {
+        let key = cf_string(key)?;
+        let value =
+            Owned(unsafe { IORegistryEntryCreateCFProperty(self.0, key.0, ptr::null(), 0) });
+        (!value.0.is_null()).then_some(value)
+    }
+
+    // The property `key` of this registry entry or the nearest of its
+    // parents having it
+    fn inherited(&self, key: &CStr) -> Option<Owned> {
+        let key = cf_string(key)?;
+        let value = Owned(unsafe {
+            IORegistryEntrySearchCFProperty(
+                self.0,
+                c"IOService".as_ptr(),
+                key.0,
+                ptr::null(),
+                SEARCH_PARENTS,
+            )
+        });
+        (!value.0.is_null()).then_some(value)
+    }
+
+    fn string(&self, key: &CStr) -> Option<String> {
+        string_value(self.property(key)?.0)
+    }
+
+    // The dictionary `key` of this registry entry or the nearest of its
+    // parents having it
+    fn inherited_dictionary(&self, key: &CStr) -> Option<Owned> {
+        self.inherited(key)
+            .filter(|value| unsafe { CFGetTypeID(value.0) == CFDictionaryGetTypeID() })
+    }
+
+    fn boolean(&self, key: &CStr) -> Option<bool> {
+        boolean_value(self.property(key)?.0)
+    }
+
+    fn number(&self, key: &CStr) -> Option<i64> {
+        number_value(self.property(key)?.0)
+    }
+
+    // The nearest IOMedia above this entry on each way up the IOService
+    // plane
+    fn lower_media(&self, depth: u32) -> Vec<Object> {
+        self.nearest_media(depth, IORegistryEntryGetParentIterator)
+    }
+
+    // The nearest IOMedia below this entry on each way down the IOService
+    // plane
+    fn upper_media(&self, depth: u32) -> Vec<Object> {
+        self.nearest_media(depth, IORegistryEntryGetChildIterator)
+    }
+
+    // The nearest IOMedia on each way through the IOService plane
+    // `iterate` takes from this entry
+    fn nearest_media(
+        &self,
+        depth: u32,
+        iterate: unsafe extern "C" fn(IoObject, *const c_char, *mut IoObject) -> i32,
+    ) -> Vec<Object> {
+        let mut found = Vec::new();
+        let mut iterator = 0;
+        if depth == 0 || unsafe { iterate(self.0, c"IOService".as_ptr(), &mut iterator) } != 0 {
+            return found;
+        }
+        let iterator = Object(iterator);
+        loop {
+            let parent = Object(unsafe { IOIteratorNext(iterator.0) });
+            if parent.0 == 0 {
+                break;
+            }
+            if unsafe { IOObjectConformsTo(parent.0, c"IOMedia".as_ptr()) } != 0 {
+                found.push(parent);
+            } else {
+                found.extend(parent.nearest_media(depth - 1, iterate));
+            }
+        }
+        found
+    }
+}
+
+// Helper: The IOMedia of the BSD device `name`, e.g. "disk3s5"
+fn media(name: &str) -> Option<Object> {
+    let name = CString::new(name).ok()?;
+    let matching = unsafe { IOBSDNameMatching(0, 0, name.as_ptr()) };
+    if matching.is_null() {
+        return None;
+    }
+    // IOServiceGetMatchingService takes the dictionary over
+    let media = Object(unsafe { IOServiceGetMatchingService(0, matching) });
+    (media.0 != 0).then_some(media)
+}
+
+// Helper: The string `key` of the dictionary `dictionary`, trimmed
+fn field(dictionary: &Owned, key: &CStr) -> Option<String> {
+    let key = cf_string(key)?;
+    let value = unsafe { CFDictionaryGetValue(dictionary.0, key.0) };
+    trimmed(&string_value(value)?)
+}
+
+// Helper: The truth of `value`, if a CFBoolean
+fn boolean_value(value: CFTypeRef) -> Option<bool> {
+    if value.is_null() || unsafe { CFGetTypeID(value) != CFBooleanGetTypeID() } {
+        return None;
+    }
+    Some(unsafe { CFBooleanGetValue(value) } != 0)
+}
+
+// Helper: The boolean `key` of the dictionary `dictionary`
+fn boolean_field(dictionary: &Owned, key: &CStr) -> Option<bool> {
+    let key = cf_string(key)?;
+    boolean_value(unsafe { CFDictionaryGetValue(dictionary.0, key.0) })
+}
+
+// Helper: The number `key` of the dictionary `dictionary`
+fn number_field(dictionary: &Owned, key: &CStr) -> Option<u32> {
+    let key = cf_string(key)?;
+    let value = unsafe { CFDictionaryGetValue(dictionary.0, key.0) };
+    u32::try_from(number_value(value)?).ok()
+}
+
+// Helper: The value of `value`, if a CFNumber
+fn number_value(value: CFTypeRef) -> Option<i64> {
+    if value.is_null() || unsafe { CFGetTypeID(value) != CFNumberGetTypeID() } {
+        return None;
+    }
+    let mut number = 0i64;
+    let converted = unsafe {
+        CFNumberGetValue(
+            value,
+            K_CF_NUMBER_SINT64_TYPE,
+            (&mut number as *mut i64).cast(),
+        )
+    } != 0;
+    converted.then_some(number)
+}
+
+// Helper: The logical and physical sector sizes of the disk at `device`
+fn sector_sizes(device: &str) -> io::Result<(u32, u32)> {
+    use std::os::unix::io::AsRawFd;
+    let file = std::fs::File::open(device)?;
+    let mut logical = 0u32;
+    let mut physical = 0u32;
+    if unsafe { libc::ioctl(file.as_raw_fd(), DKIOCGETBLOCKSIZE, &mut logical) } != 0
+        || unsafe { libc::ioctl(file.as_raw_fd(), DKIOCGETPHYSICALBLOCKSIZE, &mut physical) } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((logical, physical))
+}
+
+// Helper: The bus of the Physical Interconnect `interconnect`
+fn bus_of(interconnect: &str) -> Option<Bus> {
+    match interconnect {
+        "USB" => Some(Bus::Usb),
+        "SATA" => Some(Bus::Sata),
+        "PCI-Express" | "Apple Fabric" => Some(Bus::Nvme),
+        "Secure Digital" => Some(Bus::Mmc),
+        _ => None,
+    }
+}
+
+// The transports of disks by their Physical Interconnect; the internal SSDs
+// of Apple silicon are NVMe on the Apple Fabric
+const TRANSPORTS: [(&str, Transport); 9] = [
+    ("PCI-Express", Transport::Nvme),
+    ("Apple Fabric", Transport::Nvme),
+    ("SATA", Transport::Sata),
+    ("SAS", Transport::Sas),
+    ("SCSI", Transport::Scsi),
+    ("Fibre Channel Interface", Transport::FibreChannel),
+    ("USB", Transport::Usb),
+    ("Secure Digital", Transport::Mmc),
+    ("Virtual Interface", Transport::Unknown),
+];
+
+pub(super) fn info(disk: &Layer) -> Info {
+    let Some(media) = media(&disk.name) else {
+        return Info::default();
+    };
+    let characteristics = media.inherited_dictionary(c"Device Characteristics");
+    let interconnect = media
+        .inherited_dictionary(c"Protocol Characteristics")
+        .and_then(|protocol| field(&protocol, c"Physical Interconnect"));
+    let bus = interconnect.as_deref().and_then(bus_of);
+    let transport = TRANSPORTS
+        .iter()
+        .find(|(known, _)| interconnect.as_deref() == Some(*known))
+        .map_or(Transport::Unknown, |(_, transport)| *transport);
+    let removable = match (media.boolean(c"Removable"), media.boolean(c"Ejectable")) {
+        (None, None) => None,
+        (removable, ejectable) => Some(removable == Some(true) || ejectable == Some(true)),
+    };
+    let characteristic = |key: &CStr| field(characteristics.as_ref()?, key);
+    let size = |key: &CStr| number_field(characteristics.as_ref()?, key);
+    let (logical, physical, permission_limited) = match sector_sizes(&disk.device) {
+        Ok((logical, physical)) => (Some(logical), Some(physical), false),
+        Err(err) => (
+            size(c"Logical Block Size"),
+            size(c"Physical Block Size"),
+            err.kind() == io::ErrorKind::PermissionDenied,
+        ),
+    };
+    Info {
+        model: characteristic(c"Product Name"),
+        vendor: characteristic(c"Vendor Name"),
+        serial: characteristic(c"Serial Number"),
+        firmware: characteristic(c"Product Revision Level"),
+        removable: match bus {
+            Some(Bus::Usb) => Some(true),
+            _ => removable,
+        },
+        bus,
+        transport,
+        size: media
+            .number(c"Size")
+            .and_then(|size| u64::try_from(size).ok()),
+        rotational: match characteristic(c"Medium Type").as_deref() {
+            Some("Rotational") => Some(true),
+            Some("Solid State") => Some(false),
+            _ => None,
+        },
+        logical_sector_size: logical,
+        physical_sector_size: physical,
+        permission_limited,
+    }
+}
+
+pub(super) fn discard(backing: &Backing, disk: &Layer) -> Discard {
+    let media = media(&disk.name);
+    let feature = |dictionary: &CStr, key: &CStr| {
+        boolean_field(&media.as_ref()?.inherited_dictionary(dictionary)?, key)
+    };
+    let supports = feature(c"IOStorageFeatures", c"Unmap")
+        .or_else(|| feature(c"Device Characteristics", c"TRIM Support"));
+    Discard {
+        device_supports: supports,
+        mount_option_enabled: None,
+        fstrim_capable: supports
+            .map(|supports| supports && matches!(backing.fs_type.as_str(), "apfs" | "hfs")),
+    }
+}
+
+pub(super) fn partitions(disk: &Layer, mounts: &[Mount]) -> io::Result<Vec<Partition>> {
+    let Some(media) = media(&disk.name) else {
+        return Ok(Vec::new());
+    };
+    let mut partitions: Vec<Partition> = media
+        .upper_media(MAX_DEPTH)
+        .iter()
+        .filter_map(|part| {
+            let name = part.string(c"BSD Name")?;
+            let device = format!("/dev/{name}");
+            let bytes = |key: &CStr| u64::try_from(part.number(key)?).ok();
+            // GPT type GUIDs in lowercase, as on the other platforms
+            let type_id = part
+                .string(c"Content")
+                .filter(|content| !content.is_empty())
+                .map(|content| {
+                    if content.len() == 36 && content.matches('-').count() == 4 {
+                        content.to_ascii_lowercase()
+                    } else {
+                        content
+                    }
+                });
+            Some(Partition {
+                number: u32::try_from(part.number(c"Partition ID")?).ok()?,
+                start: bytes(c"Base")?,
+                size: bytes(c"Size")?,
+                type_id,
+                mounted_at: mounts
+                    .iter()
+                    .filter(|mount| mount.source.rsplit('@').next() == Some(device.as_str()))
+                    .map(|mount| mount.mount_point.clone())
+                    .collect(),
+                name,
+                device,
+            })
+        })
+        .collect();
+    partitions.sort_by_key(|partition| partition.start);
+    Ok(partitions)
+}
+
+#[cfg(feature = "device_health")]
+pub(super) fn health(disk: &Layer) -> Health {
+    let Some(media) = media(&disk.name) else {
+        return Health::default();
+    };
+    let inherited = |key: &CStr| media.inherited(key);
+    let capable = [c"SMART Capable", c"NVMe SMART Capable"]
+        .into_iter()
+        .any(|key| inherited(key).and_then(|value| boolean_value(value.0)) == Some(true));
+    let status = inherited(c"SMART Status").and_then(|value| string_value(value.0));
+    Health {
+        failure_predicted: match status.as_deref() {
+            Some("Verified") if capable => Some(false),
+            Some("Failing") if capable => Some(true),
+            _ => None,
+        },
+        ..Health::default()
+    }
+}
+
+pub(super) fn resolve(path: &Path) -> io::Result<Option<Backing>> {
+    Ok(mounts::holding(path)?.as_ref().and_then(resolve_mount))
+}
+
+pub(super) fn resolve_mount(mount: &Mount) -> Option<Backing> {
+    // The device of a snapshot mounted as com.apple.os.update-…@/dev/disk3s1s1
+    let source = mount.source.rsplit('@').next().unwrap_or(&mount.source);
+    let start = source.strip_prefix("/dev/").and_then(media)?;
+    let mut chain: Vec<Layer> = Vec::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(media) = queue.pop_front() {
+        let Some(name) = media.string(c"BSD Name") else {
+            continue;
+        };
+        if chain.iter().any(|layer| layer.name == name) {
+            continue;
+        }
+        let lower = media.lower_media(MAX_DEPTH);
+        // A synthesized disk, such as an APFS container, is whole too
+        let kind = match media.boolean(c"Whole") {
+            Some(true) => Kind::Disk,
+            _ => Kind::Partition,
+        };
+        chain.push(Layer {
+            device: format!("/dev/{name}"),
+            name,
+            kind,
+            lower: lower
+                .iter()
+                .filter_map(|media| media.string(c"BSD Name"))
+                .collect(),
+        });
+        queue.extend(lower);
+    }
+    Some(Backing {
+        mount_point: mount.mount_point.clone(),
+        source: mount.source.clone(),
+        fs_type: mount.fs_type.clone(),
+        chain,
+    })
+}
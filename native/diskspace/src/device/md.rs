@@ -0,0 +1,405 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// md RAID arrays, from /proc/mdstat and the md directory of each in sysfs.
+// mdstat has an entry per array:
+//
+//   md1 : active raid5 sdd1[4] sdc1[2] sdb1[1] sda1[0](F) sde1[3](S)
+//         2930276352 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [_UU]
+//         [=>.........]  recovery =  8.5% (124589056/1465138176) finish=120.3min speed=185718K/sec
+//
+// An array may be inactive, with no level, or active (auto-read-only)
+// before its first write; raid0 and linear arrays have no [n/m] of disks
+// and working ones; a sync may be DELAYED or PENDING rather than under
+// way. The number after each member is its number in the superblock, not
+// its slot in the array, so a member without a (F)aulty, (S)pare or
+// (R)eplacement flag is taken to be rebuilding if its number is beyond the
+// slots of a degraded array, and in sync otherwise; the state and slot of
+// each member in sysfs, where readable, are the ones to go by.
+//
+// The parser is portable so that fixtures are tested on any host.
+
+#[cfg(target_os = "linux")]
+use crate::atoms;
+#[cfg(target_os = "linux")]
+use rustler::{Atom, Encoder, Env, NifResult, Term};
+#[cfg(target_os = "linux")]
+use std::{fs, path::Path};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MemberState {
+    InSync,
+    Rebuilding,
+    Spare,
+    Faulty,
+    // Being rebuilt to take over from an in-sync member
+    Replacement,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Member {
+    pub name: String,
+    // The slot in the array, from sysfs
+    pub slot: Option<u32>,
+    pub state: MemberState,
+}
+
+// A resync, recovery, reshape, check or repair, under way or waiting
+#[derive(Debug, PartialEq)]
+pub(crate) struct Sync {
+    pub action: String,
+    // None while DELAYED or PENDING
+    pub percent: Option<f64>,
+    pub finish_minutes: Option<f64>,
+    pub speed_kib_per_sec: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Array {
+    pub name: String,
+    pub active: bool,
+    pub level: Option<String>,
+    // The [n/m] of the array, none for raid0 and linear
+    pub raid_disks: Option<u32>,
+    pub active_disks: Option<u32>,
+    pub members: Vec<Member>,
+    pub sync: Option<Sync>,
+    // From sysfs: the array_state, and the number of missing members
+    pub array_state: Option<String>,
+    pub missing: Option<u32>,
+}
+
+impl Array {
+    // Fewer working members than slots, as mdstat has it
+    fn short(&self) -> bool {
+        self.active_disks
+            .zip(self.raid_disks)
+            .is_some_and(|(active, raid)| active < raid)
+    }
+
+    // Missing members, or a faulty one
+    pub(crate) fn degraded(&self) -> bool {
+        let missing = match self.missing {
+            Some(missing) => missing > 0,
+            None => self.short(),
+        };
+        missing
+            || self
+                .members
+                .iter()
+                .any(|member| member.state == MemberState::Faulty)
+    }
+}
+
+// Parse the contents of /proc/mdstat
+pub(crate) fn parse(text: &str) -> Vec<Array> {
+    let mut arrays: Vec<Array> = Vec::new();
+    for line in text.lines() {
+        if let Some((name, rest)) = line
+            .split_once(" : ")
+            .filter(|(name, _)| name.starts_with("md") && !name.contains(char::is_whitespace))
+        {
+            arrays.push(parse_header(name, rest));
+        } else if line.starts_with(char::is_whitespace) {
+            if let Some(array) = arrays.last_mut() {
+                parse_detail(array, line.trim());
+            }
+        }
+    }
+    for array in &mut arrays {
+        settle_states(array);
+    }
+    arrays
+}
+
+// Helper: An array from the line naming it, `rest` following its " : "
+fn parse_header(name: &str, rest: &str) -> Array {
+    let mut tokens = rest.split_whitespace().peekable();
+    let active = tokens.next() == Some("active");
+    // (auto-read-only), (read-only)
+    while tokens.peek().is_some_and(|token| token.starts_with('(')) {
+        tokens.next();
+    }
+    let level = match tokens.peek() {
+        Some(token) if active && !token.contains('[') => tokens.next().map(str::to_string),
+        _ => None,
+    };
+    let members = tokens
+        .filter_map(|token| {
+            let (name, rest) = token.split_once('[')?;
+            let (number, flags) = rest.split_once(']')?;
+            let state = if flags.contains("(F)") {
+                MemberState::Faulty
+            } else if flags.contains("(S)") {
+                MemberState::Spare
+            } else if flags.contains("(R)") {
+                MemberState::Replacement
+            } else {
+                MemberState::InSync
+            };
+            Some((name.to_string(), number.parse::<u32>().ok(), state))
+        })
+        .map(|(name, number, state)| Member {
+            name,
+            // Until settled, the number in the superblock
+            slot: number,
+            state,
+        })
+        .collect();
+    Array {
+        name: name.to_string(),
+        active,
+        level,
+        raid_disks: None,
+        active_disks: None,
+        members,
+        sync: None,
+        array_state: None,
+        missing: None,
+    }
+}
+
+// Helper: A line of the entry of `array` after the first
+fn parse_detail(array: &mut Array, line: &str) {
+    if line.contains(" blocks") {
+        // [3/2] [_UU]
+        let disks = line
+            .split_whitespace()
+            .filter_map(|token| token.strip_prefix('[')?.strip_suffix(']'))
+            .find_map(|token| token.split_once('/'));
+        if let Some((raid, active)) = disks {
+            array.raid_disks = raid.parse().ok();
+            array.active_disks = active.parse().ok();
+        }
+        return;
+    }
+    let actions = ["resync", "recovery", "reshape", "check", "repair"];
+    // resync=DELAYED, recovery=PENDING
+    if let Some((action, _)) = line
+        .split_once('=')
+        .filter(|(action, _)| actions.contains(action))
+    {
+        array.sync = Some(Sync {
+            action: action.to_string(),
+            percent: None,
+            finish_minutes: None,
+            speed_kib_per_sec: None,
+        });
+        return;
+    }
+    // [=>....]  recovery =  8.5% (…) finish=120.3min speed=185718K/sec
+    let mut tokens = line.split_whitespace();
+    let Some(action) = tokens.by_ref().find(|token| actions.contains(token)) else {
+        return;
+    };
+    let rest: Vec<&str> = tokens.collect();
+    let value = |key: &str, suffix: &str| {
+        rest.iter()
+            .find_map(|token| token.strip_prefix(key)?.strip_suffix(suffix))
+    };
+    array.sync = Some(Sync {
+        action: action.to_string(),
+        percent: rest
+            .iter()
+            .find_map(|token| token.strip_suffix('%')?.parse().ok()),
+        finish_minutes: value("finish=", "min").and_then(|minutes| minutes.parse().ok()),
+        speed_kib_per_sec: value("speed=", "K/sec").and_then(|speed| speed.parse().ok()),
+    });
+}
+
+// Helper: Tell the rebuilding members of `array` from those in sync, and
+// forget the numbers in the superblock, which aren't slots
+fn settle_states(array: &mut Array) {
+    let degraded = array.short();
+    for member in &mut array.members {
+        let beyond = member
+            .slot
+            .zip(array.raid_disks)
+            .is_some_and(|(n, raid)| n >= raid);
+        if member.state == MemberState::InSync && degraded && beyond {
+            member.state = MemberState::Rebuilding;
+        }
+        member.slot = None;
+    }
+}
+
+// Helper: `array` with what its md directory in the sysfs at `sys` says
+#[cfg(target_os = "linux")]
+pub(crate) fn read_sysfs(sys: &Path, array: &mut Array) {
+    let md = sys.join("class/block").join(&array.name).join("md");
+    let attribute = |file: &Path| {
+        fs::read_to_string(file)
+            .ok()
+            .map(|value| value.trim().to_string())
+    };
+    array.array_state = attribute(&md.join("array_state"));
+    array.missing = attribute(&md.join("degraded")).and_then(|missing| missing.parse().ok());
+    for member in &mut array.members {
+        let dir = md.join(format!("dev-{}", member.name));
+        member.slot = attribute(&dir.join("slot")).and_then(|slot| slot.parse().ok());
+        let Some(state) = attribute(&dir.join("state")) else {
+            continue;
+        };
+        let flags: Vec<&str> = state.split(',').collect();
+        member.state = if flags.contains(&"faulty") {
+            MemberState::Faulty
+        } else if flags.contains(&"replacement") {
+            MemberState::Replacement
+        } else if flags.contains(&"in_sync") {
+            MemberState::InSync
+        } else if member.slot.is_some() {
+            // A spare given a slot, being recovered into it
+            MemberState::Rebuilding
+        } else {
+            MemberState::Spare
+        };
+    }
+}
+
+// Helper: The atom of the sync action `action`
+#[cfg(target_os = "linux")]
+fn action(action: &str) -> Atom {
+    match action {
+        "resync" => atoms::resync(),
+        "recovery" => atoms::recovery(),
+        "reshape" => atoms::reshape(),
+        "check" => atoms::check(),
+        "repair" => atoms::repair(),
+        _ => atoms::unknown(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl MemberState {
+    fn atom(self) -> Atom {
+        match self {
+            MemberState::InSync => atoms::in_sync(),
+            MemberState::Rebuilding => atoms::rebuilding(),
+            MemberState::Spare => atoms::spare(),
+            MemberState::Faulty => atoms::faulty(),
+            MemberState::Replacement => atoms::replacement(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Array {
+    // %{name, device, level, array_state, raid_disks, active_disks,
+    // degraded, members: [%{name, device, slot, state}], sync}
+    pub(crate) fn encode<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        let members = self
+            .members
+            .iter()
+            .map(|member| {
+                rustler::types::map::map_new(env)
+                    .map_put(atoms::name().to_term(env), member.name.as_str())?
+                    .map_put(
+                        atoms::device().to_term(env),
+                        format!("/dev/{}", member.name.replace('!', "/")),
+                    )?
+                    .map_put(atoms::slot().to_term(env), member.slot)?
+                    .map_put(atoms::state().to_term(env), member.state.atom())
+            })
+            .collect::<NifResult<Vec<Term>>>()?;
+        let sync = match &self.sync {
+            Some(sync) => rustler::types::map::map_new(env)
+                .map_put(atoms::action().to_term(env), action(&sync.action))?
+                .map_put(atoms::percent().to_term(env), sync.percent)?
+                .map_put(atoms::finish_minutes().to_term(env), sync.finish_minutes)?
+                .map_put(
+                    atoms::speed_kib_per_sec().to_term(env),
+                    sync.speed_kib_per_sec,
+                )?,
+            None => rustler::types::atom::nil().to_term(env),
+        };
+        rustler::types::map::map_new(env)
+            .map_put(atoms::name().to_term(env), self.name.as_str())?
+            .map_put(atoms::device().to_term(env), format!("/dev/{}", self.name))?
+            .map_put(atoms::active().to_term(env), self.active)?
+            .map_put(atoms::level().to_term(env), self.level.as_deref())?
+            .map_put(
+                atoms::array_state().to_term(env),
+                self.array_state.as_deref(),
+            )?
+            .map_put(atoms::raid_disks().to_term(env), self.raid_disks)?
+            .map_put(atoms::active_disks().to_term(env), self.active_disks)?
+            .map_put(atoms::degraded().to_term(env), self.degraded())?
+            .map_put(atoms::members().to_term(env), members.encode(env))?
+            .map_put(atoms::sync().to_term(env), sync)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states(array: &Array) -> Vec<(&str, MemberState)> {
+        array
+            .members
+            .iter()
+            .map(|member| (member.name.as_str(), member.state))
+            .collect()
+    }
+
+    #[test]
+    fn parses_a_degraded_array_recovering() {
+        let arrays = parse(include_str!("../../fixtures/mdstat_degraded.txt"));
+        assert_eq!(arrays.len(), 2);
+        let md1 = &arrays[0];
+        assert_eq!(md1.name, "md1");
+        assert_eq!(md1.level.as_deref(), Some("raid5"));
+        assert_eq!((md1.raid_disks, md1.active_disks), (Some(3), Some(2)));
+        assert_eq!(
+            states(md1),
+            [
+                ("sdd1", MemberState::Rebuilding),
+                ("sdc1", MemberState::InSync),
+                ("sdb1", MemberState::InSync),
+                ("sda1", MemberState::Faulty),
+                ("sde1", MemberState::Spare),
+            ]
+        );
+        assert_eq!(
+            md1.sync,
+            Some(Sync {
+                action: "recovery".to_string(),
+                percent: Some(8.5),
+                finish_minutes: Some(120.3),
+                speed_kib_per_sec: Some(185718),
+            })
+        );
+        assert!(md1.degraded());
+        assert!(!arrays[1].degraded());
+        assert_eq!(arrays[1].sync, None);
+    }
+
+    #[test]
+    fn tolerates_the_quirks_of_mdstat() {
+        let arrays = parse(include_str!("../../fixtures/mdstat_quirks.txt"));
+        let array = |name: &str| arrays.iter().find(|array| array.name == name).unwrap();
+        let md127 = array("md127");
+        assert!(!md127.active);
+        assert_eq!(md127.level, None);
+        assert_eq!(states(md127), [("sdg1", MemberState::Spare)]);
+        let md126 = array("md126");
+        assert!(md126.active);
+        assert_eq!(md126.level.as_deref(), Some("raid1"));
+        assert_eq!(md126.sync.as_ref().unwrap().action, "resync");
+        assert_eq!(md126.sync.as_ref().unwrap().percent, None);
+        let md5 = array("md5");
+        assert_eq!(md5.sync.as_ref().unwrap().action, "reshape");
+        assert_eq!(md5.sync.as_ref().unwrap().percent, Some(15.7));
+        let md4 = array("md4");
+        assert_eq!(md4.level.as_deref(), Some("raid0"));
+        assert_eq!(md4.raid_disks, None);
+        assert!(!md4.degraded());
+        let md3 = array("md3");
+        assert_eq!(md3.sync.as_ref().unwrap().action, "check");
+        assert_eq!(md3.members[0].state, MemberState::InSync);
+        assert_eq!(md3.members[3].state, MemberState::Replacement);
+        assert!(!md3.degraded());
+        let md2 = array("md2");
+        assert_eq!(states(md2)[0], ("sdr1", MemberState::Rebuilding));
+        assert!(md2.degraded());
+    }
+}
@@ -0,0 +1,800 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// backing_device: the devices a path is stored on, from the mount holding
+// it down to the disks, e.g. /srv/data on /dev/mapper/vg0-data, a logical
+// volume on /dev/nvme0n1p2, a partition of /dev/nvme0n1. The devices are
+// listed as a chain, those nearer the mount first, each naming those
+// directly beneath it; a filesystem or volume over several disks (btrfs
+// RAID, striped LVM, md, spanned Windows volumes) has all of them at the
+// bottom, as the disks of the result.
+//
+// On Linux the chain is read from sysfs, on macOS from the IOMedia objects
+// of the IOKit registry above the BSD device the volume is mounted from,
+// and on Windows from the disk extents of the volume. Filesystems on no
+// block device (NFS, tmpfs, overlay) get {:error, :not_block_device}, the
+// BSDs {:error, :unsupported}.
+//
+// A device-mapper device on Linux is named dm-N, which tells operators
+// nothing; its dm map has the name they know it by, the type of mapping
+// the prefix of its uuid tells (LVM-, CRYPT-, mpath-), and for LVM the
+// volume group and logical volume that name is made of. list_mounts puts
+// the same in the map of each mount on such a device.
+//
+// A filesystem is encrypted if any device of its chain is dm-crypt, be it
+// LUKS on a partition, LVM on LUKS or LUKS on LVM; the uuid cryptsetup gives
+// the device has that of the LUKS header in it, as luksUUID tells it. Apart
+// from those, ext4 and f2fs encrypt directories themselves with fscrypt,
+// once the filesystem has the encrypt feature.
+//
+// device_info: what the disks at the bottom of the chain are, their model,
+// vendor, serial number and firmware revision, as the operators who get to
+// replace them know them, and whether they are removable and the bus they
+// are on. Those come from sysfs on Linux, the IOKit registry on macOS and
+// StorageDeviceProperty on Windows, and are nil where the disk doesn't
+// tell, as virtio disks without a serial don't. Their transport, the
+// protocol they are reached by, tells SAS, iSCSI and Fibre Channel disks
+// from other SCSI ones, and is :unknown for virtual disks and those that
+// don't tell. The list_mounts option device_info puts those of each mount
+// in its map.
+//
+// The logical and physical sector sizes, 512 and 4096 for a 512e disk, are
+// asked of the device itself, with an ioctl on its node on Linux and macOS,
+// which takes the rights to open it for reading. Without them they are
+// read from sysfs or the registry instead, nil if not there either, and
+// permission_limited says so. Also there are the size of the disk, and
+// whether it is rotational, a hard disk, rather than solid state.
+//
+// The discard map of each disk says whether it takes TRIM/discard, whether
+// the filesystem is mounted to discard the blocks it frees as it does,
+// which only Linux has a mount option for, and whether it can be trimmed
+// in one go, as fstrim does on Linux and Optimize-Volume on Windows.
+//
+// partitions: the partition table of each disk, where each partition
+// starts and how big it is, its type, and where the filesystem on it is
+// mounted, with the size of the disk, so that the space left unallocated
+// can be told. Linux has the partitions of a disk in sysfs, and their
+// types in the udev database; macOS has them as the IOMedia above the
+// partition scheme of the disk, and Windows in the layout
+// IOCTL_DISK_GET_DRIVE_LAYOUT_EX gives.
+
+mod health;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(any(target_os = "linux", test))]
+mod md;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+pub(crate) use windows::{attachment, performance};
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use crate::media::Readiness;
+use crate::mounts::Mount;
+use crate::{atoms, get_path_from_term, make_error_tuple, path_from_cstring};
+use rustler::{Env, NifResult, Term};
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+use {
+    crate::{make_errno_error_tuple, posix},
+    rustler::{Atom, Encoder},
+    std::io,
+    std::path::Path,
+};
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Kind {
+    Disk,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    Partition,
+    // device-mapper: LVM, dm-crypt, multipath
+    #[cfg(target_os = "linux")]
+    Dm,
+    // md RAID
+    #[cfg(target_os = "linux")]
+    Md,
+    #[cfg(target_os = "linux")]
+    Loop,
+    #[cfg(windows)]
+    Volume,
+    // Over other devices, as none of the above
+    #[cfg(target_os = "linux")]
+    Other,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+impl Kind {
+    fn atom(self) -> Atom {
+        match self {
+            Kind::Disk => atoms::disk(),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Kind::Partition => atoms::partition(),
+            #[cfg(target_os = "linux")]
+            Kind::Dm => atoms::dm(),
+            #[cfg(target_os = "linux")]
+            Kind::Md => atoms::md(),
+            #[cfg(target_os = "linux")]
+            Kind::Loop => atoms::loop_(),
+            #[cfg(windows)]
+            Kind::Volume => atoms::volume(),
+            #[cfg(target_os = "linux")]
+            Kind::Other => atoms::other(),
+        }
+    }
+}
+
+// A device of the chain, and the names of those directly beneath it
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+#[derive(Debug)]
+pub(crate) struct Layer {
+    pub name: String,
+    pub device: String,
+    pub kind: Kind,
+    pub lower: Vec<String>,
+    // Linux only: what a device-mapper device is
+    #[cfg(target_os = "linux")]
+    pub dm: Option<Dm>,
+}
+
+// The type of mapping of a device-mapper device
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DmType {
+    Lvm,
+    // dm-crypt: LUKS and plain
+    Crypt,
+    Mpath,
+    Other,
+}
+
+#[cfg(target_os = "linux")]
+impl DmType {
+    fn atom(self) -> Atom {
+        match self {
+            DmType::Lvm => atoms::lvm(),
+            DmType::Crypt => atoms::crypt(),
+            DmType::Mpath => atoms::mpath(),
+            DmType::Other => atoms::other(),
+        }
+    }
+}
+
+// A device-mapper device as operators know it: vg0-data, or luks-<uuid>
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq)]
+pub(crate) struct Dm {
+    pub name: String,
+    pub kind: DmType,
+    // LVM only: the volume group and logical volume
+    pub vg: Option<String>,
+    pub lv: Option<String>,
+    // LUKS only: the uuid of its header
+    pub luks_uuid: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+impl Dm {
+    // %{name, type, vg, lv, luks_uuid}
+    fn encode<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        rustler::types::map::map_new(env)
+            .map_put(atoms::name().to_term(env), self.name.as_str())?
+            .map_put(atoms::type_().to_term(env), self.kind.atom())?
+            .map_put(atoms::vg().to_term(env), self.vg.as_deref())?
+            .map_put(atoms::lv().to_term(env), self.lv.as_deref())?
+            .map_put(atoms::luks_uuid().to_term(env), self.luks_uuid.as_deref())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+impl Layer {
+    // %{name, type, vg, lv, luks_uuid} of a device-mapper device, nil for another
+    fn encode_dm<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        #[cfg(target_os = "linux")]
+        if let Some(dm) = &self.dm {
+            return dm.encode(env);
+        }
+        Ok(rustler::types::atom::nil().to_term(env))
+    }
+}
+
+// The bus a disk is attached by
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Bus {
+    Usb,
+    Nvme,
+    Sata,
+    #[cfg(target_os = "linux")]
+    Virtio,
+    Mmc,
+    #[cfg(any(target_os = "linux", windows))]
+    Scsi,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+impl Bus {
+    fn atom(self) -> Atom {
+        match self {
+            Bus::Usb => atoms::usb(),
+            Bus::Nvme => atoms::nvme(),
+            Bus::Sata => atoms::sata(),
+            #[cfg(target_os = "linux")]
+            Bus::Virtio => atoms::virtio(),
+            Bus::Mmc => atoms::mmc(),
+            #[cfg(any(target_os = "linux", windows))]
+            Bus::Scsi => atoms::scsi(),
+        }
+    }
+}
+
+// The protocol a disk is reached by, finer than its bus: SAS and iSCSI
+// disks are SCSI disks too, and USB and virtio carry SCSI or their own
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Transport {
+    Nvme,
+    Sata,
+    Sas,
+    Scsi,
+    #[cfg(any(target_os = "linux", windows))]
+    Iscsi,
+    FibreChannel,
+    #[cfg(target_os = "linux")]
+    Virtio,
+    Usb,
+    Mmc,
+    // Virtual disks, and those that don't tell
+    #[default]
+    Unknown,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+impl Transport {
+    fn atom(self) -> Atom {
+        match self {
+            Transport::Nvme => atoms::nvme(),
+            Transport::Sata => atoms::sata(),
+            Transport::Sas => atoms::sas(),
+            Transport::Scsi => atoms::scsi(),
+            #[cfg(any(target_os = "linux", windows))]
+            Transport::Iscsi => atoms::iscsi(),
+            Transport::FibreChannel => atoms::fc(),
+            #[cfg(target_os = "linux")]
+            Transport::Virtio => atoms::virtio(),
+            Transport::Usb => atoms::usb(),
+            Transport::Mmc => atoms::mmc(),
+            Transport::Unknown => atoms::unknown(),
+        }
+    }
+}
+
+// What a disk tells of itself, the strings trimmed, None if blank
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Info {
+    pub model: Option<String>,
+    pub vendor: Option<String>,
+    pub serial: Option<String>,
+    pub firmware: Option<String>,
+    // Removable media, or attached by USB
+    pub removable: Option<bool>,
+    pub bus: Option<Bus>,
+    pub transport: Transport,
+    pub size: Option<u64>,
+    pub rotational: Option<bool>,
+    pub logical_sector_size: Option<u32>,
+    pub physical_sector_size: Option<u32>,
+    // The device was not to be opened to ask it its sector sizes
+    pub permission_limited: bool,
+}
+
+// Helper: `value` trimmed, None if that leaves nothing
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn trimmed(value: &str) -> Option<String> {
+    let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+pub(crate) struct Backing {
+    pub mount_point: String,
+    pub source: String,
+    pub fs_type: String,
+    // Linux only: the mount options, as only there do they tell of discard
+    #[cfg(target_os = "linux")]
+    pub options: Vec<String>,
+    pub chain: Vec<Layer>,
+}
+
+// Whether a disk takes TRIM/discard, and the filesystem on it uses it
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Discard {
+    pub device_supports: Option<bool>,
+    pub mount_option_enabled: Option<bool>,
+    pub fstrim_capable: Option<bool>,
+}
+
+// A partition of a disk, where it starts and its size in bytes, and the
+// mount points of the filesystem on it
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+#[derive(Debug, PartialEq)]
+pub(crate) struct Partition {
+    pub number: u32,
+    pub name: String,
+    pub device: String,
+    pub start: u64,
+    pub size: u64,
+    // The type GUID of a GPT partition, in lowercase, or the type id of an
+    // MBR one, as "0x83"
+    pub type_id: Option<String>,
+    pub mounted_at: Vec<String>,
+}
+
+// Helper: The devices beneath the filesystem holding `path`, None if it is
+// on none
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+pub(crate) fn resolve(path: &Path) -> io::Result<Option<Backing>> {
+    #[cfg(target_os = "linux")]
+    {
+        linux::resolve(path)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        macos::resolve(path)
+    }
+    #[cfg(windows)]
+    {
+        windows::resolve(path)
+    }
+}
+
+// Helper: The devices beneath the filesystem mounted as `mount`, None if it
+// is on none
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn resolve_mount(mount: &Mount) -> Option<Backing> {
+    #[cfg(target_os = "linux")]
+    {
+        linux::resolve_mount(mount)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        macos::resolve_mount(mount)
+    }
+    #[cfg(windows)]
+    {
+        windows::resolve(Path::new(&mount.mount_point))
+            .ok()
+            .flatten()
+    }
+}
+
+// Helper: What the disk `disk` tells of itself
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn info(disk: &Layer) -> Info {
+    #[cfg(target_os = "linux")]
+    {
+        linux::info(disk)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        macos::info(disk)
+    }
+    #[cfg(windows)]
+    {
+        windows::info(disk)
+    }
+}
+
+// Helper: Whether the disk `disk` of `backing` takes discard, and the
+// filesystem of `backing` uses it
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn discard(backing: &Backing, disk: &Layer) -> Discard {
+    #[cfg(target_os = "linux")]
+    {
+        linux::discard(backing, disk)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        macos::discard(backing, disk)
+    }
+    #[cfg(windows)]
+    {
+        windows::discard(backing, disk)
+    }
+}
+
+// Helper: The partitions of the disk `disk`, in the order they are on it,
+// joined against `mounts`
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn partitions(disk: &Layer, mounts: &[Mount]) -> io::Result<Vec<Partition>> {
+    #[cfg(target_os = "linux")]
+    {
+        linux::partitions(disk, mounts)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        macos::partitions(disk, mounts)
+    }
+    #[cfg(windows)]
+    {
+        windows::partitions(disk, mounts)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+impl Backing {
+    // The devices with none beneath them
+    pub(crate) fn disks(&self) -> impl Iterator<Item = &Layer> {
+        self.chain.iter().filter(|layer| layer.lower.is_empty())
+    }
+
+    // Linux only: The disks beneath `layer`, each once
+    #[cfg(target_os = "linux")]
+    fn disks_beneath<'a>(&'a self, layer: &'a Layer) -> Vec<&'a Layer> {
+        let mut disks: Vec<&Layer> = Vec::new();
+        let mut queue = std::collections::VecDeque::from([layer]);
+        while let Some(layer) = queue.pop_front() {
+            if layer.lower.is_empty() {
+                if !disks.iter().any(|disk| disk.name == layer.name) {
+                    disks.push(layer);
+                }
+                continue;
+            }
+            queue.extend(
+                layer
+                    .lower
+                    .iter()
+                    .filter_map(|name| self.chain.iter().find(|lower| &lower.name == name)),
+            );
+        }
+        disks
+    }
+
+    // [%{name, device, luks_uuid, disks}] of each dm-crypt device of the
+    // chain, with the disks it encrypts; none off Linux
+    fn encode_encryption<'a>(&self, env: Env<'a>) -> NifResult<Vec<Term<'a>>> {
+        #[cfg(target_os = "linux")]
+        {
+            self.chain
+                .iter()
+                .filter_map(|layer| {
+                    let dm = layer.dm.as_ref().filter(|dm| dm.kind == DmType::Crypt)?;
+                    Some((layer, dm))
+                })
+                .map(|(layer, dm)| {
+                    let disks: Vec<&str> = self
+                        .disks_beneath(layer)
+                        .iter()
+                        .map(|disk| disk.device.as_str())
+                        .collect();
+                    rustler::types::map::map_new(env)
+                        .map_put(atoms::name().to_term(env), layer.name.as_str())?
+                        .map_put(atoms::device().to_term(env), layer.device.as_str())?
+                        .map_put(atoms::luks_uuid().to_term(env), dm.luks_uuid.as_deref())?
+                        .map_put(atoms::disks().to_term(env), disks)
+                })
+                .collect()
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = env;
+            Ok(Vec::new())
+        }
+    }
+
+    fn encode<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        let chain = self
+            .chain
+            .iter()
+            .map(|layer| {
+                rustler::types::map::map_new(env)
+                    .map_put(atoms::name().to_term(env), layer.name.as_str())?
+                    .map_put(atoms::device().to_term(env), layer.device.as_str())?
+                    .map_put(atoms::kind().to_term(env), layer.kind.atom())?
+                    .map_put(atoms::lower().to_term(env), &layer.lower)?
+                    .map_put(atoms::dm().to_term(env), layer.encode_dm(env)?)
+            })
+            .collect::<NifResult<Vec<Term>>>()?;
+        let disks: Vec<&str> = self.disks().map(|layer| layer.device.as_str()).collect();
+        let encryption = self.encode_encryption(env)?;
+        // Whether the chain is encrypted is told on Linux only
+        let encrypted = cfg!(target_os = "linux").then_some(!encryption.is_empty());
+        #[cfg(target_os = "linux")]
+        let fscrypt = linux::fscrypt(Path::new(&self.mount_point));
+        #[cfg(not(target_os = "linux"))]
+        let fscrypt: Option<bool> = None;
+        rustler::types::map::map_new(env)
+            .map_put(atoms::mount_point().to_term(env), self.mount_point.as_str())?
+            .map_put(atoms::source().to_term(env), self.source.as_str())?
+            .map_put(atoms::chain().to_term(env), chain)?
+            .map_put(atoms::disks().to_term(env), disks)?
+            .map_put(atoms::encrypted().to_term(env), encrypted)?
+            .map_put(atoms::encryption().to_term(env), encryption)?
+            .map_put(atoms::fscrypt().to_term(env), fscrypt)
+    }
+
+    // [%{name, device, model, vendor, serial, firmware, is_removable, bus,
+    // transport, size_bytes, rotational, logical_sector_size, physical_sector_size, permission_limited,
+    // discard}] of each disk
+    fn encode_info<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        self.disks()
+            .map(|disk| {
+                let info = info(disk);
+                let discard = discard(self, disk);
+                let discard = rustler::types::map::map_new(env)
+                    .map_put(
+                        atoms::device_supports().to_term(env),
+                        discard.device_supports,
+                    )?
+                    .map_put(
+                        atoms::mount_option_enabled().to_term(env),
+                        discard.mount_option_enabled,
+                    )?
+                    .map_put(atoms::fstrim_capable().to_term(env), discard.fstrim_capable)?;
+                rustler::types::map::map_new(env)
+                    .map_put(atoms::name().to_term(env), disk.name.as_str())?
+                    .map_put(atoms::device().to_term(env), disk.device.as_str())?
+                    .map_put(atoms::model().to_term(env), info.model)?
+                    .map_put(atoms::vendor().to_term(env), info.vendor)?
+                    .map_put(atoms::serial().to_term(env), info.serial)?
+                    .map_put(atoms::firmware().to_term(env), info.firmware)?
+                    .map_put(atoms::is_removable().to_term(env), info.removable)?
+                    .map_put(atoms::bus().to_term(env), info.bus.map(Bus::atom))?
+                    .map_put(atoms::transport().to_term(env), info.transport.atom())?
+                    .map_put(atoms::size_bytes().to_term(env), info.size)?
+                    .map_put(atoms::rotational().to_term(env), info.rotational)?
+                    .map_put(
+                        atoms::logical_sector_size().to_term(env),
+                        info.logical_sector_size,
+                    )?
+                    .map_put(
+                        atoms::physical_sector_size().to_term(env),
+                        info.physical_sector_size,
+                    )?
+                    .map_put(
+                        atoms::permission_limited().to_term(env),
+                        info.permission_limited,
+                    )?
+                    .map_put(atoms::discard().to_term(env), discard)
+            })
+            .collect::<NifResult<Vec<Term>>>()
+            .map(|disks| disks.encode(env))
+    }
+
+    // {:ok, [%{name, device, size_bytes, partitions: [%{number, name,
+    // device, start, size, type_guid_or_id, mounted_at}]}]} of each disk, or
+    // {:error, posix, %{errno, errstr}}
+    fn encode_partitions<'a>(&self, env: Env<'a>, mounts: &[Mount]) -> NifResult<Term<'a>> {
+        let mut disks = Vec::new();
+        for disk in self.disks() {
+            let partitions = match partitions(disk, mounts) {
+                Ok(partitions) => partitions,
+                Err(err) => return make_errno_error_tuple(env, posix::atom(env, &err), err),
+            };
+            let partitions = partitions
+                .iter()
+                .map(|partition| {
+                    rustler::types::map::map_new(env)
+                        .map_put(atoms::number().to_term(env), partition.number)?
+                        .map_put(atoms::name().to_term(env), partition.name.as_str())?
+                        .map_put(atoms::device().to_term(env), partition.device.as_str())?
+                        .map_put(atoms::start().to_term(env), partition.start)?
+                        .map_put(atoms::size().to_term(env), partition.size)?
+                        .map_put(
+                            atoms::type_guid_or_id().to_term(env),
+                            partition.type_id.as_deref(),
+                        )?
+                        .map_put(atoms::mounted_at().to_term(env), &partition.mounted_at)
+                })
+                .collect::<NifResult<Vec<Term>>>()?;
+            disks.push(
+                rustler::types::map::map_new(env)
+                    .map_put(atoms::name().to_term(env), disk.name.as_str())?
+                    .map_put(atoms::device().to_term(env), disk.device.as_str())?
+                    .map_put(atoms::size_bytes().to_term(env), info(disk).size)?
+                    .map_put(atoms::partitions().to_term(env), partitions)?,
+            );
+        }
+        Ok((atoms::ok(), disks).encode(env))
+    }
+}
+
+// Whether the media of the removable disk `path` is on, or is the device
+// node of, are in it and mounted
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) fn media_ready(path: &Path) -> io::Result<Readiness> {
+    use std::os::unix::fs::FileTypeExt;
+    let metadata = match std::fs::metadata(path) {
+        // The node of a card comes and goes with it
+        Err(err) if err.kind() == io::ErrorKind::NotFound && path.starts_with("/dev") => {
+            return Ok(Readiness::NoMedia)
+        }
+        metadata => metadata?,
+    };
+    let (backing, mounted) = if metadata.file_type().is_block_device() {
+        let node = Mount {
+            mount_id: None,
+            parent_id: None,
+            device: String::new(),
+            root: None,
+            mount_point: String::new(),
+            fs_type: String::new(),
+            source: path.to_string_lossy().into_owned(),
+            options: Vec::new(),
+            read_only: false,
+        };
+        let backing = resolve_mount(&node);
+        let name = backing.as_ref().and_then(|backing| backing.chain.first());
+        let name = name.map(|layer| layer.name.clone());
+        // Mounted if a mount is on the node, or on a device over it
+        let mounted = name.is_some_and(|name| {
+            crate::mounts::list().is_ok_and(|mounts| {
+                mounts
+                    .iter()
+                    .filter_map(resolve_mount)
+                    .any(|mounted| mounted.chain.iter().any(|layer| layer.name == name))
+            })
+        });
+        (backing, mounted)
+    } else {
+        (resolve(path)?, true)
+    };
+    let removable = backing.is_some_and(|backing| {
+        backing
+            .disks()
+            .any(|disk| info(disk).removable == Some(true))
+    });
+    Ok(match (removable, mounted) {
+        (false, _) => Readiness::NotRemovable,
+        (true, true) => Readiness::Ready,
+        (true, false) => Readiness::NoMedia,
+    })
+}
+
+// Helper: `map` with the device_info of the disks beneath `mount` put, nil
+// if it is on none
+pub(crate) fn put<'a>(env: Env<'a>, map: Term<'a>, mount: &Mount) -> NifResult<Term<'a>> {
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    let info = match resolve_mount(mount) {
+        Some(backing) => backing.encode_info(env)?,
+        None => rustler::types::atom::nil().to_term(env),
+    };
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    let info = {
+        let _ = mount;
+        rustler::types::atom::nil().to_term(env)
+    };
+    map.map_put(atoms::device_info().to_term(env), info)
+}
+
+// Helper: `map` with the dm of the device-mapper device `mount` is on put,
+// or as it is if on none
+pub(crate) fn put_dm<'a>(env: Env<'a>, map: Term<'a>, mount: &Mount) -> NifResult<Term<'a>> {
+    #[cfg(target_os = "linux")]
+    if let Some(dm) = linux::mount_dm(mount) {
+        return map.map_put(atoms::dm().to_term(env), dm.encode(env)?);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (env, mount);
+    Ok(map)
+}
+
+// {:ok, %{name, device, level, array_state, raid_disks, active_disks,
+// degraded, members, sync}} of the md array `path` is the device node of
+// or the filesystem holding it is on, or {:error, :not_raid}
+#[rustler::nif(schedule = "DirtyIo")]
+fn raid_info_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "linux")]
+    {
+        let name = match linux::md_array(&path) {
+            Ok(Some(name)) => name,
+            Ok(None) => return make_error_tuple(env, atoms::not_raid()),
+            Err(err) => return make_errno_error_tuple(env, posix::atom(env, &err), err),
+        };
+        let mdstat = match std::fs::read_to_string("/proc/mdstat") {
+            Ok(mdstat) => mdstat,
+            Err(err) => return make_errno_error_tuple(env, posix::atom(env, &err), err),
+        };
+        let Some(mut array) = md::parse(&mdstat)
+            .into_iter()
+            .find(|array| array.name == name)
+        else {
+            return make_error_tuple(env, atoms::not_raid());
+        };
+        md::read_sysfs(Path::new("/sys"), &mut array);
+        Ok((atoms::ok(), array.encode(env)?).encode(env))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+// {:ok, %{mount_point, source, chain, disks, encrypted, encryption,
+// fscrypt}} of the filesystem holding
+// `path`, or {:error, :not_block_device} if it is on none
+#[rustler::nif(schedule = "DirtyIo")]
+fn backing_device_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    match resolve(&path) {
+        Ok(Some(backing)) => Ok((atoms::ok(), backing.encode(env)?).encode(env)),
+        Ok(None) => make_error_tuple(env, atoms::not_block_device()),
+        Err(err) => make_errno_error_tuple(env, posix::atom(env, &err), err),
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+// {:ok, [%{name, device, model, vendor, serial, firmware, is_removable, bus,
+// transport, size_bytes, rotational, logical_sector_size, physical_sector_size, permission_limited, discard}]}
+// of each disk beneath the filesystem holding `path`, or the error of
+// backing_device
+#[rustler::nif(schedule = "DirtyIo")]
+fn device_info_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    match resolve(&path) {
+        Ok(Some(backing)) => Ok((atoms::ok(), backing.encode_info(env)?).encode(env)),
+        Ok(None) => make_error_tuple(env, atoms::not_block_device()),
+        Err(err) => make_errno_error_tuple(env, posix::atom(env, &err), err),
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+// {:ok, [%{name, device, size_bytes, partitions}]} of each disk beneath the
+// filesystem holding `path`, or the error of backing_device
+#[rustler::nif(schedule = "DirtyIo")]
+fn partitions_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    {
+        let backing = match resolve(&path) {
+            Ok(Some(backing)) => backing,
+            Ok(None) => return make_error_tuple(env, atoms::not_block_device()),
+            Err(err) => return make_errno_error_tuple(env, posix::atom(env, &err), err),
+        };
+        match crate::mounts::list() {
+            Ok(mounts) => backing.encode_partitions(env, &mounts),
+            Err(err) => make_errno_error_tuple(env, posix::atom(env, &err), err),
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
@@ -0,0 +1,521 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The disks beneath a volume on Windows: IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS
+// on the volume, opened without any access, which needs no rights, gives
+// the disk numbers of its extents, as \\.\PhysicalDriveN. A spanned or
+// striped dynamic volume has an extent on each of its disks, and a volume
+// with more of them than the first call has room for fails with
+// ERROR_MORE_DATA, having filled in how many there are.
+//
+// What a disk tells of itself is the STORAGE_DEVICE_DESCRIPTOR of its
+// StorageDeviceProperty, which IOCTL_STORAGE_QUERY_PROPERTY gets without
+// any access either: its vendor, product, revision and serial number are
+// strings at offsets into it, 0 for those it lacks. It also has the
+// BusType the disk is attached by, its transport too, and whether its
+// media are removable; USB disks are counted removable too, as the hard
+// disks among them have fixed media. Its StorageAccessAlignmentProperty has the logical and
+// physical sector sizes, its StorageDeviceSeekPenaltyProperty whether it
+// is rotational, and its StorageDeviceTrimProperty whether it takes TRIM;
+// IOCTL_DISK_GET_DRIVE_GEOMETRY_EX has its size. NTFS and ReFS send TRIM as they free blocks unless disabled for
+// the whole system, which no volume tells, and Optimize-Volume -ReTrim
+// trims the free space of a volume on such a disk in one go.
+//
+// The I/O counters of a disk come from IOCTL_DISK_PERFORMANCE, which fails
+// with ERROR_INVALID_FUNCTION while they are disabled with diskperf -N,
+// and whether it predicts its failure from IOCTL_STORAGE_PREDICT_FAILURE,
+// which disks that don't do SMART, NVMe ones among them, fail.
+//
+// The partitions of a disk are in the layout IOCTL_DISK_GET_DRIVE_LAYOUT_EX
+// gives, which also takes no access: a GPT partition with its type GUID,
+// an MBR one with its type id, and the unused entries of an MBR table and
+// the extended partition holding logical ones numbered 0, which are
+// skipped. Each is \\.\HarddiskNPartitionM, and the volume on it the one
+// with an extent on the disk starting where it does.
+
+use super::{trimmed, Backing, Bus, Discard, Info, Kind, Layer, Partition, Transport};
+use crate::mounts::Mount;
+use crate::{volume_device, volume_root};
+use std::io;
+use std::path::Path;
+use widestring::WideCString;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{CloseHandle, ERROR_INSUFFICIENT_BUFFER, ERROR_MORE_DATA, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    BusTypeAta, BusTypeFibre, BusTypeMmc, BusTypeNvme, BusTypeSas, BusTypeSata, BusTypeScsi,
+    BusTypeSd, BusTypeUsb, BusTypeiScsi, CreateFileW, GetVolumeInformationW,
+    FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ, FILE_SHARE_WRITE,
+    IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS, OPEN_EXISTING, STORAGE_BUS_TYPE,
+};
+use windows::Win32::System::Ioctl::{
+    PropertyStandardQuery, StorageAccessAlignmentProperty, StorageDeviceProperty,
+    StorageDeviceSeekPenaltyProperty, StorageDeviceTrimProperty, DEVICE_SEEK_PENALTY_DESCRIPTOR,
+    DEVICE_TRIM_DESCRIPTOR, DISK_EXTENT, DISK_GEOMETRY_EX, DISK_PERFORMANCE,
+    DRIVE_LAYOUT_INFORMATION_EX, IOCTL_DISK_GET_DRIVE_GEOMETRY_EX, IOCTL_DISK_GET_DRIVE_LAYOUT_EX,
+    IOCTL_DISK_PERFORMANCE, IOCTL_STORAGE_QUERY_PROPERTY, PARTITION_INFORMATION_EX,
+    PARTITION_STYLE_GPT, PARTITION_STYLE_MBR, STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR,
+    STORAGE_DEVICE_DESCRIPTOR, STORAGE_PROPERTY_ID, STORAGE_PROPERTY_QUERY, VOLUME_DISK_EXTENTS,
+};
+use windows::Win32::System::IO::DeviceIoControl;
+#[cfg(feature = "device_health")]
+use {
+    super::health::Health,
+    windows::Win32::System::Ioctl::{IOCTL_STORAGE_PREDICT_FAILURE, STORAGE_PREDICT_FAILURE},
+};
+
+// Closes the handle it holds once dropped
+struct Handle(HANDLE);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.0) };
+    }
+}
+
+// Helper: A WinAPI error as the io::Error of its code
+fn winapi_error(e: windows::core::Error) -> io::Error {
+    io::Error::from_raw_os_error(e.code().0 & 0xFFFF)
+}
+
+pub(super) fn resolve(path: &Path) -> io::Result<Option<Backing>> {
+    std::fs::metadata(path)?;
+    let code_error = |code: u32| io::Error::from_raw_os_error(code as i32);
+    let wide = WideCString::from_os_str(path.as_os_str())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let root = volume_root(&wide).map_err(code_error)?;
+    // Network shares and subst drives have no volume
+    let Ok(volume) = volume_device(&root) else {
+        return Ok(None);
+    };
+    let disks = disk_numbers(&volume)?;
+    let volume_name = volume.to_string_lossy();
+    let mut chain = vec![Layer {
+        name: volume_name.trim_start_matches("\\\\?\\").to_string(),
+        device: volume_name.clone(),
+        kind: Kind::Volume,
+        lower: disks
+            .iter()
+            .map(|disk| format!("PhysicalDrive{disk}"))
+            .collect(),
+    }];
+    chain.extend(disks.iter().map(|disk| Layer {
+        name: format!("PhysicalDrive{disk}"),
+        device: format!("\\\\.\\PhysicalDrive{disk}"),
+        kind: Kind::Disk,
+        lower: Vec::new(),
+    }));
+    Ok(Some(Backing {
+        mount_point: root.to_string_lossy(),
+        source: volume_name,
+        fs_type: fs_type(&root),
+        chain,
+    }))
+}
+
+// Helper: The name of the filesystem on the volume mounted at `root`, as
+// "NTFS", empty if it has none
+fn fs_type(root: &WideCString) -> String {
+    let mut fs_name = [0u16; 32];
+    let info = unsafe {
+        GetVolumeInformationW(
+            PCWSTR::from_raw(root.as_ptr()),
+            None,
+            None,
+            None,
+            None,
+            Some(&mut fs_name),
+        )
+    };
+    let end = fs_name
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(fs_name.len());
+    match info {
+        Ok(()) => String::from_utf16_lossy(&fs_name[..end]),
+        Err(_) => String::new(),
+    }
+}
+
+// Helper: Open the device `device` without any access, for queries
+fn open(device: &WideCString) -> io::Result<Handle> {
+    unsafe {
+        CreateFileW(
+            PCWSTR::from_raw(device.as_ptr()),
+            0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            None,
+        )
+    }
+    .map(Handle)
+    .map_err(winapi_error)
+}
+
+// Helper: The descriptor of the storage property `property` of the device
+// open as `handle`, in u64s for its alignment
+fn query_property(handle: &Handle, property: STORAGE_PROPERTY_ID) -> io::Result<Vec<u64>> {
+    let query = STORAGE_PROPERTY_QUERY {
+        PropertyId: property,
+        QueryType: PropertyStandardQuery,
+        AdditionalParameters: [0],
+    };
+    let mut buffer = vec![0u64; 512];
+    let mut returned = 0u32;
+    unsafe {
+        DeviceIoControl(
+            handle.0,
+            IOCTL_STORAGE_QUERY_PROPERTY,
+            Some((&query as *const STORAGE_PROPERTY_QUERY).cast()),
+            std::mem::size_of::<STORAGE_PROPERTY_QUERY>() as u32,
+            Some(buffer.as_mut_ptr().cast()),
+            (buffer.len() * 8) as u32,
+            Some(&mut returned),
+            None,
+        )
+    }
+    .map_err(winapi_error)?;
+    Ok(buffer)
+}
+
+// Helper: The bus of the STORAGE_BUS_TYPE `bus_type`
+fn bus_of(bus_type: STORAGE_BUS_TYPE) -> Option<Bus> {
+    [
+        (BusTypeUsb, Bus::Usb),
+        (BusTypeNvme, Bus::Nvme),
+        (BusTypeAta, Bus::Sata),
+        (BusTypeSata, Bus::Sata),
+        (BusTypeSd, Bus::Mmc),
+        (BusTypeMmc, Bus::Mmc),
+        (BusTypeScsi, Bus::Scsi),
+        (BusTypeSas, Bus::Scsi),
+    ]
+    .into_iter()
+    .find_map(|(known, bus)| (known == bus_type).then_some(bus))
+}
+
+// The transports of disks by their BusType; virtual disks (VHDs, Storage
+// Spaces) and RAID controllers hide theirs
+const TRANSPORTS: [(STORAGE_BUS_TYPE, Transport); 10] = [
+    (BusTypeNvme, Transport::Nvme),
+    (BusTypeAta, Transport::Sata),
+    (BusTypeSata, Transport::Sata),
+    (BusTypeSas, Transport::Sas),
+    (BusTypeScsi, Transport::Scsi),
+    (BusTypeiScsi, Transport::Iscsi),
+    (BusTypeFibre, Transport::FibreChannel),
+    (BusTypeUsb, Transport::Usb),
+    (BusTypeSd, Transport::Mmc),
+    (BusTypeMmc, Transport::Mmc),
+];
+
+pub(super) fn info(disk: &Layer) -> Info {
+    let handle = match WideCString::from_str(&disk.device)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+        .and_then(|device| open(&device))
+    {
+        Ok(handle) => handle,
+        Err(err) => {
+            return Info {
+                permission_limited: err.kind() == io::ErrorKind::PermissionDenied,
+                ..Info::default()
+            }
+        }
+    };
+    let alignment = query_property(&handle, StorageAccessAlignmentProperty).ok();
+    let alignment = alignment
+        .as_ref()
+        .map(|buffer| unsafe { &*(buffer.as_ptr() as *const STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR) });
+    let seek_penalty = query_property(&handle, StorageDeviceSeekPenaltyProperty)
+        .ok()
+        .map(|buffer| {
+            unsafe { &*(buffer.as_ptr() as *const DEVICE_SEEK_PENALTY_DESCRIPTOR) }
+                .IncursSeekPenalty
+        });
+    let drive = Info {
+        size: disk_size(&handle).ok(),
+        rotational: seek_penalty,
+        logical_sector_size: alignment.map(|alignment| alignment.BytesPerLogicalSector),
+        physical_sector_size: alignment.map(|alignment| alignment.BytesPerPhysicalSector),
+        ..Info::default()
+    };
+    let Ok(buffer) = query_property(&handle, StorageDeviceProperty) else {
+        return drive;
+    };
+    let bytes: &[u8] =
+        unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast(), buffer.len() * 8) };
+    let descriptor = unsafe { &*(buffer.as_ptr() as *const STORAGE_DEVICE_DESCRIPTOR) };
+    let field = |offset: u32| {
+        let rest = bytes.get(offset as usize..).filter(|_| offset != 0)?;
+        let end = rest
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(rest.len());
+        trimmed(&String::from_utf8_lossy(&rest[..end]))
+    };
+    let bus = bus_of(descriptor.BusType);
+    Info {
+        model: field(descriptor.ProductIdOffset),
+        vendor: field(descriptor.VendorIdOffset),
+        serial: field(descriptor.SerialNumberOffset),
+        firmware: field(descriptor.ProductRevisionOffset),
+        removable: Some(descriptor.RemovableMedia || bus == Some(Bus::Usb)),
+        bus,
+        transport: TRANSPORTS
+            .iter()
+            .find(|(known, _)| *known == descriptor.BusType)
+            .map_or(Transport::Unknown, |(_, transport)| *transport),
+        ..drive
+    }
+}
+
+// Whether the disk beneath the volume `volume`, as \\?\Volume{GUID}, is
+// attached by USB, and whether its media are removable
+pub(crate) fn attachment(volume: &WideCString) -> io::Result<(bool, bool)> {
+    let buffer = query_property(&open(volume)?, StorageDeviceProperty)?;
+    let descriptor = unsafe { &*(buffer.as_ptr() as *const STORAGE_DEVICE_DESCRIPTOR) };
+    Ok((descriptor.BusType == BusTypeUsb, descriptor.RemovableMedia))
+}
+
+// Helper: The size in bytes of the disk open as `handle`
+fn disk_size(handle: &Handle) -> io::Result<u64> {
+    let mut geometry = DISK_GEOMETRY_EX::default();
+    let mut returned = 0u32;
+    unsafe {
+        DeviceIoControl(
+            handle.0,
+            IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
+            None,
+            0,
+            Some((&mut geometry as *mut DISK_GEOMETRY_EX).cast()),
+            std::mem::size_of::<DISK_GEOMETRY_EX>() as u32,
+            Some(&mut returned),
+            None,
+        )
+    }
+    .map_err(winapi_error)?;
+    Ok(geometry.DiskSize.max(0) as u64)
+}
+
+pub(super) fn discard(backing: &Backing, disk: &Layer) -> Discard {
+    let supports = WideCString::from_str(&disk.device)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+        .and_then(|device| open(&device))
+        .and_then(|handle| query_property(&handle, StorageDeviceTrimProperty))
+        .ok()
+        .map(|buffer| unsafe { &*(buffer.as_ptr() as *const DEVICE_TRIM_DESCRIPTOR) }.TrimEnabled);
+    Discard {
+        device_supports: supports,
+        mount_option_enabled: None,
+        fstrim_capable: supports
+            .map(|supports| supports && matches!(backing.fs_type.as_str(), "NTFS" | "ReFS")),
+    }
+}
+
+#[cfg(feature = "device_health")]
+pub(super) fn health(disk: &Layer) -> Health {
+    let failure_predicted = WideCString::from_str(&disk.device)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+        .and_then(|device| open(&device))
+        .and_then(|handle| predict_failure(&handle))
+        .ok();
+    Health {
+        failure_predicted,
+        ..Health::default()
+    }
+}
+
+// Helper: Whether the disk open as `handle` predicts its failure
+#[cfg(feature = "device_health")]
+fn predict_failure(handle: &Handle) -> io::Result<bool> {
+    let mut prediction = STORAGE_PREDICT_FAILURE::default();
+    let mut returned = 0u32;
+    unsafe {
+        DeviceIoControl(
+            handle.0,
+            IOCTL_STORAGE_PREDICT_FAILURE,
+            None,
+            0,
+            Some((&mut prediction as *mut STORAGE_PREDICT_FAILURE).cast()),
+            std::mem::size_of::<STORAGE_PREDICT_FAILURE>() as u32,
+            Some(&mut returned),
+            None,
+        )
+    }
+    .map_err(winapi_error)?;
+    Ok(prediction.PredictFailure != 0)
+}
+
+pub(crate) fn performance(disk: &Layer) -> io::Result<DISK_PERFORMANCE> {
+    let device = WideCString::from_str(&disk.device)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let handle = open(&device)?;
+    let mut performance = DISK_PERFORMANCE::default();
+    let mut returned = 0u32;
+    unsafe {
+        DeviceIoControl(
+            handle.0,
+            IOCTL_DISK_PERFORMANCE,
+            None,
+            0,
+            Some((&mut performance as *mut DISK_PERFORMANCE).cast()),
+            std::mem::size_of::<DISK_PERFORMANCE>() as u32,
+            Some(&mut returned),
+            None,
+        )
+    }
+    .map_err(winapi_error)?;
+    Ok(performance)
+}
+
+pub(super) fn partitions(disk: &Layer, mounts: &[Mount]) -> io::Result<Vec<Partition>> {
+    let number: u32 = disk
+        .name
+        .strip_prefix("PhysicalDrive")
+        .and_then(|number| number.parse().ok())
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let device = WideCString::from_str(&disk.device)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let layout = drive_layout(&open(&device)?)?;
+    // Where the extent on this disk of the volume of each mount starts
+    let starts: Vec<(i64, &str)> = mounts
+        .iter()
+        .filter_map(|mount| {
+            let volume = WideCString::from_str(mount.source.trim_end_matches('\\')).ok()?;
+            let extents = extents(&volume).ok()?;
+            let mount_point = mount.mount_point.as_str();
+            Some(
+                extents
+                    .into_iter()
+                    .filter(|(disk, _)| *disk == number)
+                    .map(move |(_, start)| (start, mount_point)),
+            )
+        })
+        .flatten()
+        .collect();
+    let mut partitions: Vec<Partition> = layout
+        .iter()
+        .filter(|entry| entry.PartitionNumber != 0)
+        .map(|entry| {
+            let type_id = if entry.PartitionStyle == PARTITION_STYLE_GPT {
+                let guid = unsafe { entry.Anonymous.Gpt.PartitionType };
+                Some(format!("{guid:?}").to_ascii_lowercase())
+            } else if entry.PartitionStyle == PARTITION_STYLE_MBR {
+                Some(format!("0x{:02x}", unsafe {
+                    entry.Anonymous.Mbr.PartitionType
+                }))
+            } else {
+                None
+            };
+            let name = format!("Harddisk{number}Partition{}", entry.PartitionNumber);
+            Partition {
+                number: entry.PartitionNumber,
+                device: format!("\\\\.\\{name}"),
+                name,
+                start: entry.StartingOffset.max(0) as u64,
+                size: entry.PartitionLength.max(0) as u64,
+                type_id,
+                mounted_at: starts
+                    .iter()
+                    .filter(|(start, _)| *start == entry.StartingOffset)
+                    .map(|(_, mount_point)| mount_point.to_string())
+                    .collect(),
+            }
+        })
+        .collect();
+    partitions.sort_by_key(|partition| partition.start);
+    Ok(partitions)
+}
+
+// Helper: The entries of the partition table of the disk open as `handle`
+fn drive_layout(handle: &Handle) -> io::Result<Vec<PARTITION_INFORMATION_EX>> {
+    let header = std::mem::offset_of!(DRIVE_LAYOUT_INFORMATION_EX, PartitionEntry);
+    // An MBR disk has at least 4 entries, a GPT disk room for 128
+    let mut slots = 16usize;
+    loop {
+        let size = header + slots * std::mem::size_of::<PARTITION_INFORMATION_EX>();
+        // In u64s, for the alignment of the entries
+        let mut buffer = vec![0u64; size.div_ceil(8)];
+        let mut returned = 0u32;
+        let result = unsafe {
+            DeviceIoControl(
+                handle.0,
+                IOCTL_DISK_GET_DRIVE_LAYOUT_EX,
+                None,
+                0,
+                Some(buffer.as_mut_ptr().cast()),
+                size as u32,
+                Some(&mut returned),
+                None,
+            )
+        };
+        match result {
+            Ok(()) => {
+                let layout = unsafe { &*(buffer.as_ptr() as *const DRIVE_LAYOUT_INFORMATION_EX) };
+                let count = (layout.PartitionCount as usize).min(slots);
+                let first = unsafe { buffer.as_ptr().cast::<u8>().add(header) }
+                    .cast::<PARTITION_INFORMATION_EX>();
+                return Ok((0..count).map(|i| unsafe { *first.add(i) }).collect());
+            }
+            // The layout doesn't say how many entries it has until it fits
+            Err(e)
+                if (e.code().0 & 0xFFFF) as u32 == ERROR_INSUFFICIENT_BUFFER.0 && slots < 1024 =>
+            {
+                slots *= 4;
+            }
+            Err(e) => return Err(winapi_error(e)),
+        }
+    }
+}
+
+// Helper: The numbers of the disks `volume` has extents on, each once
+fn disk_numbers(volume: &WideCString) -> io::Result<Vec<u32>> {
+    let mut disks: Vec<u32> = extents(volume)?.into_iter().map(|(disk, _)| disk).collect();
+    disks.sort_unstable();
+    disks.dedup();
+    Ok(disks)
+}
+
+// Helper: The disk number and starting offset of each extent of `volume`
+fn extents(volume: &WideCString) -> io::Result<Vec<(u32, i64)>> {
+    let handle = open(volume)?;
+    let header = std::mem::offset_of!(VOLUME_DISK_EXTENTS, Extents);
+    let mut slots = 4usize;
+    loop {
+        let size = header + slots * std::mem::size_of::<DISK_EXTENT>();
+        // In u64s, for the alignment of the extents
+        let mut buffer = vec![0u64; size.div_ceil(8)];
+        let mut returned = 0u32;
+        let result = unsafe {
+            DeviceIoControl(
+                handle.0,
+                IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS,
+                None,
+                0,
+                Some(buffer.as_mut_ptr().cast()),
+                size as u32,
+                Some(&mut returned),
+                None,
+            )
+        };
+        let extents = unsafe { &*(buffer.as_ptr() as *const VOLUME_DISK_EXTENTS) };
+        let count = extents.NumberOfDiskExtents as usize;
+        match result {
+            Ok(()) => {
+                let first =
+                    unsafe { buffer.as_ptr().cast::<u8>().add(header) }.cast::<DISK_EXTENT>();
+                return Ok((0..count.min(slots))
+                    .map(|i| {
+                        let extent = unsafe { &*first.add(i) };
+                        (extent.DiskNumber, extent.StartingOffset)
+                    })
+                    .collect());
+            }
+            Err(e) if (e.code().0 & 0xFFFF) as u32 == ERROR_MORE_DATA.0 && count > slots => {
+                slots = count;
+            }
+            Err(e) => return Err(winapi_error(e)),
+        }
+    }
+}
@@ -0,0 +1,309 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Per-device I/O counters from /proc/diskstats on Linux, as iostat reads
+// them: the reads, writes, sectors and milliseconds each device has seen
+// since boot, which, sampled twice, give its throughput and how busy it is.
+// diskstats of a path has those of the devices backing_device finds beneath
+// it, those nearer the mount first.
+//
+// Each line has the major and minor numbers and the name of a device,
+// followed by its counters, whose number grew with the kernel: 11 until
+// 4.18 added 4 for discards, and 5.5 2 for flushes; those a kernel lacks
+// are nil. Before 2.6.25 partitions had only 4: the reads and sectors read
+// and the writes and sectors written.
+//
+// The parser is portable so that fixtures of each format are tested on any
+// host.
+//
+// On Windows diskstats of a path has the counters IOCTL_DISK_PERFORMANCE
+// gives for each disk beneath it: the reads and writes, the bytes read and
+// written, the time spent at each and idle, and the queue depth. The
+// counters are off once disabled with diskperf -N, giving
+// {:error, :counters_disabled, hint}.
+
+use crate::{atoms, get_path_from_term, make_error_tuple, path_from_cstring};
+#[cfg(target_os = "linux")]
+use rustler::Atom;
+use rustler::{Env, NifResult, Term};
+#[cfg(windows)]
+use {
+    crate::make_error_tuple3, windows::Win32::Foundation::ERROR_INVALID_FUNCTION,
+    windows::Win32::System::Ioctl::DISK_PERFORMANCE,
+};
+#[cfg(any(target_os = "linux", windows))]
+use {
+    crate::{device, make_errno_error_tuple, posix},
+    rustler::Encoder,
+};
+
+// How many counters the kernel has at most: those of 5.5 and later
+#[cfg(any(target_os = "linux", test))]
+const COUNTERS: usize = 17;
+
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq)]
+pub(crate) struct DiskStats {
+    pub major: u32,
+    pub minor: u32,
+    pub name: String,
+    // In the order of /proc/diskstats, None for those the kernel lacks
+    pub counters: [Option<u64>; COUNTERS],
+}
+
+// Helper: The keys of the counters, in the order of /proc/diskstats
+#[cfg(target_os = "linux")]
+fn keys() -> [Atom; COUNTERS] {
+    [
+        atoms::reads_completed(),
+        atoms::reads_merged(),
+        atoms::sectors_read(),
+        atoms::read_time_ms(),
+        atoms::writes_completed(),
+        atoms::writes_merged(),
+        atoms::sectors_written(),
+        atoms::write_time_ms(),
+        atoms::ios_in_progress(),
+        atoms::io_time_ms(),
+        atoms::weighted_io_time_ms(),
+        atoms::discards_completed(),
+        atoms::discards_merged(),
+        atoms::sectors_discarded(),
+        atoms::discard_time_ms(),
+        atoms::flushes_completed(),
+        atoms::flush_time_ms(),
+    ]
+}
+
+// Parse the contents of /proc/diskstats, skipping lines that don't parse
+#[cfg(any(target_os = "linux", test))]
+pub(crate) fn parse(text: &str) -> Vec<DiskStats> {
+    text.lines().filter_map(parse_line).collect()
+}
+
+#[cfg(any(target_os = "linux", test))]
+fn parse_line(line: &str) -> Option<DiskStats> {
+    let mut fields = line.split_whitespace();
+    let major = fields.next()?.parse().ok()?;
+    let minor = fields.next()?.parse().ok()?;
+    let name = fields.next()?.to_string();
+    let values = fields
+        .map(str::parse)
+        .collect::<Result<Vec<u64>, _>>()
+        .ok()?;
+    let mut counters = [None; COUNTERS];
+    match values.len() {
+        // A partition before 2.6.25
+        4 => {
+            for (index, value) in [0, 2, 4, 6].into_iter().zip(values) {
+                counters[index] = Some(value);
+            }
+        }
+        len if len >= 11 => {
+            for (counter, value) in counters.iter_mut().zip(values) {
+                *counter = Some(value);
+            }
+        }
+        _ => return None,
+    }
+    Some(DiskStats {
+        major,
+        minor,
+        name,
+        counters,
+    })
+}
+
+// Helper: The counters of all devices
+#[cfg(target_os = "linux")]
+fn read() -> std::io::Result<Vec<DiskStats>> {
+    std::fs::read_to_string("/proc/diskstats").map(|text| parse(&text))
+}
+
+// Helper: [%{name, major, minor, reads_completed, …}] of `stats`
+#[cfg(target_os = "linux")]
+fn encode<'a>(env: Env<'a>, stats: &[&DiskStats]) -> NifResult<Term<'a>> {
+    let keys = keys();
+    stats
+        .iter()
+        .map(|stats| {
+            let map = rustler::types::map::map_new(env)
+                .map_put(atoms::name().to_term(env), stats.name.as_str())?
+                .map_put(atoms::major().to_term(env), stats.major)?
+                .map_put(atoms::minor().to_term(env), stats.minor)?;
+            keys.iter()
+                .zip(stats.counters)
+                .try_fold(map, |map, (key, value)| {
+                    map.map_put(key.to_term(env), value)
+                })
+        })
+        .collect::<NifResult<Vec<Term>>>()
+        .map(|stats| stats.encode(env))
+}
+
+// Helper: %{name, reads_completed, writes_completed, bytes_read,
+// bytes_written, read_time_ms, write_time_ms, idle_time_ms,
+// ios_in_progress} of the disk `name`, the times given in 100ns
+#[cfg(windows)]
+fn encode_performance<'a>(
+    env: Env<'a>,
+    name: &str,
+    performance: &DISK_PERFORMANCE,
+) -> NifResult<Term<'a>> {
+    let count = |value: i64| value.max(0) as u64;
+    let ms = |value: i64| count(value) / 10_000;
+    rustler::types::map::map_new(env)
+        .map_put(atoms::name().to_term(env), name)?
+        .map_put(atoms::reads_completed().to_term(env), performance.ReadCount)?
+        .map_put(
+            atoms::writes_completed().to_term(env),
+            performance.WriteCount,
+        )?
+        .map_put(
+            atoms::bytes_read().to_term(env),
+            count(performance.BytesRead),
+        )?
+        .map_put(
+            atoms::bytes_written().to_term(env),
+            count(performance.BytesWritten),
+        )?
+        .map_put(atoms::read_time_ms().to_term(env), ms(performance.ReadTime))?
+        .map_put(
+            atoms::write_time_ms().to_term(env),
+            ms(performance.WriteTime),
+        )?
+        .map_put(atoms::idle_time_ms().to_term(env), ms(performance.IdleTime))?
+        .map_put(
+            atoms::ios_in_progress().to_term(env),
+            performance.QueueDepth,
+        )
+}
+
+// {:ok, [%{name, major, minor, reads_completed, …}]} of each device, or
+// {:error, posix, %{errno, errstr}}
+#[rustler::nif(schedule = "DirtyIo")]
+fn diskstats_nif(env: Env) -> NifResult<Term> {
+    #[cfg(target_os = "linux")]
+    match read() {
+        Ok(stats) => Ok((atoms::ok(), encode(env, &stats.iter().collect::<Vec<_>>())?).encode(env)),
+        Err(err) => make_errno_error_tuple(env, posix::atom(env, &err), err),
+    }
+    #[cfg(not(target_os = "linux"))]
+    make_error_tuple(env, atoms::unsupported())
+}
+
+// {:ok, [%{name, major, minor, reads_completed, …}]} of the devices beneath
+// the filesystem holding `path`, those nearer the mount first, or on
+// Windows [%{name, reads_completed, …}] of the disks, or the error of
+// backing_device
+#[rustler::nif(schedule = "DirtyIo")]
+fn path_diskstats_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "linux")]
+    {
+        let backing = match device::resolve(&path) {
+            Ok(Some(backing)) => backing,
+            Ok(None) => return make_error_tuple(env, atoms::not_block_device()),
+            Err(err) => return make_errno_error_tuple(env, posix::atom(env, &err), err),
+        };
+        let stats = match read() {
+            Ok(stats) => stats,
+            Err(err) => return make_errno_error_tuple(env, posix::atom(env, &err), err),
+        };
+        let chain: Vec<&DiskStats> = backing
+            .chain
+            .iter()
+            .filter_map(|layer| stats.iter().find(|stats| stats.name == layer.name))
+            .collect();
+        Ok((atoms::ok(), encode(env, &chain)?).encode(env))
+    }
+    #[cfg(windows)]
+    {
+        let backing = match device::resolve(&path) {
+            Ok(Some(backing)) => backing,
+            Ok(None) => return make_error_tuple(env, atoms::not_block_device()),
+            Err(err) => return make_errno_error_tuple(env, posix::atom(env, &err), err),
+        };
+        let mut disks = Vec::new();
+        for disk in backing.disks() {
+            match device::performance(disk) {
+                Ok(performance) => disks.push(encode_performance(env, &disk.name, &performance)?),
+                Err(err) if err.raw_os_error() == Some(ERROR_INVALID_FUNCTION.0 as i32) => {
+                    let hint = "enable them with diskperf -Y";
+                    return make_error_tuple3(env, atoms::counters_disabled(), hint.encode(env));
+                }
+                Err(err) => return make_errno_error_tuple(env, posix::atom(env, &err), err),
+            }
+        }
+        Ok((atoms::ok(), disks).encode(env))
+    }
+    #[cfg(not(any(target_os = "linux", windows)))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device<'a>(stats: &'a [DiskStats], name: &str) -> &'a DiskStats {
+        stats.iter().find(|stats| stats.name == name).unwrap()
+    }
+
+    #[test]
+    fn parses_the_format_of_each_kernel() {
+        let old = parse(include_str!("../fixtures/diskstats_2.6.18.txt"));
+        assert_eq!(old.len(), 5);
+        let sda = device(&old, "sda");
+        assert_eq!((sda.major, sda.minor), (8, 0));
+        assert_eq!(sda.counters[2], Some(23411830));
+        assert_eq!(sda.counters[10], Some(46443007));
+        assert_eq!(sda.counters[11], None);
+        let sda2 = device(&old, "sda2");
+        assert_eq!(
+            sda2.counters[..8],
+            [
+                Some(938210),
+                None,
+                Some(23401398),
+                None,
+                Some(4794115),
+                None,
+                Some(38351176),
+                None
+            ]
+        );
+
+        let discards = parse(include_str!("../fixtures/diskstats_4.19.txt"));
+        let sda = device(&discards, "sda");
+        assert_eq!(sda.counters[13], Some(98304000));
+        assert_eq!(sda.counters[14], Some(2331));
+        assert_eq!(sda.counters[15], None);
+
+        let flushes = parse(include_str!("../fixtures/diskstats_6.8.txt"));
+        let nvme = device(&flushes, "nvme0n1");
+        assert_eq!((nvme.major, nvme.minor), (259, 0));
+        assert_eq!(nvme.counters[15], Some(1189427));
+        assert_eq!(nvme.counters[16], Some(587150));
+        assert!(nvme.counters.iter().all(Option::is_some));
+        assert_eq!(
+            flushes
+                .iter()
+                .map(|stats| stats.name.as_str())
+                .collect::<Vec<_>>(),
+            ["loop0", "nvme0n1", "nvme0n1p1", "nvme0n1p2", "dm-0"]
+        );
+    }
+
+    #[test]
+    fn skips_lines_that_do_not_parse() {
+        assert_eq!(parse("8 0 sda 1 2 3\n\nnot diskstats at all\n"), []);
+    }
+}
@@ -0,0 +1,204 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Cache of directory contents across du walks, for cache_mode:
+// :mtime_heuristic. A directory is described by a fingerprint of its own
+// mtime and of its entries (their number, and the sums of their mtimes and
+// sizes); while that is unchanged, its whole content is taken from the cache
+// instead of being walked. Changes further down that don't show in the
+// fingerprint go unnoticed, which is why it's a heuristic the caller opts
+// into. Entries are kept per walk options, and the least recently used are
+// evicted beyond max_entries.
+
+use super::walk::Totals;
+use crate::{atoms, get_path_from_term, make_error_tuple, make_error_tuple3, options};
+use crate::{path_from_cstring, Atom};
+use rustler::{Encoder, Env, NifResult, ResourceArc, Term};
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::UNIX_EPOCH;
+
+pub(crate) struct DuCache {
+    state: Mutex<State>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for DuCache {}
+
+struct State {
+    entries: HashMap<PathBuf, Entry>,
+    max_entries: usize,
+    // Bumped by every walk that stores entries, for the recency of entries
+    generation: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+struct Entry {
+    signature: u64,
+    fingerprint: Fingerprint,
+    contents: Totals,
+    used: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct Fingerprint {
+    mtime: u128,
+    entries: u64,
+    entry_mtimes: u128,
+    entry_sizes: u64,
+}
+
+impl Fingerprint {
+    pub(crate) fn new(dir: &Metadata) -> Fingerprint {
+        Fingerprint {
+            mtime: mtime_nanos(dir),
+            entries: 0,
+            entry_mtimes: 0,
+            entry_sizes: 0,
+        }
+    }
+
+    pub(crate) fn add(&mut self, entry: &Metadata) {
+        self.entries += 1;
+        self.entry_mtimes = self.entry_mtimes.wrapping_add(mtime_nanos(entry));
+        self.entry_sizes = self.entry_sizes.wrapping_add(entry.len());
+    }
+}
+
+// Helper: 0 where the platform has no mtime
+fn mtime_nanos(metadata: &Metadata) -> u128 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos())
+}
+
+fn lock(cache: &DuCache) -> MutexGuard<'_, State> {
+    cache.state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// A cache as used by one walk, whose options make up `signature`
+pub(crate) struct Handle {
+    cache: ResourceArc<DuCache>,
+    signature: u64,
+}
+
+impl Handle {
+    // From the `cache` and `cache_mode` options; a cache is only used with
+    // the mode spelled out
+    pub(crate) fn decode(opts: Term, signature: u64) -> Result<Option<Handle>, Atom> {
+        let Some(cache) = options::get::<ResourceArc<DuCache>>(opts, atoms::cache())? else {
+            return Ok(None);
+        };
+        options::get_with(opts, atoms::cache_mode(), |value| {
+            (value.decode::<Atom>().ok()? == atoms::mtime_heuristic()).then_some(())
+        })?
+        .ok_or(atoms::cache_mode())?;
+        Ok(Some(Handle { cache, signature }))
+    }
+
+    // The content of the directory at `path`, if cached with this fingerprint
+    pub(crate) fn lookup(&self, path: &Path, fingerprint: &Fingerprint) -> Option<Totals> {
+        let mut state = lock(&self.cache);
+        let generation = state.generation;
+        let hit = state.entries.get_mut(path).and_then(|entry| {
+            let valid = entry.signature == self.signature && entry.fingerprint == *fingerprint;
+            valid.then(|| {
+                entry.used = generation;
+                entry.contents
+            })
+        });
+        match hit {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        hit
+    }
+
+    // Stores the directories a completed walk went through
+    pub(crate) fn store(&self, walked: Vec<(PathBuf, Fingerprint, Totals)>) {
+        let mut state = lock(&self.cache);
+        state.generation += 1;
+        let generation = state.generation;
+        for (path, fingerprint, contents) in walked {
+            state.entries.insert(
+                path,
+                Entry {
+                    signature: self.signature,
+                    fingerprint,
+                    contents,
+                    used: generation,
+                },
+            );
+        }
+        let excess = state.entries.len().saturating_sub(state.max_entries);
+        if excess == 0 {
+            return;
+        }
+        let mut by_use: Vec<(u64, PathBuf)> = state
+            .entries
+            .iter()
+            .map(|(path, entry)| (entry.used, path.clone()))
+            .collect();
+        by_use.sort_unstable();
+        for (_, path) in by_use.into_iter().take(excess) {
+            state.entries.remove(&path);
+        }
+        state.evictions += excess as u64;
+    }
+}
+
+#[rustler::nif]
+fn du_cache_new_nif<'a>(env: Env<'a>, opts: Term<'a>) -> NifResult<Term<'a>> {
+    let max_entries = match options::get::<usize>(opts, atoms::max_entries()) {
+        Ok(max_entries) => max_entries.unwrap_or(100_000),
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let cache = ResourceArc::new(DuCache {
+        state: Mutex::new(State {
+            entries: HashMap::new(),
+            max_entries,
+            generation: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }),
+    });
+    Ok((atoms::ok(), cache).encode(env))
+}
+
+// Drops the entries of `prefix` and of everything below it
+#[rustler::nif]
+fn du_cache_invalidate_nif<'a>(
+    env: Env<'a>,
+    cache: ResourceArc<DuCache>,
+    prefix_term: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let Some(prefix) = get_path_from_term(env, prefix_term)
+        .ok()
+        .as_ref()
+        .and_then(path_from_cstring)
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    lock(&cache)
+        .entries
+        .retain(|path, _| !path.starts_with(&prefix));
+    Ok(atoms::ok().encode(env))
+}
+
+#[rustler::nif]
+fn du_cache_stats_nif<'a>(env: Env<'a>, cache: ResourceArc<DuCache>) -> NifResult<Term<'a>> {
+    let state = lock(&cache);
+    rustler::types::map::map_new(env)
+        .map_put(atoms::entries().to_term(env), state.entries.len())?
+        .map_put(atoms::max_entries().to_term(env), state.max_entries)?
+        .map_put(atoms::hits().to_term(env), state.hits)?
+        .map_put(atoms::misses().to_term(env), state.misses)?
+        .map_put(atoms::evictions().to_term(env), state.evictions)
+}
@@ -0,0 +1,369 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Approximate du (du_estimate). The first LEVELS levels below the root are
+// walked exactly; of the directories below them, a uniform sample is walked
+// in full, and the size of the others extrapolated from it with a confidence
+// interval. When one sampled directory dominates the sample, the estimate
+// hinges on how many more like it the others hide, so the level-1 directory
+// it sits in is walked completely instead and left out of the extrapolation.
+
+use super::walk::{self, Summary};
+use super::{decode_args, encode_result, job, Options};
+use crate::{atoms, make_error_tuple3, options};
+use rustler::env::OwnedEnv;
+use rustler::{Atom, Env, NifResult, Term};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Depth of the directories sampled
+const LEVELS: u32 = 2;
+// For a 95% confidence interval
+const Z: f64 = 1.96;
+// Share of the sampled bytes beyond which a single directory dominates
+const SKEW_SHARE: f64 = 0.5;
+// Level-1 directories walked completely because of skew, at most
+const MAX_DESCENTS: usize = 8;
+
+#[derive(Debug, PartialEq)]
+struct Estimate {
+    bytes: u64,
+    low: u64,
+    high: u64,
+    sampled_fraction: f64,
+}
+
+// Why there is no estimate: the root couldn't be read, or a walk was halted
+// or cancelled, with its summary so far
+enum Stop {
+    Failed(io::Error),
+    Incomplete(Box<Summary>),
+}
+
+impl From<io::Error> for Stop {
+    fn from(e: io::Error) -> Stop {
+        Stop::Failed(e)
+    }
+}
+
+// A directory at depth LEVELS, the level-1 directory it is in, and what it
+// holds once walked
+struct Dir {
+    path: PathBuf,
+    group: usize,
+    bytes: Option<u64>,
+}
+
+struct Sampler<'a> {
+    root: &'a Path,
+    options: &'a Options,
+    cancel: &'a AtomicBool,
+    dirs: Vec<Dir>,
+    // Allocated bytes counted exactly so far
+    known: u64,
+    rng: SplitMix,
+}
+
+impl Sampler<'_> {
+    // Walks a uniform `fraction` of `from`, at least one of them
+    fn sample(&mut self, mut from: Vec<usize>, fraction: f64) -> Result<(), Stop> {
+        if from.is_empty() {
+            return Ok(());
+        }
+        let n = ((from.len() as f64 * fraction).ceil() as usize).clamp(1, from.len());
+        for i in 0..n {
+            let j = i + self.rng.below(from.len() - i);
+            from.swap(i, j);
+        }
+        from.truncate(n);
+        self.walk(&from)
+    }
+
+    fn walk(&mut self, indices: &[usize]) -> Result<(), Stop> {
+        if indices.is_empty() {
+            return Ok(());
+        }
+        let paths: Vec<PathBuf> = indices.iter().map(|&i| self.dirs[i].path.clone()).collect();
+        let summary = walk::walk_below(self.root, &paths, LEVELS, self.options, self.cancel)?;
+        if summary.halted.is_some() || summary.cancelled {
+            return Err(Stop::Incomplete(Box::new(summary)));
+        }
+        self.known += summary.bytes;
+        for (&i, node) in indices.iter().zip(&summary.nodes) {
+            self.dirs[i].bytes = Some(node.bytes);
+        }
+        Ok(())
+    }
+
+    // The sampled directories outside the groups walked completely
+    fn samples(&self, whole: &[bool]) -> Vec<(usize, u64)> {
+        self.dirs
+            .iter()
+            .enumerate()
+            .filter(|(_, dir)| !whole[dir.group])
+            .filter_map(|(i, dir)| dir.bytes.map(|bytes| (i, bytes)))
+            .collect()
+    }
+
+    fn unsampled(&self) -> Vec<usize> {
+        (0..self.dirs.len())
+            .filter(|&i| self.dirs[i].bytes.is_none())
+            .collect()
+    }
+}
+
+fn estimate(
+    root: &Path,
+    options: &Options,
+    fraction: f64,
+    seed: u64,
+    cancel: &AtomicBool,
+) -> Result<Estimate, Stop> {
+    let mut levels = walk::walk_levels(root, LEVELS, options, cancel)?;
+    if levels.halted.is_some() || levels.cancelled {
+        return Err(Stop::Incomplete(Box::new(levels)));
+    }
+    let mut groups: HashMap<Option<PathBuf>, usize> = HashMap::new();
+    let dirs: Vec<Dir> = std::mem::take(&mut levels.unread)
+        .into_iter()
+        .map(|path| {
+            let next = groups.len();
+            let group = *groups
+                .entry(path.parent().map(Path::to_path_buf))
+                .or_insert(next);
+            Dir {
+                path,
+                group,
+                bytes: None,
+            }
+        })
+        .collect();
+    let mut sampler = Sampler {
+        root,
+        options,
+        cancel,
+        dirs,
+        known: levels.bytes,
+        rng: SplitMix(seed),
+    };
+    sampler.sample((0..sampler.dirs.len()).collect(), fraction)?;
+    let mut whole = vec![false; groups.len()];
+    for _ in 0..MAX_DESCENTS {
+        let samples = sampler.samples(&whole);
+        let sum: u64 = samples.iter().map(|(_, bytes)| bytes).sum();
+        let Some(&(largest, bytes)) = samples.iter().max_by_key(|(_, bytes)| *bytes) else {
+            break;
+        };
+        if samples.len() < 2 || bytes as f64 <= SKEW_SHARE * sum as f64 {
+            break;
+        }
+        let group = sampler.dirs[largest].group;
+        whole[group] = true;
+        let rest: Vec<usize> = sampler
+            .unsampled()
+            .into_iter()
+            .filter(|&i| sampler.dirs[i].group == group)
+            .collect();
+        sampler.walk(&rest)?;
+    }
+    // The groups walked completely may have taken all the samples
+    let unsampled = sampler.unsampled();
+    if !unsampled.is_empty() && sampler.samples(&whole).is_empty() {
+        sampler.sample(unsampled, fraction)?;
+    }
+    let samples: Vec<u64> = sampler
+        .samples(&whole)
+        .into_iter()
+        .map(|(_, bytes)| bytes)
+        .collect();
+    Ok(extrapolate(
+        sampler.known,
+        &samples,
+        sampler.unsampled().len(),
+        sampler.dirs.len(),
+    ))
+}
+
+// Helper: The estimate of `known` bytes plus `unsampled` directories drawn
+// from the same population as `samples`, out of `total` directories sampled
+// from. With a single sample there is no spread to go by, so its standard
+// deviation is taken to be as large as its mean.
+fn extrapolate(known: u64, samples: &[u64], unsampled: usize, total: usize) -> Estimate {
+    let sampled_fraction = if total == 0 {
+        1.0
+    } else {
+        (total - unsampled) as f64 / total as f64
+    };
+    if unsampled == 0 || samples.is_empty() {
+        return Estimate {
+            bytes: known,
+            low: known,
+            high: known,
+            sampled_fraction,
+        };
+    }
+    let n = samples.len() as f64;
+    let population = n + unsampled as f64;
+    let mean = samples.iter().map(|&x| x as f64).sum::<f64>() / n;
+    let variance = if samples.len() > 1 {
+        samples
+            .iter()
+            .map(|&x| (x as f64 - mean).powi(2))
+            .sum::<f64>()
+            / (n - 1.0)
+    } else {
+        mean * mean
+    };
+    // Standard error of the population total, with the finite population
+    // correction as the samples are drawn without replacement
+    let spread = Z * population * ((1.0 - n / population) * variance / n).sqrt();
+    let rest = unsampled as f64 * mean;
+    Estimate {
+        bytes: known.saturating_add(rest.round() as u64),
+        low: known.saturating_add((rest - spread).max(0.0).round() as u64),
+        high: known.saturating_add((rest + spread).round() as u64),
+        sampled_fraction,
+    }
+}
+
+// Small PRNG for picking samples (SplitMix64)
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // A number below `n`, with negligible bias for the counts at hand
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// Helper: The sample_fraction and seed options
+fn decode_sampling(opts: Term) -> Result<(f64, u64), Atom> {
+    let fraction = options::get_with(opts, atoms::sample_fraction(), |value| {
+        value
+            .decode::<f64>()
+            .ok()
+            .or_else(|| value.decode::<u64>().ok().map(|n| n as f64))
+            .filter(|f| *f > 0.0 && *f <= 1.0)
+    })?
+    .unwrap_or(0.1);
+    let seed = options::get(opts, atoms::seed())?.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64)
+    });
+    Ok((fraction, seed))
+}
+
+#[rustler::nif]
+fn du_estimate_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    opts: Term<'a>,
+    reference: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let (root, options) = match decode_args(env, path_term, opts) {
+        Ok(args) => args,
+        Err(error) => return error,
+    };
+    let (fraction, seed) = match decode_sampling(opts) {
+        Ok(sampling) => sampling,
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let pid = env.pid();
+    let mut owned_env = OwnedEnv::new();
+    let reference = owned_env.save(reference);
+    job::start(env, move |cancel| {
+        let estimated = estimate(&root, &options, fraction, seed, cancel);
+        let _ = owned_env.send_and_clear(&pid, |env| {
+            let result = encode_estimate(env, estimated, &options)
+                .unwrap_or_else(|_| atoms::error().to_term(env));
+            (atoms::du_result(), reference.load(env), result)
+        });
+    })
+}
+
+// Helper: {:ok, %{estimate_bytes, confidence_low, confidence_high,
+// sampled_fraction}}, or an error as du gives it
+fn encode_estimate<'a>(
+    env: Env<'a>,
+    estimated: Result<Estimate, Stop>,
+    options: &Options,
+) -> NifResult<Term<'a>> {
+    let estimate = match estimated {
+        Ok(estimate) => estimate,
+        Err(Stop::Failed(e)) => return encode_result(env, Err(e), options),
+        Err(Stop::Incomplete(summary)) => return encode_result(env, Ok(*summary), options),
+    };
+    let map = rustler::types::map::map_new(env)
+        .map_put(atoms::estimate_bytes().to_term(env), estimate.bytes)?
+        .map_put(atoms::confidence_low().to_term(env), estimate.low)?
+        .map_put(atoms::confidence_high().to_term(env), estimate.high)?
+        .map_put(
+            atoms::sampled_fraction().to_term(env),
+            estimate.sampled_fraction,
+        )?;
+    Ok(rustler::types::tuple::make_tuple(
+        env,
+        &[atoms::ok().to_term(env), map],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_exact_once_everything_is_walked() {
+        let estimate = extrapolate(1000, &[10, 20, 30], 0, 3);
+        assert_eq!(
+            estimate,
+            Estimate {
+                bytes: 1000,
+                low: 1000,
+                high: 1000,
+                sampled_fraction: 1.0
+            }
+        );
+        assert_eq!(extrapolate(7, &[], 0, 0).sampled_fraction, 1.0);
+    }
+
+    #[test]
+    fn extrapolates_the_mean_within_bounds() {
+        // Identical samples leave no spread
+        let even = extrapolate(100, &[50, 50], 8, 10);
+        assert_eq!((even.bytes, even.low, even.high), (500, 500, 500));
+        assert_eq!(even.sampled_fraction, 0.2);
+
+        let uneven = extrapolate(100, &[10, 90], 8, 10);
+        assert_eq!(uneven.bytes, 500);
+        assert!(uneven.low < 500 && uneven.high > 500);
+        assert!(uneven.low >= 100);
+
+        // A single sample gives wide bounds rather than none
+        let single = extrapolate(0, &[40], 9, 10);
+        assert_eq!(single.bytes, 360);
+        assert!(single.low < 360 && single.high > 360);
+    }
+
+    #[test]
+    fn samples_are_reproducible_and_in_range() {
+        let draw = |seed| {
+            let mut rng = SplitMix(seed);
+            (0..100).map(|_| rng.below(7)).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42), draw(43));
+        assert!(draw(1).iter().all(|&n| n < 7));
+    }
+}
@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// File extensions for by_extension: the lowercased text after the last dot of
+// the name, or one of the configured compound extensions ("tar.gz") the name
+// ends with. Names starting with their only dot (".bashrc"), ending with a dot,
+// or whose extension is implausibly long have none.
+
+use std::ffi::OsStr;
+
+// Longer "extensions" are more likely parts of a name ("report.final-draft")
+pub(crate) const MAX_EXTENSION_LEN: usize = 16;
+
+pub(crate) struct Extensions {
+    // Lowercased, without the leading dot
+    compound: Vec<String>,
+}
+
+impl Extensions {
+    pub(crate) fn new<S: AsRef<str>>(compound: &[S]) -> Extensions {
+        Extensions {
+            compound: compound
+                .iter()
+                .map(|ext| ext.as_ref().trim_start_matches('.').to_lowercase())
+                .filter(|ext| !ext.is_empty())
+                .collect(),
+        }
+    }
+
+    pub(crate) fn of(&self, name: &OsStr) -> Option<String> {
+        let name = name.to_string_lossy().to_lowercase();
+        for compound in &self.compound {
+            if let Some(stem) = name.strip_suffix(compound.as_str()) {
+                if stem.len() > 1 && stem.ends_with('.') {
+                    return Some(compound.clone());
+                }
+            }
+        }
+        let dot = name.rfind('.').filter(|&dot| dot > 0)?;
+        let ext = &name[dot + 1..];
+        (!ext.is_empty() && ext.len() <= MAX_EXTENSION_LEN).then(|| ext.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ext(compound: &[&str], name: &str) -> Option<String> {
+        Extensions::new(compound).of(OsStr::new(name))
+    }
+
+    #[test]
+    fn takes_the_last_dot_component_lowercased() {
+        assert_eq!(ext(&[], "app.LOG").as_deref(), Some("log"));
+        assert_eq!(ext(&[], "archive.tar.gz").as_deref(), Some("gz"));
+        assert_eq!(ext(&[], "Makefile"), None);
+        assert_eq!(ext(&[], ".bashrc"), None);
+        assert_eq!(ext(&[], "trailing."), None);
+        assert_eq!(ext(&[], ".config.json").as_deref(), Some("json"));
+    }
+
+    #[test]
+    fn caps_the_length() {
+        assert_eq!(
+            ext(&[], "a.abcdefghijklmnop").as_deref(),
+            Some("abcdefghijklmnop")
+        );
+        assert_eq!(ext(&[], "a.abcdefghijklmnopq"), None);
+    }
+
+    #[test]
+    fn recognizes_compound_extensions() {
+        let compound = [".tar.gz", "TAR.ZST"];
+        assert_eq!(ext(&compound, "src.tar.gz").as_deref(), Some("tar.gz"));
+        assert_eq!(ext(&compound, "SRC.TAR.ZST").as_deref(), Some("tar.zst"));
+        assert_eq!(ext(&compound, "notes.gz").as_deref(), Some("gz"));
+        // The compound extension alone is a dotfile-like name
+        assert_eq!(ext(&compound, ".tar.gz").as_deref(), Some("gz"));
+        assert_eq!(ext(&compound, "xtar.gz").as_deref(), Some("gz"));
+    }
+}
@@ -0,0 +1,84 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The min_size and older_than criteria of du, which narrow top_files and the
+// `matching` summary down to the files worth cleaning up, e.g. over 100 MB
+// and untouched for 90 days. Ages count from the start of the walk, and a
+// timestamp in the future (clock skew, or a file copied from another
+// machine) makes for an age of zero.
+
+use super::TopBy;
+use crate::{atoms, options};
+use rustler::{Atom, Term};
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime};
+
+pub(crate) struct Filter {
+    min_size: u64,
+    older_than: Option<Duration>,
+    // Age by last access rather than last modification
+    atime: bool,
+    now: SystemTime,
+}
+
+impl Filter {
+    // None unless min_size or older_than is given
+    pub(crate) fn decode(opts: Term) -> Result<Option<Filter>, Atom> {
+        let min_size: Option<u64> = options::get(opts, atoms::min_size())?;
+        let older_than: Option<u64> = options::get(opts, atoms::older_than())?;
+        let atime = options::get(opts, atoms::atime())?.unwrap_or(false);
+        if min_size.is_none() && older_than.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Filter {
+            min_size: min_size.unwrap_or(0),
+            older_than: older_than.map(Duration::from_secs),
+            atime,
+            now: SystemTime::now(),
+        }))
+    }
+
+    // `size` being the file's size as top_by measures it
+    pub(crate) fn matches(&self, size: u64, metadata: &Metadata) -> bool {
+        if size < self.min_size {
+            return false;
+        }
+        let Some(older_than) = self.older_than else {
+            return true;
+        };
+        let time = if self.atime {
+            metadata.accessed()
+        } else {
+            metadata.modified()
+        };
+        // No timestamp at all tells nothing about the age
+        time.is_ok_and(|time| age(self.now, time) >= older_than)
+    }
+}
+
+// Helper: The size of a file as `by` measures it
+pub(crate) fn size(by: TopBy, allocated: u64, metadata: &Metadata) -> u64 {
+    match by {
+        TopBy::Bytes => allocated,
+        TopBy::ApparentBytes => metadata.len(),
+    }
+}
+
+// Helper: How long before `now` `time` was, zero if it's later
+fn age(now: SystemTime, time: SystemTime) -> Duration {
+    now.duration_since(time).unwrap_or(Duration::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn future_times_are_of_age_zero() {
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(3600);
+        assert_eq!(age(now, now - hour), hour);
+        assert_eq!(age(now, now), Duration::ZERO);
+        assert_eq!(age(now, now + hour), Duration::ZERO);
+    }
+}
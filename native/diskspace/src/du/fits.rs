@@ -0,0 +1,77 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Whether a tree measured by du would fit onto the filesystem of another
+// path: its allocated bytes against the space available to the user, and its
+// inodes against the inodes available. Filesystems that allocate inodes on
+// demand (btrfs, NTFS, ...) report no inode limit and only the bytes count.
+
+use crate::{atoms, get_path_from_term, make_error_tuple, path_from_cstring};
+use rustler::{Encoder, Env, NifResult, Term};
+use std::path::Path;
+
+// Bytes available to the user, and inodes available if they are limited
+struct Capacity {
+    bytes: u64,
+    inodes: Option<u64>,
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn fits_nif<'a>(env: Env<'a>, path_term: Term<'a>, bytes: u64, inodes: u64) -> NifResult<Term<'a>> {
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .as_ref()
+        .and_then(path_from_cstring)
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let capacity = match capacity(env, &path) {
+        Ok(capacity) => capacity,
+        Err(error) => return error,
+    };
+    let fits = bytes <= capacity.bytes && capacity.inodes.is_none_or(|free| inodes <= free);
+    Ok(rustler::types::tuple::make_tuple(
+        env,
+        &[atoms::ok().to_term(env), fits.encode(env)],
+    ))
+}
+
+#[cfg(unix)]
+fn capacity<'a>(env: Env<'a>, path: &Path) -> Result<Capacity, NifResult<Term<'a>>> {
+    use nix::sys::statvfs::statvfs;
+    let stats = statvfs(path).map_err(|err| {
+        let err = std::io::Error::from_raw_os_error(err as i32);
+        crate::make_errno_error_tuple(env, atoms::statvfs_failed(), err)
+    })?;
+    Ok(Capacity {
+        bytes: stats.blocks_available() as u64 * stats.fragment_size() as u64,
+        // No inode table at all means no limit
+        inodes: (stats.files() > 0).then_some(stats.files_available() as u64),
+    })
+}
+
+#[cfg(windows)]
+fn capacity<'a>(env: Env<'a>, path: &Path) -> Result<Capacity, NifResult<Term<'a>>> {
+    use widestring::WideCString;
+    use windows::core::PCWSTR;
+    use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+    let wide = WideCString::from_os_str(path.as_os_str())
+        .map_err(|_| make_error_tuple(env, atoms::path_conversion_failed()))?;
+    let mut available: u64 = 0;
+    unsafe {
+        GetDiskFreeSpaceExW(
+            PCWSTR::from_raw(wide.as_ptr()),
+            Some(&mut available),
+            None,
+            None,
+        )
+    }
+    .map_err(|e| {
+        let code = (e.code().0 & 0xFFFF) as u32;
+        crate::make_winapi_error_tuple(env, atoms::winapi_failed(), code)
+    })?;
+    Ok(Capacity {
+        bytes: available,
+        inodes: None,
+    })
+}
@@ -0,0 +1,274 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Gitignore-style glob matching of paths relative to the du root:
+//   - a pattern without a slash (other than a trailing one) matches the name
+//     at any depth, e.g. "node_modules" or "*.o"
+//   - any other pattern is anchored at the root, e.g. "build/out" or "/target"
+//   - "*" and "?" never match "/", "[a-z]" / "[!a-z]" are character classes
+//   - "**" as a whole component matches zero or more directories
+//   - a trailing "/" only matches directories, a leading "!" re-includes
+//     what an earlier pattern excluded; the last matching pattern wins
+// Patterns work on bytes so non-UTF-8 names still match on Unix.
+
+use std::path::Path;
+
+enum Segment {
+    // "**"
+    AnyDirs,
+    Glob(Vec<u8>),
+}
+
+struct Pattern {
+    segments: Vec<Segment>,
+    dir_only: bool,
+    negated: bool,
+}
+
+pub(crate) struct Matcher {
+    patterns: Vec<Pattern>,
+}
+
+impl Matcher {
+    pub(crate) fn new<S: AsRef<str>>(patterns: &[S]) -> Matcher {
+        Matcher {
+            patterns: patterns
+                .iter()
+                .filter_map(|p| Pattern::parse(p.as_ref()))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    // `relative` is the path below the root; the root itself never matches
+    pub(crate) fn is_excluded(&self, relative: &Path, is_dir: bool) -> bool {
+        self.decide(relative, is_dir) == Some(true)
+    }
+
+    // Whether the last matching pattern excludes (true) or re-includes
+    // (false) `relative`; None if no pattern matches
+    pub(crate) fn decide(&self, relative: &Path, is_dir: bool) -> Option<bool> {
+        let components: Vec<&[u8]> = relative
+            .components()
+            .map(|c| c.as_os_str().as_encoded_bytes())
+            .collect();
+        if components.is_empty() {
+            return None;
+        }
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| (is_dir || !p.dir_only) && match_segments(&p.segments, &components))
+            .map(|p| !p.negated)
+    }
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Option<Pattern> {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            return None;
+        }
+        let mut segments = Vec::new();
+        if !anchored {
+            segments.push(Segment::AnyDirs);
+        }
+        for part in pattern.split('/').filter(|part| !part.is_empty()) {
+            segments.push(match part {
+                "**" => Segment::AnyDirs,
+                _ => Segment::Glob(part.as_bytes().to_vec()),
+            });
+        }
+        // A trailing "/**" matches everything inside, but not the directory
+        // itself: one component, then any number more
+        if anchored && matches!(segments.last(), Some(Segment::AnyDirs)) && segments.len() > 1 {
+            segments.insert(segments.len() - 1, Segment::Glob(b"*".to_vec()));
+        }
+        Some(Pattern {
+            segments,
+            dir_only,
+            negated,
+        })
+    }
+}
+
+// Recursion is bounded by the number of segments in the pattern, not by the
+// depth of the path
+fn match_segments(segments: &[Segment], components: &[&[u8]]) -> bool {
+    match segments.first() {
+        None => components.is_empty(),
+        Some(Segment::AnyDirs) => {
+            (0..=components.len()).any(|skip| match_segments(&segments[1..], &components[skip..]))
+        }
+        Some(Segment::Glob(glob)) => {
+            !components.is_empty()
+                && match_glob(glob, components[0])
+                && match_segments(&segments[1..], &components[1..])
+        }
+    }
+}
+
+// Helper: Matches a single path component, backtracking to the last "*"
+fn match_glob(glob: &[u8], name: &[u8]) -> bool {
+    let (mut g, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if glob.get(g) == Some(&b'*') {
+            star = Some((g, n));
+            g += 1;
+            continue;
+        }
+        if let Some(next) = match_one(glob, g, name[n]) {
+            g = next;
+            n += 1;
+            continue;
+        }
+        match star {
+            Some((star_g, star_n)) => {
+                g = star_g + 1;
+                n = star_n + 1;
+                star = Some((star_g, n));
+            }
+            None => return false,
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+// Helper: If the token at glob[g] matches the byte c, the index of the next token
+fn match_one(glob: &[u8], g: usize, c: u8) -> Option<usize> {
+    match *glob.get(g)? {
+        b'?' => Some(g + 1),
+        b'\\' if g + 1 < glob.len() => (glob[g + 1] == c).then_some(g + 2),
+        b'[' => match match_class(glob, g, c) {
+            Some((matched, next)) => matched.then_some(next),
+            // An unterminated class is a literal "["
+            None => (c == b'[').then_some(g + 1),
+        },
+        literal => (literal == c).then_some(g + 1),
+    }
+}
+
+// Helper: Whether c is in the class starting at glob[g] == '[', and the index
+// past the closing ']'; None if the class isn't terminated
+fn match_class(glob: &[u8], g: usize, c: u8) -> Option<(bool, usize)> {
+    let mut i = g + 1;
+    let negated = matches!(glob.get(i), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let lo = *glob.get(i)?;
+        // A "]" right after the opening bracket is a member, not the end
+        if lo == b']' && !first {
+            return Some((matched != negated && c != b'/', i + 1));
+        }
+        first = false;
+        if glob.get(i + 1) == Some(&b'-') && glob.get(i + 2).is_some_and(|&hi| hi != b']') {
+            matched |= (lo..=glob[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= lo == c;
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn excluded(patterns: &[&str], path: &str, is_dir: bool) -> bool {
+        Matcher::new(patterns).is_excluded(Path::new(path), is_dir)
+    }
+
+    #[test]
+    fn names_match_at_any_depth() {
+        let patterns = ["node_modules", "*.o"];
+        assert!(excluded(&patterns, "node_modules", true));
+        assert!(excluded(&patterns, "web/app/node_modules", true));
+        assert!(excluded(&patterns, "main.o", false));
+        assert!(excluded(&patterns, "src/lib/util.o", false));
+        assert!(!excluded(&patterns, "main.obj", false));
+        assert!(!excluded(&patterns, "node_modules_old", true));
+    }
+
+    #[test]
+    fn slashes_anchor_at_the_root() {
+        let patterns = ["/target", "build/out"];
+        assert!(excluded(&patterns, "target", true));
+        assert!(!excluded(&patterns, "crates/x/target", true));
+        assert!(excluded(&patterns, "build/out", true));
+        assert!(!excluded(&patterns, "web/build/out", true));
+    }
+
+    #[test]
+    fn double_stars_match_any_number_of_directories() {
+        assert!(excluded(&["**/.git"], ".git", true));
+        assert!(excluded(&["**/.git"], "vendor/lib/.git", true));
+        assert!(excluded(&["docs/**/*.png"], "docs/a.png", false));
+        assert!(excluded(&["docs/**/*.png"], "docs/img/2024/a.png", false));
+        assert!(!excluded(&["docs/**/*.png"], "src/docs/a.png", false));
+        // "dir/**" matches the content but not the directory itself
+        assert!(excluded(&["logs/**"], "logs/today.txt", false));
+        assert!(!excluded(&["logs/**"], "logs", true));
+    }
+
+    #[test]
+    fn wildcards_stay_within_a_component() {
+        assert!(excluded(&["a*c"], "abbbc", false));
+        assert!(!excluded(&["/a*c"], "ab/c", false));
+        assert!(excluded(&["file?.txt"], "file1.txt", false));
+        assert!(!excluded(&["file?.txt"], "file10.txt", false));
+        assert!(excluded(&["*"], "anything", false));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(excluded(&["core.[0-9]*"], "core.1234", false));
+        assert!(!excluded(&["core.[0-9]*"], "core.dump", false));
+        assert!(excluded(&["[!.]*.tmp"], "x.tmp", false));
+        assert!(!excluded(&["[!.]*.tmp"], ".x.tmp", false));
+        assert!(excluded(&["[]x]"], "]", false));
+        // Unterminated classes and escapes are literals
+        assert!(excluded(&["a[b"], "a[b", false));
+        assert!(excluded(&["\\*.log"], "*.log", false));
+        assert!(!excluded(&["\\*.log"], "debug.log", false));
+    }
+
+    #[test]
+    fn trailing_slash_only_matches_directories() {
+        assert!(excluded(&["cache/"], "cache", true));
+        assert!(excluded(&["cache/"], "app/cache", true));
+        assert!(!excluded(&["cache/"], "cache", false));
+    }
+
+    #[test]
+    fn negation_re_includes_and_last_match_wins() {
+        let patterns = ["*.log", "!keep.log"];
+        assert!(excluded(&patterns, "debug.log", false));
+        assert!(!excluded(&patterns, "keep.log", false));
+        assert!(excluded(&["!keep.log", "*.log"], "keep.log", false));
+    }
+
+    #[test]
+    fn ignores_empty_patterns_and_never_matches_the_root() {
+        let matcher = Matcher::new(&["", "/", "!"]);
+        assert!(matcher.is_empty());
+        assert!(!excluded(&["*"], "", true));
+    }
+}
@@ -0,0 +1,146 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Per-directory ignore files (.gitignore and the like) for
+// respect_ignore_files. Each file holds glob patterns (see glob.rs) relative
+// to the directory it is in, one per line:
+//   - blank lines and lines starting with "#" are skipped, "\#" and "\!"
+//     start a pattern with a literal "#" or "!"
+//   - trailing spaces are dropped unless escaped with "\"
+// The rules in effect in a directory are those of its own ignore files, then
+// of its ancestors' up to the root; the innermost file with a matching
+// pattern decides, so a deeper file can re-include what a shallower one
+// ignores. As with git, nothing inside an ignored directory can be
+// re-included.
+
+use super::glob::Matcher;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub(crate) struct Ignores {
+    dir: PathBuf,
+    matcher: Matcher,
+    parent: Option<Arc<Ignores>>,
+}
+
+impl Ignores {
+    // The rules in effect in `dir`, whose parent directory has `parent`'s;
+    // the ignore files that can't be read are skipped
+    pub(crate) fn load(
+        dir: &Path,
+        names: &[String],
+        parent: Option<&Arc<Ignores>>,
+    ) -> Option<Arc<Ignores>> {
+        let patterns: Vec<String> = names
+            .iter()
+            .filter_map(|name| fs::read(dir.join(name)).ok())
+            .flat_map(|text| patterns(&String::from_utf8_lossy(&text)))
+            .collect();
+        if patterns.is_empty() {
+            return parent.cloned();
+        }
+        Some(Arc::new(Ignores {
+            dir: dir.to_path_buf(),
+            matcher: Matcher::new(&patterns),
+            parent: parent.cloned(),
+        }))
+    }
+
+    // The rules that apply to the entries of `dir` before its own ignore
+    // files are read: those of the directories from `root` down to its parent
+    pub(crate) fn above(root: &Path, dir: &Path, names: &[String]) -> Option<Arc<Ignores>> {
+        let mut components = dir.strip_prefix(root).ok()?.components();
+        components.next_back()?;
+        let mut path = root.to_path_buf();
+        let mut ignores = Ignores::load(&path, names, None);
+        for component in components {
+            path.push(component);
+            ignores = Ignores::load(&path, names, ignores.as_ref());
+        }
+        ignores
+    }
+
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut level = Some(self);
+        while let Some(ignores) = level {
+            let decision = path
+                .strip_prefix(&ignores.dir)
+                .ok()
+                .and_then(|relative| ignores.matcher.decide(relative, is_dir));
+            if let Some(ignored) = decision {
+                return ignored;
+            }
+            level = ignores.parent.as_deref();
+        }
+        false
+    }
+}
+
+// Helper: The patterns in the text of an ignore file
+fn patterns(text: &str) -> Vec<String> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            let mut line = line.trim_end_matches('\r');
+            while line.ends_with(' ') && !line.ends_with("\\ ") {
+                line = &line[..line.len() - 1];
+            }
+            line.to_string()
+        })
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(dir: &str, text: &str, parent: Option<Arc<Ignores>>) -> Arc<Ignores> {
+        Arc::new(Ignores {
+            dir: PathBuf::from(dir),
+            matcher: Matcher::new(&patterns(text)),
+            parent,
+        })
+    }
+
+    #[test]
+    fn parses_comments_escapes_and_trailing_spaces() {
+        let text = "# build output\n\ntarget/  \n\\#notes\n\\!bang\nspace\\ \r\n";
+        assert_eq!(
+            patterns(text),
+            ["target/", "\\#notes", "\\!bang", "space\\ "]
+        );
+        let ignores = level("/r", text, None);
+        assert!(ignores.is_ignored(Path::new("/r/target"), true));
+        assert!(!ignores.is_ignored(Path::new("/r/target"), false));
+        assert!(ignores.is_ignored(Path::new("/r/#notes"), false));
+        assert!(ignores.is_ignored(Path::new("/r/!bang"), false));
+        assert!(ignores.is_ignored(Path::new("/r/space "), false));
+        assert!(!ignores.is_ignored(Path::new("/r/space"), false));
+    }
+
+    #[test]
+    fn patterns_are_relative_to_their_file() {
+        let root = level("/r", "/dist\nbuild/out\n*.log", None);
+        let sub = level("/r/web", "/cache", Some(root));
+        assert!(sub.is_ignored(Path::new("/r/dist"), true));
+        assert!(!sub.is_ignored(Path::new("/r/web/dist"), true));
+        assert!(sub.is_ignored(Path::new("/r/build/out"), true));
+        assert!(sub.is_ignored(Path::new("/r/web/cache"), true));
+        assert!(!sub.is_ignored(Path::new("/r/cache"), true));
+        assert!(sub.is_ignored(Path::new("/r/web/debug.log"), false));
+    }
+
+    #[test]
+    fn deeper_files_take_precedence() {
+        let root = level("/r", "*.log\n!keep.log", None);
+        let sub = level("/r/a", "!debug.log\nkeep.log", Some(root.clone()));
+        assert!(root.is_ignored(Path::new("/r/a/debug.log"), false));
+        assert!(!sub.is_ignored(Path::new("/r/a/debug.log"), false));
+        assert!(sub.is_ignored(Path::new("/r/a/keep.log"), false));
+        assert!(!sub.is_ignored(Path::new("/r/keep.log"), false));
+        // Files a deeper rule doesn't mention fall back to the outer rules
+        assert!(sub.is_ignored(Path::new("/r/a/other.log"), false));
+    }
+}
@@ -0,0 +1,137 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Background du walks, which du/2 also goes through. du_start runs the walk
+// on an OS thread of its own, leaving the dirty schedulers to calls such as
+// stat_fs, and hands back a token for cancelling it; the outcome is sent to
+// the calling process as {:du_result, ref, result}. du_async does the same for
+// a given process and reference, with the result shaped as du/2 returns it,
+// as there is no du_await in between to reshape it. du_stream does the same
+// but sends each completed directory and then {:du_done, ref, result} to a
+// given process; see stream.rs. The thread owns everything it needs, so it
+// ends with the walk whether or not the token is still around.
+
+use super::stream::Stream;
+use super::{decode_args, encode_result, run_walk};
+use crate::{atoms, make_errno_error_tuple, make_error_tuple3, reshape_error};
+use rustler::env::OwnedEnv;
+use rustler::{Encoder, Env, LocalPid, NifResult, ResourceArc, Term};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+pub(crate) struct DuToken {
+    cancel: Arc<AtomicBool>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for DuToken {}
+
+#[rustler::nif]
+fn du_start_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    opts: Term<'a>,
+    reference: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let pid = env.pid();
+    start_walk(env, path_term, opts, pid, reference, false)
+}
+
+#[rustler::nif]
+fn du_async_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    opts: Term<'a>,
+    pid: LocalPid,
+    reference: Term<'a>,
+) -> NifResult<Term<'a>> {
+    start_walk(env, path_term, opts, pid, reference, true)
+}
+
+// Helper: Starts a walk that sends {:du_result, ref, result} to `pid`,
+// reshaped or not, returning {:ok, token}
+fn start_walk<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    opts: Term<'a>,
+    pid: LocalPid,
+    reference: Term<'a>,
+    reshape: bool,
+) -> NifResult<Term<'a>> {
+    let (root, options) = match decode_args(env, path_term, opts) {
+        Ok(args) => args,
+        Err(error) => return error,
+    };
+    let mut owned_env = OwnedEnv::new();
+    let reference = owned_env.save(reference);
+    start(env, move |cancel| {
+        let walked = run_walk(&root, &options, cancel, None);
+        // The process may be gone by now, which is fine
+        let _ = owned_env.send_and_clear(&pid, |env| {
+            let result = encode_result(env, walked, &options).map_or_else(
+                |_| atoms::error().to_term(env),
+                |result| {
+                    if reshape {
+                        reshape_error(env, result)
+                    } else {
+                        result
+                    }
+                },
+            );
+            (atoms::du_result(), reference.load(env), result)
+        });
+    })
+}
+
+#[rustler::nif]
+fn du_stream_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    opts: Term<'a>,
+    pid: LocalPid,
+    reference: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let (root, options) = match decode_args(env, path_term, opts) {
+        Ok(args) => args,
+        Err(error) => return error,
+    };
+    let stream = match Stream::new(opts, pid, reference) {
+        Ok(stream) => stream,
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    start(env, move |cancel| {
+        let walked = run_walk(&root, &options, cancel, Some(&stream));
+        stream.done(|env| {
+            encode_result(env, walked, &options).map_or_else(
+                |_| atoms::error().to_term(env),
+                |result| reshape_error(env, result),
+            )
+        });
+    })
+}
+
+// Cancelling is idempotent, and a no-op once the walk is done
+#[rustler::nif]
+fn du_cancel_nif(token: ResourceArc<DuToken>) -> rustler::Atom {
+    token.cancel.store(true, Ordering::Relaxed);
+    atoms::ok()
+}
+
+// Helper: Runs `walk` on a thread of its own, returning {:ok, token}
+pub(super) fn start<'a>(
+    env: Env<'a>,
+    walk: impl FnOnce(&AtomicBool) + Send + 'static,
+) -> NifResult<Term<'a>> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let token = ResourceArc::new(DuToken {
+        cancel: cancel.clone(),
+    });
+    let started = thread::Builder::new()
+        .name("disk_space_du".to_string())
+        .spawn(move || walk(&cancel));
+    if let Err(e) = started {
+        return make_errno_error_tuple(env, atoms::du_failed(), e);
+    }
+    Ok((atoms::ok(), token).encode(env))
+}
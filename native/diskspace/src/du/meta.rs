@@ -0,0 +1,316 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Platform-specific per-entry facts the walker needs beyond std's Metadata.
+
+use std::borrow::Cow;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(windows)]
+use std::os::windows::ffi::OsStrExt;
+#[cfg(windows)]
+use windows::core::PCWSTR;
+#[cfg(windows)]
+use windows::Win32::Foundation::{
+    CloseHandle, GetLastError, SetLastError, ERROR_CANT_RESOLVE_FILENAME, NO_ERROR,
+};
+#[cfg(windows)]
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, GetCompressedFileSizeW, GetDiskFreeSpaceW, GetFileInformationByHandle,
+    GetVolumePathNameW, BY_HANDLE_FILE_INFORMATION, FILE_ATTRIBUTE_COMPRESSED,
+    FILE_ATTRIBUTE_PINNED, FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS, FILE_ATTRIBUTE_RECALL_ON_OPEN,
+    FILE_ATTRIBUTE_REPARSE_POINT, FILE_ATTRIBUTE_UNPINNED, FILE_FLAG_BACKUP_SEMANTICS,
+    FILE_FLAG_OPEN_REPARSE_POINT, FILE_READ_ATTRIBUTES, FILE_SHARE_DELETE, FILE_SHARE_READ,
+    FILE_SHARE_WRITE, INVALID_FILE_SIZE, OPEN_EXISTING,
+};
+
+// The path of an entry as the platform's own calls take it. On Windows that's
+// a NUL-terminated wide path in verbatim ("\\?\") form, which isn't limited
+// to MAX_PATH characters; NativeDir builds those of a directory's entries onto
+// the directory's own instead of widening every full path anew.
+#[cfg(unix)]
+pub(crate) type Native = Path;
+
+#[cfg(windows)]
+pub(crate) type Native = [u16];
+
+#[cfg(unix)]
+pub(crate) fn native(path: &Path) -> Cow<'_, Native> {
+    Cow::Borrowed(path)
+}
+
+#[cfg(windows)]
+pub(crate) fn native(path: &Path) -> Cow<'_, Native> {
+    let mut wide = verbatim(path);
+    wide.push(0);
+    Cow::Owned(wide)
+}
+
+pub(crate) struct NativeDir {
+    #[cfg(windows)]
+    wide: Vec<u16>,
+    // Length of the directory's path with its trailing separator
+    #[cfg(windows)]
+    len: usize,
+}
+
+#[cfg(unix)]
+impl NativeDir {
+    pub(crate) fn new(_dir: &Path) -> NativeDir {
+        NativeDir {}
+    }
+
+    pub(crate) fn entry<'p>(&mut self, path: &'p Path) -> &'p Native {
+        path
+    }
+}
+
+#[cfg(windows)]
+impl NativeDir {
+    pub(crate) fn new(dir: &Path) -> NativeDir {
+        let mut wide = verbatim(dir);
+        if wide.last() != Some(&SEPARATOR) {
+            wide.push(SEPARATOR);
+        }
+        NativeDir {
+            len: wide.len(),
+            wide,
+        }
+    }
+
+    // `path` being an entry of the directory
+    pub(crate) fn entry(&mut self, path: &Path) -> &Native {
+        self.wide.truncate(self.len);
+        if let Some(name) = path.file_name() {
+            self.wide.extend(name.encode_wide());
+        }
+        self.wide.push(0);
+        &self.wide
+    }
+}
+
+#[cfg(windows)]
+const SEPARATOR: u16 = b'\\' as u16;
+
+// Helper: `path` made absolute and verbatim, e.g. "\\?\C:\dir" or
+// "\\?\UNC\server\share\dir", without the NUL; verbatim paths are taken
+// as they are, so they can't be relative or hold "." or ".." components
+#[cfg(windows)]
+fn verbatim(path: &Path) -> Vec<u16> {
+    let utf16 = |text: &str| text.encode_utf16().collect::<Vec<u16>>();
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let wide: Vec<u16> = absolute.as_os_str().encode_wide().collect();
+    if wide.starts_with(&utf16(r"\\?\")) || wide.starts_with(&utf16(r"\\.\")) {
+        return wide;
+    }
+    let (mut prefixed, rest) = match wide.strip_prefix(utf16(r"\\").as_slice()) {
+        Some(share) => (utf16(r"\\?\UNC\"), share),
+        None => (utf16(r"\\?\"), wide.as_slice()),
+    };
+    prefixed.extend_from_slice(rest);
+    prefixed
+}
+
+// Error recorded for a directory reached again through a symlink
+#[cfg(unix)]
+pub(crate) fn loop_error() -> io::Error {
+    io::Error::from_raw_os_error(libc::ELOOP)
+}
+
+#[cfg(windows)]
+pub(crate) fn loop_error() -> io::Error {
+    io::Error::from_raw_os_error(ERROR_CANT_RESOLVE_FILENAME.0 as i32)
+}
+
+// Identity of a file or directory: (device, inode) on Unix, (volume
+// serial number, file index) on Windows
+pub(crate) struct FileId {
+    pub dev: u64,
+    pub ino: u64,
+    pub links: u64,
+}
+
+// Allocated size of an entry, and on Windows how much NTFS compression saved
+// on it and how much of its last cluster goes unused
+#[derive(Default)]
+pub(crate) struct Allocation {
+    pub bytes: u64,
+    pub compression_savings: u64,
+    pub slack: u64,
+}
+
+#[cfg(unix)]
+pub(crate) fn allocation(
+    _native: &Native,
+    metadata: &Metadata,
+    _cluster: Option<u64>,
+) -> Allocation {
+    // st_blocks is always in 512-byte units, whatever st_blksize says, and
+    // already covers whole blocks
+    Allocation {
+        bytes: metadata.blocks() * 512,
+        ..Allocation::default()
+    }
+}
+
+// GetCompressedFileSizeW gives the clusters allocated to compressed and sparse
+// files, but just the length of the others, which take up whole clusters of
+// `cluster` bytes. Files resident in the MFT report nothing on disk and have
+// no slack.
+#[cfg(windows)]
+pub(crate) fn allocation(native: &Native, metadata: &Metadata, cluster: Option<u64>) -> Allocation {
+    use std::os::windows::fs::MetadataExt;
+    if !metadata.is_file() {
+        return Allocation {
+            bytes: metadata.len(),
+            ..Allocation::default()
+        };
+    }
+    // Querying a placeholder could have it downloaded, and the data of one
+    // still in the cloud takes no space
+    let on_disk = if is_placeholder(metadata) {
+        if metadata.file_attributes() & CLOUD_ONLY != 0 {
+            0
+        } else {
+            metadata.len()
+        }
+    } else {
+        compressed_file_size(native).unwrap_or(metadata.len())
+    };
+    let bytes = cluster.map_or(on_disk, |cluster| on_disk.div_ceil(cluster) * cluster);
+    let compressed = metadata.file_attributes() & FILE_ATTRIBUTE_COMPRESSED.0 != 0;
+    Allocation {
+        bytes,
+        compression_savings: if compressed {
+            metadata.len().saturating_sub(on_disk)
+        } else {
+            0
+        },
+        slack: bytes - on_disk,
+    }
+}
+
+// Attributes that cloud filters such as OneDrive's set on their placeholders,
+// the reparse tag itself not being exposed by std's directory entries: the
+// data is fetched on access or on open, or the file is pinned to or unpinned
+// from the device
+#[cfg(windows)]
+const CLOUD_ONLY: u32 = FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS.0 | FILE_ATTRIBUTE_RECALL_ON_OPEN.0;
+#[cfg(windows)]
+const CLOUD: u32 = CLOUD_ONLY | FILE_ATTRIBUTE_PINNED.0 | FILE_ATTRIBUTE_UNPINNED.0;
+
+// Whether a file is a cloud placeholder (a reparse point of the
+// IO_REPARSE_TAG_CLOUD family), told from the attributes FindFirstFileExW
+// found for it, so that it's never opened
+#[cfg(unix)]
+pub(crate) fn is_placeholder(_metadata: &Metadata) -> bool {
+    false
+}
+
+#[cfg(windows)]
+pub(crate) fn is_placeholder(metadata: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    let attributes = metadata.file_attributes();
+    attributes & FILE_ATTRIBUTE_REPARSE_POINT.0 != 0 && attributes & CLOUD != 0
+}
+
+// Allocation unit of the volume holding `path`, where the allocated size
+// isn't already in whole units
+#[cfg(unix)]
+pub(crate) fn cluster_size(_root: &Native) -> Option<u64> {
+    None
+}
+
+#[cfg(windows)]
+pub(crate) fn cluster_size(root: &Native) -> Option<u64> {
+    let mut volume = [0u16; 1024];
+    unsafe { GetVolumePathNameW(PCWSTR::from_raw(root.as_ptr()), &mut volume) }.ok()?;
+    let (mut sectors_per_cluster, mut bytes_per_sector) = (0u32, 0u32);
+    unsafe {
+        GetDiskFreeSpaceW(
+            PCWSTR::from_raw(volume.as_ptr()),
+            Some(&mut sectors_per_cluster),
+            Some(&mut bytes_per_sector),
+            None,
+            None,
+        )
+    }
+    .ok()?;
+    let cluster = u64::from(sectors_per_cluster) * u64::from(bytes_per_sector);
+    (cluster > 0).then_some(cluster)
+}
+
+// Whether a regular file has holes, taking up less than its length. Unix has
+// no flag for it, so compressed files (btrfs, ZFS) count as well there.
+#[cfg(unix)]
+pub(crate) fn is_sparse(metadata: &Metadata, allocated: u64) -> bool {
+    allocated < metadata.len()
+}
+
+#[cfg(windows)]
+pub(crate) fn is_sparse(metadata: &Metadata, _allocated: u64) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    use windows::Win32::Storage::FileSystem::FILE_ATTRIBUTE_SPARSE_FILE;
+    metadata.file_attributes() & FILE_ATTRIBUTE_SPARSE_FILE.0 != 0
+}
+
+#[cfg(windows)]
+// Helper: On-disk size of NTFS-compressed and sparse files (the length otherwise)
+fn compressed_file_size(native: &Native) -> Option<u64> {
+    let mut high: u32 = 0;
+    // INVALID_FILE_SIZE is also a valid low word, so the error has to be
+    // told apart through GetLastError
+    unsafe { SetLastError(NO_ERROR) };
+    let low = unsafe { GetCompressedFileSizeW(PCWSTR::from_raw(native.as_ptr()), Some(&mut high)) };
+    if low == INVALID_FILE_SIZE && unsafe { GetLastError() } != NO_ERROR {
+        return None;
+    }
+    Some((u64::from(high) << 32) | u64::from(low))
+}
+
+// `follow` tells whether `metadata` describes the target of a symlink at
+// `native` rather than the link itself
+#[cfg(unix)]
+pub(crate) fn file_id(_native: &Native, metadata: &Metadata, _follow: bool) -> Option<FileId> {
+    Some(FileId {
+        dev: metadata.dev(),
+        ino: metadata.ino(),
+        links: metadata.nlink(),
+    })
+}
+
+#[cfg(windows)]
+// The link count and file index are only available through a handle. Opening
+// with FILE_READ_ATTRIBUTES doesn't touch the data, and reparse points are
+// opened themselves unless followed.
+pub(crate) fn file_id(native: &Native, _metadata: &Metadata, follow: bool) -> Option<FileId> {
+    let mut flags = FILE_FLAG_BACKUP_SEMANTICS;
+    if !follow {
+        flags |= FILE_FLAG_OPEN_REPARSE_POINT;
+    }
+    let handle = unsafe {
+        CreateFileW(
+            PCWSTR::from_raw(native.as_ptr()),
+            FILE_READ_ATTRIBUTES.0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            None,
+            OPEN_EXISTING,
+            flags,
+            None,
+        )
+    }
+    .ok()?;
+    let mut info = BY_HANDLE_FILE_INFORMATION::default();
+    let result = unsafe { GetFileInformationByHandle(handle, &mut info) };
+    let _ = unsafe { CloseHandle(handle) };
+    result.ok()?;
+    Some(FileId {
+        dev: u64::from(info.dwVolumeSerialNumber),
+        ino: (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow),
+        links: u64::from(info.nNumberOfLinks),
+    })
+}
@@ -0,0 +1,577 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Recursive directory size (du). Walks run on OS threads of their own rather
+// than on dirty schedulers, which a walk over millions of files would occupy
+// for minutes; see job.rs.
+
+mod cache;
+mod estimate;
+mod extension;
+mod filter;
+mod fits;
+mod glob;
+mod ignore;
+mod job;
+pub(crate) mod meta;
+mod owner;
+mod progress;
+mod queue;
+mod stream;
+mod top;
+mod walk;
+
+use crate::{
+    atoms, get_path_from_term, make_errno_error_tuple, make_error_tuple, make_error_tuple3,
+    options, path_from_cstring, path_to_term, posix, telemetry,
+};
+use progress::Progress;
+use rustler::{Atom, Encoder, Env, NifResult, Term};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use walk::{Node, Summary};
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Symlinks {
+    // Leave symlinks out entirely
+    Skip,
+    // Count each symlink with its own size
+    CountLink,
+    // Count what the symlink points to, walking linked directories
+    Follow,
+}
+
+impl Symlinks {
+    fn decode(term: Term) -> Option<Symlinks> {
+        let atom: Atom = term.decode().ok()?;
+        if atom == atoms::skip() {
+            Some(Symlinks::Skip)
+        } else if atom == atoms::count_link() {
+            Some(Symlinks::CountLink)
+        } else if atom == atoms::follow() {
+            Some(Symlinks::Follow)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum OnError {
+    // Record the failure and carry on with the rest of the tree
+    Continue,
+    // Stop the walk and fail
+    Halt,
+}
+
+impl OnError {
+    fn decode(term: Term) -> Option<OnError> {
+        let atom: Atom = term.decode().ok()?;
+        if atom == atoms::continue_() {
+            Some(OnError::Continue)
+        } else if atom == atoms::halt() {
+            Some(OnError::Halt)
+        } else {
+            None
+        }
+    }
+}
+
+// What top_files and top_dirs rank by
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum TopBy {
+    Bytes,
+    ApparentBytes,
+}
+
+impl TopBy {
+    fn decode(term: Term) -> Option<TopBy> {
+        let atom: Atom = term.decode().ok()?;
+        if atom == atoms::bytes() {
+            Some(TopBy::Bytes)
+        } else if atom == atoms::apparent_bytes() {
+            Some(TopBy::ApparentBytes)
+        } else {
+            None
+        }
+    }
+}
+
+// How cloud placeholders count on Windows
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Placeholders {
+    // At their full length, as if they were local
+    Nominal,
+    // At the space their local data takes, none if it's only in the cloud
+    OnDisk,
+    // Not at all
+    Exclude,
+}
+
+impl Placeholders {
+    fn decode(term: Term) -> Option<Placeholders> {
+        let atom: Atom = term.decode().ok()?;
+        if atom == atoms::nominal() {
+            Some(Placeholders::Nominal)
+        } else if atom == atoms::on_disk() {
+            Some(Placeholders::OnDisk)
+        } else if atom == atoms::exclude() {
+            Some(Placeholders::Exclude)
+        } else {
+            None
+        }
+    }
+}
+
+// How du returns the nodes down to max_depth
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Shape {
+    // Not at all, just the totals
+    Summary,
+    // As a list under `entries`
+    Flat,
+    // Nested under `children`
+    Tree,
+}
+
+impl Shape {
+    fn decode(term: Term) -> Option<Shape> {
+        let atom: Atom = term.decode().ok()?;
+        if atom == atoms::summary() {
+            Some(Shape::Summary)
+        } else if atom == atoms::flat() {
+            Some(Shape::Flat)
+        } else if atom == atoms::tree() {
+            Some(Shape::Tree)
+        } else {
+            None
+        }
+    }
+}
+
+pub(crate) struct Options {
+    pub dedupe_hardlinks: bool,
+    pub one_file_system: bool,
+    pub max_depth: u32,
+    pub exclude: glob::Matcher,
+    pub symlinks: Symlinks,
+    pub workers: usize,
+    pub on_error: OnError,
+    pub max_errors: usize,
+    pub progress: Option<progress::Reporter>,
+    pub top_files: usize,
+    pub top_dirs: usize,
+    pub top_dirs_nested: bool,
+    pub top_by: TopBy,
+    pub by_extension: Option<extension::Extensions>,
+    pub cache: Option<cache::Handle>,
+    pub ignore_files: Vec<String>,
+    pub count_placeholders: Placeholders,
+    pub filter: Option<filter::Filter>,
+    pub by_owner: bool,
+    pub resolve_names: bool,
+    pub shape: Shape,
+}
+
+impl Options {
+    fn decode(opts: Term) -> Result<Options, Atom> {
+        let exclude: Vec<String> = options::get(opts, atoms::exclude())?.unwrap_or_default();
+        let mut options = Options {
+            dedupe_hardlinks: options::get(opts, atoms::dedupe_hardlinks())?.unwrap_or(true),
+            one_file_system: options::get(opts, atoms::one_file_system())?.unwrap_or(false),
+            max_depth: options::get(opts, atoms::max_depth())?.unwrap_or(0),
+            exclude: glob::Matcher::new(&exclude),
+            symlinks: options::get_with(opts, atoms::symlinks(), Symlinks::decode)?
+                .unwrap_or(Symlinks::CountLink),
+            workers: options::get_with(opts, atoms::workers(), |value| {
+                value.decode::<usize>().ok().filter(|n| *n > 0)
+            })?
+            .unwrap_or_else(default_workers),
+            on_error: options::get_with(opts, atoms::on_error(), OnError::decode)?
+                .unwrap_or(OnError::Continue),
+            max_errors: options::get(opts, atoms::max_errors())?.unwrap_or(1000),
+            progress: progress::Reporter::decode(opts)?,
+            top_files: options::get(opts, atoms::top_files())?.unwrap_or(0),
+            top_dirs: options::get(opts, atoms::top_dirs())?.unwrap_or(0),
+            top_dirs_nested: options::get(opts, atoms::top_dirs_nested())?.unwrap_or(false),
+            top_by: options::get_with(opts, atoms::top_by(), TopBy::decode)?
+                .unwrap_or(TopBy::Bytes),
+            by_extension: decode_by_extension(opts)?,
+            cache: None,
+            ignore_files: options::get(opts, atoms::respect_ignore_files())?.unwrap_or_default(),
+            count_placeholders: options::get_with(
+                opts,
+                atoms::count_placeholders(),
+                Placeholders::decode,
+            )?
+            .unwrap_or(Placeholders::OnDisk),
+            filter: filter::Filter::decode(opts)?,
+            by_owner: options::get(opts, atoms::by_owner())?.unwrap_or(false),
+            resolve_names: options::get(opts, atoms::resolve_names())?.unwrap_or(false),
+            shape: options::get_with(opts, atoms::shape(), Shape::decode)?.unwrap_or(Shape::Tree),
+        };
+        options.cache = cache::Handle::decode(opts, options.cache_signature(&exclude))?;
+        Ok(options)
+    }
+
+    // Helper: Tells apart the options that change what a directory adds up
+    // to, so that cached contents are only reused by walks that agree on them
+    fn cache_signature(&self, exclude: &[String]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.dedupe_hardlinks.hash(&mut hasher);
+        self.one_file_system.hash(&mut hasher);
+        (self.symlinks as u8).hash(&mut hasher);
+        exclude.hash(&mut hasher);
+        self.ignore_files.hash(&mut hasher);
+        (self.count_placeholders as u8).hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+// Helper: The by_extension and compound_extensions options
+fn decode_by_extension(opts: Term) -> Result<Option<extension::Extensions>, Atom> {
+    let compound: Vec<String> =
+        options::get(opts, atoms::compound_extensions())?.unwrap_or_default();
+    let by_extension = options::get(opts, atoms::by_extension())?.unwrap_or(false);
+    Ok(by_extension.then(|| extension::Extensions::new(&compound)))
+}
+
+// Helper: One walker per CPU, up to 4; more rarely helps a single disk
+fn default_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get().min(4))
+}
+
+// Helper: Decode the path and options of du_start
+fn decode_args<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    opts: Term<'a>,
+) -> Result<(PathBuf, Options), NifResult<Term<'a>>> {
+    let options = match Options::decode(opts) {
+        Ok(options) => options,
+        Err(key) => {
+            return Err(make_error_tuple3(
+                env,
+                atoms::invalid_option(),
+                key.to_term(env),
+            ))
+        }
+    };
+    match get_path_from_term(env, path_term)
+        .ok()
+        .as_ref()
+        .and_then(path_from_cstring)
+    {
+        Some(root) => Ok((root, options)),
+        None => Err(make_error_tuple(env, atoms::invalid_path())),
+    }
+}
+
+// Helper: {:ok, summary}, {:error, :cancelled, partial_summary}, or an error
+fn encode_result<'a>(
+    env: Env<'a>,
+    walked: io::Result<Summary>,
+    options: &Options,
+) -> NifResult<Term<'a>> {
+    let summary = match walked {
+        Ok(summary) => summary,
+        Err(e) => return make_errno_error_tuple(env, atoms::invalid_path(), e),
+    };
+    if let Some((path, e)) = &summary.halted {
+        return make_error_tuple3(env, atoms::du_failed(), encode_error(env, path, e)?);
+    }
+    let map = encode_summary(env, &summary, options)?;
+    if summary.cancelled {
+        return make_error_tuple3(env, atoms::cancelled(), map);
+    }
+    Ok(rustler::types::tuple::make_tuple(
+        env,
+        &[atoms::ok().to_term(env), map],
+    ))
+}
+
+// Helper: Walk, reporting progress if asked to, counted for nif_stats
+fn run_walk(
+    root: &Path,
+    options: &Options,
+    cancel: &AtomicBool,
+    stream: Option<&stream::Stream>,
+) -> io::Result<Summary> {
+    telemetry::timed(
+        telemetry::Op::Du,
+        || walk_with_progress(root, options, cancel, stream),
+        |walked| match walked {
+            Err(_) => Some(atoms::invalid_path()),
+            Ok(summary) if summary.halted.is_some() => Some(atoms::du_failed()),
+            Ok(summary) if summary.cancelled => Some(atoms::cancelled()),
+            Ok(_) => None,
+        },
+    )
+}
+
+fn walk_with_progress(
+    root: &Path,
+    options: &Options,
+    cancel: &AtomicBool,
+    stream: Option<&stream::Stream>,
+) -> io::Result<Summary> {
+    match &options.progress {
+        Some(reporter) => {
+            let progress = Progress::default();
+            reporter.run(&progress, || {
+                walk::walk(root, options, cancel, Some(&progress), stream)
+            })
+        }
+        None => walk::walk(root, options, cancel, None, stream),
+    }
+}
+
+fn encode_summary<'a>(env: Env<'a>, summary: &Summary, options: &Options) -> NifResult<Term<'a>> {
+    let errors = summary
+        .errors
+        .iter()
+        .map(|(path, e)| encode_error(env, path, e))
+        .collect::<NifResult<Vec<Term>>>()?;
+    let skipped_mounts: Vec<Term> = summary
+        .skipped_mounts
+        .iter()
+        .map(|path| path_to_term(env, path))
+        .collect();
+    let deduplicated_dirs: Vec<Term> = summary
+        .deduplicated_dirs
+        .iter()
+        .map(|path| path_to_term(env, path))
+        .collect();
+    let map = rustler::types::map::map_new(env)
+        .map_put(atoms::bytes().to_term(env), summary.bytes)?
+        .map_put(atoms::apparent_bytes().to_term(env), summary.apparent_bytes)?
+        .map_put(atoms::files().to_term(env), summary.files)?
+        .map_put(atoms::dirs().to_term(env), summary.dirs)?
+        .map_put(atoms::symlinks().to_term(env), summary.symlinks)?
+        .map_put(atoms::other().to_term(env), summary.other)?
+        .map_put(atoms::inodes().to_term(env), summary.inodes())?
+        .map_put(
+            atoms::hardlinked_saved_bytes().to_term(env),
+            summary.hardlinked_saved_bytes,
+        )?
+        .map_put(atoms::sparse_files().to_term(env), summary.sparse_files)?
+        .map_put(
+            atoms::sparse_savings_bytes().to_term(env),
+            summary.sparse_savings_bytes,
+        )?
+        .map_put(atoms::skipped_mounts().to_term(env), skipped_mounts)?
+        .map_put(atoms::deduplicated_dirs().to_term(env), deduplicated_dirs)?
+        .map_put(
+            atoms::excluded_entries().to_term(env),
+            summary.excluded_entries,
+        )?
+        .map_put(atoms::excluded_bytes().to_term(env), summary.excluded_bytes)?
+        .map_put(
+            atoms::ignored_entries().to_term(env),
+            summary.ignored_entries,
+        )?
+        .map_put(atoms::ignored_bytes().to_term(env), summary.ignored_bytes)?
+        .map_put(atoms::errors().to_term(env), errors.encode(env))?
+        .map_put(atoms::error_count().to_term(env), summary.error_count)?;
+    #[cfg(windows)]
+    let map = map
+        .map_put(
+            atoms::compression_savings_bytes().to_term(env),
+            summary.compression_savings_bytes,
+        )?
+        .map_put(atoms::slack_bytes().to_term(env), summary.slack_bytes)?
+        .map_put(
+            atoms::placeholder_files().to_term(env),
+            summary.placeholder_files,
+        )?
+        .map_put(
+            atoms::placeholder_bytes().to_term(env),
+            summary.placeholder_bytes,
+        )?;
+    let map = if options.top_files > 0 {
+        map.map_put(
+            atoms::top_files().to_term(env),
+            encode_top(env, &summary.top_files),
+        )?
+    } else {
+        map
+    };
+    let map = if options.top_dirs > 0 {
+        map.map_put(
+            atoms::top_dirs().to_term(env),
+            encode_top(env, &summary.top_dirs),
+        )?
+    } else {
+        map
+    };
+    let map = if options.filter.is_some() {
+        let matching = rustler::types::map::map_new(env)
+            .map_put(atoms::bytes().to_term(env), summary.matching_bytes)?
+            .map_put(
+                atoms::apparent_bytes().to_term(env),
+                summary.matching_apparent_bytes,
+            )?
+            .map_put(atoms::files().to_term(env), summary.matching_files)?;
+        map.map_put(atoms::matching().to_term(env), matching)?
+    } else {
+        map
+    };
+    let map = if options.by_extension.is_some() {
+        map.map_put(
+            atoms::by_extension().to_term(env),
+            encode_by_extension(env, summary)?,
+        )?
+    } else {
+        map
+    };
+    let map = if options.by_owner {
+        map.map_put(
+            atoms::by_owner().to_term(env),
+            encode_by_owner(env, summary, options.resolve_names)?,
+        )?
+    } else {
+        map
+    };
+    if options.max_depth == 0 {
+        return Ok(map);
+    }
+    match options.shape {
+        Shape::Summary => Ok(map),
+        Shape::Flat => map.map_put(
+            atoms::entries().to_term(env),
+            encode_flat(env, &summary.nodes, options.max_depth)?,
+        ),
+        Shape::Tree => map.map_put(
+            atoms::children().to_term(env),
+            encode_tree(env, &summary.nodes, options.max_depth)?,
+        ),
+    }
+}
+
+// Helper: [{path, size}]
+fn encode_top<'a>(env: Env<'a>, top: &[(PathBuf, u64)]) -> Term<'a> {
+    top.iter()
+        .map(|(path, size)| (path_to_term(env, path), *size).encode(env))
+        .collect::<Vec<Term>>()
+        .encode(env)
+}
+
+// Helper: %{"log" => %{bytes: b, files: f}, ..., none: %{...}}
+fn encode_by_extension<'a>(env: Env<'a>, summary: &Summary) -> NifResult<Term<'a>> {
+    let mut map = rustler::types::map::map_new(env);
+    for (ext, (bytes, files)) in &summary.by_extension {
+        let key = match ext {
+            Some(ext) => ext.encode(env),
+            None => atoms::none().to_term(env),
+        };
+        let value = rustler::types::map::map_new(env)
+            .map_put(atoms::bytes().to_term(env), *bytes)?
+            .map_put(atoms::files().to_term(env), *files)?;
+        map = map.map_put(key, value)?;
+    }
+    Ok(map)
+}
+
+// Helper: %{1000 => %{bytes: b, files: f}, ..., unknown: %{...}}, keyed by
+// uid or SID, with the owner's :name as well if asked to
+fn encode_by_owner<'a>(
+    env: Env<'a>,
+    summary: &Summary,
+    resolve_names: bool,
+) -> NifResult<Term<'a>> {
+    let mut map = rustler::types::map::map_new(env);
+    for (owner, (bytes, files)) in &summary.by_owner {
+        let key = match owner {
+            Some(owner) => owner.encode(env),
+            None => atoms::unknown().to_term(env),
+        };
+        let value = rustler::types::map::map_new(env)
+            .map_put(atoms::bytes().to_term(env), *bytes)?
+            .map_put(atoms::files().to_term(env), *files)?;
+        let value = match owner {
+            Some(owner) if resolve_names => {
+                value.map_put(atoms::name().to_term(env), owner::name(owner))?
+            }
+            _ => value,
+        };
+        map = map.map_put(key, value)?;
+    }
+    Ok(map)
+}
+
+// Helper: %{path, errno, errstr, posix} for an entry that could not be read
+fn encode_error<'a>(env: Env<'a>, path: &Path, e: &io::Error) -> NifResult<Term<'a>> {
+    rustler::types::map::map_new(env)
+        .map_put(atoms::path().to_term(env), path_to_term(env, path))?
+        .map_put(atoms::errno().to_term(env), e.raw_os_error().unwrap_or(0))?
+        .map_put(atoms::errstr().to_term(env), e.to_string())?
+        .map_put(atoms::posix().to_term(env), posix::atom(env, e))
+}
+
+// Helper: The nodes down to max_depth below each node, and those directly
+// below the root, largest bytes first
+fn arrange(nodes: &[Node], max_depth: u32) -> (Vec<Vec<usize>>, Vec<usize>) {
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+    let mut top = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if node.depth > max_depth {
+            continue;
+        }
+        match node.parent {
+            Some(parent) => children[parent].push(i),
+            None => top.push(i),
+        }
+    }
+    // Ties are broken by path, as workers create nodes in no particular order
+    let by_size = |list: &mut Vec<usize>| {
+        list.sort_by(|a, b| {
+            let (a, b) = (&nodes[*a], &nodes[*b]);
+            b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path))
+        })
+    };
+    children.iter_mut().for_each(by_size);
+    by_size(&mut top);
+    (children, top)
+}
+
+// Helper: Nests the nodes down to max_depth into `children` lists, largest
+// first. Children come after their parent, so building the terms backwards
+// needs no recursion.
+fn encode_tree<'a>(env: Env<'a>, nodes: &[Node], max_depth: u32) -> NifResult<Term<'a>> {
+    let (children, top) = arrange(nodes, max_depth);
+    let mut terms: Vec<Option<Term<'a>>> = vec![None; nodes.len()];
+    for i in (0..nodes.len()).rev() {
+        if nodes[i].depth > max_depth {
+            continue;
+        }
+        let child_terms: Vec<Term> = children[i]
+            .iter()
+            .filter_map(|c| terms[*c].take())
+            .collect();
+        let map = stream::encode_entry(env, &nodes[i])?
+            .map_put(atoms::children().to_term(env), child_terms)?;
+        terms[i] = Some(map);
+    }
+    Ok(top
+        .iter()
+        .filter_map(|i| terms[*i].take())
+        .collect::<Vec<Term>>()
+        .encode(env))
+}
+
+// Helper: The nodes of encode_tree in the same order, depth first, as a list
+// of entries with their `depth` instead of `children`
+fn encode_flat<'a>(env: Env<'a>, nodes: &[Node], max_depth: u32) -> NifResult<Term<'a>> {
+    let (children, top) = arrange(nodes, max_depth);
+    let mut entries = Vec::new();
+    let mut stack: Vec<usize> = top.into_iter().rev().collect();
+    while let Some(i) = stack.pop() {
+        let entry = stream::encode_entry(env, &nodes[i])?
+            .map_put(atoms::depth().to_term(env), nodes[i].depth)?;
+        entries.push(entry);
+        stack.extend(children[i].iter().rev());
+    }
+    Ok(entries.encode(env))
+}
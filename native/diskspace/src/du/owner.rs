@@ -0,0 +1,118 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// File owners for by_owner: the uid on Unix, straight from the metadata, and
+// the owner SID in its string form ("S-1-5-21-...") on Windows, where it has
+// to be read from each file's security descriptor. Names are only looked up
+// once per owner, when the result is encoded.
+
+use super::meta::Native;
+use std::fs::Metadata;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(windows)]
+use windows::core::{PCWSTR, PWSTR};
+#[cfg(windows)]
+use windows::Win32::Foundation::{LocalFree, ERROR_SUCCESS, HLOCAL};
+#[cfg(windows)]
+use windows::Win32::Security::Authorization::{
+    ConvertSidToStringSidW, ConvertStringSidToSidW, GetNamedSecurityInfoW, SE_FILE_OBJECT,
+};
+#[cfg(windows)]
+use windows::Win32::Security::{
+    LookupAccountSidW, OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, PSID, SID_NAME_USE,
+};
+
+#[cfg(unix)]
+pub(crate) type Owner = u32;
+
+#[cfg(windows)]
+pub(crate) type Owner = String;
+
+#[cfg(unix)]
+pub(crate) fn of(_native: &Native, metadata: &Metadata) -> Option<Owner> {
+    Some(metadata.uid())
+}
+
+#[cfg(windows)]
+// Reading the security descriptor opens the file for READ_CONTROL, which
+// doesn't touch its data; None if that's denied
+pub(crate) fn of(native: &Native, _metadata: &Metadata) -> Option<Owner> {
+    let mut sid = PSID::default();
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    let status = unsafe {
+        GetNamedSecurityInfoW(
+            PCWSTR::from_raw(native.as_ptr()),
+            SE_FILE_OBJECT,
+            OWNER_SECURITY_INFORMATION,
+            Some(&mut sid),
+            None,
+            None,
+            None,
+            &mut descriptor,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return None;
+    }
+    // The SID points into the descriptor, so it's converted before freeing
+    let mut string = PWSTR::null();
+    let converted = unsafe { ConvertSidToStringSidW(sid, &mut string) };
+    let owner = converted.ok().and_then(|()| {
+        let owner = unsafe { string.to_string() }.ok();
+        unsafe { LocalFree(Some(HLOCAL(string.0.cast()))) };
+        owner
+    });
+    unsafe { LocalFree(Some(HLOCAL(descriptor.0))) };
+    owner
+}
+
+// The user name of `owner`, or its id if it has none
+#[cfg(unix)]
+pub(crate) fn name(owner: &Owner) -> String {
+    use nix::unistd::{Uid, User};
+    match User::from_uid(Uid::from_raw(*owner)) {
+        Ok(Some(user)) => user.name,
+        _ => owner.to_string(),
+    }
+}
+
+#[cfg(windows)]
+// "DOMAIN\name", or just the name for accounts without a domain
+pub(crate) fn name(owner: &Owner) -> String {
+    lookup_account(owner).unwrap_or_else(|| owner.clone())
+}
+
+#[cfg(windows)]
+fn lookup_account(owner: &str) -> Option<String> {
+    let wide: Vec<u16> = owner.encode_utf16().chain(Some(0)).collect();
+    let mut sid = PSID::default();
+    unsafe { ConvertStringSidToSidW(PCWSTR::from_raw(wide.as_ptr()), &mut sid) }.ok()?;
+    let mut name = [0u16; 256];
+    let mut domain = [0u16; 256];
+    let (mut name_len, mut domain_len) = (name.len() as u32, domain.len() as u32);
+    let mut use_ = SID_NAME_USE::default();
+    let found = unsafe {
+        LookupAccountSidW(
+            PCWSTR::null(),
+            sid,
+            Some(PWSTR(name.as_mut_ptr())),
+            &mut name_len,
+            Some(PWSTR(domain.as_mut_ptr())),
+            &mut domain_len,
+            &mut use_,
+        )
+    };
+    unsafe { LocalFree(Some(HLOCAL(sid.0))) };
+    found.ok()?;
+    let name = String::from_utf16_lossy(&name[..name_len as usize]);
+    Some(match domain_len {
+        0 => name,
+        len => format!(
+            "{}\\{}",
+            String::from_utf16_lossy(&domain[..len as usize]),
+            name
+        ),
+    })
+}
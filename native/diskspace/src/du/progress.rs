@@ -0,0 +1,140 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Progress reports for long walks. Walkers add to shared counters after each
+// directory; a reporter thread of its own samples them at a fixed interval
+// and sends {:du_progress, ref, %{...}} to the requested process, so the
+// walk never waits on message passing.
+
+use super::walk::Summary;
+use crate::{atoms, options, path_to_term};
+use rustler::env::OwnedEnv;
+use rustler::{Atom, Env, LocalPid, NifResult, Term};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+// Counters published by the walkers
+#[derive(Default)]
+pub(crate) struct Progress {
+    bytes: AtomicU64,
+    apparent_bytes: AtomicU64,
+    files: AtomicU64,
+    current_path: Mutex<PathBuf>,
+}
+
+impl Progress {
+    // Adds what a walker counted in `dir` since `before`
+    pub(crate) fn add(&self, dir: &Path, before: &Summary, after: &Summary) {
+        self.bytes
+            .fetch_add(after.bytes - before.bytes, Ordering::Relaxed);
+        self.apparent_bytes.fetch_add(
+            after.apparent_bytes - before.apparent_bytes,
+            Ordering::Relaxed,
+        );
+        self.files
+            .fetch_add(after.files - before.files, Ordering::Relaxed);
+        let mut current_path = self
+            .current_path
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        current_path.clear();
+        current_path.push(dir);
+    }
+
+    fn encode<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        let current_path = self
+            .current_path
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        rustler::types::map::map_new(env)
+            .map_put(
+                atoms::bytes_so_far().to_term(env),
+                self.bytes.load(Ordering::Relaxed),
+            )?
+            .map_put(
+                atoms::apparent_bytes_so_far().to_term(env),
+                self.apparent_bytes.load(Ordering::Relaxed),
+            )?
+            .map_put(
+                atoms::files_so_far().to_term(env),
+                self.files.load(Ordering::Relaxed),
+            )?
+            .map_put(
+                atoms::current_path().to_term(env),
+                path_to_term(env, &current_path),
+            )
+    }
+}
+
+// Where and how often to report, from the `progress: {pid, ref}` and
+// `progress_interval: ms` options
+pub(crate) struct Reporter {
+    pid: LocalPid,
+    // In external term format, as the message environment is cleared after
+    // each send
+    reference: Vec<u8>,
+    interval: Duration,
+}
+
+impl Reporter {
+    pub(crate) fn decode(opts: Term) -> Result<Option<Reporter>, Atom> {
+        let interval: u64 = options::get_with(opts, atoms::progress_interval(), |value| {
+            value.decode::<u64>().ok().filter(|ms| *ms > 0)
+        })?
+        .unwrap_or(100);
+        options::get_with(opts, atoms::progress(), |value| {
+            let (pid, reference): (LocalPid, Term) = value.decode().ok()?;
+            Some(Reporter {
+                pid,
+                reference: reference.to_binary().as_slice().to_vec(),
+                interval: Duration::from_millis(interval),
+            })
+        })
+    }
+
+    // Runs `walk` while reporting on `progress` from a thread of its own
+    pub(crate) fn run<R>(&self, progress: &Progress, walk: impl FnOnce() -> R) -> R {
+        let done = (Mutex::new(false), Condvar::new());
+        thread::scope(|scope| {
+            // Should the thread fail to start, the walk just goes unreported
+            let _ = thread::Builder::new()
+                .name("disk_space_du_progress".to_string())
+                .spawn_scoped(scope, || self.report(progress, &done));
+            let result = walk();
+            *done.0.lock().unwrap_or_else(PoisonError::into_inner) = true;
+            done.1.notify_all();
+            result
+        })
+    }
+
+    fn report(&self, progress: &Progress, done: &(Mutex<bool>, Condvar)) {
+        let mut env = OwnedEnv::new();
+        let (lock, wakeup) = done;
+        let mut finished = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            finished = wakeup
+                .wait_timeout(finished, self.interval)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+            if *finished {
+                return;
+            }
+            let sent = env.send_and_clear(&self.pid, |env| {
+                let reference = env
+                    .binary_to_term(&self.reference)
+                    .map_or_else(|| atoms::error().to_term(env), |(term, _)| term);
+                let progress = progress
+                    .encode(env)
+                    .unwrap_or_else(|_| atoms::error().to_term(env));
+                (atoms::du_progress(), reference, progress)
+            });
+            // Nobody is listening any more
+            if sent.is_err() {
+                return;
+            }
+        }
+    }
+}
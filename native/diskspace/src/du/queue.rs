@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Work queue shared by the walker threads. Each worker drains its own stack
+// of pending directories and only hands part of it over while another worker
+// sits idle, so the lock is rarely taken during a busy walk. The walk is over
+// once every worker is idle and nothing is left to take.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+pub(crate) struct Queue<T> {
+    state: Mutex<State<T>>,
+    wakeup: Condvar,
+    // Mirrors State::idle so that busy workers can check it without locking
+    idle: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+struct State<T> {
+    items: Vec<T>,
+    workers: usize,
+    idle: usize,
+    done: bool,
+}
+
+impl<T> Queue<T> {
+    pub(crate) fn new(workers: usize) -> Queue<T> {
+        Queue {
+            state: Mutex::new(State {
+                items: Vec::new(),
+                workers,
+                idle: 0,
+                done: false,
+            }),
+            wakeup: Condvar::new(),
+            idle: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Called by a worker whose own stack is empty. Blocks until there is
+    // something to take, or returns None once the walk is over.
+    pub(crate) fn take(&self) -> Option<T> {
+        let mut state = self.lock();
+        state.idle += 1;
+        loop {
+            if let Some(item) = state.items.pop() {
+                state.idle -= 1;
+                self.idle.store(state.idle, Ordering::Relaxed);
+                return Some(item);
+            }
+            if state.done || state.idle >= state.workers {
+                state.done = true;
+                self.wakeup.notify_all();
+                return None;
+            }
+            self.idle.store(state.idle, Ordering::Relaxed);
+            state = self
+                .wakeup
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    // Moves the oldest half of a worker's stack (the shallowest directories,
+    // likely the largest subtrees) to the queue if another worker is idle
+    pub(crate) fn share(&self, local: &mut Vec<T>) {
+        if local.len() < 2 || self.idle.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut state = self.lock();
+        state.items.extend(local.drain(..local.len() / 2));
+        self.wakeup.notify_all();
+    }
+
+    // For a worker that never started or stopped early, so that the others
+    // don't wait for it
+    pub(crate) fn leave(&self) {
+        let mut state = self.lock();
+        state.workers -= 1;
+        self.wakeup.notify_all();
+    }
+
+    // Ends the walk for every worker, e.g. after a worker panicked. Busy
+    // workers are expected to check is_stopped between entries.
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.lock().done = true;
+        self.wakeup.notify_all();
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+    use std::thread;
+
+    // Each item n > 0 spawns the items n - 1 and n - 1, so a walk from
+    // n = 12 processes 2^13 - 1 items in total
+    fn run(queue: &Queue<u32>, processed: &AtomicU64) {
+        let mut local = Vec::new();
+        loop {
+            let Some(n) = local.pop().or_else(|| queue.take()) else {
+                return;
+            };
+            processed.fetch_add(1, Ordering::Relaxed);
+            if n > 0 {
+                local.extend([n - 1, n - 1]);
+            }
+            queue.share(&mut local);
+        }
+    }
+
+    #[test]
+    fn processes_every_item_once_across_workers() {
+        for workers in [1, 2, 8] {
+            let queue = Queue::new(workers);
+            queue.lock().items.push(12);
+            let processed = AtomicU64::new(0);
+            thread::scope(|scope| {
+                for _ in 0..workers {
+                    scope.spawn(|| run(&queue, &processed));
+                }
+            });
+            assert_eq!(processed.load(Ordering::Relaxed), (1 << 13) - 1);
+        }
+    }
+
+    #[test]
+    fn finishes_when_workers_leave() {
+        let queue = Queue::new(3);
+        queue.lock().items.push(4);
+        queue.leave();
+        queue.leave();
+        let processed = AtomicU64::new(0);
+        run(&queue, &processed);
+        assert_eq!(processed.load(Ordering::Relaxed), (1 << 5) - 1);
+    }
+
+    #[test]
+    fn stop_releases_waiting_workers() {
+        let queue: Queue<u32> = Queue::new(2);
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| queue.take());
+            while queue.idle.load(Ordering::Relaxed) == 0 {
+                thread::yield_now();
+            }
+            queue.stop();
+            assert_eq!(waiting.join().unwrap(), None);
+            assert!(queue.is_stopped());
+        });
+    }
+}
@@ -0,0 +1,196 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Streamed du results: {:du_entry, ref, %{...}} for each directory once its
+// whole subtree has been walked, so children always come before their
+// parent. Each directory waits for its own read and for each subdirectory;
+// the last of these to finish completes it and, in turn, its parent.
+//
+// At most max_in_flight entries are unacknowledged at a time. Once that many
+// are out, the walker completing a directory waits for du_ack(ref) while
+// holding the lock, which pauses the whole walk until the consumer catches
+// up. Entries are sent under the lock as well, which keeps them in order.
+
+use super::walk::{Node, Totals};
+use crate::{atoms, options, path_to_term};
+use rustler::env::OwnedEnv;
+use rustler::{Atom, Env, LocalPid, NifResult, Term};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+// Windows of the running streams by reference (in external term format), for
+// du_ack
+static WINDOWS: LazyLock<Mutex<HashMap<Vec<u8>, Arc<Window>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Window {
+    in_flight: Mutex<usize>,
+    acked: Condvar,
+    max: usize,
+}
+
+impl Window {
+    // Waits for room for one more entry; false if the walk got cancelled
+    fn acquire(&self, cancel: &AtomicBool) -> bool {
+        let mut in_flight = lock(&self.in_flight);
+        while *in_flight >= self.max {
+            if cancel.load(Ordering::Relaxed) {
+                return false;
+            }
+            in_flight = self
+                .acked
+                .wait_timeout(in_flight, Duration::from_millis(50))
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        *in_flight += 1;
+        true
+    }
+
+    fn release(&self) {
+        let mut in_flight = lock(&self.in_flight);
+        *in_flight = in_flight.saturating_sub(1);
+        self.acked.notify_all();
+    }
+}
+
+// A directory still waiting for its read or for some of its subdirectories
+struct Open {
+    node: Node,
+    remaining: u32,
+}
+
+pub(crate) struct Stream {
+    pid: LocalPid,
+    reference: Vec<u8>,
+    window: Arc<Window>,
+    // Indexed like the walk's nodes; None once sent
+    dirs: Mutex<Vec<Option<Open>>>,
+}
+
+impl Stream {
+    // From the `max_in_flight` option; registers the stream for du_ack
+    pub(crate) fn new(opts: Term, pid: LocalPid, reference: Term) -> Result<Stream, Atom> {
+        let max = options::get_with(opts, atoms::max_in_flight(), |value| {
+            value.decode::<usize>().ok().filter(|n| *n > 0)
+        })?
+        .unwrap_or(64);
+        let window = Arc::new(Window {
+            in_flight: Mutex::new(0),
+            acked: Condvar::new(),
+            max,
+        });
+        let reference = reference.to_binary().as_slice().to_vec();
+        lock(&WINDOWS).insert(reference.clone(), window.clone());
+        Ok(Stream {
+            pid,
+            reference,
+            window,
+            dirs: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub(crate) fn reference<'a>(&self, env: Env<'a>) -> Term<'a> {
+        env.binary_to_term(&self.reference)
+            .map_or_else(|| atoms::error().to_term(env), |(term, _)| term)
+    }
+
+    // A directory entered by the walk, `node` counting just itself; it is
+    // complete once read, along with all subdirectories opened meanwhile
+    pub(crate) fn open(&self, index: usize, node: Node) {
+        let mut dirs = lock(&self.dirs);
+        if let Some(Some(parent)) = node.parent.map(|parent| &mut dirs[parent]) {
+            parent.remaining += 1;
+        }
+        if dirs.len() <= index {
+            dirs.resize_with(index + 1, || None);
+        }
+        dirs[index] = Some(Open { node, remaining: 1 });
+    }
+
+    // The directory `index` has been read, its entries adding up to `totals`.
+    // Sends it and whichever ancestors that completes. A receiver that is
+    // gone cancels the walk, as nothing would come of it.
+    pub(crate) fn read(&self, index: usize, totals: &Totals, cancel: &AtomicBool) {
+        let mut dirs = lock(&self.dirs);
+        let mut next = Some((index, *totals));
+        while let Some((i, totals)) = next.take() {
+            let Some(open) = dirs[i].as_mut() else {
+                return;
+            };
+            open.node.add(&totals);
+            open.remaining -= 1;
+            if open.remaining > 0 {
+                return;
+            }
+            let Some(Open { node, .. }) = dirs[i].take() else {
+                return;
+            };
+            next = node.parent.map(|parent| (parent, node.totals()));
+            if !cancel.load(Ordering::Relaxed) && !self.send(&node, cancel) {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Helper: Sends one entry once there is room for it
+    fn send(&self, node: &Node, cancel: &AtomicBool) -> bool {
+        if !self.window.acquire(cancel) {
+            return true;
+        }
+        let sent = OwnedEnv::new().send_and_clear(&self.pid, |env| {
+            let entry = encode_entry(env, node).unwrap_or_else(|_| atoms::error().to_term(env));
+            (atoms::du_entry(), self.reference(env), entry)
+        });
+        sent.is_ok()
+    }
+
+    // Sends {:du_done, ref, result}, the result built by `encode`
+    pub(crate) fn done(&self, encode: impl FnOnce(Env) -> Term) {
+        let _ = OwnedEnv::new().send_and_clear(&self.pid, |env| {
+            (atoms::du_done(), self.reference(env), encode(env))
+        });
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let mut windows = lock(&WINDOWS);
+        // The same reference may have been reused for a newer stream
+        if windows
+            .get(&self.reference)
+            .is_some_and(|window| Arc::ptr_eq(window, &self.window))
+        {
+            windows.remove(&self.reference);
+        }
+    }
+}
+
+// Helper: %{path, bytes, apparent_bytes, files, dirs, symlinks, other}
+pub(super) fn encode_entry<'a>(env: Env<'a>, node: &Node) -> NifResult<Term<'a>> {
+    rustler::types::map::map_new(env)
+        .map_put(atoms::path().to_term(env), path_to_term(env, &node.path))?
+        .map_put(atoms::bytes().to_term(env), node.bytes)?
+        .map_put(atoms::apparent_bytes().to_term(env), node.apparent_bytes)?
+        .map_put(atoms::files().to_term(env), node.files)?
+        .map_put(atoms::dirs().to_term(env), node.dirs)?
+        .map_put(atoms::symlinks().to_term(env), node.symlinks)?
+        .map_put(atoms::other().to_term(env), node.other)
+}
+
+// Acknowledging an unknown or finished stream does nothing
+#[rustler::nif]
+fn du_ack_nif(reference: Term) -> Atom {
+    let key = reference.to_binary().as_slice().to_vec();
+    let window = lock(&WINDOWS).get(&key).cloned();
+    if let Some(window) = window {
+        window.release();
+    }
+    atoms::ok()
+}
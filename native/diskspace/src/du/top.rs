@@ -0,0 +1,252 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Selection of the largest entries of a walk. Files go through a min-heap of
+// at most `limit` entries, whose smallest is evicted by anything larger; equal
+// sizes are ordered by path so that the result doesn't depend on which worker
+// saw what first, and entries with an identity (multiply-linked files) appear
+// once. Directories are picked from their cumulative sizes after the walk.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::path::{Path, PathBuf};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Entry {
+    size: u64,
+    // For equal sizes the greater path is the smaller entry
+    path: Reverse<PathBuf>,
+    id: Option<(u64, u64)>,
+}
+
+pub(crate) struct Top {
+    limit: usize,
+    heap: BinaryHeap<Reverse<Entry>>,
+}
+
+impl Top {
+    pub(crate) fn new(limit: usize) -> Top {
+        Top {
+            limit,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    // Whether an entry of this size could make it in; lets callers skip the
+    // work of building entries that can't
+    pub(crate) fn admits(&self, size: u64) -> bool {
+        self.limit > 0
+            && (self.heap.len() < self.limit
+                || self
+                    .heap
+                    .peek()
+                    .is_some_and(|Reverse(min)| size >= min.size))
+    }
+
+    pub(crate) fn push(&mut self, size: u64, path: PathBuf, id: Option<(u64, u64)>) {
+        if !self.admits(size) {
+            return;
+        }
+        // Of several links to a file, the first path in order is listed
+        if id.is_some() {
+            let linked = self.heap.iter().find(|Reverse(entry)| entry.id == id);
+            if linked.is_some_and(|Reverse(entry)| entry.path.0 <= path) {
+                return;
+            }
+            self.heap.retain(|Reverse(entry)| entry.id != id);
+        }
+        self.heap.push(Reverse(Entry {
+            size,
+            path: Reverse(path),
+            id,
+        }));
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: Top) {
+        for Reverse(entry) in other.heap {
+            self.push(entry.size, entry.path.0, entry.id);
+        }
+    }
+
+    // Largest first
+    pub(crate) fn into_sorted(self) -> Vec<(PathBuf, u64)> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(entry)| (entry.path.0, entry.size))
+            .collect()
+    }
+}
+
+// Picks the `limit` largest of `dirs`, given as (cumulative size, parent,
+// path), largest first. Unless `nested`, a listed directory only stays listed
+// while what is left of it besides the listed directories below it still
+// ranks among the `limit` largest, so that ancestors which are large only
+// because of a listed subdirectory give way to the deepest directories that
+// hold the space.
+pub(crate) fn dirs(dirs: &[(u64, Option<usize>, &Path)], limit: usize, nested: bool) -> Vec<usize> {
+    let larger = |a: &usize, b: &usize| -> Ordering {
+        let (a, b) = (&dirs[*a], &dirs[*b]);
+        b.0.cmp(&a.0).then_with(|| a.2.cmp(b.2))
+    };
+    let mut order: Vec<usize> = (0..dirs.len()).collect();
+    order.sort_by(larger);
+    if nested {
+        order.truncate(limit);
+        return order;
+    }
+    // Ancestors sort before their subdirectories, so a directory never has
+    // listed subdirectories when it gets listed. `rest` is what is left of
+    // each listed directory besides the listed directories below it.
+    let mut listed = vec![false; dirs.len()];
+    let mut rest: HashMap<usize, u64> = HashMap::new();
+    let closest_listed = |listed: &[bool], i: usize| {
+        std::iter::successors(dirs[i].1, |&a| dirs[a].1).find(|&a| listed[a])
+    };
+    for i in order {
+        let size = dirs[i].0;
+        if rest.len() == limit {
+            // Neither this directory nor any after it can displace anything
+            let smallest = rest.values().min().copied().unwrap_or(0);
+            if size.saturating_mul(2) <= smallest {
+                break;
+            }
+        }
+        listed[i] = true;
+        rest.insert(i, size);
+        if let Some(ancestor) = closest_listed(&listed, i) {
+            rest.entry(ancestor).and_modify(|left| *left -= size);
+        }
+        if rest.len() > limit {
+            let evicted = rest
+                .iter()
+                .min_by(|(&a, left_a), (&b, left_b)| {
+                    left_a.cmp(left_b).then_with(|| dirs[b].2.cmp(dirs[a].2))
+                })
+                .map(|(&e, _)| e);
+            let Some(evicted) = evicted else { break };
+            listed[evicted] = false;
+            let left = rest.remove(&evicted).unwrap_or(0);
+            if let Some(ancestor) = closest_listed(&listed, evicted) {
+                rest.entry(ancestor).and_modify(|sum| *sum += left);
+            }
+        }
+    }
+    let mut picked: Vec<usize> = rest.into_keys().collect();
+    picked.sort_by(larger);
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(top: Top) -> Vec<(String, u64)> {
+        top.into_sorted()
+            .into_iter()
+            .map(|(path, size)| (path.to_string_lossy().into_owned(), size))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_largest_in_descending_order() {
+        let mut top = Top::new(3);
+        for (name, size) in [("a", 5), ("b", 50), ("c", 1), ("d", 20), ("e", 30)] {
+            top.push(size, name.into(), None);
+        }
+        assert_eq!(
+            sorted(top),
+            [("b".into(), 50), ("e".into(), 30), ("d".into(), 20)]
+        );
+    }
+
+    #[test]
+    fn breaks_ties_by_path_whatever_the_order() {
+        let names = ["x", "b", "a", "c"];
+        let mut forward = Top::new(2);
+        let mut backward = Top::new(2);
+        for name in names {
+            forward.push(7, name.into(), None);
+        }
+        for name in names.iter().rev() {
+            backward.push(7, (*name).into(), None);
+        }
+        assert_eq!(sorted(forward), [("a".into(), 7), ("b".into(), 7)]);
+        assert_eq!(sorted(backward), [("a".into(), 7), ("b".into(), 7)]);
+    }
+
+    #[test]
+    fn lists_linked_files_once() {
+        let mut top = Top::new(3);
+        top.push(10, "one".into(), Some((1, 42)));
+        top.push(10, "two".into(), Some((1, 42)));
+        top.push(10, "link".into(), Some((1, 42)));
+        top.push(10, "copy".into(), Some((1, 43)));
+        assert_eq!(sorted(top), [("copy".into(), 10), ("link".into(), 10)]);
+    }
+
+    #[test]
+    fn merges_like_a_single_selection() {
+        let mut left = Top::new(2);
+        let mut right = Top::new(2);
+        left.push(1, "l1".into(), None);
+        left.push(9, "l9".into(), None);
+        right.push(5, "r5".into(), None);
+        right.push(9, "same".into(), Some((1, 1)));
+        left.merge(right);
+        left.push(9, "same-link".into(), Some((1, 1)));
+        assert_eq!(sorted(left), [("l9".into(), 9), ("same".into(), 9)]);
+    }
+
+    // root/a (100) holds a/b (95), which holds a/b/c (90); root/d is 20
+    // and root/e is 12
+    fn tree() -> Vec<(u64, Option<usize>, &'static Path)> {
+        vec![
+            (100, None, Path::new("a")),
+            (95, Some(0), Path::new("a/b")),
+            (90, Some(1), Path::new("a/b/c")),
+            (20, None, Path::new("d")),
+            (12, None, Path::new("e")),
+        ]
+    }
+
+    #[test]
+    fn nested_directories_rank_by_their_full_size() {
+        assert_eq!(dirs(&tree(), 3, true), [0, 1, 2]);
+        assert_eq!(dirs(&tree(), 10, true), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn prefers_the_deepest_directory_holding_the_space() {
+        assert_eq!(dirs(&tree(), 1, false), [2]);
+        assert_eq!(dirs(&tree(), 2, false), [2, 3]);
+        assert_eq!(dirs(&tree(), 3, false), [2, 3, 4]);
+        // Once every directory fits, they are all listed
+        assert_eq!(dirs(&tree(), 5, false), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn keeps_ancestors_with_enough_besides_their_listed_subdirectories() {
+        // a (100) holds a/b (40) and 60 of its own
+        let tree = [
+            (100, None, Path::new("a")),
+            (40, Some(0), Path::new("a/b")),
+            (30, None, Path::new("c")),
+        ];
+        assert_eq!(dirs(&tree, 2, false), [0, 1]);
+        assert_eq!(dirs(&tree, 1, false), [0]);
+    }
+
+    #[test]
+    fn a_zero_limit_takes_nothing() {
+        assert!(dirs(&tree(), 0, false).is_empty());
+        assert!(dirs(&tree(), 0, true).is_empty());
+        let mut top = Top::new(0);
+        assert!(!top.admits(u64::MAX));
+        top.push(1, "a".into(), None);
+        assert!(sorted(top).is_empty());
+    }
+}
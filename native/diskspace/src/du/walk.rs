@@ -0,0 +1,961 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Directory tree walker. Each worker thread runs an iterative traversal (an
+// explicit stack of pending directories, since trees can be thousands of
+// levels deep) and keeps its own counters; directories move between workers
+// through the shared queue, and the counters are added up at the end.
+//
+// With a cache, a directory at or below max_depth whose fingerprint matches
+// its cached one has its whole content counted from the cache, and isn't
+// walked; see cache.rs. Only walks that complete without errors store what
+// they went through, as the others would cache partial contents.
+
+use super::cache::{self, Fingerprint};
+use super::filter;
+use super::ignore::Ignores;
+use super::meta::{self, Native, NativeDir};
+use super::owner::{self, Owner};
+use super::progress::Progress;
+use super::queue::Queue;
+use super::stream::Stream;
+use super::top::{self, Top};
+use super::{OnError, Options, Placeholders, Symlinks, TopBy};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+// Upper bound on hard-linked files tracked at once. An entry costs about
+// 40 bytes, so the table stays below ~40 MB; past the bound, further
+// multiply-linked files are counted at every link.
+pub(crate) const MAX_TRACKED_LINKS: usize = 1_000_000;
+
+#[derive(Default)]
+pub(crate) struct Summary {
+    // Allocated size on disk
+    pub bytes: u64,
+    // Sum of file lengths
+    pub apparent_bytes: u64,
+    // Regular files, directories, symlinks counted as such, and other
+    // entries (sockets, FIFOs, devices)
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    pub other: u64,
+    // Allocated size of the extra links that were not counted again
+    pub hardlinked_saved_bytes: u64,
+    // Counted files with holes, and how much less they take than their length
+    pub sparse_files: u64,
+    pub sparse_savings_bytes: u64,
+    // On Windows, cloud files whose data may not be local, and their lengths
+    pub placeholder_files: u64,
+    pub placeholder_bytes: u64,
+    // On Windows, what NTFS compression saved on the counted files, and the
+    // unused space at the end of their last clusters
+    pub compression_savings_bytes: u64,
+    pub slack_bytes: u64,
+    // Directories on other filesystems that one_file_system didn't enter
+    pub skipped_mounts: Vec<PathBuf>,
+    // Directories not walked again after being entered through another path,
+    // e.g. a bind mount
+    pub deduplicated_dirs: Vec<PathBuf>,
+    // Entries matched by the exclude patterns and their own allocated size;
+    // the content of excluded directories is never read
+    pub excluded_entries: u64,
+    pub excluded_bytes: u64,
+    // Entries ignored by respect_ignore_files, including everything inside
+    // ignored directories, and their allocated size
+    pub ignored_entries: u64,
+    pub ignored_bytes: u64,
+    // Per-entry failures, up to max_errors, and how many there were in all
+    pub errors: Vec<(PathBuf, io::Error)>,
+    pub error_count: u64,
+    // The failure that stopped the walk with on_error: :halt
+    pub halted: Option<(PathBuf, io::Error)>,
+    // Whether the walk was cancelled before it was done
+    pub cancelled: bool,
+    // Directories down to max_depth below the root (at any depth with
+    // top_dirs), parents before children
+    pub nodes: Vec<Node>,
+    // The top_files largest files and the top_dirs largest directories,
+    // largest first, with their top_by size
+    pub top_files: Vec<(PathBuf, u64)>,
+    pub top_dirs: Vec<(PathBuf, u64)>,
+    // Allocated bytes and number of counted files per extension, with
+    // by_extension
+    pub by_extension: HashMap<Option<String>, (u64, u64)>,
+    // Likewise per owner with by_owner, None for files whose owner couldn't
+    // be read
+    pub by_owner: HashMap<Option<Owner>, (u64, u64)>,
+    // Allocated and apparent size and number of the counted files that meet
+    // min_size and older_than
+    pub matching_bytes: u64,
+    pub matching_apparent_bytes: u64,
+    pub matching_files: u64,
+    // Directories counted but not read, at the depth the walk stopped at
+    pub unread: Vec<PathBuf>,
+}
+
+// Cumulative usage of a directory. Content deeper than the deepest node is
+// counted into its closest ancestor node.
+#[derive(Clone)]
+pub(crate) struct Node {
+    pub path: PathBuf,
+    pub parent: Option<usize>,
+    pub depth: u32,
+    pub bytes: u64,
+    pub apparent_bytes: u64,
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    pub other: u64,
+}
+
+// A worker's share of the usage of a node
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Totals {
+    bytes: u64,
+    apparent_bytes: u64,
+    files: u64,
+    dirs: u64,
+    symlinks: u64,
+    other: u64,
+}
+
+// A directory still to be read, with its depth below the root, the node its
+// entries are counted into, and the ignore rules of its ancestors; the
+// entries of an ignored directory only count as ignored
+struct Pending {
+    path: PathBuf,
+    depth: u32,
+    node: Option<usize>,
+    ignores: Option<Arc<Ignores>>,
+    ignored: bool,
+}
+
+// State all workers see. The sets are only locked for multiply-linked files,
+// for directories with one_file_system or :follow, and for new nodes.
+struct Shared<'o> {
+    root: &'o Path,
+    options: &'o Options,
+    queue: Queue<Pending>,
+    // Set from outside to cancel the walk
+    cancel: &'o AtomicBool,
+    progress: Option<&'o Progress>,
+    stream: Option<&'o Stream>,
+    // Depth at which directories are no longer read
+    stop_depth: Option<u32>,
+    // Cluster size of the root's volume, where sizes need rounding up to it
+    cluster: Option<u64>,
+    // Links still expected per (device, inode); an entry is dropped once all
+    // links have been seen, so only partially visited files take up memory
+    links: Mutex<HashMap<(u64, u64), u64>>,
+    // Device of the root with one_file_system, whether to keep track of the
+    // directories entered, and those entered so far
+    root_dev: Option<u64>,
+    track_dirs: bool,
+    visited_dirs: Mutex<HashSet<(u64, u64)>>,
+    // Nodes as created, counting just the directory itself
+    nodes: Mutex<Vec<Node>>,
+    halted: Mutex<Option<(PathBuf, io::Error)>>,
+}
+
+struct Walker<'s, 'o> {
+    shared: &'s Shared<'o>,
+    summary: Summary,
+    // What the entries of each directory read add to its node, and the
+    // running totals of the directory being read
+    totals: Vec<(usize, Totals)>,
+    current: Totals,
+    // The ignore rules in the directory being read, and whether it's ignored
+    ignores: Option<Arc<Ignores>>,
+    ignored: bool,
+    pending: Vec<Pending>,
+    top_files: Top,
+    // Directories read with a cache that had nothing for them
+    uncached: Vec<(usize, Fingerprint)>,
+}
+
+// Ends the walk for all workers if one of them panics, so that the others
+// don't wait forever for its directories
+struct StopOnPanic<'q>(&'q Queue<Pending>);
+
+impl Drop for StopOnPanic<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.stop();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Walks the tree under `root` with `options.workers` threads (the calling
+// thread being one of them), until done or until `cancel` is set, publishing
+// to `progress` and sending completed directories to `stream` if given. Only
+// a failure to stat `root` itself is an error; everything below is collected.
+pub(crate) fn walk(
+    root: &Path,
+    options: &Options,
+    cancel: &AtomicBool,
+    progress: Option<&Progress>,
+    stream: Option<&Stream>,
+) -> io::Result<Summary> {
+    let metadata = root_metadata(root, options)?;
+    let shared = Shared::new(root, &metadata, options, cancel, progress, stream);
+    let mut first = Walker::new(&shared);
+    first.visit(root, &meta::native(root), &metadata, 0, None);
+    Ok(finish(&shared, first))
+}
+
+// Walks the tree under `root` down to `levels` below it: directories at that
+// depth are counted themselves, and listed in `unread` instead of being read
+pub(crate) fn walk_levels(
+    root: &Path,
+    levels: u32,
+    options: &Options,
+    cancel: &AtomicBool,
+) -> io::Result<Summary> {
+    let metadata = root_metadata(root, options)?;
+    let mut shared = Shared::new(root, &metadata, options, cancel, None, None);
+    shared.stop_depth = Some(levels);
+    let mut first = Walker::new(&shared);
+    first.visit(root, &meta::native(root), &metadata, 0, None);
+    Ok(finish(&shared, first))
+}
+
+// Walks what the directories `dirs`, at `depth` below `root`, hold, leaving
+// out the directories themselves. The content of each ends up in the node of
+// the same index.
+pub(crate) fn walk_below(
+    root: &Path,
+    dirs: &[PathBuf],
+    depth: u32,
+    options: &Options,
+    cancel: &AtomicBool,
+) -> io::Result<Summary> {
+    let metadata = root_metadata(root, options)?;
+    let shared = Shared::new(root, &metadata, options, cancel, None, None);
+    let mut first = Walker::new(&shared);
+    let mut nodes = lock(&shared.nodes);
+    for (i, path) in dirs.iter().enumerate() {
+        nodes.push(Node {
+            path: path.clone(),
+            parent: None,
+            depth,
+            bytes: 0,
+            apparent_bytes: 0,
+            files: 0,
+            dirs: 0,
+            symlinks: 0,
+            other: 0,
+        });
+        first.pending.push(Pending {
+            path: path.clone(),
+            depth,
+            node: Some(i),
+            ignores: Ignores::above(root, path, &options.ignore_files),
+            ignored: false,
+        });
+    }
+    drop(nodes);
+    Ok(finish(&shared, first))
+}
+
+// Helper: The metadata of the root, through a symlink with :follow
+fn root_metadata(root: &Path, options: &Options) -> io::Result<Metadata> {
+    if options.symlinks == Symlinks::Follow {
+        fs::metadata(root)
+    } else {
+        fs::symlink_metadata(root)
+    }
+}
+
+impl<'o> Shared<'o> {
+    fn new(
+        root: &'o Path,
+        metadata: &Metadata,
+        options: &'o Options,
+        cancel: &'o AtomicBool,
+        progress: Option<&'o Progress>,
+        stream: Option<&'o Stream>,
+    ) -> Shared<'o> {
+        let follow = options.symlinks == Symlinks::Follow;
+        // Without following symlinks, only a bind mount leads into a directory
+        // twice, and Windows has none (its mount points are reparse points)
+        let track_dirs = options.one_file_system || follow || cfg!(unix);
+        let root_dev = if options.one_file_system {
+            meta::file_id(&meta::native(root), metadata, follow).map(|id| id.dev)
+        } else {
+            None
+        };
+        Shared {
+            root,
+            options,
+            queue: Queue::new(options.workers),
+            cancel,
+            progress,
+            stream,
+            stop_depth: None,
+            cluster: meta::cluster_size(&meta::native(root)),
+            links: Mutex::new(HashMap::new()),
+            root_dev,
+            track_dirs,
+            visited_dirs: Mutex::new(HashSet::new()),
+            nodes: Mutex::new(Vec::new()),
+            halted: Mutex::new(None),
+        }
+    }
+}
+
+// Helper: Runs the other workers alongside `first` until the walk is over,
+// and adds up what they found
+fn finish(shared: &Shared, first: Walker) -> Summary {
+    let options = shared.options;
+    let cancel = shared.cancel;
+    let walkers = thread::scope(|scope| {
+        let spawned: Vec<_> = (1..options.workers)
+            .filter_map(|_| {
+                let started = thread::Builder::new()
+                    .name("disk_space_du".to_string())
+                    .spawn_scoped(scope, || Walker::new(shared).run());
+                if started.is_err() {
+                    // Carry on with fewer threads
+                    shared.queue.leave();
+                }
+                started.ok()
+            })
+            .collect();
+        let mut walkers = vec![first.run()];
+        for handle in spawned {
+            match handle.join() {
+                Ok(walker) => walkers.push(walker),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+        walkers
+    });
+    let nodes = std::mem::take(&mut *lock(&shared.nodes));
+    let halted = lock(&shared.halted).take();
+    // A cancellation that came in after the last directory changes nothing
+    let cancelled = cancel.load(Ordering::Relaxed) && shared.queue.is_stopped();
+    let cache = options
+        .cache
+        .as_ref()
+        .filter(|_| halted.is_none() && !cancelled);
+    let mut summary = merge(walkers, nodes, options, cache);
+    summary.halted = halted;
+    summary.cancelled = cancelled;
+    summary
+}
+
+// Helper: Adds up the workers' counters and folds the nodes into their
+// ancestors, storing the contents of uncached directories into `cache`
+fn merge(
+    walkers: Vec<Walker>,
+    mut nodes: Vec<Node>,
+    options: &Options,
+    cache: Option<&cache::Handle>,
+) -> Summary {
+    let mut summary = Summary::default();
+    let own: Vec<Totals> = nodes.iter().map(Node::totals).collect();
+    let mut totals = own.clone();
+    let mut top_files = Top::new(options.top_files);
+    let mut uncached = Vec::new();
+    for walker in walkers {
+        top_files.merge(walker.top_files);
+        uncached.extend(walker.uncached);
+        let part = walker.summary;
+        summary.bytes += part.bytes;
+        summary.apparent_bytes += part.apparent_bytes;
+        summary.files += part.files;
+        summary.dirs += part.dirs;
+        summary.symlinks += part.symlinks;
+        summary.other += part.other;
+        summary.hardlinked_saved_bytes += part.hardlinked_saved_bytes;
+        summary.sparse_files += part.sparse_files;
+        summary.sparse_savings_bytes += part.sparse_savings_bytes;
+        summary.compression_savings_bytes += part.compression_savings_bytes;
+        summary.placeholder_files += part.placeholder_files;
+        summary.matching_bytes += part.matching_bytes;
+        summary.matching_apparent_bytes += part.matching_apparent_bytes;
+        summary.matching_files += part.matching_files;
+        summary.placeholder_bytes += part.placeholder_bytes;
+        summary.slack_bytes += part.slack_bytes;
+        summary.excluded_entries += part.excluded_entries;
+        summary.excluded_bytes += part.excluded_bytes;
+        summary.ignored_entries += part.ignored_entries;
+        summary.ignored_bytes += part.ignored_bytes;
+        summary.skipped_mounts.extend(part.skipped_mounts);
+        summary.deduplicated_dirs.extend(part.deduplicated_dirs);
+        summary.unread.extend(part.unread);
+        summary.errors.extend(part.errors);
+        summary.error_count += part.error_count;
+        for (ext, (bytes, files)) in part.by_extension {
+            let sum = summary.by_extension.entry(ext).or_default();
+            sum.0 += bytes;
+            sum.1 += files;
+        }
+        for (owner, (bytes, files)) in part.by_owner {
+            let sum = summary.by_owner.entry(owner).or_default();
+            sum.0 += bytes;
+            sum.1 += files;
+        }
+        for (i, part) in walker.totals {
+            totals[i].add(&part);
+        }
+    }
+    // The order workers got to them in is arbitrary
+    summary.skipped_mounts.sort();
+    summary.deduplicated_dirs.sort();
+    summary.unread.sort();
+    summary.errors.sort_by(|a, b| a.0.cmp(&b.0));
+    // Each worker kept up to max_errors
+    summary.errors.truncate(options.max_errors);
+    summary.top_files = top_files.into_sorted();
+    // Children always come after their parent, so a single backwards pass
+    // folds every node into its ancestors
+    for i in (0..nodes.len()).rev() {
+        if let Some(parent) = nodes[i].parent {
+            let child = totals[i];
+            totals[parent].add(&child);
+        }
+    }
+    if let Some(cache) = cache.filter(|_| summary.error_count == 0) {
+        cache.store(
+            uncached
+                .into_iter()
+                .map(|(i, fingerprint)| {
+                    let contents = totals[i].minus(&own[i]);
+                    (nodes[i].path.clone(), fingerprint, contents)
+                })
+                .collect(),
+        );
+    }
+    for (node, totals) in nodes.iter_mut().zip(totals) {
+        node.bytes = totals.bytes;
+        node.apparent_bytes = totals.apparent_bytes;
+        node.files = totals.files;
+        node.dirs = totals.dirs;
+        node.symlinks = totals.symlinks;
+        node.other = totals.other;
+    }
+    if options.top_dirs > 0 {
+        let dirs: Vec<(u64, Option<usize>, &Path)> = nodes
+            .iter()
+            .map(|node| (node.size(options.top_by), node.parent, node.path.as_path()))
+            .collect();
+        summary.top_dirs = top::dirs(&dirs, options.top_dirs, options.top_dirs_nested)
+            .into_iter()
+            .map(|i| (nodes[i].path.clone(), dirs[i].0))
+            .collect();
+    }
+    summary.nodes = nodes;
+    summary
+}
+
+impl Node {
+    pub(crate) fn add(&mut self, totals: &Totals) {
+        self.bytes += totals.bytes;
+        self.apparent_bytes += totals.apparent_bytes;
+        self.files += totals.files;
+        self.dirs += totals.dirs;
+        self.symlinks += totals.symlinks;
+        self.other += totals.other;
+    }
+
+    pub(crate) fn totals(&self) -> Totals {
+        Totals {
+            bytes: self.bytes,
+            apparent_bytes: self.apparent_bytes,
+            files: self.files,
+            dirs: self.dirs,
+            symlinks: self.symlinks,
+            other: self.other,
+        }
+    }
+
+    fn size(&self, by: TopBy) -> u64 {
+        match by {
+            TopBy::Bytes => self.bytes,
+            TopBy::ApparentBytes => self.apparent_bytes,
+        }
+    }
+}
+
+impl Summary {
+    // Inodes a copy of the tree would take, the counted entries being those
+    // dedupe_hardlinks left to count
+    pub(crate) fn inodes(&self) -> u64 {
+        self.files + self.dirs + self.symlinks + self.other
+    }
+
+    fn add(&mut self, totals: &Totals) {
+        self.bytes += totals.bytes;
+        self.apparent_bytes += totals.apparent_bytes;
+        self.files += totals.files;
+        self.dirs += totals.dirs;
+        self.symlinks += totals.symlinks;
+        self.other += totals.other;
+    }
+
+    // Helper: A copy of the counters, for progress deltas
+    fn counts(&self) -> Summary {
+        Summary {
+            bytes: self.bytes,
+            apparent_bytes: self.apparent_bytes,
+            files: self.files,
+            ..Summary::default()
+        }
+    }
+}
+
+impl Totals {
+    fn add(&mut self, other: &Totals) {
+        self.bytes += other.bytes;
+        self.apparent_bytes += other.apparent_bytes;
+        self.files += other.files;
+        self.dirs += other.dirs;
+        self.symlinks += other.symlinks;
+        self.other += other.other;
+    }
+
+    // Helper: What `self` counts beyond `part` of it
+    fn minus(&self, part: &Totals) -> Totals {
+        Totals {
+            bytes: self.bytes.saturating_sub(part.bytes),
+            apparent_bytes: self.apparent_bytes.saturating_sub(part.apparent_bytes),
+            files: self.files.saturating_sub(part.files),
+            dirs: self.dirs.saturating_sub(part.dirs),
+            symlinks: self.symlinks.saturating_sub(part.symlinks),
+            other: self.other.saturating_sub(part.other),
+        }
+    }
+}
+
+impl<'s, 'o> Walker<'s, 'o> {
+    fn new(shared: &'s Shared<'o>) -> Walker<'s, 'o> {
+        Walker {
+            shared,
+            summary: Summary::default(),
+            totals: Vec::new(),
+            current: Totals::default(),
+            ignores: None,
+            ignored: false,
+            pending: Vec::new(),
+            top_files: Top::new(shared.options.top_files),
+            uncached: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> Self {
+        let queue = &self.shared.queue;
+        let _stop = StopOnPanic(queue);
+        while let Some(dir) = self.pending.pop().or_else(|| queue.take()) {
+            if self.stopped() {
+                break;
+            }
+            match self.shared.progress {
+                Some(progress) => {
+                    let path = dir.path.clone();
+                    let before = self.summary.counts();
+                    self.read_dir(dir);
+                    progress.add(&path, &before, &self.summary);
+                }
+                None => self.read_dir(dir),
+            }
+            queue.share(&mut self.pending);
+        }
+        self
+    }
+
+    // `native` is `path` as the platform's calls take it, `depth` the depth
+    // of `path` below the root, and `node` the node of the directory
+    // containing it
+    fn visit(
+        &mut self,
+        path: &Path,
+        native: &Native,
+        metadata: &Metadata,
+        depth: u32,
+        mut node: Option<usize>,
+    ) {
+        let file_type = metadata.file_type();
+        let allocation = meta::allocation(native, metadata, self.shared.cluster);
+        let allocated = allocation.bytes;
+        let mut entry = Totals {
+            bytes: allocated,
+            apparent_bytes: metadata.len(),
+            ..Totals::default()
+        };
+        // Whether the entry has a node of its own to count it into
+        let mut own_node = false;
+        if file_type.is_symlink() && self.shared.options.symlinks == Symlinks::Skip {
+            return;
+        }
+        if self.is_excluded(path, file_type.is_dir()) {
+            self.summary.excluded_entries += 1;
+            self.summary.excluded_bytes += allocated;
+            return;
+        }
+        if self.ignored || self.is_ignored(path, file_type.is_dir()) {
+            self.summary.ignored_entries += 1;
+            self.summary.ignored_bytes += allocated;
+            let walk_dir = file_type.is_dir() && self.shared.stop_depth != Some(depth);
+            if walk_dir && self.enter_dir(path, native, metadata) {
+                self.pending.push(Pending {
+                    path: path.to_path_buf(),
+                    depth,
+                    node: None,
+                    ignores: None,
+                    ignored: true,
+                });
+            }
+            return;
+        }
+        if file_type.is_file() {
+            let placeholder = meta::is_placeholder(metadata);
+            if placeholder {
+                self.summary.placeholder_files += 1;
+                self.summary.placeholder_bytes += metadata.len();
+                match self.shared.options.count_placeholders {
+                    Placeholders::Exclude => return,
+                    Placeholders::Nominal => entry.bytes = metadata.len(),
+                    Placeholders::OnDisk => {}
+                }
+            }
+            let options = self.shared.options;
+            let size = filter::size(options.top_by, entry.bytes, metadata);
+            let matching = options.filter.as_ref().map(|f| f.matches(size, metadata));
+            if matching != Some(false) {
+                self.rank_file(path, native, metadata, size);
+            }
+            // Placeholders are never opened, so their identity is unknown
+            let dedupe = options.dedupe_hardlinks && !placeholder;
+            if dedupe && self.seen_before(native, metadata) {
+                self.summary.hardlinked_saved_bytes += allocated;
+                return;
+            }
+            entry.files = 1;
+            if matching == Some(true) {
+                self.summary.matching_bytes += entry.bytes;
+                self.summary.matching_apparent_bytes += entry.apparent_bytes;
+                self.summary.matching_files += 1;
+            }
+            self.add_extension(path, entry.bytes);
+            self.add_owner(native, metadata, entry.bytes);
+            if !placeholder && meta::is_sparse(metadata, allocated) {
+                self.summary.sparse_files += 1;
+                self.summary.sparse_savings_bytes += metadata.len().saturating_sub(allocated);
+            }
+            self.summary.compression_savings_bytes += allocation.compression_savings;
+            self.summary.slack_bytes += allocation.slack;
+        } else if file_type.is_dir() {
+            if !self.enter_dir(path, native, metadata) {
+                return;
+            }
+            entry.dirs = 1;
+            if self.shared.stop_depth == Some(depth) {
+                self.summary.unread.push(path.to_path_buf());
+                self.summary.add(&entry);
+                self.current.add(&entry);
+                return;
+            }
+            let options = self.shared.options;
+            let wants_node = depth <= options.max_depth
+                || options.top_dirs > 0
+                || self.shared.stream.is_some()
+                || options.cache.is_some();
+            if depth > 0 && wants_node {
+                let created = Node {
+                    path: path.to_path_buf(),
+                    parent: node,
+                    depth,
+                    bytes: allocated,
+                    apparent_bytes: metadata.len(),
+                    files: 0,
+                    dirs: 1,
+                    symlinks: 0,
+                    other: 0,
+                };
+                let mut nodes = lock(&self.shared.nodes);
+                let index = nodes.len();
+                if let Some(stream) = self.shared.stream {
+                    stream.open(index, created.clone());
+                }
+                nodes.push(created);
+                node = Some(index);
+                own_node = true;
+            }
+            self.pending.push(Pending {
+                path: path.to_path_buf(),
+                depth,
+                node,
+                ignores: self.ignores.clone(),
+                ignored: false,
+            });
+        } else if file_type.is_symlink() {
+            entry.symlinks = 1;
+        } else {
+            entry.other = 1;
+        }
+        // Symlinks and special files only contribute their own size
+        self.summary.add(&entry);
+        if !own_node {
+            self.current.add(&entry);
+        }
+    }
+
+    fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        !self.shared.options.exclude.is_empty()
+            && path
+                .strip_prefix(self.shared.root)
+                .is_ok_and(|relative| self.shared.options.exclude.is_excluded(relative, is_dir))
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.ignores
+            .as_ref()
+            .is_some_and(|ignores| ignores.is_ignored(path, is_dir))
+    }
+
+    // Refuses directories on other filesystems with one_file_system
+    // (recording them), and directories already entered through a bind mount
+    // or a followed symlink (recording the former as deduplicated and the
+    // latter as loops)
+    fn enter_dir(&mut self, path: &Path, native: &Native, metadata: &Metadata) -> bool {
+        if !self.shared.track_dirs {
+            return true;
+        }
+        let follow = self.shared.options.symlinks == Symlinks::Follow;
+        let Some(id) = meta::file_id(native, metadata, follow) else {
+            return true;
+        };
+        if self.shared.root_dev.is_some_and(|dev| dev != id.dev) {
+            self.summary.skipped_mounts.push(path.to_path_buf());
+            return false;
+        }
+        if lock(&self.shared.visited_dirs).insert((id.dev, id.ino)) {
+            return true;
+        }
+        if follow {
+            self.error(path.to_path_buf(), meta::loop_error());
+        } else {
+            self.summary.deduplicated_dirs.push(path.to_path_buf());
+        }
+        false
+    }
+
+    // Offers a file to the top_files selection. Every link is offered, so that
+    // which one gets listed doesn't depend on the order of the walk; the
+    // identity is only looked up for candidates.
+    fn rank_file(&mut self, path: &Path, native: &Native, metadata: &Metadata, size: u64) {
+        if !self.top_files.admits(size) {
+            return;
+        }
+        let follow = self.shared.options.symlinks == Symlinks::Follow;
+        let id = (!meta::is_placeholder(metadata))
+            .then(|| meta::file_id(native, metadata, follow))
+            .flatten()
+            .filter(|id| id.links > 1)
+            .map(|id| (id.dev, id.ino));
+        self.top_files.push(size, path.to_path_buf(), id);
+    }
+
+    fn add_extension(&mut self, path: &Path, allocated: u64) {
+        let Some(extensions) = &self.shared.options.by_extension else {
+            return;
+        };
+        let ext = path.file_name().and_then(|name| extensions.of(name));
+        let sum = self.summary.by_extension.entry(ext).or_default();
+        sum.0 += allocated;
+        sum.1 += 1;
+    }
+
+    fn add_owner(&mut self, native: &Native, metadata: &Metadata, allocated: u64) {
+        if !self.shared.options.by_owner {
+            return;
+        }
+        let owner = owner::of(native, metadata);
+        let sum = self.summary.by_owner.entry(owner).or_default();
+        sum.0 += allocated;
+        sum.1 += 1;
+    }
+
+    fn seen_before(&mut self, native: &Native, metadata: &Metadata) -> bool {
+        let follow = self.shared.options.symlinks == Symlinks::Follow;
+        let Some(file) = meta::file_id(native, metadata, follow).filter(|id| id.links > 1) else {
+            return false;
+        };
+        let file_id = (file.dev, file.ino);
+        let mut links = lock(&self.shared.links);
+        match links.get_mut(&file_id) {
+            Some(remaining) => {
+                *remaining -= 1;
+                if *remaining == 0 {
+                    links.remove(&file_id);
+                }
+                true
+            }
+            None => {
+                if links.len() < MAX_TRACKED_LINKS {
+                    links.insert(file_id, file.links - 1);
+                }
+                false
+            }
+        }
+    }
+
+    fn read_dir(&mut self, dir: Pending) {
+        self.current = Totals::default();
+        self.ignored = dir.ignored;
+        let names = &self.shared.options.ignore_files;
+        self.ignores = if dir.ignored || names.is_empty() {
+            None
+        } else {
+            Ignores::load(&dir.path, names, dir.ignores.as_ref())
+        };
+        match fs::read_dir(&dir.path) {
+            Ok(entries) => match self.cache_for(&dir) {
+                Some((cache, node)) => self.read_cached(&dir, entries, cache, node),
+                None => self.read_entries(&dir, entries),
+            },
+            Err(e) => self.error(dir.path.clone(), e),
+        }
+        let Some(node) = dir.node else {
+            return;
+        };
+        self.totals.push((node, self.current));
+        // A directory cut short by the end of the walk is incomplete
+        if let Some(stream) = self.shared.stream {
+            if !self.shared.queue.is_stopped() {
+                stream.read(node, &self.current, self.shared.cancel);
+            }
+        }
+    }
+
+    fn read_entries(&mut self, dir: &Pending, entries: fs::ReadDir) {
+        let mut native = NativeDir::new(&dir.path);
+        for entry in entries {
+            if self.stopped() {
+                break;
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.error(dir.path.clone(), e);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            match self.entry_metadata(&entry) {
+                Ok(metadata) => {
+                    let native = native.entry(&path);
+                    self.visit(&path, native, &metadata, dir.depth + 1, dir.node)
+                }
+                Err(e) => self.error(path, e),
+            }
+        }
+    }
+
+    // The cache and node of a directory that may come from the cache: one
+    // with a node of its own, whose content isn't needed as nodes
+    fn cache_for(&self, dir: &Pending) -> Option<(&'o cache::Handle, usize)> {
+        let options = self.shared.options;
+        let cache = options.cache.as_ref()?;
+        let node = dir.node.filter(|_| dir.depth >= options.max_depth.max(1))?;
+        Some((cache, node))
+    }
+
+    // Reads a directory through the cache: its entries are stat'ed for the
+    // fingerprint first, and only visited if the cache has nothing matching.
+    // A directory with unreadable entries is walked as usual.
+    fn read_cached(
+        &mut self,
+        dir: &Pending,
+        entries: fs::ReadDir,
+        cache: &cache::Handle,
+        node: usize,
+    ) {
+        let mut listed = Vec::new();
+        let mut fingerprint = fs::metadata(&dir.path).ok().map(|m| Fingerprint::new(&m));
+        for entry in entries {
+            if self.stopped() {
+                return;
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    fingerprint = None;
+                    self.error(dir.path.clone(), e);
+                    continue;
+                }
+            };
+            let metadata = self.entry_metadata(&entry);
+            match (&mut fingerprint, &metadata) {
+                (Some(fingerprint), Ok(metadata)) => fingerprint.add(metadata),
+                (_, Err(_)) => fingerprint = None,
+                (None, _) => {}
+            }
+            listed.push((entry.path(), metadata));
+        }
+        if let Some(fingerprint) = fingerprint {
+            if let Some(contents) = cache.lookup(&dir.path, &fingerprint) {
+                self.summary.add(&contents);
+                self.current.add(&contents);
+                return;
+            }
+            self.uncached.push((node, fingerprint));
+        }
+        let mut native = NativeDir::new(&dir.path);
+        for (path, metadata) in listed {
+            if self.stopped() {
+                break;
+            }
+            match metadata {
+                Ok(metadata) => {
+                    let native = native.entry(&path);
+                    self.visit(&path, native, &metadata, dir.depth + 1, dir.node)
+                }
+                Err(e) => self.error(path, e),
+            }
+        }
+    }
+
+    // Checked between entries, which keeps cancellation prompt even within
+    // huge directories
+    fn stopped(&self) -> bool {
+        if self.shared.cancel.load(Ordering::Relaxed) {
+            self.shared.queue.stop();
+        }
+        self.shared.queue.is_stopped()
+    }
+
+    // Records a failure, or stops the walk on the first one with :halt
+    fn error(&mut self, path: PathBuf, e: io::Error) {
+        self.summary.error_count += 1;
+        if self.shared.options.on_error == OnError::Halt {
+            lock(&self.shared.halted).get_or_insert((path, e));
+            self.shared.queue.stop();
+        } else if self.summary.errors.len() < self.shared.options.max_errors {
+            self.summary.errors.push((path, e));
+        }
+    }
+
+    // DirEntry::metadata doesn't traverse symlinks, so a followed link is
+    // stat'ed again; a dangling one fails here
+    fn entry_metadata(&self, entry: &fs::DirEntry) -> io::Result<Metadata> {
+        let metadata = entry.metadata()?;
+        if metadata.file_type().is_symlink() && self.shared.options.symlinks == Symlinks::Follow {
+            fs::metadata(entry.path())
+        } else {
+            Ok(metadata)
+        }
+    }
+}
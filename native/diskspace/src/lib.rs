@@ -1,20 +1,22 @@
-// This file was incrementally generated/adapted by xAI's Grok 4 
-// model over multiple rounds of prompting for reviews and improvements 
-// that were suggested by Grok 4, GPT-5 and Gemini 2.5 Pro, and 
-// according to the warnings/errors of the GitHub Actions workflow 
+// This file was incrementally generated/adapted by xAI's Grok 4
+// model over multiple rounds of prompting for reviews and improvements
+// that were suggested by Grok 4, GPT-5 and Gemini 2.5 Pro, and
+// according to the warnings/errors of the GitHub Actions workflow
 // across Linux, macOS, and Windows
 
-use rustler::{Atom, Binary, Env, Error, NifResult, Term};
-use std::ffi::CString;
-#[cfg(unix)]
+use rustler::env::OwnedEnv;
+use rustler::wrapper::NIF_TERM;
+use rustler::{Atom, Binary, Encoder, Env, Error, LocalPid, NewBinary, NifResult, Term};
+use small_path::SmallPath;
+use std::ffi::{CStr, CString};
 use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 // Unix-specific imports
 #[cfg(unix)]
 use std::ffi::OsStr;
 #[cfg(unix)]
 use std::os::unix::ffi::OsStrExt;
-#[cfg(unix)]
-use std::path::Path;
 // Windows-specific imports
 #[cfg(windows)]
 use std::ptr;
@@ -28,7 +30,8 @@ use windows::Win32::Foundation::{
 };
 #[cfg(windows)]
 use windows::Win32::Storage::FileSystem::{
-    GetDiskFreeSpaceExW, GetFileAttributesW, FILE_ATTRIBUTE_DIRECTORY, INVALID_FILE_ATTRIBUTES,
+    GetDiskFreeSpaceExW, GetFileAttributesW, GetVolumeNameForVolumeMountPointW, GetVolumePathNameW,
+    FILE_ATTRIBUTE_DIRECTORY, INVALID_FILE_ATTRIBUTES,
 };
 #[cfg(windows)]
 use windows::Win32::System::Diagnostics::Debug::{
@@ -40,6 +43,44 @@ use windows::Win32::System::Diagnostics::Debug::{
 use nix::sys::statfs::{statfs, Statfs};
 #[cfg(all(unix, not(target_os = "linux")))]
 use nix::sys::statvfs::{statvfs, Statvfs};
+#[cfg(any(test, feature = "bench"))]
+mod alloc_count;
+mod apfs;
+mod batch;
+#[cfg(feature = "bench")]
+mod bench;
+mod bitlocker;
+mod btrfs;
+mod cached;
+mod capacity;
+mod config;
+mod container;
+mod device;
+mod diskstats;
+mod du;
+mod media;
+mod mounts;
+mod ntfs_quota;
+mod on_disk;
+mod options;
+mod overhead;
+mod pool;
+mod posix;
+mod preallocate;
+mod probe;
+mod quota;
+mod reserve;
+mod small_path;
+mod space_information;
+mod sync;
+mod target;
+mod telemetry;
+mod tmpfs;
+mod volume_dirty;
+mod volume_id;
+mod watch;
+mod wide_path;
+mod zfs;
 mod atoms {
     rustler::atoms! {
         ok,
@@ -57,7 +98,466 @@ mod atoms {
         total,
         used,
         errno,
-        errstr
+        errstr,
+        invalid_option,
+        list_mounts_failed,
+        exclude,
+        pseudo,
+        squashfs_loop,
+        appimage,
+        path_prefix,
+        mount_id,
+        parent_id,
+        device,
+        root,
+        mount_point,
+        fs_type,
+        source,
+        options,
+        read_only,
+        invalid_snapshot,
+        added,
+        removed,
+        changed,
+        old,
+        new,
+        bytes,
+        apparent_bytes,
+        files,
+        dirs,
+        errors,
+        path,
+        hardlinked_saved_bytes,
+        dedupe_hardlinks,
+        one_file_system,
+        skipped_mounts,
+        max_depth,
+        children,
+        excluded_entries,
+        excluded_bytes,
+        symlinks,
+        skip,
+        count_link,
+        follow,
+        workers,
+        posix,
+        error_count,
+        max_errors,
+        on_error,
+        continue_ = "continue",
+        halt,
+        du_failed,
+        cancelled,
+        du_result,
+        progress,
+        progress_interval,
+        du_progress,
+        bytes_so_far,
+        apparent_bytes_so_far,
+        files_so_far,
+        current_path,
+        top_files,
+        top_by,
+        top_dirs,
+        top_dirs_nested,
+        by_extension,
+        compound_extensions,
+        none,
+        other,
+        inodes,
+        du_entry,
+        du_done,
+        max_in_flight,
+        reason,
+        info,
+        cache,
+        cache_mode,
+        mtime_heuristic,
+        max_entries,
+        entries,
+        hits,
+        misses,
+        evictions,
+        sample_fraction,
+        seed,
+        estimate_bytes,
+        confidence_low,
+        confidence_high,
+        sampled_fraction,
+        respect_ignore_files,
+        ignored_entries,
+        ignored_bytes,
+        sparse_files,
+        sparse_savings_bytes,
+        compression_savings_bytes,
+        slack_bytes,
+        count_placeholders,
+        nominal,
+        on_disk,
+        placeholder_files,
+        placeholder_bytes,
+        min_size,
+        older_than,
+        atime,
+        matching,
+        by_owner,
+        resolve_names,
+        unknown,
+        name,
+        shape,
+        summary,
+        flat,
+        tree,
+        depth,
+        deduplicated_dirs,
+        disk_space,
+        pool_failed,
+        unique,
+        concurrency,
+        timeout,
+        interval_ms,
+        dest,
+        disk_space_sample,
+        at,
+        result,
+        watch_failed,
+        watcher,
+        ref_ = "ref",
+        running,
+        samples,
+        last_sample,
+        last_error,
+        alert_below,
+        percent,
+        disk_space_alert,
+        low,
+        recovered,
+        alert_state,
+        hysteresis,
+        debounce_samples,
+        trend_window_ms,
+        trend_min_samples,
+        fill_rate_bytes_per_sec,
+        eta_full_seconds,
+        history_size,
+        disk_space_report,
+        mounts_changed,
+        notify,
+        always,
+        on_change,
+        on_change_by,
+        align_to,
+        interval,
+        jitter_ms,
+        overloaded,
+        pool_size,
+        pool_queue_limit,
+        disk_space_event,
+        filesystem_changed,
+        on_filesystem_change,
+        hold_alerts,
+        alerts_held,
+        state,
+        paused,
+        stopped,
+        labels,
+        already_running,
+        duration_ms,
+        max_consecutive_timeouts,
+        backoff_max_ms,
+        circuit,
+        responsive,
+        unresponsive,
+        timeouts,
+        retry_in_ms,
+        start_at,
+        opts,
+        message_format,
+        tuple,
+        map,
+        struct_ = "__struct__",
+        type_ = "type",
+        data,
+        event,
+        sample,
+        report,
+        alert,
+        event_struct = "Elixir.DiskSpace.Event",
+        stat_fs,
+        du,
+        list_mounts,
+        watcher_tick,
+        calls,
+        failures,
+        latency_us,
+        sum,
+        max,
+        histogram,
+        infinity,
+        telemetry_disabled,
+        humanize,
+        binary,
+        decimal,
+        all,
+        stat_fs_cached,
+        user,
+        group,
+        bytes_used,
+        bytes_soft_limit,
+        bytes_hard_limit,
+        bytes_grace_expires,
+        inodes_used,
+        inodes_soft_limit,
+        inodes_hard_limit,
+        inodes_grace_expires,
+        mount_not_found,
+        quotas_not_enabled,
+        quotactl_failed,
+        unsupported,
+        reserved_blocks,
+        reserved_bytes,
+        reserved_percent,
+        overhead_bytes,
+        non_root_total,
+        profile,
+        total_bytes,
+        used_bytes,
+        profiles,
+        devices,
+        unallocated_bytes,
+        data_ratio,
+        estimated_writable_bytes,
+        metadata,
+        mixed,
+        system,
+        global_reserve,
+        single,
+        dup,
+        raid0,
+        raid1,
+        raid1c3,
+        raid1c4,
+        raid10,
+        raid5,
+        raid6,
+        btrfs_ioctl_failed,
+        not_btrfs,
+        qgroups_disabled,
+        qgroup_not_found,
+        subvolume_id,
+        qgroup_id,
+        referenced_bytes,
+        exclusive_bytes,
+        max_referenced_bytes,
+        max_exclusive_bytes,
+        parents,
+        inconsistent,
+        dataset,
+        available_bytes,
+        quota_bytes,
+        reservation_bytes,
+        refquota_bytes,
+        refreservation_bytes,
+        pool_available_bytes,
+        limited_by,
+        pool,
+        zfs_unavailable,
+        zfs_ioctl_failed,
+        quotas_disabled,
+        enforced,
+        limit_bytes,
+        default_limit_bytes,
+        api,
+        get_disk_space_information,
+        get_disk_free_space_ex,
+        actual_total,
+        actual_available,
+        caller_total,
+        caller_available,
+        bytes_per_sector,
+        allocation_unit_bytes,
+        container,
+        volume,
+        container_free,
+        collapse_apfs,
+        apfs_volumes,
+        total_capacity,
+        available_capacity,
+        important_capacity,
+        opportunistic_capacity,
+        purgeable,
+        statvfs,
+        not_apfs,
+        requires_full_disk_access,
+        snapshot_list_failed,
+        created_at,
+        count,
+        snapshots,
+        memory_backed,
+        size_bytes,
+        size_is_ram_fraction,
+        noswap,
+        project,
+        containerized,
+        runtime,
+        root_fs_type,
+        upperdir,
+        upperdir_device,
+        project_id,
+        project_quota,
+        docker,
+        podman,
+        kubernetes,
+        containerd,
+        lxc,
+        write_zeros,
+        keep_partial,
+        metadata_bytes,
+        estimated_bytes,
+        fits,
+        shortfall,
+        required,
+        insufficient_space,
+        reserved_at,
+        bytes_written,
+        bytes_per_second,
+        direct,
+        syncfs,
+        sync,
+        chain,
+        disks,
+        kind,
+        lower,
+        disk,
+        partition,
+        dm,
+        md,
+        loop_ = "loop",
+        not_block_device,
+        model,
+        vendor,
+        serial,
+        firmware,
+        is_removable,
+        bus,
+        usb,
+        nvme,
+        sata,
+        virtio,
+        mmc,
+        scsi,
+        device_info,
+        logical_sector_size,
+        physical_sector_size,
+        permission_limited,
+        discard,
+        device_supports,
+        mount_option_enabled,
+        fstrim_capable,
+        major,
+        minor,
+        reads_completed,
+        reads_merged,
+        sectors_read,
+        read_time_ms,
+        writes_completed,
+        writes_merged,
+        sectors_written,
+        write_time_ms,
+        ios_in_progress,
+        io_time_ms,
+        weighted_io_time_ms,
+        discards_completed,
+        discards_merged,
+        sectors_discarded,
+        discard_time_ms,
+        flushes_completed,
+        flush_time_ms,
+        bytes_read,
+        idle_time_ms,
+        counters_disabled,
+        rotational,
+        lvm,
+        crypt,
+        mpath,
+        vg,
+        lv,
+        not_raid,
+        active,
+        level,
+        array_state,
+        raid_disks,
+        active_disks,
+        degraded,
+        members,
+        slot,
+        in_sync,
+        rebuilding,
+        spare,
+        faulty,
+        replacement,
+        action,
+        resync,
+        recovery,
+        reshape,
+        check,
+        repair,
+        finish_minutes,
+        speed_kib_per_sec,
+        partitions,
+        number,
+        start,
+        size,
+        type_guid_or_id,
+        mounted_at,
+        transport,
+        sas,
+        iscsi,
+        fc,
+        unavailable,
+        device_health_disabled,
+        failure_predicted,
+        critical_warning,
+        protected,
+        encryption_in_progress,
+        conversion_status,
+        method,
+        access_denied,
+        fully_decrypted,
+        fully_encrypted,
+        decryption_in_progress,
+        encryption_paused,
+        decryption_paused,
+        aes_128_diffuser,
+        aes_256_diffuser,
+        aes_128,
+        aes_256,
+        hardware,
+        xts_aes_128,
+        xts_aes_256,
+        luks_uuid,
+        encrypted,
+        encryption,
+        fscrypt,
+        volume_id,
+        volume_id_raw,
+        media,
+        fixed,
+        removable,
+        usb_fixed,
+        cdrom,
+        ramdisk,
+        remote,
+        ready,
+        no_media,
+        not_removable,
+        unmounted,
+        closed,
+        columnar,
+        paths,
+        budget_exceeded,
+        elapsed_us,
+        budget_ms
     }
 }
 // Helper: Create {error, Reason} tuple
@@ -74,7 +574,6 @@ fn make_error_tuple3<'a>(env: Env<'a>, reason: Atom, detail: Term<'a>) -> NifRes
         &[atoms::error().to_term(env), reason.to_term(env), detail],
     ))
 }
-#[cfg(unix)]
 // Helper: Create error tuple with errno details
 fn make_errno_error_tuple<'a>(env: Env<'a>, reason: Atom, err: io::Error) -> NifResult<Term<'a>> {
     let errnum = err.raw_os_error().unwrap_or(0);
@@ -126,6 +625,56 @@ fn make_winapi_error_tuple<'a>(env: Env<'a>, reason: Atom, errnum: u32) -> NifRe
         .map_put(atoms::errstr().to_term(env), errstr)?;
     make_error_tuple3(env, reason, detail)
 }
+#[cfg(windows)]
+// Helper: The root of the volume holding `path`, e.g. "C:\", or the WinAPI
+// error code
+fn volume_root(path: &WideCString) -> Result<WideCString, u32> {
+    let mut root = [0u16; 1024];
+    if let Err(e) = unsafe { GetVolumePathNameW(PCWSTR::from_raw(path.as_ptr()), &mut root) } {
+        return Err((e.code().0 & 0xFFFF) as u32);
+    }
+    let end = root
+        .iter()
+        .position(|unit| *unit == 0)
+        .unwrap_or(root.len());
+    WideCString::from_vec(&root[..end]).map_err(|_| ERROR_PATH_NOT_FOUND.0)
+}
+#[cfg(windows)]
+// Helper: The device of the volume mounted at `root`, \\?\Volume{GUID}
+// without the trailing backslash, as CreateFileW opens the volume itself,
+// or the WinAPI error code
+fn volume_device(root: &WideCString) -> Result<WideCString, u32> {
+    let mut name = [0u16; 64];
+    if let Err(e) =
+        unsafe { GetVolumeNameForVolumeMountPointW(PCWSTR::from_raw(root.as_ptr()), &mut name) }
+    {
+        return Err((e.code().0 & 0xFFFF) as u32);
+    }
+    let mut end = name.iter().position(|unit| *unit == 0).unwrap_or(0);
+    if end > 0 && name[end - 1] == u16::from(b'\\') {
+        end -= 1;
+    }
+    WideCString::from_vec(&name[..end]).map_err(|_| ERROR_PATH_NOT_FOUND.0)
+}
+// Helper: {:error, reason, info} as {:error, %{reason: reason, info: info}},
+// the shape the Elixir wrappers give errors, for results sent as messages
+// that no wrapper sees
+fn reshape_error<'a>(env: Env<'a>, result: Term<'a>) -> Term<'a> {
+    let Ok(items) = rustler::types::tuple::get_tuple(result) else {
+        return result;
+    };
+    if items.first() != Some(&atoms::error().to_term(env)) || items.len() < 2 {
+        return result;
+    }
+    let info = items
+        .get(2)
+        .copied()
+        .unwrap_or_else(|| rustler::types::atom::nil().to_term(env));
+    rustler::types::map::map_new(env)
+        .map_put(atoms::reason().to_term(env), items[1])
+        .and_then(|map| map.map_put(atoms::info().to_term(env), info))
+        .map_or(result, |map| (atoms::error(), map).encode(env))
+}
 // Helper: Convert Elixir term to a path
 fn get_path_from_term<'a>(_env: Env<'a>, term: Term<'a>) -> NifResult<CString> {
     // Try binary first
@@ -151,29 +700,220 @@ fn get_path_from_term<'a>(_env: Env<'a>, term: Term<'a>) -> NifResult<CString> {
         Err(_) => Err(Error::BadArg),
     }
 }
+// Helper: Convert a decoded path to a PathBuf (kept byte-exact on Unix)
+fn path_from_cstring(path: &CString) -> Option<PathBuf> {
+    #[cfg(unix)]
+    {
+        Some(PathBuf::from(OsStr::from_bytes(path.as_bytes())))
+    }
+    #[cfg(windows)]
+    {
+        path.to_str().ok().map(PathBuf::from)
+    }
+}
+// Helper: Encode a path as an Elixir binary (raw bytes on Unix)
+fn path_to_term<'a>(env: Env<'a>, path: &Path) -> Term<'a> {
+    #[cfg(unix)]
+    let bytes = path.as_os_str().as_bytes();
+    #[cfg(windows)]
+    let lossy = path.to_string_lossy();
+    #[cfg(windows)]
+    let bytes = lossy.as_bytes();
+    let mut binary = NewBinary::new(env, bytes.len());
+    binary.as_mut_slice().copy_from_slice(bytes);
+    Binary::from(binary).encode(env)
+}
+// Space figures of the filesystem holding a directory, in bytes
+struct Space {
+    available: u64,
+    free: u64,
+    total: u64,
+    used: u64,
+}
+// Why a stat failed, as the error tuple helpers encode it
+enum StatError {
+    Reason(Atom),
+    Errno(Atom, io::Error),
+    #[cfg(windows)]
+    WinApi(Atom, u32),
+    // Given up on after the timeout of stat_fs_many
+    Timeout(Duration),
+}
+impl StatError {
+    fn reason(&self) -> Atom {
+        match self {
+            StatError::Reason(reason) | StatError::Errno(reason, _) => *reason,
+            #[cfg(windows)]
+            StatError::WinApi(reason, _) => *reason,
+            StatError::Timeout(_) => atoms::timeout(),
+        }
+    }
+}
 #[rustler::nif(schedule = "DirtyIo")]
 fn stat_fs<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
-    let path_cstr = match get_path_from_term(env, path_term) {
-        Ok(path) => path,
-        Err(_) => return make_error_tuple(env, atoms::invalid_path()),
+    // On the stack, as stat_fs is called over and over
+    let Ok(path) = SmallPath::from_term(path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    encode_stat(env, stat_path(path.as_c_str()))
+}
+// The longest a stat on a normal scheduler should take unless told
+// otherwise, as the BEAM expects of a NIF that doesn't yield
+const FAST_BUDGET: Duration = Duration::from_millis(1);
+// stat_fs on a normal scheduler, for paths known to be on fast local
+// filesystems such as tmpfs and ext4 on an SSD, where handing the call to a
+// dirty scheduler takes longer than the stat. One that blocks, as on a hung
+// NFS server, blocks the scheduler and every process waiting on it with it.
+// A stat taking longer than `budget_ms`, FAST_BUDGET if nil, is counted for
+// nif_stats as failing with :budget_exceeded, and fails with
+// {:error, :budget_exceeded, %{elapsed_us, budget_ms}} in debug builds, so
+// that a path that doesn't belong here shows in development
+#[rustler::nif]
+fn stat_fs_fast<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    budget_ms: Option<u64>,
+) -> NifResult<Term<'a>> {
+    let Ok(path) = SmallPath::from_term(path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let budget = budget_ms.map_or(FAST_BUDGET, Duration::from_millis);
+    let started = std::time::Instant::now();
+    let stat = stat_dir(path.as_c_str());
+    let elapsed = started.elapsed();
+    let over = stat.is_ok() && elapsed > budget;
+    let failure = match &stat {
+        Ok(_) => over.then(atoms::budget_exceeded),
+        Err(err) => Some(err.reason()),
     };
+    telemetry::record(telemetry::Op::StatFs, elapsed, failure);
+    #[cfg(debug_assertions)]
+    if over {
+        let detail = make_map(
+            env,
+            [
+                (
+                    atoms::elapsed_us(),
+                    (elapsed.as_micros() as u64).encode(env),
+                ),
+                (atoms::budget_ms(), (budget.as_millis() as u64).encode(env)),
+            ],
+        )?;
+        return make_error_tuple3(env, atoms::budget_exceeded(), detail);
+    }
+    encode_stat(env, stat)
+}
+// stat_fs on a dirty CPU scheduler, for callers that keep the dirty IO
+// schedulers for IO that takes long
+#[rustler::nif(schedule = "DirtyCpu")]
+fn stat_fs_dirty_cpu<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = SmallPath::from_term(path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    encode_stat(env, stat_path(path.as_c_str()))
+}
+// Sends {:disk_space, ref, result} to `pid` once a pool thread has statted
+// `path`, `result` being what stat/2 returns; dropped if `pid` is gone by then
+#[rustler::nif]
+fn stat_fs_async_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    pid: LocalPid,
+    reference: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let path_cstr = get_path_from_term(env, path_term).ok();
+    let mut owned_env = OwnedEnv::new();
+    let reference = owned_env.save(reference);
+    let submitted = pool::submit(move || {
+        let stat = match &path_cstr {
+            Some(path_cstr) => stat_path(path_cstr),
+            None => Err(StatError::Reason(atoms::invalid_path())),
+        };
+        let _ = owned_env.send_and_clear(&pid, |env| {
+            let result = encode_stat(env, stat)
+                .map_or_else(|_| atoms::error().to_term(env), |r| reshape_error(env, r));
+            (atoms::disk_space(), reference.load(env), result).encode(env)
+        });
+    });
+    match submitted {
+        Ok(()) => Ok(atoms::ok().encode(env)),
+        Err(refused) => pool::encode_refused(env, refused),
+    }
+}
+// Helper: %{available, free, total, used}
+fn encode_space<'a>(env: Env<'a>, space: &Space) -> NifResult<Term<'a>> {
+    make_map(
+        env,
+        [
+            (atoms::available(), space.available.encode(env)),
+            (atoms::free(), space.free.encode(env)),
+            (atoms::total(), space.total.encode(env)),
+            (atoms::used(), space.used.encode(env)),
+        ],
+    )
+}
+// Helper: The map of `pairs`, made at once, without the maps in between of
+// map_put or the vectors of map_from_arrays
+fn make_map<'a, const N: usize>(env: Env<'a>, pairs: [(Atom, Term<'a>); N]) -> NifResult<Term<'a>> {
+    let keys = pairs.map(|(key, _)| key.to_term(env).as_c_arg());
+    let values = pairs.map(|(_, value)| value.as_c_arg());
+    make_map_of(env, &keys, &values)
+}
+// Helper: The map of `keys` and `values` as the terms they are, for callers
+// making many maps of the same keys, whose terms they make once
+fn make_map_of<'a>(env: Env<'a>, keys: &[NIF_TERM], values: &[NIF_TERM]) -> NifResult<Term<'a>> {
+    // The values are read for as many as there are keys
+    if keys.len() != values.len() {
+        return Err(Error::BadArg);
+    }
+    // Fails on a key given twice
+    unsafe { rustler::wrapper::map::make_map_from_arrays(env.as_c_arg(), keys, values) }
+        .map(|map| unsafe { Term::new(env, map) })
+        .ok_or(Error::BadArg)
+}
+// Helper: The list of `items` as the terms they are, without the vector
+// encoding a Vec<Term> makes of them
+fn make_list<'a>(env: Env<'a>, items: &[NIF_TERM]) -> Term<'a> {
+    unsafe {
+        Term::new(
+            env,
+            rustler::wrapper::list::make_list(env.as_c_arg(), items),
+        )
+    }
+}
+// Helper: {:ok, %{available, free, total, used}} or the error tuple
+fn encode_stat<'a>(env: Env<'a>, stat: Result<Space, StatError>) -> NifResult<Term<'a>> {
+    match stat {
+        Ok(space) => {
+            let map = encode_space(env, &space)?;
+            Ok((atoms::ok(), map).encode(env))
+        }
+        Err(StatError::Reason(reason)) => make_error_tuple(env, reason),
+        Err(StatError::Errno(reason, err)) => make_errno_error_tuple(env, reason, err),
+        #[cfg(windows)]
+        Err(StatError::WinApi(reason, err_code)) => make_winapi_error_tuple(env, reason, err_code),
+        Err(StatError::Timeout(timeout)) => {
+            let detail = rustler::types::map::map_new(env)
+                .map_put(atoms::timeout().to_term(env), timeout.as_millis() as u64)?;
+            make_error_tuple3(env, atoms::timeout(), detail)
+        }
+    }
+}
+// Helper: The space figures of the filesystem holding the directory
+// `path_cstr`, counted for nif_stats
+fn stat_path(path_cstr: &CStr) -> Result<Space, StatError> {
+    telemetry::timed(
+        telemetry::Op::StatFs,
+        || stat_dir(path_cstr),
+        |stat| stat.as_ref().err().map(StatError::reason),
+    )
+}
+// Helper: stat_path, uncounted
+pub(crate) fn stat_dir(path_cstr: &CStr) -> Result<Space, StatError> {
     #[cfg(windows)]
     {
-        let path_str = match path_cstr.to_str() {
-            Ok(s) => s,
-            Err(_) => return make_error_tuple(env, atoms::path_conversion_failed()),
-        };
-        let is_unc = path_str.starts_with("\\\\") && !path_str.starts_with("\\\\?\\");
-        let long_path_str = if is_unc {
-            format!("\\\\?\\UNC{}", &path_str[2..])
-        } else if !path_str.starts_with("\\\\?\\") {
-            format!("\\\\?\\{}", path_str)
-        } else {
-            path_str.to_string()
-        };
-        let wide_str = match WideCString::from_str(&long_path_str) {
-            Ok(ws) => ws,
-            Err(_) => return make_error_tuple(env, atoms::path_conversion_failed()),
+        let Some(wide_str) = wide_path::long(path_cstr) else {
+            return Err(StatError::Reason(atoms::path_conversion_failed()));
         };
         let long_wpath = PCWSTR::from_raw(wide_str.as_ptr());
         let attr = unsafe { GetFileAttributesW(long_wpath) };
@@ -186,95 +926,124 @@ fn stat_fs<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
             } else {
                 atoms::winapi_failed()
             };
-            return make_winapi_error_tuple(env, reason, err_code);
+            return Err(StatError::WinApi(reason, err_code));
         }
         if (attr & FILE_ATTRIBUTE_DIRECTORY.0) == 0 {
-            return make_error_tuple(env, atoms::not_directory());
-        }
-        let mut avail: u64 = 0;
-        let mut total: u64 = 0;
-        let mut free: u64 = 0;
-        let result = unsafe {
-            GetDiskFreeSpaceExW(
-                long_wpath,
-                Some(&mut avail),
-                Some(&mut total),
-                Some(&mut free),
-            )
-        };
-        if let Err(e) = result {
-            let err_code = (e.code().0 & 0xFFFF) as u32;
-            return make_winapi_error_tuple(env, atoms::winapi_failed(), err_code);
+            return Err(StatError::Reason(atoms::not_directory()));
         }
-        let used = total.saturating_sub(free);
-        let map = rustler::types::map::map_new(env)
-            .map_put(atoms::available().to_term(env), avail)?
-            .map_put(atoms::free().to_term(env), free)?
-            .map_put(atoms::total().to_term(env), total)?
-            .map_put(atoms::used().to_term(env), used)?;
-        Ok(rustler::types::tuple::make_tuple(
-            env,
-            &[atoms::ok().to_term(env), map],
-        ))
+        disk_free_space(&wide_str)
+            .map_err(|err_code| StatError::WinApi(atoms::winapi_failed(), err_code))
     }
     #[cfg(unix)]
     {
-        let os_path = Path::new(OsStr::from_bytes(path_cstr.as_bytes()));
-        let metadata = match std::fs::metadata(&os_path) {
+        let os_path = Path::new(OsStr::from_bytes(path_cstr.to_bytes()));
+        let metadata = match std::fs::metadata(os_path) {
             Ok(m) => m,
-            Err(e) => return make_errno_error_tuple(env, atoms::not_directory(), e),
+            Err(e) => return Err(StatError::Errno(atoms::not_directory(), e)),
         };
         if !metadata.is_dir() {
-            return make_error_tuple(env, atoms::not_directory());
+            return Err(StatError::Reason(atoms::not_directory()));
         }
         #[cfg(target_os = "linux")]
         {
-            let statfs_buf: Statfs = match statfs(os_path) {
-                Ok(buf) => buf,
+            match statfs(os_path) {
+                Ok(buf) => Ok(space_of_statfs(&buf)),
                 Err(err) => {
                     let io_err = io::Error::from_raw_os_error(err as i32);
-                    return make_errno_error_tuple(env, atoms::statfs_failed(), io_err);
+                    Err(StatError::Errno(atoms::statfs_failed(), io_err))
                 }
-            };
-            let block_size = statfs_buf.block_size() as u64;
-            let avail = statfs_buf.blocks_available() as u64 * block_size;
-            let free = statfs_buf.blocks_free() as u64 * block_size;
-            let total = statfs_buf.blocks() as u64 * block_size;
-            let used = total.saturating_sub(free);
-            let map = rustler::types::map::map_new(env)
-                .map_put(atoms::available().to_term(env), avail)?
-                .map_put(atoms::free().to_term(env), free)?
-                .map_put(atoms::total().to_term(env), total)?
-                .map_put(atoms::used().to_term(env), used)?;
-            Ok(rustler::types::tuple::make_tuple(
-                env,
-                &[atoms::ok().to_term(env), map],
-            ))
+            }
         }
         #[cfg(not(target_os = "linux"))]
         {
-            let statvfs_buf: Statvfs = match statvfs(os_path) {
-                Ok(buf) => buf,
+            match statvfs(os_path) {
+                Ok(buf) => Ok(space_of_statvfs(&buf)),
                 Err(err) => {
                     let io_err = io::Error::from_raw_os_error(err as i32);
-                    return make_errno_error_tuple(env, atoms::statvfs_failed(), io_err);
+                    Err(StatError::Errno(atoms::statvfs_failed(), io_err))
                 }
-            };
-            let frag_size = statvfs_buf.fragment_size() as u64;
-            let avail = statvfs_buf.blocks_available() as u64 * frag_size;
-            let free = statvfs_buf.blocks_free() as u64 * frag_size;
-            let total = statvfs_buf.blocks() as u64 * frag_size;
-            let used = total.saturating_sub(free);
-            let map = rustler::types::map::map_new(env)
-                .map_put(atoms::available().to_term(env), avail)?
-                .map_put(atoms::free().to_term(env), free)?
-                .map_put(atoms::total().to_term(env), total)?
-                .map_put(atoms::used().to_term(env), used)?;
-            Ok(rustler::types::tuple::make_tuple(
-                env,
-                &[atoms::ok().to_term(env), map],
-            ))
+            }
         }
     }
 }
+// Helper: The space figures of the filesystem holding `wide_path`, or the
+// WinAPI error code
+#[cfg(windows)]
+fn disk_free_space(wide_path: &WideCString) -> Result<Space, u32> {
+    let mut avail: u64 = 0;
+    let mut total: u64 = 0;
+    let mut free: u64 = 0;
+    let result = unsafe {
+        GetDiskFreeSpaceExW(
+            PCWSTR::from_raw(wide_path.as_ptr()),
+            Some(&mut avail),
+            Some(&mut total),
+            Some(&mut free),
+        )
+    };
+    if let Err(e) = result {
+        return Err((e.code().0 & 0xFFFF) as u32);
+    }
+    let used = total.saturating_sub(free);
+    Ok(Space {
+        available: avail,
+        free,
+        total,
+        used,
+    })
+}
+// Helper: The space figures in `statfs_buf`, whose types vary from target to target
+#[cfg(target_os = "linux")]
+#[allow(clippy::unnecessary_cast)]
+fn space_of_statfs(statfs_buf: &Statfs) -> Space {
+    let block_size = statfs_buf.block_size() as u64;
+    let avail = statfs_buf.blocks_available() as u64 * block_size;
+    let free = statfs_buf.blocks_free() as u64 * block_size;
+    let total = statfs_buf.blocks() as u64 * block_size;
+    let used = total.saturating_sub(free);
+    Space {
+        available: avail,
+        free,
+        total,
+        used,
+    }
+}
+// Helper: The space figures in `statvfs_buf`, whose types vary from target to target
+#[cfg(all(unix, not(target_os = "linux")))]
+#[allow(clippy::unnecessary_cast)]
+fn space_of_statvfs(statvfs_buf: &Statvfs) -> Space {
+    let frag_size = statvfs_buf.fragment_size() as u64;
+    let avail = statvfs_buf.blocks_available() as u64 * frag_size;
+    let free = statvfs_buf.blocks_free() as u64 * frag_size;
+    let total = statvfs_buf.blocks() as u64 * frag_size;
+    let used = total.saturating_sub(free);
+    Space {
+        available: avail,
+        free,
+        total,
+        used,
+    }
+}
 rustler::init!("Elixir.DiskSpace");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_a_path_without_allocating() {
+        let dir = std::env::temp_dir();
+        let bytes = dir.as_os_str().as_encoded_bytes();
+        // Once first, for what the statics of telemetry set up
+        let _ = SmallPath::new(bytes).map(|path| stat_path(path.as_c_str()).is_ok());
+        let before = alloc_count::count();
+        let Some(path) = SmallPath::new(bytes) else {
+            panic!("{} not decoded", dir.display());
+        };
+        let statted = stat_path(path.as_c_str()).is_ok();
+        let allocations = alloc_count::count() - before;
+        assert!(statted);
+        // Those of stat_fs short of its terms, which the BEAM allocates
+        assert_eq!(allocations, 0);
+    }
+}
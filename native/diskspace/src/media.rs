@@ -0,0 +1,279 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// media: what a Windows drive is, so that callers can tell a backup target
+// on an internal disk from one on a USB disk that may be unplugged. The
+// kind of drive is what GetDriveTypeW says of the root of its volume:
+// removable, fixed, a CD or DVD drive, a RAM disk or a network share. A USB
+// hard disk or SSD says it is fixed, as its media are; the STORAGE_DEVICE_
+// DESCRIPTOR of the disk beneath the volume tells it by its BusType, and
+// it is :usb_fixed. A fixed drive whose disk says its media are removable
+// after all, as some card readers do, is :removable.
+//
+// stat's media option adds it for the volume holding a path, the
+// list_mounts option of the same name to each mount; it is nil on other
+// platforms.
+//
+// media_ready: whether the media of a removable drive, an SD card say, are
+// in it and mounted, so that a kiosk offers to export to it only then. On
+// Windows the free space of the drive is asked for with the critical error
+// mode of the thread on, which fails with ERROR_NOT_READY where Windows
+// would otherwise ask the user to insert a disk; the error mode of the
+// thread rather than of the process, as other threads of the VM have their
+// own. On Linux and macOS the drive is ready if the filesystem holding the
+// path is on a removable disk, or a device node given is mounted; the node
+// of a card reader comes and goes with the card, as mmcblk0 does, and a
+// missing one under /dev has no media.
+
+use crate::mounts::Mount;
+use crate::{atoms, get_path_from_term, make_error_tuple};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use crate::{make_errno_error_tuple, path_from_cstring, posix};
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+use rustler::Atom;
+use rustler::{Encoder, Env, NifResult, Term};
+#[cfg(windows)]
+use {
+    crate::{device, make_winapi_error_tuple, volume_device, volume_root},
+    widestring::WideCString,
+    windows::core::PCWSTR,
+    windows::Win32::Foundation::ERROR_NOT_READY,
+    windows::Win32::Storage::FileSystem::{GetDiskFreeSpaceExW, GetDriveTypeW},
+    windows::Win32::System::Diagnostics::Debug::{
+        SetThreadErrorMode, SEM_FAILCRITICALERRORS, SEM_NOOPENFILEERRORBOX, THREAD_ERROR_MODE,
+    },
+    windows::Win32::System::WindowsProgramming::{
+        DRIVE_CDROM, DRIVE_FIXED, DRIVE_RAMDISK, DRIVE_REMOTE, DRIVE_REMOVABLE,
+    },
+};
+
+#[cfg(any(windows, test))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Media {
+    Fixed,
+    Removable,
+    // A fixed disk attached by USB
+    UsbFixed,
+    Cdrom,
+    Ramdisk,
+    Remote,
+    Unknown,
+}
+
+#[cfg(windows)]
+impl Media {
+    fn atom(self) -> Atom {
+        match self {
+            Media::Fixed => atoms::fixed(),
+            Media::Removable => atoms::removable(),
+            Media::UsbFixed => atoms::usb_fixed(),
+            Media::Cdrom => atoms::cdrom(),
+            Media::Ramdisk => atoms::ramdisk(),
+            Media::Remote => atoms::remote(),
+            Media::Unknown => atoms::unknown(),
+        }
+    }
+}
+
+// Whether the media of a removable drive are in it and mounted
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Readiness {
+    Ready,
+    NoMedia,
+    NotRemovable,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+impl Readiness {
+    fn atom(self) -> Atom {
+        match self {
+            Readiness::Ready => atoms::ready(),
+            Readiness::NoMedia => atoms::no_media(),
+            Readiness::NotRemovable => atoms::not_removable(),
+        }
+    }
+}
+
+// The drive types of GetDriveTypeW, DRIVE_UNKNOWN and DRIVE_NO_ROOT_DIR
+// being unknown
+#[cfg(windows)]
+const DRIVE_TYPES: [(u32, Media); 5] = [
+    (DRIVE_REMOVABLE, Media::Removable),
+    (DRIVE_FIXED, Media::Fixed),
+    (DRIVE_REMOTE, Media::Remote),
+    (DRIVE_CDROM, Media::Cdrom),
+    (DRIVE_RAMDISK, Media::Ramdisk),
+];
+
+// Helper: The media of a drive GetDriveTypeW says is `drive`, on a disk
+// attached by USB and with removable media as `attachment` says, if asked
+#[cfg(any(windows, test))]
+fn classify(drive: Media, attachment: Option<(bool, bool)>) -> Media {
+    match (drive, attachment) {
+        (Media::Fixed, Some((_, true))) => Media::Removable,
+        (Media::Fixed, Some((true, false))) => Media::UsbFixed,
+        (drive, _) => drive,
+    }
+}
+
+// Helper: The media of the volume mounted at `root`, whose device is
+// `volume`, as \\?\Volume{GUID}, None for a network share, which has none
+#[cfg(windows)]
+fn media_of(root: &WideCString, volume: Option<&WideCString>) -> Media {
+    let drive_type = unsafe { GetDriveTypeW(PCWSTR::from_raw(root.as_ptr())) };
+    let drive = DRIVE_TYPES
+        .iter()
+        .find(|(known, _)| *known == drive_type)
+        .map_or(Media::Unknown, |(_, media)| *media);
+    // Only a fixed drive has its disk asked
+    let attachment = volume
+        .filter(|_| drive == Media::Fixed)
+        .and_then(|volume| device::attachment(volume).ok());
+    classify(drive, attachment)
+}
+
+// Helper: Whether the media of the drive mounted at `root` are in it, or
+// the WinAPI error code, asked without Windows asking the user for them
+#[cfg(windows)]
+fn readiness(root: &WideCString) -> Result<Readiness, u32> {
+    let mut previous = THREAD_ERROR_MODE(0);
+    let _ = unsafe {
+        SetThreadErrorMode(
+            SEM_FAILCRITICALERRORS | SEM_NOOPENFILEERRORBOX,
+            Some(&mut previous),
+        )
+    };
+    let volume = volume_device(root).ok();
+    let readiness = match media_of(root, volume.as_ref()) {
+        Media::Removable | Media::UsbFixed | Media::Cdrom => {
+            match unsafe { GetDiskFreeSpaceExW(PCWSTR::from_raw(root.as_ptr()), None, None, None) }
+            {
+                Ok(()) => Ok(Readiness::Ready),
+                Err(e) if (e.code().0 & 0xFFFF) as u32 == ERROR_NOT_READY.0 => {
+                    Ok(Readiness::NoMedia)
+                }
+                Err(e) => Err((e.code().0 & 0xFFFF) as u32),
+            }
+        }
+        _ => Ok(Readiness::NotRemovable),
+    };
+    let _ = unsafe { SetThreadErrorMode(previous, None) };
+    readiness
+}
+
+// Helper: `map` with the media of `mount` put, nil off Windows
+pub(crate) fn put<'a>(env: Env<'a>, map: Term<'a>, mount: &Mount) -> NifResult<Term<'a>> {
+    #[cfg(windows)]
+    let media = WideCString::from_str(&mount.mount_point).ok().map(|root| {
+        let volume = WideCString::from_str(mount.source.trim_end_matches('\\')).ok();
+        media_of(&root, volume.as_ref()).atom()
+    });
+    #[cfg(not(windows))]
+    let media = {
+        let _ = mount;
+        rustler::types::atom::nil()
+    };
+    map.map_put(atoms::media().to_term(env), media)
+}
+
+// {:ok, media} of the volume holding `path`, nil off Windows
+#[rustler::nif(schedule = "DirtyIo")]
+fn media_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(windows)]
+    {
+        let Some(path) = path
+            .to_str()
+            .ok()
+            .and_then(|path| WideCString::from_str(path).ok())
+        else {
+            return make_error_tuple(env, atoms::path_conversion_failed());
+        };
+        let root = match volume_root(&path) {
+            Ok(root) => root,
+            Err(code) => return make_winapi_error_tuple(env, atoms::invalid_path(), code),
+        };
+        let volume = volume_device(&root).ok();
+        Ok((atoms::ok(), media_of(&root, volume.as_ref()).atom()).encode(env))
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = path;
+        Ok((atoms::ok(), rustler::types::atom::nil()).encode(env))
+    }
+}
+
+// {:ok, :ready | :no_media | :not_removable} of the drive holding `path`,
+// or whose root or device node it is
+#[rustler::nif(schedule = "DirtyIo")]
+fn media_ready_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(windows)]
+    {
+        let Ok(path) = path.to_str() else {
+            return make_error_tuple(env, atoms::path_conversion_failed());
+        };
+        // A drive as "E:" is its root
+        let path = match path.len() == 2 && path.ends_with(':') {
+            true => format!("{path}\\"),
+            false => path.to_string(),
+        };
+        let Ok(path) = WideCString::from_str(&path) else {
+            return make_error_tuple(env, atoms::path_conversion_failed());
+        };
+        let root = match volume_root(&path) {
+            Ok(root) => root,
+            Err(code) => return make_winapi_error_tuple(env, atoms::invalid_path(), code),
+        };
+        match readiness(&root) {
+            Ok(readiness) => Ok((atoms::ok(), readiness.atom()).encode(env)),
+            Err(code) => make_winapi_error_tuple(env, atoms::winapi_failed(), code),
+        }
+    }
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        let Some(path) = path_from_cstring(&path) else {
+            return make_error_tuple(env, atoms::invalid_path());
+        };
+        match crate::device::media_ready(&path) {
+            Ok(readiness) => Ok((atoms::ok(), readiness.atom()).encode(env)),
+            Err(err) => make_errno_error_tuple(env, posix::atom(env, &err), err),
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_usb_disks_from_internal_ones() {
+        assert_eq!(classify(Media::Fixed, Some((false, false))), Media::Fixed);
+        assert_eq!(classify(Media::Fixed, Some((true, false))), Media::UsbFixed);
+        assert_eq!(classify(Media::Fixed, Some((true, true))), Media::Removable);
+        assert_eq!(
+            classify(Media::Fixed, Some((false, true))),
+            Media::Removable
+        );
+        // Not to be asked, as without a volume
+        assert_eq!(classify(Media::Fixed, None), Media::Fixed);
+        assert_eq!(
+            classify(Media::Removable, Some((true, true))),
+            Media::Removable
+        );
+        assert_eq!(classify(Media::Remote, None), Media::Remote);
+        for drive in [Media::Cdrom, Media::Ramdisk, Media::Unknown] {
+            assert_eq!(classify(drive, Some((true, false))), drive);
+        }
+    }
+}
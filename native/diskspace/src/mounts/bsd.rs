@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// getfsstat(2) based enumeration for macOS and the BSDs, getvfsstat(2) on
+// NetBSD, which returns statvfs structures where everyone else returns
+// statfs. Each call reads into a buffer of its own: the buffer of
+// getmntinfo(3) is a single one for the whole process, freed and allocated
+// again by each call, and the mount table is read from several threads at
+// once. Where libc offers neither, getmntinfo is called under a lock held
+// until its entries are copied.
+
+use super::Mount;
+use std::io;
+use std::os::raw::c_char;
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+use std::sync::{Mutex, PoisonError};
+
+#[cfg(target_os = "netbsd")]
+type MntBuf = libc::statvfs;
+#[cfg(not(target_os = "netbsd"))]
+type MntBuf = libc::statfs;
+
+// Helper: getfsstat into `buf`, room for `capacity` entries, or the number
+// of mounts for a null `buf`. MNT_NOWAIT: don't block on unresponsive
+// network filesystems
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
+unsafe fn fsstat(buf: *mut MntBuf, capacity: usize) -> libc::c_int {
+    let size = capacity * std::mem::size_of::<MntBuf>();
+    libc::getfsstat(buf, size as _, libc::MNT_NOWAIT)
+}
+
+#[cfg(target_os = "netbsd")]
+unsafe fn fsstat(buf: *mut MntBuf, capacity: usize) -> libc::c_int {
+    let size = capacity * std::mem::size_of::<MntBuf>();
+    libc::getvfsstat(buf, size, libc::MNT_NOWAIT)
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+pub(crate) fn read() -> io::Result<Vec<Mount>> {
+    let count = unsafe { fsstat(std::ptr::null_mut(), 0) };
+    if count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Room for a few more, as mounts may come between the calls; a full
+    // buffer may have left some out, and is read again twice the size
+    let mut capacity = count as usize + 8;
+    loop {
+        let mut buf: Vec<MntBuf> = Vec::with_capacity(capacity);
+        let filled = unsafe { fsstat(buf.as_mut_ptr(), capacity) };
+        if filled < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let filled = filled as usize;
+        if filled < capacity {
+            // The first `filled` entries were written by the call
+            unsafe { buf.set_len(filled) };
+            return Ok(buf.iter().map(to_mount).collect());
+        }
+        capacity *= 2;
+    }
+}
+
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+pub(crate) fn read() -> io::Result<Vec<Mount>> {
+    // Held until the entries are copied, as the next call frees the buffer
+    static GETMNTINFO: Mutex<()> = Mutex::new(());
+    let _held = GETMNTINFO.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut buf: *mut MntBuf = std::ptr::null_mut();
+    // MNT_NOWAIT: don't block on unresponsive network filesystems
+    let count = unsafe { libc::getmntinfo(&mut buf, libc::MNT_NOWAIT) };
+    if count <= 0 || buf.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let entries = unsafe { std::slice::from_raw_parts(buf, count as usize) };
+    Ok(entries.iter().map(to_mount).collect())
+}
+
+fn c_field(field: &[c_char]) -> String {
+    let bytes: Vec<u8> = field
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn to_mount(entry: &MntBuf) -> Mount {
+    #[cfg(target_os = "netbsd")]
+    let (flags, fsid, read_only_flag) =
+        (entry.f_flag as u64, entry.f_fsidx, libc::ST_RDONLY as u64);
+    #[cfg(not(target_os = "netbsd"))]
+    let (flags, fsid, read_only_flag) =
+        (entry.f_flags as u64, entry.f_fsid, libc::MNT_RDONLY as u64);
+    // fsid_t keeps its two words private on the BSDs
+    let fsid: [i32; 2] = unsafe { std::mem::transmute(fsid) };
+    let read_only = flags & read_only_flag != 0;
+    Mount {
+        mount_id: None,
+        parent_id: None,
+        device: format!("{:x}:{:x}", fsid[0], fsid[1]),
+        root: None,
+        mount_point: c_field(&entry.f_mntonname),
+        fs_type: c_field(&entry.f_fstypename),
+        source: c_field(&entry.f_mntfromname),
+        options: vec![if read_only { "ro" } else { "rw" }.to_string()],
+        read_only,
+    }
+}
@@ -0,0 +1,125 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Comparison of two mount tables. Entries are matched on
+// (mount_point, device, root) rather than mount IDs, which the kernel
+// recycles; `root` tells bind mounts of the same device apart. Duplicate
+// keys (stacked mounts) are paired up in table order.
+
+use super::Mount;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Diff<'m> {
+    pub added: Vec<&'m Mount>,
+    pub removed: Vec<&'m Mount>,
+    // (old, new) pairs that differ in read-only state, options, type or source
+    pub changed: Vec<(&'m Mount, &'m Mount)>,
+}
+
+fn key(mount: &Mount) -> (&str, &str, Option<&str>) {
+    (&mount.mount_point, &mount.device, mount.root.as_deref())
+}
+
+fn differs(old: &Mount, new: &Mount) -> bool {
+    old.read_only != new.read_only
+        || old.options != new.options
+        || old.fs_type != new.fs_type
+        || old.source != new.source
+}
+
+pub(crate) fn diff<'m>(old: &'m [Mount], new: &'m [Mount]) -> Diff<'m> {
+    let mut unmatched: HashMap<_, Vec<&Mount>> = HashMap::new();
+    for mount in old.iter().rev() {
+        unmatched.entry(key(mount)).or_default().push(mount);
+    }
+    let mut result = Diff::default();
+    for mount in new {
+        match unmatched.get_mut(&key(mount)).and_then(Vec::pop) {
+            Some(previous) if differs(previous, mount) => result.changed.push((previous, mount)),
+            Some(_) => {}
+            None => result.added.push(mount),
+        }
+    }
+    // Keep the removed entries in their original table order
+    result.removed = old
+        .iter()
+        .filter(|m| {
+            unmatched
+                .get(&key(m))
+                .is_some_and(|rest| rest.iter().any(|r| std::ptr::eq(*r, *m)))
+        })
+        .collect();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mountinfo::parse;
+    use super::*;
+
+    const UBUNTU: &str = include_str!("../../fixtures/mountinfo_ubuntu.txt");
+
+    #[test]
+    fn identical_tables_have_no_differences() {
+        let mounts = parse(UBUNTU);
+        assert_eq!(diff(&mounts, &mounts), Diff::default());
+    }
+
+    #[test]
+    fn detects_mounts_unmounts_and_remounts() {
+        let old = parse(UBUNTU);
+        let mut new = old.clone();
+        // Unmount /boot, remount /srv/nfs read-only, mount a USB stick
+        new.retain(|m| m.mount_point != "/boot");
+        let nfs = new
+            .iter_mut()
+            .find(|m| m.mount_point == "/srv/nfs")
+            .unwrap();
+        nfs.options[0] = "ro".to_string();
+        nfs.read_only = true;
+        new.push(Mount {
+            mount_id: Some(120),
+            parent_id: Some(30),
+            device: "8:17".to_string(),
+            root: Some("/".to_string()),
+            mount_point: "/media/usb".to_string(),
+            fs_type: "vfat".to_string(),
+            source: "/dev/sdb1".to_string(),
+            options: vec!["rw".to_string()],
+            read_only: false,
+        });
+        let result = diff(&old, &new);
+        assert_eq!(result.added.len(), 1);
+        assert_eq!(result.added[0].mount_point, "/media/usb");
+        assert_eq!(result.removed.len(), 1);
+        assert_eq!(result.removed[0].mount_point, "/boot");
+        assert_eq!(result.changed.len(), 1);
+        let (before, after) = result.changed[0];
+        assert!(!before.read_only && after.read_only);
+    }
+
+    #[test]
+    fn recycled_mount_ids_are_not_changes() {
+        let old = parse(UBUNTU);
+        let mut new = old.clone();
+        for mount in new.iter_mut() {
+            mount.mount_id = mount.mount_id.map(|id| id + 1000);
+        }
+        assert_eq!(diff(&old, &new), Diff::default());
+    }
+
+    #[test]
+    fn pairs_stacked_duplicates() {
+        let old = parse(UBUNTU);
+        let boot = old.iter().find(|m| m.mount_point == "/boot").unwrap();
+        let mut new = old.clone();
+        new.push(boot.clone());
+        let result = diff(&old, &new);
+        assert_eq!(result.added, [boot]);
+        assert!(result.removed.is_empty());
+        let result = diff(&new, &old);
+        assert_eq!(result.removed, [boot]);
+        assert!(std::ptr::eq(result.removed[0], &new[new.len() - 1]));
+    }
+}
@@ -0,0 +1,200 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Composable mount filters. Each entry of the `exclude:` option is either a
+// named preset atom or a `{kind, value}` tuple; a mount is dropped when any
+// of the filters matches it.
+
+use super::Mount;
+use crate::atoms;
+use rustler::{Atom, Term};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Filter {
+    // Kernel and virtual filesystems that never hold user data
+    Pseudo,
+    // Read-only squashfs loop mounts of snap packages
+    SquashfsLoop,
+    // FUSE mounts of running AppImages
+    AppImage,
+    FsType(String),
+    PathPrefix(String),
+}
+
+const PSEUDO_FS_TYPES: &[&str] = &[
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devfs",
+    "devpts",
+    "efivarfs",
+    "fdescfs",
+    "fusectl",
+    "hugetlbfs",
+    "linprocfs",
+    "linsysfs",
+    "kernfs",
+    "mqueue",
+    "nsfs",
+    "proc",
+    "procfs",
+    "pstore",
+    "ptyfs",
+    "rpc_pipefs",
+    "securityfs",
+    "selinuxfs",
+    "sysfs",
+    "tracefs",
+];
+
+const SNAP_PATHS: &[&str] = &["/snap", "/var/lib/snapd", "/run"];
+
+impl Filter {
+    pub(crate) fn matches(&self, mount: &Mount) -> bool {
+        match self {
+            Filter::Pseudo => PSEUDO_FS_TYPES.contains(&mount.fs_type.as_str()),
+            Filter::SquashfsLoop => {
+                mount.fs_type == "squashfs"
+                    && mount.read_only
+                    && mount.source.starts_with("/dev/loop")
+                    && SNAP_PATHS.iter().any(|p| is_under(&mount.mount_point, p))
+            }
+            Filter::AppImage => {
+                mount.fs_type.starts_with("fuse.")
+                    && mount.read_only
+                    && is_under(&mount.mount_point, "/tmp")
+                    && mount.mount_point[4..].starts_with("/.mount_")
+            }
+            Filter::FsType(fs_type) => mount.fs_type == *fs_type,
+            Filter::PathPrefix(prefix) => is_under(&mount.mount_point, prefix),
+        }
+    }
+}
+
+// Helper: Path-component-aware prefix check ("/run" covers "/run/x" but not "/runner")
+pub(crate) fn is_under(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path == prefix || (path.starts_with(prefix) && path.as_bytes().get(prefix.len()) == Some(&b'/'))
+}
+
+pub(crate) fn excluded(filters: &[Filter], mount: &Mount) -> bool {
+    filters.iter().any(|f| f.matches(mount))
+}
+
+// Helper: Decode the `exclude:` option, a single filter or a list of them
+pub(crate) fn decode(term: Term) -> Option<Vec<Filter>> {
+    match term.decode::<Vec<Term>>() {
+        Ok(list) => list.into_iter().map(decode_one).collect(),
+        Err(_) => decode_one(term).map(|f| vec![f]),
+    }
+}
+
+fn decode_one(term: Term) -> Option<Filter> {
+    if let Ok(preset) = term.decode::<Atom>() {
+        return if preset == atoms::pseudo() {
+            Some(Filter::Pseudo)
+        } else if preset == atoms::squashfs_loop() {
+            Some(Filter::SquashfsLoop)
+        } else if preset == atoms::appimage() {
+            Some(Filter::AppImage)
+        } else {
+            None
+        };
+    }
+    let (kind, value): (Atom, String) = term.decode().ok()?;
+    if kind == atoms::fs_type() {
+        Some(Filter::FsType(value))
+    } else if kind == atoms::path_prefix() {
+        Some(Filter::PathPrefix(value))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mountinfo::parse;
+    use super::*;
+
+    fn kept(fixture: &str, filters: &[Filter]) -> Vec<String> {
+        parse(fixture)
+            .into_iter()
+            .filter(|m| !excluded(filters, m))
+            .map(|m| m.mount_point)
+            .collect()
+    }
+
+    const UBUNTU: &str = include_str!("../../fixtures/mountinfo_ubuntu.txt");
+    const FEDORA: &str = include_str!("../../fixtures/mountinfo_fedora.txt");
+
+    #[test]
+    fn squashfs_loop_drops_snaps_on_ubuntu() {
+        let mounts = kept(UBUNTU, &[Filter::SquashfsLoop]);
+        assert!(!mounts.iter().any(|m| m.starts_with("/snap/")));
+        assert!(!mounts.contains(&"/var/lib/snapd/seed/snaps/core22".to_string()));
+        assert!(!mounts.contains(&"/run/snapd/ns/lxd.mnt".to_string()));
+        // Deliberately mounted images elsewhere, and writable ones, survive
+        assert!(mounts.contains(&"/mnt/Release Images".to_string()));
+        assert!(mounts.contains(&"/srv/rootfs".to_string()));
+        assert_eq!(mounts.len(), 20);
+    }
+
+    #[test]
+    fn squashfs_loop_keeps_squashfs_outside_snap_paths_on_fedora() {
+        let mounts = kept(FEDORA, &[Filter::SquashfsLoop]);
+        assert_eq!(mounts.len(), 27);
+        assert!(mounts.contains(&"/var/lib/images/toolbox".to_string()));
+    }
+
+    #[test]
+    fn appimage_drops_only_appimage_fuse_mounts() {
+        let mounts = kept(UBUNTU, &[Filter::AppImage]);
+        assert!(!mounts.iter().any(|m| m.starts_with("/tmp/.mount_")));
+        assert!(mounts.contains(&"/run/user/1000/doc".to_string()));
+        assert_eq!(kept(FEDORA, &[Filter::AppImage]).len(), 27);
+    }
+
+    #[test]
+    fn presets_compose() {
+        let filters = [Filter::Pseudo, Filter::SquashfsLoop, Filter::AppImage];
+        assert_eq!(
+            kept(UBUNTU, &filters),
+            [
+                "/dev",
+                "/run",
+                "/",
+                "/dev/shm",
+                "/boot",
+                "/mnt/Release Images",
+                "/srv/rootfs",
+                "/srv/nfs",
+                "/run/user/1000",
+                "/run/user/1000/doc"
+            ]
+        );
+        assert_eq!(
+            kept(FEDORA, &[Filter::Pseudo, Filter::FsType("tmpfs".into())]),
+            [
+                "/dev",
+                "/",
+                "/home",
+                "/boot",
+                "/boot/efi",
+                "/var/lib/images/toolbox",
+                "/run/user/1000/gvfs"
+            ]
+        );
+    }
+
+    #[test]
+    fn path_prefix_respects_component_boundaries() {
+        assert!(is_under("/run", "/run"));
+        assert!(is_under("/run/user", "/run/"));
+        assert!(!is_under("/runner", "/run"));
+        assert!(is_under("/anything", "/"));
+    }
+}
@@ -0,0 +1,270 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Mounted filesystem enumeration: /proc/self/mountinfo on Linux,
+// getfsstat(2) on macOS and the BSDs, volume enumeration on Windows.
+
+#[cfg(all(unix, not(target_os = "linux")))]
+mod bsd;
+pub(crate) mod diff;
+pub(crate) mod filter;
+#[cfg(any(target_os = "linux", test))]
+pub(crate) mod mountinfo;
+#[cfg(windows)]
+mod windows;
+
+use crate::{
+    atoms, config, device, make_errno_error_tuple, make_error_tuple, make_error_tuple3, media,
+    options, telemetry, tmpfs, volume_id,
+};
+use rustler::{Encoder, Env, NifResult, ResourceArc, Term};
+use std::io;
+#[cfg(unix)]
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Mount {
+    // Linux only: mount IDs from mountinfo (these get recycled)
+    pub mount_id: Option<u32>,
+    pub parent_id: Option<u32>,
+    // "major:minor" on Linux, the fsid on the BSDs, the volume serial on Windows
+    pub device: String,
+    // Linux only: the root of the mount within its filesystem (bind mounts)
+    pub root: Option<String>,
+    pub mount_point: String,
+    pub fs_type: String,
+    pub source: String,
+    pub options: Vec<String>,
+    pub read_only: bool,
+}
+
+impl Mount {
+    pub(crate) fn encode<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        rustler::types::map::map_new(env)
+            .map_put(atoms::mount_id().to_term(env), self.mount_id)?
+            .map_put(atoms::parent_id().to_term(env), self.parent_id)?
+            .map_put(atoms::device().to_term(env), &self.device)?
+            .map_put(atoms::root().to_term(env), &self.root)?
+            .map_put(atoms::mount_point().to_term(env), &self.mount_point)?
+            .map_put(atoms::fs_type().to_term(env), &self.fs_type)?
+            .map_put(atoms::source().to_term(env), &self.source)?
+            .map_put(atoms::options().to_term(env), &self.options)?
+            .map_put(atoms::read_only().to_term(env), self.read_only)
+    }
+
+    // Helper: Rebuild a mount from a map previously returned by list_mounts
+    pub(crate) fn decode(term: Term) -> Option<Mount> {
+        let field = |key: rustler::Atom| term.map_get(key).ok();
+        Some(Mount {
+            mount_id: field(atoms::mount_id())?.decode().ok()?,
+            parent_id: field(atoms::parent_id())?.decode().ok()?,
+            device: field(atoms::device())?.decode().ok()?,
+            root: field(atoms::root())?.decode().ok()?,
+            mount_point: field(atoms::mount_point())?.decode().ok()?,
+            fs_type: field(atoms::fs_type())?.decode().ok()?,
+            source: field(atoms::source())?.decode().ok()?,
+            options: field(atoms::options())?.decode().ok()?,
+            read_only: field(atoms::read_only())?.decode().ok()?,
+        })
+    }
+}
+
+// Opaque, immutable copy of the mount table for cheap comparisons
+pub(crate) struct Snapshot {
+    pub mounts: Vec<Mount>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for Snapshot {}
+
+// Helper: Accept either a snapshot resource or a list of mount maps
+fn decode_table(term: Term) -> Option<Vec<Mount>> {
+    if let Ok(snapshot) = term.decode::<ResourceArc<Snapshot>>() {
+        return Some(snapshot.mounts.clone());
+    }
+    term.decode::<Vec<Term>>()
+        .ok()?
+        .into_iter()
+        .map(Mount::decode)
+        .collect()
+}
+
+// The mount table, counted for nif_stats
+pub(crate) fn list() -> io::Result<Vec<Mount>> {
+    telemetry::timed(telemetry::Op::ListMounts, read, |table| {
+        table.as_ref().err().map(|_| atoms::list_mounts_failed())
+    })
+}
+
+// Helper: The mount table of the platform
+fn read() -> io::Result<Vec<Mount>> {
+    #[cfg(target_os = "linux")]
+    {
+        mountinfo::read()
+    }
+    #[cfg(all(unix, not(target_os = "linux")))]
+    {
+        bsd::read()
+    }
+    #[cfg(windows)]
+    {
+        windows::read()
+    }
+}
+
+// The mount of the filesystem holding `path`: the last one mounted of those
+// of its device, or, for a path with a device of its own such as a btrfs
+// subvolume, the one with the longest mount point it lies under
+#[cfg(target_os = "linux")]
+pub(crate) fn holding(path: &Path) -> io::Result<Option<Mount>> {
+    use std::os::unix::fs::MetadataExt;
+    let path = std::fs::canonicalize(path)?;
+    let dev = std::fs::metadata(&path)?.dev();
+    let device = format!("{}:{}", libc::major(dev), libc::minor(dev));
+    let table = list()?;
+    if let Some(mount) = table.iter().rev().find(|mount| mount.device == device) {
+        return Ok(Some(mount.clone()));
+    }
+    Ok(table
+        .into_iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.len()))
+}
+
+// The mount of the filesystem holding `path`: the one with the longest mount
+// point it lies under
+#[cfg(all(unix, not(target_os = "linux")))]
+pub(crate) fn holding(path: &Path) -> io::Result<Option<Mount>> {
+    let path = std::fs::canonicalize(path)?;
+    Ok(list()?
+        .into_iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.len()))
+}
+
+// Helper: List the mounts that survive the `exclude:` option, or the error tuple
+fn list_filtered<'a>(env: Env<'a>, opts: Term<'a>) -> Result<Vec<Mount>, NifResult<Term<'a>>> {
+    let filters = match options::get_with(opts, atoms::exclude(), filter::decode) {
+        Ok(filters) => filters.or_else(config::exclude).unwrap_or_default(),
+        Err(key) => {
+            return Err(make_error_tuple3(
+                env,
+                atoms::invalid_option(),
+                key.to_term(env),
+            ))
+        }
+    };
+    match list() {
+        Ok(mut mounts) => {
+            mounts.retain(|m| !filter::excluded(&filters, m));
+            Ok(mounts)
+        }
+        Err(e) => Err(make_errno_error_tuple(env, atoms::list_mounts_failed(), e)),
+    }
+}
+
+fn encode_list<'a>(env: Env<'a>, mounts: &[&Mount]) -> NifResult<Term<'a>> {
+    let entries = mounts
+        .iter()
+        .map(|m| m.encode(env))
+        .collect::<NifResult<Vec<Term>>>()?;
+    Ok(entries.encode(env))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn list_mounts_nif<'a>(env: Env<'a>, opts: Term<'a>) -> NifResult<Term<'a>> {
+    let mounts = match list_filtered(env, opts) {
+        Ok(mounts) => mounts,
+        Err(error) => return error,
+    };
+    let device_info = match options::get(opts, atoms::device_info()) {
+        Ok(device_info) => device_info.unwrap_or(false),
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let volume_ids = match options::get(opts, atoms::volume_id()) {
+        Ok(volume_id) => volume_id.unwrap_or(false).then(volume_id::Lookup::new),
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let media = match options::get(opts, atoms::media()) {
+        Ok(media) => media.unwrap_or(false),
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let mem_total = mounts
+        .iter()
+        .any(|m| m.fs_type == "tmpfs")
+        .then(tmpfs::mem_total)
+        .flatten();
+    let entries = mounts
+        .iter()
+        .map(|m| {
+            let map = tmpfs::put(env, m.encode(env)?, m, mem_total)?;
+            let map = device::put_dm(env, map, m)?;
+            let map = match &volume_ids {
+                Some(lookup) => volume_id::put(env, map, m, lookup)?,
+                None => map,
+            };
+            let map = match media {
+                true => media::put(env, map, m)?,
+                false => map,
+            };
+            match device_info {
+                true => device::put(env, map, m),
+                false => Ok(map),
+            }
+        })
+        .collect::<NifResult<Vec<Term>>>()?
+        .encode(env);
+    Ok(rustler::types::tuple::make_tuple(
+        env,
+        &[atoms::ok().to_term(env), entries],
+    ))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn snapshot_nif<'a>(env: Env<'a>, opts: Term<'a>) -> NifResult<Term<'a>> {
+    let mounts = match list_filtered(env, opts) {
+        Ok(mounts) => mounts,
+        Err(error) => return error,
+    };
+    let snapshot = ResourceArc::new(Snapshot { mounts });
+    Ok(rustler::types::tuple::make_tuple(
+        env,
+        &[atoms::ok().to_term(env), snapshot.encode(env)],
+    ))
+}
+
+#[rustler::nif]
+fn mounts_diff_nif<'a>(env: Env<'a>, old: Term<'a>, new: Term<'a>) -> NifResult<Term<'a>> {
+    let (old, new) = match (decode_table(old), decode_table(new)) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return make_error_tuple(env, atoms::invalid_snapshot()),
+    };
+    let map = encode_diff(env, &diff::diff(&old, &new))?;
+    Ok(rustler::types::tuple::make_tuple(
+        env,
+        &[atoms::ok().to_term(env), map],
+    ))
+}
+
+// Helper: %{added, removed, changed} as mounts_diff returns it
+pub(crate) fn encode_diff<'a>(env: Env<'a>, result: &diff::Diff) -> NifResult<Term<'a>> {
+    let changed = result
+        .changed
+        .iter()
+        .map(|(before, after)| {
+            rustler::types::map::map_new(env)
+                .map_put(atoms::old().to_term(env), before.encode(env)?)?
+                .map_put(atoms::new().to_term(env), after.encode(env)?)
+        })
+        .collect::<NifResult<Vec<Term>>>()?;
+    rustler::types::map::map_new(env)
+        .map_put(
+            atoms::added().to_term(env),
+            encode_list(env, &result.added)?,
+        )?
+        .map_put(
+            atoms::removed().to_term(env),
+            encode_list(env, &result.removed)?,
+        )?
+        .map_put(atoms::changed().to_term(env), changed)
+}
@@ -0,0 +1,124 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Parser for the Linux /proc/<pid>/mountinfo format, see proc(5):
+// 36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+// The parser itself is platform-independent so it can be tested against
+// captured fixtures on any host.
+
+use super::Mount;
+
+#[cfg(target_os = "linux")]
+pub(crate) fn read() -> std::io::Result<Vec<Mount>> {
+    let contents = std::fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(parse(&contents))
+}
+
+// Helper: Parse a whole mountinfo file, silently skipping malformed lines
+pub(crate) fn parse(contents: &str) -> Vec<Mount> {
+    contents.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<Mount> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    // The optional fields are terminated by a lone "-", which can't appear
+    // before the sixth field.
+    let separator = fields.iter().skip(6).position(|f| *f == "-")? + 6;
+    if fields.len() < separator + 4 {
+        return None;
+    }
+    let mount_id = fields[0].parse().ok()?;
+    let parent_id = fields[1].parse().ok()?;
+    let mount_options: Vec<String> = fields[5].split(',').map(str::to_string).collect();
+    let super_options: Vec<&str> = fields[separator + 3].split(',').collect();
+    let read_only = mount_options.iter().any(|o| o == "ro") || super_options.contains(&"ro");
+    // Per-mount options first, then the superblock ones that add information
+    let mut options = mount_options;
+    for option in super_options {
+        if !options.iter().any(|o| o == option) {
+            options.push(option.to_string());
+        }
+    }
+    Some(Mount {
+        mount_id: Some(mount_id),
+        parent_id: Some(parent_id),
+        device: fields[2].to_string(),
+        root: Some(unescape(fields[3])),
+        mount_point: unescape(fields[4]),
+        fs_type: unescape(fields[separator + 1]),
+        source: unescape(fields[separator + 2]),
+        options,
+        read_only,
+    })
+}
+
+// Helper: Undo the kernel's octal escaping of space, tab, newline and backslash
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 3 < bytes.len() {
+            let octal = &bytes[i + 1..i + 4];
+            if octal.iter().all(|b| (b'0'..=b'7').contains(b)) {
+                let value = octal
+                    .iter()
+                    .fold(0u32, |acc, b| acc * 8 + u32::from(b - b'0'));
+                out.push(value as u8);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_optional_fields_and_merges_super_options() {
+        let line = "36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 shared:2 - ext3 /dev/root rw,errors=continue";
+        let mount = parse_line(line).unwrap();
+        assert_eq!(mount.mount_id, Some(36));
+        assert_eq!(mount.parent_id, Some(35));
+        assert_eq!(mount.device, "98:0");
+        assert_eq!(mount.root.as_deref(), Some("/mnt1"));
+        assert_eq!(mount.mount_point, "/mnt/parent");
+        assert_eq!(mount.fs_type, "ext3");
+        assert_eq!(mount.source, "/dev/root");
+        assert_eq!(mount.options, ["rw", "noatime", "errors=continue"]);
+        assert!(!mount.read_only);
+    }
+
+    #[test]
+    fn unescapes_octal_sequences() {
+        assert_eq!(unescape("/mnt/Release\\040Images"), "/mnt/Release Images");
+        assert_eq!(unescape("/a\\134b"), "/a\\b");
+        assert_eq!(unescape("/trailing\\04"), "/trailing\\04");
+    }
+
+    #[test]
+    fn skips_malformed_lines() {
+        assert!(parse("garbage\n36 35 98:0 / /x rw - ext4\n").is_empty());
+    }
+
+    #[test]
+    fn parses_fixtures() {
+        let ubuntu = parse(include_str!("../../fixtures/mountinfo_ubuntu.txt"));
+        assert_eq!(ubuntu.len(), 26);
+        let release = ubuntu
+            .iter()
+            .find(|m| m.mount_point == "/mnt/Release Images")
+            .unwrap();
+        assert!(release.read_only);
+        let fedora = parse(include_str!("../../fixtures/mountinfo_fedora.txt"));
+        assert_eq!(fedora.len(), 27);
+        assert!(fedora
+            .iter()
+            .any(|m| m.fs_type == "btrfs" && m.root.as_deref() == Some("/home")));
+    }
+}
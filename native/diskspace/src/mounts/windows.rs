@@ -0,0 +1,86 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Volume enumeration for Windows: every volume GUID path with at least one
+// mount point (drive letter or mounted folder) yields one entry per mount point.
+
+use super::Mount;
+use std::io;
+use windows::core::PCWSTR;
+use windows::Win32::Storage::FileSystem::{
+    FindFirstVolumeW, FindNextVolumeW, FindVolumeClose, GetVolumeInformationW,
+    GetVolumePathNamesForVolumeNameW,
+};
+use windows::Win32::System::SystemServices::FILE_READ_ONLY_VOLUME;
+
+const MAX_GUID_PATH: usize = 64;
+
+pub(crate) fn read() -> io::Result<Vec<Mount>> {
+    let mut name = [0u16; MAX_GUID_PATH];
+    let handle = unsafe { FindFirstVolumeW(&mut name) }
+        .map_err(|e| io::Error::from_raw_os_error(e.code().0 & 0xFFFF))?;
+    let mut mounts = Vec::new();
+    loop {
+        mounts.extend(volume_mounts(&name));
+        if unsafe { FindNextVolumeW(handle, &mut name) }.is_err() {
+            break;
+        }
+    }
+    let _ = unsafe { FindVolumeClose(handle) };
+    Ok(mounts)
+}
+
+fn from_wide(buffer: &[u16]) -> String {
+    let end = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+    String::from_utf16_lossy(&buffer[..end])
+}
+
+fn volume_mounts(name: &[u16]) -> Vec<Mount> {
+    let volume = PCWSTR::from_raw(name.as_ptr());
+    let mut needed: u32 = 0;
+    let _ = unsafe { GetVolumePathNamesForVolumeNameW(volume, None, &mut needed) };
+    if needed == 0 {
+        return Vec::new();
+    }
+    let mut paths = vec![0u16; needed as usize];
+    if unsafe { GetVolumePathNamesForVolumeNameW(volume, Some(&mut paths), &mut needed) }.is_err() {
+        return Vec::new();
+    }
+    let mut serial: u32 = 0;
+    let mut flags: u32 = 0;
+    let mut fs_name = [0u16; 32];
+    // Fails for empty card readers and unformatted volumes; keep the entry
+    let info = unsafe {
+        GetVolumeInformationW(
+            volume,
+            None,
+            Some(&mut serial),
+            None,
+            Some(&mut flags),
+            Some(&mut fs_name),
+        )
+    };
+    let fs_type = if info.is_ok() {
+        from_wide(&fs_name)
+    } else {
+        String::new()
+    };
+    let read_only = info.is_ok() && flags & FILE_READ_ONLY_VOLUME != 0;
+    let source = from_wide(name);
+    // The path list is a sequence of NUL-terminated strings ending in an empty one
+    paths
+        .split(|c| *c == 0)
+        .filter(|p| !p.is_empty())
+        .map(|p| Mount {
+            mount_id: None,
+            parent_id: None,
+            device: format!("{:04X}-{:04X}", serial >> 16, serial & 0xFFFF),
+            root: None,
+            mount_point: String::from_utf16_lossy(p),
+            fs_type: fs_type.clone(),
+            source: source.clone(),
+            options: vec![if read_only { "ro" } else { "rw" }.to_string()],
+            read_only,
+        })
+        .collect()
+}
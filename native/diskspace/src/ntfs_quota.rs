@@ -0,0 +1,217 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// quota_info: the NTFS quota of the calling user on the volume holding a
+// path, the usual reason GetDiskFreeSpaceExW reports less space available
+// to them than free. Windows only; elsewhere quota_info returns
+// {:error, :unsupported}, quota/2 reading those of Linux instead.
+//
+// The quota comes from the DiskQuotaControl COM object of the volume, by
+// the SID of the user owning the process token, so that no account name
+// has to be resolved. COM wants an apartment, and a dirty scheduler thread
+// may have been left in either kind by whatever ran on it before, so each
+// call initializes a single-threaded one on a thread of its own, torn down
+// once the quota is read.
+//
+// Limits of -1 (none) and -2 (not set) are nil. A volume that neither
+// tracks nor enforces quotas returns {:error, :quotas_disabled}.
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Env, NifResult, Term};
+#[cfg(windows)]
+use {
+    crate::{make_winapi_error_tuple, volume_root},
+    rustler::{Atom, Encoder},
+    std::thread,
+    widestring::WideCString,
+    windows::core::PCWSTR,
+    windows::Win32::Foundation::{CloseHandle, HANDLE},
+    windows::Win32::Security::{GetTokenInformation, TokenUser, TOKEN_QUERY, TOKEN_USER},
+    windows::Win32::Storage::FileSystem::{
+        CLSID_DiskQuotaControl, IDiskQuotaControl, DISKQUOTA_USERNAME_RESOLVE_NONE,
+    },
+    windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_INPROC_SERVER,
+        COINIT_APARTMENTTHREADED,
+    },
+    windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken},
+};
+
+// The quota states of <dskquota.h>, in the low bits of the state of a volume
+#[cfg(any(windows, test))]
+const DISKQUOTA_STATE_MASK: u32 = 3;
+#[cfg(any(windows, test))]
+const DISKQUOTA_STATE_DISABLED: u32 = 0;
+#[cfg(any(windows, test))]
+const DISKQUOTA_STATE_ENFORCE: u32 = 2;
+
+// The quota of a user on a volume, in bytes
+#[cfg(any(windows, test))]
+#[derive(Debug, PartialEq)]
+struct QuotaInfo {
+    enforced: bool,
+    limit: Option<u64>,
+    used: u64,
+    default_limit: Option<u64>,
+}
+
+#[cfg(any(windows, test))]
+impl QuotaInfo {
+    // From the quota state of the volume and the figures of its
+    // IDiskQuotaControl, None if the volume has quotas disabled
+    fn decode(state: u32, limit: i64, used: i64, default_limit: i64) -> Option<QuotaInfo> {
+        match state & DISKQUOTA_STATE_MASK {
+            DISKQUOTA_STATE_DISABLED => None,
+            state => Some(QuotaInfo {
+                enforced: state == DISKQUOTA_STATE_ENFORCE,
+                limit: u64::try_from(limit).ok(),
+                used: u64::try_from(used).unwrap_or(0),
+                default_limit: u64::try_from(default_limit).ok(),
+            }),
+        }
+    }
+}
+
+// Why reading a quota failed
+#[cfg(windows)]
+enum Failure {
+    Disabled,
+    WinApi(Atom, u32),
+}
+
+#[cfg(windows)]
+impl From<windows::core::Error> for Failure {
+    fn from(err: windows::core::Error) -> Failure {
+        Failure::WinApi(atoms::winapi_failed(), (err.code().0 & 0xFFFF) as u32)
+    }
+}
+
+// Helper: The SID of the user owning the process token, within the
+// TOKEN_USER it is read from
+#[cfg(windows)]
+fn token_user() -> Result<Vec<u64>, Failure> {
+    let mut token = HANDLE::default();
+    unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) }?;
+    let mut length = 0;
+    // Fails with ERROR_INSUFFICIENT_BUFFER, having set the length
+    let _ = unsafe { GetTokenInformation(token, TokenUser, None, 0, &mut length) };
+    // u64s to align the TOKEN_USER
+    let mut buffer = vec![0u64; (length as usize).div_ceil(8).max(1)];
+    let result = unsafe {
+        GetTokenInformation(
+            token,
+            TokenUser,
+            Some(buffer.as_mut_ptr().cast()),
+            (buffer.len() * 8) as u32,
+            &mut length,
+        )
+    };
+    let _ = unsafe { CloseHandle(token) };
+    result?;
+    Ok(buffer)
+}
+
+// Helper: The quota of the calling user on the volume `root`, in a COM
+// apartment already initialized
+#[cfg(windows)]
+fn query(root: &WideCString) -> Result<QuotaInfo, Failure> {
+    unsafe {
+        let control: IDiskQuotaControl =
+            CoCreateInstance(&CLSID_DiskQuotaControl, None, CLSCTX_INPROC_SERVER)?;
+        control.Initialize(PCWSTR::from_raw(root.as_ptr()), false)?;
+        let mut state = 0;
+        control.GetQuotaState(&mut state)?;
+        if state & DISKQUOTA_STATE_MASK == DISKQUOTA_STATE_DISABLED {
+            return Err(Failure::Disabled);
+        }
+        let mut default_limit = 0;
+        control.GetDefaultQuotaLimit(&mut default_limit)?;
+        let token_user = token_user()?;
+        let sid = (*token_user.as_ptr().cast::<TOKEN_USER>()).User.Sid;
+        let user = control.FindUserSid(sid, DISKQUOTA_USERNAME_RESOLVE_NONE)?;
+        let (mut limit, mut used) = (0, 0);
+        user.GetQuotaLimit(&mut limit)?;
+        user.GetQuotaUsed(&mut used)?;
+        QuotaInfo::decode(state, limit, used, default_limit).ok_or(Failure::Disabled)
+    }
+}
+
+// %{enforced, limit_bytes, used_bytes, default_limit_bytes} of the calling
+// user on the NTFS volume holding `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn quota_info_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(windows)]
+    {
+        let Some(path) = path
+            .to_str()
+            .ok()
+            .and_then(|path| WideCString::from_str(path).ok())
+        else {
+            return make_error_tuple(env, atoms::path_conversion_failed());
+        };
+        let quota = thread::spawn(move || {
+            let root =
+                volume_root(&path).map_err(|code| Failure::WinApi(atoms::invalid_path(), code))?;
+            unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) }.ok()?;
+            let quota = query(&root);
+            unsafe { CoUninitialize() };
+            quota
+        })
+        .join()
+        .unwrap_or(Err(Failure::WinApi(atoms::winapi_failed(), 0)));
+        let quota = match quota {
+            Ok(quota) => quota,
+            Err(Failure::Disabled) => return make_error_tuple(env, atoms::quotas_disabled()),
+            Err(Failure::WinApi(reason, code)) => {
+                return make_winapi_error_tuple(env, reason, code)
+            }
+        };
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::enforced().to_term(env), quota.enforced)?
+            .map_put(atoms::limit_bytes().to_term(env), quota.limit)?
+            .map_put(atoms::used_bytes().to_term(env), quota.used)?
+            .map_put(
+                atoms::default_limit_bytes().to_term(env),
+                quota.default_limit,
+            )?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_quota_state_and_limits() {
+        assert_eq!(QuotaInfo::decode(0, 1 << 30, 4096, -1), None);
+        // DISKQUOTA_LOGFLAG bits above the state are ignored
+        assert_eq!(QuotaInfo::decode(0x100, 1 << 30, 4096, -1), None);
+        assert_eq!(
+            QuotaInfo::decode(2, 1 << 30, 4096, -1),
+            Some(QuotaInfo {
+                enforced: true,
+                limit: Some(1 << 30),
+                used: 4096,
+                default_limit: None,
+            })
+        );
+        assert_eq!(
+            QuotaInfo::decode(1, -2, 0, 5 << 30),
+            Some(QuotaInfo {
+                enforced: false,
+                limit: None,
+                used: 0,
+                default_limit: Some(5 << 30),
+            })
+        );
+    }
+}
@@ -0,0 +1,280 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// estimate_on_disk: the space files would take up on the filesystem holding
+// a path, rather than the sum of their sizes. Each file takes up a whole
+// number of allocation units (the clusters of FAT and NTFS, the fragments
+// of statvfs), so that a million small files copied to a FAT32 card with
+// 32 KiB clusters take gigabytes more than they add up to, and some
+// metadata per file on filesystems that allocate it from free space: the
+// directory entries of FAT, the MFT records of NTFS, the inodes of XFS.
+// ext2/3/4 have their inode tables allocated up front, so nothing there.
+//
+// With the sizes of the files, each is rounded up to the unit; with the
+// totals of a du summary, each file is taken to waste half a unit, as they
+// do on average. The allocation unit is the fragment size of statvfs, or
+// the cluster size from GetDiskFreeSpaceW on Windows.
+
+use crate::{atoms, encode_stat, get_path_from_term, make_error_tuple, stat_path};
+use crate::{make_error_tuple3, options, path_from_cstring};
+use rustler::{Encoder, Env, NifResult, Term};
+use std::collections::HashMap;
+use std::path::Path;
+#[cfg(windows)]
+use {
+    crate::{make_winapi_error_tuple, volume_root},
+    widestring::WideCString,
+    windows::core::PCWSTR,
+    windows::Win32::Storage::FileSystem::{GetDiskFreeSpaceW, GetVolumeInformationW},
+};
+
+// What is to be written: each size, or the totals of a du summary
+#[derive(Debug, PartialEq)]
+enum Planned {
+    Sizes(Vec<u64>),
+    Summary { apparent_bytes: u64, files: u64 },
+}
+
+// The metadata to count per file: a number of bytes, or those for some
+// filesystem types, the others taking the defaults
+enum Metadata {
+    Bytes(u64),
+    ByType(HashMap<String, u64>),
+}
+
+#[derive(Debug, PartialEq)]
+struct Estimate {
+    bytes: u64,
+    apparent_bytes: u64,
+    files: u64,
+    metadata_bytes: u64,
+}
+
+impl Estimate {
+    fn compute(planned: &Planned, unit: u64, per_file: u64) -> Estimate {
+        let unit = unit.max(1);
+        let (apparent_bytes, files, data) = match planned {
+            Planned::Sizes(sizes) => (
+                sizes
+                    .iter()
+                    .fold(0u64, |sum, size| sum.saturating_add(*size)),
+                sizes.len() as u64,
+                sizes.iter().fold(0u64, |sum, size| {
+                    sum.saturating_add(size.div_ceil(unit).saturating_mul(unit))
+                }),
+            ),
+            Planned::Summary {
+                apparent_bytes,
+                files,
+            } => (
+                *apparent_bytes,
+                *files,
+                apparent_bytes
+                    .div_ceil(unit)
+                    .saturating_mul(unit)
+                    .saturating_add(files.saturating_mul(unit / 2)),
+            ),
+        };
+        let metadata_bytes = files.saturating_mul(per_file);
+        Estimate {
+            bytes: data.saturating_add(metadata_bytes),
+            apparent_bytes,
+            files,
+            metadata_bytes,
+        }
+    }
+}
+
+// The metadata a file takes from free space on a filesystem of `fs_type`
+fn default_metadata(fs_type: &str) -> u64 {
+    match fs_type.to_ascii_lowercase().as_str() {
+        // An 8.3 directory entry and a long name one, of 32 bytes each
+        "vfat" | "msdos" | "fat" | "fat12" | "fat16" | "fat32" => 64,
+        // The file, stream extension and file name directory entries
+        "exfat" => 96,
+        // A record of the MFT
+        "ntfs" | "ntfs3" => 1024,
+        // An inode, of 512 bytes by default
+        "xfs" => 512,
+        _ => 0,
+    }
+}
+
+impl Metadata {
+    fn per_file(&self, fs_type: &str) -> u64 {
+        match self {
+            Metadata::Bytes(bytes) => *bytes,
+            Metadata::ByType(types) => types
+                .get(fs_type)
+                .copied()
+                .unwrap_or_else(|| default_metadata(fs_type)),
+        }
+    }
+}
+
+// Helper: The planned writes, from a list of sizes or a du summary
+fn decode_planned(term: Term) -> Option<Planned> {
+    if let Ok(sizes) = term.decode::<Vec<u64>>() {
+        return Some(Planned::Sizes(sizes));
+    }
+    let field = |key: rustler::Atom| term.map_get(key).ok()?.decode::<u64>().ok();
+    Some(Planned::Summary {
+        apparent_bytes: field(atoms::apparent_bytes())?,
+        files: field(atoms::files())?,
+    })
+}
+
+// Helper: The metadata_bytes option, an integer or a map of them by type
+fn decode_metadata(term: Term) -> Option<Metadata> {
+    if let Ok(bytes) = term.decode::<u64>() {
+        return Some(Metadata::Bytes(bytes));
+    }
+    term.decode::<HashMap<String, u64>>()
+        .ok()
+        .map(Metadata::ByType)
+}
+
+// Helper: The allocation unit and type of the filesystem holding `path`
+#[cfg(unix)]
+fn geometry<'a>(env: Env<'a>, path: &Path) -> Result<(u64, String), NifResult<Term<'a>>> {
+    let stats = nix::sys::statvfs::statvfs(path).map_err(|err| {
+        let err = std::io::Error::from_raw_os_error(err as i32);
+        crate::make_errno_error_tuple(env, atoms::statvfs_failed(), err)
+    })?;
+    Ok((
+        stats.fragment_size() as u64,
+        fs_type(path).unwrap_or_default(),
+    ))
+}
+
+// Helper: The type of the filesystem holding `path`
+#[cfg(unix)]
+fn fs_type(path: &Path) -> Option<String> {
+    Some(crate::mounts::holding(path).ok()??.fs_type)
+}
+
+// Helper: The cluster size and type of the volume holding `path`
+#[cfg(windows)]
+fn geometry<'a>(env: Env<'a>, path: &Path) -> Result<(u64, String), NifResult<Term<'a>>> {
+    let winapi_error = |code| make_winapi_error_tuple(env, atoms::winapi_failed(), code);
+    let wide = WideCString::from_os_str(path.as_os_str())
+        .map_err(|_| make_error_tuple(env, atoms::path_conversion_failed()))?;
+    let root = volume_root(&wide).map_err(winapi_error)?;
+    let (mut sectors_per_cluster, mut bytes_per_sector) = (0, 0);
+    unsafe {
+        GetDiskFreeSpaceW(
+            PCWSTR::from_raw(root.as_ptr()),
+            Some(&mut sectors_per_cluster),
+            Some(&mut bytes_per_sector),
+            None,
+            None,
+        )
+    }
+    .map_err(|e| winapi_error((e.code().0 & 0xFFFF) as u32))?;
+    let mut fs_name = [0u16; 64];
+    let fs_type = match unsafe {
+        GetVolumeInformationW(
+            PCWSTR::from_raw(root.as_ptr()),
+            None,
+            None,
+            None,
+            None,
+            Some(&mut fs_name),
+        )
+    } {
+        Ok(()) => {
+            let end = fs_name.iter().position(|unit| *unit == 0).unwrap_or(64);
+            String::from_utf16_lossy(&fs_name[..end])
+        }
+        Err(_) => String::new(),
+    };
+    Ok((
+        u64::from(sectors_per_cluster) * u64::from(bytes_per_sector),
+        fs_type,
+    ))
+}
+
+// %{estimated_bytes, apparent_bytes, files, allocation_unit_bytes,
+// metadata_bytes, fs_type, available, fits, shortfall} for writing
+// `planned` under `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn estimate_on_disk_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    planned: Term<'a>,
+    opts: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let Some(planned) = decode_planned(planned) else {
+        return Err(rustler::Error::BadArg);
+    };
+    let metadata = match options::get_with(opts, atoms::metadata_bytes(), decode_metadata) {
+        Ok(metadata) => metadata.unwrap_or_else(|| Metadata::ByType(HashMap::new())),
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let Ok(path_cstr) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let space = match stat_path(&path_cstr) {
+        Ok(space) => space,
+        Err(err) => return encode_stat(env, Err(err)),
+    };
+    let Some(path) = path_from_cstring(&path_cstr) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let (unit, fs_type) = match geometry(env, &path) {
+        Ok(geometry) => geometry,
+        Err(error) => return error,
+    };
+    let estimate = Estimate::compute(&planned, unit, metadata.per_file(&fs_type));
+    let map = rustler::types::map::map_new(env)
+        .map_put(atoms::estimated_bytes().to_term(env), estimate.bytes)?
+        .map_put(
+            atoms::apparent_bytes().to_term(env),
+            estimate.apparent_bytes,
+        )?
+        .map_put(atoms::files().to_term(env), estimate.files)?
+        .map_put(atoms::allocation_unit_bytes().to_term(env), unit)?
+        .map_put(
+            atoms::metadata_bytes().to_term(env),
+            estimate.metadata_bytes,
+        )?
+        .map_put(atoms::fs_type().to_term(env), fs_type)?
+        .map_put(atoms::available().to_term(env), space.available)?
+        .map_put(
+            atoms::fits().to_term(env),
+            estimate.bytes <= space.available,
+        )?
+        .map_put(
+            atoms::shortfall().to_term(env),
+            estimate.bytes.saturating_sub(space.available),
+        )?;
+    Ok((atoms::ok(), map).encode(env))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_each_file_up_to_the_allocation_unit() {
+        let sizes = Planned::Sizes(vec![0, 1, 32_768, 32_769]);
+        assert_eq!(
+            Estimate::compute(&sizes, 32_768, 64),
+            Estimate {
+                bytes: 131_072 + 4 * 64,
+                apparent_bytes: 65_538,
+                files: 4,
+                metadata_bytes: 256,
+            }
+        );
+        // A million 1 KB files on FAT32 with 32 KiB clusters
+        let summary = Planned::Summary {
+            apparent_bytes: 1_000_000_000,
+            files: 1_000_000,
+        };
+        let estimate = Estimate::compute(&summary, 32_768, default_metadata("vfat"));
+        assert_eq!(estimate.bytes, 1_000_013_824 + 16_384_000_000 + 64_000_000);
+        assert_eq!(default_metadata("NTFS"), 1024);
+        assert_eq!(default_metadata("ext4"), 0);
+    }
+}
@@ -0,0 +1,23 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Option maps passed down from the Elixir wrappers (`Map.new(opts)`).
+// Absent keys yield `Ok(None)`; a present key with an unusable value yields
+// `Err(key)` so the NIF can answer `{:error, :invalid_option, key}`.
+
+use rustler::{Atom, Decoder, Term};
+
+pub(crate) fn get<'a, T: Decoder<'a>>(opts: Term<'a>, key: Atom) -> Result<Option<T>, Atom> {
+    get_with(opts, key, |value| value.decode().ok())
+}
+
+pub(crate) fn get_with<'a, T>(
+    opts: Term<'a>,
+    key: Atom,
+    decode: impl FnOnce(Term<'a>) -> Option<T>,
+) -> Result<Option<T>, Atom> {
+    match opts.map_get(key) {
+        Ok(value) => decode(value).map(Some).ok_or(key),
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,205 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// filesystem_overhead: where the space of an ext2/3/4 filesystem goes before
+// its users get any, the usual reason its total and available space fall
+// short of the size of its disk. Its statfs counts free blocks the root
+// user may still take (the reserved blocks, 5% by default) and, on ext4,
+// a few reserved clusters nobody may take, which sysfs exposes in
+// /sys/fs/ext4/<dev>/reserved_clusters. Its total leaves out the blocks of
+// its metadata (the overhead), found as the size of its block device, from
+// /sys/dev/block/<major>:<minor>/size, less that total.
+//
+// The reserved percentage is of the blocks of the device, as tune2fs -m
+// sets it; both it and the overhead are nil if the device has no size in
+// sysfs. Other filesystems have no such figures, and get nil for all of
+// them. Linux only; elsewhere filesystem_overhead returns
+// {:error, :unsupported}.
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Env, NifResult, Term};
+#[cfg(target_os = "linux")]
+use {
+    crate::{make_errno_error_tuple, mounts, path_from_cstring},
+    nix::sys::statfs::statfs,
+    rustler::Encoder,
+    std::fs,
+    std::io,
+    std::path::Path,
+};
+
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq)]
+struct Overhead {
+    reserved_blocks: u64,
+    reserved_bytes: u64,
+    reserved_percent: Option<f64>,
+    overhead_bytes: Option<u64>,
+    non_root_total: u64,
+}
+
+// The statfs figures of a filesystem, in blocks of `block_size`
+#[cfg(any(target_os = "linux", test))]
+struct Counts {
+    block_size: u64,
+    blocks: u64,
+    free: u64,
+    available: u64,
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl Overhead {
+    // From the statfs `counts` of an ext filesystem, its `reserved_clusters`
+    // (in blocks) and the size in bytes of its device if known
+    fn compute(counts: &Counts, reserved_clusters: u64, device_bytes: Option<u64>) -> Overhead {
+        let withheld = counts.free.saturating_sub(counts.available);
+        let reserved_blocks = withheld.saturating_sub(reserved_clusters);
+        let total = counts.blocks.saturating_mul(counts.block_size);
+        let device_blocks = device_bytes
+            .map(|bytes| bytes / counts.block_size)
+            .filter(|blocks| *blocks > 0);
+        Overhead {
+            reserved_blocks,
+            reserved_bytes: reserved_blocks.saturating_mul(counts.block_size),
+            reserved_percent: device_blocks.map(|device_blocks| {
+                (reserved_blocks as u128 * 10_000 / device_blocks as u128) as f64 / 100.0
+            }),
+            overhead_bytes: device_bytes.map(|bytes| bytes.saturating_sub(total)),
+            non_root_total: counts
+                .blocks
+                .saturating_sub(withheld)
+                .saturating_mul(counts.block_size),
+        }
+    }
+}
+
+// %{fs_type, reserved_blocks, reserved_bytes, reserved_percent,
+// overhead_bytes, non_root_total} of the filesystem holding `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn filesystem_overhead_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "linux")]
+    {
+        let Some(path) = path_from_cstring(&path) else {
+            return make_error_tuple(env, atoms::invalid_path());
+        };
+        let mount = match mounts::holding(&path) {
+            Ok(Some(mount)) => mount,
+            Ok(None) => return make_error_tuple(env, atoms::mount_not_found()),
+            Err(err) => return make_errno_error_tuple(env, atoms::invalid_path(), err),
+        };
+        let overhead = if matches!(mount.fs_type.as_str(), "ext2" | "ext3" | "ext4") {
+            match statfs(&path) {
+                Ok(buf) => {
+                    let counts = Counts {
+                        block_size: buf.block_size() as u64,
+                        blocks: buf.blocks(),
+                        free: buf.blocks_free(),
+                        available: buf.blocks_available(),
+                    };
+                    let device = &mount.device;
+                    Some(Overhead::compute(
+                        &counts,
+                        reserved_clusters(device),
+                        device_bytes(device),
+                    ))
+                }
+                Err(err) => {
+                    let err = io::Error::from_raw_os_error(err as i32);
+                    return make_errno_error_tuple(env, atoms::statfs_failed(), err);
+                }
+            }
+        } else {
+            None
+        };
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::fs_type().to_term(env), mount.fs_type.as_str())?
+            .map_put(
+                atoms::reserved_blocks().to_term(env),
+                overhead.as_ref().map(|o| o.reserved_blocks),
+            )?
+            .map_put(
+                atoms::reserved_bytes().to_term(env),
+                overhead.as_ref().map(|o| o.reserved_bytes),
+            )?
+            .map_put(
+                atoms::reserved_percent().to_term(env),
+                overhead.as_ref().and_then(|o| o.reserved_percent),
+            )?
+            .map_put(
+                atoms::overhead_bytes().to_term(env),
+                overhead.as_ref().and_then(|o| o.overhead_bytes),
+            )?
+            .map_put(
+                atoms::non_root_total().to_term(env),
+                overhead.as_ref().map(|o| o.non_root_total),
+            )?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+// Helper: The reserved clusters of the ext4 filesystem on `device`
+// ("major:minor"), 0 if it has none in sysfs, as ext2 and ext3 don't
+#[cfg(target_os = "linux")]
+fn reserved_clusters(device: &str) -> u64 {
+    let Ok(link) = fs::read_link(format!("/sys/dev/block/{device}")) else {
+        return 0;
+    };
+    let Some(name) = link.file_name() else {
+        return 0;
+    };
+    let path = Path::new("/sys/fs/ext4")
+        .join(name)
+        .join("reserved_clusters");
+    read_number(&path).unwrap_or(0)
+}
+
+// Helper: The size in bytes of the block device `device` ("major:minor")
+#[cfg(target_os = "linux")]
+fn device_bytes(device: &str) -> Option<u64> {
+    let sectors = read_number(Path::new(&format!("/sys/dev/block/{device}/size")))?;
+    // sysfs counts 512-byte sectors whatever the device's own
+    Some(sectors.saturating_mul(512))
+}
+
+// Helper: A sysfs file holding a number
+#[cfg(target_os = "linux")]
+fn read_number(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_reserved_blocks_from_reserved_clusters() {
+        // A 100 GiB ext4 filesystem of 4 KiB blocks, 5% reserved
+        let device_blocks = 26_214_400;
+        let counts = Counts {
+            block_size: 4096,
+            blocks: device_blocks - 400_000,
+            free: 20_000_000,
+            available: 20_000_000 - 1_310_720 - 4096,
+        };
+        let overhead = Overhead::compute(&counts, 4096, Some(device_blocks * 4096));
+        assert_eq!(overhead.reserved_blocks, 1_310_720);
+        assert_eq!(overhead.reserved_bytes, 1_310_720 * 4096);
+        assert_eq!(overhead.reserved_percent, Some(5.0));
+        assert_eq!(overhead.overhead_bytes, Some(400_000 * 4096));
+        assert_eq!(
+            overhead.non_root_total,
+            (counts.blocks - 1_310_720 - 4096) * 4096
+        );
+        let unknown = Overhead::compute(&counts, 0, None);
+        assert_eq!(unknown.reserved_percent, None);
+        assert_eq!(unknown.overhead_bytes, None);
+    }
+}
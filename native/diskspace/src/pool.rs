@@ -0,0 +1,211 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Worker threads shared by stat_fs_async, the concurrent stats of
+// stat_fs_many and the ticks of watchers, so that a stat hanging on an
+// unresponsive network mount holds up neither the caller nor a dirty
+// scheduler, and so that hundreds of watchers don't take a thread each.
+// Threads are started on demand, up to `size` of them; further jobs queue up,
+// up to `queue_limit` of them, beyond which a job is refused as overloaded
+// rather than letting the queue grow without bound. configure sets both,
+// taking effect as workers start and finish their jobs; see config.rs.
+//
+// rustler's init! offers no unload callback to stop the threads from, let
+// alone to join them, so instead each thread ends on its own once it has had
+// nothing to do for IDLE: the pool is empty again shortly after the last job,
+// and starts over with the next. Jobs that hold resources of this library,
+// as watcher ticks do, keep it loaded until they are done.
+
+use crate::{atoms, make_errno_error_tuple, make_error_tuple, options};
+use rustler::{Atom, Env, NifResult, Term};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_SIZE: usize = 8;
+const DEFAULT_QUEUE_LIMIT: usize = 4096;
+// For either
+const MAX: usize = 1 << 20;
+const IDLE: Duration = Duration::from_secs(10);
+
+thread_local! {
+    static WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Pool {
+    state: Mutex<State>,
+    work: Condvar,
+}
+
+struct State {
+    size: usize,
+    queue_limit: usize,
+    jobs: VecDeque<Job>,
+    workers: usize,
+    // Workers waiting for a job
+    idle: usize,
+}
+
+static POOL: Pool = Pool {
+    state: Mutex::new(State {
+        size: DEFAULT_SIZE,
+        queue_limit: DEFAULT_QUEUE_LIMIT,
+        jobs: VecDeque::new(),
+        workers: 0,
+        idle: 0,
+    }),
+    work: Condvar::new(),
+};
+
+fn lock() -> MutexGuard<'static, State> {
+    POOL.state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Why a job wasn't taken
+#[derive(Debug)]
+pub(crate) enum Refused {
+    Overloaded,
+    NotStarted(io::Error),
+}
+
+// The size and queue limit of the pool in `opts`, %{pool_size,
+// pool_queue_limit}, each if given
+pub(crate) fn decode(opts: Term) -> Result<(Option<usize>, Option<usize>), Atom> {
+    let within = |value: Term| {
+        value
+            .decode::<usize>()
+            .ok()
+            .filter(|n| (1..=MAX).contains(n))
+    };
+    Ok((
+        options::get_with(opts, atoms::pool_size(), within)?,
+        options::get_with(opts, atoms::pool_queue_limit(), within)?,
+    ))
+}
+
+// Sets the size and queue limit of the pool, either left as it is if None
+pub(crate) fn set(size: Option<usize>, queue_limit: Option<usize>) {
+    let mut state = lock();
+    state.size = size.unwrap_or(state.size);
+    state.queue_limit = queue_limit.unwrap_or(state.queue_limit);
+}
+
+// The size and queue limit of the pool
+pub(crate) fn settings() -> (usize, usize) {
+    let state = lock();
+    (state.size, state.queue_limit)
+}
+
+// Helper: {:error, :overloaded} or the errno error of a refused job
+pub(crate) fn encode_refused<'a>(env: Env<'a>, refused: Refused) -> NifResult<Term<'a>> {
+    match refused {
+        Refused::Overloaded => make_error_tuple(env, atoms::overloaded()),
+        Refused::NotStarted(e) => make_errno_error_tuple(env, atoms::pool_failed(), e),
+    }
+}
+
+// Whether the calling thread is one of the pool's, whose jobs mustn't wait on
+// jobs queued behind them
+pub(crate) fn on_worker() -> bool {
+    WORKER.with(Cell::get)
+}
+
+// Runs `job` on a pool thread; refused if the queue is full, or if there is
+// no thread and none could be started
+pub(crate) fn submit(job: impl FnOnce() + Send + 'static) -> Result<(), Refused> {
+    let mut state = lock();
+    if state.jobs.len() >= state.queue_limit {
+        return Err(Refused::Overloaded);
+    }
+    state.jobs.push_back(Box::new(job));
+    if state.idle >= state.jobs.len() || state.workers >= state.size {
+        POOL.work.notify_one();
+        return Ok(());
+    }
+    // Started under the lock, so that the job is still the last one should
+    // that fail
+    let started = thread::Builder::new()
+        .name("disk_space_pool".to_string())
+        .spawn(work);
+    match started {
+        Ok(_) => {
+            state.workers += 1;
+            Ok(())
+        }
+        // The job waits for one of the others
+        Err(_) if state.workers > 0 => Ok(()),
+        Err(e) => {
+            state.jobs.pop_back();
+            Err(Refused::NotStarted(e))
+        }
+    }
+}
+
+fn work() {
+    WORKER.with(|worker| worker.set(true));
+    let mut state = lock();
+    loop {
+        // Down to a smaller size one job at a time
+        if state.workers > state.size {
+            state.workers -= 1;
+            return;
+        }
+        if let Some(job) = state.jobs.pop_front() {
+            drop(state);
+            // A panicking job mustn't take the thread and its count with it
+            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            state = lock();
+            continue;
+        }
+        state.idle += 1;
+        let (next, timeout) = POOL
+            .work
+            .wait_timeout(state, IDLE)
+            .unwrap_or_else(PoisonError::into_inner);
+        state = next;
+        state.idle -= 1;
+        if timeout.timed_out() && state.jobs.is_empty() {
+            state.workers -= 1;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+
+    #[test]
+    fn runs_every_job_on_a_bounded_number_of_threads() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let (done, finished) = mpsc::channel();
+        for i in 0..50 {
+            let (running, most, done) = (running.clone(), most.clone(), done.clone());
+            submit(move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(2));
+                running.fetch_sub(1, Ordering::SeqCst);
+                done.send(i).unwrap();
+            })
+            .unwrap();
+        }
+        let mut ran: Vec<i32> = (0..50).map(|_| finished.recv().unwrap()).collect();
+        ran.sort_unstable();
+        assert_eq!(ran, (0..50).collect::<Vec<_>>());
+        assert!(most.load(Ordering::SeqCst) <= DEFAULT_SIZE);
+        // A panic leaves the pool working
+        submit(|| panic!("job failed")).unwrap();
+        submit(move || done.send(50).unwrap()).unwrap();
+        assert_eq!(finished.recv().unwrap(), 50);
+    }
+}
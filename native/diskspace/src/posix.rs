@@ -0,0 +1,97 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// POSIX error names (as used by Erlang's :file module, e.g. :eacces) for OS
+// errors. Windows error codes are mapped onto the closest POSIX name.
+
+use rustler::{Atom, Env};
+use std::io;
+
+#[cfg(unix)]
+fn name(errno: i32) -> &'static str {
+    match errno {
+        libc::EACCES => "eacces",
+        libc::EPERM => "eperm",
+        libc::ENOENT => "enoent",
+        libc::ENOTDIR => "enotdir",
+        libc::EISDIR => "eisdir",
+        libc::ELOOP => "eloop",
+        libc::ENAMETOOLONG => "enametoolong",
+        libc::EIO => "eio",
+        libc::EBUSY => "ebusy",
+        libc::EINTR => "eintr",
+        libc::EAGAIN => "eagain",
+        libc::EINVAL => "einval",
+        libc::ENOMEM => "enomem",
+        libc::EMFILE => "emfile",
+        libc::ENFILE => "enfile",
+        libc::ENODEV => "enodev",
+        libc::ENXIO => "enxio",
+        libc::ENOSPC => "enospc",
+        libc::EROFS => "erofs",
+        libc::EEXIST => "eexist",
+        libc::EXDEV => "exdev",
+        libc::ESTALE => "estale",
+        libc::ETIMEDOUT => "etimedout",
+        libc::ENOTCONN => "enotconn",
+        libc::EOVERFLOW => "eoverflow",
+        libc::EFBIG => "efbig",
+        libc::EDQUOT => "edquot",
+        libc::EOPNOTSUPP => "eopnotsupp",
+        // A distinct errno only there
+        #[cfg(any(target_os = "macos", target_os = "netbsd"))]
+        libc::ENOTSUP => "eopnotsupp",
+        _ => "unknown",
+    }
+}
+
+#[cfg(windows)]
+fn name(code: i32) -> &'static str {
+    use windows::Win32::Foundation::*;
+    let Ok(code) = u32::try_from(code) else {
+        return "unknown";
+    };
+    match WIN32_ERROR(code) {
+        ERROR_ACCESS_DENIED | ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION => "eacces",
+        ERROR_PRIVILEGE_NOT_HELD => "eperm",
+        ERROR_FILE_NOT_FOUND | ERROR_PATH_NOT_FOUND | ERROR_INVALID_DRIVE | ERROR_BAD_NETPATH
+        | ERROR_BAD_NET_NAME => "enoent",
+        ERROR_DIRECTORY => "enotdir",
+        ERROR_CANT_RESOLVE_FILENAME => "eloop",
+        ERROR_FILENAME_EXCED_RANGE => "enametoolong",
+        ERROR_NOT_READY | ERROR_CRC | ERROR_READ_FAULT | ERROR_GEN_FAILURE => "eio",
+        ERROR_BUSY => "ebusy",
+        ERROR_INVALID_PARAMETER | ERROR_INVALID_NAME => "einval",
+        ERROR_NOT_ENOUGH_MEMORY | ERROR_OUTOFMEMORY => "enomem",
+        ERROR_TOO_MANY_OPEN_FILES => "emfile",
+        ERROR_DISK_FULL | ERROR_HANDLE_DISK_FULL => "enospc",
+        ERROR_DISK_QUOTA_EXCEEDED => "edquot",
+        ERROR_WRITE_PROTECT => "erofs",
+        ERROR_FILE_EXISTS | ERROR_ALREADY_EXISTS => "eexist",
+        ERROR_NOT_SAME_DEVICE => "exdev",
+        ERROR_SEM_TIMEOUT => "etimedout",
+        ERROR_NOT_SUPPORTED | ERROR_INVALID_FUNCTION => "eopnotsupp",
+        _ => "unknown",
+    }
+}
+
+pub(crate) fn atom(env: Env, err: &io::Error) -> Atom {
+    let name = err.raw_os_error().map_or("unknown", name);
+    // Creating an atom only fails for names over 255 characters
+    Atom::from_str(env, name).unwrap_or_else(|_| crate::atoms::error())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_common_errors() {
+        assert_eq!(name(libc::EACCES), "eacces");
+        assert_eq!(name(libc::ENOENT), "enoent");
+        assert_eq!(name(libc::ELOOP), "eloop");
+        assert_eq!(name(0), "unknown");
+        let err = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(err.raw_os_error().map_or("unknown", name), "unknown");
+    }
+}
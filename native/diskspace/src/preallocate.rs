@@ -0,0 +1,252 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// preallocate: create or extend a file to a size with its space actually
+// allocated, so that writing it later can't fail with ENOSPC halfway. The
+// space is reserved with fallocate(2) on Linux (not posix_fallocate, which
+// glibc emulates by writing, hiding that the filesystem can't), with
+// posix_fallocate on FreeBSD, F_PREALLOCATE on macOS, contiguous if it can
+// be, and SetFileInformationByHandle(FileAllocationInfo) on Windows, which
+// then get the file extended to the size. A file already as large keeps its
+// size and contents; preallocating never shrinks.
+//
+// Where the filesystem can't preallocate, the error is :eopnotsupp, unless
+// write_zeros asks to write zeros from the end of the file instead, which
+// allocates on all but compressing or deduplicating filesystems. A failure
+// removes the file if it was created, or truncates it back to its former
+// size, unless keep_partial asks to keep what was allocated.
+
+use crate::{atoms, get_path_from_term, make_errno_error_tuple, make_error_tuple, posix};
+use crate::{make_error_tuple3, options, path_from_cstring};
+use rustler::{Encoder, Env, NifResult, Term};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+#[cfg(windows)]
+use {
+    std::os::windows::io::AsRawHandle,
+    windows::Win32::Foundation::{
+        ERROR_INVALID_FUNCTION, ERROR_INVALID_PARAMETER, ERROR_NOT_SUPPORTED, HANDLE,
+    },
+    windows::Win32::Storage::FileSystem::{
+        FileAllocationInfo, SetFileInformationByHandle, FILE_ALLOCATION_INFO,
+    },
+};
+
+#[derive(Default)]
+struct Options {
+    write_zeros: bool,
+    keep_partial: bool,
+}
+
+// Grows `path` to at least `bytes` with its space allocated, undoing that
+// on failure unless `keep_partial`
+fn preallocate(path: &Path, bytes: u64, options: &Options) -> io::Result<()> {
+    let (file, former_len) = match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => (file, None),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            let file = OpenOptions::new().write(true).open(path)?;
+            let len = file.metadata()?.len();
+            (file, Some(len))
+        }
+        Err(err) => return Err(err),
+    };
+    let result = allocate(&file, bytes).or_else(|err| {
+        if options.write_zeros && unsupported(&err) {
+            write_zeros(&file, bytes)
+        } else {
+            Err(err)
+        }
+    });
+    if result.is_err() && !options.keep_partial {
+        match former_len {
+            Some(len) => {
+                let _ = file.set_len(len);
+            }
+            None => {
+                drop(file);
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+    result
+}
+
+// Helper: Allocate the first `bytes` of `file`, extending it
+#[cfg(target_os = "linux")]
+fn allocate(file: &File, bytes: u64) -> io::Result<()> {
+    if bytes == 0 {
+        return Ok(());
+    }
+    let len = i64::try_from(bytes).map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))?;
+    if unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Helper: Allocate the first `bytes` of `file`, extending it
+#[cfg(target_os = "freebsd")]
+fn allocate(file: &File, bytes: u64) -> io::Result<()> {
+    if bytes == 0 {
+        return Ok(());
+    }
+    let len = i64::try_from(bytes).map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))?;
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+// Helper: Allocate the first `bytes` of `file`, extending it
+#[cfg(target_os = "macos")]
+fn allocate(file: &File, bytes: u64) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = file.metadata()?;
+    let allocated = metadata.blocks().saturating_mul(512);
+    if bytes > allocated {
+        let length = i64::try_from(bytes - allocated)
+            .map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))?;
+        let mut store = libc::fstore_t {
+            fst_flags: libc::F_ALLOCATECONTIG | libc::F_ALLOCATEALL,
+            fst_posmode: libc::F_PEOFPOSMODE,
+            fst_offset: 0,
+            fst_length: length,
+            fst_bytesalloc: 0,
+        };
+        let fd = file.as_raw_fd();
+        if unsafe { libc::fcntl(fd, libc::F_PREALLOCATE, &mut store) } == -1 {
+            // Not in one piece then
+            store.fst_flags = libc::F_ALLOCATEALL;
+            if unsafe { libc::fcntl(fd, libc::F_PREALLOCATE, &mut store) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    if metadata.len() < bytes {
+        file.set_len(bytes)?;
+    }
+    Ok(())
+}
+
+// Helper: Allocate the first `bytes` of `file`, extending it
+#[cfg(windows)]
+fn allocate(file: &File, bytes: u64) -> io::Result<()> {
+    let info = FILE_ALLOCATION_INFO {
+        AllocationSize: i64::try_from(bytes)
+            .map_err(|_| io::Error::from_raw_os_error(ERROR_INVALID_PARAMETER.0 as i32))?,
+    };
+    unsafe {
+        SetFileInformationByHandle(
+            HANDLE(file.as_raw_handle()),
+            FileAllocationInfo,
+            (&info as *const FILE_ALLOCATION_INFO).cast(),
+            std::mem::size_of::<FILE_ALLOCATION_INFO>() as u32,
+        )
+    }
+    .map_err(|e| io::Error::from_raw_os_error(e.code().0 & 0xFFFF))?;
+    if file.metadata()?.len() < bytes {
+        file.set_len(bytes)?;
+    }
+    Ok(())
+}
+
+// Helper: No preallocation elsewhere
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "macos",
+    windows
+)))]
+fn allocate(_file: &File, _bytes: u64) -> io::Result<()> {
+    Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+}
+
+// Helper: Whether preallocating failed as the filesystem can't
+#[cfg(unix)]
+fn unsupported(err: &io::Error) -> bool {
+    err.raw_os_error()
+        .is_some_and(|errno| errno == libc::EOPNOTSUPP || errno == libc::ENOTSUP)
+}
+
+// Helper: Whether preallocating failed as the filesystem can't
+#[cfg(windows)]
+fn unsupported(err: &io::Error) -> bool {
+    err.raw_os_error().is_some_and(|code| {
+        code == ERROR_NOT_SUPPORTED.0 as i32 || code == ERROR_INVALID_FUNCTION.0 as i32
+    })
+}
+
+// Helper: Extend `file` to `bytes` by writing zeros, flushed so that the
+// filesystem has allocated them
+fn write_zeros(mut file: &File, bytes: u64) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    if len >= bytes {
+        return Ok(());
+    }
+    file.seek(SeekFrom::Start(len))?;
+    let zeros = vec![0u8; 1 << 20];
+    let mut left = bytes - len;
+    while left > 0 {
+        let chunk = left.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        left -= chunk as u64;
+    }
+    file.sync_data()
+}
+
+// Helper: The options of preallocate, or the key of the one that isn't
+fn decode_options(opts: Term) -> Result<Options, rustler::Atom> {
+    Ok(Options {
+        write_zeros: options::get(opts, atoms::write_zeros())?.unwrap_or(false),
+        keep_partial: options::get(opts, atoms::keep_partial())?.unwrap_or(false),
+    })
+}
+
+// :ok once `path` has `bytes` allocated, or {:error, posix, %{errno, errstr}}
+#[rustler::nif(schedule = "DirtyIo")]
+fn preallocate_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    bytes: u64,
+    opts: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let options = match decode_options(opts) {
+        Ok(options) => options,
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    match preallocate(&path, bytes, &options) {
+        Ok(()) => Ok(atoms::ok().encode(env)),
+        Err(err) => make_errno_error_tuple(env, posix::atom(env, &err), err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_and_removes_what_it_created_on_failure() {
+        let path = std::env::temp_dir().join(format!("disk_space_prealloc_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        preallocate(&path, 1 << 20, &Options::default()).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 1 << 20);
+        // Never shrinks
+        preallocate(&path, 4096, &Options::default()).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 1 << 20);
+        // Too large for any filesystem: back to the size it had
+        assert!(preallocate(&path, 1 << 62, &Options::default()).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 1 << 20);
+        fs::remove_file(&path).unwrap();
+        assert!(preallocate(&path, 1 << 62, &Options::default()).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,230 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// probe_writable: whether bytes can actually be written under a directory,
+// by writing them. On thin-provisioned disks and some NFS servers, statfs
+// reports space that the pool behind it no longer has, and writes fail with
+// ENOSPC or EIO all the same. The probe writes a temporary file in the
+// directory in chunks, bypassing the page cache where it can so that the
+// storage itself has to take the data, syncs it, and removes it again,
+// timing the writes and the sync for the throughput.
+//
+// The cache is bypassed with O_DIRECT on Linux and FreeBSD, F_NOCACHE on
+// macOS and FILE_FLAG_WRITE_THROUGH on Windows. O_DIRECT wants aligned
+// buffers and sizes, so the bytes are written in chunks of a multiple of
+// ALIGN from an aligned buffer, the last chunk rounded up; a filesystem
+// that refuses O_DIRECT (tmpfs, some FUSE ones) is written through the
+// cache instead, and the result says so.
+//
+// The file is removed by a guard when dropped, so also when failing and
+// when a panic unwinds out of the NIF.
+
+use crate::path_from_cstring;
+use crate::{atoms, get_path_from_term, make_error_tuple, make_error_tuple3, options, posix};
+use rustler::{Encoder, Env, NifResult, Term};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+// The alignment O_DIRECT asks of buffers, offsets and sizes
+const ALIGN: usize = 4096;
+const CHUNK: usize = 1 << 20;
+
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+// The temporary file, removed once dropped
+struct TempFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug)]
+struct Probe {
+    bytes_written: u64,
+    seconds: f64,
+    direct: bool,
+}
+
+// Helper: The bytes to write for `bytes`, in whole units of ALIGN
+fn aligned(bytes: u64) -> u64 {
+    bytes.div_ceil(ALIGN as u64) * ALIGN as u64
+}
+
+// Helper: Create a file of a name of its own in `dir`, bypassing the cache
+// if `direct`; the file and whether it does
+fn create(dir: &Path, direct: bool) -> io::Result<(TempFile, bool)> {
+    let name = format!(
+        ".disk_space_probe_{}_{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = dir.join(name);
+    // Nothing to bypass the cache with elsewhere
+    let direct = direct
+        && cfg!(any(
+            target_os = "linux",
+            target_os = "freebsd",
+            target_os = "macos",
+            windows
+        ));
+    #[cfg_attr(
+        not(any(target_os = "linux", target_os = "freebsd", windows)),
+        allow(unused_variables)
+    )]
+    let open = |direct: bool| {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+        if direct {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_DIRECT);
+        }
+        #[cfg(windows)]
+        if direct {
+            use std::os::windows::fs::OpenOptionsExt;
+            use windows::Win32::Storage::FileSystem::FILE_FLAG_WRITE_THROUGH;
+            options.custom_flags(FILE_FLAG_WRITE_THROUGH.0);
+        }
+        options.open(&path)
+    };
+    let (file, direct) = match open(direct) {
+        Ok(file) => (file, direct),
+        Err(err) if direct && err.raw_os_error() == Some(einval()) => (open(false)?, false),
+        Err(err) => return Err(err),
+    };
+    #[cfg(target_os = "macos")]
+    let direct = direct && {
+        use std::os::unix::io::AsRawFd;
+        let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) };
+        result != -1
+    };
+    Ok((TempFile { path, file }, direct))
+}
+
+// Helper: EINVAL, as O_DIRECT is refused with
+fn einval() -> i32 {
+    #[cfg(unix)]
+    {
+        libc::EINVAL
+    }
+    #[cfg(windows)]
+    {
+        windows::Win32::Foundation::ERROR_INVALID_PARAMETER.0 as i32
+    }
+}
+
+// Writes `bytes` to a temporary file in `dir` and syncs it; the bytes
+// written before failing along with the error
+fn probe(dir: &Path, bytes: u64, direct: bool) -> Result<Probe, (u64, io::Error)> {
+    let (mut temp, direct) = create(dir, direct).map_err(|err| (0, err))?;
+    // Aligned within a buffer one ALIGN larger
+    let buffer = vec![0u8; CHUNK + ALIGN];
+    let offset = buffer.as_ptr().align_offset(ALIGN);
+    let chunk = &buffer[offset..offset + CHUNK];
+    let total = if direct { aligned(bytes) } else { bytes };
+    let started = Instant::now();
+    let mut written = 0u64;
+    while written < total {
+        let len = (total - written).min(CHUNK as u64) as usize;
+        temp.file
+            .write_all(&chunk[..len])
+            .map_err(|err| (written, err))?;
+        written += len as u64;
+    }
+    temp.file.sync_all().map_err(|err| (written, err))?;
+    Ok(Probe {
+        bytes_written: written,
+        seconds: started.elapsed().as_secs_f64(),
+        direct,
+    })
+}
+
+// {:ok, %{bytes_written, duration_ms, bytes_per_second, direct}} once
+// `bytes` were written under `path` and synced, or
+// {:error, posix, %{errno, errstr, bytes_written}}
+#[rustler::nif(schedule = "DirtyIo")]
+fn probe_writable_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    bytes: u64,
+    opts: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let direct = match options::get(opts, atoms::direct()) {
+        Ok(direct) => direct.unwrap_or(true),
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    if !path.is_dir() {
+        return make_error_tuple(env, atoms::not_directory());
+    }
+    match probe(&path, bytes, direct) {
+        Ok(probe) => {
+            let rate = if probe.seconds > 0.0 {
+                (probe.bytes_written as f64 / probe.seconds) as u64
+            } else {
+                0
+            };
+            let map = rustler::types::map::map_new(env)
+                .map_put(atoms::bytes_written().to_term(env), probe.bytes_written)?
+                .map_put(
+                    atoms::duration_ms().to_term(env),
+                    (probe.seconds * 1000.0).round() as u64,
+                )?
+                .map_put(atoms::bytes_per_second().to_term(env), rate)?
+                .map_put(atoms::direct().to_term(env), probe.direct)?;
+            Ok((atoms::ok(), map).encode(env))
+        }
+        Err((written, err)) => {
+            let info = rustler::types::map::map_new(env)
+                .map_put(atoms::errno().to_term(env), err.raw_os_error().unwrap_or(0))?
+                .map_put(atoms::errstr().to_term(env), err.to_string())?
+                .map_put(atoms::bytes_written().to_term(env), written)?;
+            make_error_tuple3(env, posix::atom(env, &err), info)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_and_removes_a_file() {
+        let dir = std::env::temp_dir();
+        let written = probe(&dir, 3 * CHUNK as u64 + 1, true).unwrap();
+        if written.direct {
+            assert_eq!(written.bytes_written, 3 * CHUNK as u64 + ALIGN as u64);
+        } else {
+            assert_eq!(written.bytes_written, 3 * CHUNK as u64 + 1);
+        }
+        let left = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(Result::ok)
+            .any(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with(&format!(".disk_space_probe_{}_", std::process::id()))
+            });
+        assert!(!left);
+        assert_eq!(aligned(0), 0);
+        assert_eq!(aligned(4097), 8192);
+        let missing = dir.join("disk_space_missing_dir");
+        assert_eq!(
+            probe(&missing, 1, false).unwrap_err().1.kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
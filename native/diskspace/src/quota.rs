@@ -0,0 +1,390 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// quota: the disk quota of a user, group or project on the filesystem
+// holding a path, from quotactl(2) on the block device of its mount. Most filesystems answer
+// Q_GETQUOTA with a struct if_dqblk; XFS keeps its own interface,
+// Q_XGETQUOTA and a struct fs_disk_quota, counted in 512-byte basic blocks.
+// Both are decoded into the same figures: space in bytes, limits of 0 (none)
+// as nil, and grace times as the Unix time the grace period ends, nil while
+// under the soft limit.
+//
+// A filesystem mounted without quota accounting, or a kernel built without
+// it, gives {:error, :quotas_not_enabled}. XFS has no record at all of an id
+// that never owned anything, which is reported as nothing used and no
+// limits. The project of a directory is its fsx_projid, as set by
+// xfs_quota or chattr -p. Quotas are Linux only; elsewhere quota returns
+// {:error, :unsupported}.
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Atom, Env, Error, NifResult, Term};
+#[cfg(target_os = "linux")]
+use {
+    crate::{make_errno_error_tuple, mounts, mounts::Mount, path_from_cstring},
+    rustler::Encoder,
+    std::ffi::CString,
+    std::fs::File,
+    std::io,
+    std::os::unix::io::AsRawFd,
+    std::path::Path,
+};
+
+// PRJQUOTA of <linux/quota.h>, which libc lacks
+#[cfg(target_os = "linux")]
+pub(crate) const PRJQUOTA: i32 = 2;
+
+// The units of the limits of an if_dqblk, and of the counts of an
+// fs_disk_quota
+#[cfg(any(target_os = "linux", test))]
+const QIF_DQBLKSIZE: u64 = 1024;
+#[cfg(any(target_os = "linux", test))]
+const BBSIZE: u64 = 512;
+
+// fs_disk_quota's d_flags for timers in 40 bits rather than 32
+#[cfg(any(target_os = "linux", test))]
+const FS_DQ_BIGTIME: i8 = -0x80;
+
+// struct if_dqblk of <linux/quota.h>
+#[cfg(any(target_os = "linux", test))]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct Dqblk {
+    bhardlimit: u64,
+    bsoftlimit: u64,
+    curspace: u64,
+    ihardlimit: u64,
+    isoftlimit: u64,
+    curinodes: u64,
+    btime: u64,
+    itime: u64,
+    valid: u32,
+}
+
+// struct fs_disk_quota of <linux/dqblk_xfs.h>
+#[cfg(any(target_os = "linux", test))]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct FsDiskQuota {
+    version: i8,
+    flags: i8,
+    fieldmask: u16,
+    id: u32,
+    blk_hardlimit: u64,
+    blk_softlimit: u64,
+    ino_hardlimit: u64,
+    ino_softlimit: u64,
+    bcount: u64,
+    icount: u64,
+    itimer: i32,
+    btimer: i32,
+    iwarns: u16,
+    bwarns: u16,
+    itimer_hi: i8,
+    btimer_hi: i8,
+    rtbtimer_hi: i8,
+    padding2: i8,
+    rtb_hardlimit: u64,
+    rtb_softlimit: u64,
+    rtbcount: u64,
+    rtbtimer: i32,
+    rtbwarns: u16,
+    padding3: i16,
+    padding4: [u8; 8],
+}
+
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq)]
+pub(crate) struct Quota {
+    bytes_used: u64,
+    bytes_soft_limit: Option<u64>,
+    bytes_hard_limit: Option<u64>,
+    bytes_grace_expires: Option<i64>,
+    inodes_used: u64,
+    inodes_soft_limit: Option<u64>,
+    inodes_hard_limit: Option<u64>,
+    inodes_grace_expires: Option<i64>,
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl Quota {
+    fn from_dqblk(dqblk: &Dqblk) -> Quota {
+        Quota {
+            bytes_used: dqblk.curspace,
+            bytes_soft_limit: limit(dqblk.bsoftlimit, QIF_DQBLKSIZE),
+            bytes_hard_limit: limit(dqblk.bhardlimit, QIF_DQBLKSIZE),
+            bytes_grace_expires: grace(dqblk.btime as i64),
+            inodes_used: dqblk.curinodes,
+            inodes_soft_limit: limit(dqblk.isoftlimit, 1),
+            inodes_hard_limit: limit(dqblk.ihardlimit, 1),
+            inodes_grace_expires: grace(dqblk.itime as i64),
+        }
+    }
+
+    fn from_xfs(quota: &FsDiskQuota) -> Quota {
+        let bigtime = quota.flags & FS_DQ_BIGTIME != 0;
+        Quota {
+            bytes_used: quota.bcount.saturating_mul(BBSIZE),
+            bytes_soft_limit: limit(quota.blk_softlimit, BBSIZE),
+            bytes_hard_limit: limit(quota.blk_hardlimit, BBSIZE),
+            bytes_grace_expires: grace(timer(quota.btimer, quota.btimer_hi, bigtime)),
+            inodes_used: quota.icount,
+            inodes_soft_limit: limit(quota.ino_softlimit, 1),
+            inodes_hard_limit: limit(quota.ino_hardlimit, 1),
+            inodes_grace_expires: grace(timer(quota.itimer, quota.itimer_hi, bigtime)),
+        }
+    }
+
+    // Helper: %{bytes_used, bytes_soft_limit, ..., inodes_grace_expires}
+    #[cfg(target_os = "linux")]
+    pub(crate) fn encode<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        rustler::types::map::map_new(env)
+            .map_put(atoms::bytes_used().to_term(env), self.bytes_used)?
+            .map_put(
+                atoms::bytes_soft_limit().to_term(env),
+                self.bytes_soft_limit,
+            )?
+            .map_put(
+                atoms::bytes_hard_limit().to_term(env),
+                self.bytes_hard_limit,
+            )?
+            .map_put(
+                atoms::bytes_grace_expires().to_term(env),
+                self.bytes_grace_expires,
+            )?
+            .map_put(atoms::inodes_used().to_term(env), self.inodes_used)?
+            .map_put(
+                atoms::inodes_soft_limit().to_term(env),
+                self.inodes_soft_limit,
+            )?
+            .map_put(
+                atoms::inodes_hard_limit().to_term(env),
+                self.inodes_hard_limit,
+            )?
+            .map_put(
+                atoms::inodes_grace_expires().to_term(env),
+                self.inodes_grace_expires,
+            )
+    }
+}
+
+// Helper: A limit of `value` units of `unit` bytes, None for none
+#[cfg(any(target_os = "linux", test))]
+fn limit(value: u64, unit: u64) -> Option<u64> {
+    (value != 0).then(|| value.saturating_mul(unit))
+}
+
+// Helper: The end of a grace period, None for none running
+#[cfg(any(target_os = "linux", test))]
+fn grace(time: i64) -> Option<i64> {
+    (time != 0).then_some(time)
+}
+
+// Helper: An XFS timer from its low 32 bits and, with bigtime, its high ones
+#[cfg(any(target_os = "linux", test))]
+fn timer(low: i32, high: i8, bigtime: bool) -> i64 {
+    if bigtime {
+        (i64::from(high as u8) << 32) | i64::from(low as u32)
+    } else {
+        i64::from(low)
+    }
+}
+
+// The quota of the user (`kind` :user), group (:group) or project
+// (:project) `id` on the filesystem holding `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn quota_nif<'a>(env: Env<'a>, path_term: Term<'a>, kind: Atom, id: u32) -> NifResult<Term<'a>> {
+    if kind != atoms::user() && kind != atoms::group() && kind != atoms::project() {
+        return Err(Error::BadArg);
+    }
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "linux")]
+    {
+        let Some(path) = path_from_cstring(&path) else {
+            return make_error_tuple(env, atoms::invalid_path());
+        };
+        let mount = match mounts::holding(&path) {
+            Ok(Some(mount)) => mount,
+            Ok(None) => return make_error_tuple(env, atoms::mount_not_found()),
+            Err(err) => return make_errno_error_tuple(env, atoms::invalid_path(), err),
+        };
+        let type_ = if kind == atoms::user() {
+            libc::USRQUOTA
+        } else if kind == atoms::group() {
+            libc::GRPQUOTA
+        } else {
+            PRJQUOTA
+        };
+        match read(&mount, type_, id) {
+            Ok(quota) => Ok((atoms::ok(), quota.encode(env)?).encode(env)),
+            Err(err) if not_enabled(&err) => make_error_tuple(env, atoms::quotas_not_enabled()),
+            Err(err) => make_errno_error_tuple(env, atoms::quotactl_failed(), err),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (path, id);
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+// The quota of type `type_` of `id` on the filesystem of `mount`
+#[cfg(target_os = "linux")]
+pub(crate) fn read(mount: &Mount, type_: i32, id: u32) -> io::Result<Quota> {
+    let device = CString::new(mount.source.as_str())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    if mount.fs_type == "xfs" {
+        get_xfs(&device, type_, id)
+    } else {
+        get(&device, type_, id)
+    }
+}
+
+// The project of the file or directory `path`, 0 for none
+#[cfg(target_os = "linux")]
+pub(crate) fn project_id(path: &Path) -> io::Result<u32> {
+    // struct fsxattr of <linux/fs.h>
+    #[repr(C)]
+    #[derive(Default)]
+    struct FsXattr {
+        xflags: u32,
+        extsize: u32,
+        nextents: u32,
+        projid: u32,
+        cowextsize: u32,
+        pad: [u8; 8],
+    }
+    // _IOR('X', 31, struct fsxattr)
+    const FS_IOC_FSGETXATTR: u64 = 0x801c_581f;
+    let file = File::open(path)?;
+    let mut fsxattr = FsXattr::default();
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FSGETXATTR as _, &mut fsxattr) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fsxattr.projid)
+}
+
+// Helper: QCMD of <linux/quota.h>
+#[cfg(target_os = "linux")]
+fn qcmd(cmd: i32, type_: i32) -> i32 {
+    (cmd << 8) | (type_ & 0xff)
+}
+
+// Helper: Q_GETQUOTA on the block device `device`
+#[cfg(target_os = "linux")]
+fn get(device: &CString, type_: i32, id: u32) -> io::Result<Quota> {
+    let mut dqblk = Dqblk::default();
+    let result = unsafe {
+        libc::quotactl(
+            qcmd(libc::Q_GETQUOTA, type_),
+            device.as_ptr(),
+            id as i32,
+            (&mut dqblk as *mut Dqblk).cast(),
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Quota::from_dqblk(&dqblk))
+}
+
+// Helper: Q_XGETQUOTA on the block device `device` of an XFS filesystem
+#[cfg(target_os = "linux")]
+fn get_xfs(device: &CString, type_: i32, id: u32) -> io::Result<Quota> {
+    // XQM_CMD(3) of <linux/dqblk_xfs.h>
+    const Q_XGETQUOTA: i32 = ((b'X' as i32) << 8) + 3;
+    let mut quota = FsDiskQuota::default();
+    let result = unsafe {
+        libc::quotactl(
+            qcmd(Q_XGETQUOTA, type_),
+            device.as_ptr(),
+            id as i32,
+            (&mut quota as *mut FsDiskQuota).cast(),
+        )
+    };
+    if result != 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ENOENT) {
+            return Ok(Quota::from_xfs(&FsDiskQuota::default()));
+        }
+        return Err(err);
+    }
+    Ok(Quota::from_xfs(&quota))
+}
+
+// Whether quotactl failed for want of quota accounting
+#[cfg(target_os = "linux")]
+pub(crate) fn not_enabled(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::ESRCH | libc::ENOSYS | libc::EOPNOTSUPP)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_dqblk() {
+        let dqblk = Dqblk {
+            bhardlimit: 2_000,
+            bsoftlimit: 1_000,
+            curspace: 1_500 * 1024,
+            ihardlimit: 0,
+            isoftlimit: 100,
+            curinodes: 42,
+            btime: 1_760_000_000,
+            itime: 0,
+            valid: 0x3f,
+        };
+        assert_eq!(std::mem::size_of::<Dqblk>(), 72);
+        assert_eq!(
+            Quota::from_dqblk(&dqblk),
+            Quota {
+                bytes_used: 1_536_000,
+                bytes_soft_limit: Some(1_024_000),
+                bytes_hard_limit: Some(2_048_000),
+                bytes_grace_expires: Some(1_760_000_000),
+                inodes_used: 42,
+                inodes_soft_limit: Some(100),
+                inodes_hard_limit: None,
+                inodes_grace_expires: None,
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_fs_disk_quota() {
+        let quota = FsDiskQuota {
+            version: 1,
+            flags: 1 | FS_DQ_BIGTIME,
+            blk_hardlimit: 4_096,
+            bcount: 10,
+            ino_softlimit: 5,
+            icount: 7,
+            btimer: 0,
+            btimer_hi: 0,
+            itimer: 0x10,
+            itimer_hi: 1,
+            ..FsDiskQuota::default()
+        };
+        assert_eq!(std::mem::size_of::<FsDiskQuota>(), 112);
+        assert_eq!(
+            Quota::from_xfs(&quota),
+            Quota {
+                bytes_used: 5_120,
+                bytes_soft_limit: None,
+                bytes_hard_limit: Some(2_097_152),
+                bytes_grace_expires: None,
+                inodes_used: 7,
+                inodes_soft_limit: Some(5),
+                inodes_hard_limit: None,
+                inodes_grace_expires: Some(0x1_0000_0010),
+            }
+        );
+        assert_eq!(timer(-1, 0, false), -1);
+    }
+}
@@ -0,0 +1,214 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// reserve: advisory reservations of space, so that two uploads that each
+// check for enough space don't then fill the disk together. A reservation
+// holds bytes of the filesystem of a path, keyed by its device as the
+// watchers tell filesystems apart; reserve and ensure_free take those
+// outstanding on the same filesystem off what stat reports available, and
+// reserve checks and records under the same lock, so that of two racing
+// for the last of the space only one gets it. The stat comes before the
+// lock is taken, so that a stat blocking on a hung mount holds up no
+// release, nor the garbage collection of a reservation.
+//
+// Reservations are held by a resource, and released by release or once the
+// resource is garbage collected. They only bind callers in this VM, and
+// are kept in the statics of this library: a new version of it, loaded
+// once the old code is purged, starts out with none, and release treats a
+// reservation of the old one as released already.
+
+use crate::du::meta;
+use crate::{atoms, encode_stat, get_path_from_term, make_error_tuple, make_error_tuple3};
+use crate::{path_from_cstring, path_to_term, stat_path};
+use rustler::{Atom, Encoder, Env, NifResult, ResourceArc, Term, TermType};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(|| Mutex::new(Registry::default()));
+
+fn lock() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Entry {
+    device: u64,
+    path: PathBuf,
+    bytes: u64,
+    // Unix time in milliseconds
+    reserved_at: u64,
+}
+
+// The outstanding reservations, by id
+#[derive(Default)]
+struct Registry {
+    next: u64,
+    entries: BTreeMap<u64, Entry>,
+}
+
+impl Registry {
+    // The bytes reserved on `device`
+    fn reserved(&self, device: u64) -> u64 {
+        self.entries
+            .values()
+            .filter(|entry| entry.device == device)
+            .fold(0u64, |sum, entry| sum.saturating_add(entry.bytes))
+    }
+
+    // Records the reservation of `bytes` if what is `available` on its
+    // device leaves room for it besides the others; its id, or what is
+    // available net of them
+    fn reserve(&mut self, entry: Entry, available: u64) -> Result<u64, u64> {
+        let available = available.saturating_sub(self.reserved(entry.device));
+        if entry.bytes > available {
+            return Err(available);
+        }
+        self.next += 1;
+        self.entries.insert(self.next, entry);
+        Ok(self.next)
+    }
+
+    fn release(&mut self, id: u64) {
+        self.entries.remove(&id);
+    }
+}
+
+// The handle reserve returns
+pub(crate) struct Reservation {
+    id: u64,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for Reservation {}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        lock().release(self.id);
+    }
+}
+
+// Helper: The device of the filesystem `path` is on
+fn device(path: &Path) -> Option<u64> {
+    let metadata = fs::metadata(path).ok()?;
+    meta::file_id(&meta::native(path), &metadata, true).map(|id| id.dev)
+}
+
+// Helper: The bytes reserved on the filesystem of `path`
+fn reserved(path: &Path) -> u64 {
+    device(path).map_or(0, |device| lock().reserved(device))
+}
+
+// {:ok, reservation} holding `bytes` on the filesystem of `path`, or
+// {:error, :insufficient_space, %{required, available, shortfall}}
+#[rustler::nif(schedule = "DirtyIo")]
+fn reserve_nif<'a>(env: Env<'a>, path_term: Term<'a>, bytes: u64) -> NifResult<Term<'a>> {
+    let Ok(path_cstr) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let Some(path) = path_from_cstring(&path_cstr) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let space = match stat_path(&path_cstr) {
+        Ok(space) => space,
+        Err(err) => return encode_stat(env, Err(err)),
+    };
+    let Some(device) = device(&path) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let reserved_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64);
+    let entry = Entry {
+        device,
+        path,
+        bytes,
+        reserved_at,
+    };
+    // Locked for the check and the record alone, not for the stat, which may
+    // block on a hung mount: reservations made since count all the same
+    let reserved = lock().reserve(entry, space.available);
+    match reserved {
+        Ok(id) => Ok((atoms::ok(), ResourceArc::new(Reservation { id })).encode(env)),
+        Err(available) => {
+            let info = rustler::types::map::map_new(env)
+                .map_put(atoms::required().to_term(env), bytes)?
+                .map_put(atoms::available().to_term(env), available)?
+                .map_put(atoms::shortfall().to_term(env), bytes - available)?;
+            make_error_tuple3(env, atoms::insufficient_space(), info)
+        }
+    }
+}
+
+// Releasing is idempotent, and a reservation of an earlier version of the
+// library, which doesn't decode as one of this, is released already
+#[rustler::nif]
+fn release_nif(reservation: Term) -> NifResult<Atom> {
+    match reservation.decode::<ResourceArc<Reservation>>() {
+        Ok(reservation) => lock().release(reservation.id),
+        Err(_) if reservation.get_type() == TermType::Ref => {}
+        Err(err) => return Err(err),
+    }
+    Ok(atoms::ok())
+}
+
+// The bytes reserved on the filesystem of `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn reserved_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    Ok((atoms::ok(), reserved(&path)).encode(env))
+}
+
+// The outstanding reservations as %{path, device, bytes, reserved_at}, in
+// the order they were made
+#[rustler::nif]
+fn reservations_nif(env: Env) -> NifResult<Term> {
+    let registry = lock();
+    registry
+        .entries
+        .values()
+        .map(|entry| {
+            rustler::types::map::map_new(env)
+                .map_put(atoms::path().to_term(env), path_to_term(env, &entry.path))?
+                .map_put(atoms::device().to_term(env), entry.device)?
+                .map_put(atoms::bytes().to_term(env), entry.bytes)?
+                .map_put(atoms::reserved_at().to_term(env), entry.reserved_at)
+        })
+        .collect::<NifResult<Vec<Term>>>()
+        .map(|entries| entries.encode(env))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(device: u64, bytes: u64) -> Entry {
+        Entry {
+            device,
+            path: PathBuf::from("/srv/uploads"),
+            bytes,
+            reserved_at: 0,
+        }
+    }
+
+    #[test]
+    fn takes_outstanding_reservations_off_the_available() {
+        let mut registry = Registry::default();
+        let first = registry.reserve(entry(1, 600), 1000).unwrap();
+        // The second upload of the same size no longer fits
+        assert_eq!(registry.reserve(entry(1, 600), 1000), Err(400));
+        // Another filesystem is unaffected
+        assert!(registry.reserve(entry(2, 600), 1000).is_ok());
+        assert_eq!(registry.reserved(1), 600);
+        registry.release(first);
+        registry.release(first);
+        assert_eq!(registry.reserved(1), 0);
+        assert!(registry.reserve(entry(1, 600), 1000).is_ok());
+    }
+}
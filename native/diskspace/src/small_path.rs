@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// small_path: a path decoded from a term into a NUL-terminated buffer on
+// the stack, for stat_fs, which would otherwise allocate a CString for its
+// path on every call. Paths shorter than INLINE bytes, which most are, stay
+// inline; longer ones, and those given as charlists, which are decoded
+// through a String, go on the heap as get_path_from_term would put them.
+//
+// It is taken as a &CStr wherever a &CString would be, and rejected as
+// get_path_from_term rejects it: an empty binary, or one with a NUL in it.
+
+use rustler::{Binary, Error, NifResult, Term};
+use std::ffi::{CStr, CString};
+
+const INLINE: usize = 512;
+
+// Large on purpose, as it is kept on the stack
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum SmallPath {
+    // The bytes of the path, followed by NULs
+    Inline([u8; INLINE]),
+    Heap(CString),
+}
+
+impl SmallPath {
+    // The path `bytes`, None if they have a NUL in them
+    pub(crate) fn new(bytes: &[u8]) -> Option<SmallPath> {
+        if bytes.contains(&0) {
+            return None;
+        }
+        if bytes.len() >= INLINE {
+            return CString::new(bytes).ok().map(SmallPath::Heap);
+        }
+        let mut inline = [0u8; INLINE];
+        inline[..bytes.len()].copy_from_slice(bytes);
+        Some(SmallPath::Inline(inline))
+    }
+
+    // The path `term`, a binary or a charlist
+    pub(crate) fn from_term(term: Term) -> NifResult<SmallPath> {
+        if let Ok(binary) = term.decode::<Binary>() {
+            if binary.is_empty() {
+                return Err(Error::BadArg);
+            }
+            return SmallPath::new(binary.as_slice()).ok_or(Error::BadArg);
+        }
+        let path_str: String = term.decode().map_err(|_| Error::BadArg)?;
+        CString::new(path_str)
+            .map(SmallPath::Heap)
+            .map_err(|_| Error::BadArg)
+    }
+
+    pub(crate) fn as_c_str(&self) -> &CStr {
+        match self {
+            // There is a NUL after the path, as it is shorter than the buffer
+            SmallPath::Inline(inline) => CStr::from_bytes_until_nul(inline).unwrap_or_default(),
+            SmallPath::Heap(path) => path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_short_paths_inline() {
+        let Some(path) = SmallPath::new(b"/var/lib/data") else {
+            panic!("not decoded");
+        };
+        assert!(matches!(path, SmallPath::Inline(_)));
+        assert_eq!(path.as_c_str().to_bytes(), b"/var/lib/data");
+
+        let long = vec![b'a'; INLINE];
+        let Some(path) = SmallPath::new(&long) else {
+            panic!("not decoded");
+        };
+        assert!(matches!(path, SmallPath::Heap(_)));
+        assert_eq!(path.as_c_str().to_bytes(), &long[..]);
+
+        let Some(path) = SmallPath::new(&long[1..]) else {
+            panic!("not decoded");
+        };
+        assert_eq!(path.as_c_str().to_bytes().len(), INLINE - 1);
+        assert!(SmallPath::new(b"/var\0/lib").is_none());
+    }
+}
@@ -0,0 +1,232 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// space_information: the space of the volume holding a path, both in all
+// (actual) and as the calling user sees it (caller), and its geometry.
+// GetDiskSpaceInformationW tells both apart, and also accounts for the
+// quota of a directory under a quota-managed folder, which GetDiskFreeSpaceExW
+// doesn't.
+//
+// GetDiskSpaceInformationW is only in the kernel32 of Windows 10 1809 and
+// later, and importing it would keep the NIF from loading on older ones
+// (Windows 8.1, Server 2012), so it is looked up once with GetProcAddress.
+// Without it, or if it fails, the figures come from GetDiskFreeSpaceExW and
+// the geometry from GetDiskFreeSpaceW, which knows no actual total: that is
+// nil. The result names the API it came from. Windows only; elsewhere
+// space_information returns {:error, :unsupported}.
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Env, NifResult, Term};
+#[cfg(windows)]
+use {
+    crate::{make_winapi_error_tuple, volume_root},
+    rustler::Encoder,
+    std::sync::LazyLock,
+    widestring::WideCString,
+    windows::core::{s, w, HRESULT, PCWSTR},
+    windows::Win32::Storage::FileSystem::{
+        GetDiskFreeSpaceExW, GetDiskFreeSpaceW, DISK_SPACE_INFORMATION,
+    },
+    windows::Win32::System::LibraryLoader::{GetModuleHandleW, GetProcAddress},
+};
+
+// The space of a volume in bytes, the actual total unknown to the fallback
+#[cfg(any(windows, test))]
+#[derive(Debug, PartialEq)]
+struct Information {
+    actual_total: Option<u64>,
+    actual_available: u64,
+    caller_total: u64,
+    caller_available: u64,
+    bytes_per_sector: u32,
+    allocation_unit_bytes: u64,
+}
+
+// The figures of GetDiskSpaceInformationW, in allocation units
+#[cfg(any(windows, test))]
+struct Units {
+    actual_total: u64,
+    actual_available: u64,
+    caller_total: u64,
+    caller_available: u64,
+    sectors_per_unit: u32,
+    bytes_per_sector: u32,
+}
+
+#[cfg(any(windows, test))]
+impl Information {
+    fn from_units(units: &Units) -> Information {
+        let unit = u64::from(units.sectors_per_unit) * u64::from(units.bytes_per_sector);
+        Information {
+            actual_total: Some(units.actual_total.saturating_mul(unit)),
+            actual_available: units.actual_available.saturating_mul(unit),
+            caller_total: units.caller_total.saturating_mul(unit),
+            caller_available: units.caller_available.saturating_mul(unit),
+            bytes_per_sector: units.bytes_per_sector,
+            allocation_unit_bytes: unit,
+        }
+    }
+}
+
+#[cfg(windows)]
+type GetDiskSpaceInformation =
+    unsafe extern "system" fn(PCWSTR, *mut DISK_SPACE_INFORMATION) -> HRESULT;
+
+// Helper: GetDiskSpaceInformationW, if this Windows has it
+#[cfg(windows)]
+fn get_disk_space_information() -> Option<GetDiskSpaceInformation> {
+    static FUNCTION: LazyLock<Option<GetDiskSpaceInformation>> = LazyLock::new(|| unsafe {
+        let kernel32 = GetModuleHandleW(w!("kernel32.dll")).ok()?;
+        let address = GetProcAddress(kernel32, s!("GetDiskSpaceInformationW"))?;
+        Some(std::mem::transmute::<
+            unsafe extern "system" fn() -> isize,
+            GetDiskSpaceInformation,
+        >(address))
+    });
+    *FUNCTION
+}
+
+// Helper: The space of the volume holding `path` by GetDiskSpaceInformationW,
+// None if unavailable or failing
+#[cfg(windows)]
+fn by_disk_space_information(path: &WideCString) -> Option<Information> {
+    let function = get_disk_space_information()?;
+    let mut info = DISK_SPACE_INFORMATION::default();
+    unsafe { function(PCWSTR::from_raw(path.as_ptr()), &mut info) }
+        .ok()
+        .ok()?;
+    Some(Information::from_units(&Units {
+        actual_total: info.ActualTotalAllocationUnits,
+        actual_available: info.ActualAvailableAllocationUnits,
+        caller_total: info.CallerTotalAllocationUnits,
+        caller_available: info.CallerAvailableAllocationUnits,
+        sectors_per_unit: info.SectorsPerAllocationUnit,
+        bytes_per_sector: info.BytesPerSector,
+    }))
+}
+
+// Helper: The space of the volume holding `path` by GetDiskFreeSpaceExW and
+// GetDiskFreeSpaceW, or the reason and WinAPI error code
+#[cfg(windows)]
+fn by_disk_free_space(path: &WideCString) -> Result<Information, (rustler::Atom, u32)> {
+    let code = |e: windows::core::Error| (e.code().0 & 0xFFFF) as u32;
+    let root = volume_root(path).map_err(|code| (atoms::invalid_path(), code))?;
+    let (mut caller_available, mut caller_total, mut actual_available) = (0, 0, 0);
+    unsafe {
+        GetDiskFreeSpaceExW(
+            PCWSTR::from_raw(path.as_ptr()),
+            Some(&mut caller_available),
+            Some(&mut caller_total),
+            Some(&mut actual_available),
+        )
+    }
+    .map_err(|e| (atoms::winapi_failed(), code(e)))?;
+    let (mut sectors_per_unit, mut bytes_per_sector) = (0, 0);
+    unsafe {
+        GetDiskFreeSpaceW(
+            PCWSTR::from_raw(root.as_ptr()),
+            Some(&mut sectors_per_unit),
+            Some(&mut bytes_per_sector),
+            None,
+            None,
+        )
+    }
+    .map_err(|e| (atoms::winapi_failed(), code(e)))?;
+    Ok(Information {
+        actual_total: None,
+        actual_available,
+        caller_total,
+        caller_available,
+        bytes_per_sector,
+        allocation_unit_bytes: u64::from(sectors_per_unit) * u64::from(bytes_per_sector),
+    })
+}
+
+// %{api, actual_total, actual_available, caller_total, caller_available,
+// bytes_per_sector, allocation_unit_bytes} of the volume holding `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn space_information_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(windows)]
+    {
+        let Some(path) = path
+            .to_str()
+            .ok()
+            .and_then(|path| WideCString::from_str(path).ok())
+        else {
+            return make_error_tuple(env, atoms::path_conversion_failed());
+        };
+        let (api, info) = match by_disk_space_information(&path) {
+            Some(info) => (atoms::get_disk_space_information(), info),
+            None => match by_disk_free_space(&path) {
+                Ok(info) => (atoms::get_disk_free_space_ex(), info),
+                Err((reason, code)) => return make_winapi_error_tuple(env, reason, code),
+            },
+        };
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::api().to_term(env), api)?
+            .map_put(atoms::actual_total().to_term(env), info.actual_total)?
+            .map_put(
+                atoms::actual_available().to_term(env),
+                info.actual_available,
+            )?
+            .map_put(atoms::caller_total().to_term(env), info.caller_total)?
+            .map_put(
+                atoms::caller_available().to_term(env),
+                info.caller_available,
+            )?
+            .map_put(
+                atoms::bytes_per_sector().to_term(env),
+                info.bytes_per_sector,
+            )?
+            .map_put(
+                atoms::allocation_unit_bytes().to_term(env),
+                info.allocation_unit_bytes,
+            )?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_allocation_units_to_bytes() {
+        let info = Information::from_units(&Units {
+            actual_total: 1000,
+            actual_available: 400,
+            caller_total: 500,
+            caller_available: 100,
+            sectors_per_unit: 8,
+            bytes_per_sector: 512,
+        });
+        assert_eq!(
+            info,
+            Information {
+                actual_total: Some(4_096_000),
+                actual_available: 1_638_400,
+                caller_total: 2_048_000,
+                caller_available: 409_600,
+                bytes_per_sector: 512,
+                allocation_unit_bytes: 4096,
+            }
+        );
+        let huge = Information::from_units(&Units {
+            actual_total: u64::MAX,
+            actual_available: 0,
+            caller_total: 0,
+            caller_available: 0,
+            sectors_per_unit: 1,
+            bytes_per_sector: 4096,
+        });
+        assert_eq!(huge.actual_total, Some(u64::MAX));
+    }
+}
@@ -0,0 +1,146 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The sync option of stat: flush what is dirty before statting, as the
+// blocks of files just deleted are only counted free once the metadata
+// saying so is written back. :syncfs flushes the filesystem holding the
+// path, with syncfs(2) on Linux; elsewhere on Unix there is no such call,
+// and sync(2), which :sync asks for everywhere, flushes all of them.
+//
+// On Windows both flush the volume, with FlushFileBuffers on a handle to
+// it, which takes administrator rights; without them the flush is skipped
+// and the error it was refused with returned as a warning, as a stat that
+// went ahead unflushed rather than no stat at all.
+
+use crate::{atoms, get_path_from_term, make_errno_error_tuple, make_error_tuple, posix};
+use crate::{make_error_tuple3, path_from_cstring};
+use rustler::{Atom, Encoder, Env, NifResult, Term};
+use std::io;
+use std::path::Path;
+#[cfg(windows)]
+use {
+    crate::{volume_device, volume_root},
+    widestring::WideCString,
+    windows::core::PCWSTR,
+    windows::Win32::Foundation::{CloseHandle, ERROR_ACCESS_DENIED, GENERIC_WRITE},
+    windows::Win32::Storage::FileSystem::{
+        CreateFileW, FlushFileBuffers, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ,
+        FILE_SHARE_WRITE, OPEN_EXISTING,
+    },
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    // The filesystem holding the path
+    Syncfs,
+    // All of them
+    Sync,
+}
+
+// Helper: Flush the filesystem holding `path`, or all of them
+#[cfg(target_os = "linux")]
+fn flush(path: &Path, mode: Mode) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if mode == Mode::Sync {
+        unsafe { libc::sync() };
+        return Ok(());
+    }
+    let dir = std::fs::File::open(path)?;
+    if unsafe { libc::syncfs(dir.as_raw_fd()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Helper: Flush all filesystems, there being no syncfs
+#[cfg(all(unix, not(target_os = "linux")))]
+fn flush(path: &Path, _mode: Mode) -> io::Result<()> {
+    std::fs::metadata(path)?;
+    unsafe { libc::sync() };
+    Ok(())
+}
+
+// Helper: Flush the volume holding `path`
+#[cfg(windows)]
+fn flush(path: &Path, _mode: Mode) -> io::Result<()> {
+    std::fs::metadata(path)?;
+    let winapi_error = |e: windows::core::Error| io::Error::from_raw_os_error(e.code().0 & 0xFFFF);
+    let wide = WideCString::from_os_str(path.as_os_str())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let volume = volume_root(&wide)
+        .and_then(|root| volume_device(&root))
+        .map_err(|code| io::Error::from_raw_os_error(code as i32))?;
+    let handle = unsafe {
+        CreateFileW(
+            PCWSTR::from_raw(volume.as_ptr()),
+            GENERIC_WRITE.0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            None,
+        )
+    }
+    .map_err(winapi_error)?;
+    let result = unsafe { FlushFileBuffers(handle) }.map_err(winapi_error);
+    let _ = unsafe { CloseHandle(handle) };
+    result
+}
+
+// Helper: Whether flushing was refused for want of rights, which is only
+// warned about
+#[cfg(windows)]
+fn denied(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(ERROR_ACCESS_DENIED.0 as i32)
+}
+
+// Helper: Windows alone needs rights to flush
+#[cfg(unix)]
+fn denied(_err: &io::Error) -> bool {
+    false
+}
+
+// {:ok, nil} once the filesystem holding `path` was flushed as `mode`
+// asks, {:ok, posix} if that was refused for want of rights, or
+// {:error, posix, %{errno, errstr}}
+#[rustler::nif(schedule = "DirtyIo")]
+fn sync_fs_nif<'a>(env: Env<'a>, path_term: Term<'a>, mode: Atom) -> NifResult<Term<'a>> {
+    let mode = if mode == atoms::syncfs() {
+        Mode::Syncfs
+    } else if mode == atoms::sync() {
+        Mode::Sync
+    } else {
+        return make_error_tuple3(env, atoms::invalid_option(), atoms::sync().to_term(env));
+    };
+    let Some(path) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    match flush(&path, mode) {
+        Ok(()) => Ok((atoms::ok(), rustler::types::atom::nil()).encode(env)),
+        Err(err) if denied(&err) => Ok((atoms::ok(), posix::atom(env, &err)).encode(env)),
+        Err(err) => make_errno_error_tuple(env, posix::atom(env, &err), err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flushes_or_says_why_not() {
+        let dir = std::env::temp_dir();
+        for mode in [Mode::Syncfs, Mode::Sync] {
+            if let Err(err) = flush(&dir, mode) {
+                assert!(denied(&err), "{err}");
+            }
+        }
+        let missing = dir.join("disk_space_missing_dir");
+        assert_eq!(
+            flush(&missing, Mode::Syncfs).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
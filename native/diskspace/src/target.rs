@@ -0,0 +1,230 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// open_target and stat_target: statting the same directory over and over
+// without decoding, walking and checking its path each time. open_target
+// opens the directory once, and stat_target only asks for the space of the
+// filesystem it is on: on Unix with fstatfs (fstatvfs but on Linux) on a
+// descriptor of it, opened with O_PATH on Linux so that a directory that
+// can be searched but not read will do, as it does for stat_fs; on Windows
+// with GetDiskFreeSpaceExW on the \\?\Volume{GUID}\ name of its volume,
+// worked out once, or on the root of a network share, which has none.
+//
+// A descriptor keeps its filesystem mounted: umount fails with EBUSY while
+// a target is open, and a lazy one, umount -l, leaves the target answering
+// for the detached filesystem rather than for the path. close_target lets
+// go of it without waiting for the garbage collector. A filesystem that
+// goes away regardless, as a forced NFS unmount or a USB disk pulled out
+// does, fails fstatfs with ESTALE, EIO, ENOTCONN, ENODEV or EBADF, and a
+// Windows volume removed fails with ERROR_NOT_READY and the like; either is
+// {:error, :unmounted}, and the target is of no further use.
+//
+// stat_target clones the handle out of its lock rather than holding the lock
+// through the stat, so that closing a target whose filesystem hangs doesn't
+// wait for the stat: the descriptor is closed once that stat is done, and
+// the target answers :closed from then on.
+//
+// A target closes its descriptor as it is closed or garbage collected. The
+// BEAM doesn't unload a NIF library while resources of it are alive, so an
+// unload leaves none open.
+
+use crate::{atoms, encode_stat, get_path_from_term, make_error_tuple, telemetry};
+use crate::{Space, StatError};
+use rustler::{Atom, Encoder, Env, NifResult, ResourceArc, Term};
+use std::ffi::CString;
+use std::sync::{Arc, PoisonError, RwLock};
+#[cfg(target_os = "linux")]
+use {crate::space_of_statfs, nix::sys::statfs::fstatfs};
+#[cfg(all(unix, not(target_os = "linux")))]
+use {crate::space_of_statvfs, nix::sys::statvfs::fstatvfs};
+#[cfg(windows)]
+use {
+    crate::{disk_free_space, stat_dir, volume_device, volume_root},
+    widestring::WideCString,
+    windows::Win32::Foundation::{
+        ERROR_BAD_NETPATH, ERROR_DEV_NOT_EXIST, ERROR_INVALID_DRIVE, ERROR_NETNAME_DELETED,
+        ERROR_NOT_READY, ERROR_PATH_NOT_FOUND, ERROR_UNRECOGNIZED_VOLUME,
+    },
+};
+#[cfg(unix)]
+use {
+    nix::errno::Errno, std::ffi::OsStr, std::fs::OpenOptions, std::io, std::os::fd::OwnedFd,
+    std::os::unix::ffi::OsStrExt, std::os::unix::fs::OpenOptionsExt, std::path::Path,
+};
+
+// What stat_target asks: a descriptor of the directory
+#[cfg(unix)]
+type Handle = OwnedFd;
+// What stat_target asks: the \\?\Volume{GUID}\ name of the volume, or the
+// root of a network share
+#[cfg(windows)]
+type Handle = WideCString;
+
+// The handle open_target returns
+pub(crate) struct Target {
+    // None once closed
+    handle: RwLock<Option<Arc<Handle>>>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for Target {}
+
+// The errors of a filesystem that is gone
+#[cfg(unix)]
+const UNMOUNTED: [Errno; 5] = [
+    Errno::ESTALE,
+    Errno::EIO,
+    Errno::ENOTCONN,
+    Errno::ENODEV,
+    Errno::EBADF,
+];
+#[cfg(windows)]
+const UNMOUNTED: [u32; 7] = [
+    ERROR_NOT_READY.0,
+    ERROR_INVALID_DRIVE.0,
+    ERROR_PATH_NOT_FOUND.0,
+    ERROR_DEV_NOT_EXIST.0,
+    ERROR_UNRECOGNIZED_VOLUME.0,
+    ERROR_BAD_NETPATH.0,
+    ERROR_NETNAME_DELETED.0,
+];
+
+// Helper: The handle of the directory `path_cstr`, failing as stat_fs would
+#[cfg(unix)]
+fn open(path_cstr: &CString) -> Result<Handle, StatError> {
+    let path = Path::new(OsStr::from_bytes(path_cstr.as_bytes()));
+    #[cfg(target_os = "linux")]
+    let flags = libc::O_PATH | libc::O_DIRECTORY;
+    #[cfg(not(target_os = "linux"))]
+    let flags = libc::O_DIRECTORY;
+    match OpenOptions::new().read(true).custom_flags(flags).open(path) {
+        Ok(dir) => Ok(dir.into()),
+        Err(err) if err.raw_os_error() == Some(libc::ENOTDIR) => {
+            Err(StatError::Reason(atoms::not_directory()))
+        }
+        Err(err) => Err(StatError::Errno(atoms::not_directory(), err)),
+    }
+}
+
+#[cfg(windows)]
+fn open(path_cstr: &CString) -> Result<Handle, StatError> {
+    // Checked as stat_fs checks it
+    stat_dir(path_cstr)?;
+    let conversion_failed = || StatError::Reason(atoms::path_conversion_failed());
+    let path = path_cstr
+        .to_str()
+        .ok()
+        .and_then(|path| WideCString::from_str(path).ok())
+        .ok_or_else(conversion_failed)?;
+    let root = volume_root(&path).map_err(|code| StatError::WinApi(atoms::invalid_path(), code))?;
+    // Network shares have no volume
+    match volume_device(&root) {
+        Ok(volume) => WideCString::from_str(format!("{}\\", volume.to_string_lossy()))
+            .map_err(|_| conversion_failed()),
+        Err(_) => Ok(root),
+    }
+}
+
+// Helper: The space figures of the filesystem `handle` is on
+#[cfg(target_os = "linux")]
+fn stat(handle: &Handle) -> Result<Space, StatError> {
+    fstatfs(handle)
+        .map(|buf| space_of_statfs(&buf))
+        .map_err(|errno| stat_error(errno, atoms::statfs_failed()))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn stat(handle: &Handle) -> Result<Space, StatError> {
+    fstatvfs(handle)
+        .map(|buf| space_of_statvfs(&buf))
+        .map_err(|errno| stat_error(errno, atoms::statvfs_failed()))
+}
+
+#[cfg(windows)]
+fn stat(handle: &Handle) -> Result<Space, StatError> {
+    disk_free_space(handle).map_err(|code| match UNMOUNTED.contains(&code) {
+        true => StatError::WinApi(atoms::unmounted(), code),
+        false => StatError::WinApi(atoms::winapi_failed(), code),
+    })
+}
+
+// Helper: The error of a failed fstatfs, :unmounted for a filesystem gone
+#[cfg(unix)]
+fn stat_error(errno: Errno, failed: Atom) -> StatError {
+    let reason = match UNMOUNTED.contains(&errno) {
+        true => atoms::unmounted(),
+        false => failed,
+    };
+    StatError::Errno(reason, io::Error::from_raw_os_error(errno as i32))
+}
+
+// {:ok, target} for statting the directory `path` with stat_target, or the
+// error tuple stat_fs would return for it
+#[rustler::nif(schedule = "DirtyIo")]
+fn open_target_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path_cstr) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    match open(&path_cstr) {
+        Ok(handle) => {
+            let target = ResourceArc::new(Target {
+                handle: RwLock::new(Some(Arc::new(handle))),
+            });
+            Ok((atoms::ok(), target).encode(env))
+        }
+        Err(err) => encode_stat(env, Err(err)),
+    }
+}
+
+// {:ok, %{available, free, total, used}} of the filesystem `target` is on,
+// counted for nif_stats as stat_fs is
+#[rustler::nif(schedule = "DirtyIo")]
+fn stat_target_nif(env: Env, target: ResourceArc<Target>) -> NifResult<Term> {
+    let handle = target
+        .handle
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let stat = match handle {
+        Some(handle) => telemetry::timed(
+            telemetry::Op::StatFs,
+            || stat(&handle),
+            |stat| stat.as_ref().err().map(StatError::reason),
+        ),
+        None => Err(StatError::Reason(atoms::closed())),
+    };
+    encode_stat(env, stat)
+}
+
+// :ok, the descriptor of `target` closed, if it wasn't already, or once a
+// stat of it still running is done
+#[rustler::nif]
+fn close_target_nif(target: ResourceArc<Target>) -> Atom {
+    let handle = target
+        .handle
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    drop(handle);
+    atoms::ok()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_the_filesystem_of_an_open_directory() {
+        let dir = std::env::temp_dir();
+        let path_cstr = CString::new(dir.as_os_str().as_bytes()).unwrap();
+        let Ok(handle) = open(&path_cstr) else {
+            panic!("{} not opened", dir.display());
+        };
+        let Ok(space) = stat(&handle) else {
+            panic!("{} not statted", dir.display());
+        };
+        assert!(space.total > 0);
+        assert!(space.available <= space.free && space.free <= space.total);
+        assert_eq!(space.used, space.total - space.free);
+    }
+}
@@ -0,0 +1,286 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Counters for nif_stats: for each kind of operation, how many ran, how many
+// of those failed and why, and how long they took. The operations are
+// stat_fs, each stat of a path, whatever function it was made for; du, each
+// walk; list_mounts, each read of the mount table; and watcher_tick, each
+// tick of a watcher, failing with :overloaded or :pool_failed when the pool
+// wouldn't take it. A stat on a normal scheduler that overran its budget
+// counts as failing with :budget_exceeded, though release builds return its
+// figures. The lookups of stat_fs_cached are counted apart, as hits and
+// misses, each miss also being a stat.
+//
+// Recording one takes a handful of relaxed atomic operations; only a failure
+// also takes a lock, to count it by its reason. The latencies go into
+// buckets with fixed upper bounds, a power of ten apart. nif_stats reads the
+// counters one at a time, so a snapshot taken while operations run may be
+// off by those in flight, as may nif_stats_reset.
+//
+// Built without the telemetry feature, the counters are compiled out,
+// recording does nothing and both NIFs return {:error, :telemetry_disabled}.
+
+use crate::atoms;
+use rustler::{Atom, Env, NifResult, Term};
+use std::time::Duration;
+#[cfg(feature = "telemetry")]
+use {
+    rustler::Encoder,
+    std::sync::atomic::{AtomicU64, Ordering},
+    std::sync::{Mutex, PoisonError},
+    std::time::Instant,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Op {
+    StatFs,
+    Du,
+    ListMounts,
+    WatcherTick,
+}
+
+// Runs `run` as an `op`, failed for the reason `failure` tells from its result
+#[cfg(feature = "telemetry")]
+pub(crate) fn timed<T>(
+    op: Op,
+    run: impl FnOnce() -> T,
+    failure: impl FnOnce(&T) -> Option<Atom>,
+) -> T {
+    let started = Instant::now();
+    let result = run();
+    record(op, started.elapsed(), failure(&result));
+    result
+}
+
+#[cfg(not(feature = "telemetry"))]
+pub(crate) fn timed<T>(
+    _op: Op,
+    run: impl FnOnce() -> T,
+    _failure: impl FnOnce(&T) -> Option<Atom>,
+) -> T {
+    run()
+}
+
+#[cfg(feature = "telemetry")]
+pub(crate) fn record(op: Op, elapsed: Duration, failure: Option<Atom>) {
+    COUNTERS[op as usize].record(elapsed, failure);
+}
+
+#[cfg(not(feature = "telemetry"))]
+pub(crate) fn record(_op: Op, _elapsed: Duration, _failure: Option<Atom>) {}
+
+// Counts a lookup of stat_fs_cached
+#[cfg(feature = "telemetry")]
+pub(crate) fn count_cache(hit: bool) {
+    let counter = if hit { &CACHE_HITS } else { &CACHE_MISSES };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(not(feature = "telemetry"))]
+pub(crate) fn count_cache(_hit: bool) {}
+
+// Upper bounds of the latency buckets in microseconds, the last one open
+#[cfg(feature = "telemetry")]
+const BOUNDS_US: [u64; 7] = [10, 100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+#[cfg(feature = "telemetry")]
+const BUCKETS: usize = BOUNDS_US.len() + 1;
+
+#[cfg(feature = "telemetry")]
+struct Counters {
+    calls: AtomicU64,
+    sum_us: AtomicU64,
+    max_us: AtomicU64,
+    buckets: [AtomicU64; BUCKETS],
+    failures: Mutex<Vec<(Atom, u64)>>,
+}
+
+// One for each Op, in order
+#[cfg(feature = "telemetry")]
+static COUNTERS: [Counters; 4] = [const { Counters::new() }; 4];
+
+#[cfg(feature = "telemetry")]
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "telemetry")]
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "telemetry")]
+impl Counters {
+    const fn new() -> Counters {
+        Counters {
+            calls: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+            max_us: AtomicU64::new(0),
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+            failures: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn record(&self, elapsed: Duration, failure: Option<Atom>) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(micros, Ordering::Relaxed);
+        self.max_us.fetch_max(micros, Ordering::Relaxed);
+        self.buckets[bucket(micros)].fetch_add(1, Ordering::Relaxed);
+        if let Some(reason) = failure {
+            let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+            match failures.iter_mut().find(|(seen, _)| *seen == reason) {
+                Some((_, count)) => *count += 1,
+                None => failures.push((reason, 1)),
+            }
+        }
+    }
+
+    fn reset(&self) {
+        self.calls.store(0, Ordering::Relaxed);
+        self.sum_us.store(0, Ordering::Relaxed);
+        self.max_us.store(0, Ordering::Relaxed);
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    // Helper: %{calls, failures, latency_us: %{sum, max, histogram}}
+    fn encode<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        let failures = self
+            .failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let failures = failures
+            .iter()
+            .try_fold(rustler::types::map::map_new(env), |map, (reason, count)| {
+                map.map_put(reason.to_term(env), count)
+            })?;
+        let histogram: Vec<Term<'a>> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let count = count.load(Ordering::Relaxed);
+                match BOUNDS_US.get(i) {
+                    Some(bound) => (bound, count).encode(env),
+                    None => (atoms::infinity(), count).encode(env),
+                }
+            })
+            .collect();
+        let latency = rustler::types::map::map_new(env)
+            .map_put(
+                atoms::sum().to_term(env),
+                self.sum_us.load(Ordering::Relaxed),
+            )?
+            .map_put(
+                atoms::max().to_term(env),
+                self.max_us.load(Ordering::Relaxed),
+            )?
+            .map_put(atoms::histogram().to_term(env), histogram)?;
+        rustler::types::map::map_new(env)
+            .map_put(
+                atoms::calls().to_term(env),
+                self.calls.load(Ordering::Relaxed),
+            )?
+            .map_put(atoms::failures().to_term(env), failures)?
+            .map_put(atoms::latency_us().to_term(env), latency)
+    }
+}
+
+// Helper: The bucket of a latency of `micros`
+#[cfg(feature = "telemetry")]
+fn bucket(micros: u64) -> usize {
+    BOUNDS_US
+        .iter()
+        .position(|bound| micros <= *bound)
+        .unwrap_or(BOUNDS_US.len())
+}
+
+#[cfg(feature = "telemetry")]
+impl Op {
+    const ALL: [Op; 4] = [Op::StatFs, Op::Du, Op::ListMounts, Op::WatcherTick];
+
+    fn atom(self) -> Atom {
+        match self {
+            Op::StatFs => atoms::stat_fs(),
+            Op::Du => atoms::du(),
+            Op::ListMounts => atoms::list_mounts(),
+            Op::WatcherTick => atoms::watcher_tick(),
+        }
+    }
+}
+
+// %{stat_fs: counters, du: counters, list_mounts: counters, watcher_tick:
+// counters, stat_fs_cached: %{hits, misses}}
+#[rustler::nif]
+fn nif_stats_nif(env: Env) -> NifResult<Term> {
+    #[cfg(feature = "telemetry")]
+    {
+        let cache = rustler::types::map::map_new(env)
+            .map_put(
+                atoms::hits().to_term(env),
+                CACHE_HITS.load(Ordering::Relaxed),
+            )?
+            .map_put(
+                atoms::misses().to_term(env),
+                CACHE_MISSES.load(Ordering::Relaxed),
+            )?;
+        Op::ALL
+            .iter()
+            .try_fold(rustler::types::map::map_new(env), |map, op| {
+                map.map_put(op.atom().to_term(env), COUNTERS[*op as usize].encode(env)?)
+            })?
+            .map_put(atoms::stat_fs_cached().to_term(env), cache)
+    }
+    #[cfg(not(feature = "telemetry"))]
+    {
+        crate::make_error_tuple(env, atoms::telemetry_disabled())
+    }
+}
+
+#[rustler::nif]
+fn nif_stats_reset_nif(env: Env) -> NifResult<Term> {
+    #[cfg(feature = "telemetry")]
+    {
+        for counters in &COUNTERS {
+            counters.reset();
+        }
+        CACHE_HITS.store(0, Ordering::Relaxed);
+        CACHE_MISSES.store(0, Ordering::Relaxed);
+        Ok(atoms::ok().to_term(env))
+    }
+    #[cfg(not(feature = "telemetry"))]
+    {
+        crate::make_error_tuple(env, atoms::telemetry_disabled())
+    }
+}
+
+#[cfg(all(test, feature = "telemetry"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_by_upper_bound() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(10), 0);
+        assert_eq!(bucket(11), 1);
+        assert_eq!(bucket(1_000_000), 5);
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn counts_calls_and_latencies() {
+        let counters = Counters::new();
+        counters.record(Duration::from_micros(5), None);
+        counters.record(Duration::from_millis(20), None);
+        counters.record(Duration::from_millis(3), None);
+        assert_eq!(counters.calls.load(Ordering::Relaxed), 3);
+        assert_eq!(counters.sum_us.load(Ordering::Relaxed), 23_005);
+        assert_eq!(counters.max_us.load(Ordering::Relaxed), 20_000);
+        assert_eq!(counters.buckets[4].load(Ordering::Relaxed), 1);
+        counters.reset();
+        assert_eq!(counters.calls.load(Ordering::Relaxed), 0);
+        assert_eq!(counters.buckets[0].load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,188 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Memory-backed filesystems: tmpfs (and ramfs) take their space from RAM
+// and swap, so that filling /tmp or /dev/shm competes with memory. For
+// these, list_mounts adds memory_backed: true, the size= limit of the mount
+// in bytes, that size as a fraction of MemTotal from /proc/meminfo, and
+// whether it was mounted noswap (Linux 6.4 and later); stat's tmpfs_details
+// option adds the same for the filesystem holding a path.
+//
+// The kernel shows the size= of a tmpfs in KiB, but it may be given with
+// any of the k, m, g, t, p and e suffixes, or as a percentage of RAM; a
+// tmpfs without one is unlimited, and ramfs never has one, so both have
+// a nil size and fraction.
+
+use crate::mounts::Mount;
+use crate::{atoms, get_path_from_term, make_error_tuple};
+#[cfg(target_os = "linux")]
+use crate::{make_errno_error_tuple, mounts, path_from_cstring};
+use rustler::{Encoder, Env, NifResult, Term};
+
+#[derive(Debug, PartialEq)]
+struct Tmpfs {
+    size: Option<u64>,
+    fraction: Option<f64>,
+    noswap: bool,
+}
+
+impl Tmpfs {
+    // The tmpfs figures of `mount`, None if it isn't memory-backed
+    fn describe(mount: &Mount, mem_total: Option<u64>) -> Option<Tmpfs> {
+        if !matches!(mount.fs_type.as_str(), "tmpfs" | "ramfs") {
+            return None;
+        }
+        let size = mount
+            .options
+            .iter()
+            .find_map(|option| option.strip_prefix("size="))
+            .and_then(|size| parse_size(size, mem_total));
+        let fraction = size
+            .zip(mem_total.filter(|total| *total > 0))
+            .map(|(size, total)| (size as f64 / total as f64 * 1000.0).round() / 1000.0);
+        Some(Tmpfs {
+            size,
+            fraction,
+            noswap: mount.options.iter().any(|option| option == "noswap"),
+        })
+    }
+}
+
+// Helper: A size= option in bytes, a percentage taking `mem_total`
+fn parse_size(size: &str, mem_total: Option<u64>) -> Option<u64> {
+    if let Some(percent) = size.strip_suffix('%') {
+        let percent: u64 = percent.parse().ok()?;
+        return mem_total?.checked_mul(percent).map(|bytes| bytes / 100);
+    }
+    let digits = size.bytes().take_while(u8::is_ascii_digit).count();
+    let number: u64 = size[..digits].parse().ok()?;
+    let shift = match size[digits..].to_ascii_lowercase().as_str() {
+        "" => 0,
+        "k" => 10,
+        "m" => 20,
+        "g" => 30,
+        "t" => 40,
+        "p" => 50,
+        "e" => 60,
+        _ => return None,
+    };
+    number.checked_mul(1 << shift)
+}
+
+// MemTotal of /proc/meminfo, in bytes
+#[cfg(target_os = "linux")]
+pub(crate) fn mem_total() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("MemTotal:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+// MemTotal, unknown off Linux
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn mem_total() -> Option<u64> {
+    None
+}
+
+// Helper: `map` with memory_backed, size_bytes, size_is_ram_fraction and
+// noswap put, or as it is if `mount` isn't memory-backed
+pub(crate) fn put<'a>(
+    env: Env<'a>,
+    map: Term<'a>,
+    mount: &Mount,
+    mem_total: Option<u64>,
+) -> NifResult<Term<'a>> {
+    match Tmpfs::describe(mount, mem_total) {
+        Some(tmpfs) => encode_into(env, map, &tmpfs),
+        None => Ok(map),
+    }
+}
+
+// Helper: The tmpfs figures, put in `map`
+fn encode_into<'a>(env: Env<'a>, map: Term<'a>, tmpfs: &Tmpfs) -> NifResult<Term<'a>> {
+    map.map_put(atoms::memory_backed().to_term(env), true)?
+        .map_put(atoms::size_bytes().to_term(env), tmpfs.size)?
+        .map_put(atoms::size_is_ram_fraction().to_term(env), tmpfs.fraction)?
+        .map_put(atoms::noswap().to_term(env), tmpfs.noswap)
+}
+
+// %{memory_backed, size_bytes, size_is_ram_fraction, noswap} of the
+// memory-backed filesystem holding `path`, or nil for another filesystem
+#[rustler::nif(schedule = "DirtyIo")]
+fn tmpfs_details_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "linux")]
+    {
+        let Some(path) = path_from_cstring(&path) else {
+            return make_error_tuple(env, atoms::invalid_path());
+        };
+        let tmpfs = match mounts::holding(&path) {
+            Ok(Some(mount)) => Tmpfs::describe(&mount, mem_total()),
+            Ok(None) => None,
+            Err(err) => return make_errno_error_tuple(env, atoms::invalid_path(), err),
+        };
+        let details = match tmpfs {
+            Some(tmpfs) => encode_into(env, rustler::types::map::map_new(env), &tmpfs)?,
+            None => rustler::types::atom::nil().to_term(env),
+        };
+        Ok((atoms::ok(), details).encode(env))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        Ok((atoms::ok(), rustler::types::atom::nil()).encode(env))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mount(fs_type: &str, options: &[&str]) -> Mount {
+        Mount {
+            mount_id: None,
+            parent_id: None,
+            device: "0:42".to_string(),
+            root: None,
+            mount_point: "/dev/shm".to_string(),
+            fs_type: fs_type.to_string(),
+            source: fs_type.to_string(),
+            options: options.iter().map(|option| option.to_string()).collect(),
+            read_only: false,
+        }
+    }
+
+    #[test]
+    fn relates_the_size_of_a_tmpfs_to_ram() {
+        let ram = Some(16 << 30);
+        assert_eq!(
+            Tmpfs::describe(&mount("tmpfs", &["rw", "size=8388608k", "noswap"]), ram),
+            Some(Tmpfs {
+                size: Some(8 << 30),
+                fraction: Some(0.5),
+                noswap: true,
+            })
+        );
+        assert_eq!(
+            Tmpfs::describe(&mount("tmpfs", &["rw", "size=25%"]), ram),
+            Some(Tmpfs {
+                size: Some(4 << 30),
+                fraction: Some(0.25),
+                noswap: false,
+            })
+        );
+        assert_eq!(
+            Tmpfs::describe(&mount("ramfs", &["rw"]), ram),
+            Some(Tmpfs {
+                size: None,
+                fraction: None,
+                noswap: false,
+            })
+        );
+        assert_eq!(Tmpfs::describe(&mount("ext4", &["rw"]), ram), None);
+        assert_eq!(parse_size("2G", None), Some(2 << 30));
+        assert_eq!(parse_size("2x", None), None);
+    }
+}
@@ -0,0 +1,106 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// volume_dirty: whether the volume holding a path has its dirty bit set,
+// as NTFS and FAT volumes do when not dismounted cleanly, chkdsk running on
+// them at the next boot. Until then their free space may be counted wrong,
+// which tells apart the odd figures stat gets after a crash. Windows only;
+// elsewhere it returns {:error, :unsupported}.
+//
+// The bit is asked for with FSCTL_IS_VOLUME_DIRTY, which takes no access
+// to the volume; its handle is opened for reading its attributes only,
+// which needs no administrator rights, and with FILE_FLAG_BACKUP_SEMANTICS,
+// as a volume is no file. Where it is refused regardless the result is
+// {:error, :access_denied}. stat's volume_dirty option adds the bit, nil if
+// it can't be told.
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+use rustler::{Env, NifResult, Term};
+#[cfg(windows)]
+use {
+    crate::{make_winapi_error_tuple, volume_device, volume_root},
+    rustler::Encoder,
+    widestring::WideCString,
+    windows::core::PCWSTR,
+    windows::Win32::Foundation::{CloseHandle, ERROR_ACCESS_DENIED},
+    windows::Win32::Storage::FileSystem::{
+        CreateFileW, FILE_FLAG_BACKUP_SEMANTICS, FILE_READ_ATTRIBUTES, FILE_SHARE_READ,
+        FILE_SHARE_WRITE, OPEN_EXISTING,
+    },
+    windows::Win32::System::Ioctl::{FSCTL_IS_VOLUME_DIRTY, VOLUME_IS_DIRTY},
+    windows::Win32::System::IO::DeviceIoControl,
+};
+
+// Helper: Whether the volume `volume`, as \\?\Volume{GUID}, is dirty, or
+// the WinAPI error code
+#[cfg(windows)]
+fn is_dirty(volume: &WideCString) -> Result<bool, u32> {
+    let winapi_error = |e: windows::core::Error| (e.code().0 & 0xFFFF) as u32;
+    let handle = unsafe {
+        CreateFileW(
+            PCWSTR::from_raw(volume.as_ptr()),
+            FILE_READ_ATTRIBUTES.0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAG_BACKUP_SEMANTICS,
+            None,
+        )
+    }
+    .map_err(winapi_error)?;
+    let mut flags = 0u32;
+    let mut returned = 0u32;
+    let result = unsafe {
+        DeviceIoControl(
+            handle,
+            FSCTL_IS_VOLUME_DIRTY,
+            None,
+            0,
+            Some((&mut flags as *mut u32).cast()),
+            std::mem::size_of::<u32>() as u32,
+            Some(&mut returned),
+            None,
+        )
+    }
+    .map_err(winapi_error);
+    let _ = unsafe { CloseHandle(handle) };
+    result.map(|()| flags & VOLUME_IS_DIRTY != 0)
+}
+
+// {:ok, dirty} of the volume holding `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn volume_dirty_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(windows)]
+    {
+        let Some(path) = path
+            .to_str()
+            .ok()
+            .and_then(|path| WideCString::from_str(path).ok())
+        else {
+            return make_error_tuple(env, atoms::path_conversion_failed());
+        };
+        let root = match volume_root(&path) {
+            Ok(root) => root,
+            Err(code) => return make_winapi_error_tuple(env, atoms::invalid_path(), code),
+        };
+        // Network shares have no volume to open
+        let Ok(volume) = volume_device(&root) else {
+            return make_error_tuple(env, atoms::not_block_device());
+        };
+        match is_dirty(&volume) {
+            Ok(dirty) => Ok((atoms::ok(), dirty).encode(env)),
+            Err(code) if code == ERROR_ACCESS_DENIED.0 => {
+                make_error_tuple(env, atoms::access_denied())
+            }
+            Err(code) => make_winapi_error_tuple(env, atoms::winapi_failed(), code),
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
@@ -0,0 +1,264 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// volume_id: one identifier per filesystem that stays the same across
+// mounts and reboots, for inventories. On Linux it is the UUID of the
+// filesystem as udev names its link in /dev/disk/by-uuid, on macOS the
+// volume UUID getattrlist gives as ATTR_VOL_UUID, and on Windows the GUID
+// of the volume, which its \\?\Volume{GUID}\ name has in it. The BSDs have
+// none to give.
+//
+// volume_id is the lowercase canonical text of a UUID wherever the value
+// is one, and the value lowercased otherwise, as the serial number udev
+// gives a FAT filesystem, 1234-ABCD; volume_id_raw is the value as the
+// platform gives it. Filesystems without one, tmpfs or NFS, have both nil.
+// stat's volume_id option adds both for the filesystem holding a path, and
+// the list_mounts option of the same name to each mount.
+
+use crate::mounts::Mount;
+use crate::{atoms, get_path_from_term, make_error_tuple};
+#[cfg(unix)]
+use crate::{make_errno_error_tuple, mounts, path_from_cstring};
+use rustler::{Encoder, Env, NifResult, Term};
+#[cfg(target_os = "macos")]
+use std::{ffi::CString, os::unix::ffi::OsStrExt, path::Path};
+#[cfg(target_os = "linux")]
+use std::{fs, os::unix::fs::MetadataExt, path::PathBuf};
+#[cfg(windows)]
+use {
+    crate::{make_winapi_error_tuple, volume_device, volume_root},
+    widestring::WideCString,
+};
+
+#[derive(Debug, PartialEq)]
+struct VolumeId {
+    id: String,
+    raw: String,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows, test))]
+impl VolumeId {
+    // The identifier `raw`, as the platform gives it
+    fn new(raw: &str) -> VolumeId {
+        VolumeId {
+            id: canonical(raw).unwrap_or_else(|| raw.to_ascii_lowercase()),
+            raw: raw.to_string(),
+        }
+    }
+}
+
+// Helper: The UUID in `raw`, with or without dashes and braces, as its
+// lowercase canonical text, None if there is none
+#[cfg(any(target_os = "linux", target_os = "macos", windows, test))]
+fn canonical(raw: &str) -> Option<String> {
+    let inner = match (raw.find('{'), raw.rfind('}')) {
+        (Some(open), Some(close)) if open < close => &raw[open + 1..close],
+        _ => raw,
+    };
+    let hex: String = inner.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let hex = hex.to_ascii_lowercase();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+// What the identifiers of filesystems are found in, gathered once for all
+// the mounts of list_mounts
+pub(crate) struct Lookup {
+    // Linux only: the device number, node and UUID of each link in
+    // /dev/disk/by-uuid
+    #[cfg(target_os = "linux")]
+    by_uuid: Vec<(String, PathBuf, String)>,
+}
+
+impl Lookup {
+    pub(crate) fn new() -> Lookup {
+        #[cfg(target_os = "linux")]
+        {
+            let by_uuid = fs::read_dir("/dev/disk/by-uuid")
+                .map(|entries| {
+                    entries
+                        .filter_map(Result::ok)
+                        .filter_map(|entry| {
+                            let uuid = entry.file_name().into_string().ok()?;
+                            let node = fs::canonicalize(entry.path()).ok()?;
+                            let rdev = fs::metadata(&node).ok()?.rdev();
+                            let device = format!("{}:{}", libc::major(rdev), libc::minor(rdev));
+                            Some((device, node, uuid))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            Lookup { by_uuid }
+        }
+        #[cfg(not(target_os = "linux"))]
+        Lookup {}
+    }
+
+    // The identifier of the filesystem mounted as `mount`, None if it has
+    // none
+    fn of(&self, mount: &Mount) -> Option<VolumeId> {
+        #[cfg(target_os = "linux")]
+        {
+            // The device number of a btrfs filesystem is its own, not that
+            // of the device it is mounted from
+            let source = fs::canonicalize(&mount.source).ok();
+            self.by_uuid
+                .iter()
+                .find(|(device, node, _)| *device == mount.device || source.as_ref() == Some(node))
+                .map(|(_, _, uuid)| VolumeId::new(uuid))
+        }
+        #[cfg(target_os = "macos")]
+        {
+            volume_uuid(Path::new(&mount.mount_point)).map(|raw| VolumeId::new(&raw))
+        }
+        #[cfg(windows)]
+        {
+            canonical(&mount.source).map(|_| VolumeId::new(&mount.source))
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+        {
+            let _ = mount;
+            None
+        }
+    }
+}
+
+// Helper: The ATTR_VOL_UUID of the volume mounted at `mount_point`, in the
+// uppercase diskutil shows it in, None if it has none
+#[cfg(target_os = "macos")]
+fn volume_uuid(mount_point: &Path) -> Option<String> {
+    #[repr(C, packed(4))]
+    struct Reply {
+        length: u32,
+        uuid: [u8; 16],
+    }
+    let path = CString::new(mount_point.as_os_str().as_bytes()).ok()?;
+    let mut request = libc::attrlist {
+        bitmapcount: libc::ATTR_BIT_MAP_COUNT,
+        reserved: 0,
+        commonattr: 0,
+        volattr: libc::ATTR_VOL_INFO | libc::ATTR_VOL_UUID,
+        dirattr: 0,
+        fileattr: 0,
+        forkattr: 0,
+    };
+    let mut reply = Reply {
+        length: 0,
+        uuid: [0; 16],
+    };
+    let result = unsafe {
+        libc::getattrlist(
+            path.as_ptr(),
+            &mut request as *mut libc::attrlist as *mut libc::c_void,
+            &mut reply as *mut Reply as *mut libc::c_void,
+            std::mem::size_of::<Reply>(),
+            0,
+        )
+    };
+    let uuid = reply.uuid;
+    if result != 0 || uuid == [0; 16] {
+        return None;
+    }
+    let hex: String = uuid.iter().map(|byte| format!("{byte:02X}")).collect();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+// Helper: `map` with volume_id and volume_id_raw put, nil if `volume_id` is
+// None
+fn encode_into<'a>(
+    env: Env<'a>,
+    map: Term<'a>,
+    volume_id: Option<VolumeId>,
+) -> NifResult<Term<'a>> {
+    let (id, raw) = volume_id.map(|id| (id.id, id.raw)).unzip();
+    map.map_put(atoms::volume_id().to_term(env), id)?
+        .map_put(atoms::volume_id_raw().to_term(env), raw)
+}
+
+// Helper: `map` with the volume_id and volume_id_raw of `mount` put
+pub(crate) fn put<'a>(
+    env: Env<'a>,
+    map: Term<'a>,
+    mount: &Mount,
+    lookup: &Lookup,
+) -> NifResult<Term<'a>> {
+    encode_into(env, map, lookup.of(mount))
+}
+
+// {:ok, %{volume_id, volume_id_raw}} of the filesystem holding `path`
+#[rustler::nif(schedule = "DirtyIo")]
+fn volume_id_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(unix)]
+    let volume_id = {
+        let Some(path) = path_from_cstring(&path) else {
+            return make_error_tuple(env, atoms::invalid_path());
+        };
+        match mounts::holding(&path) {
+            Ok(Some(mount)) => Lookup::new().of(&mount),
+            Ok(None) => None,
+            Err(err) => return make_errno_error_tuple(env, atoms::invalid_path(), err),
+        }
+    };
+    #[cfg(windows)]
+    let volume_id = {
+        let Some(path) = path
+            .to_str()
+            .ok()
+            .and_then(|path| WideCString::from_str(path).ok())
+        else {
+            return make_error_tuple(env, atoms::path_conversion_failed());
+        };
+        let root = match volume_root(&path) {
+            Ok(root) => root,
+            Err(code) => return make_winapi_error_tuple(env, atoms::invalid_path(), code),
+        };
+        // Network shares have no volume, nor a GUID
+        volume_device(&root)
+            .ok()
+            .map(|device| VolumeId::new(&format!("{}\\", device.to_string_lossy())))
+    };
+    let map = encode_into(env, rustler::types::map::map_new(env), volume_id)?;
+    Ok((atoms::ok(), map).encode(env))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_uuids_and_keeps_other_identifiers() {
+        assert_eq!(
+            VolumeId::new("3F2B9C1E-8A4D-4E1F-9B2C-7D6E5F4A3B21"),
+            VolumeId {
+                id: "3f2b9c1e-8a4d-4e1f-9b2c-7d6e5f4a3b21".to_string(),
+                raw: "3F2B9C1E-8A4D-4E1F-9B2C-7D6E5F4A3B21".to_string(),
+            }
+        );
+        assert_eq!(
+            VolumeId::new(r"\\?\Volume{6b29fc40-ca47-1067-b31d-00dd010662da}\").id,
+            "6b29fc40-ca47-1067-b31d-00dd010662da"
+        );
+        assert_eq!(VolumeId::new("1234-ABCD").id, "1234-abcd");
+        assert_eq!(VolumeId::new("0123456789ABCDEF").id, "0123456789abcdef");
+        assert_eq!(canonical("{not-a-guid}"), None);
+    }
+}
@@ -0,0 +1,191 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Threshold alerts for watchers with alert_below: %{bytes: n, percent: p}.
+// Space turns low once the available bytes fall below either threshold that
+// is set, and recovers once they are back above both plus the hysteresis, in
+// bytes, percent of the total or both added up, so that space hovering
+// around a threshold doesn't make it flap. With debounce_samples: k, either
+// change only happens once it has held for k samples in a row.
+//
+// The watcher only reports the changes, starting out as ok. Error samples are
+// not taken in: they neither change the state nor break a streak.
+
+use crate::{atoms, options};
+use rustler::{Atom, Term};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum State {
+    Ok,
+    Low,
+}
+
+impl State {
+    pub(super) fn atom(self) -> Atom {
+        match self {
+            State::Ok => atoms::ok(),
+            State::Low => atoms::low(),
+        }
+    }
+
+    // What the alert message calls the change into this state
+    pub(super) fn transition(self) -> Atom {
+        match self {
+            State::Ok => atoms::recovered(),
+            State::Low => atoms::low(),
+        }
+    }
+}
+
+// An amount of space given in bytes, in percent of the total, or both
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct Level {
+    bytes: Option<u64>,
+    percent: Option<f64>,
+}
+
+impl Level {
+    // From %{bytes: n, percent: p}, at least one of them set
+    pub(super) fn decode(value: Term) -> Option<Level> {
+        let bytes = options::get::<u64>(value, atoms::bytes()).ok()?;
+        let percent = options::get_with(value, atoms::percent(), |percent| {
+            percent
+                .decode::<f64>()
+                .or_else(|_| percent.decode::<u64>().map(|n| n as f64))
+                .ok()
+                .filter(|p| (0.0..=100.0).contains(p))
+        })
+        .ok()?;
+        if bytes.is_none() && percent.is_none() {
+            return None;
+        }
+        Some(Level { bytes, percent })
+    }
+
+    // Helper: Each part in bytes of `total`
+    fn parts(&self, total: u64) -> (f64, f64) {
+        (
+            self.bytes.unwrap_or(0) as f64,
+            self.percent.unwrap_or(0.0) * total as f64 / 100.0,
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Alert {
+    below: Level,
+    hysteresis: Level,
+    debounce: u32,
+    state: State,
+    // Samples in a row that called for the other state
+    streak: u32,
+}
+
+impl Alert {
+    pub(super) fn new(below: Level, hysteresis: Level, debounce: u32) -> Alert {
+        Alert {
+            below,
+            hysteresis,
+            debounce: debounce.max(1),
+            state: State::Ok,
+            streak: 0,
+        }
+    }
+
+    pub(super) fn state(&self) -> State {
+        self.state
+    }
+
+    // Takes in a sample; the new state if it changed
+    pub(super) fn update(&mut self, available: u64, total: u64) -> Option<State> {
+        let (bytes, percent) = self.below.parts(total);
+        let threshold = bytes.max(percent);
+        let (bytes, percent) = self.hysteresis.parts(total);
+        let available = available as f64;
+        let (changes, next) = match self.state {
+            State::Ok => (available < threshold, State::Low),
+            State::Low => (available >= threshold + bytes + percent, State::Ok),
+        };
+        if !changes {
+            self.streak = 0;
+            return None;
+        }
+        self.streak += 1;
+        if self.streak < self.debounce {
+            return None;
+        }
+        self.streak = 0;
+        self.state = next;
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(bytes: Option<u64>, percent: Option<f64>) -> Level {
+        Level { bytes, percent }
+    }
+
+    // Helper: The changes over samples of `available` bytes out of 1000
+    fn run(alert: &mut Alert, samples: &[u64]) -> Vec<(usize, State)> {
+        samples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, available)| alert.update(*available, 1000).map(|state| (i, state)))
+            .collect()
+    }
+
+    #[test]
+    fn changes_when_either_threshold_is_breached() {
+        let mut alert = Alert::new(level(Some(100), Some(10.0)), Level::default(), 1);
+        assert_eq!(alert.update(500, 1000), None);
+        // Below 10 % only
+        assert_eq!(alert.update(99, 10_000), Some(State::Low));
+        assert_eq!(alert.update(99, 10_000), None);
+        assert_eq!(alert.update(200, 1000), Some(State::Ok));
+        // Below 100 bytes only
+        assert_eq!(alert.update(50, 100), Some(State::Low));
+        assert_eq!(alert.state(), State::Low);
+    }
+
+    #[test]
+    fn hysteresis_keeps_a_hovering_level_from_flapping() {
+        let hovering = [110, 95, 105, 98, 102, 99, 130, 151, 140, 90];
+        let mut alert = Alert::new(level(Some(100), None), Level::default(), 1);
+        assert_eq!(run(&mut alert, &hovering).len(), 7);
+        // 100 bytes plus 5 % of 1000
+        let mut alert = Alert::new(level(Some(100), None), level(None, Some(5.0)), 1);
+        assert_eq!(
+            run(&mut alert, &hovering),
+            vec![(1, State::Low), (7, State::Ok), (9, State::Low)]
+        );
+        let mut alert = Alert::new(level(None, Some(10.0)), level(Some(10), Some(2.0)), 1);
+        assert_eq!(
+            run(&mut alert, &hovering),
+            vec![(1, State::Low), (6, State::Ok), (9, State::Low)]
+        );
+    }
+
+    #[test]
+    fn debounce_needs_samples_in_a_row() {
+        let mut alert = Alert::new(level(Some(100), None), Level::default(), 3);
+        let samples = [90, 90, 200, 90, 90, 90, 90, 200, 200, 90, 200, 200, 200];
+        assert_eq!(
+            run(&mut alert, &samples),
+            vec![(5, State::Low), (12, State::Ok)]
+        );
+        assert_eq!(alert.state(), State::Ok);
+    }
+
+    #[test]
+    fn hysteresis_and_debounce_combine() {
+        let mut alert = Alert::new(level(Some(100), None), level(Some(50), None), 2);
+        let samples = [99, 120, 99, 99, 140, 160, 120, 160, 160, 99];
+        assert_eq!(
+            run(&mut alert, &samples),
+            vec![(3, State::Low), (8, State::Ok)]
+        );
+    }
+}
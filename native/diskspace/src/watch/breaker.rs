@@ -0,0 +1,145 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// A circuit breaker for path watchers with a timeout, so that a path on a
+// dead NFS server doesn't tie up a worker for the whole timeout on every
+// tick. After max_consecutive_timeouts stats in a row have timed out, the
+// path is unresponsive: the watcher backs off to twice the interval, then
+// twice that and so on up to backoff_max_ms, each tick probing the path
+// (the half-open state of a circuit breaker). The first probe that doesn't
+// time out makes it responsive again, back on the interval. reset skips the
+// wait for the next probe.
+//
+// Other errors, such as a path gone missing, are quick and count as
+// responses: they end a run of timeouts.
+
+use crate::atoms;
+use rustler::Atom;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Circuit {
+    Responsive,
+    Unresponsive,
+}
+
+impl Circuit {
+    pub(super) fn atom(self) -> Atom {
+        match self {
+            Circuit::Responsive => atoms::responsive(),
+            Circuit::Unresponsive => atoms::unresponsive(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Breaker {
+    max_timeouts: u32,
+    ceiling: Duration,
+    // Timeouts in a row
+    timeouts: u32,
+    // While unresponsive, the time until the next probe
+    backoff: Option<Duration>,
+}
+
+impl Breaker {
+    pub(super) fn new(max_timeouts: u32, ceiling: Duration) -> Breaker {
+        Breaker {
+            max_timeouts: max_timeouts.max(1),
+            ceiling,
+            timeouts: 0,
+            backoff: None,
+        }
+    }
+
+    pub(super) fn circuit(&self) -> Circuit {
+        match self.backoff {
+            Some(_) => Circuit::Unresponsive,
+            None => Circuit::Responsive,
+        }
+    }
+
+    pub(super) fn timeouts(&self) -> u32 {
+        self.timeouts
+    }
+
+    // While unresponsive, the time from one probe to the next
+    pub(super) fn backoff(&self) -> Option<Duration> {
+        self.backoff
+    }
+
+    // Takes in whether a stat timed out, on a watcher of `interval`; the new
+    // circuit if it changed
+    pub(super) fn update(&mut self, timed_out: bool, interval: Duration) -> Option<Circuit> {
+        let before = self.circuit();
+        if !timed_out {
+            self.timeouts = 0;
+            self.backoff = None;
+        } else {
+            self.timeouts = self.timeouts.saturating_add(1);
+            if let Some(backoff) = self.backoff {
+                self.backoff = Some(backoff.saturating_mul(2).min(self.ceiling));
+            } else if self.timeouts >= self.max_timeouts {
+                self.backoff = Some(interval.saturating_mul(2).min(self.ceiling));
+            }
+        }
+        Some(self.circuit()).filter(|circuit| *circuit != before)
+    }
+
+    // Forgets the timeouts so far, making the path responsive again; whether
+    // it was unresponsive
+    pub(super) fn reset(&mut self) -> bool {
+        let unresponsive = self.backoff.is_some();
+        self.timeouts = 0;
+        self.backoff = None;
+        unresponsive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn opens_after_timeouts_in_a_row_and_backs_off() {
+        let mut breaker = Breaker::new(3, 10 * SECOND);
+        assert_eq!(breaker.update(true, SECOND), None);
+        assert_eq!(breaker.update(true, SECOND), None);
+        // A quick error ends the run
+        assert_eq!(breaker.update(false, SECOND), None);
+        assert_eq!(breaker.update(true, SECOND), None);
+        assert_eq!(breaker.update(true, SECOND), None);
+        assert_eq!(breaker.update(true, SECOND), Some(Circuit::Unresponsive));
+        assert_eq!(breaker.backoff(), Some(2 * SECOND));
+        let backoffs: Vec<Option<Duration>> = (0..4)
+            .map(|_| {
+                assert_eq!(breaker.update(true, SECOND), None);
+                breaker.backoff()
+            })
+            .collect();
+        assert_eq!(
+            backoffs,
+            [
+                Some(4 * SECOND),
+                Some(8 * SECOND),
+                Some(10 * SECOND),
+                Some(10 * SECOND)
+            ]
+        );
+        assert_eq!(breaker.timeouts(), 7);
+    }
+
+    #[test]
+    fn closes_on_the_first_response_or_a_reset() {
+        let mut breaker = Breaker::new(1, 60 * SECOND);
+        assert_eq!(breaker.update(true, SECOND), Some(Circuit::Unresponsive));
+        assert_eq!(breaker.update(false, SECOND), Some(Circuit::Responsive));
+        assert_eq!(breaker.backoff(), None);
+        breaker.update(true, SECOND);
+        assert!(breaker.reset());
+        assert!(!breaker.reset());
+        assert_eq!(breaker.circuit(), Circuit::Responsive);
+    }
+}
@@ -0,0 +1,92 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The shape of a watcher's messages, from message_format. By default they
+// are tuples such as {:disk_space_sample, ref, sample}, tagged by their first
+// element. With :map, each is instead %{event, type, ref, data}: `event`
+// being the tag without its disk_space_ prefix, `type` the transition of an
+// alert or the kind of an event, nil for the rest, and `data` the last
+// element of the tuple. :struct makes the same map a %DiskSpace.Event{}.
+
+use crate::atoms;
+use rustler::{Atom, Encoder, Env, NifResult, Term};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Format {
+    Tuple,
+    Map,
+    Struct,
+}
+
+impl Format {
+    pub(super) fn decode(value: Term) -> Option<Format> {
+        let atom = value.decode::<Atom>().ok()?;
+        if atom == atoms::tuple() {
+            Some(Format::Tuple)
+        } else if atom == atoms::map() {
+            Some(Format::Map)
+        } else if atom == atoms::struct_() {
+            Some(Format::Struct)
+        } else {
+            None
+        }
+    }
+
+    // The message tagged `tag`, with the transition or kind `type_` if any
+    pub(super) fn message<'a>(
+        self,
+        env: Env<'a>,
+        tag: Atom,
+        reference: Term<'a>,
+        type_: Option<Atom>,
+        data: Term<'a>,
+    ) -> Term<'a> {
+        match self {
+            Format::Tuple => match type_ {
+                Some(type_) => (tag, reference, type_, data).encode(env),
+                None => (tag, reference, data).encode(env),
+            },
+            Format::Map | Format::Struct => self
+                .encode_map(env, tag, reference, type_, data)
+                .unwrap_or_else(|_| atoms::error().to_term(env)),
+        }
+    }
+
+    // Helper: %{event, type, ref, data}, with __struct__ for :struct
+    fn encode_map<'a>(
+        self,
+        env: Env<'a>,
+        tag: Atom,
+        reference: Term<'a>,
+        type_: Option<Atom>,
+        data: Term<'a>,
+    ) -> NifResult<Term<'a>> {
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::event().to_term(env), event(tag))?
+            .map_put(atoms::type_().to_term(env), type_)?
+            .map_put(atoms::ref_().to_term(env), reference)?
+            .map_put(atoms::data().to_term(env), data)?;
+        match self {
+            Format::Struct => map.map_put(
+                atoms::struct_().to_term(env),
+                atoms::event_struct().to_term(env),
+            ),
+            _ => Ok(map),
+        }
+    }
+}
+
+// Helper: The event of a message tagged `tag`
+fn event(tag: Atom) -> Atom {
+    if tag == atoms::disk_space_sample() {
+        atoms::sample()
+    } else if tag == atoms::disk_space_alert() {
+        atoms::alert()
+    } else if tag == atoms::disk_space_event() {
+        atoms::event()
+    } else if tag == atoms::disk_space_report() {
+        atoms::report()
+    } else {
+        tag
+    }
+}
@@ -0,0 +1,68 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The last history_size successful samples of a watcher, for
+// watcher_history, as {at, available, used}: 24 bytes each, allocated up
+// front, so a watcher never holds more than 24 * history_size bytes of them.
+// Each entry carries its own timestamp rather than a slot per tick, so the
+// history stays meaningful whatever the interval between samples.
+
+use std::collections::VecDeque;
+
+// 1.5 MiB of entries
+pub(super) const MAX_SIZE: usize = 65_536;
+
+pub(super) type Entry = (u64, u64, u64);
+
+#[derive(Debug)]
+pub(super) struct History {
+    entries: VecDeque<Entry>,
+    size: usize,
+}
+
+impl History {
+    pub(super) fn new(size: usize) -> History {
+        History {
+            entries: VecDeque::with_capacity(size),
+            size,
+        }
+    }
+
+    // Adds a sample taken `at` (milliseconds since the Unix epoch), dropping
+    // the oldest one once full
+    pub(super) fn push(&mut self, at: u64, available: u64, used: u64) {
+        if self.size == 0 {
+            return;
+        }
+        if self.entries.len() == self.size {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((at, available, used));
+    }
+
+    // The last `count` entries, oldest first
+    pub(super) fn last(&self, count: usize) -> Vec<Entry> {
+        let skip = self.entries.len().saturating_sub(count);
+        self.entries.iter().skip(skip).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_entries_oldest_first() {
+        let mut history = History::new(3);
+        assert_eq!(history.last(5), vec![]);
+        for at in 1..=5 {
+            history.push(at, 100 - at, at);
+        }
+        assert_eq!(history.last(5), vec![(3, 97, 3), (4, 96, 4), (5, 95, 5)]);
+        assert_eq!(history.last(1), vec![(5, 95, 5)]);
+        assert_eq!(history.last(0), vec![]);
+        let mut none = History::new(0);
+        none.push(1, 2, 3);
+        assert_eq!(none.last(1), vec![]);
+    }
+}
@@ -0,0 +1,122 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The filesystem behind a watched path, as the device of the path itself:
+// st_dev on Unix, the volume serial number on Windows. A volume unmounted
+// from under the path leaves it on the parent filesystem, whose numbers the
+// watcher would otherwise go on reporting as if nothing happened.
+//
+// The watcher is bound to the filesystem of its first successful sample, and
+// sends {:disk_space_event, ref, :filesystem_changed, %{old, new}} each time
+// the device it sees changes from one sample to the next. With
+// on_filesystem_change: :follow, it binds to the new one right away; with
+// :hold_alerts, it stays bound to the old one, holding back alerts while it
+// sees another, until watcher_ack binds it to the one it sees or the old one
+// is back.
+
+use crate::atoms;
+use crate::du::meta;
+use rustler::{Atom, Term};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug)]
+pub(super) struct Identity {
+    hold: bool,
+    bound: Option<u64>,
+    seen: Option<u64>,
+}
+
+impl Identity {
+    // From on_filesystem_change: :follow or :hold_alerts
+    pub(super) fn decode(value: Term) -> Option<Identity> {
+        let atom = value.decode::<Atom>().ok()?;
+        if atom == atoms::follow() {
+            Some(Identity::new(false))
+        } else if atom == atoms::hold_alerts() {
+            Some(Identity::new(true))
+        } else {
+            None
+        }
+    }
+
+    pub(super) fn new(hold: bool) -> Identity {
+        Identity {
+            hold,
+            bound: None,
+            seen: None,
+        }
+    }
+
+    // Takes in the device of a successful sample; the old and new device if
+    // it changed since the last one
+    pub(super) fn update(&mut self, device: u64) -> Option<(u64, u64)> {
+        let previous = self.seen.replace(device);
+        if self.bound.is_none() || !self.hold {
+            self.bound = Some(device);
+        }
+        previous
+            .filter(|previous| *previous != device)
+            .map(|previous| (previous, device))
+    }
+
+    pub(super) fn bound(&self) -> Option<u64> {
+        self.bound
+    }
+
+    // Whether alerts are held back, being on another filesystem than bound to
+    pub(super) fn held(&self) -> bool {
+        self.seen != self.bound
+    }
+
+    // Binds to the filesystem last seen
+    pub(super) fn ack(&mut self) {
+        self.bound = self.seen;
+    }
+}
+
+// The device of the filesystem `path` is on, following symlinks
+pub(super) fn device(path: &Path) -> Option<u64> {
+    let metadata = fs::metadata(path).ok()?;
+    meta::file_id(&meta::native(path), &metadata, true).map(|id| id.dev)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_filesystem_by_default() {
+        let mut identity = Identity::new(false);
+        assert_eq!(identity.update(1), None);
+        assert_eq!(identity.update(1), None);
+        assert_eq!(identity.update(2), Some((1, 2)));
+        assert_eq!(identity.bound(), Some(2));
+        assert!(!identity.held());
+        assert_eq!(identity.update(2), None);
+    }
+
+    #[test]
+    fn holds_alerts_until_acknowledged_or_back() {
+        let mut identity = Identity::new(true);
+        identity.update(1);
+        assert_eq!(identity.update(2), Some((1, 2)));
+        assert!(identity.held());
+        assert_eq!(identity.bound(), Some(1));
+        // Remounted
+        assert_eq!(identity.update(1), Some((2, 1)));
+        assert!(!identity.held());
+        identity.update(3);
+        identity.ack();
+        assert_eq!(identity.bound(), Some(3));
+        assert!(!identity.held());
+    }
+
+    #[test]
+    fn tells_the_device_of_a_path() {
+        let dir = std::env::temp_dir();
+        assert!(device(&dir).is_some());
+        assert_eq!(device(&dir), device(&dir.join(".")));
+        assert_eq!(device(&dir.join("disk_space_missing_entry")), None);
+    }
+}
@@ -0,0 +1,901 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Watchers: a path statted every interval_ms on the worker pool, sending
+// {:disk_space_sample, ref, %{path, at, result}} to a process, `result` being
+// what stat/2 returns, so a path that is missing or unmounted for a while just
+// yields error samples. The watcher stops once unwatch is called, the last
+// handle to it is garbage collected, or the process has exited.
+//
+// With alert_below, samples are only sent on crossing the thresholds (with
+// hysteresis and debounce_samples taken into account), as
+// {:disk_space_alert, ref, :low | :recovered, sample}; see alert.rs. As
+// nothing is sent in between, a process that has exited is then only noticed
+// at the next alert. The same goes for samples left out by notify: :on_change
+// or {:on_change_by, bytes}; see notify.rs. Either way, the samples not sent
+// still count towards the history, the trend and the alert state.
+//
+// Ticks follow the monotonic clock, aligned or jittered as asked for; see
+// schedule.rs. The timer thread hands each tick to the pool as it comes due,
+// and the tick schedules the next once done, so a watcher is never sampled
+// twice at once and ticks that a slow stat overran are skipped; see
+// timer.rs. A tick the pool has no room to queue is skipped too.
+// set_interval changes the interval of a running watcher from the next tick
+// on, rescheduling the tick it waits for.
+//
+// Every sample, alert and event carries the watcher's labels, kept in
+// external term format like the reference, and is shaped as message_format
+// asks for; see format.rs.
+//
+// watcher_pause stops the ticks without stopping the watcher, keeping its
+// state for watcher_resume, which takes a sample right away.
+//
+// Path watchers also check that the path is still on the filesystem they
+// started out on, sending an event when it isn't; see identity.rs. Given a
+// timeout, they back off from a path whose stats keep timing out, sending an
+// event as it becomes unresponsive and once it is back; see breaker.rs.
+//
+// Each sample also carries the fill rate and time to full estimated from the
+// recent ones; see trend.rs. The last ones are kept for watcher_history;
+// see history.rs.
+//
+// watch_all_mounts starts the same kind of watcher for the whole mount
+// table, sending one report per tick; see report.rs.
+//
+// rustler's init! offers no unload callback to stop the threads from, so the
+// timer's heap and each tick on the pool instead hold the Watch as a resource
+// of this library: the BEAM doesn't unload a NIF library while resources of
+// it are alive, which keeps the code of both around while a watcher runs.
+//
+// The running watchers are registered in WATCHERS for list_watchers, each
+// removed again as it stops. The registry lives in the library's
+// statics, as does everything else here: init! offers no upgrade callback
+// either, which makes the BEAM refuse to load a new version of the library
+// while old code still has this one loaded, so there is never a second
+// registry that watchers would have to be carried over to. For a hot upgrade,
+// export_watchers instead stops them all, waiting for the ticks on the pool
+// so that no sample of theirs comes after, and hands back what it takes to
+// start them again: their options, with the interval as last set and the
+// time of the tick each was waiting for as start_at, which import_watchers
+// in the new code passes back in along with the same references.
+
+use crate::mounts::filter::{self, Filter};
+use crate::mounts::Mount;
+use crate::{
+    atoms, encode_stat, get_path_from_term, make_errno_error_tuple, make_error_tuple,
+    make_error_tuple3, options, path_from_cstring, path_to_term, reshape_error, stat_path, Space,
+    StatError,
+};
+use crate::{batch, config, pool, telemetry};
+use alert::{Alert, Level};
+use breaker::{Breaker, Circuit};
+use format::Format;
+use history::History;
+use identity::Identity;
+use notify::Notify;
+use rustler::env::OwnedEnv;
+use rustler::{Atom, Encoder, Env, LocalPid, NifResult, ResourceArc, Term};
+use schedule::{Jitter, Schedule};
+use std::ffi::CString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use trend::{Estimate, Trend};
+
+mod alert;
+mod breaker;
+mod format;
+mod history;
+mod identity;
+mod notify;
+mod report;
+mod schedule;
+mod timer;
+mod trend;
+
+static WATCHERS: LazyLock<Mutex<Vec<ResourceArc<Watch>>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+// How long export_watchers waits for ticks on the pool, in all
+const EXPORT_WAIT: Duration = Duration::from_secs(5);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// A watcher, shared by its thread, its handles and the registry
+pub(crate) struct Watch {
+    target: Target,
+    dest: LocalPid,
+    // In external term format, so as not to depend on the process that
+    // started the watcher
+    reference: Vec<u8>,
+    labels: Option<Vec<u8>>,
+    format: Format,
+    // As given, for export_watchers
+    opts: Vec<u8>,
+    schedule: Schedule,
+    started: Instant,
+    control: Mutex<Control>,
+    // Notified as a tick is done
+    idle: Condvar,
+    handles: AtomicUsize,
+    status: Mutex<Status>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for Watch {}
+
+// What a watcher samples
+enum Target {
+    Path {
+        path: CString,
+        path_buf: PathBuf,
+        // Of each stat, as for stat_fs_many
+        timeout: Option<Duration>,
+    },
+    // The mounts that survive `filters`, statted as stat_fs_many would
+    Mounts {
+        filters: Vec<Filter>,
+        stats: batch::Options,
+    },
+}
+
+// When the watcher samples next
+struct Control {
+    stopped: bool,
+    paused: bool,
+    // Resumed since the last tick, which makes the next one right away
+    resumed: bool,
+    interval: Duration,
+    jitter: Jitter,
+    // The tick last taken, and the one that the timer waits for, without
+    // their jitter
+    last: Option<Instant>,
+    next: Option<Instant>,
+    // Of the schedule, bumped each time the next tick is set
+    generation: u64,
+    // While a tick is on the pool
+    busy: bool,
+    // While the path is unresponsive, the time from one tick to the next,
+    // instead of the interval
+    backoff: Option<Duration>,
+}
+
+// What watcher_info reports on the samples so far
+struct Status {
+    samples: u64,
+    // The last sample and the last one with an error, in external term format
+    last_sample: Option<Vec<u8>>,
+    last_error: Option<Vec<u8>>,
+    alert: Option<Alert>,
+    trend: Trend,
+    history: History,
+    notify: Notify,
+    identity: Identity,
+    breaker: Breaker,
+    // As of the last sample without an error
+    estimate: Estimate,
+    // Mount watchers only: the mount table as of the last report
+    mounts: Option<Vec<Mount>>,
+}
+
+// The handle watch returns; the watcher stops with the last of them
+pub(crate) struct Watcher {
+    watch: ResourceArc<Watch>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for Watcher {}
+
+impl Watcher {
+    fn new(watch: &ResourceArc<Watch>) -> ResourceArc<Watcher> {
+        watch.handles.fetch_add(1, Ordering::SeqCst);
+        ResourceArc::new(Watcher {
+            watch: watch.clone(),
+        })
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        if self.watch.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.watch.stop();
+        }
+    }
+}
+
+struct Options {
+    interval: Duration,
+    schedule: Schedule,
+    dest: Option<LocalPid>,
+    alert: Option<Alert>,
+    trend: Trend,
+    history: History,
+    notify: Notify,
+    identity: Identity,
+    breaker: Breaker,
+    labels: Option<Vec<u8>>,
+    format: Format,
+    paused: bool,
+}
+
+impl Options {
+    fn decode(opts: Term) -> Result<Options, Atom> {
+        Ok(Options {
+            interval: options::get_with(opts, atoms::interval_ms(), |value| {
+                value.decode::<u64>().ok().filter(|ms| *ms > 0)
+            })?
+            .map_or(Duration::from_secs(1), Duration::from_millis),
+            schedule: Schedule::decode(opts)?,
+            dest: options::get(opts, atoms::dest())?,
+            alert: match options::get_with(opts, atoms::alert_below(), Level::decode)? {
+                Some(below) => Some(Alert::new(
+                    below,
+                    options::get_with(opts, atoms::hysteresis(), Level::decode)?
+                        .unwrap_or_default(),
+                    options::get_with(opts, atoms::debounce_samples(), |value| {
+                        value.decode::<u32>().ok().filter(|k| *k > 0)
+                    })?
+                    .unwrap_or(1),
+                )),
+                None => None,
+            },
+            trend: Trend::new(
+                options::get_with(opts, atoms::trend_window_ms(), |value| {
+                    value.decode::<u64>().ok().filter(|ms| *ms > 0)
+                })?
+                .map_or(Duration::from_secs(600), Duration::from_millis),
+                options::get_with(opts, atoms::trend_min_samples(), |value| {
+                    value.decode::<usize>().ok().filter(|n| *n >= 2)
+                })?
+                .unwrap_or(3),
+            ),
+            history: History::new(
+                options::get_with(opts, atoms::history_size(), |value| {
+                    value
+                        .decode::<usize>()
+                        .ok()
+                        .filter(|n| *n <= history::MAX_SIZE)
+                })?
+                .unwrap_or(256),
+            ),
+            notify: options::get_with(opts, atoms::notify(), Notify::decode)?
+                .unwrap_or_else(|| Notify::new(None)),
+            identity: options::get_with(opts, atoms::on_filesystem_change(), Identity::decode)?
+                .unwrap_or_else(|| Identity::new(false)),
+            breaker: Breaker::new(
+                options::get_with(opts, atoms::max_consecutive_timeouts(), |value| {
+                    value.decode::<u32>().ok().filter(|n| *n > 0)
+                })?
+                .unwrap_or(3),
+                options::get_with(opts, atoms::backoff_max_ms(), |value| {
+                    value.decode::<u64>().ok().filter(|ms| *ms > 0)
+                })?
+                .map_or(Duration::from_secs(600), Duration::from_millis),
+            ),
+            labels: options::get_with(opts, atoms::labels(), |value| {
+                Some(value.to_binary().as_slice().to_vec())
+            })?,
+            format: options::get_with(opts, atoms::message_format(), Format::decode)?
+                .unwrap_or(Format::Tuple),
+            paused: options::get(opts, atoms::paused())?.unwrap_or(false),
+        })
+    }
+}
+
+#[rustler::nif]
+fn watch_nif<'a>(
+    env: Env<'a>,
+    path_term: Term<'a>,
+    opts: Term<'a>,
+    reference: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let Some((path, path_buf)) = get_path_from_term(env, path_term)
+        .ok()
+        .and_then(|path| path_from_cstring(&path).map(|path_buf| (path, path_buf)))
+    else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    let timeout = match options::get::<u64>(opts, atoms::timeout()) {
+        Ok(timeout) => timeout.map(Duration::from_millis).or_else(config::timeout),
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let target = Target::Path {
+        path,
+        path_buf,
+        timeout,
+    };
+    start(env, target, opts, reference)
+}
+
+#[rustler::nif]
+fn watch_all_mounts_nif<'a>(
+    env: Env<'a>,
+    opts: Term<'a>,
+    reference: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let target = options::get_with(opts, atoms::exclude(), filter::decode).and_then(|filters| {
+        Ok(Target::Mounts {
+            filters: filters
+                .or_else(config::exclude)
+                .unwrap_or_else(|| vec![Filter::Pseudo]),
+            stats: batch::Options::decode(opts)?,
+        })
+    });
+    match target {
+        Ok(target) => start(env, target, opts, reference),
+        Err(key) => make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    }
+}
+
+// Helper: Starts a watcher of `target`, returning {:ok, watcher}
+fn start<'a>(
+    env: Env<'a>,
+    target: Target,
+    opts: Term<'a>,
+    reference: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let options = match Options::decode(opts) {
+        Ok(options) => options,
+        Err(key) => return make_error_tuple3(env, atoms::invalid_option(), key.to_term(env)),
+    };
+    let watch = ResourceArc::new(Watch {
+        target,
+        dest: options.dest.unwrap_or_else(|| env.pid()),
+        reference: reference.to_binary().as_slice().to_vec(),
+        labels: options.labels,
+        format: options.format,
+        opts: opts.to_binary().as_slice().to_vec(),
+        started: Instant::now(),
+        control: Mutex::new(Control {
+            stopped: false,
+            paused: options.paused,
+            resumed: false,
+            interval: options.interval,
+            jitter: options.schedule.jitter(),
+            last: None,
+            next: None,
+            generation: 0,
+            busy: false,
+            backoff: None,
+        }),
+        idle: Condvar::new(),
+        schedule: options.schedule,
+        handles: AtomicUsize::new(0),
+        status: Mutex::new(Status {
+            samples: 0,
+            last_sample: None,
+            last_error: None,
+            alert: options.alert,
+            trend: options.trend,
+            history: options.history,
+            notify: options.notify,
+            identity: options.identity,
+            breaker: options.breaker,
+            estimate: Estimate::default(),
+            mounts: None,
+        }),
+    });
+    let watcher = Watcher::new(&watch);
+    // Registered first, so that stopping always removes it after
+    lock(&WATCHERS).push(watch.clone());
+    if let Err(e) = schedule(&watch) {
+        return make_errno_error_tuple(env, atoms::watch_failed(), e);
+    }
+    Ok((atoms::ok(), watcher).encode(env))
+}
+
+// Stopping is idempotent
+#[rustler::nif]
+fn unwatch_nif(watcher: ResourceArc<Watcher>) -> Atom {
+    watcher.watch.stop();
+    atoms::ok()
+}
+
+// The running watchers, each with a handle of its own
+#[rustler::nif]
+fn list_watchers_nif(env: Env) -> NifResult<Term> {
+    let watches = lock(&WATCHERS).clone();
+    watches
+        .iter()
+        .filter(|watch| !watch.stopped())
+        .map(|watch| watch.encode_info(env, Watcher::new(watch)))
+        .collect::<NifResult<Vec<Term>>>()
+        .map(|infos| infos.encode(env))
+}
+
+// From the next tick on; also on a stopped watcher, to no effect
+#[rustler::nif]
+fn set_interval_nif(watcher: ResourceArc<Watcher>, interval_ms: u64) -> Atom {
+    lock(&watcher.watch.control).interval = Duration::from_millis(interval_ms.max(1));
+    // A watcher that fails to reschedule has stopped
+    let _ = schedule(&watcher.watch);
+    atoms::ok()
+}
+
+// Helper: Hands the tick after the last one, or the first, to the timer,
+// unless stopped or a tick is on the pool, which does so once done; stops
+// the watcher if the timer can't be started
+fn schedule(watch: &ResourceArc<Watch>) -> io::Result<()> {
+    let (at, generation) = {
+        let mut control = lock(&watch.control);
+        if control.stopped || control.paused || control.busy {
+            return Ok(());
+        }
+        let wall = now();
+        let now = Instant::now();
+        let (tick, delay) = if std::mem::take(&mut control.resumed) {
+            (now, Duration::ZERO)
+        } else {
+            let tick = watch.next_tick(&control, now, wall);
+            (tick, control.jitter.draw())
+        };
+        control.next = Some(tick);
+        control.generation += 1;
+        (tick + delay, control.generation)
+    };
+    timer::add(at, watch.clone(), generation).inspect_err(|_| watch.stop())
+}
+
+// Helper: Called by the timer as generation `generation` of the schedule
+// comes due, unless rescheduled, paused or stopped since
+fn fire(watch: &ResourceArc<Watch>, generation: u64) {
+    let tick = {
+        let mut control = lock(&watch.control);
+        if control.stopped || control.paused || control.busy || control.generation != generation {
+            return;
+        }
+        let Some(tick) = control.next.take() else {
+            return;
+        };
+        control.busy = true;
+        tick
+    };
+    let running = watch.clone();
+    let submitted = pool::submit(move || {
+        let sample = || match &running.target {
+            Target::Path {
+                path,
+                path_buf,
+                timeout,
+            } => running.sample(path, path_buf, *timeout),
+            Target::Mounts { filters, stats } => running.report(filters, stats),
+        };
+        if !telemetry::timed(telemetry::Op::WatcherTick, sample, |_| None) {
+            running.stop();
+        }
+        done(&running, tick);
+    });
+    if let Err(refused) = submitted {
+        let reason = match refused {
+            pool::Refused::Overloaded => atoms::overloaded(),
+            pool::Refused::NotStarted(_) => atoms::pool_failed(),
+        };
+        telemetry::record(telemetry::Op::WatcherTick, Duration::ZERO, Some(reason));
+        done(watch, tick);
+    }
+}
+
+// Helper: Schedules the tick after `tick`, taken or skipped
+fn done(watch: &ResourceArc<Watch>, tick: Instant) {
+    {
+        let mut control = lock(&watch.control);
+        control.busy = false;
+        control.last = Some(tick);
+    }
+    watch.idle.notify_all();
+    let _ = schedule(watch);
+}
+
+// Stops every watcher, returning for each %{path, ref, opts}: what it takes
+// to start it again, with path nil for a mount watcher
+#[rustler::nif(schedule = "DirtyIo")]
+fn export_watchers_nif(env: Env) -> NifResult<Term> {
+    let watches = lock(&WATCHERS).clone();
+    for watch in &watches {
+        watch.stop();
+    }
+    let deadline = Instant::now() + EXPORT_WAIT;
+    watches
+        .iter()
+        .map(|watch| watch.export(env, deadline))
+        .collect::<NifResult<Vec<Term>>>()
+        .map(|descriptors| descriptors.encode(env))
+}
+
+#[rustler::nif]
+fn watcher_info_nif(env: Env, watcher: ResourceArc<Watcher>) -> NifResult<Term> {
+    watcher.watch.encode_info(env, watcher.clone())
+}
+
+// Stops sampling until resumed, keeping the watcher's state; a sample being
+// taken may still be sent. Pausing a paused watcher does nothing
+#[rustler::nif]
+fn watcher_pause_nif(watcher: ResourceArc<Watcher>) -> Atom {
+    lock(&watcher.watch.control).paused = true;
+    atoms::ok()
+}
+
+// Samples right away, then on the interval again. Resuming a watcher that
+// isn't paused does nothing
+#[rustler::nif]
+fn watcher_resume_nif(watcher: ResourceArc<Watcher>) -> Atom {
+    {
+        let mut control = lock(&watcher.watch.control);
+        if !control.paused {
+            return atoms::ok();
+        }
+        control.paused = false;
+        control.resumed = true;
+    }
+    // A watcher that fails to reschedule has stopped
+    let _ = schedule(&watcher.watch);
+    atoms::ok()
+}
+
+// Probes the unresponsive watchers right away: the one given, or each one of
+// the path given. Watchers that aren't unresponsive are left alone
+#[rustler::nif]
+fn reset_nif<'a>(env: Env<'a>, target: Term<'a>) -> NifResult<Term<'a>> {
+    let watches = if let Ok(watcher) = target.decode::<ResourceArc<Watcher>>() {
+        vec![watcher.watch.clone()]
+    } else if let Ok(path) = get_path_from_term(env, target) {
+        lock(&WATCHERS)
+            .iter()
+            .filter(|watch| matches!(&watch.target, Target::Path { path: watched, .. } if *watched == path))
+            .cloned()
+            .collect()
+    } else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    for watch in &watches {
+        reset(watch);
+    }
+    Ok(atoms::ok().encode(env))
+}
+
+// Helper: Makes `watch` responsive again, ticking right away unless paused
+fn reset(watch: &ResourceArc<Watch>) {
+    {
+        let mut status = lock(&watch.status);
+        if !status.breaker.reset() {
+            return;
+        }
+        let mut control = lock(&watch.control);
+        control.backoff = None;
+        control.resumed = !control.paused;
+    }
+    // A watcher that fails to reschedule has stopped
+    let _ = schedule(watch);
+}
+
+// Binds a watcher holding back alerts to the filesystem it now sees
+#[rustler::nif]
+fn watcher_ack_nif(watcher: ResourceArc<Watcher>) -> Atom {
+    lock(&watcher.watch.status).identity.ack();
+    atoms::ok()
+}
+
+// The last `count` successful samples as {at, available, used}, oldest first
+#[rustler::nif]
+fn watcher_history_nif(watcher: ResourceArc<Watcher>, count: usize) -> Vec<(u64, u64, u64)> {
+    lock(&watcher.watch.status).history.last(count)
+}
+
+impl Watch {
+    fn stopped(&self) -> bool {
+        lock(&self.control).stopped
+    }
+
+    // :running, :paused or :stopped
+    fn state(&self) -> Atom {
+        let control = lock(&self.control);
+        if control.stopped {
+            atoms::stopped()
+        } else if control.paused {
+            atoms::paused()
+        } else {
+            atoms::running()
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        lock(&self.control).interval
+    }
+
+    // Helper: The tick after the last one, or the first, without its jitter
+    fn next_tick(&self, control: &Control, now: Instant, wall: u64) -> Instant {
+        match (control.last, control.backoff) {
+            (Some(last), Some(backoff)) => (last + backoff).max(now),
+            (Some(last), None) => self.schedule.next(last, control.interval, now, wall),
+            (None, _) => self.schedule.first(control.interval, now, wall),
+        }
+    }
+
+    // Helper: %{path, ref, opts} of a stopped watcher, once its tick on the
+    // pool, if any, is done or `deadline` has passed
+    fn export<'a>(&self, env: Env<'a>, deadline: Instant) -> NifResult<Term<'a>> {
+        let (control, _) = self
+            .idle
+            .wait_timeout_while(
+                lock(&self.control),
+                deadline.saturating_duration_since(Instant::now()),
+                |control| control.busy,
+            )
+            .unwrap_or_else(PoisonError::into_inner);
+        let wall = now();
+        let now = Instant::now();
+        let tick = control
+            .next
+            .unwrap_or_else(|| self.next_tick(&control, now, wall));
+        let start_at = wall + tick.saturating_duration_since(now).as_millis() as u64;
+        let opts = decode_stored(env, &self.opts)
+            .map_put(
+                atoms::interval_ms().to_term(env),
+                control.interval.as_millis() as u64,
+            )?
+            .map_put(atoms::dest().to_term(env), self.dest)?
+            .map_put(atoms::paused().to_term(env), control.paused)?
+            .map_put(atoms::start_at().to_term(env), start_at)?;
+        rustler::types::map::map_new(env)
+            .map_put(atoms::path().to_term(env), self.encode_path(env))?
+            .map_put(atoms::ref_().to_term(env), self.reference(env))?
+            .map_put(atoms::opts().to_term(env), opts)
+    }
+
+    fn stop(&self) {
+        lock(&self.control).stopped = true;
+        timer::remove(self);
+        self.deregister();
+    }
+
+    fn deregister(&self) {
+        lock(&WATCHERS).retain(|watch| !std::ptr::eq(&**watch, self));
+    }
+
+    // Helper: Takes and sends one sample, or just takes it if alert_below or
+    // notify leave it out; false if the process is gone
+    fn sample(&self, path: &CString, path_buf: &Path, timeout: Option<Duration>) -> bool {
+        let stat = match timeout {
+            Some(timeout) => batch::stat_within(path, timeout),
+            None => stat_path(path),
+        };
+        let device = stat.as_ref().ok().and_then(|_| identity::device(path_buf));
+        let at = now();
+        let timed_out = matches!(stat, Err(StatError::Timeout(_)));
+        let (changed, quiet, transition, estimate, circuit, tripped) = {
+            let mut status = lock(&self.status);
+            let tripped = status.breaker.update(timed_out, self.interval());
+            let circuit = status.breaker.circuit();
+            let backoff = status.breaker.backoff();
+            lock(&self.control).backoff = backoff;
+            let tripped = tripped.map(|circuit| (circuit, status.breaker.timeouts(), backoff));
+            let changed = device.and_then(|device| status.identity.update(device));
+            // The samples so far are of another filesystem
+            if changed.is_some() {
+                status.trend.reset();
+            }
+            let held = status.identity.held();
+            let estimate = match &stat {
+                Ok(space) => {
+                    let since = self.started.elapsed().as_secs_f64();
+                    status.history.push(at, space.available, space.used);
+                    status.estimate = status.trend.update(since, space.used, space.available);
+                    status.estimate
+                }
+                Err(_) => Estimate::default(),
+            };
+            let (quiet, transition) = match (status.alert.as_mut(), &stat) {
+                (Some(alert), Ok(space)) if !held => {
+                    let transition = alert.update(space.available, space.total);
+                    (transition.is_none(), transition)
+                }
+                (Some(_), _) => (true, None),
+                (None, _) => {
+                    let space = stat
+                        .as_ref()
+                        .ok()
+                        .map(|space| (space.available, space.used));
+                    (!status.notify.wants(space), None)
+                }
+            };
+            (changed, quiet, transition, estimate, circuit, tripped)
+        };
+        let failed = stat.is_err();
+        let mut owned_env = OwnedEnv::new();
+        if let Some((old, new)) = changed {
+            let sent = self.send_event(&mut owned_env, atoms::filesystem_changed(), |env, map| {
+                map.map_put(atoms::old().to_term(env), old)?
+                    .map_put(atoms::new().to_term(env), new)
+            });
+            if !sent {
+                return false;
+            }
+        }
+        if let Some((circuit, timeouts, backoff)) = tripped {
+            let sent = self.send_event(&mut owned_env, circuit.atom(), |env, map| match backoff {
+                Some(backoff) => map
+                    .map_put(atoms::timeouts().to_term(env), timeouts)?
+                    .map_put(
+                        atoms::retry_in_ms().to_term(env),
+                        backoff.as_millis() as u64,
+                    ),
+                None => Ok(map),
+            });
+            if !sent {
+                return false;
+            }
+        }
+        if quiet {
+            owned_env.run(|env| {
+                self.record(self.encode_sample(env, at, stat, estimate, circuit), failed)
+            });
+            return true;
+        }
+        let sent = owned_env.send_and_clear(&self.dest, |env| {
+            let sample = self.encode_sample(env, at, stat, estimate, circuit);
+            self.record(sample, failed);
+            match transition {
+                Some(state) => self.message(
+                    env,
+                    atoms::disk_space_alert(),
+                    Some(state.transition()),
+                    sample,
+                ),
+                None => self.message(env, atoms::disk_space_sample(), None, sample),
+            }
+        });
+        sent.is_ok()
+    }
+
+    // Helper: Sends {:disk_space_event, ref, event, details}, `details` being
+    // the map `put` fills in plus the labels; false if the process is gone
+    fn send_event(
+        &self,
+        owned_env: &mut OwnedEnv,
+        event: Atom,
+        put: impl for<'a> FnOnce(Env<'a>, Term<'a>) -> NifResult<Term<'a>>,
+    ) -> bool {
+        let sent = owned_env.send_and_clear(&self.dest, |env| {
+            let details = put(env, rustler::types::map::map_new(env))
+                .and_then(|map| self.put_labels(env, map))
+                .unwrap_or_else(|_| atoms::error().to_term(env));
+            self.message(env, atoms::disk_space_event(), Some(event), details)
+        });
+        sent.is_ok()
+    }
+
+    // Helper: Counts `sample` into the status
+    fn record(&self, sample: Term, failed: bool) {
+        let bytes = sample.to_binary().as_slice().to_vec();
+        let mut status = lock(&self.status);
+        status.samples += 1;
+        if failed {
+            status.last_error = Some(bytes.clone());
+        }
+        status.last_sample = Some(bytes);
+    }
+
+    fn reference<'a>(&self, env: Env<'a>) -> Term<'a> {
+        decode_stored(env, &self.reference)
+    }
+
+    // Helper: The message tagged `tag` in the watcher's format
+    fn message<'a>(
+        &self,
+        env: Env<'a>,
+        tag: Atom,
+        type_: Option<Atom>,
+        data: Term<'a>,
+    ) -> Term<'a> {
+        self.format
+            .message(env, tag, self.reference(env), type_, data)
+    }
+
+    // Helper: Adds the labels, or nil, to `map`
+    fn put_labels<'a>(&self, env: Env<'a>, map: Term<'a>) -> NifResult<Term<'a>> {
+        let labels = self.labels.as_ref().map_or_else(
+            || rustler::types::atom::nil().to_term(env),
+            |bytes| decode_stored(env, bytes),
+        );
+        map.map_put(atoms::labels().to_term(env), labels)
+    }
+
+    // Helper: The watched path, or nil for the mount table
+    fn encode_path<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match &self.target {
+            Target::Path { path_buf, .. } => path_to_term(env, path_buf),
+            Target::Mounts { .. } => rustler::types::atom::nil().to_term(env),
+        }
+    }
+
+    // Helper: %{path, at, result, circuit, fill_rate_bytes_per_sec,
+    // eta_full_seconds}
+    fn encode_sample<'a>(
+        &self,
+        env: Env<'a>,
+        at: u64,
+        stat: Result<Space, StatError>,
+        estimate: Estimate,
+        circuit: Circuit,
+    ) -> Term<'a> {
+        let sample = encode_stat(env, stat).and_then(|result| {
+            rustler::types::map::map_new(env)
+                .map_put(atoms::path().to_term(env), self.encode_path(env))?
+                .map_put(atoms::at().to_term(env), at)?
+                .map_put(atoms::result().to_term(env), reshape_error(env, result))?
+                .map_put(atoms::circuit().to_term(env), circuit.atom())
+                .and_then(|map| self.put_labels(env, map))
+                .and_then(|map| encode_estimate(env, map, estimate))
+        });
+        sample.unwrap_or_else(|_| atoms::error().to_term(env))
+    }
+
+    // Helper: %{watcher, ref, path, interval_ms, dest, running, state, samples,
+    // labels, last_sample, last_error, alert_state, device, alerts_held,
+    // circuit, timeouts, fill_rate_bytes_per_sec, eta_full_seconds}
+    fn encode_info<'a>(&self, env: Env<'a>, watcher: ResourceArc<Watcher>) -> NifResult<Term<'a>> {
+        let status = lock(&self.status);
+        let stored = |bytes: &Option<Vec<u8>>| {
+            bytes.as_ref().map_or_else(
+                || rustler::types::atom::nil().to_term(env),
+                |bytes| decode_stored(env, bytes),
+            )
+        };
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::watcher().to_term(env), watcher)?
+            .map_put(atoms::ref_().to_term(env), self.reference(env))?
+            .map_put(atoms::path().to_term(env), self.encode_path(env))?
+            .map_put(
+                atoms::interval_ms().to_term(env),
+                self.interval().as_millis() as u64,
+            )?
+            .map_put(atoms::dest().to_term(env), self.dest)?
+            .map_put(atoms::running().to_term(env), !self.stopped())?
+            .map_put(atoms::state().to_term(env), self.state())?;
+        self.put_labels(env, map)?
+            .map_put(atoms::samples().to_term(env), status.samples)?
+            .map_put(
+                atoms::last_sample().to_term(env),
+                stored(&status.last_sample),
+            )?
+            .map_put(atoms::last_error().to_term(env), stored(&status.last_error))?
+            .map_put(
+                atoms::alert_state().to_term(env),
+                status.alert.as_ref().map(|alert| alert.state().atom()),
+            )?
+            .map_put(atoms::device().to_term(env), status.identity.bound())?
+            .map_put(atoms::alerts_held().to_term(env), status.identity.held())?
+            .map_put(
+                atoms::circuit().to_term(env),
+                status.breaker.circuit().atom(),
+            )?
+            .map_put(atoms::timeouts().to_term(env), status.breaker.timeouts())
+            .and_then(|map| encode_estimate(env, map, status.estimate))
+    }
+}
+
+// Helper: A term kept in external term format
+fn decode_stored<'a>(env: Env<'a>, bytes: &[u8]) -> Term<'a> {
+    env.binary_to_term(bytes)
+        .map_or_else(|| atoms::error().to_term(env), |(term, _)| term)
+}
+
+// Helper: Adds fill_rate_bytes_per_sec and eta_full_seconds to `map`
+fn encode_estimate<'a>(env: Env<'a>, map: Term<'a>, estimate: Estimate) -> NifResult<Term<'a>> {
+    map.map_put(
+        atoms::fill_rate_bytes_per_sec().to_term(env),
+        estimate.fill_rate,
+    )?
+    .map_put(atoms::eta_full_seconds().to_term(env), estimate.eta_full)
+}
+
+// Helper: Milliseconds since the Unix epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
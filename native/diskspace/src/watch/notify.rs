@@ -0,0 +1,109 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Which samples a watcher sends, from notify: :always, :on_change or
+// {:on_change_by, bytes}. A sample is compared with the last one sent, not
+// the last one taken, so that a slow drift still gets through once it adds
+// up. The first sample is always sent, and so is the first error sample
+// after a successful one and the first successful one after an error.
+
+use crate::atoms;
+use rustler::{Atom, Term};
+
+#[derive(Debug)]
+pub(super) struct Notify {
+    // More bytes than this of change in available or used; None to send all
+    min_change: Option<u64>,
+    // The available and used bytes of the last sample sent, None if it was
+    // an error; None before the first
+    last: Option<Option<(u64, u64)>>,
+}
+
+impl Notify {
+    pub(super) fn decode(value: Term) -> Option<Notify> {
+        let min_change = if let Ok(atom) = value.decode::<Atom>() {
+            if atom == atoms::always() {
+                None
+            } else if atom == atoms::on_change() {
+                Some(0)
+            } else {
+                return None;
+            }
+        } else {
+            let (tag, bytes) = value.decode::<(Atom, u64)>().ok()?;
+            if tag != atoms::on_change_by() {
+                return None;
+            }
+            Some(bytes)
+        };
+        Some(Notify::new(min_change))
+    }
+
+    pub(super) fn new(min_change: Option<u64>) -> Notify {
+        Notify {
+            min_change,
+            last: None,
+        }
+    }
+
+    // Whether to send a sample of `space`, (available, used) or None for an
+    // error; remembers it if so
+    pub(super) fn wants(&mut self, space: Option<(u64, u64)>) -> bool {
+        let wanted = match (self.min_change, self.last) {
+            (None, _) | (_, None) => true,
+            (Some(min), Some(Some((available, used)))) => {
+                space.is_none_or(|(a, u)| a.abs_diff(available) > min || u.abs_diff(used) > min)
+            }
+            (Some(_), Some(None)) => space.is_some(),
+        };
+        if wanted {
+            self.last = Some(space);
+        }
+        wanted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(notify: &mut Notify, samples: &[Option<(u64, u64)>]) -> Vec<usize> {
+        (0..samples.len())
+            .filter(|i| notify.wants(samples[*i]))
+            .collect()
+    }
+
+    #[test]
+    fn always_sends_every_sample() {
+        let samples = [Some((10, 5)), Some((10, 5)), None, None];
+        assert_eq!(run(&mut Notify::new(None), &samples), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn on_change_skips_identical_samples() {
+        let samples = [
+            Some((10, 5)),
+            Some((10, 5)),
+            Some((9, 5)),
+            Some((9, 5)),
+            None,
+            None,
+            Some((9, 5)),
+        ];
+        assert_eq!(run(&mut Notify::new(Some(0)), &samples), [0, 2, 4, 6]);
+    }
+
+    #[test]
+    fn on_change_by_adds_up_a_drift() {
+        let samples = [
+            Some((1000, 0)),
+            Some((990, 10)),
+            Some((980, 20)),
+            Some((970, 30)),
+            Some((960, 40)),
+            Some((1000, 40)),
+        ];
+        // Compared with the first until 30 bytes have gone, then with the fourth
+        assert_eq!(run(&mut Notify::new(Some(25)), &samples), [0, 3, 5]);
+    }
+}
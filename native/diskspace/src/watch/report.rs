@@ -0,0 +1,85 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Mount watchers, from watch_all_mounts: each tick lists the mounts that
+// survive the exclude filters, sends {:mounts_changed, ref, diff} if the
+// table differs from the last tick's, then stats every mount point and sends
+// {:disk_space_report, ref, [mount]}, each mount map as list_mounts returns
+// it plus the `at` and `result` of its stat and the watcher's `labels`. A mount point that fails to
+// stat gets an error result; a mount table that can't be read gets
+// {:disk_space_report, ref, {:error, info}} instead.
+
+use super::{now, Watch};
+use crate::mounts::{self, diff, filter, filter::Filter, Mount};
+use crate::{atoms, batch, make_errno_error_tuple, reshape_error, Space, StatError};
+use rustler::env::OwnedEnv;
+use rustler::{Encoder, Env, NifResult, Term};
+use std::ffi::CString;
+
+impl Watch {
+    // Helper: Takes and sends one report; false if the process is gone
+    pub(super) fn report(&self, filters: &[Filter], stats: &batch::Options) -> bool {
+        let mut table = match mounts::list() {
+            Ok(table) => table,
+            Err(e) => {
+                let sent = OwnedEnv::new().send_and_clear(&self.dest, |env| {
+                    let report = make_errno_error_tuple(env, atoms::list_mounts_failed(), e)
+                        .map_or_else(|_| atoms::error().to_term(env), |r| reshape_error(env, r));
+                    self.record(report, true);
+                    self.message(env, atoms::disk_space_report(), None, report)
+                });
+                return sent.is_ok();
+            }
+        };
+        table.retain(|mount| !filter::excluded(filters, mount));
+        let previous = super::lock(&self.status).mounts.replace(table.clone());
+        if let Some(previous) = previous {
+            let changes = diff::diff(&previous, &table);
+            if changes != diff::Diff::default() {
+                let sent = OwnedEnv::new().send_and_clear(&self.dest, |env| {
+                    let changes = mounts::encode_diff(env, &changes)
+                        .and_then(|changes| self.put_labels(env, changes))
+                        .unwrap_or_else(|_| atoms::error().to_term(env));
+                    self.message(env, atoms::mounts_changed(), None, changes)
+                });
+                if sent.is_err() {
+                    return false;
+                }
+            }
+        }
+        let paths = table
+            .iter()
+            .map(|mount| CString::new(mount.mount_point.as_str()).ok())
+            .collect();
+        let results = batch::stat_all(paths, stats);
+        let failed = results.iter().any(Result::is_err);
+        let at = now();
+        let sent = OwnedEnv::new().send_and_clear(&self.dest, |env| {
+            let report = self
+                .encode_report(env, at, &table, results)
+                .unwrap_or_else(|_| atoms::error().to_term(env));
+            self.record(report, failed);
+            self.message(env, atoms::disk_space_report(), None, report)
+        });
+        sent.is_ok()
+    }
+
+    // Helper: Each mount map with its `at`, `result` and `labels`
+    fn encode_report<'a>(
+        &self,
+        env: Env<'a>,
+        at: u64,
+        table: &[Mount],
+        results: Vec<Result<Space, StatError>>,
+    ) -> NifResult<Term<'a>> {
+        let entries = table
+            .iter()
+            .zip(results)
+            .map(|(mount, stat)| {
+                let entry = batch::encode_entry(env, at, mount, stat)?;
+                self.put_labels(env, entry)
+            })
+            .collect::<NifResult<Vec<Term>>>()?;
+        Ok(entries.encode(env))
+    }
+}
@@ -0,0 +1,234 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// When a watcher samples. Ticks are scheduled on the monotonic clock, so that
+// a jump of the system clock neither stalls a watcher nor makes it race. By
+// default the first tick is right away and each next one an interval after
+// the last; with align_to: :interval the ticks instead fall on the multiples
+// of the interval since the Unix epoch, for time series with clean
+// timestamps. Only the distance to the next multiple is read off the system
+// clock, afresh for each tick, so a jump shifts a single tick at most.
+//
+// With jitter_ms, each tick is put off by its own random delay of up to that
+// many milliseconds, spreading out the stats of watchers that would otherwise
+// all fire at once. The delay doesn't carry over: the tick after is scheduled
+// from where this one would have been without it.
+//
+// With start_at, the first tick is put off until that many milliseconds since
+// the Unix epoch, as import_watchers does to pick up where an exported
+// watcher left off.
+
+use crate::{atoms, options};
+use rustler::{Atom, Term};
+use std::hash::{BuildHasher, RandomState};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Align {
+    None,
+    Interval,
+}
+
+impl Align {
+    pub(super) fn decode(value: Term) -> Option<Align> {
+        let atom = value.decode::<Atom>().ok()?;
+        if atom == atoms::interval() {
+            Some(Align::Interval)
+        } else if atom == atoms::none() {
+            Some(Align::None)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Schedule {
+    align: Align,
+    jitter_ms: u64,
+    start_at: Option<u64>,
+}
+
+impl Schedule {
+    pub(super) fn decode(opts: Term) -> Result<Schedule, Atom> {
+        Ok(Schedule {
+            align: options::get_with(opts, atoms::align_to(), Align::decode)?
+                .unwrap_or(Align::None),
+            jitter_ms: options::get(opts, atoms::jitter_ms())?.unwrap_or(0),
+            start_at: options::get(opts, atoms::start_at())?,
+        })
+    }
+
+    // The first tick, for a watcher started at `now` when the system clock
+    // read `wall` milliseconds since the Unix epoch
+    pub(super) fn first(&self, interval: Duration, now: Instant, wall: u64) -> Instant {
+        let wait = self.start_at.map_or(0, |at| at.saturating_sub(wall));
+        match self.align {
+            Align::None => now + Duration::from_millis(wait),
+            // The first multiple at start_at or after
+            Align::Interval if wait > 0 => aligned(
+                interval,
+                now + Duration::from_millis(wait - 1),
+                wall + wait - 1,
+            ),
+            Align::Interval => aligned(interval, now, wall),
+        }
+    }
+
+    // The tick after `last`, the first one still to come at `now`; ticks
+    // that have gone by already are skipped rather than caught up on
+    pub(super) fn next(
+        &self,
+        last: Instant,
+        interval: Duration,
+        now: Instant,
+        wall: u64,
+    ) -> Instant {
+        match self.align {
+            Align::None => {
+                let behind = now.saturating_duration_since(last).as_nanos();
+                let ticks = behind / interval.as_nanos().max(1) + 1;
+                interval
+                    .checked_mul(u32::try_from(ticks).unwrap_or(u32::MAX))
+                    .and_then(|since| last.checked_add(since))
+                    .unwrap_or(now + interval)
+            }
+            // Waking up a little early on the system clock, which may be
+            // slewed against the monotonic one, mustn't sample twice
+            Align::Interval => {
+                let tick = aligned(interval, now, wall);
+                if tick < last + interval / 2 {
+                    tick + interval
+                } else {
+                    tick
+                }
+            }
+        }
+    }
+
+    pub(super) fn jitter(&self) -> Jitter {
+        Jitter {
+            max_ms: self.jitter_ms,
+            state: RandomState::new().hash_one(Instant::now()) | 1,
+        }
+    }
+}
+
+// Helper: The next multiple of `interval` since the Unix epoch after `wall`,
+// on the monotonic clock
+fn aligned(interval: Duration, now: Instant, wall: u64) -> Instant {
+    let interval = (interval.as_millis() as u64).max(1);
+    now + Duration::from_millis(interval - wall % interval)
+}
+
+// The random delays of one watcher's ticks
+#[derive(Debug)]
+pub(super) struct Jitter {
+    max_ms: u64,
+    // xorshift64*, never 0
+    state: u64,
+}
+
+impl Jitter {
+    // A delay of 0 to max_ms milliseconds
+    pub(super) fn draw(&mut self) -> Duration {
+        if self.max_ms == 0 {
+            return Duration::ZERO;
+        }
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let random = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        Duration::from_millis(random % (self.max_ms + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn schedule(align: Align, jitter_ms: u64) -> Schedule {
+        Schedule {
+            align,
+            jitter_ms,
+            start_at: None,
+        }
+    }
+
+    #[test]
+    fn skips_the_ticks_gone_by() {
+        let schedule = schedule(Align::None, 0);
+        let start = Instant::now();
+        assert_eq!(schedule.first(SECOND, start, 12_345), start);
+        let next = |now| schedule.next(start, SECOND, now, 0);
+        assert_eq!(next(start), start + SECOND);
+        assert_eq!(next(start + SECOND / 2), start + SECOND);
+        assert_eq!(next(start + SECOND), start + 2 * SECOND);
+        assert_eq!(next(start + 3500 * SECOND / 1000), start + 4 * SECOND);
+    }
+
+    #[test]
+    fn aligns_to_multiples_of_the_interval() {
+        let schedule = schedule(Align::Interval, 0);
+        let now = Instant::now();
+        let interval = Duration::from_secs(60);
+        assert_eq!(
+            schedule.first(interval, now, 60_000 * 100 + 15_000),
+            now + Duration::from_secs(45)
+        );
+        // Right on a multiple, the next one is a whole interval away
+        assert_eq!(
+            schedule.next(now, interval, now, 60_000 * 7),
+            now + interval
+        );
+        // A clock that jumped only moves the next tick
+        let later = now + Duration::from_secs(40);
+        assert_eq!(
+            schedule.next(now, interval, later, 60_000 * 3 + 59_000),
+            later + SECOND
+        );
+        // Nor does it sample twice when woken just ahead of a multiple
+        assert_eq!(
+            schedule.next(now, interval, now, 60_000 * 3 + 59_999),
+            now + Duration::from_millis(60_001)
+        );
+    }
+
+    #[test]
+    fn starts_no_earlier_than_start_at() {
+        let now = Instant::now();
+        let interval = Duration::from_secs(60);
+        let at = |align, start_at| {
+            let schedule = Schedule {
+                start_at: Some(start_at),
+                ..schedule(align, 0)
+            };
+            schedule.first(interval, now, 60_000 * 5 + 10_000)
+        };
+        assert_eq!(at(Align::None, 60_000 * 5), now);
+        assert_eq!(at(Align::None, 60_000 * 6), now + Duration::from_secs(50));
+        // Right on a multiple, that one
+        assert_eq!(
+            at(Align::Interval, 60_000 * 6),
+            now + Duration::from_secs(50)
+        );
+        assert_eq!(
+            at(Align::Interval, 60_000 * 6 + 1),
+            now + Duration::from_secs(110)
+        );
+    }
+
+    #[test]
+    fn jitters_within_bounds() {
+        let mut none = schedule(Align::None, 0).jitter();
+        assert_eq!(none.draw(), Duration::ZERO);
+        let mut jitter = schedule(Align::None, 50).jitter();
+        let delays: Vec<Duration> = (0..1000).map(|_| jitter.draw()).collect();
+        assert!(delays
+            .iter()
+            .all(|delay| *delay <= Duration::from_millis(50)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+}
@@ -0,0 +1,139 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The one thread that wakes watchers up. It keeps the next tick of every
+// running watcher in a heap and, as each comes due, fires it, which hands the
+// watcher's sample to the worker pool, so that watchers take no thread of
+// their own however many there are. The thread never stats anything itself,
+// so one hung mount delays no other watcher's tick. Like the pool's workers,
+// it ends on its own once it has had no ticks to wait for for IDLE.
+//
+// Each tick carries the generation of the watcher's schedule it was made
+// for. Rescheduling a watcher, as set_interval does, bumps the generation
+// rather than digging the old tick out of the heap, which then fires to no
+// effect. Stopped watchers are taken out right away, so as not to keep them
+// alive until their next tick.
+
+use super::{lock, Watch};
+use rustler::ResourceArc;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const IDLE: Duration = Duration::from_secs(10);
+
+struct Timer {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+struct State {
+    ticks: BinaryHeap<Tick>,
+    running: bool,
+    // Orders the ticks due at the same instant by when they were added
+    added: u64,
+}
+
+static TIMER: Timer = Timer {
+    state: Mutex::new(State {
+        ticks: BinaryHeap::new(),
+        running: false,
+        added: 0,
+    }),
+    wake: Condvar::new(),
+};
+
+struct Tick {
+    at: Instant,
+    added: u64,
+    watch: ResourceArc<Watch>,
+    generation: u64,
+}
+
+// The earliest first, for the max-heap
+impl Ord for Tick {
+    fn cmp(&self, other: &Tick) -> Ordering {
+        (other.at, other.added).cmp(&(self.at, self.added))
+    }
+}
+
+impl PartialOrd for Tick {
+    fn partial_cmp(&self, other: &Tick) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Tick {
+    fn eq(&self, other: &Tick) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Tick {}
+
+// Fires generation `generation` of `watch`'s schedule at `at`; fails only if
+// the thread wasn't running and couldn't be started
+pub(super) fn add(at: Instant, watch: ResourceArc<Watch>, generation: u64) -> io::Result<()> {
+    let mut state = lock(&TIMER.state);
+    if !state.running {
+        thread::Builder::new()
+            .name("disk_space_timer".to_string())
+            .spawn(run)?;
+        state.running = true;
+    }
+    state.added += 1;
+    let added = state.added;
+    state.ticks.push(Tick {
+        at,
+        added,
+        watch,
+        generation,
+    });
+    TIMER.wake.notify_one();
+    Ok(())
+}
+
+// Takes out the ticks of `watch`
+pub(super) fn remove(watch: &Watch) {
+    lock(&TIMER.state)
+        .ticks
+        .retain(|tick| !std::ptr::eq(&*tick.watch, watch));
+}
+
+fn run() {
+    let mut state = lock(&TIMER.state);
+    loop {
+        let now = Instant::now();
+        match state.ticks.peek().map(|tick| tick.at) {
+            Some(at) if at <= now => {
+                let Some(tick) = state.ticks.pop() else {
+                    continue;
+                };
+                drop(state);
+                super::fire(&tick.watch, tick.generation);
+                state = lock(&TIMER.state);
+            }
+            Some(at) => {
+                state = TIMER
+                    .wake
+                    .wait_timeout(state, at - now)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+            None => {
+                let (next, timeout) = TIMER
+                    .wake
+                    .wait_timeout(state, IDLE)
+                    .unwrap_or_else(PoisonError::into_inner);
+                state = next;
+                if timeout.timed_out() && state.ticks.is_empty() {
+                    state.running = false;
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// The fill rate of a watched filesystem, from the used bytes of its recent
+// samples: those of the last trend_window_ms, up to MAX_SAMPLES of them. The
+// rate is the median of the slopes between every pair of samples (the
+// Theil-Sen estimator), so that a single outlier, such as a big temporary
+// file deleted again right away, barely moves it. With fewer than
+// trend_min_samples samples there is no rate, and without a positive one
+// there is no time to full.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Keeps the pairs to look at to MAX_SAMPLES * (MAX_SAMPLES - 1) / 2
+const MAX_SAMPLES: usize = 256;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(super) struct Estimate {
+    pub(super) fill_rate: Option<f64>,
+    pub(super) eta_full: Option<f64>,
+}
+
+#[derive(Debug)]
+pub(super) struct Trend {
+    window: f64,
+    min_samples: usize,
+    // Seconds since the watcher started, and the used bytes then
+    samples: VecDeque<(f64, u64)>,
+}
+
+impl Trend {
+    pub(super) fn new(window: Duration, min_samples: usize) -> Trend {
+        Trend {
+            window: window.as_secs_f64(),
+            min_samples: min_samples.max(2),
+            samples: VecDeque::new(),
+        }
+    }
+
+    // Takes in a sample taken `at` seconds in, with `used` bytes used and
+    // `available` available, and estimates from the window as it then is
+    pub(super) fn update(&mut self, at: f64, used: u64, available: u64) -> Estimate {
+        self.samples.push_back((at, used));
+        while self.samples.len() > MAX_SAMPLES
+            || self
+                .samples
+                .front()
+                .is_some_and(|(first, _)| at - first > self.window)
+        {
+            self.samples.pop_front();
+        }
+        let fill_rate = self.fill_rate();
+        Estimate {
+            fill_rate,
+            eta_full: fill_rate
+                .filter(|rate| *rate > 0.0)
+                .map(|rate| available as f64 / rate),
+        }
+    }
+
+    // Forgets the samples so far
+    pub(super) fn reset(&mut self) {
+        self.samples.clear();
+    }
+
+    // Helper: The median of the pairwise slopes, in bytes per second
+    fn fill_rate(&self) -> Option<f64> {
+        if self.samples.len() < self.min_samples {
+            return None;
+        }
+        let mut slopes = Vec::new();
+        for (i, (t0, u0)) in self.samples.iter().enumerate() {
+            for (t1, u1) in self.samples.iter().skip(i + 1) {
+                if t1 > t0 {
+                    slopes.push((*u1 as f64 - *u0 as f64) / (t1 - t0));
+                }
+            }
+        }
+        if slopes.is_empty() {
+            return None;
+        }
+        slopes.sort_unstable_by(f64::total_cmp);
+        let middle = slopes.len() / 2;
+        Some(match slopes.len() % 2 {
+            1 => slopes[middle],
+            _ => (slopes[middle - 1] + slopes[middle]) / 2.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper: The estimate after samples of `used` bytes, one a second, out
+    // of 10_000
+    fn run(trend: &mut Trend, used: &[u64]) -> Estimate {
+        let mut estimate = Estimate::default();
+        for (i, used) in used.iter().enumerate() {
+            estimate = trend.update(i as f64, *used, 10_000 - used);
+        }
+        estimate
+    }
+
+    #[test]
+    fn estimates_a_steady_fill() {
+        let mut trend = Trend::new(Duration::from_secs(60), 3);
+        assert_eq!(run(&mut trend, &[1000, 1100]), Estimate::default());
+        let estimate = trend.update(2.0, 1200, 8800);
+        assert_eq!(estimate.fill_rate, Some(100.0));
+        assert_eq!(estimate.eta_full, Some(88.0));
+    }
+
+    #[test]
+    fn shrugs_off_a_single_outlier() {
+        let mut trend = Trend::new(Duration::from_secs(60), 3);
+        let estimate = run(&mut trend, &[1000, 1010, 1020, 9000, 1040, 1050, 1060]);
+        assert_eq!(estimate.fill_rate, Some(10.0));
+        assert_eq!(estimate.eta_full, Some(8940.0 / 10.0));
+    }
+
+    #[test]
+    fn has_no_eta_for_a_flat_or_shrinking_fill() {
+        let mut trend = Trend::new(Duration::from_secs(60), 3);
+        let estimate = run(&mut trend, &[5000, 5000, 5000, 5000]);
+        assert_eq!(estimate.fill_rate, Some(0.0));
+        assert_eq!(estimate.eta_full, None);
+        let mut trend = Trend::new(Duration::from_secs(60), 3);
+        let estimate = run(&mut trend, &[5000, 4000, 3000]);
+        assert_eq!(estimate.fill_rate, Some(-1000.0));
+        assert_eq!(estimate.eta_full, None);
+    }
+
+    #[test]
+    fn forgets_samples_outside_the_window() {
+        let mut trend = Trend::new(Duration::from_secs(3), 2);
+        // Shrinking at first, then filling at 50 bytes a second
+        let estimate = run(&mut trend, &[9000, 6000, 3000, 3050, 3100, 3150, 3200]);
+        assert_eq!(estimate.fill_rate, Some(50.0));
+        assert_eq!(trend.samples.len(), 4);
+    }
+}
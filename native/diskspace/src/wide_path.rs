@@ -0,0 +1,219 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// wide_path: the \\?\ long form of a path as a wide string, ready for the
+// WinAPI, as stat_fs passes it to GetFileAttributesW and
+// GetDiskFreeSpaceExW. Working it out takes a UTF-8 check, prefixing,
+// formatting and a conversion to UTF-16 on every call, which shows in the
+// time of a stat; the wide strings of paths of up to MAX_KEY bytes are kept,
+// keyed by the path as given, so that statting a path again takes a lookup
+// and no conversion.
+//
+// The cache is split into SHARDS by a hash of the path, each behind a lock
+// of its own, so that callers statting different paths seldom wait for one
+// another, and converting happens outside of them. Each shard keeps at most
+// SHARD_CAPACITY paths, the least recently used making way for a new one,
+// found by a scan as in stat_fs_cached's cache. A path that can't be
+// converted is not kept.
+//
+// The long form depends on the path alone, so a kept one is the same as a
+// fresh one. Built without the wide_path_cache feature, the cache is
+// compiled out and every call converts.
+
+#[cfg(any(windows, all(feature = "wide_path_cache", test)))]
+use std::sync::Arc;
+#[cfg(all(feature = "wide_path_cache", windows))]
+use std::sync::LazyLock;
+#[cfg(all(feature = "wide_path_cache", any(windows, test)))]
+use {
+    std::collections::hash_map::DefaultHasher,
+    std::collections::HashMap,
+    std::hash::{Hash, Hasher},
+    std::sync::{Mutex, MutexGuard, PoisonError},
+};
+#[cfg(windows)]
+use {std::ffi::CStr, widestring::WideCString};
+
+#[cfg(all(feature = "wide_path_cache", any(windows, test)))]
+const SHARDS: usize = 16;
+#[cfg(all(feature = "wide_path_cache", any(windows, test)))]
+const SHARD_CAPACITY: usize = 64;
+// The longest path kept, in bytes
+#[cfg(all(feature = "wide_path_cache", any(windows, test)))]
+const MAX_KEY: usize = 1024;
+
+#[cfg(all(feature = "wide_path_cache", windows))]
+static CACHE: LazyLock<Cache<WideCString>> = LazyLock::new(Cache::new);
+
+#[cfg(all(feature = "wide_path_cache", any(windows, test)))]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(all(feature = "wide_path_cache", any(windows, test)))]
+struct Shard<V> {
+    // Each value with the stamp of its last use
+    entries: HashMap<Box<[u8]>, (Arc<V>, u64)>,
+    // Stamps each use of an entry, for finding the least recently used
+    clock: u64,
+}
+
+#[cfg(all(feature = "wide_path_cache", any(windows, test)))]
+impl<V> Shard<V> {
+    fn get(&mut self, key: &[u8]) -> Option<Arc<V>> {
+        self.clock += 1;
+        let (value, used) = self.entries.get_mut(key)?;
+        *used = self.clock;
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, key: &[u8], value: Arc<V>) {
+        if self.entries.len() >= SHARD_CAPACITY && !self.entries.contains_key(key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(key.into(), (value, self.clock));
+    }
+}
+
+#[cfg(all(feature = "wide_path_cache", any(windows, test)))]
+struct Cache<V> {
+    shards: Vec<Mutex<Shard<V>>>,
+}
+
+#[cfg(all(feature = "wide_path_cache", any(windows, test)))]
+impl<V> Cache<V> {
+    fn new() -> Cache<V> {
+        let shards = (0..SHARDS)
+            .map(|_| {
+                Mutex::new(Shard {
+                    entries: HashMap::new(),
+                    clock: 0,
+                })
+            })
+            .collect();
+        Cache { shards }
+    }
+
+    // The value kept for `key`, or the one `convert` makes of it, kept if
+    // `key` isn't too long
+    fn get_or_convert(&self, key: &[u8], convert: impl FnOnce() -> Option<V>) -> Option<Arc<V>> {
+        if key.len() > MAX_KEY {
+            return convert().map(Arc::new);
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let shard = &self.shards[hasher.finish() as usize % SHARDS];
+        if let Some(value) = lock(shard).get(key) {
+            return Some(value);
+        }
+        let value = Arc::new(convert()?);
+        lock(shard).insert(key, value.clone());
+        Some(value)
+    }
+}
+
+// Helper: The \\?\ long form of `path` in UTF-16 and NUL-terminated,
+// \\?\UNC\server\share for a UNC path; one in the long form already is
+// kept as it is. The prefix and the path are written into a single buffer
+// of the size they take, which WideCString then takes over as it is
+#[cfg(any(windows, test))]
+fn long_form(path: &str) -> Vec<u16> {
+    let (prefix, rest) = if path.starts_with(r"\\?\") {
+        ("", path)
+    } else if let Some(share) = path.strip_prefix(r"\\") {
+        (r"\\?\UNC\", share)
+    } else {
+        (r"\\?\", path)
+    };
+    let mut units = Vec::with_capacity(prefix.len() + rest.encode_utf16().count() + 1);
+    units.extend(prefix.encode_utf16());
+    units.extend(rest.encode_utf16());
+    units.push(0);
+    units
+}
+
+// Helper: The long form of `path_cstr` as a wide string, None if it isn't
+// UTF-8
+#[cfg(windows)]
+fn convert(path_cstr: &CStr) -> Option<WideCString> {
+    let path = path_cstr.to_str().ok()?;
+    WideCString::from_vec(long_form(path)).ok()
+}
+
+// The long form of `path_cstr` as a wide string, None if it isn't UTF-8
+#[cfg(windows)]
+pub(crate) fn long(path_cstr: &CStr) -> Option<Arc<WideCString>> {
+    #[cfg(feature = "wide_path_cache")]
+    {
+        CACHE.get_or_convert(path_cstr.to_bytes(), || convert(path_cstr))
+    }
+    #[cfg(not(feature = "wide_path_cache"))]
+    {
+        convert(path_cstr).map(Arc::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper: The long form of `path` as a String, without its NUL
+    fn long_form_text(path: &str) -> String {
+        let units = long_form(path);
+        assert_eq!(units.len(), units.capacity());
+        assert_eq!(units.last(), Some(&0));
+        String::from_utf16_lossy(&units[..units.len() - 1])
+    }
+
+    #[test]
+    fn prefixes_paths_with_their_long_form() {
+        assert_eq!(long_form_text(r"C:\data"), r"\\?\C:\data");
+        assert_eq!(
+            long_form_text(r"\\server\share\dir"),
+            r"\\?\UNC\server\share\dir"
+        );
+        assert_eq!(long_form_text(r"\\?\C:\data"), r"\\?\C:\data");
+        assert_eq!(
+            long_form_text(r"\\?\Volume{6b29fc40-ca47-1067-b31d-00dd010662da}\"),
+            r"\\?\Volume{6b29fc40-ca47-1067-b31d-00dd010662da}\"
+        );
+        assert_eq!(long_form_text(r"D:\données"), r"\\?\D:\données");
+    }
+
+    #[cfg(feature = "wide_path_cache")]
+    #[test]
+    fn keeps_the_most_recently_used_paths() {
+        let cache = Cache::new();
+        let converted = |key: &str| cache.get_or_convert(key.as_bytes(), || Some(long_form(key)));
+        let first = converted(r"C:\data").unwrap();
+        assert!(Arc::ptr_eq(&first, &converted(r"C:\data").unwrap()));
+        assert!(cache.get_or_convert(b"D:", || None).is_none());
+
+        // Filling a shard evicts the least recently used of it
+        let mut shard = Shard {
+            entries: HashMap::new(),
+            clock: 0,
+        };
+        for i in 0..SHARD_CAPACITY {
+            shard.insert(format!("{i}").as_bytes(), Arc::new(i));
+        }
+        assert_eq!(shard.get(b"0").as_deref(), Some(&0));
+        shard.insert(b"new", Arc::new(SHARD_CAPACITY));
+        assert_eq!(shard.entries.len(), SHARD_CAPACITY);
+        assert!(shard.get(b"1").is_none());
+        assert!(shard.get(b"0").is_some());
+
+        // Too long a path is converted each time
+        let long = "x".repeat(MAX_KEY + 1);
+        let once = converted(&long).unwrap();
+        assert!(!Arc::ptr_eq(&once, &converted(&long).unwrap()));
+    }
+}
@@ -0,0 +1,241 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// zfs_details: the dataset behind a ZFS mount and the properties that bound
+// its space, for stat's zfs_details option. Its statfs figures already
+// account for a quota, but not for which one applies, nor whether it is the
+// dataset or the pool that runs out first.
+//
+// The dataset is the source of its mount. Its properties come from the
+// ZFS_IOC_OBJSET_STATS ioctl on /dev/zfs, which any user may issue, as an
+// nvlist (see nvlist.rs) of {value, source} lists keyed by property name;
+// the kstats under /proc/spl only name datasets, without their properties.
+// The ioctl takes a zfs_cmd_t, of which only the name of the dataset and
+// the buffer for the nvlist matter here; the rest is left zeroed, within a
+// buffer larger than any version of it. A buffer for the nvlist found too
+// small comes back with the size it needs.
+//
+// The space is limited by the dataset when it has less available than the
+// root dataset of its pool, and by the pool otherwise. Without the zfs
+// module loaded, zfs_details returns {:error, :zfs_unavailable}. Linux only;
+// elsewhere it returns {:error, :unsupported}.
+
+#[cfg(any(target_os = "linux", test))]
+mod nvlist;
+
+use crate::{atoms, get_path_from_term, make_error_tuple};
+#[cfg(any(target_os = "linux", test))]
+use nvlist::Value;
+use rustler::{Env, NifResult, Term};
+#[cfg(target_os = "linux")]
+use {
+    crate::{make_errno_error_tuple, mounts, path_from_cstring},
+    rustler::Encoder,
+    std::fs::{File, OpenOptions},
+    std::io,
+    std::os::unix::io::AsRawFd,
+};
+
+// The properties of a dataset, in bytes, its limits None when unset
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq)]
+struct Dataset {
+    used: u64,
+    available: u64,
+    referenced: u64,
+    quota: Option<u64>,
+    reservation: Option<u64>,
+    refquota: Option<u64>,
+    refreservation: Option<u64>,
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl Dataset {
+    fn decode(pairs: &[(String, Value)]) -> Option<Dataset> {
+        let limit = |name| property(pairs, name).filter(|value| *value != 0);
+        Some(Dataset {
+            used: property(pairs, "used")?,
+            available: property(pairs, "available")?,
+            referenced: property(pairs, "referenced").unwrap_or(0),
+            quota: limit("quota"),
+            reservation: limit("reservation"),
+            refquota: limit("refquota"),
+            refreservation: limit("refreservation"),
+        })
+    }
+}
+
+// Helper: The value of the numeric property `name`
+#[cfg(any(target_os = "linux", test))]
+fn property(pairs: &[(String, Value)], name: &str) -> Option<u64> {
+    match nvlist::get(pairs, name)? {
+        Value::List(property) => match nvlist::get(property, "value")? {
+            Value::U64(value) => Some(*value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Helper: The pool of `dataset`, the name of its root dataset
+#[cfg(any(target_os = "linux", test))]
+fn pool(dataset: &str) -> &str {
+    dataset.split(['/', '@']).next().unwrap_or(dataset)
+}
+
+#[cfg(target_os = "linux")]
+const ZFS_IOC_OBJSET_STATS: u64 = 0x5a12;
+
+// Offsets within zfs_cmd_t: zc_name[MAXPATHLEN], then zc_nvlist_src and
+// its size, then zc_nvlist_dst and its size
+#[cfg(target_os = "linux")]
+const NAME_LENGTH: usize = 4096;
+#[cfg(target_os = "linux")]
+const NVLIST_DST: usize = 4112;
+#[cfg(target_os = "linux")]
+const NVLIST_DST_SIZE: usize = 4120;
+#[cfg(target_os = "linux")]
+const ZFS_CMD_SIZE: usize = 32 * 1024;
+
+// Helper: The properties of `dataset`, through /dev/zfs
+#[cfg(target_os = "linux")]
+fn objset_stats(zfs: &File, dataset: &str) -> io::Result<Dataset> {
+    if dataset.len() >= NAME_LENGTH || dataset.contains('\0') {
+        return Err(io::Error::from_raw_os_error(libc::ENAMETOOLONG));
+    }
+    let mut size = 64 * 1024;
+    // Once more should the properties grow between the two calls
+    for _ in 0..3 {
+        let mut buffer = vec![0u8; size];
+        let mut cmd = vec![0u8; ZFS_CMD_SIZE];
+        cmd[..dataset.len()].copy_from_slice(dataset.as_bytes());
+        let address = buffer.as_mut_ptr() as u64;
+        cmd[NVLIST_DST..NVLIST_DST + 8].copy_from_slice(&address.to_ne_bytes());
+        cmd[NVLIST_DST_SIZE..NVLIST_DST_SIZE + 8].copy_from_slice(&(size as u64).to_ne_bytes());
+        let result =
+            unsafe { libc::ioctl(zfs.as_raw_fd(), ZFS_IOC_OBJSET_STATS as _, cmd.as_mut_ptr()) };
+        let returned = u64::from_ne_bytes(
+            cmd[NVLIST_DST_SIZE..NVLIST_DST_SIZE + 8]
+                .try_into()
+                .unwrap(),
+        );
+        if result < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOMEM) && returned as usize > size {
+                size = returned as usize;
+                continue;
+            }
+            return Err(err);
+        }
+        let packed = buffer.get(..returned as usize).unwrap_or(&buffer);
+        return nvlist::decode(packed)
+            .as_deref()
+            .and_then(Dataset::decode)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData));
+    }
+    Err(io::Error::from_raw_os_error(libc::ENOMEM))
+}
+
+// %{dataset, used_bytes, available_bytes, referenced_bytes, quota_bytes,
+// reservation_bytes, refquota_bytes, refreservation_bytes,
+// pool_available_bytes, limited_by} of the ZFS dataset holding `path`, or
+// nil for another filesystem
+#[rustler::nif(schedule = "DirtyIo")]
+fn zfs_details_nif<'a>(env: Env<'a>, path_term: Term<'a>) -> NifResult<Term<'a>> {
+    let Ok(path) = get_path_from_term(env, path_term) else {
+        return make_error_tuple(env, atoms::invalid_path());
+    };
+    #[cfg(target_os = "linux")]
+    {
+        let Some(path) = path_from_cstring(&path) else {
+            return make_error_tuple(env, atoms::invalid_path());
+        };
+        let dataset = match mounts::holding(&path) {
+            Ok(Some(mount)) if mount.fs_type == "zfs" => mount.source,
+            Ok(_) => return Ok((atoms::ok(), rustler::types::atom::nil()).encode(env)),
+            Err(err) => return make_errno_error_tuple(env, atoms::invalid_path(), err),
+        };
+        let zfs = match OpenOptions::new().read(true).write(true).open("/dev/zfs") {
+            Ok(zfs) => zfs,
+            Err(err)
+                if matches!(
+                    err.raw_os_error(),
+                    Some(libc::ENOENT | libc::ENODEV | libc::ENXIO)
+                ) =>
+            {
+                return make_error_tuple(env, atoms::zfs_unavailable());
+            }
+            Err(err) => return make_errno_error_tuple(env, atoms::zfs_ioctl_failed(), err),
+        };
+        let stats = objset_stats(&zfs, &dataset).and_then(|stats| {
+            let root = pool(&dataset);
+            if root == dataset {
+                return Ok((stats.available, stats));
+            }
+            Ok((objset_stats(&zfs, root)?.available, stats))
+        });
+        let (pool_available, stats) = match stats {
+            Ok(stats) => stats,
+            Err(err) => return make_errno_error_tuple(env, atoms::zfs_ioctl_failed(), err),
+        };
+        let limited_by = if stats.available < pool_available {
+            atoms::dataset()
+        } else {
+            atoms::pool()
+        };
+        let map = rustler::types::map::map_new(env)
+            .map_put(atoms::dataset().to_term(env), dataset.as_str())?
+            .map_put(atoms::used_bytes().to_term(env), stats.used)?
+            .map_put(atoms::available_bytes().to_term(env), stats.available)?
+            .map_put(atoms::referenced_bytes().to_term(env), stats.referenced)?
+            .map_put(atoms::quota_bytes().to_term(env), stats.quota)?
+            .map_put(atoms::reservation_bytes().to_term(env), stats.reservation)?
+            .map_put(atoms::refquota_bytes().to_term(env), stats.refquota)?
+            .map_put(
+                atoms::refreservation_bytes().to_term(env),
+                stats.refreservation,
+            )?
+            .map_put(atoms::pool_available_bytes().to_term(env), pool_available)?
+            .map_put(atoms::limited_by().to_term(env), limited_by)?;
+        Ok((atoms::ok(), map).encode(env))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        make_error_tuple(env, atoms::unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::nvlist::tests::{packed_list, property, stream};
+    use super::*;
+
+    #[test]
+    fn decodes_dataset_properties() {
+        let buffer = stream(packed_list(&[
+            property("used", 10 << 30),
+            property("available", 5 << 30),
+            property("referenced", 8 << 30),
+            property("quota", 15 << 30),
+            property("reservation", 0),
+            property("refquota", 0),
+        ]));
+        let pairs = nvlist::decode(&buffer).unwrap();
+        assert_eq!(
+            Dataset::decode(&pairs),
+            Some(Dataset {
+                used: 10 << 30,
+                available: 5 << 30,
+                referenced: 8 << 30,
+                quota: Some(15 << 30),
+                reservation: None,
+                refquota: None,
+                refreservation: None,
+            })
+        );
+        assert_eq!(Dataset::decode(&pairs[2..]), None);
+        assert_eq!(pool("tank/home/alice"), "tank");
+        assert_eq!(pool("tank"), "tank");
+    }
+}
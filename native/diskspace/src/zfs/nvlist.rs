@@ -0,0 +1,174 @@
+// SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+// SPDX-License-Identifier: Apache-2.0
+
+// Decoder of the nvlists the ZFS ioctls return, packed in the native
+// encoding of libnvpair: a 4-byte stream header {encoding, endian, 0, 0},
+// then the list. A list is an 8-byte header {version, flags}, its pairs, and
+// 4 zero bytes where the size of a next pair would be. A pair is a 16-byte
+// header {size, name_sz, reserved, value_elem, type}, its NUL-terminated
+// name, and its value at the next multiple of 8, `size` covering all three.
+// The lists held by a pair of type NVLIST or NVLIST_ARRAY follow it in the
+// stream, one after the other, rather than its value.
+//
+// Only the types ZFS properties take are decoded; the others are skipped.
+
+// data_type_t of <sys/nvpair.h>
+const DATA_TYPE_UINT64: i32 = 8;
+const DATA_TYPE_STRING: i32 = 9;
+const DATA_TYPE_NVLIST: i32 = 19;
+const DATA_TYPE_NVLIST_ARRAY: i32 = 20;
+
+const NV_ENCODE_NATIVE: u8 = 0;
+// NV_LITTLE_ENDIAN or NV_BIG_ENDIAN, whichever this host is
+const NV_HOST_ENDIAN: u8 = if cfg!(target_endian = "little") { 1 } else { 0 };
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Value {
+    U64(u64),
+    String(String),
+    List(Vec<(String, Value)>),
+    Other,
+}
+
+// The pairs of a packed nvlist, None if it isn't one in the native encoding
+// of this host
+pub(super) fn decode(buffer: &[u8]) -> Option<Vec<(String, Value)>> {
+    if buffer.len() < 4 || buffer[0] != NV_ENCODE_NATIVE || buffer[1] != NV_HOST_ENDIAN {
+        return None;
+    }
+    let mut position = 4;
+    list(buffer, &mut position)
+}
+
+// Helper: The list at `position`, moving it past the end of the list
+fn list(buffer: &[u8], position: &mut usize) -> Option<Vec<(String, Value)>> {
+    *position = position.checked_add(8)?;
+    let mut pairs = Vec::new();
+    loop {
+        let size = usize::try_from(i32_at(buffer, *position)?).ok()?;
+        if size == 0 {
+            *position += 4;
+            return Some(pairs);
+        }
+        let pair = buffer.get(*position..position.checked_add(size)?)?;
+        *position += size;
+        let name_size =
+            usize::try_from(i16::from_ne_bytes(pair.get(4..6)?.try_into().ok()?)).ok()?;
+        let elements = i32_at(pair, 8)?;
+        let type_ = i32_at(pair, 12)?;
+        let name = pair.get(16..16 + name_size)?;
+        let name = String::from_utf8_lossy(name.strip_suffix(&[0]).unwrap_or(name)).into_owned();
+        let value = pair
+            .get((16 + name_size).next_multiple_of(8)..)
+            .unwrap_or_default();
+        let value = match type_ {
+            DATA_TYPE_UINT64 => Value::U64(u64::from_ne_bytes(value.get(..8)?.try_into().ok()?)),
+            DATA_TYPE_STRING => {
+                let end = value.iter().position(|byte| *byte == 0)?;
+                Value::String(String::from_utf8_lossy(&value[..end]).into_owned())
+            }
+            DATA_TYPE_NVLIST => Value::List(list(buffer, position)?),
+            DATA_TYPE_NVLIST_ARRAY => {
+                for _ in 0..elements {
+                    list(buffer, position)?;
+                }
+                Value::Other
+            }
+            _ => Value::Other,
+        };
+        pairs.push((name, value));
+    }
+}
+
+// Helper: The native i32 at `offset`
+fn i32_at(buffer: &[u8], offset: usize) -> Option<i32> {
+    Some(i32::from_ne_bytes(
+        buffer
+            .get(offset..offset.checked_add(4)?)?
+            .try_into()
+            .ok()?,
+    ))
+}
+
+// The value of the pair `name`
+pub(super) fn get<'a>(pairs: &'a [(String, Value)], name: &str) -> Option<&'a Value> {
+    pairs
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    // Helper: A pair as libnvpair packs it, followed by the lists it holds
+    pub(in crate::zfs) fn pair(name: &str, type_: i32, value: &[u8], lists: &[Vec<u8>]) -> Vec<u8> {
+        let name_size = name.len() + 1;
+        let value_offset = (16 + name_size).next_multiple_of(8);
+        let size = (value_offset + value.len()).next_multiple_of(8);
+        let mut bytes = vec![0; size];
+        bytes[0..4].copy_from_slice(&(size as i32).to_ne_bytes());
+        bytes[4..6].copy_from_slice(&(name_size as i16).to_ne_bytes());
+        bytes[8..12].copy_from_slice(&(lists.len().max(1) as i32).to_ne_bytes());
+        bytes[12..16].copy_from_slice(&type_.to_ne_bytes());
+        bytes[16..16 + name.len()].copy_from_slice(name.as_bytes());
+        bytes[value_offset..value_offset + value.len()].copy_from_slice(value);
+        for list in lists {
+            bytes.extend_from_slice(list);
+        }
+        bytes
+    }
+
+    // Helper: A list of packed pairs
+    pub(in crate::zfs) fn packed_list(pairs: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0, 0, 1, 0, 0, 0];
+        for pair in pairs {
+            bytes.extend_from_slice(pair);
+        }
+        bytes.extend_from_slice(&[0; 4]);
+        bytes
+    }
+
+    // Helper: A whole stream holding `list`
+    pub(in crate::zfs) fn stream(list: Vec<u8>) -> Vec<u8> {
+        [vec![NV_ENCODE_NATIVE, NV_HOST_ENDIAN, 0, 0], list].concat()
+    }
+
+    // Helper: A property as ZFS returns it, {value, source}
+    pub(in crate::zfs) fn property(name: &str, value: u64) -> Vec<u8> {
+        let inner = packed_list(&[
+            pair("value", DATA_TYPE_UINT64, &value.to_ne_bytes(), &[]),
+            pair("source", DATA_TYPE_STRING, b"tank\0", &[]),
+        ]);
+        pair(name, DATA_TYPE_NVLIST, &[0; 24], &[inner])
+    }
+
+    #[test]
+    fn decodes_nested_lists() {
+        let array = pair(
+            "skipped",
+            DATA_TYPE_NVLIST_ARRAY,
+            &[0; 16],
+            &[packed_list(&[]), packed_list(&[property("deep", 1)])],
+        );
+        let buffer = stream(packed_list(&[
+            property("used", 4096),
+            array,
+            pair("name", DATA_TYPE_STRING, b"tank/home\0", &[]),
+        ]));
+        let pairs = decode(&buffer).unwrap();
+        assert_eq!(pairs.len(), 3);
+        let Some(Value::List(used)) = get(&pairs, "used") else {
+            panic!("expected a list");
+        };
+        assert_eq!(get(used, "value"), Some(&Value::U64(4096)));
+        assert_eq!(get(&pairs, "skipped"), Some(&Value::Other));
+        assert_eq!(
+            get(&pairs, "name"),
+            Some(&Value::String("tank/home".to_string()))
+        );
+        assert_eq!(decode(&buffer[..buffer.len() - 4]), None);
+        assert_eq!(decode(&[1, 1, 0, 0]), None);
+    }
+}
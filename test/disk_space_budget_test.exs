@@ -0,0 +1,41 @@
+# SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+# SPDX-License-Identifier: Apache-2.0
+
+defmodule DiskSpaceBudgetTest do
+  # Counts on nif_stats/0, which the async tests reset as they go
+  use ExUnit.Case, async: false
+
+  describe "stat/2 with scheduler: :normal" do
+    test "fails a stat over its budget in debug builds, and counts it" do
+      path = System.tmp_dir!()
+      before = budget_exceeded()
+
+      case DiskSpace.stat(path, scheduler: :normal, budget_ms: 0) do
+        {:error, %{reason: :budget_exceeded, info: %{elapsed_us: elapsed_us, budget_ms: 0}}} ->
+          assert is_integer(elapsed_us)
+
+        # Release builds return the figures all the same
+        {:ok, %{total: _}} ->
+          :ok
+      end
+
+      assert {:ok, _} = DiskSpace.stat(path, scheduler: :normal, budget_ms: 60_000)
+
+      case before do
+        nil -> assert {:error, %{reason: :telemetry_disabled}} = DiskSpace.nif_stats()
+        before -> assert budget_exceeded() == before + 1
+      end
+
+      assert {:error, %{reason: :invalid_option, info: :budget_ms}} =
+               DiskSpace.stat(path, scheduler: :normal, budget_ms: -1)
+    end
+  end
+
+  # The stats counted as over their budget, nil without telemetry
+  defp budget_exceeded do
+    case DiskSpace.nif_stats() do
+      %{stat_fs: %{failures: failures}} -> Map.get(failures, :budget_exceeded, 0)
+      {:error, _} -> nil
+    end
+  end
+end
@@ -17,7 +17,7 @@ defmodule DiskSpaceTest do
       path = valid_directory_path()
       assert {:ok, stats} = DiskSpace.stat(path)
       assert is_map(stats)
-      assert Enum.sort(Map.keys(stats)) == [:available, :free, :total, :used]
+      assert Enum.sort(Map.keys(stats)) == stat_keys()
       assert is_integer(stats.available)
       assert is_integer(stats.free)
       assert is_integer(stats.total)
@@ -65,7 +65,7 @@ defmodule DiskSpaceTest do
       path = valid_directory_path()
       assert {:ok, stats} = DiskSpace.stat(path, humanize: :binary)
       assert is_map(stats)
-      assert Enum.sort(Map.keys(stats)) == [:available, :free, :total, :used]
+      assert Enum.sort(Map.keys(stats)) == stat_keys()
       assert is_binary(stats.available)
       assert String.match?(stats.available, ~r/^[0-9.]+ [KMGTP]?i?B$/)
       assert String.match?(stats.free, ~r/^[0-9.]+ [KMGTP]?i?B$/)
@@ -77,7 +77,7 @@ defmodule DiskSpaceTest do
       path = valid_directory_path()
       assert {:ok, stats} = DiskSpace.stat(path, humanize: :decimal)
       assert is_map(stats)
-      assert Enum.sort(Map.keys(stats)) == [:available, :free, :total, :used]
+      assert Enum.sort(Map.keys(stats)) == stat_keys()
       assert is_binary(stats.available)
       assert String.match?(stats.available, ~r/^[0-9.]+ [kMGTP]?B$/)
       assert String.match?(stats.free, ~r/^[0-9.]+ [kMGTP]?B$/)
@@ -86,54 +86,2254 @@ defmodule DiskSpaceTest do
     end
   end
 
-  describe "stat!/2" do
-    test "returns stats map directly on success" do
+  describe "configure/1" do
+    test "resizes the worker pool, rejecting invalid options as a whole" do
       path = valid_directory_path()
-      stats = DiskSpace.stat!(path)
-      assert is_map(stats)
-      assert Enum.sort(Map.keys(stats)) == [:available, :free, :total, :used]
-      assert is_integer(stats.available)
-      assert is_integer(stats.free)
-      assert is_integer(stats.total)
-      assert is_integer(stats.used)
-      assert stats.total >= stats.free
-      assert stats.total >= stats.used
+      assert :ok = DiskSpace.configure(pool_size: 2)
+      refs = for _ <- 1..10, do: make_ref()
+      for ref <- refs, do: :ok = DiskSpace.stat_fs_async(path, self(), ref)
+
+      for ref <- refs do
+        assert_receive {:disk_space, ^ref, {:ok, _}}, 5000
+      end
+
+      assert {:error, %{reason: :invalid_option, info: :pool_queue_limit}} =
+               DiskSpace.configure(%{pool_size: 4, pool_queue_limit: 0})
+
+      assert {:error, %{reason: :invalid_option, info: :pool_size}} =
+               DiskSpace.configure(pool_size: -1)
+
+      assert :ok = DiskSpace.configure(pool_size: 8, pool_queue_limit: 4096)
     end
 
-    test "raises DiskSpace.Error on failure" do
-      path = Path.join(valid_directory_path(), "nonexistent_#{System.unique_integer()}")
+    test "sets defaults that calls fall back on, rejecting invalid ones as a whole" do
+      assert %{timeout: nil, exclude: nil, humanize: nil, pool_size: 8} = DiskSpace.get_config()
+      assert :ok = DiskSpace.configure(exclude: [{:fs_type, "no_such_fs"}], humanize: :decimal)
 
-      assert_raise DiskSpace.Error, fn ->
-        DiskSpace.stat!(path)
+      assert %{exclude: [{:fs_type, "no_such_fs"}], humanize: :decimal, timeout: nil} =
+               DiskSpace.get_config()
+
+      assert {:ok, %{available: available}} = DiskSpace.stat(valid_directory_path())
+      assert is_binary(available)
+
+      assert {:ok, %{available: available}} =
+               DiskSpace.stat(valid_directory_path(), humanize: nil)
+
+      assert is_integer(available)
+
+      assert {:error, %{reason: :invalid_option, info: :humanize}} =
+               DiskSpace.configure(timeout: 1_000, humanize: :octal)
+
+      assert %{timeout: nil, humanize: :decimal} = DiskSpace.get_config()
+      assert :ok = DiskSpace.configure(exclude: nil, humanize: nil)
+      assert %{exclude: nil, humanize: nil} = DiskSpace.get_config()
+    end
+  end
+
+  describe "stat_fs_cached/2" do
+    test "reuses a fresh result and counts the hits" do
+      path = valid_directory_path()
+      assert :ok = DiskSpace.cache_invalidate(:all)
+      assert :ok = DiskSpace.nif_stats_reset()
+      assert {:ok, %{total: total}} = DiskSpace.stat_fs_cached(path, 60_000)
+
+      tasks = for _ <- 1..20, do: Task.async(fn -> DiskSpace.stat_fs_cached(path, 60_000) end)
+      assert Enum.all?(Task.await_many(tasks), &match?({:ok, %{total: ^total}}, &1))
+      assert %{stat_fs_cached: %{hits: 20, misses: 1}} = DiskSpace.nif_stats()
+
+      assert :ok = DiskSpace.cache_invalidate(path <> "/.")
+      assert {:ok, _} = DiskSpace.stat_fs_cached(path, 60_000)
+      assert {:ok, _} = DiskSpace.stat_fs_cached(path, 0)
+      assert %{stat_fs_cached: %{misses: 3}} = DiskSpace.nif_stats()
+
+      assert {:error, %{reason: _}} =
+               DiskSpace.stat_fs_cached(Path.join(path, "disk_space_missing"), 60_000)
+    end
+  end
+
+  describe "open_target/1" do
+    test "stats the directory it opened until closed" do
+      path = valid_directory_path()
+      assert {:ok, target} = DiskSpace.open_target(path)
+      assert {:ok, %{total: total, available: available}} = DiskSpace.stat_target(target)
+      assert %{total: ^total} = DiskSpace.stat!(path, humanize: nil)
+      assert is_integer(available)
+
+      assert :ok = DiskSpace.close_target(target)
+      assert :ok = DiskSpace.close_target(target)
+      assert {:error, %{reason: :closed}} = DiskSpace.stat_target(target)
+
+      assert {:error, %{reason: _}} = DiskSpace.open_target(Path.join(path, "disk_space_missing"))
+    end
+  end
+
+  describe "ensure_free/3" do
+    test "compares the bytes required against the space available" do
+      path = valid_directory_path()
+      %{available: available, total: total} = DiskSpace.stat!(path, humanize: nil)
+
+      assert :ok = DiskSpace.ensure_free(path, 0)
+
+      assert {:error, %{reason: :insufficient_space, info: info}} =
+               DiskSpace.ensure_free(path, available + total)
+
+      assert %{required: required, available: now, shortfall: shortfall} = info
+      assert required == available + total and shortfall == required - now
+    end
+
+    test "counts the margin and the overhead of each file" do
+      path = valid_directory_path()
+
+      assert {:error, %{reason: :insufficient_space, info: %{required: required}}} =
+               DiskSpace.ensure_free(path, 0, margin: {:percent, 100})
+
+      assert required == DiskSpace.stat!(path, humanize: nil).total
+
+      assert {:error, %{info: %{required: 2_000_000_000_000_000_001}}} =
+               DiskSpace.ensure_free(path, 1,
+                 file_count: 1_000_000_000,
+                 per_file_overhead: 2_000_000_000
+               )
+
+      assert :ok = DiskSpace.ensure_free(path, 0, quota: {:user, 0})
+    end
+
+    test "fails like stat/2 for a path that can't be examined" do
+      missing = Path.join(valid_directory_path(), "disk_space_missing")
+      assert {:error, %{reason: reason}} = DiskSpace.ensure_free(missing, 1)
+      refute reason == :insufficient_space
+    end
+  end
+
+  describe "estimate_on_disk/3" do
+    test "rounds file sizes up to the allocation unit" do
+      path = valid_directory_path()
+
+      assert {:ok, %{allocation_unit_bytes: unit, files: 3, apparent_bytes: 3} = estimate} =
+               DiskSpace.estimate_on_disk(path, [1, 1, 1], metadata_bytes: 0)
+
+      assert estimate.estimated_bytes == 3 * unit
+      assert estimate.fits and estimate.shortfall == 0
+      assert is_binary(estimate.fs_type)
+
+      assert {:ok, %{metadata_bytes: 300}} =
+               DiskSpace.estimate_on_disk(path, [1, 1, 1], metadata_bytes: 100)
+    end
+
+    test "takes a du summary, and compares against the space available" do
+      path = valid_directory_path()
+      summary = %{apparent_bytes: 4_000_000_000_000_000_000, files: 1}
+
+      assert {:ok, %{fits: false, shortfall: shortfall, available: available} = estimate} =
+               DiskSpace.estimate_on_disk(path, {:ok, summary})
+
+      assert shortfall == estimate.estimated_bytes - available
+
+      assert {:error, %{reason: :invalid_option, info: :metadata_bytes}} =
+               DiskSpace.estimate_on_disk(path, [], metadata_bytes: -1)
+    end
+  end
+
+  describe "reserve/2" do
+    test "holds space until released" do
+      path = valid_directory_path()
+      %{available: available} = DiskSpace.stat!(path, humanize: nil)
+      half = div(available, 2) + 1
+
+      assert {:ok, reservation} = DiskSpace.reserve(path, half)
+      assert [%{bytes: ^half, reserved_at: at} | _] = DiskSpace.reservations()
+      assert is_integer(at)
+
+      assert {:error, %{reason: :insufficient_space, info: %{shortfall: _}}} =
+               DiskSpace.reserve(path, half)
+
+      assert {:error, %{reason: :insufficient_space}} = DiskSpace.ensure_free(path, half)
+
+      assert :ok = DiskSpace.release(reservation)
+      assert :ok = DiskSpace.release(reservation)
+      refute Enum.any?(DiskSpace.reservations(), &(&1.bytes == half))
+      assert {:ok, _} = DiskSpace.reserve(path, 0)
+    end
+
+    test "releases what is garbage collected" do
+      path = valid_directory_path()
+
+      {pid, monitor} = spawn_monitor(fn -> {:ok, _} = DiskSpace.reserve(path, 1234) end)
+      assert_receive {:DOWN, ^monitor, :process, ^pid, :normal}
+      refute Enum.any?(DiskSpace.reservations(), &(&1.bytes == 1234))
+    end
+  end
+
+  describe "can_fit/3" do
+    setup do
+      root = Path.join(System.tmp_dir!(), "disk_space_fit_#{System.unique_integer([:positive])}")
+      File.mkdir_p!(Path.join(root, "src"))
+      File.write!(Path.join(root, "src/one.bin"), :binary.copy(<<1>>, 10_000))
+      on_exit(fn -> File.rm_rf!(root) end)
+      %{src: Path.join(root, "src"), dest: root}
+    end
+
+    test "tells whether a tree can be copied", %{src: src, dest: dest} do
+      assert {:ok, %{required: required, available: available, margin: 0}} =
+               DiskSpace.can_fit(src, dest)
+
+      assert required >= 10_000 and required <= available
+
+      assert {:error, %{reason: :will_not_fit, info: %{shortfall: shortfall}}} =
+               DiskSpace.can_fit(src, dest, margin: {:percent, 100})
+
+      assert shortfall > 0
+    end
+
+    test "needs no space to move within a filesystem", %{src: src, dest: dest} do
+      assert {:ok, %{required: 0}} = DiskSpace.can_fit(src, dest, operation: :move)
+
+      assert {:error, %{reason: :invalid_option, info: :operation}} =
+               DiskSpace.can_fit(src, dest, operation: :link)
+
+      assert {:error, %{reason: :enoent}} = DiskSpace.can_fit(Path.join(src, "missing"), dest)
+    end
+  end
+
+  describe "preallocate/3" do
+    setup do
+      name = "disk_space_prealloc_#{System.unique_integer([:positive])}"
+      dir = Path.join(System.tmp_dir!(), name)
+      File.mkdir_p!(dir)
+      on_exit(fn -> File.rm_rf!(dir) end)
+      %{dir: dir}
+    end
+
+    test "allocates a file, and removes it if it can't", %{dir: dir} do
+      path = Path.join(dir, "segment")
+
+      case DiskSpace.preallocate(path, 1_048_576) do
+        :ok ->
+          assert File.stat!(path).size == 1_048_576
+          assert :ok = DiskSpace.preallocate(path, 4096)
+          assert File.stat!(path).size == 1_048_576
+
+        {:error, %{reason: :eopnotsupp}} ->
+          refute File.exists?(path)
+          assert :ok = DiskSpace.preallocate(path, 1_048_576, write_zeros: true)
+          assert File.stat!(path).size == 1_048_576
       end
+
+      huge = Path.join(dir, "huge")
+      assert {:error, %{reason: reason, info: %{errno: _}}} =
+               DiskSpace.preallocate(huge, 2 ** 62)
+      assert is_atom(reason)
+      refute File.exists?(huge)
+    end
+
+    test "rejects options it doesn't know the values of", %{dir: dir} do
+      assert {:error, %{reason: :invalid_option, info: :keep_partial}} =
+               DiskSpace.preallocate(Path.join(dir, "file"), 1, keep_partial: "yes")
     end
   end
 
-  describe "humanize/2" do
-    test "returns {:error, reason} unchanged" do
-      assert {:error, :eio} = DiskSpace.humanize({:error, :eio}, :binary)
+  describe "probe_writable/3" do
+    setup do
+      dir = Path.join(System.tmp_dir!(), "disk_space_probe_#{System.unique_integer([:positive])}")
+      File.mkdir_p!(dir)
+      on_exit(fn -> File.rm_rf!(dir) end)
+      %{dir: dir}
     end
 
-    test "humanizes byte counts in {:ok, stats_map}" do
-      input = {:ok, %{free: 123_456_789}}
-      {:ok, result} = DiskSpace.humanize(input, :binary)
-      assert is_binary(result.free)
-      assert String.contains?(result.free, "MiB")
+    test "writes the bytes and removes the file", %{dir: dir} do
+      assert {:ok, %{bytes_written: written, direct: direct} = info} =
+               DiskSpace.probe_writable(dir, 100_000)
+
+      assert written == if(direct, do: 102_400, else: 100_000)
+      assert is_integer(info.duration_ms) and is_integer(info.bytes_per_second)
+      assert File.ls!(dir) == []
+
+      assert {:ok, %{bytes_written: 100_000, direct: false}} =
+               DiskSpace.probe_writable(dir, 100_000, direct: false)
     end
 
-    test "humanizes byte counts in map directly" do
-      input = %{total: 1_000_000}
-      result = DiskSpace.humanize(input, :decimal)
-      assert is_binary(result.total)
-      assert String.contains?(result.total, "MB")
+    test "refuses what isn't a directory", %{dir: dir} do
+      assert {:error, %{reason: :not_directory}} =
+               DiskSpace.probe_writable(Path.join(dir, "missing"), 1)
     end
   end
 
-  defp valid_directory_path do
-    if :os.type() == {:win32, :nt} do
-      "C:\\"
-    else
-      "/tmp"
+  describe "backing_device/1" do
+    test "resolves the disks beneath a path, or says why not" do
+      case DiskSpace.backing_device(valid_directory_path()) do
+        {:ok, %{chain: [_ | _] = chain, disks: [_ | _] = disks} = backing} ->
+          assert Enum.all?(chain, &(is_binary(&1.name) and is_atom(&1.kind)))
+          bottom = for %{lower: [], device: device} <- chain, do: device
+          assert disks == bottom
+
+          for %{dm: %{} = dm} <- chain do
+            assert dm.type in [:lvm, :crypt, :mpath, :other]
+            if dm.type != :lvm, do: assert(is_nil(dm.vg) and is_nil(dm.lv))
+          end
+
+          crypt = for %{dm: %{type: :crypt}, device: device} <- chain, do: device
+          assert Enum.map(backing.encryption, & &1.device) == crypt
+          if is_boolean(backing.encrypted), do: assert(backing.encrypted == (crypt != []))
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:not_block_device, :unsupported]
+      end
+    end
+  end
+
+  describe "device_info/1" do
+    test "describes each disk beneath a path, or says why not" do
+      case DiskSpace.device_info(valid_directory_path()) do
+        {:ok, [_ | _] = disks} ->
+          for disk <- disks do
+            assert is_binary(disk.device)
+
+            for key <- [:model, :vendor, :serial, :firmware] do
+              assert is_nil(disk[key]) or (is_binary(disk[key]) and disk[key] != "")
+            end
+
+            assert disk.is_removable in [nil, true, false]
+            assert disk.rotational in [nil, true, false]
+            assert is_nil(disk.size_bytes) or is_integer(disk.size_bytes)
+            assert disk.bus in [nil, :usb, :nvme, :sata, :virtio, :mmc, :scsi]
+            if disk.bus == :usb, do: assert(disk.is_removable)
+
+            assert disk.transport in [
+                     :nvme,
+                     :sata,
+                     :sas,
+                     :scsi,
+                     :iscsi,
+                     :fc,
+                     :virtio,
+                     :usb,
+                     :mmc,
+                     :unknown
+                   ]
+            assert is_boolean(disk.permission_limited)
+
+            for key <- [:logical_sector_size, :physical_sector_size] do
+              assert is_nil(disk[key]) or (is_integer(disk[key]) and disk[key] >= 512)
+            end
+
+            for {_, value} <- disk.discard, do: assert(value in [nil, true, false])
+          end
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:not_block_device, :unsupported]
+      end
+    end
+  end
+
+  describe "diskstats/0 and diskstats/1" do
+    test "reads the I/O counters of all devices, or those beneath a path" do
+      case DiskSpace.diskstats() do
+        {:ok, devices} ->
+          for device <- devices do
+            assert is_binary(device.name)
+            assert is_integer(device.reads_completed) and is_integer(device.sectors_written)
+            assert is_nil(device.flushes_completed) or is_integer(device.flushes_completed)
+          end
+
+          case DiskSpace.diskstats(valid_directory_path()) do
+            {:ok, beneath} ->
+              names = Enum.map(devices, & &1.name)
+              assert Enum.all?(beneath, &(&1.name in names))
+
+            {:error, %{reason: reason}} ->
+              assert reason == :not_block_device
+          end
+
+        {:error, %{reason: reason}} ->
+          assert reason == :unsupported
+
+          case DiskSpace.diskstats(valid_directory_path()) do
+            {:ok, disks} -> assert Enum.all?(disks, &is_integer(&1.bytes_read))
+            {:error, %{reason: _}} -> :ok
+          end
+      end
+    end
+  end
+
+  describe "raid_info/1" do
+    test "describes the md array beneath a path, if any" do
+      case DiskSpace.raid_info(valid_directory_path()) do
+        {:ok, array} ->
+          assert is_binary(array.name) and is_boolean(array.degraded)
+
+          for member <- array.members do
+            assert member.state in [:in_sync, :rebuilding, :spare, :faulty, :replacement]
+          end
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:not_raid, :not_block_device, :unsupported]
+      end
+    end
+  end
+
+  describe "partitions/1" do
+    test "lists the partitions of the disks beneath a path" do
+      case DiskSpace.partitions(valid_directory_path()) do
+        {:ok, disks} ->
+          for disk <- disks, partition <- disk.partitions do
+            assert is_integer(partition.start) and is_integer(partition.size)
+            assert is_list(partition.mounted_at)
+            assert is_nil(disk.size_bytes) or partition.start + partition.size <= disk.size_bytes
+          end
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:not_block_device, :unsupported, :eacces]
+      end
+    end
+  end
+
+  describe "device_health/1" do
+    test "tells whether the disks beneath a path predict their failure" do
+      case DiskSpace.device_health(valid_directory_path()) do
+        {:ok, disks} ->
+          assert Enum.any?(disks, &is_boolean(&1.failure_predicted))
+
+        {:error, %{reason: reason}} ->
+          assert reason in [
+                   :device_health_disabled,
+                   :unavailable,
+                   :not_block_device,
+                   :unsupported
+                 ]
+      end
+    end
+  end
+
+  describe "encryption_status/1" do
+    test "tells whether BitLocker protects the volume, or says why not" do
+      case DiskSpace.encryption_status(valid_directory_path()) do
+        {:ok, %{protected: protected, encryption_in_progress: in_progress}} ->
+          assert is_boolean(protected) and is_boolean(in_progress)
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:unsupported, :access_denied, :not_block_device]
+      end
+    end
+  end
+
+  describe "volume_dirty/1" do
+    test "tells whether the volume is dirty, or says why not" do
+      case DiskSpace.volume_dirty(valid_directory_path()) do
+        {:ok, dirty} -> assert is_boolean(dirty)
+        {:error, %{reason: reason}} -> assert reason in [:unsupported, :access_denied]
+      end
+
+      {:ok, stats} = DiskSpace.stat(valid_directory_path(), volume_dirty: true)
+      assert is_nil(stats.volume_dirty) or is_boolean(stats.volume_dirty)
+    end
+  end
+
+  describe "media_ready/1" do
+    test "tells whether the media are in, or says why not" do
+      case DiskSpace.media_ready(valid_directory_path()) do
+        {:ok, readiness} -> assert readiness in [:ready, :no_media, :not_removable]
+        {:error, %{reason: reason}} -> assert reason == :unsupported
+      end
+    end
+  end
+
+  describe "quota/2" do
+    test "reads the quota of a user, or says why not" do
+      case DiskSpace.quota(valid_directory_path(), {:user, 0}) do
+        {:ok, %{bytes_used: used, inodes_used: inodes}} ->
+          assert is_integer(used) and is_integer(inodes)
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:quotas_not_enabled, :quotactl_failed, :unsupported]
+      end
+
+      missing = Path.join(valid_directory_path(), "disk_space_missing")
+      assert {:error, %{reason: _}} = DiskSpace.quota(missing, {:group, 0})
+      assert {:error, %{reason: _}} = DiskSpace.quota(missing, {:project, 1})
+    end
+  end
+
+  describe "container_context/0" do
+    test "tells whether in a container, or that it can't" do
+      case DiskSpace.container_context() do
+        {:ok, %{containerized: containerized, runtime: runtime, project_quota: quota}} ->
+          assert is_boolean(containerized)
+          assert runtime in [:kubernetes, :podman, :docker, :containerd, :lxc, :unknown]
+          assert quota in [nil, :unknown] or is_map(quota)
+
+        {:error, %{reason: reason}} ->
+          assert reason == :unsupported
+      end
+    end
+  end
+
+  describe "quota_info/1" do
+    test "reads the NTFS quota of the current user, or says why not" do
+      case DiskSpace.quota_info(valid_directory_path()) do
+        {:ok, %{used_bytes: used, enforced: enforced}} ->
+          assert is_integer(used) and is_boolean(enforced)
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:quotas_disabled, :winapi_failed, :unsupported]
+      end
+    end
+
+    test "is reflected in the stats of stat/2 on Windows" do
+      stats = DiskSpace.stat!(valid_directory_path())
+
+      case :os.type() do
+        {:win32, _} -> assert stats.is_quota_limited == stats.available < stats.free
+        _ -> refute Map.has_key?(stats, :is_quota_limited)
+      end
+    end
+  end
+
+  describe "stat/2 with sync" do
+    test "flushes before statting and reports how long it took" do
+      path = valid_directory_path()
+
+      for sync <- [:syncfs, :sync] do
+        assert {:ok, %{duration_ms: duration_ms} = stats} = DiskSpace.stat(path, sync: sync)
+        assert is_integer(duration_ms) and duration_ms >= 0
+        assert Map.get(stats, :sync_warning) in [nil, :eacces]
+      end
+
+      assert {:ok, stats} = DiskSpace.stat(path, sync: :none)
+      refute Map.has_key?(stats, :duration_ms)
+    end
+
+    test "rejects an unknown mode" do
+      assert {:error, %{reason: :invalid_option, info: :sync}} =
+               DiskSpace.stat(valid_directory_path(), sync: :fsync)
+    end
+  end
+
+  describe "stat/2 with scheduler" do
+    test "stats the same on every scheduler" do
+      path = valid_directory_path()
+      {:ok, %{total: total}} = DiskSpace.stat(path, humanize: nil)
+
+      # A budget the stat won't overrun, however busy the machine
+      for scheduler <- [:dirty_io, :dirty_cpu, :normal] do
+        assert {:ok, %{total: ^total}} =
+                 DiskSpace.stat(path, humanize: nil, scheduler: scheduler, budget_ms: 60_000)
+      end
+
+      assert {:error, %{reason: :invalid_option, info: :scheduler}} =
+               DiskSpace.stat(path, scheduler: :dirty)
+    end
+  end
+
+  describe "stat/2 with apfs_details" do
+    test "adds the container of APFS volumes, nil elsewhere" do
+      case DiskSpace.stat(valid_directory_path(), apfs_details: true) do
+        {:ok, %{apfs: nil}} ->
+          :ok
+
+        {:ok, %{apfs: %{container: "disk" <> _, container_free: free}}} ->
+          assert is_integer(free)
+      end
+    end
+
+    test "collapses the volumes of a container in stat_all_async/3" do
+      ref = make_ref()
+      assert :ok = DiskSpace.stat_all_async([collapse_apfs: true], self(), ref)
+      assert_receive {:disk_space_report, ^ref, entries} when is_list(entries), 10_000
+
+      mount_points = Enum.map(entries, & &1.mount_point)
+
+      for %{apfs_volumes: [first | others], mount_point: mount_point} <- entries do
+        assert first == mount_point
+        assert Enum.all?(others, &(&1 not in mount_points))
+      end
+    end
+  end
+
+  describe "capacity_details/1" do
+    test "reads the capacity of the volume as Finder reports it on macOS" do
+      case DiskSpace.capacity_details(valid_directory_path()) do
+        {:ok, %{important_capacity: important, purgeable: purgeable, statvfs: stats}} ->
+          assert is_integer(important) and is_integer(purgeable)
+          assert is_integer(stats.available)
+
+        {:error, %{reason: reason}} ->
+          assert reason == :unsupported
+      end
+    end
+  end
+
+  describe "apfs_snapshots/1" do
+    test "lists the snapshots of an APFS volume, or says why not" do
+      case DiskSpace.apfs_snapshots(valid_directory_path()) do
+        {:ok, %{count: count, snapshots: snapshots}} ->
+          assert count == length(snapshots)
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:requires_full_disk_access, :not_apfs, :unsupported]
+      end
+    end
+  end
+
+  describe "space_information/1" do
+    test "reads the actual and caller space of the volume on Windows" do
+      case DiskSpace.space_information(valid_directory_path()) do
+        {:ok, %{api: api, caller_available: available, allocation_unit_bytes: unit}} ->
+          assert api in [:get_disk_space_information, :get_disk_free_space_ex]
+          assert is_integer(available) and unit > 0
+
+        {:error, %{reason: reason}} ->
+          assert reason == :unsupported
+      end
+    end
+  end
+
+  describe "stat/2 with btrfs_details" do
+    test "adds the allocation of btrfs filesystems, nil elsewhere" do
+      case DiskSpace.stat(valid_directory_path(), btrfs_details: true) do
+        {:ok, %{btrfs: nil}} ->
+          :ok
+
+        {:ok, %{btrfs: %{profiles: [_ | _], estimated_writable_bytes: writable}}} ->
+          assert is_integer(writable)
+      end
+
+      refute Map.has_key?(DiskSpace.stat!(valid_directory_path()), :btrfs)
+    end
+  end
+
+  describe "stat/2 with zfs_details" do
+    test "adds the dataset of ZFS filesystems, nil elsewhere" do
+      case DiskSpace.stat(valid_directory_path(), zfs_details: true) do
+        {:ok, %{zfs: nil}} ->
+          :ok
+
+        {:ok, %{zfs: %{dataset: dataset, limited_by: limited_by}}} ->
+          assert is_binary(dataset)
+          assert limited_by in [:dataset, :pool]
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:zfs_unavailable, :unsupported]
+      end
+    end
+  end
+
+  describe "btrfs_qgroup/1" do
+    test "reads the qgroup of a btrfs subvolume, or says why not" do
+      case DiskSpace.btrfs_qgroup(valid_directory_path()) do
+        {:ok, %{subvolume_id: id, qgroup_id: qgroup_id, parents: parents}} ->
+          assert qgroup_id == "0/#{id}"
+          assert is_list(parents)
+
+        {:error, %{reason: reason}} ->
+          assert reason in [:not_btrfs, :qgroups_disabled, :qgroup_not_found, :btrfs_ioctl_failed]
+      end
+    end
+  end
+
+  describe "filesystem_overhead/1" do
+    test "reports the reserved blocks of ext filesystems, nil elsewhere" do
+      case DiskSpace.filesystem_overhead(valid_directory_path()) do
+        {:ok, %{fs_type: fs_type, reserved_blocks: reserved, non_root_total: usable}}
+        when fs_type in ["ext2", "ext3", "ext4"] ->
+          assert is_integer(reserved) and is_integer(usable)
+
+        {:ok, %{reserved_blocks: nil, reserved_percent: nil, overhead_bytes: nil}} ->
+          :ok
+
+        {:error, %{reason: :unsupported}} ->
+          :ok
+      end
+    end
+  end
+
+  describe "nif_stats/0" do
+    test "counts the calls, their failures and latencies" do
+      assert :ok = DiskSpace.nif_stats_reset()
+      {:ok, _} = DiskSpace.stat(valid_directory_path())
+      {:error, _} = DiskSpace.stat(Path.join(valid_directory_path(), "disk_space_missing"))
+      {:ok, _} = DiskSpace.list_mounts()
+
+      assert %{stat_fs: stat_fs, du: %{}, list_mounts: list_mounts, watcher_tick: %{}} =
+               DiskSpace.nif_stats()
+
+      assert %{calls: calls, failures: failures, latency_us: %{histogram: histogram}} =
+               stat_fs
+
+      assert calls >= 2
+      assert Enum.sum(Map.values(failures)) >= 1
+      assert length(histogram) == 8
+      assert {:infinity, _} = List.last(histogram)
+      assert %{calls: mount_reads} = list_mounts
+      assert mount_reads >= 1
+    end
+  end
+
+  describe "bench_stat/3" do
+    test "reports the latencies of the stats, or that it isn't built in" do
+      case DiskSpace.bench_stat(valid_directory_path(), 100, encode: true) do
+        {:ok, report} ->
+          assert %{iterations: 100, encode: true, failures: 0, backend: backend} = report
+          assert is_binary(backend)
+          assert %{min: min, median: median, p99: p99, max: max} = report.latency_ns
+          assert min <= median and median <= p99 and p99 <= max
+          assert %{total: total, per_call: per_call} = report.allocations
+          assert is_integer(total) and is_float(per_call)
+
+        {:error, %{reason: reason}} ->
+          assert reason == :bench_disabled
+      end
+    end
+  end
+
+  describe "stat_fs_async/3" do
+    test "sends the result of stat/2 as a message" do
+      path = valid_directory_path()
+      ref = make_ref()
+      assert :ok = DiskSpace.stat_fs_async(path, self(), ref)
+      assert_receive {:disk_space, ^ref, {:ok, stats}}, 5000
+      assert Enum.sort(Map.keys(stats)) == [:available, :free, :total, :used]
+      assert stats.total >= stats.used
+    end
+
+    test "sends errors in the same envelope" do
+      missing = Path.join(valid_directory_path(), "nonexistent_#{System.unique_integer()}")
+      ref = make_ref()
+      assert :ok = DiskSpace.stat_fs_async(missing, self(), ref)
+      assert_receive {:disk_space, ^ref, {:error, %{reason: reason}}}, 5000
+      assert {:error, %{reason: ^reason}} = DiskSpace.stat(missing)
+    end
+
+    test "runs many stats concurrently and drops results for exited processes" do
+      path = valid_directory_path()
+      refs = for _ <- 1..50, do: make_ref()
+      for ref <- refs, do: :ok = DiskSpace.stat_fs_async(path, self(), ref)
+
+      for ref <- refs do
+        assert_receive {:disk_space, ^ref, {:ok, _}}, 5000
+      end
+
+      dead = spawn(fn -> :ok end)
+      Process.sleep(10)
+      refute Process.alive?(dead)
+      ref = make_ref()
+      assert :ok = DiskSpace.stat_fs_async(path, dead, ref)
+      assert :ok = DiskSpace.stat_fs_async(path, self(), ref)
+      assert_receive {:disk_space, ^ref, {:ok, _}}, 5000
+    end
+  end
+
+  describe "stat_fs_many/2" do
+    test "returns a result per path, in order, failures included" do
+      path = valid_directory_path()
+      missing = Path.join(path, "nonexistent_#{System.unique_integer()}")
+
+      results = DiskSpace.stat_fs_many([path, missing, path])
+      assert [{^path, {:ok, stats}}, {^missing, {:error, %{reason: reason}}}, {^path, _}] = results
+
+      assert Enum.sort(Map.keys(stats)) == [:available, :free, :total, :used]
+      assert {:error, %{reason: ^reason}} = DiskSpace.stat(missing)
+      assert DiskSpace.stat_fs_many([]) == []
+    end
+
+    test "stats repeated paths once with unique: true" do
+      path = valid_directory_path()
+      missing = Path.join(path, "nonexistent_#{System.unique_integer()}")
+      results = DiskSpace.stat_fs_many([path, missing, path, missing], unique: true)
+      assert [{^path, first}, {^missing, error}, {^path, first}, {^missing, error}] = results
+      assert {:ok, _} = first
+      assert {:error, _} = error
+
+      assert {:error, %{reason: :invalid_option, info: :unique}} =
+               DiskSpace.stat_fs_many([path], unique: 1)
+    end
+
+    test "keeps the order with concurrency and a timeout" do
+      path = valid_directory_path()
+      missing = Path.join(path, "nonexistent_#{System.unique_integer()}")
+      paths = List.flatten(List.duplicate([path, missing, path], 5))
+
+      results = DiskSpace.stat_fs_many(paths, concurrency: 4, timeout: 10_000)
+      assert Enum.map(results, &elem(&1, 0)) == paths
+
+      for {result_path, result} <- results do
+        if result_path == path, do: assert({:ok, _} = result), else: assert({:error, _} = result)
+      end
+
+      assert {:error, %{reason: :invalid_option, info: :concurrency}} =
+               DiskSpace.stat_fs_many([path], concurrency: 0)
+
+      assert {:error, %{reason: :invalid_option, info: :timeout}} =
+               DiskSpace.stat_fs_many([path], timeout: :infinity)
+    end
+
+    test "returns a list per figure with columnar: true" do
+      path = valid_directory_path()
+      missing = Path.join(path, "nonexistent_#{System.unique_integer()}")
+      paths = [path, missing, path]
+
+      columns = DiskSpace.stat_fs_many(paths, columnar: true)
+      assert %{paths: ^paths, available: [available, nil, _], errors: errors} = columns
+      assert Enum.sort(Map.keys(columns)) == [:available, :errors, :free, :paths, :total, :used]
+      assert is_integer(available)
+
+      for key <- [:free, :total, :used], do: assert([_, nil, _] = columns[key])
+      assert [{^missing, {:error, %{reason: _}}}] = errors
+
+      assert %{paths: [], available: [], errors: []} =
+               DiskSpace.stat_fs_many([], columnar: true, unique: true)
+
+      assert {:error, %{reason: :invalid_option, info: :columnar}} =
+               DiskSpace.stat_fs_many([path], columnar: :yes)
+    end
+  end
+
+  describe "stat_all_async/3" do
+    test "reports every mount with how long its stat took" do
+      ref = make_ref()
+      assert :ok = DiskSpace.stat_all_async([concurrency: 4, timeout: 5_000], self(), ref)
+
+      assert {:error, %{reason: :already_running, info: nil}} =
+               DiskSpace.stat_all_async([], self(), ref)
+
+      assert_receive {:disk_space_report, ^ref, mounts}, 10_000
+      assert is_list(mounts)
+
+      for mount <- mounts do
+        assert %{mount_point: _, at: at, result: result, duration_ms: ms} = mount
+        assert is_integer(at)
+        assert is_float(ms) and ms >= 0
+        assert match?({:ok, _}, result) or match?({:error, %{reason: _}}, result)
+      end
+
+      # The reference is free again once the report is out
+      assert :ok = DiskSpace.stat_all_async([exclude: [:pseudo, :squashfs_loop]], self(), ref)
+      assert_receive {:disk_space_report, ^ref, _}, 10_000
+
+      assert {:error, %{reason: :invalid_option, info: :concurrency}} =
+               DiskSpace.stat_all_async([concurrency: 0], self(), make_ref())
+    end
+  end
+
+  describe "watch/2" do
+    test "sends a sample every interval until unwatched" do
+      path = valid_directory_path()
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 20)
+
+      for _ <- 1..3 do
+        assert_receive {:disk_space_sample, ^ref, %{path: ^path, at: at, result: result}}, 5000
+        assert is_integer(at)
+        assert {:ok, %{available: _, free: _, total: _, used: _}} = result
+      end
+
+      assert :ok = DiskSpace.unwatch(watcher)
+      assert :ok = DiskSpace.unwatch(watcher)
+      Process.sleep(50)
+      flush_messages()
+      refute_receive {:disk_space_sample, ^ref, _}, 100
+    end
+
+    test "keeps sending error samples for a missing path and honors dest" do
+      missing = Path.join(valid_directory_path(), "nonexistent_#{System.unique_integer()}")
+      parent = self()
+      dest = spawn(fn -> forward_samples(parent) end)
+      {ref, watcher} = DiskSpace.watch(missing, interval_ms: 10, dest: dest)
+
+      for _ <- 1..2 do
+        assert_receive {:forwarded, {:disk_space_sample, ^ref, %{result: {:error, _}}}}, 5000
+      end
+
+      DiskSpace.unwatch(watcher)
+
+      assert {:error, %{reason: :invalid_option, info: :interval_ms}} =
+               DiskSpace.watch(missing, interval_ms: 0)
+
+      assert {:error, %{reason: :invalid_option, info: :dest}} =
+               DiskSpace.watch(missing, dest: :nobody)
+    end
+
+    test "sends alerts on crossing the thresholds only" do
+      path = valid_directory_path()
+      # Less than 100 % of the space is ever available
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 10, alert_below: %{percent: 100})
+      assert_receive {:disk_space_alert, ^ref, :low, %{path: ^path, result: {:ok, _}}}, 5000
+      Process.sleep(50)
+      refute_received {:disk_space_alert, ^ref, _, _}
+      refute_received {:disk_space_sample, ^ref, _}
+      assert %{alert_state: :low} = DiskSpace.watcher_info(watcher)
+      DiskSpace.unwatch(watcher)
+
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 10, alert_below: %{bytes: 0})
+      Process.sleep(50)
+      assert %{alert_state: :ok, samples: samples} = DiskSpace.watcher_info(watcher)
+      assert samples > 1
+      refute_received {_, ^ref, _, _}
+      DiskSpace.unwatch(watcher)
+      assert %{alert_state: nil} = DiskSpace.watcher_info(elem(DiskSpace.watch(path), 1))
+
+      for alert_below <- [%{}, %{percent: 101}, %{bytes: -1}, 5] do
+        assert {:error, %{reason: :invalid_option, info: :alert_below}} =
+                 DiskSpace.watch(path, alert_below: alert_below)
+      end
+    end
+
+    test "debounces alerts and validates the hysteresis" do
+      path = valid_directory_path()
+
+      {ref, watcher} =
+        DiskSpace.watch(path,
+          interval_ms: 10,
+          alert_below: %{percent: 100},
+          hysteresis: %{bytes: 1024, percent: 1.5},
+          debounce_samples: 3
+        )
+
+      assert_receive {:disk_space_alert, ^ref, :low, _}, 5000
+      assert %{alert_state: :low, samples: samples} = DiskSpace.watcher_info(watcher)
+      assert samples >= 3
+      DiskSpace.unwatch(watcher)
+
+      assert {:error, %{reason: :invalid_option, info: :hysteresis}} =
+               DiskSpace.watch(path, alert_below: %{bytes: 1}, hysteresis: %{percent: -1})
+
+      assert {:error, %{reason: :invalid_option, info: :debounce_samples}} =
+               DiskSpace.watch(path, alert_below: %{bytes: 1}, debounce_samples: 0)
+    end
+
+    test "estimates the fill rate once there are enough samples" do
+      path = valid_directory_path()
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 10, trend_min_samples: 4)
+
+      for _ <- 1..3 do
+        assert_receive {:disk_space_sample, ^ref, sample}, 5000
+        assert %{fill_rate_bytes_per_sec: nil, eta_full_seconds: nil} = sample
+      end
+
+      assert_receive {:disk_space_sample, ^ref, %{fill_rate_bytes_per_sec: rate} = sample}, 5000
+      assert is_float(rate)
+      assert is_nil(sample.eta_full_seconds) or sample.eta_full_seconds > 0
+      DiskSpace.unwatch(watcher)
+      assert is_float(DiskSpace.watcher_info(watcher).fill_rate_bytes_per_sec)
+
+      assert {:error, %{reason: :invalid_option, info: :trend_min_samples}} =
+               DiskSpace.watch(path, trend_min_samples: 1)
+
+      assert {:error, %{reason: :invalid_option, info: :trend_window_ms}} =
+               DiskSpace.watch(path, trend_window_ms: 0)
+    end
+
+    test "aligns and jitters the samples, and changes the interval on the fly" do
+      path = valid_directory_path()
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 50, align_to: :interval)
+
+      for _ <- 1..3 do
+        assert_receive {:disk_space_sample, ^ref, %{at: at}}, 5000
+        # Scheduled on the monotonic clock, so only roughly on the multiple
+        assert rem(at, 50) < 25
+      end
+
+      :ok = DiskSpace.set_interval(watcher, 10)
+      assert %{interval_ms: 10} = DiskSpace.watcher_info(watcher)
+      DiskSpace.unwatch(watcher)
+      assert :ok = DiskSpace.set_interval(watcher, 20)
+
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 10, jitter_ms: 5)
+      assert_receive {:disk_space_sample, ^ref, _}, 5000
+      assert_receive {:disk_space_sample, ^ref, _}, 5000
+      DiskSpace.unwatch(watcher)
+
+      assert {:error, %{reason: :invalid_option, info: :align_to}} =
+               DiskSpace.watch(path, align_to: :minute)
+
+      assert {:error, %{reason: :invalid_option, info: :jitter_ms}} =
+               DiskSpace.watch(path, jitter_ms: -1)
+    end
+
+    @tag :unix
+    test "tells when the path changes filesystem, holding alerts until acknowledged" do
+      name = "disk_space_identity_#{System.unique_integer([:positive])}"
+      dir = Path.join(System.tmp_dir!(), name)
+      File.mkdir_p!(dir)
+      on_exit(fn -> File.rm_rf!(dir) end)
+
+      {ref, watcher} =
+        DiskSpace.watch(dir,
+          interval_ms: 20,
+          on_filesystem_change: :hold_alerts,
+          alert_below: %{percent: 100}
+        )
+
+      assert_receive {:disk_space_alert, ^ref, :low, _}, 5000
+      assert %{device: old, alerts_held: false} = DiskSpace.watcher_info(watcher)
+      assert is_integer(old)
+
+      # Mounting needs root, and a mount namespace that allows it
+      case System.cmd("mount", ["-t", "tmpfs", "tmpfs", dir], stderr_to_stdout: true) do
+        {_, 0} ->
+          on_exit(fn -> System.cmd("umount", [dir]) end)
+          assert_receive {:disk_space_event, ^ref, :filesystem_changed, %{old: ^old, new: new}},
+                         5000
+
+          assert %{device: ^old, alerts_held: true} = DiskSpace.watcher_info(watcher)
+          assert :ok = DiskSpace.watcher_ack(watcher)
+          assert %{device: ^new, alerts_held: false} = DiskSpace.watcher_info(watcher)
+          System.cmd("umount", [dir])
+          assert_receive {:disk_space_event, ^ref, :filesystem_changed, %{old: ^new, new: ^old}},
+                         5000
+
+          assert %{device: ^new, alerts_held: true} = DiskSpace.watcher_info(watcher)
+
+        _ ->
+          :ok
+      end
+
+      DiskSpace.unwatch(watcher)
+
+      assert {:error, %{reason: :invalid_option, info: :on_filesystem_change}} =
+               DiskSpace.watch(dir, on_filesystem_change: :ignore)
+    end
+
+    test "pauses and resumes, sampling right away on resuming" do
+      path = valid_directory_path()
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 60_000)
+      assert_receive {:disk_space_sample, ^ref, _}, 5000
+      assert :ok = DiskSpace.watcher_pause(watcher)
+      assert :ok = DiskSpace.watcher_pause(watcher)
+      assert %{state: :paused, running: true, samples: 1} = DiskSpace.watcher_info(watcher)
+      assert [_] = DiskSpace.watcher_history(watcher, 10)
+
+      assert :ok = DiskSpace.watcher_resume(watcher)
+      assert_receive {:disk_space_sample, ^ref, _}, 5000
+      assert %{state: :running, samples: 2} = DiskSpace.watcher_info(watcher)
+      assert :ok = DiskSpace.watcher_resume(watcher)
+      refute_receive {:disk_space_sample, ^ref, _}, 100
+
+      DiskSpace.unwatch(watcher)
+      assert %{state: :stopped} = DiskSpace.watcher_info(watcher)
+    end
+
+    test "echoes the labels in every message, also once their creator has exited" do
+      path = valid_directory_path()
+      labels = %{tenant: "acme", role: "wal", weights: [1.5, {:a, "b"}]}
+      parent = self()
+
+      starter =
+        spawn(fn ->
+          send(parent, DiskSpace.watch(path, interval_ms: 10, dest: parent, labels: labels))
+        end)
+
+      assert_receive {ref, watcher}, 5000
+      Process.sleep(10)
+      refute Process.alive?(starter)
+      assert_receive {:disk_space_sample, ^ref, %{labels: ^labels}}, 5000
+      assert_receive {:disk_space_sample, ^ref, %{labels: ^labels}}, 5000
+      assert %{labels: ^labels} = DiskSpace.watcher_info(watcher)
+      assert Enum.any?(DiskSpace.list_watchers(), &match?(%{ref: ^ref, labels: ^labels}, &1))
+      DiskSpace.unwatch(watcher)
+
+      {ref, watcher} = DiskSpace.watch(path)
+      assert_receive {:disk_space_sample, ^ref, %{labels: nil}}, 5000
+      DiskSpace.unwatch(watcher)
+    end
+
+    test "sends only the samples that changed enough with :notify" do
+      path = valid_directory_path()
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 10, notify: {:on_change_by, 1024 ** 4})
+
+      assert_receive {:disk_space_sample, ^ref, %{result: {:ok, _}}}, 5000
+      Process.sleep(100)
+      DiskSpace.unwatch(watcher)
+      assert %{samples: samples} = DiskSpace.watcher_info(watcher)
+      assert samples > 1
+      refute_received {:disk_space_sample, ^ref, _}
+
+      for notify <- [:sometimes, {:on_change_by, -1}, {:on_change, 1}] do
+        assert {:error, %{reason: :invalid_option, info: :notify}} =
+                 DiskSpace.watch(path, notify: notify)
+      end
+    end
+
+    test "sends maps or structs with :message_format" do
+      path = valid_directory_path()
+      {ref, watcher} = DiskSpace.watch(path, message_format: :map, labels: :tagged)
+
+      assert_receive %{event: :sample, type: nil, ref: ^ref, data: %{labels: :tagged}}, 5000
+      DiskSpace.unwatch(watcher)
+
+      {ref, watcher} =
+        DiskSpace.watch(path, message_format: :struct, alert_below: %{percent: 100})
+
+      assert_receive %DiskSpace.Event{event: :alert, type: :low, ref: ^ref, data: sample},
+                     5000
+
+      assert %{result: {:ok, _}} = sample
+      DiskSpace.unwatch(watcher)
+
+      {ref, watcher} = DiskSpace.watch_all_mounts(message_format: :struct)
+      assert_receive %DiskSpace.Event{event: :report, ref: ^ref, data: mounts}, 5000
+      assert is_list(mounts)
+      DiskSpace.unwatch(watcher)
+
+      assert {:error, %{reason: :invalid_option, info: :message_format}} =
+               DiskSpace.watch(path, message_format: :json)
+    end
+
+    test "stays responsive while its stats don't time out, and resets by path or watcher" do
+      path = valid_directory_path()
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 10, timeout: 5_000)
+
+      assert_receive {:disk_space_sample, ^ref, %{circuit: :responsive}}, 5000
+      assert %{circuit: :responsive, timeouts: 0} = DiskSpace.watcher_info(watcher)
+      assert :ok = DiskSpace.reset(watcher)
+      assert :ok = DiskSpace.reset(path)
+      refute_received {:disk_space_event, ^ref, _, _}
+      DiskSpace.unwatch(watcher)
+
+      assert {:error, %{reason: :invalid_path}} = DiskSpace.reset(:nowhere)
+
+      for {key, value} <- [timeout: -1, max_consecutive_timeouts: 0, backoff_max_ms: 0] do
+        assert {:error, %{reason: :invalid_option, info: ^key}} =
+                 DiskSpace.watch(path, [{key, value}])
+      end
+    end
+  end
+
+  describe "export_watchers/0" do
+    test "stops every watcher and starts it again where it left off" do
+      path = valid_directory_path()
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 2_000, labels: :exported)
+      {paused_ref, paused} = DiskSpace.watch(path, paused: true)
+      assert_receive {:disk_space_sample, ^ref, %{at: at}}, 5000
+      assert %{state: :paused} = DiskSpace.watcher_info(paused)
+
+      descriptors = DiskSpace.export_watchers()
+      assert %{state: :stopped} = DiskSpace.watcher_info(watcher)
+      assert [] = DiskSpace.list_watchers()
+
+      assert %{path: ^path, opts: %{interval_ms: 2_000, start_at: start_at}} =
+               Enum.find(descriptors, &(&1.ref == ref))
+
+      assert start_at >= at + 2_000
+      assert %{opts: %{paused: true}} = Enum.find(descriptors, &(&1.ref == paused_ref))
+
+      imported = DiskSpace.import_watchers(descriptors)
+      assert {^ref, watcher} = List.keyfind(imported, ref, 0)
+      refute_receive {:disk_space_sample, ^ref, _}, 1_000
+      assert_receive {:disk_space_sample, ^ref, %{at: next, labels: :exported}}, 5000
+      assert next >= start_at
+      assert {^paused_ref, paused} = List.keyfind(imported, paused_ref, 0)
+      assert %{state: :paused} = DiskSpace.watcher_info(paused)
+
+      Enum.each([watcher, paused], &DiskSpace.unwatch/1)
+    end
+  end
+
+  describe "watch_all_mounts/1" do
+    test "reports every mount that survives the filters" do
+      {ref, watcher} = DiskSpace.watch_all_mounts(interval_ms: 20, concurrency: 4)
+      assert_receive {:disk_space_report, ^ref, mounts}, 5000
+      assert_receive {:disk_space_report, ^ref, _}, 5000
+      refute_received {:mounts_changed, ^ref, _}
+
+      {:ok, listed} = DiskSpace.list_mounts(exclude: :pseudo)
+      assert Enum.map(mounts, & &1.mount_point) == Enum.map(listed, & &1.mount_point)
+
+      for mount <- mounts do
+        assert %{fs_type: _, device: _, at: at, result: result} = mount
+        assert is_integer(at)
+        assert match?({:ok, _}, result) or match?({:error, %{reason: _}}, result)
+      end
+
+      assert %{path: nil, samples: samples} = DiskSpace.watcher_info(watcher)
+      assert samples >= 2
+      DiskSpace.unwatch(watcher)
+
+      assert {:error, %{reason: :invalid_option, info: :exclude}} =
+               DiskSpace.watch_all_mounts(exclude: [:nope])
+
+      assert {:error, %{reason: :invalid_option, info: :concurrency}} =
+               DiskSpace.watch_all_mounts(concurrency: 0)
+    end
+
+    @tag :unix
+    test "sends the changes to the mount table" do
+      name = "disk_space_mounts_#{System.unique_integer([:positive])}"
+      dir = Path.join(System.tmp_dir!(), name)
+      File.mkdir_p!(dir)
+      on_exit(fn -> File.rm_rf!(dir) end)
+      {ref, watcher} = DiskSpace.watch_all_mounts(interval_ms: 20)
+      assert_receive {:disk_space_report, ^ref, _}, 5000
+
+      # Binding needs root, and a mount namespace that allows it
+      case System.cmd("mount", ["--bind", dir, dir], stderr_to_stdout: true) do
+        {_, 0} ->
+          on_exit(fn -> System.cmd("umount", [dir]) end)
+          assert_receive {:mounts_changed, ^ref, %{added: [%{mount_point: ^dir}]}}, 5000
+          System.cmd("umount", [dir])
+          assert_receive {:mounts_changed, ^ref, %{removed: [%{mount_point: ^dir}]}}, 5000
+
+        _ ->
+          :ok
+      end
+
+      DiskSpace.unwatch(watcher)
+    end
+  end
+
+  describe "list_watchers/0 and watcher_info/1" do
+    test "describe the running watchers" do
+      path = valid_directory_path()
+      missing = Path.join(path, "nonexistent_#{System.unique_integer()}")
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 60_000)
+      {missing_ref, missing_watcher} = DiskSpace.watch(missing, interval_ms: 10)
+      assert_receive {:disk_space_sample, ^ref, sample}, 5000
+      assert_receive {:disk_space_sample, ^missing_ref, _}, 5000
+      assert_receive {:disk_space_sample, ^missing_ref, _}, 5000
+
+      assert %{ref: ^ref, path: ^path, interval_ms: 60_000, running: true} =
+               info = DiskSpace.watcher_info(watcher)
+
+      assert info.dest == self()
+      assert %{samples: 1, last_sample: ^sample, last_error: nil} = info
+
+      missing_info = DiskSpace.watcher_info(missing_watcher)
+      assert missing_info.samples >= 2
+      assert %{result: {:error, _}} = missing_info.last_error
+
+      listed = Enum.filter(DiskSpace.list_watchers(), &(&1.ref in [ref, missing_ref]))
+      assert Enum.sort(Enum.map(listed, & &1.path)) == Enum.sort([path, missing])
+
+      for %{watcher: handle} <- listed, do: assert(:ok = DiskSpace.unwatch(handle))
+      Process.sleep(100)
+      assert %{running: false} = DiskSpace.watcher_info(watcher)
+      refute Enum.any?(DiskSpace.list_watchers(), &(&1.ref in [ref, missing_ref]))
+    end
+  end
+
+  describe "watcher_history/2" do
+    test "returns the last samples, oldest first, up to history_size" do
+      path = valid_directory_path()
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 10, history_size: 3)
+      for _ <- 1..5, do: assert_receive({:disk_space_sample, ^ref, _}, 5000)
+      DiskSpace.unwatch(watcher)
+
+      history = DiskSpace.watcher_history(watcher, 10)
+      assert [{at1, available, used}, {at2, _, _}, {at3, _, _}] = history
+      assert at1 <= at2 and at2 <= at3
+      assert is_integer(available) and is_integer(used)
+      assert DiskSpace.watcher_history(watcher, 1) == [List.last(history)]
+      assert DiskSpace.watcher_history(watcher, 0) == []
+
+      {_ref, watcher} = DiskSpace.watch(path, history_size: 0)
+      assert DiskSpace.watcher_history(watcher, 10) == []
+      DiskSpace.unwatch(watcher)
+
+      assert {:error, %{reason: :invalid_option, info: :history_size}} =
+               DiskSpace.watch(path, history_size: 100_000)
+    end
+  end
+
+  describe "stat_fs_sample/1 and stat_fs_delta/2" do
+    test "samples carry the device and a monotonic timestamp" do
+      path = valid_directory_path()
+      assert {:ok, a} = DiskSpace.stat_fs_sample(path)
+      assert %{available: _, free: _, total: _, used: _, device: _, measured_at: _} = a
+      Process.sleep(5)
+      assert {:ok, b} = DiskSpace.stat_fs_sample(path)
+      assert b.device == a.device
+      assert b.measured_at > a.measured_at
+
+      assert {:ok, %{elapsed_ms: elapsed_ms, used_per_sec: rate}} = DiskSpace.stat_fs_delta(a, b)
+      assert elapsed_ms == b.measured_at - a.measured_at
+      assert is_float(rate)
+
+      missing = Path.join(path, "nonexistent_#{System.unique_integer()}")
+      assert {:error, %{reason: reason}} = DiskSpace.stat_fs_sample(missing)
+      assert {:error, %{reason: ^reason}} = DiskSpace.stat(missing)
+    end
+
+    test "computes deltas and rates" do
+      a = %{available: 1000, free: 1200, used: 500, total: 2000, device: 1, measured_at: 0}
+      b = %{a | available: 400, free: 600, used: 1100, measured_at: 2000}
+
+      assert {:ok, delta} = DiskSpace.stat_fs_delta(a, b)
+
+      assert delta == %{
+               available: -600,
+               free: -600,
+               used: 600,
+               elapsed_ms: 2000,
+               available_per_sec: -300.0,
+               free_per_sec: -300.0,
+               used_per_sec: 300.0
+             }
+
+      assert {:ok, %{used: 600, elapsed_ms: nil, used_per_sec: nil}} =
+               DiskSpace.stat_fs_delta(Map.delete(a, :measured_at), b)
+
+      assert {:ok, %{elapsed_ms: nil}} = DiskSpace.stat_fs_delta(a, %{b | measured_at: 0})
+
+      assert {:error, %{reason: :different_filesystem, info: %{a: 1, b: 2}}} =
+               DiskSpace.stat_fs_delta(a, %{b | device: 2})
+
+      assert {:error, %{reason: :invalid_sample}} = DiskSpace.stat_fs_delta(a, %{})
+      assert {:error, %{reason: :invalid_sample}} = DiskSpace.stat_fs_delta(a, {:ok, b})
+    end
+  end
+
+  describe "stat!/2" do
+    test "returns stats map directly on success" do
+      path = valid_directory_path()
+      stats = DiskSpace.stat!(path)
+      assert is_map(stats)
+      assert Enum.sort(Map.keys(stats)) == stat_keys()
+      assert is_integer(stats.available)
+      assert is_integer(stats.free)
+      assert is_integer(stats.total)
+      assert is_integer(stats.used)
+      assert stats.total >= stats.free
+      assert stats.total >= stats.used
+    end
+
+    test "raises DiskSpace.Error on failure" do
+      path = Path.join(valid_directory_path(), "nonexistent_#{System.unique_integer()}")
+
+      assert_raise DiskSpace.Error, fn ->
+        DiskSpace.stat!(path)
+      end
+    end
+  end
+
+  describe "humanize/2" do
+    test "returns {:error, reason} unchanged" do
+      assert {:error, :eio} = DiskSpace.humanize({:error, :eio}, :binary)
+    end
+
+    test "humanizes byte counts in {:ok, stats_map}" do
+      input = {:ok, %{free: 123_456_789}}
+      {:ok, result} = DiskSpace.humanize(input, :binary)
+      assert is_binary(result.free)
+      assert String.contains?(result.free, "MiB")
+    end
+
+    test "humanizes byte counts in map directly" do
+      input = %{total: 1_000_000}
+      result = DiskSpace.humanize(input, :decimal)
+      assert is_binary(result.total)
+      assert String.contains?(result.total, "MB")
+    end
+  end
+
+  describe "list_mounts/1" do
+    test "returns mount maps" do
+      assert {:ok, [_ | _] = mounts} = DiskSpace.list_mounts()
+
+      for mount <- mounts do
+        assert is_binary(mount.mount_point)
+        assert is_binary(mount.fs_type)
+        assert is_binary(mount.device)
+        assert is_list(mount.options)
+        assert is_boolean(mount.read_only)
+      end
+    end
+
+    test "adds the disks beneath each mount with device_info" do
+      {:ok, mounts} = DiskSpace.list_mounts(device_info: true)
+
+      for mount <- mounts do
+        assert is_nil(mount.device_info) or is_list(mount.device_info)
+      end
+
+      {:ok, [mount | _]} = DiskSpace.list_mounts()
+      refute Map.has_key?(mount, :device_info)
+
+      assert {:error, %{reason: :invalid_option, info: :device_info}} =
+               DiskSpace.list_mounts(device_info: :yes)
+    end
+
+    test "identifies the filesystem of each mount with volume_id" do
+      {:ok, mounts} = DiskSpace.list_mounts(volume_id: true)
+
+      for mount <- mounts do
+        assert is_nil(mount.volume_id) == is_nil(mount.volume_id_raw)
+        if mount.volume_id, do: assert(mount.volume_id == String.downcase(mount.volume_id))
+      end
+
+      {:ok, stats} = DiskSpace.stat(valid_directory_path(), volume_id: true)
+      assert is_nil(stats.volume_id) or is_binary(stats.volume_id)
+      refute Map.has_key?(DiskSpace.stat!(valid_directory_path()), :volume_id)
+    end
+
+    test "tells the media of each drive with media" do
+      media = [:fixed, :removable, :usb_fixed, :cdrom, :ramdisk, :remote, :unknown, nil]
+      {:ok, mounts} = DiskSpace.list_mounts(media: true)
+      assert Enum.all?(mounts, &(&1.media in media))
+
+      {:ok, stats} = DiskSpace.stat(valid_directory_path(), media: true)
+      assert stats.media in media
+    end
+
+    test "relates memory-backed mounts to RAM" do
+      {:ok, mounts} = DiskSpace.list_mounts()
+
+      for %{fs_type: "tmpfs", memory_backed: true} = mount <- mounts do
+        assert is_boolean(mount.noswap)
+
+        if mount.size_bytes,
+          do: assert(is_float(mount.size_is_ram_fraction) and mount.size_is_ram_fraction > 0)
+      end
+
+      refute Enum.any?(mounts, &(&1.fs_type == "tmpfs" and not Map.has_key?(&1, :memory_backed)))
+
+      case DiskSpace.stat("/dev/shm", tmpfs_details: true) do
+        {:ok, %{tmpfs: tmpfs}} -> assert is_nil(tmpfs) or tmpfs.memory_backed
+        {:error, %{reason: _}} -> :ok
+      end
+    end
+
+    test "exclude presets only ever remove entries" do
+      {:ok, all} = DiskSpace.list_mounts()
+
+      {:ok, filtered} =
+        DiskSpace.list_mounts(exclude: [:pseudo, :squashfs_loop, :appimage])
+
+      assert length(filtered) <= length(all)
+      assert Enum.all?(filtered, &(&1 in all))
+      refute Enum.any?(filtered, &(&1.fs_type in ["proc", "sysfs", "devpts"]))
+    end
+
+    test "excludes by filesystem type and path prefix" do
+      {:ok, all} = DiskSpace.list_mounts()
+      %{fs_type: fs_type} = hd(all)
+
+      {:ok, filtered} = DiskSpace.list_mounts(exclude: {:fs_type, fs_type})
+      refute Enum.any?(filtered, &(&1.fs_type == fs_type))
+
+      {:ok, filtered} = DiskSpace.list_mounts(exclude: [{:path_prefix, "/"}])
+      refute Enum.any?(filtered, &String.starts_with?(&1.mount_point, "/"))
+    end
+
+    test "rejects unknown presets" do
+      assert {:error, %{reason: :invalid_option, info: :exclude}} =
+               DiskSpace.list_mounts(exclude: :bogus)
+    end
+  end
+
+  describe "snapshot/1 and mounts_diff/2" do
+    test "a snapshot has no differences with itself or the current mounts" do
+      assert {:ok, snapshot} = DiskSpace.snapshot()
+      assert is_reference(snapshot)
+      empty = %{added: [], removed: [], changed: []}
+      assert {:ok, ^empty} = DiskSpace.mounts_diff(snapshot, snapshot)
+
+      {:ok, mounts} = DiskSpace.list_mounts()
+      assert {:ok, ^empty} = DiskSpace.mounts_diff(mounts, mounts)
+    end
+
+    test "reports added, removed and changed mounts" do
+      {:ok, [first, second | rest]} = DiskSpace.list_mounts()
+      remounted = %{second | read_only: not second.read_only, mount_id: nil}
+
+      assert {:ok, %{added: [^first], removed: [], changed: []}} =
+               DiskSpace.mounts_diff(rest, [first | rest])
+
+      assert {:ok, %{added: [], removed: [^first], changed: [%{old: ^second, new: ^remounted}]}} =
+               DiskSpace.mounts_diff([first, second | rest], [remounted | rest])
+    end
+
+    test "rejects anything that isn't a mount table" do
+      assert {:error, %{reason: :invalid_snapshot}} = DiskSpace.mounts_diff(:foo, [])
+      assert {:error, %{reason: :invalid_snapshot}} = DiskSpace.mounts_diff([%{}], [])
+    end
+  end
+
+  describe "du/2" do
+    setup do
+      root = Path.join(System.tmp_dir!(), "disk_space_du_#{System.unique_integer([:positive])}")
+      File.mkdir_p!(Path.join(root, "a/b"))
+      File.write!(Path.join(root, "a/one.bin"), :binary.copy(<<1>>, 10_000))
+      File.write!(Path.join(root, "a/b/two.bin"), :binary.copy(<<2>>, 100))
+      on_exit(fn -> File.rm_rf!(root) end)
+      %{root: root}
+    end
+
+    test "sums files and directories", %{root: root} do
+      assert {:ok, summary} = DiskSpace.du(root)
+      assert summary.files == 2
+      assert summary.dirs == 3
+      assert summary.symlinks == 0 and summary.other == 0
+      assert summary.apparent_bytes >= 10_100
+      assert summary.bytes > 0
+      assert summary.errors == []
+      assert summary.error_count == 0
+    end
+
+    test "describes a single file", %{root: root} do
+      assert {:ok, %{files: 1, dirs: 0, apparent_bytes: 10_000}} =
+               DiskSpace.du(Path.join(root, "a/one.bin"))
+    end
+
+    test "counts hard links once unless told otherwise", %{root: root} do
+      {:ok, before} = DiskSpace.du(root)
+      :ok = File.ln(Path.join(root, "a/one.bin"), Path.join(root, "a/b/one_again.bin"))
+      :ok = File.ln(Path.join(root, "a/one.bin"), Path.join(root, "a/one_more.bin"))
+
+      {:ok, %{bytes: file_bytes}} = DiskSpace.du(Path.join(root, "a/one.bin"))
+      {:ok, now} = DiskSpace.du(root)
+      assert now.files == before.files
+      assert now.hardlinked_saved_bytes == 2 * file_bytes
+
+      {:ok, naive} = DiskSpace.du(root, dedupe_hardlinks: false)
+      assert naive.files == now.files + 2
+      assert naive.apparent_bytes == now.apparent_bytes + 20_000
+      assert naive.bytes == now.bytes + now.hardlinked_saved_bytes
+      assert naive.hardlinked_saved_bytes == 0
+    end
+
+    @tag :unix
+    test "doesn't follow symlinks", %{root: root} do
+      {:ok, before} = DiskSpace.du(root)
+      :ok = File.ln_s(valid_directory_path(), Path.join(root, "elsewhere"))
+      {:ok, now} = DiskSpace.du(root)
+      assert now.files == before.files
+      assert now.dirs == before.dirs
+      assert now.apparent_bytes - before.apparent_bytes < 4096
+      assert {:ok, ^now} = DiskSpace.du(root, symlinks: :count_link)
+    end
+
+    @tag :unix
+    test "leaves symlinks out with symlinks: :skip", %{root: root} do
+      {:ok, before} = DiskSpace.du(root)
+      :ok = File.ln_s("a/one.bin", Path.join(root, "one.link"))
+      :ok = File.ln_s("a", Path.join(root, "a.link"))
+      {:ok, counted} = DiskSpace.du(root)
+
+      assert {:ok, skipped} = DiskSpace.du(root, symlinks: :skip)
+      assert {skipped.files, skipped.dirs} == {before.files, before.dirs}
+      # A symlink's length is that of its target path
+      assert counted.apparent_bytes - skipped.apparent_bytes == byte_size("a/one.bin") + 1
+    end
+
+    @tag :unix
+    test "follows symlinks, reporting loops and dangling links", %{root: root} do
+      outside = root <> "_outside"
+      File.mkdir_p!(outside)
+      on_exit(fn -> File.rm_rf!(outside) end)
+      File.write!(Path.join(outside, "three.bin"), :binary.copy(<<3>>, 5000))
+      {:ok, before} = DiskSpace.du(root)
+
+      :ok = File.ln_s(outside, Path.join(root, "outside"))
+      :ok = File.ln_s(root, Path.join(root, "a/b/loop"))
+      :ok = File.ln_s(Path.join(root, "missing"), Path.join(root, "dangling"))
+
+      assert {:ok, followed} = DiskSpace.du(root, symlinks: :follow)
+      assert followed.files == before.files + 1
+      assert followed.dirs == before.dirs + 1
+      assert followed.apparent_bytes >= before.apparent_bytes + 5000
+
+      errors = Map.new(followed.errors, &{&1.path, &1.errstr})
+      assert Map.keys(errors) |> Enum.sort() ==
+               Enum.sort([Path.join(root, "a/b/loop"), Path.join(root, "dangling")])
+    end
+
+    test "breaks usage down by subdirectory down to max_depth", %{root: root} do
+      File.mkdir_p!(Path.join(root, "small"))
+      File.write!(Path.join(root, "small/three.bin"), "3")
+      {:ok, total} = DiskSpace.du(root)
+      refute Map.has_key?(total, :children)
+
+      assert {:ok, %{children: [a, small]} = summary} = DiskSpace.du(root, max_depth: 1)
+      assert a.path == Path.join(root, "a")
+      assert small.path == Path.join(root, "small")
+      assert a.files == 2 and a.dirs == 2
+      assert a.children == []
+      assert summary.bytes >= a.bytes + small.bytes
+
+      assert {:ok, %{children: [%{children: [b]}, %{children: []}]}} =
+               DiskSpace.du(root, max_depth: 2)
+
+      assert b.path == Path.join(root, "a/b")
+      assert b.files == 1 and b.apparent_bytes >= 100
+    end
+
+    test "returns the same directories flat or as a tree", %{root: root} do
+      :rand.seed(:exsss, {1, 2, 3})
+
+      for n <- 1..5 do
+        tree = Path.join(root, "random#{n}")
+        File.mkdir_p!(tree)
+        for i <- 1..:rand.uniform(4), do: random_tree(Path.join(tree, "d#{i}"), 3)
+
+        for max_depth <- 1..4 do
+          {:ok, %{children: children} = nested} = DiskSpace.du(tree, max_depth: max_depth)
+          {:ok, %{entries: entries} = flat} =
+            DiskSpace.du(tree, max_depth: max_depth, shape: :flat)
+
+          {:ok, summary} = DiskSpace.du(tree, max_depth: max_depth, shape: :summary)
+
+          assert Map.delete(nested, :children) == summary
+          assert Map.delete(flat, :entries) == summary
+          assert flatten_tree(children, 1) == entries
+          assert Enum.all?(entries, &(&1.depth <= max_depth))
+
+          # Each directory holds at least what its listed subdirectories do
+          for entry <- [Map.put(summary, :path, tree) | entries],
+              key <- [:bytes, :files, :dirs] do
+            below = for e <- entries, Path.dirname(e.path) == entry.path, do: Map.fetch!(e, key)
+            assert Map.fetch!(entry, key) >= Enum.sum(below)
+          end
+
+          # The root has no files of its own, so its subdirectories add up to it
+          top = Enum.filter(entries, &(&1.depth == 1))
+          assert Enum.sum(Enum.map(top, & &1.files)) == summary.files
+          assert Enum.sum(Enum.map(top, & &1.dirs)) == summary.dirs - 1
+        end
+      end
+    end
+
+    @tag :unix
+    test "counts entries by type, per subdirectory too", %{root: root} do
+      :ok = File.ln_s("one.bin", Path.join(root, "a/one.link"))
+      :ok = File.ln_s("a", Path.join(root, "a.link"))
+      {_, 0} = System.cmd("mkfifo", [Path.join(root, "a/b/pipe")])
+      File.write!(Path.join(root, "a/b/skip.o"), "o")
+
+      assert {:ok, summary} = DiskSpace.du(root, max_depth: 2, exclude: ["*.o"])
+      assert {summary.files, summary.dirs, summary.symlinks, summary.other} == {2, 3, 2, 1}
+
+      assert [%{children: [b]} = a] = summary.children
+      assert {a.files, a.dirs, a.symlinks, a.other} == {2, 2, 1, 1}
+      assert {b.files, b.dirs, b.symlinks, b.other} == {1, 1, 0, 1}
+
+      assert {:ok, %{files: 3, symlinks: 0, other: 1}} = DiskSpace.du(root, symlinks: :skip)
+    end
+
+    test "prunes excluded entries and reports them", %{root: root} do
+      File.mkdir_p!(Path.join(root, "a/node_modules/dep"))
+      File.write!(Path.join(root, "a/node_modules/dep/index.js"), "x")
+      File.write!(Path.join(root, "a/b/main.o"), :binary.copy(<<0>>, 1000))
+      {:ok, all} = DiskSpace.du(root)
+      assert all.excluded_entries == 0 and all.excluded_bytes == 0
+
+      assert {:ok, summary} = DiskSpace.du(root, exclude: ["node_modules", "*.o"])
+      assert summary.files == all.files - 2
+      assert summary.dirs == all.dirs - 2
+      # node_modules is pruned, so only it and main.o are counted as excluded
+      assert summary.excluded_entries == 2
+      assert summary.bytes + summary.excluded_bytes < all.bytes
+
+      {:ok, anchored} = DiskSpace.du(root, exclude: ["/b"])
+      assert anchored.excluded_entries == 0
+      {:ok, anchored} = DiskSpace.du(root, exclude: ["/a/b", "!*.bin"])
+      assert anchored.excluded_entries == 1
+    end
+
+    test "honors ignore files, counting what they ignore separately", %{root: root} do
+      File.write!(Path.join(root, ".gitignore"), "# build output\n/a/b/\n*.log\n")
+      File.write!(Path.join(root, "a/.duignore"), "!keep.log\n")
+      File.write!(Path.join(root, "a/debug.log"), "debug")
+      File.write!(Path.join(root, "a/keep.log"), "keep")
+      {:ok, all} = DiskSpace.du(root)
+      assert all.ignored_entries == 0 and all.ignored_bytes == 0
+
+      assert {:ok, summary} = DiskSpace.du(root, respect_ignore_files: [".gitignore", ".duignore"])
+      # a/b with two.bin, and a/debug.log
+      assert summary.ignored_entries == 3
+      assert summary.files == all.files - 2
+      assert summary.dirs == all.dirs - 1
+      assert summary.bytes + summary.ignored_bytes == all.bytes
+
+      assert {:ok, %{ignored_entries: 4}} = DiskSpace.du(root, respect_ignore_files: [".gitignore"])
+
+      assert {:error, %{reason: :invalid_option, info: :respect_ignore_files}} =
+               DiskSpace.du(root, respect_ignore_files: ".gitignore")
+    end
+
+    test "gives the same result with any number of workers", %{root: root} do
+      for i <- 1..20, do: File.mkdir_p!(Path.join(root, "many/#{i}/#{i}"))
+      File.ln(Path.join(root, "a/one.bin"), Path.join(root, "many/1/1/one.bin"))
+      {:ok, serial} = DiskSpace.du(root, workers: 1)
+      assert serial.hardlinked_saved_bytes > 0
+
+      for workers <- [2, 4, 16] do
+        assert {:ok, ^serial} = DiskSpace.du(root, workers: workers)
+      end
+    end
+
+    test "lists the largest files", %{root: root} do
+      File.write!(Path.join(root, "a/b/three.bin"), :binary.copy(<<3>>, 5000))
+      File.write!(Path.join(root, "a/b/tie.bin"), :binary.copy(<<4>>, 5000))
+      :ok = File.ln(Path.join(root, "a/one.bin"), Path.join(root, "one_again.bin"))
+      {:ok, summary} = DiskSpace.du(root)
+      refute Map.has_key?(summary, :top_files)
+
+      expected = [
+        {Path.join(root, "a/one.bin"), 10_000},
+        {Path.join(root, "a/b/three.bin"), 5000},
+        {Path.join(root, "a/b/tie.bin"), 5000}
+      ]
+
+      for workers <- [1, 4], dedupe <- [true, false] do
+        assert {:ok, %{top_files: ^expected}} =
+                 DiskSpace.du(root,
+                   top_files: 3,
+                   top_by: :apparent_bytes,
+                   workers: workers,
+                   dedupe_hardlinks: dedupe
+                 )
+      end
+
+      assert {:ok, %{top_files: [{_, bytes}]}} = DiskSpace.du(root, top_files: 1)
+      assert bytes > 0
+    end
+
+    test "filters top_files and a matching summary by size and age", %{root: root} do
+      old = Path.join(root, "a/old.bin")
+      File.write!(old, :binary.copy(<<4>>, 50_000))
+      File.touch!(old, System.os_time(:second) - 10 * 86_400)
+      future = Path.join(root, "future.bin")
+      File.write!(future, :binary.copy(<<5>>, 50_000))
+      File.touch!(future, System.os_time(:second) + 86_400)
+      {:ok, all} = DiskSpace.du(root)
+      refute Map.has_key?(all, :matching)
+
+      assert {:ok, summary} =
+               DiskSpace.du(root, top_files: 10, min_size: 20_000, top_by: :apparent_bytes)
+
+      assert Map.drop(summary, [:matching, :top_files]) == all
+
+      assert Enum.map(summary.top_files, &elem(&1, 0)) |> Enum.sort() == Enum.sort([old, future])
+      assert %{files: 2, apparent_bytes: 100_000} = summary.matching
+
+      assert {:ok, %{top_files: [{^old, 50_000}], matching: %{files: 1}}} =
+               DiskSpace.du(root,
+                 top_files: 10,
+                 older_than: 86_400,
+                 min_size: 1,
+                 top_by: :apparent_bytes
+               )
+
+      assert {:ok, %{matching: %{files: 0, bytes: 0}}} = DiskSpace.du(root, older_than: 30 * 86_400)
+    end
+
+    test "lists the largest directories, deepest first", %{root: root} do
+      File.mkdir_p!(Path.join(root, "c/d"))
+      File.write!(Path.join(root, "c/d/three.bin"), :binary.copy(<<3>>, 50_000))
+      {:ok, summary} = DiskSpace.du(root, top_dirs: 2, top_by: :apparent_bytes)
+
+      # c is large only because of c/d, and a holds the 10 KB file besides a/b
+      assert [{d, d_bytes}, {a, _}] = summary.top_dirs
+      assert {d, a} == {Path.join(root, "c/d"), Path.join(root, "a")}
+      assert d_bytes >= 50_000
+
+      assert {:ok, %{top_dirs: [{c, _}, {^d, ^d_bytes}]}} =
+               DiskSpace.du(root, top_dirs: 2, top_dirs_nested: true, top_by: :apparent_bytes)
+
+      assert c == Path.join(root, "c")
+
+      for workers <- [1, 4] do
+        assert {:ok, %{top_dirs: [{^d, _}, {^a, _}]}} =
+                 DiskSpace.du(root, top_dirs: 2, top_by: :apparent_bytes, workers: workers)
+      end
+    end
+
+    test "breaks usage down by extension", %{root: root} do
+      File.write!(Path.join(root, "a/b/three.BIN"), "3")
+      File.write!(Path.join(root, "a/src.tar.gz"), "gz")
+      File.write!(Path.join(root, ".hidden"), "h")
+      File.write!(Path.join(root, "a/skip.o"), "o")
+      :ok = File.ln(Path.join(root, "a/one.bin"), Path.join(root, "one_again.bin"))
+      {:ok, summary} = DiskSpace.du(root)
+      refute Map.has_key?(summary, :by_extension)
+
+      assert {:ok, %{by_extension: by_ext} = summary} =
+               DiskSpace.du(root, by_extension: true, exclude: ["*.o"])
+
+      assert Map.keys(by_ext) |> Enum.sort() == ["bin", "gz", :none]
+      assert by_ext["bin"].files == 3
+      assert by_ext[:none].files == 1
+      assert Enum.sum(for {_, %{files: files}} <- by_ext, do: files) == summary.files
+
+      assert {:ok, %{by_extension: %{"tar.gz" => %{files: 1}, "bin" => %{files: 4}}}} =
+               DiskSpace.du(root,
+                 by_extension: true,
+                 compound_extensions: ["tar.gz"],
+                 dedupe_hardlinks: false
+               )
+    end
+
+    test "breaks usage down by owner", %{root: root} do
+      {:ok, summary} = DiskSpace.du(root)
+      refute Map.has_key?(summary, :by_owner)
+
+      assert {:ok, %{by_owner: by_owner} = summary} = DiskSpace.du(root, by_owner: true)
+      assert [{owner, %{files: 2, bytes: bytes} = usage}] = Map.to_list(by_owner)
+      assert bytes <= summary.bytes
+      refute Map.has_key?(usage, :name)
+
+      case :os.type() do
+        {:unix, _} -> assert owner == File.stat!(Path.join(root, "a/one.bin")).uid
+        {:win32, _} -> assert "S-1-" <> _ = owner
+      end
+
+      assert {:ok, %{by_owner: %{^owner => %{name: name}}}} =
+               DiskSpace.du(root, by_owner: true, resolve_names: true)
+
+      assert is_binary(name) and name != ""
+    end
+
+    @tag :unix
+    test "records unreadable directories, or halts on them", %{root: root} do
+      locked = Path.join(root, "a/locked")
+      File.mkdir_p!(Path.join(locked, "inside"))
+      File.chmod!(locked, 0o000)
+      on_exit(fn -> File.chmod(locked, 0o755) end)
+
+      # Permissions don't apply to root, which can read the directory anyway
+      if match?({:error, :eacces}, File.ls(locked)) do
+        assert {:ok, summary} = DiskSpace.du(root)
+        assert summary.files == 2
+        assert summary.error_count == 1
+        assert [%{path: ^locked, posix: :eacces, errno: errno}] = summary.errors
+        assert errno > 0
+
+        assert {:ok, %{errors: [], error_count: 1}} = DiskSpace.du(root, max_errors: 0)
+
+        assert {:error, %{reason: :du_failed, info: %{path: ^locked, posix: :eacces}}} =
+                 DiskSpace.du(root, on_error: :halt)
+      end
+    end
+
+    test "runs in the background", %{root: root} do
+      {:ok, expected} = DiskSpace.du(root)
+      assert {ref, token} = DiskSpace.du_start(root)
+      assert DiskSpace.du_await(ref, 5000) == {:ok, expected}
+      # Cancelling after completion is a no-op
+      assert DiskSpace.du_cancel(token) == :ok
+      assert DiskSpace.du_cancel(token) == :ok
+      assert DiskSpace.du_await(ref, 0) == {:error, %{reason: :timeout, info: nil}}
+    end
+
+    test "can be cancelled from another process", %{root: root} do
+      for i <- 1..200, do: File.mkdir_p!(Path.join(root, "many/#{i}/#{i}"))
+      {:ok, complete} = DiskSpace.du(root)
+
+      {ref, token} = DiskSpace.du_start(root, workers: 1)
+      Task.await(Task.async(fn -> DiskSpace.du_cancel(token) end))
+
+      # The walk may have finished before the cancellation arrived
+      case DiskSpace.du_await(ref, 5000) do
+        {:ok, summary} ->
+          assert summary == complete
+
+        {:error, %{reason: :cancelled, info: partial}} ->
+          assert partial.dirs <= complete.dirs
+          assert partial.apparent_bytes <= complete.apparent_bytes
+      end
+    end
+
+    test "delivers the result of async walks to another process", %{root: root} do
+      {:ok, expected} = DiskSpace.du(root)
+      {:ok, shallow} = DiskSpace.du(root, max_depth: 0)
+      parent = self()
+      refs = for _ <- 1..4, do: make_ref()
+
+      Task.await(
+        Task.async(fn ->
+          for ref <- refs, do: {:ok, _token} = DiskSpace.du_async(root, [], parent, ref)
+          {:ok, _token} = DiskSpace.du_async(root, [max_depth: 0], parent, :shallow)
+        end)
+      )
+
+      for ref <- refs do
+        assert_receive {:du_result, ^ref, {:ok, ^expected}}, 5000
+      end
+
+      assert_receive {:du_result, :shallow, {:ok, ^shallow}}, 5000
+
+      missing = Path.join(root, "missing")
+      assert {:ok, _token} = DiskSpace.du_async(missing, [], self(), :missing)
+      assert_receive {:du_result, :missing, {:error, %{reason: :invalid_path}}}, 5000
+
+      assert {:error, %{reason: :invalid_option, info: :workers}} =
+               DiskSpace.du_async(root, [workers: 0], self(), make_ref())
+    end
+
+    test "reports invalid arguments of background walks right away", %{root: root} do
+      assert {:error, %{reason: :invalid_option, info: :workers}} =
+               DiskSpace.du_start(root, workers: -1)
+
+      {ref, _token} = DiskSpace.du_start(Path.join(root, "missing"))
+      assert {:error, %{reason: :invalid_path, info: %{errno: _}}} = DiskSpace.du_await(ref, 5000)
+    end
+
+    test "streams directories, children before their parent", %{root: root} do
+      for i <- 1..20, do: File.mkdir_p!(Path.join(root, "many/#{i}/#{i}"))
+      {:ok, expected} = DiskSpace.du(root, max_depth: 1)
+      ref = make_ref()
+      assert {:ok, _token} = DiskSpace.du_stream(root, [max_in_flight: 1, workers: 4], self(), ref)
+
+      {entries, result} = receive_stream(ref, [])
+      assert {:ok, summary} = result
+      assert Map.delete(summary, :children) == Map.delete(expected, :children)
+      assert length(entries) == expected.dirs - 1
+
+      paths = Enum.map(entries, & &1.path)
+      assert length(Enum.uniq(paths)) == length(paths)
+
+      for {entry, i} <- Enum.with_index(entries), Path.dirname(entry.path) != root do
+        parent = Path.dirname(entry.path)
+        assert Enum.find_index(paths, &(&1 == parent)) > i
+      end
+
+      for child <- expected.children do
+        assert Enum.find(entries, &(&1.path == child.path)) == Map.delete(child, :children)
+      end
+    end
+
+    test "rejects a malformed stream window", %{root: root} do
+      assert {:error, %{reason: :invalid_option, info: :max_in_flight}} =
+               DiskSpace.du_stream(root, [max_in_flight: 0], self(), make_ref())
+    end
+
+    test "reuses the sizes of unchanged directories from a cache", %{root: root} do
+      {:ok, cache} = DiskSpace.du_cache_new()
+      opts = [cache: cache, cache_mode: :mtime_heuristic]
+
+      assert {:error, %{reason: :invalid_option, info: :cache_mode}} =
+               DiskSpace.du(root, cache: cache)
+
+      {:ok, expected} = DiskSpace.du(root)
+      assert {:ok, ^expected} = DiskSpace.du(root, opts)
+      assert %{entries: 2, hits: 0, misses: 2} = DiskSpace.du_cache_stats(cache)
+
+      # a/b is left unread as part of a
+      assert {:ok, ^expected} = DiskSpace.du(root, opts)
+      assert %{entries: 2, hits: 1, misses: 2} = DiskSpace.du_cache_stats(cache)
+
+      File.write!(Path.join(root, "a/three.bin"), "3")
+      {:ok, changed} = DiskSpace.du(root)
+      assert changed.files == expected.files + 1
+      assert {:ok, ^changed} = DiskSpace.du(root, opts)
+      assert %{hits: 2, misses: 3} = DiskSpace.du_cache_stats(cache)
+
+      assert :ok = DiskSpace.du_cache_invalidate(cache, Path.join(root, "a"))
+      assert %{entries: 0, evictions: 0} = DiskSpace.du_cache_stats(cache)
+
+      {:ok, small} = DiskSpace.du_cache_new(max_entries: 1)
+      assert {:ok, ^changed} = DiskSpace.du(root, cache: small, cache_mode: :mtime_heuristic)
+      assert %{entries: 1, max_entries: 1, evictions: 1} = DiskSpace.du_cache_stats(small)
+    end
+
+    test "estimates the size from a sample", %{root: root} do
+      for i <- 1..20, do: File.write!(Path.join(root, "a/b/more_#{i}.bin"), "")
+
+      for i <- 1..20 do
+        dir = Path.join(root, "c/#{i}")
+        File.mkdir_p!(Path.join(dir, "deep"))
+        File.write!(Path.join(dir, "deep/file.bin"), :binary.copy(<<3>>, 1000 * i))
+      end
+
+      {:ok, %{bytes: bytes}} = DiskSpace.du(root)
+
+      assert {:ok, %{estimate_bytes: ^bytes, confidence_low: ^bytes, confidence_high: ^bytes}} =
+               DiskSpace.du_estimate(root, sample_fraction: 1)
+
+      assert {:ok, estimate} = DiskSpace.du_estimate(root, sample_fraction: 0.25, seed: 7)
+      assert {:ok, ^estimate} = DiskSpace.du_estimate(root, sample_fraction: 0.25, seed: 7)
+      assert estimate.confidence_low <= estimate.estimate_bytes
+      assert estimate.estimate_bytes <= estimate.confidence_high
+      assert estimate.sampled_fraction > 0 and estimate.sampled_fraction <= 1
+
+      assert {:error, %{reason: :invalid_option, info: :sample_fraction}} =
+               DiskSpace.du_estimate(root, sample_fraction: 0)
+    end
+
+    test "reports progress to the given process", %{root: root} do
+      for i <- 1..500, do: File.mkdir_p!(Path.join(root, "many/#{i}/#{i}"))
+      {:ok, expected} = DiskSpace.du(root)
+      ref = make_ref()
+      assert {:ok, ^expected} = DiskSpace.du(root, progress: {self(), ref}, progress_interval: 1)
+
+      # The walk may well be over before the first report is due
+      for {:du_progress, ^ref, progress} <- flush_messages() do
+        assert %{bytes_so_far: _, apparent_bytes_so_far: _, files_so_far: files} = progress
+        assert files <= expected.files
+        assert String.starts_with?(progress.current_path, root)
+      end
+    end
+
+    test "keeps walking when the progress receiver is gone", %{root: root} do
+      {pid, monitor} = spawn_monitor(fn -> :ok end)
+      assert_receive {:DOWN, ^monitor, :process, ^pid, _}
+      {:ok, expected} = DiskSpace.du(root)
+      assert {:ok, ^expected} = DiskSpace.du(root, progress: {pid, :bar}, progress_interval: 1)
+    end
+
+    test "keeps stat/2 responsive while many walks run", %{root: root} do
+      for i <- 1..300, do: File.mkdir_p!(Path.join(root, "many/#{i}/#{i}"))
+      path = valid_directory_path()
+      stat_usec = fn -> :timer.tc(fn -> {:ok, _} = DiskSpace.stat(path) end) |> elem(0) end
+      baseline = Enum.max(for _ <- 1..20, do: stat_usec.())
+
+      # More walks than dirty IO schedulers, which used to be enough to queue stat/2
+      walks = for _ <- 1..(System.schedulers_dirty_io() * 2), do: DiskSpace.du_start(root)
+      during = Enum.max(for _ <- 1..20, do: stat_usec.())
+
+      for {ref, _token} <- walks, do: assert({:ok, _} = DiskSpace.du_await(ref, 60_000))
+      assert during < max(baseline * 20, 50_000)
+    end
+
+    test "stays on one filesystem when asked", %{root: root} do
+      {:ok, all} = DiskSpace.du(root)
+      assert all.skipped_mounts == []
+      assert {:ok, same_fs} = DiskSpace.du(root, one_file_system: true)
+      assert same_fs.skipped_mounts == []
+      assert Map.take(same_fs, [:files, :dirs, :apparent_bytes]) ==
+               Map.take(all, [:files, :dirs, :apparent_bytes])
+    end
+
+    @tag :unix
+    test "walks a directory bound into the tree once", %{root: root} do
+      assert {:ok, %{deduplicated_dirs: []} = plain} = DiskSpace.du(root)
+
+      # Binding needs root, and a mount namespace that allows it
+      bound = Path.join(root, "a/b/a_again")
+      File.mkdir_p!(bound)
+
+      mount = ["--bind", Path.join(root, "a"), bound]
+
+      case System.cmd("mount", mount, stderr_to_stdout: true) do
+        {_, 0} ->
+          on_exit(fn -> System.cmd("umount", [bound]) end)
+
+          for workers <- [1, 4], one_file_system <- [false, true] do
+            assert {:ok, summary} =
+                     DiskSpace.du(root, workers: workers, one_file_system: one_file_system)
+
+            assert summary.deduplicated_dirs == [bound]
+            assert summary.files == plain.files
+            assert summary.dirs == plain.dirs
+          end
+
+        _ ->
+          :ok
+      end
+    end
+
+    test "walks paths longer than 260 characters", %{root: root} do
+      segment = String.duplicate("d", 50)
+      deep = Enum.reduce(1..7, Path.join(root, "a"), fn _, dir -> Path.join(dir, segment) end)
+      file = Path.join(deep, "deep.bin")
+      File.mkdir_p!(deep)
+      File.write!(file, :binary.copy(<<1>>, 5000))
+      assert String.length(file) > 300
+
+      assert {:ok, summary} =
+               DiskSpace.du(root,
+                 max_depth: 8,
+                 shape: :flat,
+                 top_files: 2,
+                 top_by: :apparent_bytes
+               )
+
+      assert summary.errors == []
+      assert summary.files == 3
+      assert summary.dirs == 10
+      assert summary.apparent_bytes >= 15_100
+      assert [{_, 10_000}, {deep_file, 5000}] = summary.top_files
+      assert Path.split(deep_file) == Path.split(file)
+
+      assert [%{path: deepest, depth: 8, files: 1} = entry] =
+               Enum.filter(summary.entries, &(&1.depth == 8))
+
+      assert entry.apparent_bytes >= 5000
+
+      assert Path.split(deepest) == Path.split(deep)
+      refute String.starts_with?(deepest, "\\\\?\\")
+    end
+
+    test "rejects malformed options", %{root: root} do
+      assert {:error, %{reason: :invalid_option, info: :one_file_system}} =
+               DiskSpace.du(root, one_file_system: "yes")
+
+      assert {:error, %{reason: :invalid_option, info: :max_depth}} =
+               DiskSpace.du(root, max_depth: -1)
+
+      assert {:error, %{reason: :invalid_option, info: :exclude}} =
+               DiskSpace.du(root, exclude: "*.o")
+
+      assert {:error, %{reason: :invalid_option, info: :symlinks}} =
+               DiskSpace.du(root, symlinks: :maybe)
+
+      assert {:error, %{reason: :invalid_option, info: :workers}} =
+               DiskSpace.du(root, workers: 0)
+
+      assert {:error, %{reason: :invalid_option, info: :on_error}} =
+               DiskSpace.du(root, on_error: :ignore)
+
+      assert {:error, %{reason: :invalid_option, info: :progress}} =
+               DiskSpace.du(root, progress: self())
+
+      assert {:error, %{reason: :invalid_option, info: :top_by}} =
+               DiskSpace.du(root, top_files: 3, top_by: :name)
+
+      assert {:error, %{reason: :invalid_option, info: :top_dirs_nested}} =
+               DiskSpace.du(root, top_dirs: 3, top_dirs_nested: 1)
+
+      assert {:error, %{reason: :invalid_option, info: :compound_extensions}} =
+               DiskSpace.du(root, by_extension: true, compound_extensions: "tar.gz")
+
+      assert {:error, %{reason: :invalid_option, info: :dedupe_hardlinks}} =
+               DiskSpace.du(root, dedupe_hardlinks: :sometimes)
+
+      assert {:error, %{reason: :invalid_option, info: :count_placeholders}} =
+               DiskSpace.du(root, count_placeholders: :cloud)
+
+      assert {:error, %{reason: :invalid_option, info: :by_owner}} =
+               DiskSpace.du(root, by_owner: "yes")
+
+      assert {:error, %{reason: :invalid_option, info: :shape}} =
+               DiskSpace.du(root, max_depth: 1, shape: :list)
+    end
+
+    test "counts the inodes a copy would take and checks whether it fits", %{root: root} do
+      :ok = File.ln(Path.join(root, "a/one.bin"), Path.join(root, "one_again.bin"))
+      assert {:ok, %{inodes: 5} = summary} = DiskSpace.du(root)
+      assert {:ok, %{inodes: 6}} = DiskSpace.du(root, dedupe_hardlinks: false)
+
+      assert DiskSpace.fits?(summary, root)
+      assert DiskSpace.fits?({:ok, summary}, valid_directory_path())
+      refute DiskSpace.fits?(%{summary | bytes: 4_000_000_000_000_000_000}, root)
+
+      assert_raise DiskSpace.Error, fn ->
+        DiskSpace.fits?(summary, Path.join(root, "missing"))
+      end
+    end
+
+    @tag :unix
+    test "reports allocated and apparent sizes of sparse files separately", %{root: root} do
+      sparse = Path.join(root, "sparse.img")
+      {:ok, fd} = :file.open(sparse, [:write, :raw, :binary])
+      {:ok, _} = :file.position(fd, 256 * 1024 * 1024)
+      :ok = :file.write(fd, "end")
+      :ok = :file.close(fd)
+
+      assert {:ok, %{bytes: bytes, apparent_bytes: apparent_bytes}} = DiskSpace.du(sparse)
+      assert apparent_bytes == 256 * 1024 * 1024 + 3
+      assert bytes < 1024 * 1024
+    end
+
+    test "counts sparse files at their allocated size", %{root: root} do
+      gib = 1024 * 1024 * 1024
+      sparse = Path.join(root, "disk.img")
+      {:ok, fd} = :file.open(sparse, [:write, :raw, :binary])
+      {:ok, _} = :file.position(fd, gib - 4096)
+      :ok = :file.write(fd, :binary.copy(<<7>>, 4096))
+      :ok = :file.close(fd)
+
+      assert {:ok, summary} = DiskSpace.du(sparse)
+      assert summary.apparent_bytes == gib
+
+      # Filesystems without holes (FAT, or NTFS without the sparse attribute) allocate it all
+      if summary.bytes < gib do
+        assert summary.bytes <= 1024 * 1024
+        assert summary.sparse_files == 1
+        assert summary.sparse_savings_bytes == gib - summary.bytes
+      else
+        assert summary.sparse_files == 0 and summary.sparse_savings_bytes == 0
+      end
+    end
+
+    test "reports compression savings, cluster slack and placeholders on Windows only", %{
+      root: root
+    } do
+      {:ok, summary} = DiskSpace.du(root)
+      assert {:ok, ^summary} = DiskSpace.du(root, count_placeholders: :exclude)
+
+      case :os.type() do
+        {:win32, _} ->
+          assert summary.compression_savings_bytes == 0
+          assert summary.slack_bytes >= 0 and summary.slack_bytes <= summary.bytes
+          assert summary.placeholder_files == 0 and summary.placeholder_bytes == 0
+
+        _ ->
+          for key <- [:compression_savings_bytes, :slack_bytes, :placeholder_files] do
+            refute Map.has_key?(summary, key)
+          end
+      end
+    end
+
+    test "returns an error tuple for a non-existent path", %{root: root} do
+      assert {:error, %{reason: :invalid_path, info: %{errno: _, errstr: _}}} =
+               DiskSpace.du(Path.join(root, "missing"))
+    end
+  end
+
+  # The keys of the stats of stat/2, which adds :is_quota_limited on Windows
+  defp stat_keys do
+    case :os.type() do
+      {:win32, _} -> [:available, :free, :is_quota_limited, :total, :used]
+      _ -> [:available, :free, :total, :used]
+    end
+  end
+
+  defp valid_directory_path do
+    if :os.type() == {:win32, :nt} do
+      "C:\\"
+    else
+      "/tmp"
+    end
+  end
+
+  # `levels` levels of directories, each with a few files of random sizes
+  defp random_tree(dir, levels) do
+    File.mkdir_p!(dir)
+
+    for i <- 1..:rand.uniform(3) do
+      File.write!(Path.join(dir, "f#{i}"), :binary.copy("x", :rand.uniform(5000)))
+    end
+
+    if levels > 1 do
+      for i <- 1..:rand.uniform(3), do: random_tree(Path.join(dir, "d#{i}"), levels - 1)
+    end
+  end
+
+  # The entries of shape: :flat from the :children of shape: :tree
+  defp flatten_tree(nodes, depth) do
+    Enum.flat_map(nodes, fn node ->
+      entry = node |> Map.delete(:children) |> Map.put(:depth, depth)
+      [entry | flatten_tree(node.children, depth + 1)]
+    end)
+  end
+
+  # Acknowledges each entry once received, like a consumer would
+  defp receive_stream(ref, entries) do
+    receive do
+      {:du_entry, ^ref, entry} ->
+        DiskSpace.du_ack(ref)
+        receive_stream(ref, [entry | entries])
+
+      {:du_done, ^ref, result} ->
+        {Enum.reverse(entries), result}
+    after
+      5000 -> flunk("the stream stalled")
+    end
+  end
+
+  defp flush_messages(acc \\ []) do
+    receive do
+      message -> flush_messages([message | acc])
+    after
+      0 -> Enum.reverse(acc)
+    end
+  end
+
+  # Stands in for another process receiving a watcher's samples
+  defp forward_samples(parent) do
+    receive do
+      message ->
+        send(parent, {:forwarded, message})
+        forward_samples(parent)
     end
   end
 end
@@ -0,0 +1,59 @@
+# SPDX-FileCopyrightText: 2025 Isaak Tsalicoglou <isaak@overbring.com>
+# SPDX-License-Identifier: Apache-2.0
+
+defmodule DiskSpaceUpgradeTest do
+  # Reloads DiskSpace and its NIF library, so never alongside other tests
+  use ExUnit.Case, async: false
+
+  # The threads of the worker pool and of the timer end after 10 seconds
+  # without work, and the library is only unloaded once none is left
+  @idle 11_000
+
+  @moduletag timeout: 60_000
+
+  describe "export_watchers/0 across a reload" do
+    test "carries watchers over to a new copy of the library without a sample twice" do
+      path = System.tmp_dir!()
+      {DiskSpace, binary, file} = :code.get_object_code(DiskSpace)
+
+      {ref, watcher} = DiskSpace.watch(path, interval_ms: 50)
+      assert_receive {:disk_space_sample, ^ref, %{at: first}}, 5000
+      Process.sleep(200)
+
+      descriptors = DiskSpace.export_watchers()
+      assert %{state: :stopped} = DiskSpace.watcher_info(watcher)
+      before = [first | samples(ref)]
+      assert length(before) >= 2
+
+      # Nothing of the old library may be left for it to be unloaded
+      :erlang.garbage_collect()
+      Process.sleep(@idle)
+      assert [] = samples(ref)
+
+      :code.purge(DiskSpace)
+      assert :code.delete(DiskSpace)
+      :code.purge(DiskSpace)
+      assert {:module, DiskSpace} = :code.load_binary(DiskSpace, file, binary)
+      assert [] = DiskSpace.list_watchers()
+
+      assert [{^ref, watcher}] = DiskSpace.import_watchers(descriptors)
+      Process.sleep(300)
+      DiskSpace.unwatch(watcher)
+      imported = samples(ref)
+      assert imported != []
+
+      ats = before ++ imported
+      assert ats == Enum.uniq(ats)
+      assert Enum.min(imported) > Enum.max(before)
+    end
+  end
+
+  # The `at` of each sample of `ref` in the mailbox, in order
+  defp samples(ref) do
+    receive do
+      {:disk_space_sample, ^ref, %{at: at}} -> [at | samples(ref)]
+    after
+      0 -> []
+    end
+  end
+end
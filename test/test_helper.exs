@@ -1 +1,5 @@
-ExUnit.start()
+# Tests tagged :unix rely on hard links, symlinks or permissions semantics
+# that Windows runners don't offer
+exclude = if match?({:win32, _}, :os.type()), do: [:unix], else: []
+
+ExUnit.start(exclude: exclude)